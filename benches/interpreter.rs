@@ -0,0 +1,58 @@
+//! Dispatch overhead of the checked interpreter versus the fast path for
+//! verified bytecode, on a tight counting loop.
+//!
+//! `cargo bench --bench interpreter`
+
+use kayton::vm::const_pool::ValueType;
+use kayton::vm::{BytecodeBuilder, VirtualMachine};
+use std::time::Instant;
+
+const ITERATIONS: u64 = 1_000_000;
+const RUNS: usize = 20;
+
+fn counting_loop() -> Vec<u8> {
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_value(0, 1);
+    builder.load_const_value(1, 2);
+    builder.load_const_value(2, 3);
+    let top = builder.create_label();
+    builder.place_label(top);
+    builder.add_i64(1, 2, 1);
+    builder.lt_i64(1, 3, 4);
+    builder.jump_if_true_to_label(4, top);
+    builder.build()
+}
+
+fn new_vm() -> VirtualMachine {
+    let mut vm = VirtualMachine::new();
+    vm.const_pool.add_value("0", 0, ValueType::I64);
+    vm.const_pool.add_value("1", 1, ValueType::I64);
+    vm.const_pool.add_value("n", ITERATIONS, ValueType::I64);
+    vm
+}
+
+fn bench(name: &str, fast: bool) {
+    let bytecode = counting_loop();
+    let mut vm = new_vm();
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        if fast {
+            let verified = vm.verify(&bytecode).unwrap();
+            vm.eval_verified(verified).unwrap();
+        } else {
+            vm.eval_program(&bytecode).unwrap();
+        }
+    }
+    let elapsed = start.elapsed();
+    assert_eq!(vm.get_register_i64(1), ITERATIONS as i64);
+    println!(
+        "{:<9} {:>6.2} ns/instruction",
+        name,
+        elapsed.as_nanos() as f64 / (RUNS as u64 * ITERATIONS * 3) as f64
+    );
+}
+
+fn main() {
+    bench("checked", false);
+    bench("verified", true);
+}
@@ -0,0 +1,43 @@
+//! String-heavy programs: many short concatenations (inline slab slots) versus
+//! long ones (one allocation each).
+//!
+//! `cargo bench --bench strings`
+
+use kayton::builtins::print_const;
+use kayton::codegen::compile_source;
+use kayton::vm::VirtualMachine;
+use std::time::Instant;
+
+// Each string global takes two registers, so stay well under the 256 available
+const STATEMENTS: usize = 100;
+const RUNS: usize = 2_000;
+
+fn bench(name: &str, piece: &str) {
+    let mut src = format!("s = \"{}\"\n", piece);
+    for i in 0..STATEMENTS {
+        src.push_str(&format!("t{} = s + \"{}\"\n", i, i % 10));
+    }
+    let mut vm = VirtualMachine::builder().with_stdlib().build();
+    let print = print_const(&vm).unwrap();
+    let bytecode = compile_source(&src, &mut vm, print).unwrap();
+
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        vm.strings.clear();
+        vm.eval_program(&bytecode).unwrap();
+    }
+    let elapsed = start.elapsed();
+    let stats = vm.strings.stats();
+    println!(
+        "{:<6} {:>8.1} ns/concat   {} strings, {} allocations per run",
+        name,
+        elapsed.as_nanos() as f64 / (RUNS * STATEMENTS) as f64,
+        stats.inline_strings + stats.large_strings,
+        stats.allocations
+    );
+}
+
+fn main() {
+    bench("short", "key_");
+    bench("long", "a key that is well past the inline limit_");
+}
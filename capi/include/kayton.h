@@ -0,0 +1,44 @@
+/* C API for embedding the Kayton VM (built from the kayton_capi crate). */
+#ifndef KAYTON_H
+#define KAYTON_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define KAYTON_OK 0
+#define KAYTON_ERROR (-1)
+
+typedef struct KaytonVm KaytonVm;
+
+/* registers[0] is the return slot, arguments start at registers[1].
+   Return KAYTON_OK on success; any other value aborts the script. */
+typedef int (*kayton_host_fn)(uint64_t *registers, size_t len, void *user_data);
+
+KaytonVm *kayton_vm_new(void);
+void kayton_vm_free(KaytonVm *vm);
+
+/* Message of the last failed call; valid until the next call on this VM. */
+const char *kayton_last_error(const KaytonVm *vm);
+
+int kayton_compile(KaytonVm *vm, const char *source);
+int kayton_run(KaytonVm *vm);
+int kayton_eval(KaytonVm *vm, const char *source);
+
+int kayton_get_global_int(KaytonVm *vm, const char *name, int64_t *out);
+int kayton_get_global_double(KaytonVm *vm, const char *name, double *out);
+/* UTF-8 bytes, not NUL-terminated; valid until the next compile or run on this VM. */
+int kayton_get_global_string(KaytonVm *vm, const char *name,
+                             const uint8_t **out_ptr, size_t *out_len);
+
+int kayton_register_callback(KaytonVm *vm, const char *name, size_t num_params,
+                             kayton_host_fn callback, void *user_data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* KAYTON_H */
@@ -0,0 +1,255 @@
+//! C ABI for embedding Kayton. See `include/kayton.h` for the C declarations.
+//!
+//! All functions taking a `*mut KaytonVm` expect a pointer returned by
+//! `kayton_vm_new` that has not been passed to `kayton_vm_free`; string
+//! arguments must be NUL-terminated and output pointers must be writable.
+#![allow(clippy::missing_safety_doc)] // the contract above covers every function
+
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::sync::Arc;
+
+use kayton::builtins::register_builtins;
+use kayton::codegen::compile_source;
+use kayton::vm::const_pool::ValueType;
+use kayton::vm::{GlobalVarType, PtrType, VirtualMachine};
+
+pub const KAYTON_OK: c_int = 0;
+pub const KAYTON_ERROR: c_int = -1;
+
+/// Host callback: receives the call frame (`registers[0]` is the return slot,
+/// arguments start at `registers[1]`) and returns 0 on success
+pub type KaytonHostFn =
+    extern "C" fn(registers: *mut u64, len: usize, user_data: *mut c_void) -> c_int;
+
+pub struct KaytonVm {
+    vm: VirtualMachine,
+    print_const: u16,
+    program: Vec<u8>,
+    last_error: CString,
+    /// Names of registered callbacks. `vm`'s registry borrows them, so they
+    /// are declared after it and dropped after it.
+    callback_names: Vec<Box<str>>,
+}
+
+impl KaytonVm {
+    fn fail(&mut self, msg: String) -> c_int {
+        self.last_error = CString::new(msg.replace('\0', " ")).unwrap_or_default();
+        KAYTON_ERROR
+    }
+}
+
+/// Opaque user data handed back to C callbacks; the embedder guarantees thread safety
+struct UserData(*mut c_void);
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl UserData {
+    fn ptr(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+unsafe fn c_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(s) }.to_str().ok()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn kayton_vm_new() -> *mut KaytonVm {
+    let mut vm = VirtualMachine::new();
+    let print_const = register_builtins(&mut vm);
+    Box::into_raw(Box::new(KaytonVm {
+        vm,
+        print_const,
+        program: Vec::new(),
+        last_error: CString::default(),
+        callback_names: Vec::new(),
+    }))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kayton_vm_free(vm: *mut KaytonVm) {
+    if !vm.is_null() {
+        drop(unsafe { Box::from_raw(vm) });
+    }
+}
+
+/// Message of the last failed call; valid until the next call on this VM
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kayton_last_error(vm: *const KaytonVm) -> *const c_char {
+    match unsafe { vm.as_ref() } {
+        Some(vm) => vm.last_error.as_ptr(),
+        None => c"null VM".as_ptr(),
+    }
+}
+
+/// Compile `source` into the VM's current program
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kayton_compile(vm: *mut KaytonVm, source: *const c_char) -> c_int {
+    let Some(vm) = (unsafe { vm.as_mut() }) else {
+        return KAYTON_ERROR;
+    };
+    let Some(src) = (unsafe { c_str(source) }) else {
+        return vm.fail("source is null or not UTF-8".to_string());
+    };
+    match compile_source(src, &mut vm.vm, vm.print_const) {
+        Ok(bytecode) => {
+            vm.program = bytecode;
+            KAYTON_OK
+        }
+        Err(err) => vm.fail(format!("compile error: {}", err)),
+    }
+}
+
+/// Run the program produced by the last successful `kayton_compile`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kayton_run(vm: *mut KaytonVm) -> c_int {
+    let Some(vm) = (unsafe { vm.as_mut() }) else {
+        return KAYTON_ERROR;
+    };
+    let program = std::mem::take(&mut vm.program);
+    let result = vm.vm.eval_program(&program);
+    vm.program = program;
+    match result {
+        Ok(()) => KAYTON_OK,
+        Err(err) => vm.fail(format!("runtime error: {}", err)),
+    }
+}
+
+/// Compile and run `source` in one step
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kayton_eval(vm: *mut KaytonVm, source: *const c_char) -> c_int {
+    match unsafe { kayton_compile(vm, source) } {
+        KAYTON_OK => unsafe { kayton_run(vm) },
+        err => err,
+    }
+}
+
+unsafe fn global_register<'a>(
+    vm: *mut KaytonVm,
+    name: *const c_char,
+) -> Result<(&'a mut KaytonVm, usize, GlobalVarType), c_int> {
+    let Some(vm) = (unsafe { vm.as_mut() }) else {
+        return Err(KAYTON_ERROR);
+    };
+    let Some(name) = (unsafe { c_str(name) }) else {
+        return Err(vm.fail("name is null or not UTF-8".to_string()));
+    };
+    match vm.vm.global_vars.get(name) {
+        Some(var) => {
+            let (reg, typ) = (var.register_id, var.meta.typ);
+            Ok((vm, reg, typ))
+        }
+        None => Err(vm.fail(format!("unknown global '{}'", name))),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kayton_get_global_int(
+    vm: *mut KaytonVm,
+    name: *const c_char,
+    out: *mut i64,
+) -> c_int {
+    match unsafe { global_register(vm, name) } {
+        Ok((vm, reg, GlobalVarType::Value(ValueType::I64 | ValueType::Bool))) => {
+            unsafe { *out = vm.vm.get_register_i64(reg) };
+            KAYTON_OK
+        }
+        Ok((vm, _, typ)) => vm.fail(format!("global is {:?}, not an int", typ)),
+        Err(err) => err,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kayton_get_global_double(
+    vm: *mut KaytonVm,
+    name: *const c_char,
+    out: *mut f64,
+) -> c_int {
+    match unsafe { global_register(vm, name) } {
+        Ok((vm, reg, GlobalVarType::Value(ValueType::F64))) => {
+            unsafe { *out = vm.vm.get_register_f64(reg) };
+            KAYTON_OK
+        }
+        Ok((vm, reg, GlobalVarType::Value(ValueType::I64))) => {
+            unsafe { *out = vm.vm.get_register_i64(reg) as f64 };
+            KAYTON_OK
+        }
+        Ok((vm, _, typ)) => vm.fail(format!("global is {:?}, not a number", typ)),
+        Err(err) => err,
+    }
+}
+
+/// Borrow a string global as UTF-8 bytes (not NUL-terminated).
+/// The data is valid only until the next compile or run on this VM, which
+/// may free or move it; copy it out to keep it longer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kayton_get_global_string(
+    vm: *mut KaytonVm,
+    name: *const c_char,
+    out_ptr: *mut *const u8,
+    out_len: *mut usize,
+) -> c_int {
+    match unsafe { global_register(vm, name) } {
+        Ok((vm, reg, GlobalVarType::Ptr(PtrType::Slice(_)))) => {
+            unsafe {
+                *out_ptr = vm.vm.get_register_raw(reg) as *const u8;
+                *out_len = vm.vm.get_register_raw(reg + 1) as usize;
+            }
+            KAYTON_OK
+        }
+        Ok((vm, _, typ)) => vm.fail(format!("global is {:?}, not a string", typ)),
+        Err(err) => err,
+    }
+}
+
+/// Register `callback` as host function `name` taking `num_params` arguments
+/// and returning one value. `user_data` is passed back on every call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kayton_register_callback(
+    vm: *mut KaytonVm,
+    name: *const c_char,
+    num_params: usize,
+    callback: KaytonHostFn,
+    user_data: *mut c_void,
+) -> c_int {
+    let Some(vm) = (unsafe { vm.as_mut() }) else {
+        return KAYTON_ERROR;
+    };
+    let Some(name) = (unsafe { c_str(name) }) else {
+        return vm.fail("name is null or not UTF-8".to_string());
+    };
+    // Registry names are 'static. The VM owns the name instead and drops it
+    // after the registry, so the borrow never outlives it.
+    let owned: Box<str> = name.into();
+    let name: &'static str = unsafe { &*(&*owned as *const str) };
+    vm.callback_names.push(owned);
+    let user_data = UserData(user_data);
+    let num_registers = num_params + 1;
+    let index = vm.vm.host_functions.register_closure(
+        name,
+        1,
+        num_params,
+        num_registers,
+        Arc::new(move |base, registers| {
+            let mut frame: Vec<u64> = (0..num_registers)
+                .map(|i| registers.get(base + i))
+                .collect();
+            let status = callback(frame.as_mut_ptr(), frame.len(), user_data.ptr());
+            for (i, value) in frame.into_iter().enumerate() {
+                registers.set(base + i, value);
+            }
+            if status == KAYTON_OK {
+                Ok(())
+            } else {
+                Err(format!("{} returned status {}", name, status))
+            }
+        }),
+    );
+    vm.vm
+        .const_pool
+        .add_value(name, index as u64, ValueType::FuncHost);
+    KAYTON_OK
+}
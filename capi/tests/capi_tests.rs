@@ -0,0 +1,82 @@
+use kayton_capi::*;
+use std::ffi::{CStr, c_int, c_void};
+
+extern "C" fn scale(registers: *mut u64, len: usize, user_data: *mut c_void) -> c_int {
+    let frame = unsafe { std::slice::from_raw_parts_mut(registers, len) };
+    let factor = unsafe { *(user_data as *const i64) };
+    frame[0] = (frame[1] as i64 * factor) as u64;
+    KAYTON_OK
+}
+
+#[test]
+fn eval_and_read_globals() {
+    let vm = kayton_vm_new();
+    unsafe {
+        assert_eq!(
+            kayton_eval(vm, c"x = 40\nx = x + 2\ns = \"hi\"".as_ptr()),
+            KAYTON_OK
+        );
+
+        let mut x = 0i64;
+        assert_eq!(kayton_get_global_int(vm, c"x".as_ptr(), &mut x), KAYTON_OK);
+        assert_eq!(x, 42);
+
+        let mut d = 0f64;
+        assert_eq!(
+            kayton_get_global_double(vm, c"x".as_ptr(), &mut d),
+            KAYTON_OK
+        );
+        assert_eq!(d, 42.0);
+
+        let mut ptr = std::ptr::null();
+        let mut len = 0usize;
+        assert_eq!(
+            kayton_get_global_string(vm, c"s".as_ptr(), &mut ptr, &mut len),
+            KAYTON_OK
+        );
+        assert_eq!(std::slice::from_raw_parts(ptr, len), b"hi");
+
+        assert_eq!(
+            kayton_get_global_int(vm, c"missing".as_ptr(), &mut x),
+            KAYTON_ERROR
+        );
+        let err = CStr::from_ptr(kayton_last_error(vm)).to_str().unwrap();
+        assert_eq!(err, "unknown global 'missing'");
+        kayton_vm_free(vm);
+    }
+}
+
+#[test]
+fn callbacks_are_callable_from_scripts() {
+    let vm = kayton_vm_new();
+    let mut factor = 3i64;
+    unsafe {
+        assert_eq!(
+            kayton_register_callback(
+                vm,
+                c"scale".as_ptr(),
+                1,
+                scale,
+                &mut factor as *mut i64 as *mut c_void
+            ),
+            KAYTON_OK
+        );
+        assert_eq!(kayton_compile(vm, c"y = scale(14)".as_ptr()), KAYTON_OK);
+        assert_eq!(kayton_run(vm), KAYTON_OK);
+        let mut y = 0i64;
+        assert_eq!(kayton_get_global_int(vm, c"y".as_ptr(), &mut y), KAYTON_OK);
+        assert_eq!(y, 42);
+        kayton_vm_free(vm);
+    }
+}
+
+#[test]
+fn compile_errors_are_reported() {
+    let vm = kayton_vm_new();
+    unsafe {
+        assert_eq!(kayton_compile(vm, c"x = )".as_ptr()), KAYTON_ERROR);
+        let err = CStr::from_ptr(kayton_last_error(vm)).to_str().unwrap();
+        assert!(err.starts_with("compile error:"), "{}", err);
+        kayton_vm_free(vm);
+    }
+}
@@ -0,0 +1,51 @@
+//! Embedding with custom host functions: Rust closures callable from scripts.
+//!
+//! `cargo run --example host_functions`
+
+use kayton::builtins::print_const;
+use kayton::codegen::compile_source;
+use kayton::vm::VirtualMachine;
+use kayton::vm::const_pool::ValueType;
+use std::sync::{Arc, Mutex};
+
+/// Run `src` with a `scale(x)` host function and return what it printed
+fn run(src: &str, factor: i64) -> Result<String, String> {
+    let sink = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut vm = VirtualMachine::builder().output(sink.clone()).build();
+
+    let index = vm.host_functions.register_closure(
+        "scale",
+        1,
+        1,
+        2,
+        Arc::new(move |base, registers| {
+            let x = registers.get(base + 1) as i64;
+            registers.set(base, (x * factor) as u64);
+            Ok(())
+        }),
+    );
+    vm.const_pool
+        .add_value("scale", index as u64, ValueType::FuncHost);
+
+    let print = print_const(&vm).ok_or("print is not registered")?;
+    let bytecode = compile_source(src, &mut vm, print)?;
+    vm.eval_program(&bytecode).map_err(|e| e.to_string())?;
+
+    let output = sink.lock().unwrap();
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+fn main() {
+    match run("x = scale(14)\nprint(x)\nprint(scale(x))", 3) {
+        Ok(output) => print!("{}", output),
+        Err(err) => eprintln!("error: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn scale_is_called_from_the_script() {
+        assert_eq!(super::run("print(scale(14))", 3).unwrap(), "42\n");
+    }
+}
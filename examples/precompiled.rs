@@ -0,0 +1,62 @@
+//! Running a precompiled `.kayc` program in a VM built without the frontend,
+//! stdlib or disassembler: the footprint of an embedder that ships bytecode.
+//!
+//! `cargo run --example precompiled --no-default-features`
+
+use kayton::vm::const_pool::ValueType;
+use kayton::vm::{BytecodeBuilder, HostFunctionMetadata, Program, Registers, VirtualMachine};
+
+const SCALE: HostFunctionMetadata = HostFunctionMetadata {
+    name: "scale",
+    num_return_registers: 1,
+    num_params: 1,
+    num_registers: 2,
+    version: 0,
+    defaults: &[],
+};
+
+// scale(x) -> 10 * x
+fn scale(base: usize, registers: &mut Registers) -> Result<(), String> {
+    registers.set(base, registers.get(base + 1).wrapping_mul(10));
+    Ok(())
+}
+
+fn register_scale(vm: &mut VirtualMachine) -> u16 {
+    let idx = vm.host_functions.register(
+        SCALE.name,
+        SCALE.num_return_registers,
+        SCALE.num_params,
+        SCALE.num_registers,
+        scale,
+    );
+    vm.const_pool
+        .add_value(SCALE.name, idx as u64, ValueType::FuncHost) as u16
+}
+
+/// The build step: r3 = scale(4) + 2, saved as a `.kayc` image
+fn build_image() -> Result<Vec<u8>, String> {
+    let mut vm = VirtualMachine::new();
+    let scale = register_scale(&mut vm);
+    let four = vm.const_pool.add_value("four", 4, ValueType::I64) as u16;
+    let two = vm.const_pool.add_value("two", 2, ValueType::I64) as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_value(four, 1);
+    builder.call_host_fn(&SCALE, scale, &[1], 2);
+    builder.load_const_value(two, 1);
+    builder.add_i64(2, 1, 3);
+    let program = Program::with_consts(builder.build(), &vm).map_err(|e| e.to_string())?;
+    Ok(program.to_bytes())
+}
+
+fn main() -> Result<(), String> {
+    let image = build_image()?;
+
+    // The embedder: only the host functions the program calls
+    let mut vm = VirtualMachine::new();
+    register_scale(&mut vm);
+    let bytecode = vm.load_program(&image).map_err(|e| e.to_string())?;
+    vm.eval_program(&bytecode).map_err(|e| e.to_string())?;
+    assert_eq!(vm.get_register_i64(3), 42);
+    println!("{}", vm.get_register_i64(3));
+    Ok(())
+}
@@ -0,0 +1,36 @@
+//! A REPL-like loop driving `Repl::eval_line` from the host application.
+//!
+//! `cargo run --example repl_loop`
+
+use kayton::repl::{Repl, ReplAction};
+
+/// Feed `lines` to one REPL session and collect its command output
+fn drive(lines: &[&str]) -> Result<Vec<String>, String> {
+    let mut repl = Repl::new();
+    let mut outputs = Vec::new();
+    for line in lines {
+        match repl.eval_line(line)? {
+            ReplAction::Output(text) => outputs.push(text),
+            ReplAction::Continue => {}
+            ReplAction::Quit => break,
+        }
+    }
+    Ok(outputs)
+}
+
+fn main() {
+    let lines = ["x = 40", "x = x + 2", "print(x)", ":help print", ":quit"];
+    match drive(&lines) {
+        Ok(outputs) => outputs.iter().for_each(|text| println!("{}", text)),
+        Err(err) => eprintln!("error: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn session_state_and_commands() {
+        let outputs = super::drive(&["x = 1", ":help x", ":q", ":help y"]).unwrap();
+        assert_eq!(outputs, vec!["x: Value(I64)".to_string()]);
+    }
+}
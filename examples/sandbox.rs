@@ -0,0 +1,48 @@
+//! Running untrusted code: instruction limits and capabilities.
+//!
+//! `cargo run --example sandbox`
+
+use kayton::builtins::print_const;
+use kayton::codegen::compile_source;
+use kayton::vm::{Capabilities, VirtualMachine};
+
+/// Compile and run `src` in a locked-down VM
+fn run_untrusted(src: &str, max_instructions: u64) -> Result<(), String> {
+    let mut vm = VirtualMachine::builder()
+        .capabilities(Capabilities::none())
+        .max_instructions(max_instructions)
+        .with_stdlib()
+        .build();
+    let print = print_const(&vm).ok_or("print is not registered")?;
+    let bytecode = compile_source(src, &mut vm, print)?;
+    vm.eval_program(&bytecode).map_err(|e| e.to_string())
+}
+
+fn main() {
+    let busy: String = (0..100).map(|i| format!("x{} = {}\n", i, i)).collect();
+    let scripts = [
+        ("small script", "x = 1 + 2".to_string()),
+        ("busy script", busy),
+        // `now_ms` needs the time capability, so it is not even defined here
+        ("clock access", "t = now_ms()".to_string()),
+    ];
+    for (name, src) in scripts {
+        match run_untrusted(&src, 50) {
+            Ok(()) => println!("{}: ok", name),
+            Err(err) => println!("{}: rejected ({})", name, err.lines().next().unwrap_or("")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_untrusted;
+
+    #[test]
+    fn limits_and_capabilities_are_enforced() {
+        assert!(run_untrusted("x = 1 + 2", 50).is_ok());
+        let busy: String = (0..100).map(|i| format!("x{} = {}\n", i, i)).collect();
+        assert!(run_untrusted(&busy, 50).unwrap_err().contains("limit"));
+        assert!(run_untrusted("t = now_ms()", 50).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! Calling a script like a function from Rust: compile once, then run it
+//! repeatedly with different inputs preset in its globals.
+//!
+//! `cargo run --example script_function`
+
+use kayton::builtins::print_const;
+use kayton::codegen::compile_source;
+use kayton::vm::VirtualMachine;
+
+struct ScriptFn {
+    vm: VirtualMachine,
+    bytecode: Vec<u8>,
+    input_reg: usize,
+    output_reg: usize,
+}
+
+impl ScriptFn {
+    /// `src` reads global `x` and leaves its result in global `y`
+    fn compile(src: &str) -> Result<Self, String> {
+        let mut vm = VirtualMachine::builder()
+            .with_stdlib()
+            .global_i64("x", 0)
+            .build();
+        let print = print_const(&vm).ok_or("print is not registered")?;
+        let bytecode = compile_source(src, &mut vm, print)?;
+        let input_reg = vm
+            .global_vars
+            .get("x")
+            .ok_or("x is not defined")?
+            .register_id;
+        let output_reg = vm
+            .global_vars
+            .get("y")
+            .ok_or("script does not set y")?
+            .register_id;
+        Ok(Self {
+            vm,
+            bytecode,
+            input_reg,
+            output_reg,
+        })
+    }
+
+    fn call(&mut self, x: i64) -> Result<i64, String> {
+        self.vm.set_register_i64(self.input_reg, x);
+        self.vm
+            .eval_program(&self.bytecode)
+            .map_err(|e| e.to_string())?;
+        Ok(self.vm.get_register_i64(self.output_reg))
+    }
+}
+
+fn main() {
+    let mut f = match ScriptFn::compile("y = x + x + 1") {
+        Ok(f) => f,
+        Err(err) => return eprintln!("error: {}", err),
+    };
+    for x in 0..5 {
+        match f.call(x) {
+            Ok(y) => println!("f({}) = {}", x, y),
+            Err(err) => eprintln!("error: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn script_is_reusable_with_new_inputs() {
+        let mut f = super::ScriptFn::compile("y = x + x + 1").unwrap();
+        assert_eq!(f.call(3).unwrap(), 7);
+        assert_eq!(f.call(-4).unwrap(), -7);
+    }
+}
@@ -0,0 +1,185 @@
+//! A second syntax for the same VM: an S-expression reader that builds the
+//! Kayton AST directly, without the Python-like lexer.
+//!
+//! `cargo run --example sexpr_frontend`
+
+use kayton::builtins::print_const;
+use kayton::frontend::{Ast, BinOp, Expr, Frontend, Stmt, compile_with};
+use kayton::vm::VirtualMachine;
+use std::sync::{Arc, Mutex};
+
+/// `(set name expr)`, `(+ a b ...)`, `(f args ...)`, integers, `"strings"`
+/// and names; `;` starts a comment
+struct SExpr;
+
+#[derive(Debug)]
+enum Form {
+    Atom(String),
+    Str(String),
+    List(Vec<Form>),
+}
+
+/// Forms with the line each starts on
+fn read(src: &str) -> Result<Vec<(Form, u32)>, String> {
+    let mut chars = src.chars().peekable();
+    let mut line = 1;
+    let mut stack: Vec<Vec<Form>> = Vec::new();
+    let mut top = Vec::new();
+    let mut start_line = 1;
+    while let Some(c) = chars.next() {
+        let form = match c {
+            '\n' => {
+                line += 1;
+                continue;
+            }
+            ';' => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            '(' => {
+                if stack.is_empty() {
+                    start_line = line;
+                }
+                stack.push(Vec::new());
+                continue;
+            }
+            ')' => Form::List(stack.pop().ok_or(format!("line {}: unbalanced )", line))?),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => text.push(c),
+                        None => return Err(format!("line {}: unterminated string", line)),
+                    }
+                }
+                Form::Str(text)
+            }
+            c => {
+                let mut atom = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"()\";".contains(*c)) {
+                    atom.push(c);
+                }
+                Form::Atom(atom)
+            }
+        };
+        match stack.last_mut() {
+            Some(list) => list.push(form),
+            None if matches!(form, Form::List(_)) => top.push((form, start_line)),
+            None => top.push((form, line)),
+        }
+    }
+    if !stack.is_empty() {
+        return Err("unbalanced (".to_string());
+    }
+    Ok(top)
+}
+
+fn expr(form: Form) -> Result<Expr, String> {
+    Ok(match form {
+        Form::Str(text) => Expr::Str(text),
+        Form::Atom(atom) => match atom.parse() {
+            Ok(n) => Expr::Int(n),
+            Err(_) => Expr::Ident(atom),
+        },
+        Form::List(items) => {
+            let mut items = items.into_iter();
+            let head = match items.next() {
+                Some(Form::Atom(head)) => head,
+                _ => return Err("expected (operator args...)".to_string()),
+            };
+            let args = items.map(expr).collect::<Result<Vec<_>, _>>()?;
+            if head == "+" {
+                args.into_iter()
+                    .reduce(|left, right| Expr::Binary {
+                        left: Box::new(left),
+                        op: BinOp::Add,
+                        right: Box::new(right),
+                    })
+                    .ok_or("(+) needs arguments")?
+            } else {
+                Expr::Call {
+                    func: Box::new(Expr::Ident(head)),
+                    args,
+                }
+            }
+        }
+    })
+}
+
+impl Frontend for SExpr {
+    fn parse(&self, src: &str) -> Result<Ast, String> {
+        let mut ast = Ast::default();
+        for (form, line) in read(src)? {
+            let stmt = match form {
+                Form::List(items) if matches!(items.first(), Some(Form::Atom(a)) if a == "set") => {
+                    let mut items = items.into_iter().skip(1);
+                    match (items.next(), items.next(), items.next()) {
+                        (Some(Form::Atom(name)), Some(value), None) => Stmt::Assign {
+                            name,
+                            annotation: None,
+                            expr: expr(value)?,
+                        },
+                        _ => return Err(format!("line {}: expected (set name value)", line)),
+                    }
+                }
+                form => Stmt::ExprStmt(expr(form)?),
+            };
+            ast.stmts.push(stmt);
+            ast.lines.push(line);
+        }
+        Ok(ast)
+    }
+}
+
+/// Run `src` and return what it printed
+fn run(src: &str) -> Result<String, String> {
+    let sink = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut vm = VirtualMachine::builder().output(sink.clone()).build();
+    let print = print_const(&vm).ok_or("print is not registered")?;
+    let bytecode = compile_with(&SExpr, src, &mut vm, print)?;
+    vm.eval_program(&bytecode).map_err(|e| e.to_string())?;
+    let out = sink.lock().map_err(|e| e.to_string())?;
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+fn main() {
+    let src = r#"
+        ; the same program as `x = 20` / `print(x + 22)` / `print("a" + "b")`
+        (set x 20)
+        (print (+ x 22))
+        (print (+ "a" "b"))
+    "#;
+    match run(src) {
+        Ok(out) => print!("{}", out),
+        Err(err) => eprintln!("error: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kayton::frontend::KaytonSyntax;
+
+    #[test]
+    fn compiles_to_the_same_bytecode_as_kayton_syntax() {
+        let compile = |frontend: &dyn Frontend, src: &str| {
+            let mut vm = VirtualMachine::builder().with_stdlib().build();
+            let print = print_const(&vm).unwrap();
+            compile_with(frontend, src, &mut vm, print).unwrap()
+        };
+        assert_eq!(
+            compile(&SExpr, "(set x 1)\n(print (+ x 2 3))"),
+            compile(&KaytonSyntax, "x = 1\nprint(x + 2 + 3)")
+        );
+        assert_eq!(run("(set s \"hi\")\n(print s)").unwrap(), "hi\n");
+    }
+
+    #[test]
+    fn reader_and_codegen_errors_are_reported() {
+        assert_eq!(run("(print 1").unwrap_err(), "unbalanced (");
+        assert_eq!(run("(set \"x\" 2)").unwrap_err(), "line 1: expected (set name value)");
+        assert!(run("(print (+ 1 \"a\"))").is_err());
+    }
+}
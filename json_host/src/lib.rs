@@ -0,0 +1,697 @@
+//! JSON parse and stringify host functions.
+//!
+//! Parsed documents live on the heap and scripts refer to them through
+//! handles (a `Box<Json>` pointer in one register), as `vec_host` does for
+//! vectors. Every function returning a handle returns a new one that the
+//! script owns and frees with `json_host_drop`; `json_host_push` and
+//! `json_host_set_key` take ownership of the item they are given.
+//!
+//! Type mapping, as reported by `json_host_type`:
+//!
+//! | tag | JSON               | Kayton                                  |
+//! |-----|--------------------|-----------------------------------------|
+//! | 0   | `null`             | -                                       |
+//! | 1   | `true` / `false`   | bool (`json_host_as_bool`)              |
+//! | 2   | integral number    | i64 (`json_host_as_i64`)                |
+//! | 3   | other number       | f64 bits (`json_host_as_f64`)           |
+//! | 4   | string             | string pointer, length (`json_host_as_str`) |
+//! | 5   | array              | handle per item (`json_host_get`)       |
+//! | 6   | object             | handle per key and value (`json_host_key_at`, `json_host_get_key`) |
+//!
+//! Numbers without a fraction or exponent that fit in an i64 become ints,
+//! everything else becomes a float. Objects keep their key order.
+
+use std::collections::HashMap;
+use std::ptr::NonNull;
+
+use kayton::vm::number_format::format_f64;
+pub use kayton::vm::HostFunctionMetadata;
+
+pub const TYPE_NULL: u64 = 0;
+pub const TYPE_BOOL: u64 = 1;
+pub const TYPE_INT: u64 = 2;
+pub const TYPE_FLOAT: u64 = 3;
+pub const TYPE_STR: u64 = 4;
+pub const TYPE_LIST: u64 = 5;
+pub const TYPE_DICT: u64 = 6;
+
+/// Deepest nesting `Json::parse` accepts, so hostile input cannot overflow the stack
+pub const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    List(Vec<Json>),
+    Dict(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.bytes.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    /// Compact JSON text; fails on NaN and infinities, which JSON cannot express
+    pub fn stringify(&self) -> Result<String, String> {
+        let mut out = String::new();
+        self.write(&mut out)?;
+        Ok(out)
+    }
+
+    pub fn type_tag(&self) -> u64 {
+        match self {
+            Json::Null => TYPE_NULL,
+            Json::Bool(_) => TYPE_BOOL,
+            Json::Int(_) => TYPE_INT,
+            Json::Float(_) => TYPE_FLOAT,
+            Json::Str(_) => TYPE_STR,
+            Json::List(_) => TYPE_LIST,
+            Json::Dict(_) => TYPE_DICT,
+        }
+    }
+
+    fn write(&self, out: &mut String) -> Result<(), String> {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Int(n) => out.push_str(&n.to_string()),
+            Json::Float(f) if !f.is_finite() => {
+                return Err(format!("cannot stringify {}", format_f64(*f)));
+            }
+            Json::Float(f) => out.push_str(&format_f64(*f)),
+            Json::Str(s) => write_str(s, out),
+            Json::List(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write(out)?;
+                }
+                out.push(']');
+            }
+            Json::Dict(entries) => {
+                out.push('{');
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_str(key, out);
+                    out.push(':');
+                    value.write(out)?;
+                }
+                out.push('}');
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_str(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0C}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &str) -> String {
+        format!("JSON error at byte {}: {}", self.pos, msg)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.bytes.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn expect_word(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("invalid literal"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(self.error("unexpected end of input")),
+            Some(b'n') => self.expect_word("null", Json::Null),
+            Some(b't') => self.expect_word("true", Json::Bool(true)),
+            Some(b'f') => self.expect_word("false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::Str),
+            Some(b'[') => self.nested(Self::list),
+            Some(b'{') => self.nested(Self::dict),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Json, String>) -> Result<Json, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn list(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::List(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::List(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn dict(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Dict(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if self.peek() != Some(b':') {
+                return Err(self.error("expected ':'"));
+            }
+            self.pos += 1;
+            entries.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Dict(entries));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        let digits = |p: &mut Self| {
+            let from = p.pos;
+            while let Some(b'0'..=b'9') = p.peek() {
+                p.pos += 1;
+            }
+            p.pos - from
+        };
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        let int_start = self.pos;
+        let int_digits = digits(self);
+        if int_digits == 0 || (int_digits > 1 && self.bytes[int_start] == b'0') {
+            return Err(self.error("invalid number"));
+        }
+        let mut integral = true;
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            integral = false;
+            if digits(self) == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            integral = false;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if digits(self) == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+        // Only ASCII digits and signs were consumed
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap();
+        if integral && let Ok(n) = text.parse::<i64>() {
+            return Ok(Json::Int(n));
+        }
+        text.parse::<f64>()
+            .map(Json::Float)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let hex = self
+            .bytes
+            .get(self.pos..self.pos + 4)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u32::from_str_radix(h, 16).ok())
+            .ok_or_else(|| self.error("invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(hex)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            // Copy the run up to the next quote, escape or control character
+            let run_start = self.pos;
+            while let Some(b) = self.peek() {
+                if b == b'"' || b == b'\\' || b < 0x20 {
+                    break;
+                }
+                self.pos += 1;
+            }
+            // The input is a &str and runs end at ASCII bytes, so this is UTF-8
+            out.push_str(std::str::from_utf8(&self.bytes[run_start..self.pos]).unwrap());
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escape = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 1;
+                    match escape {
+                        b'"' => out.push('"'),
+                        b'\\' => out.push('\\'),
+                        b'/' => out.push('/'),
+                        b'b' => out.push('\u{08}'),
+                        b'f' => out.push('\u{0C}'),
+                        b'n' => out.push('\n'),
+                        b'r' => out.push('\r'),
+                        b't' => out.push('\t'),
+                        b'u' => out.push(self.unicode_escape()?),
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                Some(_) => return Err(self.error("control character in string")),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            // A surrogate pair spells one character outside the BMP
+            if !self.bytes[self.pos..].starts_with(b"\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.pos += 2;
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate"))
+    }
+}
+
+// Layout per call, as in vec_host:
+// base+0: return value(s) start
+// base+1..: params
+// Strings are passed and returned as (pointer, length) register pairs.
+
+fn check_len(registers: &[u64], len: usize) -> Result<(), String> {
+    if registers.len() < len {
+        return Err("insufficient registers".to_string());
+    }
+    Ok(())
+}
+
+fn new_handle(value: Json) -> u64 {
+    Box::into_raw(Box::new(value)) as u64
+}
+
+fn read_handle<'a>(reg: u64) -> Result<&'a mut Json, String> {
+    let ptr = NonNull::new(reg as *mut Json).ok_or_else(|| "null pointer".to_string())?;
+    Ok(unsafe { &mut *ptr.as_ptr() })
+}
+
+/// Take ownership of the value behind a handle, invalidating it
+fn take_handle(reg: u64) -> Result<Json, String> {
+    let ptr = NonNull::new(reg as *mut Json).ok_or_else(|| "null pointer".to_string())?;
+    Ok(*unsafe { Box::from_raw(ptr.as_ptr()) })
+}
+
+fn read_str<'a>(ptr: u64, len: u64) -> Result<&'a str, String> {
+    let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) };
+    std::str::from_utf8(bytes).map_err(|e| e.to_string())
+}
+
+fn type_error(value: &Json, expected: &str) -> String {
+    format!("expected a JSON {}, found type {}", expected, value.type_tag())
+}
+
+// parse(text) -> handle
+#[unsafe(no_mangle)]
+pub fn json_host_parse(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 3)?;
+    let value = Json::parse(read_str(registers[1], registers[2])?)?;
+    registers[0] = new_handle(value);
+    Ok(())
+}
+
+// stringify(handle) -> string handle
+#[unsafe(no_mangle)]
+pub fn json_host_stringify(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    let text = read_handle(registers[1])?.stringify()?;
+    registers[0] = new_handle(Json::Str(text));
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub fn json_host_drop(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    take_handle(registers[1])?;
+    registers[0] = 0;
+    Ok(())
+}
+
+// type(handle) -> TYPE_* tag
+#[unsafe(no_mangle)]
+pub fn json_host_type(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    registers[0] = read_handle(registers[1])?.type_tag();
+    Ok(())
+}
+
+// len(handle) -> items in a list or dict, bytes in a string
+#[unsafe(no_mangle)]
+pub fn json_host_len(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    let len = match read_handle(registers[1])? {
+        Json::List(items) => items.len(),
+        Json::Dict(entries) => entries.len(),
+        Json::Str(s) => s.len(),
+        other => return Err(type_error(other, "list, dict or string")),
+    };
+    registers[0] = len as u64;
+    Ok(())
+}
+
+// get(handle, index) -> copy of a list item or of the value of the index-th dict entry
+#[unsafe(no_mangle)]
+pub fn json_host_get(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 3)?;
+    let index = registers[2] as usize;
+    let item = match read_handle(registers[1])? {
+        Json::List(items) => items.get(index),
+        Json::Dict(entries) => entries.get(index).map(|(_, value)| value),
+        other => return Err(type_error(other, "list or dict")),
+    };
+    let item = item.ok_or_else(|| "index out of bounds".to_string())?;
+    registers[0] = new_handle(item.clone());
+    Ok(())
+}
+
+// key_at(handle, index) -> string handle of the index-th dict key
+#[unsafe(no_mangle)]
+pub fn json_host_key_at(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 3)?;
+    let index = registers[2] as usize;
+    let key = match read_handle(registers[1])? {
+        Json::Dict(entries) => entries.get(index).map(|(key, _)| key.clone()),
+        other => return Err(type_error(other, "dict")),
+    };
+    let key = key.ok_or_else(|| "index out of bounds".to_string())?;
+    registers[0] = new_handle(Json::Str(key));
+    Ok(())
+}
+
+// get_key(handle, key) -> copy of the value under key
+#[unsafe(no_mangle)]
+pub fn json_host_get_key(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 4)?;
+    let key = read_str(registers[2], registers[3])?;
+    let value = match read_handle(registers[1])? {
+        Json::Dict(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone()),
+        other => return Err(type_error(other, "dict")),
+    };
+    let value = value.ok_or_else(|| format!("key not found: {:?}", key))?;
+    registers[0] = new_handle(value);
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub fn json_host_as_i64(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    registers[0] = match read_handle(registers[1])? {
+        Json::Int(n) => *n as u64,
+        other => return Err(type_error(other, "int")),
+    };
+    Ok(())
+}
+
+// as_f64(handle) -> f64 bits; ints are converted
+#[unsafe(no_mangle)]
+pub fn json_host_as_f64(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    registers[0] = match read_handle(registers[1])? {
+        Json::Float(f) => f.to_bits(),
+        Json::Int(n) => (*n as f64).to_bits(),
+        other => return Err(type_error(other, "number")),
+    };
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub fn json_host_as_bool(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    registers[0] = match read_handle(registers[1])? {
+        Json::Bool(b) => *b as u64,
+        other => return Err(type_error(other, "bool")),
+    };
+    Ok(())
+}
+
+// as_str(handle) -> (pointer, length), valid until the handle is dropped
+#[unsafe(no_mangle)]
+pub fn json_host_as_str(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    let (ptr, len) = match read_handle(registers[1])? {
+        Json::Str(s) => (s.as_ptr() as u64, s.len() as u64),
+        other => return Err(type_error(other, "string")),
+    };
+    registers[0] = ptr;
+    registers[1] = len;
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub fn json_host_null(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 1)?;
+    registers[0] = new_handle(Json::Null);
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub fn json_host_from_i64(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    registers[0] = new_handle(Json::Int(registers[1] as i64));
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub fn json_host_from_f64(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    registers[0] = new_handle(Json::Float(f64::from_bits(registers[1])));
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub fn json_host_from_bool(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    registers[0] = new_handle(Json::Bool(registers[1] != 0));
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub fn json_host_from_str(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 3)?;
+    let text = read_str(registers[1], registers[2])?.to_string();
+    registers[0] = new_handle(Json::Str(text));
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub fn json_host_new_list(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 1)?;
+    registers[0] = new_handle(Json::List(Vec::new()));
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub fn json_host_new_dict(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 1)?;
+    registers[0] = new_handle(Json::Dict(Vec::new()));
+    Ok(())
+}
+
+// push(list, item): appends item, taking ownership of it
+#[unsafe(no_mangle)]
+pub fn json_host_push(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 3)?;
+    let Json::List(items) = read_handle(registers[1])? else {
+        return Err(type_error(read_handle(registers[1])?, "list"));
+    };
+    items.push(take_handle(registers[2])?);
+    registers[0] = 0;
+    Ok(())
+}
+
+// set_key(dict, key, item): inserts or replaces key, taking ownership of item
+#[unsafe(no_mangle)]
+pub fn json_host_set_key(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 5)?;
+    let key = read_str(registers[2], registers[3])?;
+    let Json::Dict(entries) = read_handle(registers[1])? else {
+        return Err(type_error(read_handle(registers[1])?, "dict"));
+    };
+    let item = take_handle(registers[4])?;
+    match entries.iter_mut().find(|(k, _)| k == key) {
+        Some((_, value)) => *value = item,
+        None => entries.push((key.to_string(), item)),
+    }
+    registers[0] = 0;
+    Ok(())
+}
+
+// name, num_return_registers, num_params, num_registers; a string
+// parameter is one param in two registers
+const FUNCTIONS: [(&str, usize, usize, usize); 21] = [
+    ("json_host_parse", 1, 1, 3),
+    ("json_host_stringify", 1, 1, 2),
+    ("json_host_drop", 1, 1, 2),
+    ("json_host_type", 1, 1, 2),
+    ("json_host_len", 1, 1, 2),
+    ("json_host_get", 1, 2, 3),
+    ("json_host_key_at", 1, 2, 3),
+    ("json_host_get_key", 1, 2, 4),
+    ("json_host_as_i64", 1, 1, 2),
+    ("json_host_as_f64", 1, 1, 2),
+    ("json_host_as_bool", 1, 1, 2),
+    ("json_host_as_str", 2, 1, 2),
+    ("json_host_null", 1, 0, 1),
+    ("json_host_from_i64", 1, 1, 2),
+    ("json_host_from_f64", 1, 1, 2),
+    ("json_host_from_bool", 1, 1, 2),
+    ("json_host_from_str", 1, 1, 3),
+    ("json_host_new_list", 1, 0, 1),
+    ("json_host_new_dict", 1, 0, 1),
+    ("json_host_push", 1, 2, 3),
+    ("json_host_set_key", 1, 3, 5),
+];
+
+#[unsafe(no_mangle)]
+pub fn json_host_meta_data() -> HashMap<&'static str, HostFunctionMetadata> {
+    let mut m = HashMap::new();
+    for (name, num_return_registers, num_params, num_registers) in FUNCTIONS {
+        m.insert(
+            name,
+            HostFunctionMetadata {
+                name,
+                num_return_registers,
+                num_params,
+                num_registers,
+                version: 0,
+                defaults: &[],
+            },
+        );
+    }
+    m
+}
+
+/// Help text per function, including the JSON to Kayton type mapping, for
+/// `DebugInfo::set_doc`
+#[unsafe(no_mangle)]
+pub fn json_host_docs() -> HashMap<&'static str, &'static str> {
+    HashMap::from([
+        ("json_host_parse", "Parse JSON text into a new handle."),
+        ("json_host_stringify", "Compact JSON text of a value, as a new string handle."),
+        ("json_host_drop", "Free a handle."),
+        (
+            "json_host_type",
+            "Type tag: 0 null, 1 bool, 2 int (i64), 3 float (f64), 4 string, 5 list, 6 dict.",
+        ),
+        ("json_host_len", "Items in a list or dict, or bytes in a string."),
+        ("json_host_get", "New handle to a list item, or to the value of the n-th dict entry."),
+        ("json_host_key_at", "New string handle to the n-th dict key."),
+        ("json_host_get_key", "New handle to the value under a dict key."),
+        ("json_host_as_i64", "The i64 of an integral number."),
+        ("json_host_as_f64", "The f64 bits of a number; ints are converted."),
+        ("json_host_as_bool", "1 for true, 0 for false."),
+        ("json_host_as_str", "A string's (pointer, length), valid until the handle is dropped."),
+        ("json_host_null", "New null handle."),
+        ("json_host_from_i64", "New int handle."),
+        ("json_host_from_f64", "New float handle from f64 bits."),
+        ("json_host_from_bool", "New bool handle; any non-zero value is true."),
+        ("json_host_from_str", "New string handle holding a copy of the string."),
+        ("json_host_new_list", "New empty list handle."),
+        ("json_host_new_dict", "New empty dict handle."),
+        ("json_host_push", "Append an item to a list, taking ownership of the item handle."),
+        (
+            "json_host_set_key",
+            "Set a dict key, taking ownership of the item handle; keys keep insertion order.",
+        ),
+    ])
+}
@@ -0,0 +1,180 @@
+use json_host::*;
+
+fn str_regs(s: &str) -> (u64, u64) {
+    (s.as_ptr() as u64, s.len() as u64)
+}
+
+fn parse(text: &str) -> u64 {
+    let (ptr, len) = str_regs(text);
+    let mut regs = vec![0u64, ptr, len];
+    assert_eq!(json_host_parse(&mut regs), Ok(()));
+    regs[0]
+}
+
+fn as_string(handle: u64) -> String {
+    let mut regs = vec![0u64, handle];
+    assert_eq!(json_host_as_str(&mut regs), Ok(()));
+    let bytes = unsafe { std::slice::from_raw_parts(regs[0] as *const u8, regs[1] as usize) };
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+fn drop_handle(handle: u64) {
+    let mut regs = vec![0u64, handle];
+    assert_eq!(json_host_drop(&mut regs), Ok(()));
+}
+
+#[test]
+fn parse_and_navigate() {
+    let doc = parse(r#"{"name": "kayton", "tags": [1, 2.5, true, null], "nested": {"x": -3}}"#);
+
+    let mut regs_type = vec![0u64, doc];
+    assert_eq!(json_host_type(&mut regs_type), Ok(()));
+    assert_eq!(regs_type[0], TYPE_DICT);
+
+    // name -> "kayton"
+    let (ptr, len) = str_regs("name");
+    let mut regs_key = vec![0u64, doc, ptr, len];
+    assert_eq!(json_host_get_key(&mut regs_key), Ok(()));
+    assert_eq!(as_string(regs_key[0]), "kayton");
+    drop_handle(regs_key[0]);
+
+    // tags has four items of mixed types
+    let (ptr, len) = str_regs("tags");
+    let mut regs_key = vec![0u64, doc, ptr, len];
+    assert_eq!(json_host_get_key(&mut regs_key), Ok(()));
+    let tags = regs_key[0];
+    let mut regs_len = vec![0u64, tags];
+    assert_eq!(json_host_len(&mut regs_len), Ok(()));
+    assert_eq!(regs_len[0], 4);
+    let mut types = Vec::new();
+    for i in 0..4 {
+        let mut regs_get = vec![0u64, tags, i];
+        assert_eq!(json_host_get(&mut regs_get), Ok(()));
+        let mut regs_type = vec![0u64, regs_get[0]];
+        assert_eq!(json_host_type(&mut regs_type), Ok(()));
+        types.push(regs_type[0]);
+        if i == 1 {
+            let mut regs_f = vec![0u64, regs_get[0]];
+            assert_eq!(json_host_as_f64(&mut regs_f), Ok(()));
+            assert_eq!(f64::from_bits(regs_f[0]), 2.5);
+        }
+        drop_handle(regs_get[0]);
+    }
+    assert_eq!(types, vec![TYPE_INT, TYPE_FLOAT, TYPE_BOOL, TYPE_NULL]);
+    drop_handle(tags);
+
+    // dict entries keep their order
+    let mut regs_key_at = vec![0u64, doc, 2];
+    assert_eq!(json_host_key_at(&mut regs_key_at), Ok(()));
+    assert_eq!(as_string(regs_key_at[0]), "nested");
+    drop_handle(regs_key_at[0]);
+    let mut regs_get = vec![0u64, doc, 2];
+    assert_eq!(json_host_get(&mut regs_get), Ok(()));
+    let mut regs_get_x = vec![0u64, regs_get[0], 0];
+    assert_eq!(json_host_get(&mut regs_get_x), Ok(()));
+    let mut regs_i = vec![0u64, regs_get_x[0]];
+    assert_eq!(json_host_as_i64(&mut regs_i), Ok(()));
+    assert_eq!(regs_i[0] as i64, -3);
+    drop_handle(regs_get_x[0]);
+    drop_handle(regs_get[0]);
+
+    // missing keys and wrong types are errors
+    let (ptr, len) = str_regs("missing");
+    let mut regs_key = vec![0u64, doc, ptr, len];
+    assert!(json_host_get_key(&mut regs_key).is_err());
+    let mut regs_i = vec![0u64, doc];
+    assert!(json_host_as_i64(&mut regs_i).is_err());
+
+    drop_handle(doc);
+}
+
+#[test]
+fn build_and_stringify() {
+    let mut regs_dict = vec![0u64];
+    assert_eq!(json_host_new_dict(&mut regs_dict), Ok(()));
+    let dict = regs_dict[0];
+
+    let mut regs_list = vec![0u64];
+    assert_eq!(json_host_new_list(&mut regs_list), Ok(()));
+    let list = regs_list[0];
+    for regs_item in [
+        {
+            let mut regs = vec![0u64, 7];
+            json_host_from_i64(&mut regs).unwrap();
+            regs
+        },
+        {
+            let mut regs = vec![0u64, 0.1f64.to_bits()];
+            json_host_from_f64(&mut regs).unwrap();
+            regs
+        },
+        {
+            let (ptr, len) = str_regs("a\"b\n");
+            let mut regs = vec![0u64, ptr, len];
+            json_host_from_str(&mut regs).unwrap();
+            regs
+        },
+    ] {
+        let mut regs_push = vec![0u64, list, regs_item[0]];
+        assert_eq!(json_host_push(&mut regs_push), Ok(()));
+    }
+
+    let (ptr, len) = str_regs("items");
+    let mut regs_set = vec![0u64, dict, ptr, len, list];
+    assert_eq!(json_host_set_key(&mut regs_set), Ok(()));
+    let mut regs_bool = vec![0u64, 1];
+    json_host_from_bool(&mut regs_bool).unwrap();
+    let (ptr, len) = str_regs("ok");
+    let mut regs_set = vec![0u64, dict, ptr, len, regs_bool[0]];
+    assert_eq!(json_host_set_key(&mut regs_set), Ok(()));
+    // Setting an existing key replaces its value in place
+    let mut regs_null = vec![0u64];
+    json_host_null(&mut regs_null).unwrap();
+    let mut regs_set = vec![0u64, dict, ptr, len, regs_null[0]];
+    assert_eq!(json_host_set_key(&mut regs_set), Ok(()));
+
+    let mut regs_text = vec![0u64, dict];
+    assert_eq!(json_host_stringify(&mut regs_text), Ok(()));
+    let text = as_string(regs_text[0]);
+    assert_eq!(text, r#"{"items":[7,0.1,"a\"b\n"],"ok":null}"#);
+    drop_handle(regs_text[0]);
+
+    // round trip
+    assert_eq!(Json::parse(&text).unwrap().stringify().unwrap(), text);
+    drop_handle(dict);
+}
+
+#[test]
+fn parse_edge_cases() {
+    assert_eq!(Json::parse(" 12 "), Ok(Json::Int(12)));
+    assert_eq!(Json::parse("1e2"), Ok(Json::Float(100.0)));
+    assert_eq!(Json::parse("-0.5"), Ok(Json::Float(-0.5)));
+    // Too big for an i64, so a float
+    assert_eq!(Json::parse("9223372036854775808"), Ok(Json::Float(9.223372036854776e18)));
+    assert_eq!(
+        Json::parse(r#""\u00e9\ud83d\ude00\/""#),
+        Ok(Json::Str("é😀/".to_string()))
+    );
+    assert_eq!(Json::Str("\u{1}".to_string()).stringify(), Ok(r#""\u0001""#.to_string()));
+    assert!(Json::Float(f64::NAN).stringify().is_err());
+
+    for bad in ["", "01", "1.", "[1,]", "{\"a\" 1}", "tru", "\"abc", "\"\\ud800\"", "[] x", "{1: 2}"] {
+        assert!(Json::parse(bad).is_err(), "{:?} parsed", bad);
+    }
+    let deep = "[".repeat(MAX_DEPTH + 1) + &"]".repeat(MAX_DEPTH + 1);
+    assert!(Json::parse(&deep).unwrap_err().contains("too deep"));
+    let ok = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+    assert!(Json::parse(&ok).is_ok());
+}
+
+#[test]
+fn meta_data_and_docs_cover_every_function() {
+    let meta = json_host_meta_data();
+    let docs = json_host_docs();
+    assert_eq!(meta.len(), 21);
+    for name in meta.keys() {
+        assert!(docs.contains_key(name), "{} has no doc", name);
+    }
+    assert_eq!(meta["json_host_as_str"].num_return_registers, 2);
+    assert!(docs["json_host_type"].contains("6 dict"));
+}
@@ -0,0 +1,157 @@
+//! Int-keyed dictionaries for scripts.
+//!
+//! A dictionary lives on the heap and scripts refer to it through a handle
+//! (a `Box<Map>` pointer in one register), as `vec_host` does for vectors.
+//! Keys and values are i64 bit patterns. Dict literals, `d[key]` and
+//! `len(d)` compile to calls of these functions, so they must be registered
+//! with `register_map_host` for scripts to use dicts.
+
+use std::collections::HashMap;
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use kayton::vm::{HostClosure, VirtualMachine};
+pub use kayton::vm::HostFunctionMetadata;
+
+pub type Map = HashMap<u64, u64>;
+
+// Layout per call:
+// base+0: return value
+// base+1..: params
+
+fn read_ptr(reg: u64) -> Result<NonNull<Map>, String> {
+    NonNull::new(reg as *mut Map).ok_or_else(|| "null pointer".to_string())
+}
+
+/// The map behind the handle in `registers[1]`, once `registers` is known
+/// to hold `needed` registers
+fn map_arg(registers: &[u64], needed: usize) -> Result<NonNull<Map>, String> {
+    if registers.len() < needed {
+        return Err("insufficient registers".to_string());
+    }
+    read_ptr(registers[1])
+}
+
+// new() -> map_ptr
+#[unsafe(no_mangle)]
+pub fn map_host_new(registers: &mut [u64]) -> Result<(), String> {
+    let Some(r0) = registers.get_mut(0) else {
+        return Err("missing return register".to_string());
+    };
+    *r0 = Box::into_raw(Box::<Map>::default()) as u64;
+    Ok(())
+}
+
+// drop(map_ptr)
+#[unsafe(no_mangle)]
+pub fn map_host_drop(registers: &mut [u64]) -> Result<(), String> {
+    if registers.len() < 2 {
+        return Err("insufficient registers".to_string());
+    }
+    let nn = read_ptr(registers[1])?;
+    unsafe { drop(Box::from_raw(nn.as_ptr())) };
+    registers[0] = 0;
+    Ok(())
+}
+
+// get(map_ptr, key) -> value
+#[unsafe(no_mangle)]
+pub fn map_host_get(registers: &mut [u64]) -> Result<(), String> {
+    let map = unsafe { &*map_arg(registers, 3)?.as_ptr() };
+    let key = registers[2];
+    match map.get(&key) {
+        Some(&value) => {
+            registers[0] = value;
+            Ok(())
+        }
+        None => Err(format!("key {} not found", key as i64)),
+    }
+}
+
+// set(map_ptr, key, value)
+#[unsafe(no_mangle)]
+pub fn map_host_set(registers: &mut [u64]) -> Result<(), String> {
+    let map = unsafe { &mut *map_arg(registers, 4)?.as_ptr() };
+    map.insert(registers[2], registers[3]);
+    registers[0] = 0;
+    Ok(())
+}
+
+// len(map_ptr) -> entries
+#[unsafe(no_mangle)]
+pub fn map_host_len(registers: &mut [u64]) -> Result<(), String> {
+    let map = unsafe { &*map_arg(registers, 2)?.as_ptr() };
+    registers[0] = map.len() as u64;
+    Ok(())
+}
+
+// contains(map_ptr, key) -> 1 or 0
+#[unsafe(no_mangle)]
+pub fn map_host_contains(registers: &mut [u64]) -> Result<(), String> {
+    let map = unsafe { &*map_arg(registers, 3)?.as_ptr() };
+    registers[0] = map.contains_key(&registers[2]) as u64;
+    Ok(())
+}
+
+// remove(map_ptr, key) -> value
+#[unsafe(no_mangle)]
+pub fn map_host_remove(registers: &mut [u64]) -> Result<(), String> {
+    let map = unsafe { &mut *map_arg(registers, 3)?.as_ptr() };
+    let key = registers[2];
+    match map.remove(&key) {
+        Some(value) => {
+            registers[0] = value;
+            Ok(())
+        }
+        None => Err(format!("key {} not found", key as i64)),
+    }
+}
+
+type MapFn = fn(&mut [u64]) -> Result<(), String>;
+
+// name, function, num_return_registers, num_params, num_registers
+const FUNCTIONS: [(&str, MapFn, usize, usize, usize); 7] = [
+    ("map_host_new", map_host_new, 1, 0, 1),
+    ("map_host_drop", map_host_drop, 1, 1, 2),
+    ("map_host_get", map_host_get, 1, 2, 3),
+    ("map_host_set", map_host_set, 1, 3, 4),
+    ("map_host_len", map_host_len, 1, 1, 2),
+    ("map_host_contains", map_host_contains, 1, 2, 3),
+    ("map_host_remove", map_host_remove, 1, 2, 3),
+];
+
+#[unsafe(no_mangle)]
+pub fn map_host_meta_data() -> HashMap<&'static str, HostFunctionMetadata> {
+    let mut m = HashMap::new();
+    for (name, _, num_return_registers, num_params, num_registers) in FUNCTIONS {
+        m.insert(
+            name,
+            HostFunctionMetadata {
+                name,
+                num_return_registers,
+                num_params,
+                num_registers,
+                version: 0,
+                defaults: &[],
+            },
+        );
+    }
+    m
+}
+
+/// Register every `map_host_*` function with `vm`. Dict literals, `d[key]`
+/// and `len()` on dicts compile to calls of these.
+pub fn register_map_host(vm: &mut VirtualMachine) {
+    let meta = map_host_meta_data();
+    for (name, func, _, _, num_registers) in FUNCTIONS {
+        let call: HostClosure = Arc::new(move |base, registers| {
+            let mut frame: Vec<u64> = (0..num_registers).map(|i| registers.get(base + i)).collect();
+            func(&mut frame)?;
+            for (i, value) in frame.into_iter().enumerate() {
+                registers.set(base + i, value);
+            }
+            Ok(())
+        });
+        vm.host_functions.register_metadata(meta[name].clone(), call);
+    }
+}
@@ -0,0 +1,64 @@
+use map_host::*;
+
+#[test]
+fn map_lifecycle() {
+    let mut regs = vec![0u64; 1];
+    assert_eq!(map_host_new(&mut regs), Ok(()));
+    let ptr = regs[0];
+    assert_ne!(ptr, 0);
+
+    let mut regs_set = vec![0u64, ptr, 7, 70];
+    assert_eq!(map_host_set(&mut regs_set), Ok(()));
+    let mut regs_set = vec![0u64, ptr, -1i64 as u64, 10];
+    assert_eq!(map_host_set(&mut regs_set), Ok(()));
+
+    let mut regs_len = vec![0u64, ptr];
+    assert_eq!(map_host_len(&mut regs_len), Ok(()));
+    assert_eq!(regs_len[0], 2);
+
+    let mut regs_get = vec![0u64, ptr, 7];
+    assert_eq!(map_host_get(&mut regs_get), Ok(()));
+    assert_eq!(regs_get[0], 70);
+    let mut regs_get = vec![0u64, ptr, 8];
+    assert_eq!(map_host_get(&mut regs_get), Err("key 8 not found".to_string()));
+
+    let mut regs_contains = vec![0u64, ptr, -1i64 as u64];
+    assert_eq!(map_host_contains(&mut regs_contains), Ok(()));
+    assert_eq!(regs_contains[0], 1);
+
+    let mut regs_remove = vec![0u64, ptr, -1i64 as u64];
+    assert_eq!(map_host_remove(&mut regs_remove), Ok(()));
+    assert_eq!(regs_remove[0], 10);
+    let mut regs_remove = vec![0u64, ptr, -1i64 as u64];
+    assert_eq!(map_host_remove(&mut regs_remove), Err("key -1 not found".to_string()));
+
+    let mut regs_drop = vec![0u64, ptr];
+    assert_eq!(map_host_drop(&mut regs_drop), Ok(()));
+    assert_eq!(map_host_len(&mut [0u64, 0]), Err("null pointer".to_string()));
+    assert_eq!(map_host_get(&mut [0u64, ptr]), Err("insufficient registers".to_string()));
+}
+
+#[test]
+fn scripts_use_dict_literals_and_subscripts() {
+    use kayton::builtins::print_const;
+    use kayton::codegen::compile_source;
+    use kayton::vm::VirtualMachine;
+
+    let mut vm = VirtualMachine::builder().with_stdlib().build();
+    register_map_host(&mut vm);
+    let print = print_const(&vm).unwrap();
+    let src = "d = {1: 10, 2: 20}\nd[3] = d[1] + d[2]\nd[1] = 5\nn = len(d)\ne = {}";
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let n = vm.global_vars.get("n").unwrap().register_id;
+    assert_eq!(vm.get_register_i64(n), 3);
+    let d = vm.global_vars.get("d").unwrap().register_id;
+    let d = unsafe { Box::from_raw(vm.get_register_i64(d) as *mut Map) };
+    assert_eq!(*d, Map::from([(1, 5), (2, 20), (3, 30)]));
+    let e = vm.global_vars.get("e").unwrap().register_id;
+    drop(unsafe { Box::from_raw(vm.get_register_i64(e) as *mut Map) });
+
+    let bytecode = compile_source("f = {1: 2}\nx = f[9]", &mut vm, print).unwrap();
+    let err = vm.eval_program(&bytecode).unwrap_err().to_string();
+    assert!(err.contains("key 9 not found"), "{}", err);
+}
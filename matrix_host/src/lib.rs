@@ -0,0 +1,216 @@
+//! 2D f64 matrix host functions.
+//!
+//! A matrix lives on the heap, stored row-major in one contiguous buffer, and
+//! scripts refer to it through a handle (a `Box<Matrix>` pointer in one
+//! register), as `vec_host` does for vectors. Every function returning a
+//! handle returns a new matrix that the script owns and frees with
+//! `matrix_host_drop`. Elements cross registers as f64 bit patterns.
+
+use std::collections::HashMap;
+use std::ptr::NonNull;
+
+pub use kayton::vm::HostFunctionMetadata;
+
+/// Largest number of elements a matrix may hold
+pub const MAX_ELEMENTS: usize = 1 << 28;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    pub rows: usize,
+    pub cols: usize,
+    /// Row-major: element (r, c) is at `r * cols + c`
+    pub data: Vec<f64>,
+}
+
+impl Matrix {
+    pub fn zeros(rows: usize, cols: usize) -> Result<Self, String> {
+        let len = rows
+            .checked_mul(cols)
+            .filter(|len| *len <= MAX_ELEMENTS)
+            .ok_or_else(|| format!("matrix of {}x{} is too large", rows, cols))?;
+        Ok(Self {
+            rows,
+            cols,
+            data: vec![0.0; len],
+        })
+    }
+
+    fn index(&self, row: u64, col: u64) -> Result<usize, String> {
+        let (row, col) = (row as usize, col as usize);
+        if row >= self.rows || col >= self.cols {
+            return Err(format!(
+                "index ({}, {}) out of bounds for a {}x{} matrix",
+                row, col, self.rows, self.cols
+            ));
+        }
+        Ok(row * self.cols + col)
+    }
+
+    pub fn matmul(&self, other: &Matrix) -> Result<Matrix, String> {
+        if self.cols != other.rows {
+            return Err(format!(
+                "cannot multiply a {}x{} matrix by a {}x{} matrix",
+                self.rows, self.cols, other.rows, other.cols
+            ));
+        }
+        let mut out = Matrix::zeros(self.rows, other.cols)?;
+        // i-k-j order walks both `other` and `out` along rows
+        for i in 0..self.rows {
+            let out_row = &mut out.data[i * other.cols..(i + 1) * other.cols];
+            for k in 0..self.cols {
+                let a = self.data[i * self.cols + k];
+                let other_row = &other.data[k * other.cols..(k + 1) * other.cols];
+                for (o, b) in out_row.iter_mut().zip(other_row) {
+                    *o += a * b;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut data = Vec::with_capacity(self.data.len());
+        for c in 0..self.cols {
+            data.extend((0..self.rows).map(|r| self.data[r * self.cols + c]));
+        }
+        Matrix {
+            rows: self.cols,
+            cols: self.rows,
+            data,
+        }
+    }
+}
+
+fn check_len(registers: &[u64], len: usize) -> Result<(), String> {
+    if registers.len() < len {
+        return Err("insufficient registers".to_string());
+    }
+    Ok(())
+}
+
+fn new_handle(matrix: Matrix) -> u64 {
+    Box::into_raw(Box::new(matrix)) as u64
+}
+
+fn read_handle<'a>(reg: u64) -> Result<&'a mut Matrix, String> {
+    let ptr = NonNull::new(reg as *mut Matrix).ok_or_else(|| "null pointer".to_string())?;
+    Ok(unsafe { &mut *ptr.as_ptr() })
+}
+
+// new(rows, cols) -> handle to a zero matrix
+#[unsafe(no_mangle)]
+pub fn matrix_host_new(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 3)?;
+    let matrix = Matrix::zeros(registers[1] as usize, registers[2] as usize)?;
+    registers[0] = new_handle(matrix);
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub fn matrix_host_drop(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    let ptr = NonNull::new(registers[1] as *mut Matrix).ok_or_else(|| "null pointer".to_string())?;
+    drop(unsafe { Box::from_raw(ptr.as_ptr()) });
+    registers[0] = 0;
+    Ok(())
+}
+
+// rows(handle) -> number of rows
+#[unsafe(no_mangle)]
+pub fn matrix_host_rows(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    registers[0] = read_handle(registers[1])?.rows as u64;
+    Ok(())
+}
+
+// cols(handle) -> number of columns
+#[unsafe(no_mangle)]
+pub fn matrix_host_cols(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    registers[0] = read_handle(registers[1])?.cols as u64;
+    Ok(())
+}
+
+// get(handle, row, col) -> f64 bits
+#[unsafe(no_mangle)]
+pub fn matrix_host_get(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 4)?;
+    let matrix = read_handle(registers[1])?;
+    let index = matrix.index(registers[2], registers[3])?;
+    registers[0] = matrix.data[index].to_bits();
+    Ok(())
+}
+
+// set(handle, row, col, f64 bits)
+#[unsafe(no_mangle)]
+pub fn matrix_host_set(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 5)?;
+    let matrix = read_handle(registers[1])?;
+    let index = matrix.index(registers[2], registers[3])?;
+    matrix.data[index] = f64::from_bits(registers[4]);
+    registers[0] = 0;
+    Ok(())
+}
+
+// matmul(a, b) -> handle to a * b
+#[unsafe(no_mangle)]
+pub fn matrix_host_matmul(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 3)?;
+    let product = read_handle(registers[1])?.matmul(read_handle(registers[2])?)?;
+    registers[0] = new_handle(product);
+    Ok(())
+}
+
+// transpose(handle) -> handle to the transpose
+#[unsafe(no_mangle)]
+pub fn matrix_host_transpose(registers: &mut [u64]) -> Result<(), String> {
+    check_len(registers, 2)?;
+    registers[0] = new_handle(read_handle(registers[1])?.transpose());
+    Ok(())
+}
+
+// name, num_return_registers, num_params, num_registers
+const FUNCTIONS: [(&str, usize, usize, usize); 8] = [
+    ("matrix_host_new", 1, 2, 3),
+    ("matrix_host_drop", 1, 1, 2),
+    ("matrix_host_rows", 1, 1, 2),
+    ("matrix_host_cols", 1, 1, 2),
+    ("matrix_host_get", 1, 3, 4),
+    ("matrix_host_set", 1, 4, 5),
+    ("matrix_host_matmul", 1, 2, 3),
+    ("matrix_host_transpose", 1, 1, 2),
+];
+
+#[unsafe(no_mangle)]
+pub fn matrix_host_meta_data() -> HashMap<&'static str, HostFunctionMetadata> {
+    let mut m = HashMap::new();
+    for (name, num_return_registers, num_params, num_registers) in FUNCTIONS {
+        m.insert(
+            name,
+            HostFunctionMetadata {
+                name,
+                num_return_registers,
+                num_params,
+                num_registers,
+                version: 0,
+                defaults: &[],
+            },
+        );
+    }
+    m
+}
+
+/// Help text per function, for `DebugInfo::set_doc`
+#[unsafe(no_mangle)]
+pub fn matrix_host_docs() -> HashMap<&'static str, &'static str> {
+    HashMap::from([
+        ("matrix_host_new", "New rows x cols matrix of zeros."),
+        ("matrix_host_drop", "Free a matrix."),
+        ("matrix_host_rows", "Number of rows."),
+        ("matrix_host_cols", "Number of columns."),
+        ("matrix_host_get", "Element at (row, col), as f64."),
+        ("matrix_host_set", "Set the element at (row, col) to an f64."),
+        ("matrix_host_matmul", "New matrix holding the product a * b."),
+        ("matrix_host_transpose", "New matrix holding the transpose."),
+    ])
+}
@@ -0,0 +1,84 @@
+use matrix_host::*;
+
+fn call(func: fn(&mut [u64]) -> Result<(), String>, args: &[u64]) -> Result<u64, String> {
+    let mut regs = vec![0u64];
+    regs.extend_from_slice(args);
+    func(&mut regs)?;
+    Ok(regs[0])
+}
+
+/// New matrix with `rows` filled in through `matrix_host_set`
+fn matrix(rows: &[&[f64]]) -> u64 {
+    let handle = call(matrix_host_new, &[rows.len() as u64, rows[0].len() as u64]).unwrap();
+    for (r, row) in rows.iter().enumerate() {
+        for (c, value) in row.iter().enumerate() {
+            call(matrix_host_set, &[handle, r as u64, c as u64, value.to_bits()]).unwrap();
+        }
+    }
+    handle
+}
+
+fn get(handle: u64, row: u64, col: u64) -> f64 {
+    f64::from_bits(call(matrix_host_get, &[handle, row, col]).unwrap())
+}
+
+fn drop_matrix(handle: u64) {
+    assert_eq!(call(matrix_host_drop, &[handle]), Ok(0));
+}
+
+#[test]
+fn create_get_set() {
+    let m = call(matrix_host_new, &[2, 3]).unwrap();
+    assert_eq!(call(matrix_host_rows, &[m]), Ok(2));
+    assert_eq!(call(matrix_host_cols, &[m]), Ok(3));
+    assert_eq!(get(m, 1, 2), 0.0);
+    call(matrix_host_set, &[m, 1, 2, 2.5f64.to_bits()]).unwrap();
+    assert_eq!(get(m, 1, 2), 2.5);
+    let stored = unsafe { &*(m as *const Matrix) };
+    assert_eq!(stored.data, vec![0.0, 0.0, 0.0, 0.0, 0.0, 2.5]);
+
+    let err = call(matrix_host_get, &[m, 2, 0]).unwrap_err();
+    assert_eq!(err, "index (2, 0) out of bounds for a 2x3 matrix");
+    assert!(call(matrix_host_set, &[m, 0, 3, 0]).is_err());
+    drop_matrix(m);
+
+    assert!(call(matrix_host_new, &[u64::MAX, 2]).is_err());
+}
+
+#[test]
+fn matmul_and_transpose() {
+    let a = matrix(&[&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]]);
+    let b = matrix(&[&[7.0, 8.0], &[9.0, 10.0], &[11.0, 12.0]]);
+
+    let product = call(matrix_host_matmul, &[a, b]).unwrap();
+    assert_eq!((call(matrix_host_rows, &[product]), call(matrix_host_cols, &[product])), (Ok(2), Ok(2)));
+    assert_eq!(
+        [get(product, 0, 0), get(product, 0, 1), get(product, 1, 0), get(product, 1, 1)],
+        [58.0, 64.0, 139.0, 154.0]
+    );
+
+    let t = call(matrix_host_transpose, &[a]).unwrap();
+    assert_eq!((call(matrix_host_rows, &[t]), call(matrix_host_cols, &[t])), (Ok(3), Ok(2)));
+    assert_eq!([get(t, 0, 1), get(t, 2, 0)], [4.0, 3.0]);
+
+    let err = call(matrix_host_matmul, &[a, a]).unwrap_err();
+    assert_eq!(err, "cannot multiply a 2x3 matrix by a 2x3 matrix");
+    // a * aᵀ is square
+    let square = call(matrix_host_matmul, &[a, t]).unwrap();
+    assert_eq!(get(square, 1, 1), 77.0);
+
+    for handle in [a, b, product, t, square] {
+        drop_matrix(handle);
+    }
+}
+
+#[test]
+fn meta_data_and_docs_cover_every_function() {
+    let meta = matrix_host_meta_data();
+    let docs = matrix_host_docs();
+    assert_eq!(meta.len(), docs.len());
+    for (name, m) in &meta {
+        assert_eq!(m.num_registers, m.num_params + 1, "{}", name);
+        assert!(docs.contains_key(name), "{} has no doc", name);
+    }
+}
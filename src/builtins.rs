@@ -0,0 +1,676 @@
+use crate::datetime::{format_timestamp, parse_timestamp};
+use crate::vm::const_pool::ValueType;
+use crate::vm::number_format::{format_f64, format_radix, parse_int};
+pub use crate::vm::number_format::PRINT_F64;
+use crate::vm::{HostClosure, Registers, StringHeap, VirtualMachine, request_exit};
+use crate::write;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Destination for `print` output when not writing to the console
+pub type OutputSink = Arc<Mutex<dyn Write + Send>>;
+
+/// Wall clock used by time host functions, in milliseconds since the Unix epoch
+pub type Clock = Arc<dyn Fn() -> u64 + Send + Sync>;
+
+/// Read the value printed by `print`.
+/// Layout: base+1 holds an i64, f64 bits or a string pointer, base+2 the
+/// string length (0 for integers, `PRINT_F64` for floats).
+fn print_text(base: usize, registers: &Registers) -> Vec<u8> {
+    let val = registers.get(base + 1);
+    let len = registers.get(base + 2);
+    if len == 0 {
+        format!("{}", val as i64).into_bytes()
+    } else if len == PRINT_F64 {
+        format_f64(f64::from_bits(val)).into_bytes()
+    } else {
+        unsafe { std::slice::from_raw_parts(val as *const u8, len as usize) }.to_vec()
+    }
+}
+
+/// Print a value to the console
+pub fn host_print(base: usize, registers: &mut Registers) -> Result<(), String> {
+    write::println_to_console(&print_text(base, registers));
+    Ok(())
+}
+
+pub fn system_clock() -> Clock {
+    Arc::new(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    })
+}
+
+/// Register the builtin host functions with console output and the system clock,
+/// returning the const index holding `print`
+pub fn register_builtins(vm: &mut VirtualMachine) -> u16 {
+    register_stdlib(vm, None, None, Vec::new())
+}
+
+/// Register the builtin host functions. Functions that need a capability are
+/// only registered when `vm.capabilities` grants it.
+pub fn register_stdlib(
+    vm: &mut VirtualMachine,
+    output: Option<OutputSink>,
+    clock: Option<Clock>,
+    args: Vec<String>,
+) -> u16 {
+    let bench_output = output.clone();
+    let print_idx = match output {
+        Some(sink) => vm.host_functions.register_closure(
+            "print",
+            0,
+            1,
+            3,
+            Arc::new(move |base, registers| {
+                let mut text = print_text(base, registers);
+                text.push(b'\n');
+                let mut sink = sink.lock().map_err(|e| e.to_string())?;
+                sink.write_all(&text).map_err(|e| e.to_string())
+            }),
+        ),
+        None => vm.host_functions.register("print", 0, 1, 3, host_print),
+    };
+    vm.debug_info
+        .set_doc("print", "Print an integer or string followed by a newline.");
+    let print_const = vm
+        .const_pool
+        .add_value("print", print_idx as u64, ValueType::FuncHost) as u16;
+
+    register_number_builtins(vm);
+    register_str_builtins(vm);
+    register_bytes_builtins(vm);
+    register_timestamp_builtins(vm);
+
+    #[cfg(feature = "http")]
+    if vm.capabilities.net {
+        register_http_builtins(vm);
+    }
+
+    if vm.capabilities.env {
+        register_env_builtins(vm);
+    }
+    if vm.capabilities.process {
+        register_process_builtins(vm, args);
+    }
+
+    if vm.capabilities.time {
+        let clock = clock.unwrap_or_else(system_clock);
+        let now_idx = vm.host_functions.register_closure(
+            "now_ms",
+            1,
+            0,
+            1,
+            Arc::new(move |base, registers| {
+                registers.set(base, clock());
+                Ok(())
+            }),
+        );
+        vm.debug_info
+            .set_doc("now_ms", "Milliseconds since the Unix epoch (requires the time capability).");
+        vm.const_pool
+            .add_value("now_ms", now_idx as u64, ValueType::FuncHost);
+        register_bench_builtins(vm, bench_output);
+    }
+
+    print_const
+}
+
+/// `monotonic_ns`, and `bench_report`, which `bench(name, f, iterations)`
+/// compiles to a call of after timing `f` with `monotonic_ns`
+fn register_bench_builtins(vm: &mut VirtualMachine, output: Option<OutputSink>) {
+    let start = Instant::now();
+    let idx = vm.host_functions.register_closure(
+        "monotonic_ns",
+        1,
+        0,
+        1,
+        Arc::new(move |base, registers| {
+            registers.set(base, start.elapsed().as_nanos() as u64);
+            Ok(())
+        }),
+    );
+    vm.debug_info.set_doc(
+        "monotonic_ns",
+        "Nanoseconds on a clock that never goes back, for timing (requires the time capability).",
+    );
+    vm.const_pool.add_value("monotonic_ns", idx as u64, ValueType::FuncHost);
+
+    // bench_report(name, iterations, total_ns, min_ns, max_ns)
+    let idx = vm.host_functions.register_closure(
+        "bench_report",
+        0,
+        5,
+        7,
+        Arc::new(move |base, registers| {
+            let name = str_arg(registers, base + 1)?;
+            let [iterations, total, min, max] = [3, 4, 5, 6].map(|i| registers.get(base + i));
+            let line = match total.checked_div(iterations) {
+                Some(mean) => format!(
+                    "bench {}: {} iterations, mean {}, min {}, max {}",
+                    name,
+                    iterations,
+                    format_ns(mean),
+                    format_ns(min),
+                    format_ns(max)
+                ),
+                None => format!("bench {}: 0 iterations", name),
+            };
+            match &output {
+                Some(sink) => {
+                    let mut sink = sink.lock().map_err(|e| e.to_string())?;
+                    writeln!(sink, "{}", line).map_err(|e| e.to_string())
+                }
+                None => {
+                    write::println_to_console(line.as_bytes());
+                    Ok(())
+                }
+            }
+        }),
+    );
+    vm.debug_info.set_doc(
+        "bench_report",
+        "Print timing statistics of a benchmark; called by bench(name, f, iterations).",
+    );
+    vm.const_pool.add_value("bench_report", idx as u64, ValueType::FuncHost);
+}
+
+/// A duration in the largest unit that keeps it at least 1: `850 ns`, `1.25 us`
+fn format_ns(ns: u64) -> String {
+    match ns {
+        0..1_000 => format!("{} ns", ns),
+        1_000..1_000_000 => format!("{:.2} us", ns as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.2} ms", ns as f64 / 1e6),
+        _ => format!("{:.2} s", ns as f64 / 1e9),
+    }
+}
+
+/// Read the string argument at `reg` (pointer) and `reg + 1` (length)
+pub(crate) fn str_arg(registers: &Registers, reg: usize) -> Result<&str, String> {
+    let ptr = registers.get(reg) as *const u8;
+    let len = registers.get(reg + 1) as usize;
+    let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+    std::str::from_utf8(bytes).map_err(|e| e.to_string())
+}
+
+/// `parse_int`, `to_hex` and `to_bin`. Strings returned by the formatting
+/// functions live in a heap owned by the functions, as long as the VM.
+fn register_number_builtins(vm: &mut VirtualMachine) {
+    let parse_idx = vm.host_functions.register("parse_int", 1, 2, 4, |base, registers| {
+        let value = parse_int(str_arg(registers, base + 1)?, registers.get(base + 3) as u32)?;
+        registers.set(base, value as u64);
+        Ok(())
+    });
+    vm.debug_info.set_doc(
+        "parse_int",
+        "Parse a string as an integer in the given base (2 to 36, or 0 to use its 0x/0o/0b prefix).",
+    );
+    vm.const_pool
+        .add_value("parse_int", parse_idx as u64, ValueType::FuncHost);
+
+    let strings = Arc::new(Mutex::new(StringHeap::new()));
+    for (name, radix, doc) in [
+        ("to_hex", 16, "Format an integer in hexadecimal, e.g. 0xff."),
+        ("to_bin", 2, "Format an integer in binary, e.g. 0b101."),
+    ] {
+        let strings = strings.clone();
+        let idx = vm.host_functions.register_closure(
+            name,
+            2,
+            1,
+            2,
+            Arc::new(move |base, registers| {
+                let text = format_radix(registers.get(base + 1) as i64, radix);
+                let ptr = strings.lock().map_err(|e| e.to_string())?.alloc(text.as_bytes());
+                registers.set(base, ptr as u64);
+                registers.set(base + 1, text.len() as u64);
+                Ok(())
+            }),
+        );
+        vm.debug_info.set_doc(name, doc);
+        vm.const_pool.add_value(name, idx as u64, ValueType::FuncHost);
+    }
+}
+
+/// `str_slice(s, start, end)`: the bytes from `start` up to `end` as a
+/// view of `s`, with no copy. Strings are never written, so a view of a
+/// literal points into the const pool for good, and one of a runtime string
+/// is copied only if a global still holds it when an
+/// `AllocationScope::Execution` run frees that string.
+fn register_str_builtins(vm: &mut VirtualMachine) {
+    let idx = vm.host_functions.register("str_slice", 2, 3, 5, |base, registers| {
+        let text = str_arg(registers, base + 1)?;
+        let (start, end) = (registers.get(base + 3), registers.get(base + 4));
+        if start > end || end > text.len() as u64 {
+            return Err(format!(
+                "str_slice({}, {}) is out of range for {} bytes",
+                start as i64,
+                end as i64,
+                text.len()
+            ));
+        }
+        let (start, end) = (start as usize, end as usize);
+        if !text.is_char_boundary(start) || !text.is_char_boundary(end) {
+            return Err(format!("str_slice({}, {}) splits a character", start, end));
+        }
+        set_slice(registers, base, (text[start..].as_ptr() as u64, (end - start) as u64));
+        Ok(())
+    });
+    vm.debug_info.set_doc(
+        "str_slice",
+        "Substring from byte start up to end; shares memory with the original instead of copying.",
+    );
+    vm.const_pool.add_value("str_slice", idx as u64, ValueType::FuncHost);
+}
+
+/// Buffers created by the bytes builtins. Only these are writable; bytes
+/// literals live in the const pool and must be copied with `bytes_copy` first.
+#[derive(Default)]
+struct BytesHeap {
+    buffers: Vec<Box<[u8]>>,
+}
+
+impl BytesHeap {
+    fn alloc(&mut self, data: Vec<u8>) -> (u64, u64) {
+        let data = data.into_boxed_slice();
+        let handle = (data.as_ptr() as u64, data.len() as u64);
+        self.buffers.push(data);
+        handle
+    }
+
+    /// The heap memory at `ptr..ptr + len`, if one buffer holds all of it
+    fn writable(&mut self, ptr: u64, len: usize) -> Option<&mut [u8]> {
+        self.buffers.iter_mut().find_map(|buf| {
+            let start = buf.as_ptr() as u64;
+            let offset = ptr.checked_sub(start)? as usize;
+            buf.get_mut(offset..offset.checked_add(len)?)
+        })
+    }
+}
+
+/// The bytes argument at `reg` (pointer) and `reg + 1` (length)
+fn bytes_arg<'a>(registers: &Registers, reg: usize) -> &'a [u8] {
+    let ptr = registers.get(reg) as *const u8;
+    let len = registers.get(reg + 1) as usize;
+    unsafe { std::slice::from_raw_parts(ptr, len) }
+}
+
+fn set_slice(registers: &mut Registers, base: usize, (ptr, len): (u64, u64)) {
+    registers.set(base, ptr);
+    registers.set(base + 1, len);
+}
+
+/// Bounds-checked `offset..offset + width` of a `len`-byte buffer
+fn field_range(name: &str, offset: u64, width: usize, len: usize) -> Result<std::ops::Range<usize>, String> {
+    let offset = offset as usize;
+    match offset.checked_add(width) {
+        Some(end) if end <= len => Ok(offset..end),
+        _ => Err(format!("{} at offset {} is out of range for {} bytes", name, offset as i64, len)),
+    }
+}
+
+/// Binary data: `bytes_new`, `bytes_copy`, `bytes_len`, `bytes_slice`,
+/// little-endian `read_u8`..`read_u64` / `write_u8`..`write_u64` at byte
+/// offsets, and hex conversion with `bytes_hex` / `bytes_from_hex`
+fn register_bytes_builtins(vm: &mut VirtualMachine) {
+    let heap = Arc::new(Mutex::new(BytesHeap::default()));
+    let mut register = |name: &'static str,
+                        returns: usize,
+                        params: usize,
+                        registers: usize,
+                        doc: &str,
+                        func: HostClosure| {
+        let idx = vm
+            .host_functions
+            .register_closure(name, returns, params, registers, func);
+        vm.debug_info.set_doc(name, doc);
+        vm.const_pool.add_value(name, idx as u64, ValueType::FuncHost);
+        idx
+    };
+
+    let new_heap = heap.clone();
+    let new_idx = register(
+        "bytes_new",
+        2,
+        1,
+        2,
+        "New writable bytes of the given length, all zero.",
+        Arc::new(move |base, registers| {
+            let len = registers.get(base + 1) as usize;
+            let mut heap = new_heap.lock().map_err(|e| e.to_string())?;
+            set_slice(registers, base, heap.alloc(vec![0; len]));
+            Ok(())
+        }),
+    );
+    let copy_heap = heap.clone();
+    let copy_idx = register(
+        "bytes_copy",
+        2,
+        1,
+        3,
+        "Writable copy of bytes, e.g. of a b\"...\" literal.",
+        Arc::new(move |base, registers| {
+            let data = bytes_arg(registers, base + 1).to_vec();
+            let mut heap = copy_heap.lock().map_err(|e| e.to_string())?;
+            set_slice(registers, base, heap.alloc(data));
+            Ok(())
+        }),
+    );
+    register(
+        "bytes_len",
+        1,
+        1,
+        3,
+        "Length of bytes.",
+        Arc::new(|base, registers| {
+            registers.set(base, registers.get(base + 2));
+            Ok(())
+        }),
+    );
+    let slice_idx = register(
+        "bytes_slice",
+        2,
+        3,
+        5,
+        "Bytes from start up to end; shares memory (and writability) with the original.",
+        Arc::new(|base, registers| {
+            let (ptr, len) = (registers.get(base + 1), registers.get(base + 2));
+            let (start, end) = (registers.get(base + 3), registers.get(base + 4));
+            if start > end || end > len {
+                return Err(format!(
+                    "bytes_slice({}, {}) is out of range for {} bytes",
+                    start as i64, end as i64, len
+                ));
+            }
+            set_slice(registers, base, (ptr + start, end - start));
+            Ok(())
+        }),
+    );
+    for (width, read_name, write_name) in [
+        (1, "read_u8", "write_u8"),
+        (2, "read_u16", "write_u16"),
+        (4, "read_u32", "write_u32"),
+        (8, "read_u64", "write_u64"),
+    ] {
+        register(
+            read_name,
+            1,
+            2,
+            4,
+            "Little-endian unsigned integer at a byte offset.",
+            Arc::new(move |base, registers| {
+                let data = bytes_arg(registers, base + 1);
+                let range = field_range(read_name, registers.get(base + 3), width, data.len())?;
+                let mut le = [0; 8];
+                le[..width].copy_from_slice(&data[range]);
+                registers.set(base, u64::from_le_bytes(le));
+                Ok(())
+            }),
+        );
+        let write_heap = heap.clone();
+        register(
+            write_name,
+            0,
+            3,
+            5,
+            "Store a little-endian unsigned integer at a byte offset of writable bytes.",
+            Arc::new(move |base, registers| {
+                let (ptr, len) = (registers.get(base + 1), registers.get(base + 2) as usize);
+                let range = field_range(write_name, registers.get(base + 3), width, len)?;
+                let value = registers.get(base + 4);
+                if width < 8 && value >> (width * 8) != 0 {
+                    return Err(format!("{}: {} does not fit", write_name, value as i64));
+                }
+                let mut heap = write_heap.lock().map_err(|e| e.to_string())?;
+                let field = heap
+                    .writable(ptr + range.start as u64, width)
+                    .ok_or_else(|| format!("{}: bytes are read-only; use bytes_copy", write_name))?;
+                field.copy_from_slice(&value.to_le_bytes()[..width]);
+                Ok(())
+            }),
+        );
+    }
+    let hex_heap = heap.clone();
+    register(
+        "bytes_hex",
+        2,
+        1,
+        3,
+        "Lowercase hex string of bytes.",
+        Arc::new(move |base, registers| {
+            let data = bytes_arg(registers, base + 1);
+            let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+            let mut heap = hex_heap.lock().map_err(|e| e.to_string())?;
+            set_slice(registers, base, heap.alloc(hex.into_bytes()));
+            Ok(())
+        }),
+    );
+    let from_hex_idx = register(
+        "bytes_from_hex",
+        2,
+        1,
+        3,
+        "Writable bytes from a hex string such as \"00ff\".",
+        Arc::new(move |base, registers| {
+            let hex = str_arg(registers, base + 1)?;
+            if hex.len() % 2 != 0 || !hex.is_ascii() {
+                return Err(format!("bytes_from_hex: invalid hex string {:?}", hex));
+            }
+            let data = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| format!("bytes_from_hex: invalid hex string {:?}", hex))?;
+            let mut heap = heap.lock().map_err(|e| e.to_string())?;
+            set_slice(registers, base, heap.alloc(data));
+            Ok(())
+        }),
+    );
+    for idx in [new_idx, copy_idx, slice_idx, from_hex_idx] {
+        vm.host_functions.mark_returns_bytes(idx);
+    }
+}
+
+/// `format_timestamp(ms, fmt)` and `parse_timestamp(text, fmt)` convert
+/// between `now_ms`-style timestamps and UTC dates (see `datetime`)
+fn register_timestamp_builtins(vm: &mut VirtualMachine) {
+    let strings = Arc::new(Mutex::new(StringHeap::new()));
+    let format_idx = vm.host_functions.register_closure(
+        "format_timestamp",
+        2,
+        2,
+        4,
+        Arc::new(move |base, registers| {
+            let ms = registers.get(base + 1) as i64;
+            let text = format_timestamp(ms, str_arg(registers, base + 2)?)?;
+            let ptr = strings.lock().map_err(|e| e.to_string())?.alloc(text.as_bytes());
+            registers.set(base, ptr as u64);
+            registers.set(base + 1, text.len() as u64);
+            Ok(())
+        }),
+    );
+    vm.debug_info.set_doc(
+        "format_timestamp",
+        "Format ms since the epoch as a UTC date, e.g. format_timestamp(now_ms(), \"%Y-%m-%d %H:%M:%S\").",
+    );
+    vm.const_pool
+        .add_value("format_timestamp", format_idx as u64, ValueType::FuncHost);
+
+    let parse_idx = vm.host_functions.register("parse_timestamp", 1, 2, 5, |base, registers| {
+        let ms = parse_timestamp(str_arg(registers, base + 1)?, str_arg(registers, base + 3)?)?;
+        registers.set(base, ms as u64);
+        Ok(())
+    });
+    vm.debug_info.set_doc(
+        "parse_timestamp",
+        "Parse a UTC date laid out as a format (as in format_timestamp) into ms since the epoch.",
+    );
+    vm.const_pool
+        .add_value("parse_timestamp", parse_idx as u64, ValueType::FuncHost);
+}
+
+/// `env_get(name)` returns the variable's value, or "" when it is unset
+fn register_env_builtins(vm: &mut VirtualMachine) {
+    let strings = Arc::new(Mutex::new(StringHeap::new()));
+    let idx = vm.host_functions.register_closure(
+        "env_get",
+        2,
+        1,
+        3,
+        Arc::new(move |base, registers| {
+            let value = std::env::var(str_arg(registers, base + 1)?).unwrap_or_default();
+            let ptr = strings.lock().map_err(|e| e.to_string())?.alloc(value.as_bytes());
+            registers.set(base, ptr as u64);
+            registers.set(base + 1, value.len() as u64);
+            Ok(())
+        }),
+    );
+    vm.debug_info.set_doc(
+        "env_get",
+        "Value of an environment variable, or \"\" if unset (requires the env capability).",
+    );
+    vm.const_pool.add_value("env_get", idx as u64, ValueType::FuncHost);
+}
+
+/// `arg_count()`, `arg(i)` and `exit(code)`. Until Kayton has lists the
+/// arguments are read one at a time.
+fn register_process_builtins(vm: &mut VirtualMachine, args: Vec<String>) {
+    let args = Arc::new(args);
+    let count_args = args.clone();
+    let count_idx = vm.host_functions.register_closure(
+        "arg_count",
+        1,
+        0,
+        1,
+        Arc::new(move |base, registers| {
+            registers.set(base, count_args.len() as u64);
+            Ok(())
+        }),
+    );
+    vm.debug_info
+        .set_doc("arg_count", "Number of script arguments (requires the process capability).");
+    vm.const_pool
+        .add_value("arg_count", count_idx as u64, ValueType::FuncHost);
+
+    let arg_idx = vm.host_functions.register_closure(
+        "arg",
+        2,
+        1,
+        2,
+        Arc::new(move |base, registers| {
+            let index = registers.get(base + 1) as usize;
+            let arg = args.get(index).ok_or_else(|| {
+                format!("arg({}) out of range: {} arguments", index as i64, args.len())
+            })?;
+            // `args` lives as long as this function, so the pointer stays valid
+            registers.set(base, arg.as_ptr() as u64);
+            registers.set(base + 1, arg.len() as u64);
+            Ok(())
+        }),
+    );
+    vm.debug_info
+        .set_doc("arg", "Script argument at an index (requires the process capability).");
+    vm.const_pool.add_value("arg", arg_idx as u64, ValueType::FuncHost);
+
+    let exit_idx = vm.host_functions.register("exit", 0, 1, 2, |base, registers| {
+        request_exit(registers.get(base + 1) as i64)
+    });
+    vm.debug_info.set_doc(
+        "exit",
+        "Stop the script with an exit code (requires the process capability).",
+    );
+    vm.const_pool.add_value("exit", exit_idx as u64, ValueType::FuncHost);
+}
+
+#[cfg(feature = "http")]
+fn store_response(
+    response: Result<ureq::http::Response<ureq::Body>, ureq::Error>,
+    strings: &Mutex<StringHeap>,
+    status: &std::sync::atomic::AtomicU64,
+    base: usize,
+    registers: &mut Registers,
+) -> Result<(), String> {
+    let mut response = response.map_err(|e| e.to_string())?;
+    status.store(response.status().as_u16() as u64, std::sync::atomic::Ordering::Relaxed);
+    let body = response.body_mut().read_to_string().map_err(|e| e.to_string())?;
+    let ptr = strings.lock().map_err(|e| e.to_string())?.alloc(body.as_bytes());
+    registers.set(base, ptr as u64);
+    registers.set(base + 1, body.len() as u64);
+    Ok(())
+}
+
+/// `http_get(url)` and `http_post(url, body)` return the response body;
+/// `http_status()` gives the status code of the last response. Error statuses
+/// are returned like any other, only failed requests are errors.
+#[cfg(feature = "http")]
+fn register_http_builtins(vm: &mut VirtualMachine) {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(std::time::Duration::from_secs(30)))
+        .build()
+        .into();
+    let strings = Arc::new(Mutex::new(StringHeap::new()));
+    let status = Arc::new(AtomicU64::new(0));
+
+    let (get_agent, get_strings, get_status) = (agent.clone(), strings.clone(), status.clone());
+    let get_idx = vm.host_functions.register_closure(
+        "http_get",
+        2,
+        1,
+        3,
+        Arc::new(move |base, registers| {
+            let response = get_agent.get(str_arg(registers, base + 1)?).call();
+            store_response(response, &get_strings, &get_status, base, registers)
+        }),
+    );
+    vm.debug_info.set_doc(
+        "http_get",
+        "GET a URL and return the response body (requires the net capability).",
+    );
+    vm.const_pool
+        .add_value("http_get", get_idx as u64, ValueType::FuncHost);
+
+    let post_status = status.clone();
+    let post_idx = vm.host_functions.register_closure(
+        "http_post",
+        2,
+        2,
+        5,
+        Arc::new(move |base, registers| {
+            let url = str_arg(registers, base + 1)?;
+            let response = agent.post(url).send(str_arg(registers, base + 3)?);
+            store_response(response, &strings, &post_status, base, registers)
+        }),
+    );
+    vm.debug_info.set_doc(
+        "http_post",
+        "POST a string body to a URL and return the response body (requires the net capability).",
+    );
+    vm.const_pool
+        .add_value("http_post", post_idx as u64, ValueType::FuncHost);
+
+    let status_idx = vm.host_functions.register_closure(
+        "http_status",
+        1,
+        0,
+        1,
+        Arc::new(move |base, registers| {
+            registers.set(base, status.load(Ordering::Relaxed));
+            Ok(())
+        }),
+    );
+    vm.debug_info
+        .set_doc("http_status", "Status code of the last HTTP response, 0 before any request.");
+    vm.const_pool
+        .add_value("http_status", status_idx as u64, ValueType::FuncHost);
+}
+
+/// Const index of `print` registered by the stdlib, if any
+pub fn print_const(vm: &VirtualMachine) -> Option<u16> {
+    vm.const_pool.value_index("print").map(|i| i as u16)
+}
@@ -0,0 +1,61 @@
+//! `#| expect: text` annotations: the output a script should print, one
+//! line per annotation, checked by `kayton check`. An annotation may stand
+//! on its own line or follow code on the same line:
+//!
+//! ```text
+//! x = 40 + 2
+//! print(x)  #| expect: 42
+//! print("done")
+//! #| expect: done
+//! ```
+
+use crate::diagnostics::Diagnostics;
+
+const MARKER: &str = "#| expect:";
+
+/// One expected line of output and the source line that expects it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expectation {
+    pub line: u32,
+    pub text: String,
+}
+
+/// The annotations of `src`, in source order
+pub fn expectations(src: &str) -> Vec<Expectation> {
+    src.lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let (_, text) = line.split_once(MARKER)?;
+            Some(Expectation {
+                line: i as u32 + 1,
+                text: text.strip_prefix(' ').unwrap_or(text).trim_end().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Report each line of `output` that differs from its expectation as an
+/// `expect` error, at the annotation's line
+pub fn compare(expected: &[Expectation], output: &str, diagnostics: &mut Diagnostics) {
+    let actual: Vec<&str> = output.lines().map(str::trim_end).collect();
+    for (i, expectation) in expected.iter().enumerate() {
+        match actual.get(i) {
+            Some(&got) if got == expectation.text => {}
+            Some(got) => {
+                let message = format!("expected {:?}, got {:?}", expectation.text, got);
+                diagnostics.error(expectation.line, "expect", message);
+            }
+            None => {
+                let message = format!("expected {:?}, but the output ended", expectation.text);
+                diagnostics.error(expectation.line, "expect", message);
+            }
+        }
+    }
+    if let Some(extra) = actual.get(expected.len()) {
+        let line = expected.last().map_or(0, |e| e.line);
+        let message = format!("unexpected output {:?}", extra);
+        diagnostics
+            .error(line, "expect", message)
+            .help("add a `#| expect:` line for it");
+    }
+}
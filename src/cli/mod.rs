@@ -0,0 +1,460 @@
+use crate::builtins::{OutputSink, register_builtins, register_stdlib};
+use crate::codegen::CompileMode;
+use crate::diagnostics::{Diagnostics, Renderer};
+use crate::frontend::{KaytonSyntax, compile_with_diagnostics};
+use crate::repl::Repl;
+use crate::template;
+use crate::vm::const_pool::ConstCheckpoint;
+use crate::vm::coverage::Coverage;
+use crate::vm::profile::Profile;
+use crate::vm::stepper::Stepper;
+use crate::vm::{
+    BytecodeStats, Capabilities, DebugInfo, GlobalVars, Program, VirtualMachine, VmError,
+};
+use crate::write;
+use std::sync::{Arc, Mutex};
+
+const USAGE: &str = "usage:
+  kayton                 start the REPL
+  kayton run <file> [<args>...]
+                         compile and run a script (or a compiled .kayc program)
+  kayton compile <file> [<out.kayc>] [--release]
+                         compile a script to a portable .kayc program
+                         (--release strips assert() checks)
+  kayton check <file> [--json]
+                         report compile errors and warnings; a script with
+                         `#| expect: <line>` comments is also run and its
+                         output compared with them
+  kayton stats <file>    show size and instruction mix of a script or .kayc program,
+                         and the share of its operations specialized to ints or floats
+  kayton profile <file>  run a script and annotate its source with execution counts
+  kayton coverage <file> [--lcov]
+                         run a script and report which lines ran
+  kayton watch <file>    rerun the script whenever it changes
+  kayton debug <file>    step through a script's bytecode interactively
+  kayton render <file>   print a text file with its {{ expr }} holes filled in";
+
+/// Compiles and runs scripts, keeping one VM (and its host registrations) across runs
+pub struct ScriptRunner {
+    pub vm: VirtualMachine,
+    print_const: u16,
+    /// Const pool state right after builtin registration; each run rolls back
+    /// to it so constants of earlier scripts do not pile up
+    builtins_checkpoint: ConstCheckpoint,
+    /// Code passed to `exit()` by the last run, if it called it
+    pub exit_code: Option<i64>,
+    /// Name compile errors point at, usually the script's path
+    pub origin: Option<String>,
+    /// Render compile errors with ANSI colors
+    pub color: bool,
+}
+
+impl ScriptRunner {
+    pub fn new() -> Self {
+        let mut vm = VirtualMachine::new();
+        let print_const = register_builtins(&mut vm);
+        Self::with_vm(vm, print_const)
+    }
+
+    /// Runner for `kayton run`: scripts may read the environment, `args` and
+    /// the clock, and end with `exit(code)`, like ordinary programs
+    pub fn with_args(args: Vec<String>) -> Self {
+        let mut vm = VirtualMachine::new();
+        vm.capabilities = Capabilities {
+            time: true,
+            env: true,
+            process: true,
+            ..Capabilities::none()
+        };
+        let print_const = register_stdlib(&mut vm, None, None, args);
+        Self::with_vm(vm, print_const)
+    }
+
+    /// Runner whose `print` output goes to `output` instead of the console
+    pub fn with_output(output: OutputSink) -> Self {
+        let mut vm = VirtualMachine::new();
+        let print_const = register_stdlib(&mut vm, Some(output), None, Vec::new());
+        Self::with_vm(vm, print_const)
+    }
+
+    fn with_vm(mut vm: VirtualMachine, print_const: u16) -> Self {
+        let builtins_checkpoint = vm.const_pool.checkpoint();
+        Self {
+            vm,
+            print_const,
+            builtins_checkpoint,
+            exit_code: None,
+            origin: None,
+            color: false,
+        }
+    }
+
+    /// Forget globals, strings and constants left over from the previous run
+    fn reset_program_state(&mut self) {
+        self.vm.global_vars = GlobalVars::new();
+        self.vm.debug_info = DebugInfo::new();
+        self.vm.strings.clear();
+        self.vm.const_pool.rollback(self.builtins_checkpoint);
+        self.exit_code = None;
+    }
+
+    /// Compile `src`, rendering its errors with their source lines
+    fn compile(&mut self, src: &str) -> Result<Vec<u8>, String> {
+        compile_with_diagnostics(&KaytonSyntax, src, &mut self.vm, self.print_const)
+            .map(|(bytecode, _)| bytecode)
+            .map_err(|diagnostics| self.renderer(src).render_all(&diagnostics.errors_only()))
+    }
+
+    fn renderer<'a>(&'a self, src: &'a str) -> Renderer<'a> {
+        let renderer = Renderer::new(src).with_color(self.color);
+        match &self.origin {
+            Some(origin) => renderer.with_origin(origin),
+            None => renderer,
+        }
+    }
+
+    /// An `exit()` call ends the run normally, recording its code
+    fn finish_run(&mut self, result: Result<(), VmError>) -> Result<(), String> {
+        match result {
+            Err(VmError::Exit(code)) => {
+                self.exit_code = Some(code);
+                Ok(())
+            }
+            result => result.map_err(|e| format!("runtime error: {}", e)),
+        }
+    }
+
+    /// Compile and run `src`; globals from a previous run are discarded first
+    pub fn run_source(&mut self, src: &str) -> Result<(), String> {
+        self.reset_program_state();
+        let bytecode = self.compile(src)?;
+        let result = self.vm.eval_program(&bytecode);
+        self.finish_run(result)
+    }
+
+    /// Run `src` with profiling on
+    fn run_profiled(&mut self, src: &str) -> Result<Profile, String> {
+        self.reset_program_state();
+        let bytecode = self.compile(src)?;
+        self.vm.start_profiling();
+        let result = self.vm.eval_program(&bytecode);
+        let profile = self.vm.stop_profiling().unwrap_or_default();
+        self.finish_run(result)?;
+        Ok(profile)
+    }
+
+    /// Run `src` and return its source annotated with execution counts
+    pub fn profile_source(&mut self, src: &str) -> Result<String, String> {
+        let profile = self.run_profiled(src)?;
+        Ok(profile.annotate(src, &self.vm.debug_info.source_map))
+    }
+
+    /// Run `src` and report which of its lines executed
+    pub fn coverage_source(&mut self, src: &str) -> Result<Coverage, String> {
+        let profile = self.run_profiled(src)?;
+        Ok(Coverage::from_profile(&profile, &self.vm.debug_info.source_map))
+    }
+
+    /// Fill in the `{{ expr }}` holes of template `src`
+    pub fn render_source(&mut self, src: &str) -> Result<String, String> {
+        self.reset_program_state();
+        template::render(src, &mut self.vm, self.print_const)
+    }
+
+    /// Compile `src` without running it, ready to step through on `self.vm`
+    pub fn stepper_source(&mut self, src: &str) -> Result<Stepper, String> {
+        self.reset_program_state();
+        let bytecode = self.compile(src)?;
+        Ok(Stepper::new(bytecode))
+    }
+
+    pub fn run_file(&mut self, path: &str) -> Result<(), String> {
+        if path.ends_with(".kayc") {
+            let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+            return self.run_program(&data);
+        }
+        let src = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        self.set_script_path(path);
+        self.run_source(&src)
+    }
+
+    /// Compile errors point at `path`, and `import` finds modules next to it
+    pub fn set_script_path(&mut self, path: &str) {
+        self.origin = Some(path.to_string());
+        let dir = std::path::Path::new(path)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(std::path::Path::new("."));
+        self.vm.modules.search_paths = vec![dir.to_path_buf()];
+    }
+
+    /// Compile `src` into a self-contained `.kayc` image
+    pub fn compile_source(&mut self, src: &str) -> Result<Vec<u8>, String> {
+        self.reset_program_state();
+        let bytecode = self.compile(src)?;
+        let program = Program::with_consts(bytecode, &self.vm).map_err(|e| e.to_string())?;
+        #[cfg(feature = "compress")]
+        return Ok(program.to_bytes_compressed());
+        #[cfg(not(feature = "compress"))]
+        Ok(program.to_bytes())
+    }
+
+    /// Compile `src` without running it, for its errors and warnings
+    pub fn check_source(&mut self, src: &str) -> Diagnostics {
+        self.reset_program_state();
+        match compile_with_diagnostics(&KaytonSyntax, src, &mut self.vm, self.print_const) {
+            Ok((_, diagnostics)) | Err(diagnostics) => diagnostics,
+        }
+    }
+
+    /// Run a `.kayc` image produced by `compile_source`, possibly on another machine
+    pub fn run_program(&mut self, data: &[u8]) -> Result<(), String> {
+        self.reset_program_state();
+        // The program brings its own const pool; keep ours for later `run_source` calls
+        let own_pool = std::mem::take(&mut self.vm.const_pool);
+        let result = match self.vm.load_program(data) {
+            Ok(bytecode) => {
+                let result = self.vm.eval_program(&bytecode);
+                self.finish_run(result)
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        self.vm.global_vars = GlobalVars::new();
+        self.vm.const_pool = own_pool;
+        result
+    }
+}
+
+impl Default for ScriptRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Entry point of the `kayton` binary; returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    let result = match args {
+        [] => {
+            Repl::new().run();
+            Ok(())
+        }
+        [cmd, path, script_args @ ..] if cmd == "run" => return run_file(path, script_args),
+        [cmd, path] if cmd == "watch" => watch(path),
+        [cmd, path] if cmd == "debug" => debug(path),
+        [cmd, path] if cmd == "render" => render_file(path),
+        [cmd, path, rest @ ..] if cmd == "compile" => {
+            let (mode, rest) = match rest {
+                [rest @ .., flag] if flag == "--release" => (CompileMode::Release, rest),
+                _ => (CompileMode::Debug, rest),
+            };
+            match rest {
+                [] => {
+                    let out = std::path::Path::new(path).with_extension("kayc");
+                    compile_file(path, &out.to_string_lossy(), mode)
+                }
+                [out] => compile_file(path, out, mode),
+                _ => Err(USAGE.to_string()),
+            }
+        }
+        [cmd, path] if cmd == "check" => check_file(path, false),
+        [cmd, path, flag] if cmd == "check" && flag == "--json" => check_file(path, true),
+        [cmd, path] if cmd == "profile" => profile_file(path),
+        [cmd, path] if cmd == "coverage" => coverage_file(path, false),
+        [cmd, path, flag] if cmd == "coverage" && flag == "--lcov" => coverage_file(path, true),
+        [cmd, path] if cmd == "stats" => file_stats(path).map(|stats| {
+            write::print_to_console(stats.to_string().as_bytes());
+        }),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            write::println_to_console(err.as_bytes());
+            1
+        }
+    }
+}
+
+/// `kayton run`: the exit code is the script's `exit()` code, 1 on errors
+fn run_file(path: &str, script_args: &[String]) -> i32 {
+    let mut runner = ScriptRunner::with_args(script_args.to_vec());
+    runner.color = use_color();
+    match runner.run_file(path) {
+        Ok(()) => runner.exit_code.unwrap_or(0) as i32,
+        Err(err) => {
+            write::println_to_console(err.as_bytes());
+            1
+        }
+    }
+}
+
+/// `kayton render`: templates may read the environment, like scripts
+fn render_file(path: &str) -> Result<(), String> {
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let text = ScriptRunner::with_args(Vec::new()).render_source(&src)?;
+    write::print_to_console(text.as_bytes());
+    Ok(())
+}
+
+fn compile_file(path: &str, out: &str, mode: CompileMode) -> Result<(), String> {
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut runner = ScriptRunner::new();
+    runner.vm.compile_mode = mode;
+    runner.set_script_path(path);
+    runner.color = use_color();
+    let data = runner.compile_source(&src)?;
+    std::fs::write(out, data).map_err(|e| format!("{}: {}", out, e))
+}
+
+/// Diagnostics of `src`; if it compiles and has `#| expect:` annotations,
+/// it is run and output that does not match them is reported too
+pub fn check_script(src: &str) -> Diagnostics {
+    check_script_at(src, None)
+}
+
+/// `check_script` for the script at `path`, whose directory holds the
+/// modules it imports
+fn check_script_at(src: &str, path: Option<&str>) -> Diagnostics {
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let mut runner = ScriptRunner::with_output(output.clone());
+    if let Some(path) = path {
+        runner.set_script_path(path);
+    }
+    let mut diagnostics = runner.check_source(src);
+    let expected = expect::expectations(src);
+    if diagnostics.errors().count() > 0 || expected.is_empty() {
+        return diagnostics;
+    }
+    if let Err(err) = runner.run_source(src) {
+        let message = err.strip_prefix("runtime error: ").unwrap_or(&err);
+        diagnostics.error(0, "runtime", message);
+    }
+    let printed = String::from_utf8_lossy(&output.lock().unwrap()).into_owned();
+    expect::compare(&expected, &printed, &mut diagnostics);
+    diagnostics
+}
+
+/// `kayton check`: print the diagnostics with their source lines, or as a
+/// JSON array; fails if any is an error
+fn check_file(path: &str, json: bool) -> Result<(), String> {
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let diagnostics = check_script_at(&src, Some(path));
+    if json {
+        write::println_to_console(diagnostics.to_json().as_bytes());
+    } else if !diagnostics.is_empty() {
+        let renderer = Renderer::new(&src).with_origin(path).with_color(use_color());
+        write::println_to_console(renderer.render_all(&diagnostics).as_bytes());
+    }
+    match diagnostics.errors().count() {
+        0 => Ok(()),
+        1 => Err("1 error".to_string()),
+        n => Err(format!("{} errors", n)),
+    }
+}
+
+/// Color diagnostics only on a terminal, and never when `NO_COLOR` is set
+fn use_color() -> bool {
+    use std::io::IsTerminal;
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+fn profile_file(path: &str) -> Result<(), String> {
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut runner = ScriptRunner::new();
+    runner.set_script_path(path);
+    let report = runner.profile_source(&src)?;
+    write::print_to_console(report.as_bytes());
+    Ok(())
+}
+
+fn coverage_file(path: &str, lcov: bool) -> Result<(), String> {
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut runner = ScriptRunner::new();
+    runner.set_script_path(path);
+    let coverage = runner.coverage_source(&src)?;
+    let report = if lcov {
+        coverage.to_lcov(path)
+    } else {
+        coverage.annotate(&src)
+    };
+    write::print_to_console(report.as_bytes());
+    Ok(())
+}
+
+/// Statistics for a `.kayc` program, or for a script as it compiles now
+fn file_stats(path: &str) -> Result<BytecodeStats, String> {
+    let data = if path.ends_with(".kayc") {
+        std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?
+    } else {
+        let src = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut runner = ScriptRunner::new();
+        runner.set_script_path(path);
+        runner.compile_source(&src)?
+    };
+    let program = Program::from_bytes(&data)
+        .and_then(Program::migrate)
+        .map_err(|e| e.to_string())?;
+    BytecodeStats::from_bytecode(&program.bytecode).map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "watch")]
+fn watch(path: &str) -> Result<(), String> {
+    use notify::{RecursiveMode, Watcher};
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let file = Path::new(path)
+        .canonicalize()
+        .map_err(|e| format!("{}: {}", path, e))?;
+    // Watch the directory: editors often save by replacing the file
+    let dir = file.parent().unwrap_or(Path::new("."));
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(|e| e.to_string())?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(|e| e.to_string())?;
+
+    let mut runner = ScriptRunner::new();
+    loop {
+        write::println_to_console(format!("[watch] running {}", path).as_bytes());
+        if let Err(err) = runner.run_file(path) {
+            write::println_to_console(err.as_bytes());
+        }
+        // Block until the script changes, then let a burst of save events settle
+        loop {
+            let event = rx.recv().map_err(|e| e.to_string())?;
+            if let Ok(event) = event
+                && event.paths.iter().any(|p| p.file_name() == file.file_name())
+                && !event.kind.is_access()
+            {
+                break;
+            }
+        }
+        while rx.recv_timeout(Duration::from_millis(50)).is_ok() {}
+    }
+}
+
+#[cfg(not(feature = "watch"))]
+fn watch(_path: &str) -> Result<(), String> {
+    Err("watch mode requires building kayton with the `watch` feature".to_string())
+}
+
+#[cfg(feature = "tui")]
+fn debug(path: &str) -> Result<(), String> {
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    tui::run(&src)
+}
+
+#[cfg(not(feature = "tui"))]
+fn debug(_path: &str) -> Result<(), String> {
+    Err("the debugger requires building kayton with the `tui` feature".to_string())
+}
+
+pub mod expect;
+
+#[cfg(feature = "tui")]
+mod tui;
+
+#[cfg(test)]
+mod tests;
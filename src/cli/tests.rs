@@ -0,0 +1,272 @@
+use super::*;
+
+#[test]
+fn runner_reports_compile_errors() {
+    let mut runner = ScriptRunner::new();
+    let err = runner.run_source("x = )").unwrap_err();
+    assert!(err.starts_with("error[syntax]:"), "{}", err);
+    runner.origin = Some("bad.ky".to_string());
+    let err = runner.run_source("x = 1\ny = x + \"a\"").unwrap_err();
+    assert_eq!(
+        err,
+        "error[compile]: cannot add str and int\n --> bad.ky:2\n  |\n2 | y = x + \"a\"\n  | ^^^^^^^^^^^"
+    );
+}
+
+#[test]
+fn unknown_characters_fail_the_run() {
+    let err = ScriptRunner::new().run_source("x = 3 * 4\nprint(x)\nprint(99)").unwrap_err();
+    assert!(err.starts_with("error[syntax]: unknown character '*'"), "{}", err);
+
+    let src = std::env::temp_dir().join("kayton_cli_unknown_character_test.ky");
+    std::fs::write(&src, "x = 3 * 4\nprint(x)\nprint(99)").unwrap();
+    let path = src.to_string_lossy().into_owned();
+    assert_eq!(main(&["run".to_string(), path.clone()]), 1);
+    assert_eq!(main(&["check".to_string(), path]), 1);
+    std::fs::remove_file(src).unwrap();
+}
+
+#[test]
+fn runner_reuses_vm_with_fresh_globals() {
+    let mut runner = ScriptRunner::new();
+    runner.run_source("x = 1\ny = 2").unwrap();
+    assert!(runner.vm.global_vars.get("y").is_some());
+    runner.run_source("x = 5").unwrap();
+    assert!(runner.vm.global_vars.get("y").is_none());
+    let x = runner.vm.global_vars.get("x").unwrap().register_id;
+    assert_eq!(runner.vm.get_register_i64(x), 5);
+}
+
+#[test]
+fn unknown_arguments_print_usage() {
+    assert_eq!(main(&["bogus".to_string()]), 1);
+}
+
+#[test]
+fn compiled_programs_run_in_a_fresh_vm() {
+    let data = ScriptRunner::new()
+        .compile_source("s = \"hello\"\nx = 40 + 2\nprint(s)")
+        .unwrap();
+    // The loading VM registers the same builtins but has its own const pool
+    let mut runner = ScriptRunner::new();
+    runner.run_source("pad = \"unrelated\"").unwrap();
+    runner.run_program(&data).unwrap();
+    assert!(runner.vm.global_vars.get("x").is_none());
+}
+
+#[test]
+fn compile_and_run_kayc_files() {
+    let dir = std::env::temp_dir();
+    let src = dir.join("kayton_cli_compile_test.ky");
+    let out = dir.join("kayton_cli_compile_test.kayc");
+    std::fs::write(&src, "x = 1 + 2").unwrap();
+    let args = ["compile".to_string(), src.to_string_lossy().into_owned()];
+    assert_eq!(main(&args), 0);
+    assert_eq!(main(&["run".to_string(), out.to_string_lossy().into_owned()]), 0);
+    std::fs::remove_file(src).unwrap();
+    std::fs::remove_file(out).unwrap();
+}
+
+#[test]
+fn release_compilation_strips_asserts() {
+    let dir = std::env::temp_dir();
+    let src = dir.join("kayton_cli_release_test.ky");
+    let out = dir.join("kayton_cli_release_test.kayc");
+    std::fs::write(&src, "assert(0, \"unreachable\")").unwrap();
+    let (src_arg, out_arg) = (src.to_string_lossy().into_owned(), out.to_string_lossy().into_owned());
+    let run = || main(&["run".to_string(), out_arg.clone()]);
+    assert_eq!(main(&["compile".to_string(), src_arg.clone(), out_arg.clone()]), 0);
+    assert_eq!(run(), 1);
+    let args = ["compile".to_string(), src_arg, out_arg.clone(), "--release".to_string()];
+    assert_eq!(main(&args), 0);
+    assert_eq!(run(), 0);
+    std::fs::remove_file(src).unwrap();
+    std::fs::remove_file(out).unwrap();
+}
+
+#[test]
+fn check_reports_without_running() {
+    let mut runner = ScriptRunner::new();
+    let diagnostics = runner.check_source("x = 1\nx\nprint(x)");
+    assert_eq!(diagnostics.to_string(), "line 2: warning[unused-result]: this expression's value is computed and then dropped");
+    assert!(runner.check_source("y = undefined").has_errors());
+}
+
+#[test]
+fn runs_do_not_accumulate_constants() {
+    let mut runner = ScriptRunner::new();
+    runner.run_source("s = \"one\"\nx = 1").unwrap();
+    let consts = runner.vm.const_pool.values.len() + runner.vm.const_pool.slice_count();
+    for _ in 0..10 {
+        runner.run_source("s = \"one\"\nx = 1").unwrap();
+    }
+    let after = runner.vm.const_pool.values.len() + runner.vm.const_pool.slice_count();
+    assert_eq!(after, consts);
+
+    let program = ScriptRunner::new().compile_source("y = 2").unwrap();
+    runner.run_program(&program).unwrap();
+    runner.run_source("print(\"still works\")").unwrap();
+}
+
+#[test]
+fn stats_for_scripts_and_programs() {
+    let dir = std::env::temp_dir();
+    let src = dir.join("kayton_cli_stats_test.ky");
+    std::fs::write(&src, "x = 1 + 2\nprint(x)").unwrap();
+    let stats = file_stats(&src.to_string_lossy()).unwrap();
+    assert_eq!(stats.opcodes["CALL_HOST"], 1);
+    assert_eq!(stats.opcodes["ADD_I64"], 1);
+    assert_eq!(stats.instructions, stats.opcodes.values().sum::<usize>());
+    assert_eq!(main(&["stats".to_string(), src.to_string_lossy().into_owned()]), 0);
+    std::fs::remove_file(src).unwrap();
+}
+
+#[test]
+fn profile_annotates_source_lines() {
+    let mut runner = ScriptRunner::new();
+    let report = runner
+        .profile_source("x = 1\n\ny = x + 2\nz = \"unused\"")
+        .unwrap();
+    let lines: Vec<&str> = report.lines().collect();
+    assert!(lines[0].contains("count"));
+    // `x = 1` is one instruction; the blank line has no count
+    assert!(lines[1].trim_start().starts_with("1          1"), "{}", report);
+    assert_eq!(lines[2].trim(), "2");
+    assert!(lines[3].trim_start().starts_with("3          2"), "{}", report);
+    assert!(lines[3].ends_with("y = x + 2"));
+    assert!(lines[lines.len() - 2].starts_with("4 instructions"), "{}", report);
+    assert_eq!(lines.last().unwrap(), &"peak 256 registers, call depth 0");
+}
+
+#[test]
+fn profile_reports_peak_registers_and_call_depth() {
+    let mut runner = ScriptRunner::new();
+    let src = "def g(n):\n  return n + 1\nend\ndef f(n):\n  return g(n)\nend\nx = f(1)\nprint(x)";
+    let profile = runner.run_profiled(src).unwrap();
+    assert_eq!(profile.peak_call_depth, 2);
+    assert!(profile.peak_registers > crate::vm::Registers::FIXED_COUNT);
+    // A run sized from the profile stays within it
+    runner.vm.limits.max_registers = Some(profile.peak_registers);
+    assert!(runner.run_source(src).is_ok());
+    runner.vm.limits.max_registers = Some(profile.peak_registers - 1);
+    assert!(runner.run_source(src).is_err());
+}
+
+#[test]
+fn profile_coverage_and_stats_find_imported_modules() {
+    let dir = std::env::temp_dir().join("kayton_cli_import_test");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("shapes.kay"), "side = 3").unwrap();
+    let src = dir.join("main.ky");
+    std::fs::write(&src, "import shapes\narea = shapes.side + shapes.side").unwrap();
+    let path = src.to_string_lossy().into_owned();
+    for cmd in ["profile", "coverage", "stats"] {
+        assert_eq!(main(&[cmd.to_string(), path.clone()]), 0, "kayton {}", cmd);
+    }
+    assert!(file_stats(&path).unwrap().opcodes["ADD_I64"] >= 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn coverage_reports_executed_lines() {
+    let mut runner = ScriptRunner::new();
+    let coverage = runner.coverage_source("x = 1\n\ny = x + 2").unwrap();
+    assert_eq!(coverage.lines.into_iter().collect::<Vec<_>>(), vec![(1, 1), (3, 1)]);
+
+    let dir = std::env::temp_dir();
+    let src = dir.join("kayton_cli_coverage_test.ky");
+    std::fs::write(&src, "x = 1").unwrap();
+    let path = src.to_string_lossy().into_owned();
+    assert_eq!(main(&["coverage".to_string(), path.clone()]), 0);
+    assert_eq!(main(&["coverage".to_string(), path, "--lcov".to_string()]), 0);
+    std::fs::remove_file(src).unwrap();
+}
+
+#[test]
+fn run_passes_args_and_exit_code() {
+    // SAFETY: no other test reads this variable
+    unsafe { std::env::set_var("KAYTON_CLI_TEST_VAR", "from env") };
+    let mut runner = ScriptRunner::with_args(vec!["first".to_string(), "second".to_string()]);
+    let src = "n = arg_count()\na = arg(1)\ne = env_get(\"KAYTON_CLI_TEST_VAR\")\nexit(n + 1)\nx = 99";
+    runner.run_source(src).unwrap();
+    assert_eq!(runner.exit_code, Some(3));
+    let read_str = |name: &str| {
+        let reg = runner.vm.global_vars.get(name).unwrap().register_id;
+        let ptr = runner.vm.get_register_raw(reg) as *const u8;
+        let len = runner.vm.get_register_raw(reg + 1) as usize;
+        String::from_utf8(unsafe { std::slice::from_raw_parts(ptr, len) }.to_vec()).unwrap()
+    };
+    assert_eq!(read_str("a"), "second");
+    assert_eq!(read_str("e"), "from env");
+    // exit ends the run without poisoning the VM, and the next run starts clean
+    runner.run_source("x = arg(0)").unwrap();
+    assert_eq!(runner.exit_code, None);
+    let err = runner.run_source("x = arg(2)").unwrap_err();
+    assert!(err.contains("out of range"), "{}", err);
+
+    // The default runner grants neither capability
+    let err = ScriptRunner::new().run_source("exit(1)").unwrap_err();
+    assert!(err.contains("unknown function"), "{}", err);
+
+    let src = std::env::temp_dir().join("kayton_cli_exit_test.ky");
+    std::fs::write(&src, "exit(arg_count() + 4)").unwrap();
+    let path = src.to_string_lossy().into_owned();
+    assert_eq!(main(&["run".to_string(), path, "x".to_string()]), 5);
+    std::fs::remove_file(src).unwrap();
+}
+
+#[test]
+fn stepper_runs_scripts_with_captured_output() {
+    let output = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut runner = ScriptRunner::with_output(output.clone());
+    let mut stepper = runner.stepper_source("x = 2\ny = x + 3\nprint(y)").unwrap();
+    assert!(stepper.render(&runner.vm, 4).contains("line 1"));
+    stepper.step(&mut runner.vm).unwrap();
+    assert!(output.lock().unwrap().is_empty());
+    stepper.resume(&mut runner.vm).unwrap();
+    assert!(stepper.is_finished());
+    assert_eq!(output.lock().unwrap().as_slice(), b"5\n");
+    assert!(stepper.render(&runner.vm, 4).contains("y = 5"));
+
+    #[cfg(not(feature = "tui"))]
+    assert_eq!(main(&["debug".to_string(), "script.ky".to_string()]), 1);
+}
+
+#[test]
+#[cfg(feature = "isa-strings")]
+fn templates_render_with_the_environment() {
+    let mut runner = ScriptRunner::with_args(Vec::new());
+    let path = std::env::var("PATH").unwrap();
+    assert_eq!(
+        runner.render_source("path={{ env_get(\"PATH\") }}\nn={{ 40 + 2 }}\n"),
+        Ok(format!("path={}\nn=42\n", path))
+    );
+    assert_eq!(runner.render_source("again"), Ok("again".to_string()));
+}
+
+#[test]
+fn check_compares_output_with_expect_comments() {
+    let passing = "x = 40 + 2\nprint(x)  #| expect: 42\nprint(\"done\")\n#| expect: done";
+    assert!(check_script(passing).is_empty());
+    // Without annotations the script is not run
+    assert!(check_script("print(1 + 1)").is_empty());
+
+    let failing = "print(1)  #| expect: 2\nprint(3)\n#| expect: 3\n#| expect: 4";
+    let diagnostics = check_script(failing);
+    let found: Vec<_> = diagnostics.errors().map(|d| (d.line, d.code, d.message.as_str())).collect();
+    assert_eq!(
+        found,
+        vec![
+            (1, "expect", "expected \"2\", got \"1\""),
+            (4, "expect", "expected \"4\", but the output ended"),
+        ]
+    );
+    let diagnostics = check_script("print(1)\nprint(2)\n#| expect: 1");
+    assert_eq!(diagnostics.errors().next().unwrap().message, "unexpected output \"2\"");
+
+    let diagnostics = check_script("print(1)  #| expect: 1\nassert(0, \"boom\")");
+    let found: Vec<_> = diagnostics.errors().map(|d| (d.code, d.message.clone())).collect();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].0, "runtime");
+    assert!(found[0].1.ends_with("boom"), "{}", found[0].1);
+}
@@ -0,0 +1,193 @@
+//! `kayton debug`: a terminal front end over `vm::stepper`.
+//!
+//! Keys: `s`/space step, `c` continue to the next breakpoint, up/down (or
+//! `k`/`j`) select an instruction, `b` toggle a breakpoint on it, `r` restart,
+//! `q`/Esc quit.
+
+use super::ScriptRunner;
+use crate::vm::stepper::{StepOutcome, Stepper};
+use crate::vm::VmError;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, execute, queue};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Raw mode and the alternate screen, undone on drop even when we bail out
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct Session {
+    runner: ScriptRunner,
+    stepper: Stepper,
+    output: Arc<Mutex<Vec<u8>>>,
+    /// Index into the listing of the instruction `b` acts on
+    selected: usize,
+    status: String,
+}
+
+impl Session {
+    fn start(src: &str) -> Result<Self, String> {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = ScriptRunner::with_output(output.clone());
+        let stepper = runner.stepper_source(src)?;
+        Ok(Self {
+            runner,
+            stepper,
+            output,
+            selected: 0,
+            status: "s step  c continue  b breakpoint  r restart  q quit".to_string(),
+        })
+    }
+
+    fn restart(&mut self, src: &str) -> Result<(), String> {
+        let breakpoints = std::mem::take(&mut self.stepper.breakpoints);
+        self.runner.vm.clear_poison();
+        self.stepper = self.runner.stepper_source(src)?;
+        self.stepper.breakpoints = breakpoints;
+        self.output.lock().map_err(|e| e.to_string())?.clear();
+        self.follow_pc();
+        self.status = "restarted".to_string();
+        Ok(())
+    }
+
+    /// Move the selection to the instruction about to run
+    fn follow_pc(&mut self) {
+        let pc = self.stepper.pc;
+        if let Some(index) = self.stepper.listing().iter().position(|(p, _)| *p >= pc) {
+            self.selected = index;
+        }
+    }
+
+    fn report(&mut self, result: Result<StepOutcome, VmError>) {
+        self.status = match result {
+            Ok(StepOutcome::Stepped) => format!("stepped to pc {}", self.stepper.pc),
+            Ok(StepOutcome::Breakpoint(pc)) => format!("breakpoint at pc {}", pc),
+            Ok(StepOutcome::Finished) => "program finished".to_string(),
+            Err(VmError::Exit(code)) => {
+                // `exit()` ends the program; stop stepping past it
+                self.stepper.pc = self.stepper.bytecode().len();
+                format!("script exited with code {}", code)
+            }
+            Err(err) => format!("runtime error: {}", err),
+        };
+        self.follow_pc();
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let code_rows = (height / 2).max(3);
+        let selected_pc = self.stepper.listing().get(self.selected).map(|(pc, _)| *pc);
+        let focus = selected_pc.unwrap_or(self.stepper.pc);
+        let view = self.stepper.render_around(&self.runner.vm, code_rows, focus);
+        let output = self.output.lock().map(|o| o.clone()).unwrap_or_default();
+        let output = String::from_utf8_lossy(&output);
+
+        let mut lines: Vec<(String, bool)> = Vec::new();
+        let mut in_code = false;
+        for line in view.lines() {
+            if line.starts_with("== ") {
+                in_code = line.starts_with("== code");
+            }
+            // Listing lines are "<cursor><breakpoint> <pc> <instruction>"
+            let highlight = in_code
+                && selected_pc.is_some_and(|pc| {
+                    line.get(3..).and_then(|rest| rest.split_whitespace().next())
+                        == Some(&pc.to_string())
+                });
+            lines.push((line.to_string(), highlight));
+        }
+        lines.push(("== output ==".to_string(), false));
+        let output_rows = height.saturating_sub(lines.len() + 1);
+        let output_lines: Vec<&str> = output.lines().collect();
+        let skip = output_lines.len().saturating_sub(output_rows);
+        lines.extend(output_lines[skip..].iter().map(|l| (l.to_string(), false)));
+
+        queue!(out, terminal::Clear(ClearType::All))?;
+        for (row, (line, highlight)) in lines.iter().take(height.saturating_sub(1)).enumerate() {
+            let text: String = line.chars().take(width).collect();
+            queue!(out, cursor::MoveTo(0, row as u16))?;
+            if *highlight {
+                queue!(
+                    out,
+                    SetAttribute(Attribute::Reverse),
+                    Print(text),
+                    SetAttribute(Attribute::Reset)
+                )?;
+            } else {
+                queue!(out, Print(text))?;
+            }
+        }
+        let status: String = self.status.chars().take(width).collect();
+        queue!(
+            out,
+            cursor::MoveTo(0, height.saturating_sub(1) as u16),
+            SetAttribute(Attribute::Bold),
+            Print(status),
+            SetAttribute(Attribute::Reset)
+        )?;
+        out.flush()
+    }
+}
+
+/// Compile `src` and step through it until the user quits
+pub fn run(src: &str) -> Result<(), String> {
+    let mut session = Session::start(src)?;
+    let _guard = TerminalGuard::enter().map_err(|e| e.to_string())?;
+    let mut stdout = io::stdout();
+    loop {
+        session.draw(&mut stdout).map_err(|e| e.to_string())?;
+        let Event::Key(key) = event::read().map_err(|e| e.to_string())? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Char('s') | KeyCode::Char(' ') | KeyCode::Enter => {
+                let result = session.stepper.step(&mut session.runner.vm);
+                session.report(result);
+            }
+            KeyCode::Char('c') => {
+                let result = session.stepper.resume(&mut session.runner.vm);
+                session.report(result);
+            }
+            KeyCode::Char('b') => {
+                if let Some(&(pc, _)) = session.stepper.listing().get(session.selected) {
+                    let set = session.stepper.toggle_breakpoint(pc);
+                    session.status = format!(
+                        "breakpoint {} at pc {}",
+                        if set { "set" } else { "cleared" },
+                        pc
+                    );
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                session.selected = session.selected.saturating_sub(1);
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                let last = session.stepper.listing().len().saturating_sub(1);
+                session.selected = (session.selected + 1).min(last);
+            }
+            KeyCode::Char('r') => session.restart(src)?,
+            _ => {}
+        }
+    }
+}
@@ -1,92 +1,1179 @@
-use crate::parser::{Expr, Stmt, BinOp};
-use crate::vm::{BytecodeBuilder, VirtualMachine, GlobalVarType, PtrType};
+use crate::diagnostics::Diagnostics;
+use crate::frontend::{
+    CompileBudget, KaytonSyntax, catch_compile_panic, compile_with, panic_code, panic_message,
+};
+use crate::modules::Module;
+use crate::parser::{
+    docstring, left_spine, nodes, BinOp, Expr, MatchArm, Node, Pattern, Stmt, StringPart, UnaryOp,
+};
+use crate::vm::{
+    BytecodeBuilder, GlobalVarType, PtrType, VirtualMachine, ISA_FLOAT, ISA_STRINGS,
+    SUPPORTED_ISA_FEATURES,
+};
 use crate::vm::const_pool::{SliceType, ValueType};
-use std::collections::HashMap;
+use crate::vm::debug_info::{PcRange, SourceMap, MODULE_DOC};
+use crate::vm::foreign::{method_function, method_signature, ForeignParam, MAX_METHOD_ARGS};
+use crate::vm::number_format::{self, PRINT_F64};
+use std::collections::{HashMap, HashSet};
+use std::panic;
 
-#[derive(Clone, Copy, PartialEq)]
+/// Whether compiled scripts keep their self-checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompileMode {
+    /// `assert(...)` compiles to an ASSERT instruction and `breakpoint()`
+    /// to DEBUG_BREAK
+    #[default]
+    Debug,
+    /// Both compile to nothing; assert conditions are not evaluated
+    Release,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 enum ValueKind {
     Int,
+    Float,
     Str,
+    Bytes,
+    /// Pointer to a list of ints held by the `vec_host` functions
+    List,
+    /// Pointer to an int-keyed dict of ints held by the `map_host` functions
+    Dict,
+}
+
+impl ValueKind {
+    /// Registers a value takes: strings and bytes are (pointer, length)
+    fn width(self) -> u8 {
+        match self {
+            ValueKind::Int | ValueKind::Float | ValueKind::List | ValueKind::Dict => 1,
+            ValueKind::Str | ValueKind::Bytes => 2,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ValueKind::Int => "int",
+            ValueKind::Float => "float",
+            ValueKind::Str => "str",
+            ValueKind::Bytes => "bytes",
+            ValueKind::List => "list",
+            ValueKind::Dict => "dict",
+        }
+    }
+
+    /// The kind a type annotation names, `int` to `dict`
+    fn named(name: &str) -> Option<ValueKind> {
+        [
+            ValueKind::Int,
+            ValueKind::Float,
+            ValueKind::Str,
+            ValueKind::Bytes,
+            ValueKind::List,
+            ValueKind::Dict,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+    }
 }
 
-struct CodeGenerator<'a> {
+/// A `def`, compiled separately for each combination of argument kinds it
+/// is called with
+struct Function<'s> {
+    params: &'s [String],
+    /// Kind of each parameter, if annotated
+    declared: Vec<Option<ValueKind>>,
+    /// Annotated return kind
+    returns: Option<ValueKind>,
+    body: &'s [Stmt],
+    line: u32,
+    specializations: HashMap<Vec<ValueKind>, Specialization>,
+}
+
+/// Calls of the function `bench()` makes before it starts timing
+const BENCH_WARMUP: i64 = 10;
+
+/// Registers kept free for the node being compiled; see `gen_expr`
+const REGISTER_HEADROOM: u8 = 16;
+
+struct Specialization {
+    label: u32,
+    /// Kind of the value returned, once a `return` with a value is compiled
+    returns: Option<ValueKind>,
+    /// A bare `return` was compiled
+    returns_nothing: bool,
+    /// Unset while the body is being compiled, i.e. for recursive calls
+    compiled: bool,
+}
+
+/// Variables of the code being compiled; a function body gets a fresh one
+/// and the caller's is set aside
+struct Scope {
+    vars: HashMap<String, u8>,
+    types: HashMap<String, ValueKind>,
+    declared: HashMap<String, ValueKind>,
+    next_reg: u8,
+    loops: Vec<Loop>,
+}
+
+/// Labels `break` and `continue` jump to in a `while` loop
+#[derive(Clone, Copy)]
+struct Loop {
+    end: u32,
+    test: u32,
+}
+
+struct CodeGenerator<'a, 's> {
     builder: BytecodeBuilder,
     vars: HashMap<String, u8>,
     types: HashMap<String, ValueKind>,
+    /// Variables whose kind is fixed by an annotation
+    declared: HashMap<String, ValueKind>,
     next_reg: u8,
     vm: &'a mut VirtualMachine,
     print_const: u16,
+    /// Node ids by address, numbered as by `parser::nodes`
+    node_ids: HashMap<*const (), u32>,
+    source_map: SourceMap,
+    line: u32,
+    diagnostics: Diagnostics,
+    functions: HashMap<String, Function<'s>>,
+    /// Function name and argument kinds of the body being compiled
+    current: Option<(String, Vec<ValueKind>)>,
+    /// Scopes of the callers whose code a function body interrupted; the
+    /// first is the global one
+    outer: Vec<Scope>,
+    /// Enclosing loops of the code being compiled, innermost last
+    loops: Vec<Loop>,
+    /// `const` and enum variant values, folded to literals
+    consts: HashMap<String, Expr>,
+    /// Variant names of each enum, in value order
+    enums: HashMap<String, Vec<String>>,
+    /// Modules the program imports, parsed by `modules::load_imports`
+    modules: &'s [Module],
+    /// Modules whose code has been compiled
+    imported: HashSet<String>,
+    /// Prefix of the globals being defined: `name.` while compiling module
+    /// `name`, otherwise empty
+    prefix: String,
+    budget: CompileBudget,
 }
 
-impl<'a> CodeGenerator<'a> {
+impl<'a, 's> CodeGenerator<'a, 's> {
     fn new(vm: &'a mut VirtualMachine, print_const: u16) -> Self {
+        let vm_budget = vm.compile_limits.start();
+        let mut vars = HashMap::new();
+        let mut types = HashMap::new();
+        let mut next_reg = 1; // reserve register 0 for call base
+        // Globals defined by earlier compilations (e.g. REPL lines) stay addressable
+        for (name, var) in vm.global_vars.iter() {
+            let (kind, width) = match var.meta.typ {
+                GlobalVarType::Value(ValueType::F64) => (ValueKind::Float, 1),
+                GlobalVarType::Value(ValueType::List) => (ValueKind::List, 1),
+                GlobalVarType::Value(ValueType::Dict) => (ValueKind::Dict, 1),
+                GlobalVarType::Value(_) => (ValueKind::Int, 1),
+                GlobalVarType::Ptr(PtrType::Slice(SliceType::Binary)) => (ValueKind::Bytes, 2),
+                GlobalVarType::Ptr(_) => (ValueKind::Str, 2),
+            };
+            let reg = var.register_id as u8;
+            vars.insert(name.to_string(), reg);
+            types.insert(name.to_string(), kind);
+            next_reg = next_reg.max(reg + width);
+        }
         Self {
             builder: BytecodeBuilder::new(),
-            vars: HashMap::new(),
-            types: HashMap::new(),
-            next_reg: 1, // reserve register 0 for call base
+            vars,
+            types,
+            declared: HashMap::new(),
+            next_reg,
             vm,
             print_const,
+            node_ids: HashMap::new(),
+            source_map: SourceMap::default(),
+            line: 0,
+            diagnostics: Diagnostics::new(),
+            functions: HashMap::new(),
+            current: None,
+            outer: Vec::new(),
+            loops: Vec::new(),
+            consts: HashMap::new(),
+            enums: HashMap::new(),
+            modules: &[],
+            imported: HashSet::new(),
+            prefix: String::new(),
+            budget: vm_budget,
         }
     }
 
-    fn compile(mut self, stmts: &[Stmt]) -> Vec<u8> {
-        for stmt in stmts {
+    /// `lines` holds the source line of each statement (may be empty)
+    fn compile(&mut self, stmts: &'s [Stmt], lines: &[u32]) -> Vec<u8> {
+        let all = nodes(stmts);
+        for (id, node) in all.iter().enumerate() {
+            let ptr = match node {
+                Node::Stmt(stmt) => *stmt as *const Stmt as *const (),
+                Node::Expr(expr) => *expr as *const Expr as *const (),
+            };
+            self.node_ids.insert(ptr, id as u32);
+        }
+        self.source_map.node_lines = vec![0; all.len()];
+
+        let mut first = 0;
+        if let Some(doc) = docstring(stmts) {
+            self.vm.debug_info.set_doc(MODULE_DOC, doc);
+            first = 1;
+        }
+        for (i, stmt) in stmts.iter().enumerate().skip(first) {
+            self.line = lines.get(i).copied().unwrap_or(0);
+            let start = self.builder.current_pos() as usize;
             self.gen_stmt(stmt);
+            self.record(stmt as *const Stmt as *const (), start);
         }
+        self.vm.debug_info.source_map = std::mem::take(&mut self.source_map);
         self.builder.build()
     }
 
-    fn gen_stmt(&mut self, stmt: &Stmt) {
+    /// Map the bytecode emitted since `start` to the node at `ptr`
+    fn record(&mut self, ptr: *const (), start: usize) {
+        let end = self.builder.current_pos() as usize;
+        if let Some(&node) = self.node_ids.get(&ptr) {
+            self.source_map.node_lines[node as usize] = self.line;
+            if end > start {
+                self.source_map.ranges.push(PcRange { start, end, node });
+            }
+        }
+    }
+
+    fn gen_stmt(&mut self, stmt: &'s Stmt) {
+        if let Err(limit) = self.budget.check_time() {
+            panic::panic_any(limit);
+        }
         match stmt {
-            Stmt::Assign { name, expr } => {
+            Stmt::Assign {
+                name,
+                annotation,
+                expr,
+            } => {
+                if self.consts.contains_key(name) && !self.vars.contains_key(name) {
+                    panic!("cannot assign to constant '{}'", name);
+                }
                 let reg = *self.vars.entry(name.clone()).or_insert_with(|| {
                     let r = self.next_reg;
                     self.next_reg += 1;
                     r
                 });
-                let (_r, kind) = self.gen_expr(expr, Some(reg));
+                let saved = self.next_reg;
+                let (r, kind) = self.gen_expr(expr, Some(reg));
+                if let Some(ty) = annotation {
+                    let declared = annotated_kind(ty);
+                    match self.declared.insert(name.clone(), declared) {
+                        Some(other) if other != declared => {
+                            panic!("'{}' is already declared as {}", name, other.name())
+                        }
+                        _ => {}
+                    }
+                }
+                if let Some(&declared) = self.declared.get(name)
+                    && kind != declared
+                {
+                    panic!(
+                        "'{}' is declared as {} but assigned a {}",
+                        name,
+                        declared.name(),
+                        kind.name()
+                    );
+                }
+                // Temporaries used by the expression are free again
+                self.next_reg = saved.max(reg + kind.width());
+                if r != reg {
+                    // e.g. `y = x`: the value already lives in another variable
+                    self.gen_move(r, reg, kind);
+                }
                 self.types.insert(name.clone(), kind);
+                if self.current.is_some() {
+                    // A local of the function being compiled
+                    return;
+                }
 
                 let gv_type = match kind {
                     ValueKind::Int => GlobalVarType::Value(ValueType::I64),
+                    ValueKind::Float => GlobalVarType::Value(ValueType::F64),
                     ValueKind::Str => GlobalVarType::Ptr(PtrType::Slice(SliceType::Utf8Str)),
+                    ValueKind::Bytes => GlobalVarType::Ptr(PtrType::Slice(SliceType::Binary)),
+                    ValueKind::List => GlobalVarType::Value(ValueType::List),
+                    ValueKind::Dict => GlobalVarType::Value(ValueType::Dict),
                 };
                 self.vm
                     .global_vars
-                    .insert(name, reg as usize, gv_type);
+                    .insert(&format!("{}{}", self.prefix, name), reg as usize, gv_type);
             }
             Stmt::ExprStmt(expr) => {
-                if let Expr::Call { func, args } = expr {
-                    if let Expr::Ident(fname) = &**func {
-                        if fname == "print" && args.len() == 1 {
-                            self.gen_print(&args[0]);
-                            return;
+                if let Expr::Call { func, args } = expr
+                    && let Expr::Ident(fname) = &**func
+                {
+                    if fname == "print" && args.len() == 1 {
+                        self.gen_print(&args[0]);
+                        return;
+                    }
+                    if fname == "help" {
+                        self.gen_help(args);
+                        return;
+                    }
+                    if fname == "assert" {
+                        self.gen_assert(args);
+                        return;
+                    }
+                    if self.functions.contains_key(fname) {
+                        let saved = self.next_reg;
+                        self.gen_user_call(fname, args, None, false);
+                        self.next_reg = saved;
+                        return;
+                    }
+                    if fname == "bench" && self.vm.host_functions.find(fname).is_none() {
+                        self.gen_bench(args);
+                        return;
+                    }
+                    if fname == "breakpoint" {
+                        if !args.is_empty() {
+                            panic!("breakpoint() takes no arguments");
                         }
+                        if self.vm.compile_mode == CompileMode::Debug {
+                            self.builder.debug_break();
+                        }
+                        return;
                     }
-                    panic!("unsupported call expression");
-                } else {
-                    self.gen_expr(expr, None);
                 }
+                if !has_call(expr) {
+                    self.diagnostics
+                        .warning(
+                            self.line,
+                            "unused-result",
+                            "this expression's value is computed and then dropped",
+                        )
+                        .help("assign it to a variable or remove it");
+                }
+                let saved = self.next_reg;
+                self.gen_expr(expr, None);
+                self.next_reg = saved;
+            }
+            Stmt::SetIndex {
+                target,
+                index,
+                expr,
+            } => {
+                let saved = self.next_reg;
+                let (reg, kind) = self.gen_collection_operand(target);
+                let (index, _) = self.gen_int_operand(index, index_name(kind));
+                let (value, _) = self.gen_int_operand(expr, &format!("a {} item", kind.name()));
+                self.call_lowered(&lowered(kind, "set"), &[reg, index, value], None);
+                self.next_reg = saved;
             }
+            Stmt::Const { name, expr } => {
+                let value = self.fold(name, expr);
+                self.define_const(name, value);
+            }
+            Stmt::Enum { name, variants } => {
+                if self.enums.contains_key(name) {
+                    panic!("enum '{}' is already defined", name);
+                }
+                for (i, variant) in variants.iter().enumerate() {
+                    self.define_const(variant, Expr::Int(i as i64));
+                }
+                self.enums.insert(name.clone(), variants.clone());
+                self.vm.debug_info.set_enum(name, variants);
+            }
+            Stmt::If {
+                cond,
+                then,
+                otherwise,
+            } => self.gen_if(cond, then, otherwise),
+            Stmt::While { cond, body } => self.gen_while(cond, body),
+            Stmt::Match { subject, arms } => self.gen_match(subject, arms),
+            Stmt::FuncDef {
+                name,
+                params,
+                annotations,
+                returns,
+                body,
+            } => {
+                if self.current.is_some() {
+                    panic!("functions can only be defined at the top level");
+                }
+                let mut body = &body[..];
+                if let Some(doc) = docstring(body) {
+                    self.vm.debug_info.set_doc(name, doc);
+                    body = &body[1..];
+                }
+                let signature = signature(name, params, annotations, returns.as_deref());
+                self.vm.debug_info.set_signature(name, &signature);
+                let function = Function {
+                    params,
+                    declared: annotations
+                        .iter()
+                        .map(|ty| ty.as_deref().map(annotated_kind))
+                        .collect(),
+                    returns: returns.as_deref().map(annotated_kind),
+                    body,
+                    line: self.line,
+                    specializations: HashMap::new(),
+                };
+                self.functions.insert(name.clone(), function);
+            }
+            Stmt::Return(value) => self.gen_return(value.as_ref()),
+            Stmt::Break => self.gen_loop_exit("break"),
+            Stmt::Continue => self.gen_loop_exit("continue"),
+            Stmt::Import(name) => self.gen_import(name),
+        }
+    }
+
+    /// `import name`: the module's code, compiled where it is first
+    /// imported. It sees only the globals of modules, and its own are named
+    /// `name.x`; its functions, constants and enums are dropped afterwards.
+    fn gen_import(&mut self, name: &str) {
+        if self.current.is_some() {
+            panic!("'import' is only allowed at the top level");
+        }
+        if !self.imported.insert(name.to_string()) {
+            return;
+        }
+        let modules = self.modules;
+        let Some(module) = modules.iter().find(|module| module.name == name) else {
+            panic!("module '{}' was not loaded", name);
+        };
+        let prefix = format!("{}.", name);
+        // Its globals from an earlier compilation keep their registers
+        let visible = |vars: &HashMap<String, u8>| -> HashMap<String, u8> {
+            let mut visible: HashMap<_, _> = vars
+                .iter()
+                .filter(|(var, _)| var.contains('.'))
+                .map(|(var, &reg)| (var.clone(), reg))
+                .collect();
+            for (var, &reg) in vars {
+                if let Some(own) = var.strip_prefix(&prefix) {
+                    visible.insert(own.to_string(), reg);
+                }
+            }
+            visible
+        };
+        let vars = visible(&self.vars);
+        let types = vars.keys().map(|var| {
+            let full = if self.vars.contains_key(var) {
+                var.clone()
+            } else {
+                format!("{}{}", prefix, var)
+            };
+            (var.clone(), self.types[&full])
+        });
+        let scope = Scope {
+            types: types.collect(),
+            vars,
+            declared: HashMap::new(),
+            next_reg: self.next_reg,
+            loops: Vec::new(),
+        };
+        let importer = Scope {
+            vars: std::mem::replace(&mut self.vars, scope.vars),
+            types: std::mem::replace(&mut self.types, scope.types),
+            declared: std::mem::replace(&mut self.declared, scope.declared),
+            next_reg: self.next_reg,
+            loops: std::mem::replace(&mut self.loops, scope.loops),
+        };
+        let consts = std::mem::take(&mut self.consts);
+        let enums = std::mem::take(&mut self.enums);
+        let functions = std::mem::take(&mut self.functions);
+        let outer_prefix = std::mem::replace(&mut self.prefix, prefix.clone());
+
+        let first = usize::from(docstring(&module.stmts).is_some());
+        let compiled = catch_compile_panic(|| {
+            for stmt in &module.stmts[first..] {
+                self.gen_stmt(stmt);
+            }
+        });
+        if let Err(payload) = compiled {
+            if panic_code(payload.as_ref(), "compile") == "limit" {
+                panic::resume_unwind(payload);
+            }
+            panic!("in module '{}': {}", name, panic_message(payload.as_ref()));
+        }
+
+        self.prefix = outer_prefix;
+        self.consts = consts;
+        self.enums = enums;
+        self.functions = functions;
+        self.declared = importer.declared;
+        self.loops = importer.loops;
+        // The module's registers stay taken: its globals live there
+        let vars = std::mem::replace(&mut self.vars, importer.vars);
+        let mut types = std::mem::replace(&mut self.types, importer.types);
+        for (var, reg) in vars {
+            let kind = types.remove(&var).expect("unknown type");
+            let full = if var.contains('.') { var } else { format!("{}{}", prefix, var) };
+            self.vars.insert(full.clone(), reg);
+            self.types.insert(full, kind);
+        }
+    }
+
+    /// The returned value goes to r0 (and r1) of the function's register
+    /// window, which is the caller's call base
+    fn gen_return(&mut self, value: Option<&Expr>) {
+        let Some((name, kinds)) = self.current.clone() else {
+            panic!("'return' outside a function");
+        };
+        let returned = value.map(|expr| {
+            let saved = self.next_reg;
+            // Not computed into r0 directly: a string there would overwrite
+            // r1, which may be a parameter the expression still reads
+            let (reg, kind) = self.gen_expr(expr, None);
+            if reg != 0 {
+                // The value may overlap r1, so copy the low register first
+                self.gen_move(reg, 0, kind);
+            }
+            self.next_reg = saved;
+            kind
+        });
+        if let Some(declared) = self.functions[&name].returns {
+            match returned {
+                Some(kind) if kind != declared => panic!(
+                    "'{}' is declared to return {} but returns a {}",
+                    name,
+                    declared.name(),
+                    kind.name()
+                ),
+                Some(_) => {}
+                None => panic!(
+                    "'{}' is declared to return {} but has a bare return",
+                    name,
+                    declared.name()
+                ),
+            }
+        }
+        let spec = self.specialization(&name, &kinds);
+        match (returned, spec.returns) {
+            (Some(kind), Some(other)) if kind != other => {
+                panic!("'{}' returns both {} and {}", name, other.name(), kind.name())
+            }
+            (Some(_), _) if spec.returns_nothing => {
+                panic!("'{}' returns a value on some paths but not others", name)
+            }
+            (None, Some(_)) => panic!("'{}' returns a value on some paths but not others", name),
+            (Some(kind), _) => spec.returns = Some(kind),
+            (None, None) => spec.returns_nothing = true,
+        }
+        self.builder.ret();
+    }
+
+    fn specialization(&mut self, name: &str, kinds: &[ValueKind]) -> &mut Specialization {
+        self.functions
+            .get_mut(name)
+            .and_then(|f| f.specializations.get_mut(kinds))
+            .expect("specialization being compiled")
+    }
+
+    /// Call a `def`. Frame layout as for host calls: arguments from base+1,
+    /// the return value at base. The body for these argument kinds is
+    /// compiled on first use, right here behind a jump.
+    fn gen_user_call(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        target: Option<u8>,
+        want_value: bool,
+    ) -> (u8, ValueKind) {
+        let num_params = self.functions[name].params.len();
+        if args.len() != num_params {
+            panic!(
+                "{}() takes {} arguments but {} were given",
+                name,
+                num_params,
+                args.len()
+            );
+        }
+        let base = self.next_reg;
+        self.next_reg += 1;
+        let mut end = base + 1;
+        let mut kinds = Vec::new();
+        for arg in args {
+            self.next_reg = self.next_reg.max(end + 1);
+            let (r, kind) = self.gen_expr(arg, Some(end));
+            if r != end {
+                self.gen_move(r, end, kind);
+            }
+            let function = &self.functions[name];
+            let i = kinds.len();
+            if let Some(declared) = function.declared[i]
+                && kind != declared
+            {
+                panic!(
+                    "parameter '{}' of {}() is declared as {} but given a {}",
+                    function.params[i],
+                    name,
+                    declared.name(),
+                    kind.name()
+                );
+            }
+            kinds.push(kind);
+            end += kind.width();
+            self.next_reg = self.next_reg.max(end);
+        }
+        let label = self.compile_specialization(name, &kinds);
+        self.builder.call_label(label, base);
+        let spec = &self.functions[name].specializations[&kinds];
+        let kind = match spec.returns {
+            _ if !want_value => {
+                self.next_reg = base;
+                return (base, ValueKind::Int);
+            }
+            Some(kind) => kind,
+            None if !spec.compiled => panic!(
+                "cannot tell what '{}' returns before it reaches a return statement; \
+                 put the base case first",
+                name
+            ),
+            None => panic!("'{}' does not return a value", name),
+        };
+        let width = kind.width();
+        match target {
+            Some(dst) if dst != base => {
+                self.gen_move(base, dst, kind);
+                self.next_reg = base.max(dst + width);
+                (dst, kind)
+            }
+            _ => {
+                self.next_reg = base + width;
+                (base, kind)
+            }
+        }
+    }
+
+    /// Label of the body of `name` for arguments of `kinds`, compiling it if
+    /// this is the first call with them
+    fn compile_specialization(&mut self, name: &str, kinds: &[ValueKind]) -> u32 {
+        let function = &self.functions[name];
+        if let Some(spec) = function.specializations.get(kinds) {
+            return spec.label;
+        }
+        let (params, body, line) = (function.params, function.body, function.line);
+        let declared = function.declared.clone();
+        let label = self.builder.create_label();
+        // A declared return kind is known before the body is compiled, so
+        // recursive calls can use the value without a base case first
+        let spec = Specialization {
+            label,
+            returns: function.returns,
+            returns_nothing: false,
+            compiled: false,
+        };
+        self.functions
+            .get_mut(name)
+            .unwrap()
+            .specializations
+            .insert(kinds.to_vec(), spec);
+
+        let skip = self.builder.create_label();
+        self.builder.jmp_to_label(skip);
+        self.builder.place_label(label);
+        let mut scope = Scope {
+            vars: HashMap::new(),
+            types: HashMap::new(),
+            declared: HashMap::new(),
+            next_reg: 1,
+            loops: Vec::new(),
+        };
+        for ((param, &kind), declared) in params.iter().zip(kinds).zip(declared) {
+            scope.vars.insert(param.clone(), scope.next_reg);
+            scope.types.insert(param.clone(), kind);
+            if declared.is_some() {
+                scope.declared.insert(param.clone(), kind);
+            }
+            scope.next_reg += kind.width();
+        }
+        self.enter_scope(scope);
+        let saved_line = std::mem::replace(&mut self.line, line);
+        let saved_current = self.current.replace((name.to_string(), kinds.to_vec()));
+
+        self.gen_block(body);
+        // Falling off the end returns nothing, or a zero value if other paths
+        // return one
+        if let Some(kind) = self.specialization(name, kinds).returns {
+            self.gen_zero(kind);
+        }
+        self.builder.ret();
+
+        self.current = saved_current;
+        self.line = saved_line;
+        self.leave_scope();
+        self.builder.place_label(skip);
+        self.specialization(name, kinds).compiled = true;
+        label
+    }
+
+    /// Load 0, 0.0, an empty str or bytes into r0 (and r1), or a new empty
+    /// list or dict
+    fn gen_zero(&mut self, kind: ValueKind) {
+        if let ValueKind::List | ValueKind::Dict = kind {
+            self.call_lowered(&lowered(kind, "new"), &[], Some(0));
+            return;
+        }
+        let idx = match kind {
+            ValueKind::Int => self.vm.const_pool.add_value("", 0, ValueType::I64) as u16,
+            ValueKind::Float => self.vm.const_pool.add_value("", 0, ValueType::F64) as u16,
+            ValueKind::Str => self.vm.const_pool.add_slice("", b"", SliceType::Utf8Str) as u16,
+            ValueKind::Bytes => self.vm.const_pool.add_slice("", b"", SliceType::Binary) as u16,
+            ValueKind::List | ValueKind::Dict => unreachable!(),
+        };
+        match kind {
+            ValueKind::Int | ValueKind::Float => self.builder.load_const_value(idx, 0),
+            ValueKind::Str | ValueKind::Bytes => self.builder.load_const_slice(idx, 0),
+            ValueKind::List | ValueKind::Dict => unreachable!(),
+        }
+    }
+
+    /// Make `scope` current, setting the caller's aside
+    fn enter_scope(&mut self, scope: Scope) {
+        let caller = Scope {
+            vars: std::mem::replace(&mut self.vars, scope.vars),
+            types: std::mem::replace(&mut self.types, scope.types),
+            declared: std::mem::replace(&mut self.declared, scope.declared),
+            next_reg: std::mem::replace(&mut self.next_reg, scope.next_reg),
+            loops: std::mem::replace(&mut self.loops, scope.loops),
+        };
+        self.outer.push(caller);
+    }
+
+    fn leave_scope(&mut self) {
+        let caller = self.outer.pop().expect("scope to leave");
+        self.vars = caller.vars;
+        self.types = caller.types;
+        self.declared = caller.declared;
+        self.next_reg = caller.next_reg;
+        self.loops = caller.loops;
+    }
+
+    /// The condition is tested at the bottom, so each iteration takes one
+    /// backward jump: `jmp test; top: body; test: if cond goto top`
+    fn gen_while(&mut self, cond: &Expr, body: &'s [Stmt]) {
+        let top = self.builder.create_label();
+        let test = self.builder.create_label();
+        let end = self.builder.create_label();
+        self.builder.jmp_to_label(test);
+        self.builder.place_label(top);
+        self.loops.push(Loop { end, test });
+        self.gen_block(body);
+        self.loops.pop();
+        self.builder.place_label(test);
+        let saved = self.next_reg;
+        let (reg, kind) = self.gen_expr(cond, None);
+        self.builder.jump_if_true_to_label(truth_reg(reg, kind), top);
+        self.next_reg = saved;
+        self.builder.place_label(end);
+    }
+
+    /// `break` jumps past the innermost loop, `continue` to its test
+    fn gen_loop_exit(&mut self, keyword: &str) {
+        let Some(&Loop { end, test }) = self.loops.last() else {
+            panic!("'{}' outside a loop", keyword);
+        };
+        let target = if keyword == "break" { end } else { test };
+        self.builder.jmp_to_label(target);
+    }
+
+    /// Jump past `then` when `cond` is falsy, and past `otherwise` after `then`
+    fn gen_if(&mut self, cond: &Expr, then: &'s [Stmt], otherwise: &'s [Stmt]) {
+        let saved = self.next_reg;
+        let (reg, kind) = self.gen_expr(cond, None);
+        let else_label = self.builder.create_label();
+        self.builder.jump_if_false_to_label(truth_reg(reg, kind), else_label);
+        self.next_reg = saved;
+        self.gen_block(then);
+        if otherwise.is_empty() {
+            self.builder.place_label(else_label);
+            return;
+        }
+        let end = self.builder.create_label();
+        self.builder.jmp_to_label(end);
+        self.builder.place_label(else_label);
+        self.gen_block(otherwise);
+        self.builder.place_label(end);
+    }
+
+    /// `match`: the subject is evaluated once, then each arm tests it in
+    /// turn and jumps to the next arm if its pattern does not match; a
+    /// matching arm runs its body and jumps past the rest. The subject keeps
+    /// its register until the last test, so bodies allocate above it.
+    fn gen_match(&mut self, subject: &Expr, arms: &'s [MatchArm]) {
+        let saved = self.next_reg;
+        let (reg, kind) = self.gen_expr(subject, None);
+        if !matches!(kind, ValueKind::Int | ValueKind::Str) {
+            panic!("cannot match a {}; the subject must be an int or a str", kind.name());
+        }
+        let after_subject = self.next_reg;
+        let end = self.builder.create_label();
+        for (i, arm) in arms.iter().enumerate() {
+            let last = i + 1 == arms.len();
+            let next = self.builder.create_label();
+            self.gen_pattern_test(reg, kind, &arm.pattern, next);
+            if matches!(arm.pattern, Pattern::Wildcard) && !last {
+                self.diagnostics
+                    .warning(self.line, "unreachable-case", "the arms after 'case _' never run")
+                    .help("move 'case _' last");
+            }
+            self.gen_block(&arm.body);
+            if !last {
+                self.builder.jmp_to_label(end);
+            }
+            self.builder.place_label(next);
+        }
+        self.builder.place_label(end);
+        // No body defined a variable above the subject, so its register is free
+        if self.next_reg == after_subject {
+            self.next_reg = saved;
+        }
+    }
+
+    /// Jump to `miss` unless the subject in `reg` matches `pattern`
+    fn gen_pattern_test(&mut self, reg: u8, kind: ValueKind, pattern: &Pattern, miss: u32) {
+        let value = match pattern {
+            Pattern::Wildcard => return,
+            Pattern::Value(Expr::Attribute { target, name }) => {
+                let Some(i) = self.enum_of(target).iter().position(|v| v == name) else {
+                    panic!("enum '{}' has no variant '{}'", enum_name(target), name);
+                };
+                Expr::Int(i as i64)
+            }
+            Pattern::Value(value) => value.clone(),
+        };
+        let saved = self.next_reg;
+        let tmp = self.next_reg;
+        self.next_reg += 2;
+        match (&value, kind) {
+            (Expr::Int(n), ValueKind::Int) => {
+                let idx = self.vm.const_pool.add_value("", *n as u64, ValueType::I64) as u16;
+                self.builder.load_const_value(idx, tmp);
+                // Non-zero unless the subject is `n`
+                self.builder.sub_i64(reg, tmp, tmp);
+                self.builder.jump_if_true_to_label(tmp, miss);
+            }
+            (Expr::Str(s), ValueKind::Str) => {
+                require_strings();
+                let idx = self.vm.const_pool.add_slice("", s.as_bytes(), SliceType::Utf8Str) as u16;
+                self.builder.load_const_slice(idx, tmp);
+                self.builder.str_eq(reg, tmp, tmp);
+                self.builder.jump_if_false_to_label(tmp, miss);
+            }
+            (value, _) => {
+                let pattern_kind = if matches!(value, Expr::Str(_)) { "str" } else { "int" };
+                panic!("a {} pattern cannot match a {} subject", pattern_kind, kind.name())
+            }
+        }
+        self.next_reg = saved;
+    }
+
+    /// Statements nested in a block, which share the line of the statement
+    /// opening it
+    fn gen_block(&mut self, stmts: &'s [Stmt]) {
+        for stmt in stmts {
+            let start = self.builder.current_pos() as usize;
+            self.gen_stmt(stmt);
+            self.record(stmt as *const Stmt as *const (), start);
         }
     }
 
     fn gen_print(&mut self, arg: &Expr) {
-        let (reg, kind) = self.gen_expr(arg, None);
-        let base = reg - 1;
-        if self.next_reg <= base + 2 {
-            self.next_reg = base + 3;
+        // Fresh call frame [print, value or ptr, 0 or len] so no variable is clobbered
+        let base = self.next_reg;
+        self.next_reg += 3;
+        let (reg, kind) = self.gen_expr(arg, Some(base + 1));
+        if reg != base + 1 {
+            self.gen_move(reg, base + 1, kind);
         }
         self.builder.load_const_value(self.print_const, base);
-        if kind == ValueKind::Int {
-            let zero_idx = self.vm.const_pool.add_value("", 0, ValueType::I64) as u16;
-            self.builder.load_const_value(zero_idx, base + 2);
+        // The length register tells print how to read the value
+        let marker = match kind {
+            ValueKind::Int => Some(0),
+            ValueKind::Float => Some(PRINT_F64),
+            ValueKind::Str | ValueKind::Bytes => None,
+            ValueKind::List | ValueKind::Dict => {
+                panic!("a {} cannot be printed; print its items", kind.name())
+            }
+        };
+        if let Some(marker) = marker {
+            let idx = self.vm.const_pool.add_value("", marker, ValueType::I64) as u16;
+            self.builder.load_const_value(idx, base + 2);
         }
         self.builder.call_host(base as u16);
+        // The frame is only live for the call
+        self.next_reg = base;
+    }
+
+    /// `help()` / `help(name)` is resolved at compile time into a print of the help text
+    fn gen_help(&mut self, args: &[Expr]) {
+        let text = match args {
+            [] => self.vm.help_text(MODULE_DOC),
+            [Expr::Ident(name)] | [Expr::Str(name)] => self.vm.help_text(name),
+            _ => panic!("help() expects a single name"),
+        };
+        let base = self.next_reg;
+        self.next_reg += 3;
+        let idx = self
+            .vm
+            .const_pool
+            .add_slice("", text.as_bytes(), SliceType::Utf8Str) as u16;
+        self.builder.load_const_slice(idx, base + 1);
+        self.builder.load_const_value(self.print_const, base);
+        self.builder.call_host(base as u16);
+        self.next_reg = base;
+    }
+
+    /// `assert(cond)` / `assert(cond, "message")`: fail the run unless `cond`
+    /// is truthy (a non-zero int, a non-empty str or bytes)
+    fn gen_assert(&mut self, args: &[Expr]) {
+        let (cond, message) = match args {
+            [cond] => (cond, format!("assertion on line {} failed", self.line)),
+            [cond, Expr::Str(message)] => (cond, message.clone()),
+            _ => panic!("assert() expects a condition and an optional message string"),
+        };
+        if self.vm.compile_mode == CompileMode::Release {
+            return;
+        }
+        let saved = self.next_reg;
+        let (reg, kind) = self.gen_expr(cond, None);
+        let cond_reg = truth_reg(reg, kind);
+        let idx = self
+            .vm
+            .const_pool
+            .add_slice("", message.as_bytes(), SliceType::Utf8Str) as u16;
+        self.builder.assert(cond_reg, idx);
+        self.next_reg = saved;
+    }
+
+    /// `bench(name, f, iterations)`: call `f` up to `BENCH_WARMUP` times to
+    /// warm up, then `iterations` times, each timed with `monotonic_ns`, and
+    /// report the total, fastest and slowest call with `bench_report`
+    fn gen_bench(&mut self, args: &[Expr]) {
+        let [name, Expr::Ident(func), iterations] = args else {
+            panic!("bench() expects a name, a function and an iteration count");
+        };
+        match self.functions.get(func) {
+            Some(f) if f.params.is_empty() => {}
+            Some(f) => panic!(
+                "bench() needs a function without parameters; '{}' takes {}",
+                func,
+                f.params.len()
+            ),
+            None => panic!("bench() needs a function defined with def; '{}' is not one", func),
+        }
+        for needed in ["monotonic_ns", "bench_report"] {
+            if self.vm.host_functions.find(needed).is_none() {
+                panic!("bench() needs the time capability; '{}' is missing", needed);
+            }
+        }
+        let saved = self.next_reg;
+        let label = match self.gen_expr(name, None) {
+            (reg, ValueKind::Str) => reg,
+            (_, kind) => panic!("the name of a bench() must be a str, not a {}", kind.name()),
+        };
+        let (n, _) = self.gen_int_operand(iterations, "the iteration count of bench()");
+        let [i, one, limit, total, fastest, slowest, start, elapsed] =
+            [0, 1, 2, 3, 4, 5, 6, 7].map(|k| self.next_reg + k);
+        self.next_reg += 8;
+        self.load_i64(1, one);
+        self.load_i64(BENCH_WARMUP, limit);
+        self.builder.min_i64(n, limit, limit);
+        self.gen_counted_loop(i, one, limit, |cg| {
+            cg.gen_user_call(func, &[], None, false);
+        });
+
+        self.load_i64(0, total);
+        self.load_i64(i64::MAX, fastest);
+        self.load_i64(0, slowest);
+        self.gen_counted_loop(i, one, n, |cg| {
+            cg.call_lowered("monotonic_ns", &[], Some(start));
+            cg.gen_user_call(func, &[], None, false);
+            cg.call_lowered("monotonic_ns", &[], Some(elapsed));
+            cg.builder.sub_i64(elapsed, start, elapsed);
+            cg.builder.add_i64(total, elapsed, total);
+            cg.builder.min_i64(fastest, elapsed, fastest);
+            cg.builder.max_i64(slowest, elapsed, slowest);
+        });
+        self.call_lowered("bench_report", &[label, label + 1, n, total, fastest, slowest], None);
+        self.next_reg = saved;
+    }
+
+    /// Run `body` while counter `i`, starting at 0 and stepping by the 1 in
+    /// `one`, is below `limit`
+    fn gen_counted_loop(&mut self, i: u8, one: u8, limit: u8, body: impl FnOnce(&mut Self)) {
+        self.load_i64(0, i);
+        let top = self.builder.create_label();
+        let test = self.builder.create_label();
+        self.builder.jmp_to_label(test);
+        self.builder.place_label(top);
+        let saved = self.next_reg;
+        body(self);
+        self.next_reg = saved;
+        self.builder.add_i64(i, one, i);
+        self.builder.place_label(test);
+        let cond = self.next_reg;
+        self.builder.lt_i64(i, limit, cond);
+        self.builder.jump_if_true_to_label(cond, top);
+    }
+
+    fn load_i64(&mut self, value: i64, dst: u8) {
+        let idx = self.vm.const_pool.add_value("", value as u64, ValueType::I64) as u16;
+        self.builder.load_const_value(idx, dst);
+    }
+
+    /// Const index holding the host function index, adding one if needed
+    fn host_fn_const(&mut self, fn_index: usize) -> u16 {
+        let pool = &self.vm.const_pool;
+        let existing = pool
+            .value_metadata
+            .iter()
+            .position(|m| m.typ == ValueType::FuncHost && pool.values[m.index] == fn_index as u64);
+        match existing {
+            Some(idx) => idx as u16,
+            None => self
+                .vm
+                .const_pool
+                .add_value("", fn_index as u64, ValueType::FuncHost) as u16,
+        }
+    }
+
+    /// Call a registered host function. Frame layout: base holds the function
+    /// index and receives the return value, arguments follow from base+1
+    /// (strings take two registers: pointer and length).
+    fn gen_host_call(&mut self, name: &str, args: &[Expr], target: Option<u8>) -> (u8, ValueKind) {
+        let fn_index = self
+            .vm
+            .host_functions
+            .find(name)
+            .unwrap_or_else(|| panic!("unknown function '{}'", name));
+        let meta = self.vm.host_functions.metadata[fn_index].clone();
+        if args.len() < meta.min_params() || args.len() > meta.num_params {
+            let expected = match meta.min_params() {
+                min if min == meta.num_params => min.to_string(),
+                min => format!("{} to {}", min, meta.num_params),
+            };
+            panic!("{}() takes {} arguments but {} were given", name, expected, args.len());
+        }
+        let base = self.next_reg;
+        self.next_reg += 1;
+        let mut arg_regs = Vec::new();
+        for arg in args {
+            let reg = base + 1 + arg_regs.len() as u8;
+            self.next_reg = self.next_reg.max(reg + 1);
+            let (r, kind) = self.gen_expr(arg, Some(reg));
+            arg_regs.extend((0..kind.width()).map(|i| r + i));
+        }
+        // Parameters left out get the function's defaults
+        let omitted = meta.num_params - args.len();
+        for &value in &meta.defaults[meta.defaults.len() - omitted..] {
+            let reg = base + 1 + arg_regs.len() as u8;
+            self.next_reg = self.next_reg.max(reg + 1);
+            let idx = self.vm.const_pool.add_value("", value, ValueType::I64) as u16;
+            self.builder.load_const_value(idx, reg);
+            arg_regs.push(reg);
+        }
+        let end = base + 1 + arg_regs.len() as u8;
+        self.next_reg = self.next_reg.max(base + meta.num_registers as u8).max(end);
+        let fn_const = self.host_fn_const(fn_index);
+        self.builder.call_host_fn(&meta, fn_const, &arg_regs, base);
+        // Functions returning two registers return a string or bytes (pointer, length)
+        let kind = match meta.num_return_registers {
+            2 if self.vm.host_functions.returns_bytes(fn_index) => ValueKind::Bytes,
+            2 => ValueKind::Str,
+            _ => ValueKind::Int,
+        };
+        let width = kind.width();
+        match target {
+            Some(dst) if dst != base => {
+                // `dst` was allocated before the frame, so it lies below `base`
+                // and copying in order never clobbers an unread register
+                self.gen_move(base, dst, kind);
+                self.next_reg = base.max(dst + width);
+                (dst, kind)
+            }
+            _ => {
+                self.next_reg = base + width;
+                (base, kind)
+            }
+        }
+    }
+
+    /// `object.method(args)` on a foreign object handle: a call of the host
+    /// function of `method`, which finds the object's type at run time. It
+    /// gets the handle, then the kinds of the arguments, then the arguments.
+    fn gen_method_call(
+        &mut self,
+        on: &Expr,
+        method: &str,
+        args: &[Expr],
+        target: Option<u8>,
+    ) -> (u8, ValueKind) {
+        if self.is_enum(on) {
+            panic!("enum '{}' has no function '{}'; enums have name() and parse()", enum_name(on), method);
+        }
+        let Some(fn_index) = self.vm.host_functions.find(&method_function(method)) else {
+            if self.vm.host_functions.metadata.iter().any(|meta| meta.name.starts_with('.')) {
+                panic!("no foreign type has a method '{}'", method);
+            }
+            // Without foreign types, attributes belong to enums alone
+            self.enum_of(on);
+            unreachable!();
+        };
+        if args.len() > MAX_METHOD_ARGS {
+            panic!("{}() takes at most {} arguments", method, MAX_METHOD_ARGS);
+        }
+        let meta = self.vm.host_functions.metadata[fn_index].clone();
+        let base = self.next_reg;
+        let signature_reg = base + 2;
+        self.next_reg = base + 3;
+        let (handle, kind) = self.gen_expr(on, Some(base + 1));
+        if kind != ValueKind::Int {
+            panic!("a {} has no methods; only foreign objects do", kind.name());
+        }
+        let mut arg_regs = vec![handle, signature_reg];
+        let mut kinds = Vec::new();
+        for arg in args {
+            let reg = base + 1 + arg_regs.len() as u8;
+            self.next_reg = self.next_reg.max(reg + 1);
+            let (r, kind) = self.gen_expr(arg, Some(reg));
+            kinds.push(match kind {
+                ValueKind::Int => ForeignParam::Int,
+                ValueKind::Float => ForeignParam::Float,
+                ValueKind::Str => ForeignParam::Str,
+                _ => panic!("method arguments are ints, floats or strs, not a {}", kind.name()),
+            });
+            arg_regs.extend((0..kind.width()).map(|i| r + i));
+        }
+        let signature = method_signature(&kinds);
+        let idx = self.vm.const_pool.add_value("", signature, ValueType::I64) as u16;
+        self.builder.load_const_value(idx, signature_reg);
+        self.next_reg = self.next_reg.max(base + meta.num_registers as u8);
+        let fn_const = self.host_fn_const(fn_index);
+        self.builder.call_host_fn(&meta, fn_const, &arg_regs, base);
+        match target {
+            Some(dst) if dst != base => {
+                self.gen_move(base, dst, ValueKind::Int);
+                self.next_reg = base.max(dst + 1);
+                (dst, ValueKind::Int)
+            }
+            _ => {
+                self.next_reg = base + 1;
+                (base, ValueKind::Int)
+            }
+        }
     }
 
     fn gen_expr(&mut self, expr: &Expr, target: Option<u8>) -> (u8, ValueKind) {
+        // A node takes at most a few registers before its operands are
+        // compiled, so this check runs before a register number can wrap
+        if self.next_reg > u8::MAX - REGISTER_HEADROOM {
+            panic!("expression needs more registers than a frame has; split it into assignments");
+        }
+        let start = self.builder.current_pos() as usize;
+        let result = self.gen_expr_inner(expr, target);
+        self.record(expr as *const Expr as *const (), start);
+        result
+    }
+
+    fn gen_expr_inner(&mut self, expr: &Expr, target: Option<u8>) -> (u8, ValueKind) {
         match expr {
             Expr::Int(n) => {
                 let reg = target.unwrap_or_else(|| {
@@ -101,6 +1188,20 @@ impl<'a> CodeGenerator<'a> {
                 self.builder.load_const_value(idx, reg);
                 (reg, ValueKind::Int)
             }
+            Expr::Float(x) => {
+                require_floats();
+                let reg = target.unwrap_or_else(|| {
+                    let r = self.next_reg;
+                    self.next_reg += 1;
+                    r
+                });
+                let idx = self
+                    .vm
+                    .const_pool
+                    .add_value("", x.to_bits(), ValueType::F64) as u16;
+                self.builder.load_const_value(idx, reg);
+                (reg, ValueKind::Float)
+            }
             Expr::Str(s) => {
                 let reg = target.unwrap_or_else(|| {
                     let r = self.next_reg;
@@ -117,28 +1218,1119 @@ impl<'a> CodeGenerator<'a> {
                 self.builder.load_const_slice(idx, reg);
                 (reg, ValueKind::Str)
             }
+            Expr::Bytes(bytes) => {
+                let reg = target.unwrap_or_else(|| {
+                    let r = self.next_reg;
+                    self.next_reg += 2;
+                    r
+                });
+                if target.is_some() && self.next_reg <= reg + 1 {
+                    self.next_reg = reg + 2;
+                }
+                let idx = self.vm.const_pool.add_slice("", bytes, SliceType::Binary) as u16;
+                self.builder.load_const_slice(idx, reg);
+                (reg, ValueKind::Bytes)
+            }
             Expr::Ident(name) => {
-                let reg = *self
-                    .vars
-                    .get(name)
-                    .expect("undefined variable");
+                let Some(&reg) = self.vars.get(name) else {
+                    if let Some(value) = self.consts.get(name) {
+                        return self.gen_expr_inner(&value.clone(), target);
+                    }
+                    if self.outer.first().is_some_and(|s| s.vars.contains_key(name)) {
+                        panic!("functions cannot read global '{}'; pass it as an argument", name);
+                    }
+                    panic!("undefined variable");
+                };
                 let kind = *self
                     .types
                     .get(name)
                     .expect("unknown type");
                 (reg, kind)
             }
-            Expr::Binary { left, op: BinOp::Add, right } => {
-                let (lreg, _) = self.gen_expr(left, target);
-                let (rreg, _) = self.gen_expr(right, None);
-                let dst = target.unwrap_or(lreg);
+            Expr::Binary { .. } => self.gen_binary(expr, target),
+            Expr::Unary { op: UnaryOp::BitNot, operand } => {
+                let saved = self.next_reg;
+                let (reg, kind) = self.gen_expr(operand, target);
+                if kind != ValueKind::Int {
+                    panic!("'~' needs an int, not a {}", kind.name());
+                }
+                let dst = target.unwrap_or_else(|| {
+                    if reg >= saved {
+                        reg
+                    } else {
+                        let r = self.next_reg;
+                        self.next_reg += 1;
+                        r
+                    }
+                });
+                self.builder.not_i64(reg, dst);
+                (dst, ValueKind::Int)
+            }
+            Expr::Compare { left, rest } => self.gen_chained_comparison(left, rest, target),
+            Expr::Call { func, args } => match &**func {
+                Expr::Ident(name) if self.functions.contains_key(name) => {
+                    self.gen_user_call(name, args, target, true)
+                }
+                Expr::Ident(name)
+                    if (name == "min" || name == "max")
+                        && self.vm.host_functions.find(name).is_none() =>
+                {
+                    self.gen_min_max(name == "min", args, target)
+                }
+                Expr::Ident(name)
+                    if name == "abs" && self.vm.host_functions.find(name).is_none() =>
+                {
+                    self.gen_abs(args, target)
+                }
+                Expr::Ident(name)
+                    if matches!(name.as_str(), "round" | "floor" | "ceil" | "trunc")
+                        && self.vm.host_functions.find(name).is_none() =>
+                {
+                    self.gen_round(name, args, target)
+                }
+                Expr::Ident(name) if name == "len" && self.vm.host_functions.find(name).is_none() => {
+                    self.gen_len(args, target)
+                }
+                Expr::Ident(name) => self.gen_host_call(name, args, target),
+                Expr::Attribute { target: on, name } if self.is_module(on) => {
+                    panic!(
+                        "'{}.{}' cannot be called: modules share only their globals",
+                        enum_name(on),
+                        name
+                    );
+                }
+                Expr::Attribute { target: on, name }
+                    if (name == "name" || name == "parse") && self.is_enum(on) =>
+                {
+                    let variants = self.enum_of(on).clone();
+                    let [arg] = &args[..] else {
+                        panic!("{}() takes 1 arguments but {} were given", name, args.len());
+                    };
+                    if name == "name" {
+                        self.gen_enum_name(enum_name(on), &variants, arg, target)
+                    } else {
+                        self.gen_enum_parse(enum_name(on), &variants, arg, target)
+                    }
+                }
+                Expr::Attribute { target: on, name } => self.gen_method_call(on, name, args, target),
+                _ => panic!("unsupported call expression"),
+            },
+            Expr::Attribute { target: on, name } => {
+                if let Some(var) = self.module_var(on, name) {
+                    return self.gen_expr_inner(&Expr::Ident(var), target);
+                }
+                let Some(i) = self.enum_of(on).iter().position(|v| v == name) else {
+                    panic!("enum '{}' has no variant '{}'", enum_name(on), name);
+                };
+                self.gen_expr_inner(&Expr::Int(i as i64), target)
+            }
+            Expr::InterpolatedString(parts) => {
+                require_strings();
+                let dst = target.unwrap_or(self.next_reg);
+                self.next_reg = self.next_reg.max(dst + 2);
+                // Parts are appended to one builder so the result is allocated once
+                self.builder.str_builder_new();
+                for part in parts {
+                    let saved = self.next_reg;
+                    match part {
+                        StringPart::Text(text) if text.is_empty() => {}
+                        StringPart::Text(text) => {
+                            let idx = self
+                                .vm
+                                .const_pool
+                                .add_slice("", text.as_bytes(), SliceType::Utf8Str)
+                                as u16;
+                            self.builder.load_const_slice(idx, saved);
+                            self.builder.str_append(saved);
+                        }
+                        StringPart::Expr(expr) => match self.gen_expr(expr, None) {
+                            (reg, ValueKind::Str) => self.builder.str_append(reg),
+                            (reg, ValueKind::Int) => self.builder.str_append_i64(reg),
+                            (_, ValueKind::Float) => {
+                                panic!("floats cannot be formatted in an f-string yet")
+                            }
+                            (_, ValueKind::Bytes) => {
+                                panic!("bytes cannot be formatted in an f-string; use bytes_hex")
+                            }
+                            (_, kind @ (ValueKind::List | ValueKind::Dict)) => {
+                                panic!("a {} cannot be formatted in an f-string", kind.name())
+                            }
+                        },
+                    }
+                    self.next_reg = saved;
+                }
+                self.builder.str_builder_finish(dst);
+                (dst, ValueKind::Str)
+            }
+            Expr::List(items) => {
+                let parts: Vec<&Expr> = items.iter().collect();
+                let dst = self.collection_dst(target, &parts);
+                self.call_lowered("vec_host_new", &[], Some(dst));
+                for item in items {
+                    let saved = self.next_reg;
+                    let (reg, _) = self.gen_int_operand(item, "a list item");
+                    self.call_lowered("vec_host_append", &[dst, reg], None);
+                    self.next_reg = saved;
+                }
+                self.move_to_target(dst, target, ValueKind::List)
+            }
+            Expr::Dict(entries) => {
+                let parts: Vec<&Expr> = entries.iter().flat_map(|(k, v)| [k, v]).collect();
+                let dst = self.collection_dst(target, &parts);
+                self.call_lowered("map_host_new", &[], Some(dst));
+                for (key, value) in entries {
+                    let saved = self.next_reg;
+                    let (key, _) = self.gen_int_operand(key, "a dict key");
+                    let (value, _) = self.gen_int_operand(value, "a dict item");
+                    self.call_lowered("map_host_set", &[dst, key, value], None);
+                    self.next_reg = saved;
+                }
+                self.move_to_target(dst, target, ValueKind::Dict)
+            }
+            Expr::Index { target: collection, index } => {
+                let saved = self.next_reg;
+                let (reg, kind) = self.gen_collection_operand(collection);
+                let (index, _) = self.gen_int_operand(index, index_name(kind));
+                let dst = self.call_lowered(&lowered(kind, "get"), &[reg, index], target);
+                self.next_reg = saved.max(dst + 1);
+                (dst, ValueKind::Int)
+            }
+            Expr::Ternary {
+                cond,
+                then,
+                otherwise,
+            } => self.gen_ternary(cond, then, otherwise, target),
+        }
+    }
+
+    /// Numeric promotion: an int operand of an operation whose other operand
+    /// is a float is converted to a float in a new temporary, so `1 + 2.5`
+    /// is ADD_F64. Anything else is returned as is. `expr` is the operand,
+    /// checked for int literals that have no exact float.
+    fn promote(
+        &mut self,
+        expr: &Expr,
+        reg: u8,
+        kind: ValueKind,
+        other: ValueKind,
+    ) -> (u8, ValueKind) {
+        if kind != ValueKind::Int || other != ValueKind::Float {
+            return (reg, kind);
+        }
+        if let Expr::Int(n) = expr
+            && (*n as f64) as i128 != *n as i128
+        {
+            self.diagnostics
+                .warning(
+                    self.line,
+                    "precision-loss",
+                    format!("{} becomes {} as a float", n, number_format::format_f64(*n as f64)),
+                )
+                .note("floats hold every int exactly only up to 2^53");
+        }
+        let dst = self.next_reg;
+        self.next_reg += 1;
+        self.builder.i64_to_f64(reg, dst);
+        (dst, ValueKind::Float)
+    }
+
+    /// A chain of binary operators such as `a + b + c & d`, compiled along
+    /// its left operands in a loop, so a long flat chain does not recurse
+    /// once per operator. Each node gets the target and temporaries it would
+    /// if the left operands were compiled recursively.
+    fn gen_binary(&mut self, expr: &Expr, target: Option<u8>) -> (u8, ValueKind) {
+        let (leftmost, spine) = left_spine(expr);
+        let saved = self.next_reg;
+        let start = self.builder.current_pos() as usize;
+        // In `x = 1 + x` the left operand must not land in x before x is read
+        let mut targets = vec![None; spine.len()];
+        let mut left_target = target;
+        for (i, &(node, right)) in spine.iter().enumerate().rev() {
+            targets[i] = left_target;
+            left_target = match node {
+                Expr::Binary { op, .. } if is_comparison(op) => None,
+                _ => left_target.filter(|&dst| !self.reads_var_at(right, dst)),
+            };
+        }
+        let mut value = self.gen_expr(leftmost, left_target);
+        let mut left = leftmost;
+        for (i, &(node, right)) in spine.iter().enumerate() {
+            let Expr::Binary { op, .. } = node else {
+                unreachable!("the spine holds binary nodes")
+            };
+            if i > 0 {
+                // The operands of the node before are dead; only its value is kept
+                self.next_reg = saved.max(value.0.saturating_add(value.1.width()));
+                if self.next_reg > u8::MAX - REGISTER_HEADROOM {
+                    panic!("expression needs more registers than a frame has; split it into assignments");
+                }
+                self.record(left as *const Expr as *const (), start);
+            }
+            value = match op {
+                BinOp::Add => self.gen_add(left, value, right, targets[i], saved),
+                op if is_comparison(op) => self.gen_comparison(op, value, right, targets[i], saved),
+                op => self.gen_arithmetic(op, left, value, right, targets[i], saved),
+            };
+            left = node;
+        }
+        value
+    }
+
+    /// `+`: adds numbers, promoting an int to a float when the other side is
+    /// one, and concatenates two strs or two bytes. `left` is already
+    /// compiled into `lreg`.
+    fn gen_add(
+        &mut self,
+        left: &Expr,
+        (lreg, lkind): (u8, ValueKind),
+        right: &Expr,
+        target: Option<u8>,
+        saved: u8,
+    ) -> (u8, ValueKind) {
+        let (rreg, rkind) = self.gen_expr(right, None);
+        let (lreg, lkind) = self.promote(left, lreg, lkind, rkind);
+        let (rreg, rkind) = self.promote(right, rreg, rkind, lkind);
+        match (lkind, rkind) {
+            (ValueKind::Int, ValueKind::Int) => {
+                // Reuse the left operand's register only if it is a temporary,
+                // never a variable's
+                let dst = target.unwrap_or_else(|| {
+                    if lreg >= saved {
+                        lreg
+                    } else {
+                        let r = self.next_reg;
+                        self.next_reg += 1;
+                        r
+                    }
+                });
                 self.builder.add_i64(lreg, rreg, dst);
                 (dst, ValueKind::Int)
             }
-            Expr::Call { .. } => panic!("call expressions not supported here"),
-            Expr::InterpolatedString(_) => unimplemented!("f-strings not supported"),
+            (ValueKind::Float, ValueKind::Float) => {
+                let dst = target.unwrap_or_else(|| {
+                    if lreg >= saved {
+                        lreg
+                    } else {
+                        let r = self.next_reg;
+                        self.next_reg += 1;
+                        r
+                    }
+                });
+                self.builder.add_f64(lreg, rreg, dst);
+                (dst, ValueKind::Float)
+            }
+            (kind @ (ValueKind::Str | ValueKind::Bytes), other) if kind == other => {
+                require_strings();
+                let dst = target.unwrap_or_else(|| {
+                    let r = self.next_reg;
+                    self.next_reg += 2;
+                    r
+                });
+                if self.next_reg <= dst + 1 {
+                    self.next_reg = dst + 2;
+                }
+                self.builder.str_concat(lreg, rreg, dst);
+                (dst, kind)
+            }
+            (ValueKind::List, _) | (_, ValueKind::List) => panic!("lists cannot be added"),
+            (ValueKind::Dict, _) | (_, ValueKind::Dict) => panic!("dicts cannot be added"),
+            (ValueKind::Bytes, _) | (_, ValueKind::Bytes) => {
+                panic!("bytes can only be added to bytes")
+            }
+            (ValueKind::Float, _) | (_, ValueKind::Float) => {
+                panic!("cannot add str and float")
+            }
+            _ => panic!("cannot add str and int"),
         }
     }
+
+    /// `%`, `&`, `|`, `^`, `<<` and `>>` on ints, and `**` on numbers
+    /// promoted as for `+`: an int raised to an int stays an int, and a
+    /// negative exponent fails at run time
+    fn gen_arithmetic(
+        &mut self,
+        op: &BinOp,
+        left: &Expr,
+        (lreg, lkind): (u8, ValueKind),
+        right: &Expr,
+        target: Option<u8>,
+        saved: u8,
+    ) -> (u8, ValueKind) {
+        let (rreg, rkind) = self.gen_expr(right, None);
+        if *op != BinOp::Pow && (lkind, rkind) != (ValueKind::Int, ValueKind::Int) {
+            panic!("'{}' needs two ints, not {} and {}", op_symbol(op), lkind.name(), rkind.name());
+        }
+        let (lreg, lkind) = self.promote(left, lreg, lkind, rkind);
+        let (rreg, rkind) = self.promote(right, rreg, rkind, lkind);
+        let dst = target.unwrap_or_else(|| {
+            if lreg >= saved {
+                lreg
+            } else {
+                let r = self.next_reg;
+                self.next_reg += 1;
+                r
+            }
+        });
+        let b = &mut self.builder;
+        match (op, lkind, rkind) {
+            (BinOp::Mod, _, _) => b.mod_i64(lreg, rreg, dst),
+            (BinOp::BitAnd, _, _) => b.and_i64(lreg, rreg, dst),
+            (BinOp::BitOr, _, _) => b.or_i64(lreg, rreg, dst),
+            (BinOp::BitXor, _, _) => b.xor_i64(lreg, rreg, dst),
+            (BinOp::Shl, _, _) => b.shl_i64(lreg, rreg, dst),
+            (BinOp::Shr, _, _) => b.shr_i64(lreg, rreg, dst),
+            (_, ValueKind::Int, ValueKind::Int) => b.pow_i64(lreg, rreg, dst),
+            (_, ValueKind::Float, ValueKind::Float) => b.pow_f64(lreg, rreg, dst),
+            _ => panic!("'**' needs two numbers, not {} and {}", lkind.name(), rkind.name()),
+        }
+        (dst, lkind)
+    }
+
+    /// `<`, `<=`, `>` and `>=` on numbers, giving 1 or 0. An int and a float
+    /// compare exactly with the mixed opcodes, which take the int first, so a
+    /// float on the left swaps the operands and mirrors the comparison.
+    fn gen_comparison(
+        &mut self,
+        op: &BinOp,
+        (l, l_kind): (u8, ValueKind),
+        right: &Expr,
+        target: Option<u8>,
+        saved: u8,
+    ) -> (u8, ValueKind) {
+        let (r, r_kind) = self.gen_expr(right, None);
+        let dst = target.unwrap_or(saved);
+        self.emit_comparison(op, (l, l_kind), (r, r_kind), dst);
+        self.next_reg = saved.max(dst + 1);
+        (dst, ValueKind::Int)
+    }
+
+    /// `a < b < c` as `a < b and b < c` with `b` evaluated once: each
+    /// operand is kept for the next comparison, and the first false one
+    /// jumps past the rest. The result is built in a fresh register, as an
+    /// operand still to come may read the variable at `target`.
+    fn gen_chained_comparison(
+        &mut self,
+        left: &Expr,
+        rest: &[(BinOp, Expr)],
+        target: Option<u8>,
+    ) -> (u8, ValueKind) {
+        let saved = self.next_reg;
+        let result = self.next_reg;
+        self.next_reg += 1;
+        let end = self.builder.create_label();
+        let mut l = self.gen_expr(left, None);
+        for (i, (op, right)) in rest.iter().enumerate() {
+            let r = self.gen_expr(right, None);
+            self.emit_comparison(op, l, r, result);
+            if i + 1 < rest.len() {
+                self.builder.jump_if_false_to_label(result, end);
+            }
+            l = r;
+        }
+        self.builder.place_label(end);
+        self.next_reg = saved.max(result + 1);
+        self.move_to_target(result, target, ValueKind::Int)
+    }
+
+    /// One comparison of values already in registers, 1 or 0 to `dst`
+    fn emit_comparison(
+        &mut self,
+        op: &BinOp,
+        (l, l_kind): (u8, ValueKind),
+        (r, r_kind): (u8, ValueKind),
+        dst: u8,
+    ) {
+        let b = &mut self.builder;
+        match (l_kind, r_kind, op) {
+            (ValueKind::Int, ValueKind::Int, BinOp::Lt) => b.lt_i64(l, r, dst),
+            (ValueKind::Int, ValueKind::Int, BinOp::Le) => b.lte_i64(l, r, dst),
+            (ValueKind::Int, ValueKind::Int, BinOp::Gt) => b.gt_i64(l, r, dst),
+            (ValueKind::Int, ValueKind::Int, BinOp::Ge) => b.gte_i64(l, r, dst),
+            (ValueKind::Float, ValueKind::Float, BinOp::Lt) => b.lt_f64(l, r, dst),
+            (ValueKind::Float, ValueKind::Float, BinOp::Le) => b.lte_f64(l, r, dst),
+            (ValueKind::Float, ValueKind::Float, BinOp::Gt) => b.gt_f64(l, r, dst),
+            (ValueKind::Float, ValueKind::Float, BinOp::Ge) => b.gte_f64(l, r, dst),
+            (ValueKind::Int, ValueKind::Float, BinOp::Lt) => b.lt_i64_f64(l, r, dst),
+            (ValueKind::Int, ValueKind::Float, BinOp::Le) => b.lte_i64_f64(l, r, dst),
+            (ValueKind::Int, ValueKind::Float, BinOp::Gt) => b.gt_i64_f64(l, r, dst),
+            (ValueKind::Int, ValueKind::Float, BinOp::Ge) => b.gte_i64_f64(l, r, dst),
+            (ValueKind::Float, ValueKind::Int, BinOp::Lt) => b.gt_i64_f64(r, l, dst),
+            (ValueKind::Float, ValueKind::Int, BinOp::Le) => b.gte_i64_f64(r, l, dst),
+            (ValueKind::Float, ValueKind::Int, BinOp::Gt) => b.lt_i64_f64(r, l, dst),
+            (ValueKind::Float, ValueKind::Int, BinOp::Ge) => b.lte_i64_f64(r, l, dst),
+            _ => panic!("cannot compare {} and {}", l_kind.name(), r_kind.name()),
+        }
+    }
+
+    /// Copy a value of `kind` from `src` to `dst`. Ints and floats use
+    /// MOV_VALUE, which leaves the register type alone: only strings and
+    /// bytes, and lists and dicts held by hosts, carry one that matters.
+    fn gen_move(&mut self, src: u8, dst: u8, kind: ValueKind) {
+        if let ValueKind::Int | ValueKind::Float = kind {
+            self.builder.mov_value(src, dst);
+            return;
+        }
+        for i in 0..kind.width() {
+            self.builder.mov(src + i, dst + i);
+        }
+    }
+
+    /// Register a new list or dict is built in: `target`, unless one of
+    /// `parts` reads the variable there, which the new value would overwrite
+    fn collection_dst(&mut self, target: Option<u8>, parts: &[&Expr]) -> u8 {
+        let dst = match target {
+            Some(dst) if !parts.iter().any(|part| self.reads_var_at(part, dst)) => dst,
+            _ => {
+                let r = self.next_reg;
+                self.next_reg += 1;
+                r
+            }
+        };
+        self.next_reg = self.next_reg.max(dst + 1);
+        dst
+    }
+
+    /// The one-register value in `dst`, moved to `target` if that differs
+    fn move_to_target(&mut self, dst: u8, target: Option<u8>, kind: ValueKind) -> (u8, ValueKind) {
+        match target {
+            Some(t) if t != dst => {
+                self.gen_move(dst, t, kind);
+                (t, kind)
+            }
+            _ => (dst, kind),
+        }
+    }
+
+    /// Call host function `name`, which a list or dict operation is lowered
+    /// to, on values already in `arg_regs`; the result is one register
+    fn call_lowered(&mut self, name: &str, arg_regs: &[u8], target: Option<u8>) -> u8 {
+        let fn_index = self.vm.host_functions.find(name).unwrap_or_else(|| {
+            let (what, module) = if name.starts_with("map_host") {
+                ("dicts", "map_host")
+            } else {
+                ("lists", "vec_host")
+            };
+            panic!("{} need the {} functions registered; '{}' is missing", what, module, name)
+        });
+        let meta = self.vm.host_functions.metadata[fn_index].clone();
+        let base = self.next_reg;
+        self.next_reg = self.next_reg.max(base + meta.num_registers as u8);
+        let fn_const = self.host_fn_const(fn_index);
+        self.builder.call_host_fn(&meta, fn_const, arg_regs, base);
+        match target {
+            Some(dst) if dst != base => {
+                self.builder.mov(base, dst);
+                self.next_reg = base.max(dst + 1);
+                dst
+            }
+            _ => {
+                self.next_reg = base + 1;
+                base
+            }
+        }
+    }
+
+    fn gen_collection_operand(&mut self, expr: &Expr) -> (u8, ValueKind) {
+        match self.gen_expr(expr, None) {
+            (reg, kind @ (ValueKind::List | ValueKind::Dict)) => (reg, kind),
+            (_, kind) => panic!("cannot index a {}", kind.name()),
+        }
+    }
+
+    /// Variants of the enum `expr` names; attributes exist only on enums
+    /// `module.name` as the global it reads; a name the module does not
+    /// define is an error
+    fn module_var(&self, on: &Expr, name: &str) -> Option<String> {
+        let Expr::Ident(module) = on else {
+            return None;
+        };
+        let var = format!("{}.{}", module, name);
+        if self.vars.contains_key(&var) {
+            return Some(var);
+        }
+        if self.is_module(on) {
+            panic!("module '{}' has no global '{}'", module, name);
+        }
+        None
+    }
+
+    /// `expr` names a module imported by this or an earlier compilation
+    fn is_module(&self, expr: &Expr) -> bool {
+        let Expr::Ident(module) = expr else {
+            return false;
+        };
+        let prefix = format!("{}.", module);
+        self.imported.contains(module) || self.vars.keys().any(|var| var.starts_with(&prefix))
+    }
+
+    fn is_enum(&self, expr: &Expr) -> bool {
+        matches!(expr, Expr::Ident(name) if self.enums.contains_key(name))
+    }
+
+    fn enum_of(&self, expr: &Expr) -> &Vec<String> {
+        match self.enums.get(enum_name(expr)) {
+            Some(variants) => variants,
+            None => panic!("only enums have attributes, and '{}' is not one", enum_name(expr)),
+        }
+    }
+
+    /// `Enum.name(x)`: the variant name of int `x`, picked from the names in
+    /// the const pool by one SELECT per variant; other values fail with
+    /// `VmError::InvalidEnumValue`
+    fn gen_enum_name(
+        &mut self,
+        enum_name: &str,
+        variants: &[String],
+        arg: &Expr,
+        target: Option<u8>,
+    ) -> (u8, ValueKind) {
+        let saved = self.next_reg;
+        let (value, _) = self.gen_int_operand(arg, "the argument of name()");
+        // `value` is read after dst is first written
+        let dst = target.filter(|&dst| !(dst..dst + 2).contains(&value)).unwrap_or(self.next_reg);
+        let tmp = self.next_reg.max(dst + 2);
+        let (name, cond) = (tmp, tmp + 2);
+        self.next_reg = tmp + 3;
+
+        let count = self.vm.const_pool.add_value("", variants.len() as u64, ValueType::I64) as u16;
+        let zero = self.vm.const_pool.add_value("", 0, ValueType::I64) as u16;
+        let enum_name = self.vm.const_pool.add_slice("", enum_name.as_bytes(), SliceType::Utf8Str) as u16;
+        self.builder.load_const_value(zero, cond);
+        self.builder.gte_i64(value, cond, cond);
+        self.builder.assert_enum_value(cond, value, enum_name);
+        self.builder.load_const_value(count, cond);
+        self.builder.lt_i64(value, cond, cond);
+        self.builder.assert_enum_value(cond, value, enum_name);
+
+        for (i, variant) in variants.iter().enumerate() {
+            let idx = self.vm.const_pool.add_slice("", variant.as_bytes(), SliceType::Utf8Str) as u16;
+            if i == 0 {
+                self.builder.load_const_slice(idx, dst);
+                continue;
+            }
+            let i = self.vm.const_pool.add_value("", i as u64, ValueType::I64) as u16;
+            self.builder.load_const_slice(idx, name);
+            self.builder.load_const_value(i, cond);
+            // Non-zero unless `value` is this variant
+            self.builder.sub_i64(value, cond, cond);
+            self.builder.select(cond, dst, name, dst);
+            self.builder.select(cond, dst + 1, name + 1, dst + 1);
+        }
+        self.next_reg = saved.max(dst + 2);
+        (dst, ValueKind::Str)
+    }
+
+    /// `Enum.parse(s)`: the value of the variant named by str `s`, found by
+    /// comparing it with each name; other strings fail with
+    /// `VmError::UnknownEnumName`
+    fn gen_enum_parse(
+        &mut self,
+        enum_name: &str,
+        variants: &[String],
+        arg: &Expr,
+        target: Option<u8>,
+    ) -> (u8, ValueKind) {
+        require_strings();
+        let saved = self.next_reg;
+        let s = match self.gen_expr(arg, None) {
+            (reg, ValueKind::Str) => reg,
+            (_, kind) => panic!("the argument of parse() must be a str, not a {}", kind.name()),
+        };
+        let dst = target.filter(|&dst| !(s..s + 2).contains(&dst)).unwrap_or(self.next_reg);
+        let tmp = self.next_reg.max(dst + 1);
+        let (name, cond, value) = (tmp, tmp + 2, tmp + 3);
+        self.next_reg = tmp + 4;
+
+        let missing = self.vm.const_pool.add_value("", -1i64 as u64, ValueType::I64) as u16;
+        self.builder.load_const_value(missing, dst);
+        for (i, variant) in variants.iter().enumerate() {
+            let idx = self.vm.const_pool.add_slice("", variant.as_bytes(), SliceType::Utf8Str) as u16;
+            let i = self.vm.const_pool.add_value("", i as u64, ValueType::I64) as u16;
+            self.builder.load_const_slice(idx, name);
+            self.builder.str_eq(s, name, cond);
+            self.builder.load_const_value(i, value);
+            self.builder.select(cond, value, dst, dst);
+        }
+        let zero = self.vm.const_pool.add_value("", 0, ValueType::I64) as u16;
+        let enum_name = self.vm.const_pool.add_slice("", enum_name.as_bytes(), SliceType::Utf8Str) as u16;
+        self.builder.load_const_value(zero, cond);
+        self.builder.gte_i64(dst, cond, cond);
+        self.builder.assert_enum_name(cond, s, enum_name);
+        self.next_reg = saved.max(dst + 1);
+        (dst, ValueKind::Int)
+    }
+
+    /// Bind `name` to a folded `value` for the rest of the compilation
+    fn define_const(&mut self, name: &str, value: Expr) {
+        if self.current.is_some() {
+            panic!("constants can only be defined at the top level");
+        }
+        if self.consts.contains_key(name) {
+            panic!("constant '{}' is already defined", name);
+        }
+        if self.vars.contains_key(name) {
+            panic!("'{}' is already a variable and cannot become a constant", name);
+        }
+        self.consts.insert(name.to_string(), value);
+    }
+
+    /// The literal `expr` evaluates to, for `const name = expr`: literals,
+    /// earlier constants, `+`, comparisons and conditionals of those
+    fn fold(&self, name: &str, expr: &Expr) -> Expr {
+        let not_constant = || -> ! {
+            panic!("the value of constant '{}' is not known at compile time", name)
+        };
+        match expr {
+            Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Bytes(_) => expr.clone(),
+            Expr::Ident(other) => match self.consts.get(other) {
+                Some(value) => value.clone(),
+                None => not_constant(),
+            },
+            Expr::Binary { .. } => {
+                // Along the left operands in a loop, as `gen_binary` does
+                let (leftmost, spine) = left_spine(expr);
+                let mut value = self.fold(name, leftmost);
+                for (node, right) in spine {
+                    let Expr::Binary { op, .. } = node else {
+                        unreachable!("the spine holds binary nodes")
+                    };
+                    value = self.fold_binary(name, op, value, self.fold(name, right));
+                }
+                value
+            }
+            Expr::Ternary { cond, then, otherwise } => {
+                let truthy = match self.fold(name, cond) {
+                    Expr::Int(n) => n != 0,
+                    Expr::Str(s) => !s.is_empty(),
+                    Expr::Bytes(b) => !b.is_empty(),
+                    _ => not_constant(),
+                };
+                self.fold(name, if truthy { then } else { otherwise })
+            }
+            Expr::Compare { left, rest } => {
+                let mut left = self.fold(name, left);
+                for (op, right) in rest {
+                    let right = self.fold(name, right);
+                    if self.fold_binary(name, op, left, right.clone()) == Expr::Int(0) {
+                        return Expr::Int(0);
+                    }
+                    left = right;
+                }
+                Expr::Int(1)
+            }
+            Expr::Unary { op: UnaryOp::BitNot, operand } => match self.fold(name, operand) {
+                Expr::Int(n) => Expr::Int(!n),
+                _ => not_constant(),
+            },
+            _ => not_constant(),
+        }
+    }
+
+    /// `left op right` of two folded constants
+    fn fold_binary(&self, name: &str, op: &BinOp, left: Expr, right: Expr) -> Expr {
+        let not_constant = || -> ! {
+            panic!("the value of constant '{}' is not known at compile time", name)
+        };
+        let num = |e: &Expr| match *e {
+            Expr::Int(n) => Some(n as f64),
+            Expr::Float(x) => Some(x),
+            _ => None,
+        };
+        match (op, &left, &right) {
+            (BinOp::Add, Expr::Int(a), Expr::Int(b)) => match a.checked_add(*b) {
+                Some(n) => Expr::Int(n),
+                None => panic!("constant '{}' overflows an int", name),
+            },
+            (BinOp::Add, Expr::Str(a), Expr::Str(b)) => Expr::Str(format!("{}{}", a, b)),
+            (BinOp::Add, Expr::Bytes(a), Expr::Bytes(b)) => Expr::Bytes([&a[..], b].concat()),
+            (BinOp::Add, _, _) => match (num(&left), num(&right)) {
+                (Some(a), Some(b)) => Expr::Float(a + b),
+                _ => not_constant(),
+            },
+            (BinOp::Mod, Expr::Int(_), Expr::Int(0)) => {
+                panic!("constant '{}' takes a remainder by zero", name)
+            }
+            (BinOp::Mod, Expr::Int(a), Expr::Int(b)) => Expr::Int(crate::vm::mod_i64(*a, *b)),
+            (BinOp::Mod, _, _) => not_constant(),
+            (BinOp::Pow, Expr::Int(a), Expr::Int(b)) => {
+                match u32::try_from(*b).ok().and_then(|b| a.checked_pow(b)) {
+                    Some(n) => Expr::Int(n),
+                    None if *b < 0 => panic!("constant '{}' raises an int to a negative power", name),
+                    None => panic!("constant '{}' overflows an int", name),
+                }
+            }
+            (BinOp::Pow, _, _) => match (num(&left), num(&right)) {
+                (Some(a), Some(b)) => Expr::Float(a.powf(b)),
+                _ => not_constant(),
+            },
+            (BinOp::Shl | BinOp::Shr, Expr::Int(_), Expr::Int(b)) if *b < 0 => {
+                panic!("constant '{}' shifts by a negative count", name)
+            }
+            (BinOp::BitAnd, Expr::Int(a), Expr::Int(b)) => Expr::Int(a & b),
+            (BinOp::BitOr, Expr::Int(a), Expr::Int(b)) => Expr::Int(a | b),
+            (BinOp::BitXor, Expr::Int(a), Expr::Int(b)) => Expr::Int(a ^ b),
+            (BinOp::Shl, Expr::Int(a), Expr::Int(b)) => Expr::Int(crate::vm::shl_i64(*a, *b as u64)),
+            (BinOp::Shr, Expr::Int(a), Expr::Int(b)) => Expr::Int(crate::vm::shr_i64(*a, *b as u64)),
+            (op, _, _) if !is_comparison(op) => not_constant(),
+            (_, Expr::Int(a), Expr::Int(b)) => Expr::Int(compare(op, a, b) as i64),
+            _ => match (num(&left), num(&right)) {
+                (Some(a), Some(b)) => Expr::Int(compare(op, &a, &b) as i64),
+                _ => not_constant(),
+            },
+        }
+    }
+
+    /// Evaluate `expr`, which `what` must be an int
+    fn gen_int_operand(&mut self, expr: &Expr, what: &str) -> (u8, ValueKind) {
+        match self.gen_expr(expr, None) {
+            (reg, ValueKind::Int) => (reg, ValueKind::Int),
+            (_, kind) => panic!("{} must be an int, not a {}", what, kind.name()),
+        }
+    }
+
+    /// `len(x)`: the length register of a str or bytes, or `vec_host_len` /
+    /// `map_host_len` of a list or dict
+    fn gen_len(&mut self, args: &[Expr], target: Option<u8>) -> (u8, ValueKind) {
+        let [arg] = args else {
+            panic!("len() takes 1 arguments but {} were given", args.len());
+        };
+        let saved = self.next_reg;
+        let (reg, kind) = self.gen_expr(arg, None);
+        let dst = match kind {
+            ValueKind::Str | ValueKind::Bytes => {
+                let dst = target.unwrap_or(saved);
+                self.builder.mov_value(reg + 1, dst);
+                dst
+            }
+            ValueKind::List | ValueKind::Dict => {
+                self.call_lowered(&lowered(kind, "len"), &[reg], target)
+            }
+            ValueKind::Int | ValueKind::Float => panic!("a {} has no len()", kind.name()),
+        };
+        self.next_reg = saved.max(dst + 1);
+        (dst, ValueKind::Int)
+    }
+
+    /// `min(a, b)` / `max(a, b)` on two numbers: one MIN/MAX opcode, no
+    /// branches. An int with a float is promoted as for `+`.
+    fn gen_min_max(&mut self, min: bool, args: &[Expr], target: Option<u8>) -> (u8, ValueKind) {
+        let name = if min { "min" } else { "max" };
+        let [a, b] = args else {
+            panic!("{}() takes 2 arguments but {} were given", name, args.len());
+        };
+        let saved = self.next_reg;
+        let (a_reg, a_kind) = self.gen_expr(a, None);
+        let (b_reg, b_kind) = self.gen_expr(b, None);
+        let (a, a_kind) = self.promote(a, a_reg, a_kind, b_kind);
+        let (b, b_kind) = self.promote(b, b_reg, b_kind, a_kind);
+        // Like every arithmetic opcode, MIN/MAX read both operands before
+        // writing, so `dst` may reuse either of them
+        let dst = target.unwrap_or(saved);
+        match (a_kind, b_kind, min) {
+            (ValueKind::Int, ValueKind::Int, true) => self.builder.min_i64(a, b, dst),
+            (ValueKind::Int, ValueKind::Int, false) => self.builder.max_i64(a, b, dst),
+            (ValueKind::Float, ValueKind::Float, true) => self.builder.min_f64(a, b, dst),
+            (ValueKind::Float, ValueKind::Float, false) => self.builder.max_f64(a, b, dst),
+            _ => panic!("{}() expects two ints or two floats", name),
+        }
+        self.next_reg = saved.max(dst + 1);
+        (dst, a_kind)
+    }
+
+    /// `abs(x)` on an int or a float: one ABS opcode
+    fn gen_abs(&mut self, args: &[Expr], target: Option<u8>) -> (u8, ValueKind) {
+        let [x] = args else {
+            panic!("abs() takes 1 argument but {} were given", args.len());
+        };
+        let saved = self.next_reg;
+        let (x, kind) = self.gen_expr(x, None);
+        let dst = target.unwrap_or(saved);
+        match kind {
+            ValueKind::Int => self.builder.abs_i64(x, dst),
+            ValueKind::Float => self.builder.abs_f64(x, dst),
+            _ => panic!("abs() expects an int or a float"),
+        }
+        self.next_reg = saved.max(dst + 1);
+        (dst, kind)
+    }
+
+    /// `round(x)`, `floor(x)`, `ceil(x)` and `trunc(x)`: a float to an int,
+    /// failing at runtime on NaN, infinities and values out of the int range
+    fn gen_round(&mut self, name: &str, args: &[Expr], target: Option<u8>) -> (u8, ValueKind) {
+        let [x] = args else {
+            panic!("{}() takes 1 argument but {} were given", name, args.len());
+        };
+        let saved = self.next_reg;
+        let (x, kind) = self.gen_expr(x, None);
+        if kind != ValueKind::Float {
+            panic!("{}() expects a float", name);
+        }
+        let dst = target.unwrap_or(saved);
+        match name {
+            "round" => self.builder.round_f64(x, dst),
+            "floor" => self.builder.floor_f64(x, dst),
+            "ceil" => self.builder.ceil_f64(x, dst),
+            _ => self.builder.trunc_f64(x, dst),
+        }
+        self.next_reg = saved.max(dst + 1);
+        (dst, ValueKind::Int)
+    }
+
+    /// `then if cond else otherwise`. Branches without calls are both
+    /// evaluated and picked with SELECT; a branch with a call, which may have
+    /// effects, only runs when taken.
+    fn gen_ternary(
+        &mut self,
+        cond: &Expr,
+        then: &Expr,
+        otherwise: &Expr,
+        target: Option<u8>,
+    ) -> (u8, ValueKind) {
+        let saved = self.next_reg;
+        let (reg, kind) = self.gen_expr(cond, None);
+        let mut cond_reg = truth_reg(reg, kind);
+        if has_call(then) || has_call(otherwise) {
+            return self.gen_ternary_branches(cond_reg, then, otherwise, target, saved);
+        }
+        let (a, a_kind) = self.gen_expr(then, None);
+        let (b, b_kind) = self.gen_expr(otherwise, None);
+        if a_kind != b_kind {
+            panic!("both branches of a conditional expression must have the same type");
+        }
+        let width = a_kind.width();
+        let dst = target.unwrap_or(self.next_reg);
+        // One SELECT per register: the condition must survive the first one
+        if width > 1 && (dst..dst + width).contains(&cond_reg) {
+            let copy = self.next_reg.max(dst + width);
+            self.builder.mov_value(cond_reg, copy);
+            cond_reg = copy;
+        }
+        for i in 0..width {
+            self.builder.select(cond_reg, a + i, b + i, dst + i);
+        }
+        self.next_reg = saved.max(dst + width);
+        (dst, a_kind)
+    }
+
+    fn gen_ternary_branches(
+        &mut self,
+        cond_reg: u8,
+        then: &Expr,
+        otherwise: &Expr,
+        target: Option<u8>,
+        saved: u8,
+    ) -> (u8, ValueKind) {
+        let dst = target.unwrap_or(self.next_reg);
+        // Room for a value of either width
+        self.next_reg = self.next_reg.max(dst + 2);
+        let else_label = self.builder.create_label();
+        let end = self.builder.create_label();
+        self.builder.jump_if_false_to_label(cond_reg, else_label);
+        let kind = self.gen_into(then, dst);
+        self.builder.jmp_to_label(end);
+        self.builder.place_label(else_label);
+        if self.gen_into(otherwise, dst) != kind {
+            panic!("both branches of a conditional expression must have the same type");
+        }
+        self.builder.place_label(end);
+        self.next_reg = saved.max(dst + kind.width());
+        (dst, kind)
+    }
+
+    /// Whether evaluating `expr` reads a variable held in `reg` or, for a
+    /// two-register value written to `reg`, in `reg + 1`
+    fn reads_var_at(&self, expr: &Expr, reg: u8) -> bool {
+        match expr {
+            Expr::Ident(name) => match (self.vars.get(name), self.types.get(name)) {
+                (Some(&start), Some(kind)) => {
+                    let (start, reg) = (start as u16, reg as u16);
+                    start < reg + 2 && reg < start + kind.width() as u16
+                }
+                _ => false,
+            },
+            Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Bytes(_) => false,
+            Expr::Binary { .. } => {
+                let (leftmost, spine) = left_spine(expr);
+                self.reads_var_at(leftmost, reg) || spine.iter().any(|(_, right)| self.reads_var_at(right, reg))
+            }
+            Expr::Unary { operand, .. } => self.reads_var_at(operand, reg),
+            Expr::Call { args, .. } => args.iter().any(|arg| self.reads_var_at(arg, reg)),
+            Expr::InterpolatedString(parts) => parts
+                .iter()
+                .any(|part| matches!(part, StringPart::Expr(e) if self.reads_var_at(e, reg))),
+            Expr::Ternary {
+                cond,
+                then,
+                otherwise,
+            } => [cond, then, otherwise]
+                .iter()
+                .any(|e| self.reads_var_at(e, reg)),
+            Expr::Compare { left, rest } => {
+                self.reads_var_at(left, reg) || rest.iter().any(|(_, e)| self.reads_var_at(e, reg))
+            }
+            Expr::List(items) => items.iter().any(|item| self.reads_var_at(item, reg)),
+            Expr::Dict(entries) => entries
+                .iter()
+                .any(|(key, value)| self.reads_var_at(key, reg) || self.reads_var_at(value, reg)),
+            Expr::Index { target, index } => {
+                self.reads_var_at(target, reg) || self.reads_var_at(index, reg)
+            }
+            Expr::Attribute { target, name } => match target.as_ref() {
+                Expr::Ident(module) => {
+                    self.reads_var_at(&Expr::Ident(format!("{}.{}", module, name)), reg)
+                }
+                _ => false,
+            },
+        }
+    }
+
+    /// Evaluate `expr` into `dst`, freeing its temporaries afterwards
+    fn gen_into(&mut self, expr: &Expr, dst: u8) -> ValueKind {
+        let saved = self.next_reg;
+        let (reg, kind) = self.gen_expr(expr, Some(dst));
+        if reg != dst {
+            self.gen_move(reg, dst, kind);
+        }
+        self.next_reg = saved;
+        kind
+    }
+}
+
+/// Register that is non-zero when the value in `reg` is truthy: strings and
+/// bytes are tested through their length register
+fn truth_reg(reg: u8, kind: ValueKind) -> u8 {
+    if kind == ValueKind::Float {
+        panic!("a float cannot be used as a condition");
+    }
+    if let ValueKind::List | ValueKind::Dict = kind {
+        panic!("a {} cannot be used as a condition; test len() instead", kind.name());
+    }
+    reg + kind.width() - 1
+}
+
+/// Whether evaluating `expr` calls a host function
+fn has_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Bytes(_) | Expr::Ident(_) => false,
+        Expr::Call { .. } => true,
+        Expr::Binary { .. } => {
+            let (leftmost, spine) = left_spine(expr);
+            has_call(leftmost) || spine.iter().any(|(_, right)| has_call(right))
+        }
+        Expr::Unary { operand, .. } => has_call(operand),
+        Expr::InterpolatedString(parts) => parts
+            .iter()
+            .any(|part| matches!(part, StringPart::Expr(expr) if has_call(expr))),
+        Expr::Ternary {
+            cond,
+            then,
+            otherwise,
+        } => has_call(cond) || has_call(then) || has_call(otherwise),
+        Expr::Compare { left, rest } => has_call(left) || rest.iter().any(|(_, e)| has_call(e)),
+        // Lowered to host calls
+        Expr::List(_) | Expr::Dict(_) | Expr::Index { .. } => true,
+        Expr::Attribute { .. } => false,
+    }
+}
+
+/// The kind a type annotation names
+/// `def` header as `help()` shows it, e.g. `add(a: int, b) -> int`
+fn signature(
+    name: &str,
+    params: &[String],
+    annotations: &[Option<String>],
+    returns: Option<&str>,
+) -> String {
+    let params: Vec<String> = params
+        .iter()
+        .zip(annotations)
+        .map(|(param, ty)| match ty {
+            Some(ty) => format!("{}: {}", param, ty),
+            None => param.clone(),
+        })
+        .collect();
+    let mut sig = format!("{}({})", name, params.join(", "));
+    if let Some(ty) = returns {
+        sig.push_str(&format!(" -> {}", ty));
+    }
+    sig
+}
+
+fn annotated_kind(ty: &str) -> ValueKind {
+    ValueKind::named(ty).unwrap_or_else(|| {
+        panic!("unknown type '{}'; expected int, float, str, bytes, list or dict", ty)
+    })
+}
+
+/// The name an attribute's target must be, the enum it belongs to
+fn enum_name(expr: &Expr) -> &str {
+    match expr {
+        Expr::Ident(name) => name,
+        _ => panic!("only enums have attributes"),
+    }
+}
+
+/// Host function implementing `op` ("new", "get", ...) for a list or dict
+fn lowered(kind: ValueKind, op: &str) -> String {
+    match kind {
+        ValueKind::List => format!("vec_host_{}", op),
+        _ => format!("map_host_{}", op),
+    }
+}
+
+/// What the index of a list or dict is called in errors
+fn index_name(kind: ValueKind) -> &'static str {
+    match kind {
+        ValueKind::List => "a list index",
+        _ => "a dict key",
+    }
+}
+
+/// `a op b` for a comparison operator
+fn compare<T: PartialOrd>(op: &BinOp, a: &T, b: &T) -> bool {
+    match op {
+        BinOp::Lt => a < b,
+        BinOp::Le => a <= b,
+        BinOp::Gt => a > b,
+        BinOp::Ge => a >= b,
+        _ => unreachable!("not a comparison"),
+    }
+}
+
+fn is_comparison(op: &BinOp) -> bool {
+    matches!(op, BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge)
+}
+
+/// How `op` is written, for errors
+fn op_symbol(op: &BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Mod => "%",
+        BinOp::Pow => "**",
+        BinOp::BitAnd => "&",
+        BinOp::BitOr => "|",
+        BinOp::BitXor => "^",
+        BinOp::Shl => "<<",
+        BinOp::Shr => ">>",
+        BinOp::Lt => "<",
+        BinOp::Le => "<=",
+        BinOp::Gt => ">",
+        BinOp::Ge => ">=",
+    }
+}
+
+/// Float literals and arithmetic need the `isa-float` opcodes
+fn require_floats() {
+    if SUPPORTED_ISA_FEATURES & ISA_FLOAT == 0 {
+        panic!("float operations need kayton built with the `isa-float` feature");
+    }
+}
+
+/// Runtime string operations need the `isa-strings` opcodes
+fn require_strings() {
+    if SUPPORTED_ISA_FEATURES & ISA_STRINGS == 0 {
+        panic!("string operations need kayton built with the `isa-strings` feature");
+    }
 }
 
 pub fn generate_bytecode(
@@ -146,7 +2338,55 @@ pub fn generate_bytecode(
     vm: &mut VirtualMachine,
     print_const: u16,
 ) -> Vec<u8> {
-    CodeGenerator::new(vm, print_const).compile(stmts)
+    generate_bytecode_with_lines(stmts, &[], vm, print_const)
+}
+
+/// Like `generate_bytecode`, with the source line of each statement (see
+/// `Parser::stmt_lines`) recorded in the VM's source map
+pub fn generate_bytecode_with_lines(
+    stmts: &[Stmt],
+    lines: &[u32],
+    vm: &mut VirtualMachine,
+    print_const: u16,
+) -> Vec<u8> {
+    CodeGenerator::new(vm, print_const).compile(stmts, lines)
+}
+
+/// Like `generate_bytecode_with_lines`, reporting warnings and, instead of
+/// panicking, errors to `diagnostics`. Returns `None` after an error.
+/// `budget` carries on the clock of a compilation whose parsing it timed.
+pub fn generate_bytecode_with_diagnostics(
+    stmts: &[Stmt],
+    lines: &[u32],
+    modules: &[Module],
+    vm: &mut VirtualMachine,
+    print_const: u16,
+    budget: &CompileBudget,
+    diagnostics: &mut Diagnostics,
+) -> Option<Vec<u8>> {
+    let mut generator = CodeGenerator::new(vm, print_const);
+    generator.budget = *budget;
+    generator.modules = modules;
+    let result = catch_compile_panic(|| generator.compile(stmts, lines));
+    diagnostics.items.append(&mut generator.diagnostics.items);
+    match result {
+        Ok(bytecode) => Some(bytecode),
+        Err(payload) => {
+            let code = panic_code(payload.as_ref(), "compile");
+            diagnostics.error(generator.line, code, panic_message(payload.as_ref()));
+            None
+        }
+    }
+}
+
+/// Lex, parse, apply the VM's AST rewriters and generate bytecode for `src`
+/// in the standard syntax (see `frontend::compile_with` for others).
+pub fn compile_source(
+    src: &str,
+    vm: &mut VirtualMachine,
+    print_const: u16,
+) -> Result<Vec<u8>, String> {
+    compile_with(&KaytonSyntax, src, vm, print_const)
 }
 
 #[cfg(test)]
@@ -47,7 +47,7 @@ print(x)
 "#;
     let tokens = Lexer::new(src).tokenize();
     let mut parser = Parser::new(tokens);
-    let stmts = parser.parse_program();
+    let stmts = parser.parse().unwrap();
 
     let (mut vm, print_const) = setup_vm();
     output().lock().unwrap().clear();
@@ -64,7 +64,7 @@ fn program2_codegen() {
     let src = r#"print("Hello, World")"#;
     let tokens = Lexer::new(src).tokenize();
     let mut parser = Parser::new(tokens);
-    let stmts = parser.parse_program();
+    let stmts = parser.parse().unwrap();
 
     let (mut vm, print_const) = setup_vm();
     output().lock().unwrap().clear();
@@ -81,7 +81,7 @@ fn populates_global_vars() {
     let src = r#"x = 1"#;
     let tokens = Lexer::new(src).tokenize();
     let mut parser = Parser::new(tokens);
-    let stmts = parser.parse_program();
+    let stmts = parser.parse().unwrap();
 
     let (mut vm, print_const) = setup_vm();
     generate_bytecode(&stmts, &mut vm, print_const);
@@ -90,3 +90,1105 @@ fn populates_global_vars() {
     assert_eq!(var.register_id, 1);
     assert!(matches!(var.meta.typ, GlobalVarType::Value(ValueType::I64)));
 }
+
+#[test]
+fn docstring_and_help_builtin() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let src = r#""Adds things up."
+def add(a: int, b) -> int:
+    "Sum of a and b."
+    return a + b
+end
+help()
+help(print)
+help(add)
+"#;
+    let tokens = Lexer::new(src).tokenize();
+    let mut parser = Parser::new(tokens);
+    let stmts = parser.parse().unwrap();
+
+    let (mut vm, print_const) = setup_vm();
+    output().lock().unwrap().clear();
+    let bytecode = generate_bytecode(&stmts, &mut vm, print_const);
+    vm.eval_program(&bytecode).unwrap();
+
+    let out = output().lock().unwrap().clone();
+    assert_eq!(
+        out,
+        vec![
+            "Adds things up.".to_string(),
+            "print(arg1)".to_string(),
+            "add(a: int, b) -> int\n\nSum of a and b.".to_string(),
+        ]
+    );
+}
+
+fn host_double(base: usize, registers: &mut Registers) -> Result<(), String> {
+    let val = registers.get(base + 1) as i64;
+    registers.set(base, (val * 2) as u64);
+    Ok(())
+}
+
+fn host_add(base: usize, registers: &mut Registers) -> Result<(), String> {
+    let a = registers.get(base + 1) as i64;
+    let b = registers.get(base + 2) as i64;
+    registers.set(base, (a + b) as u64);
+    Ok(())
+}
+
+#[test]
+fn host_function_calls() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let src = r#"x = double(21)
+print(x)
+y = 1
+print(add(double(y), 3))
+"#;
+    let tokens = Lexer::new(src).tokenize();
+    let stmts = Parser::new(tokens).parse().unwrap();
+
+    let (mut vm, print_const) = setup_vm();
+    vm.host_functions.register("double", 1, 1, 2, host_double);
+    vm.host_functions.register("add", 1, 2, 3, host_add);
+    output().lock().unwrap().clear();
+    let bytecode = generate_bytecode(&stmts, &mut vm, print_const);
+    vm.eval_program(&bytecode).unwrap();
+
+    let out = output().lock().unwrap().clone();
+    assert_eq!(out, vec!["42".to_string(), "5".to_string()]);
+    let y = vm.global_vars.get("y").unwrap().register_id;
+    assert_eq!(vm.get_register_i64(y), 1);
+}
+
+#[test]
+fn host_calls_may_leave_out_parameters_with_defaults() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let add = vm.host_functions.register("add", 1, 2, 3, host_add);
+    vm.host_functions.metadata[add].defaults = &[100];
+    let bytecode = compile_source("x = add(1, 2)\ny = add(1)", &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!((get("x"), get("y")), (3, 101));
+    let err = compile_source("z = add()", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "add() takes 1 to 2 arguments but 0 were given");
+}
+
+#[test]
+fn ternaries_and_min_max_compile_without_branches() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let src = "a = 7\nb = 3\nzero = 0\nlo = min(a, b)\nhi = max(a, b + 10)\n\
+               t = a if zero else b\nu = a + 1 if a else b\nv = 1 if zero else 2 if b else 3";
+    let (mut vm, print_const) = setup_vm();
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!(
+        ["lo", "hi", "t", "u", "v"].map(get),
+        [3, 13, 3, 8, 2]
+    );
+    assert_eq!(get("a"), 7);
+    // No branches at all
+    let stats = crate::vm::BytecodeStats::from_bytecode(&bytecode).unwrap();
+    assert_eq!(stats.opcodes["SELECT"], 4);
+    assert_eq!((stats.opcodes["MIN_I64"], stats.opcodes["MAX_I64"]), (1, 1));
+    assert_eq!(stats.labels, 0);
+}
+
+#[test]
+fn ternaries_only_run_the_taken_call() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    vm.host_functions.register("double", 1, 1, 2, host_double);
+    output().lock().unwrap().clear();
+    let err = compile_source("c = 0\nx = double(5) if c else \"4\"", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "both branches of a conditional expression must have the same type");
+
+    let (mut vm, print_const) = setup_vm();
+    vm.host_functions.register("double", 1, 1, 2, host_double);
+    let src = "c = 0\nx = double(5) if c else 4\nprint(x)\nc = 1\nprint(double(x) if c else x)";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(output().lock().unwrap().clone(), vec!["4".to_string(), "8".to_string()]);
+    assert_eq!(vm.host_calls.iter().sum::<u64>(), 3);
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn string_ternaries_select_both_registers() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    output().lock().unwrap().clear();
+    // `s` is both the condition and the target
+    let src = "s = \"\"\ns = \"long enough\" if s else \"no\"\nprint(s)\n\
+               s = \"long enough\" if s else \"no\"\nprint(s)";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(
+        output().lock().unwrap().clone(),
+        vec!["no".to_string(), "long enough".to_string()]
+    );
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn string_concatenation() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let src = r#"s = "Hello, "
+t = s + "World"
+u = t
+print(u + "!")
+print(s + "a much longer suffix than fits inline")
+"#;
+    let tokens = Lexer::new(src).tokenize();
+    let stmts = Parser::new(tokens).parse().unwrap();
+
+    let (mut vm, print_const) = setup_vm();
+    output().lock().unwrap().clear();
+    let bytecode = generate_bytecode(&stmts, &mut vm, print_const);
+    vm.eval_program(&bytecode).unwrap();
+
+    let out = output().lock().unwrap().clone();
+    assert_eq!(
+        out,
+        vec![
+            "Hello, World!".to_string(),
+            "Hello, a much longer suffix than fits inline".to_string()
+        ]
+    );
+    let stats = vm.strings.stats();
+    assert_eq!((stats.inline_strings, stats.large_strings), (2, 1));
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn f_strings_use_a_string_builder() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let src = r#"name = "Kayton"
+n = 40 + 2
+s = f"{name} says {n}{f"!"}"
+print(s)
+print(f"{s} twice: {s}")
+"#;
+    let tokens = Lexer::new(src).tokenize();
+    let stmts = Parser::new(tokens).parse().unwrap();
+
+    let (mut vm, print_const) = setup_vm();
+    output().lock().unwrap().clear();
+    let bytecode = generate_bytecode(&stmts, &mut vm, print_const);
+    vm.eval_program(&bytecode).unwrap();
+
+    let out = output().lock().unwrap().clone();
+    assert_eq!(
+        out,
+        vec![
+            "Kayton says 42!".to_string(),
+            "Kayton says 42! twice: Kayton says 42!".to_string()
+        ]
+    );
+    // One string per f-string, no intermediate concatenations
+    let stats = vm.strings.stats();
+    assert_eq!(stats.inline_strings + stats.large_strings, 3);
+}
+
+#[test]
+fn source_map_links_pcs_to_nodes() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let src = "x = 1\n\ny = x + 2\nprint(y)\n";
+    let (mut vm, print_const) = setup_vm();
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+
+    let tokens = Lexer::new(src).tokenize();
+    let stmts = Parser::new(tokens).parse().unwrap();
+    let all = crate::parser::nodes(&stmts);
+    let map = &vm.debug_info.source_map;
+    assert_eq!(map.node_lines.len(), all.len());
+
+    // `x + 2` loads 2 and ends with ADD_I64, which maps to the binary
+    // expression itself rather than its operands or statement
+    let binary = map
+        .ranges
+        .iter()
+        .find(|r| matches!(all[r.node as usize], crate::parser::Node::Expr(Expr::Binary { .. })))
+        .unwrap();
+    let add_pc = binary.end - 4;
+    assert_eq!(bytecode[add_pc], crate::vm::ADD_I64);
+    assert_eq!(map.node_at(add_pc), Some(binary.node));
+    assert_ne!(map.node_at(binary.start), Some(binary.node));
+    assert_eq!(map.line_at(add_pc), Some(3));
+    // Every instruction belongs to some statement
+    assert!(map.line_at(0).is_some());
+    assert_eq!(map.line_at(bytecode.len() - 1), Some(4));
+}
+
+#[test]
+fn compile_source_applies_vm_rewriters() {
+    use crate::parser::rewrite::{Rewriter, walk_expr};
+
+    /// `double(x)` -> `x + x`, so no host function is needed
+    struct Double;
+    impl Rewriter for Double {
+        fn rewrite_expr(&self, expr: Expr) -> Expr {
+            match walk_expr(self, expr) {
+                Expr::Call { func, mut args } if *func == Expr::Ident("double".into()) => {
+                    if args.len() != 1 {
+                        panic!("double() takes 1 argument");
+                    }
+                    let arg = args.pop().unwrap();
+                    Expr::Binary {
+                        left: Box::new(arg.clone()),
+                        op: BinOp::Add,
+                        right: Box::new(arg),
+                    }
+                }
+                other => other,
+            }
+        }
+    }
+
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    vm.add_rewriter(Double);
+    output().lock().unwrap().clear();
+    let bytecode = compile_source("x = 4\nprint(double(double(x)))", &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(output().lock().unwrap().clone(), vec!["16".to_string()]);
+    assert_eq!(vm.debug_info.source_map.line_at(bytecode.len() - 1), Some(2));
+
+    // Rewriters report bad input by panicking, which becomes a compile error
+    let err = compile_source("double(1, 2)", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "double() takes 1 argument");
+}
+
+#[test]
+fn asserts_are_checked_in_debug_and_stripped_in_release() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let src = "x = 1\nz = 0\nassert(x)\nassert(x + 1, \"x is not -1\")\nassert(z)\ny = 2";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    let err = vm.eval_program(&bytecode).unwrap_err().to_string();
+    assert!(err.ends_with(": assertion on line 5 failed"), "{}", err);
+
+    let (mut vm, print_const) = setup_vm();
+    vm.compile_mode = CompileMode::Release;
+    let release = compile_source(src, &mut vm, print_const).unwrap();
+    assert!(release.len() < bytecode.len());
+    assert!(!release.contains(&crate::vm::ASSERT));
+    vm.eval_program(&release).unwrap();
+    assert_eq!(vm.get_register_i64(vm.global_vars.get("y").unwrap().register_id), 2);
+
+    let err = compile_source("assert(1, 2)", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "assert() expects a condition and an optional message string");
+}
+
+#[test]
+fn breakpoints_are_planted_in_debug_builds() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let bytecode = compile_source("x = 1\nbreakpoint()\nx = 2", &mut vm, print_const).unwrap();
+    assert_eq!(bytecode.iter().filter(|&&b| b == crate::vm::DEBUG_BREAK).count(), 1);
+    assert_eq!(bytecode[4], crate::vm::DEBUG_BREAK);
+
+    vm.compile_mode = CompileMode::Release;
+    let release = compile_source("x = 1\nbreakpoint()\nx = 2", &mut vm, print_const).unwrap();
+    assert_eq!(release.len(), bytecode.len() - 1);
+}
+
+#[test]
+fn abs_min_and_max_use_numeric_opcodes() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    // No negative literals yet: start from a global
+    let mut vm = VirtualMachine::builder().global_i64("n", -12).build();
+    let print_idx = vm.host_functions.register("print", 0, 1, 3, host_print);
+    let print_const = vm.const_pool.add_value("", print_idx as u64, ValueType::FuncHost) as u16;
+    let src = "a = abs(n)\nb = abs(a)\nc = max(abs(n), 20)";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!(["a", "b", "c"].map(get), [12, 12, 20]);
+    let stats = crate::vm::BytecodeStats::from_bytecode(&bytecode).unwrap();
+    assert_eq!(stats.opcodes["ABS_I64"], 3);
+    assert_eq!(stats.labels, 0);
+
+    for (src, err) in [
+        ("abs()", "abs() takes 1 argument but 0 were given"),
+        ("abs(\"x\")", "abs() expects an int or a float"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print_const).unwrap_err(), err);
+    }
+}
+
+#[cfg(all(feature = "stdlib", feature = "isa-float"))]
+#[test]
+fn exponent_literals_round_trip_through_the_vm() {
+    let sink = std::sync::Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut vm = VirtualMachine::builder().output(sink.clone()).with_stdlib().build();
+    let print = crate::builtins::print_const(&vm).unwrap();
+    let src = "x = 1e9\ny = 2.5e-3\nz = 6.02e23\nprint(x)\nprint(y)\nprint(z)";
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_f64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!([get("x"), get("y"), get("z")], [1e9, 2.5e-3, 6.02e23]);
+    let printed = String::from_utf8(sink.lock().unwrap().clone()).unwrap();
+    assert_eq!(printed, "1000000000.0\n0.0025\n6.02e+23\n");
+}
+
+#[cfg(all(feature = "stdlib", feature = "isa-float"))]
+#[test]
+fn float_literals_use_f64_consts_and_arithmetic() {
+    let sink = std::sync::Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut vm = VirtualMachine::builder().output(sink.clone()).with_stdlib().build();
+    let print = crate::builtins::print_const(&vm).unwrap();
+    let src = "x = 3.14\ny = x + 0.86\nprint(y)\nprint(0.1 + 0.2)\nz = y if 1 else 2.5\nm = min(abs(z), 2.5)";
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(String::from_utf8(sink.lock().unwrap().clone()).unwrap(), "4.0\n0.30000000000000004\n");
+    let z = vm.global_vars.get("z").unwrap();
+    assert_eq!(z.meta.typ, GlobalVarType::Value(ValueType::F64));
+    assert_eq!(vm.format_global("z"), Some("4.0".to_string()));
+    assert_eq!(vm.format_global("m"), Some("2.5".to_string()));
+
+    for (src, err) in [
+        ("x + \"1\"", "cannot add str and float"),
+        ("assert(x)", "a float cannot be used as a condition"),
+        ("min(x, \"1\")", "min() expects two ints or two floats"),
+        ("round(1)", "round() expects a float"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print).unwrap_err(), err);
+    }
+}
+
+#[cfg(feature = "isa-float")]
+#[test]
+fn moves_of_ints_and_floats_are_specialized() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let src = "x = 1\ny = x\ns = \"ab\"\nt = s\nz = 2.5\nw = z";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let stats = crate::vm::BytecodeStats::from_bytecode(&bytecode).unwrap();
+    // The str takes one MOV per register
+    assert_eq!((stats.opcodes["MOV_VALUE"], stats.opcodes["MOV"]), (2, 2));
+    assert_eq!((stats.specialized, stats.generic), (2, 2));
+    let get = |name: &str| vm.get_register_raw(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!(get("y"), 1);
+    assert_eq!(get("w"), 2.5f64.to_bits());
+}
+
+#[test]
+fn comparisons_give_ints() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let src = "a = 3\nlt = a < 4\nle = a <= 2\ngt = a + 1 > a\nge = a >= 3\nm = 10 if a < 5 else 20";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!(["lt", "le", "gt", "ge", "m"].map(get), [1, 0, 1, 1, 10]);
+
+    let err = compile_source("x = \"a\" < 1", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "cannot compare str and int");
+}
+
+#[cfg(all(feature = "isa-float", feature = "isa-strings"))]
+#[test]
+fn chained_comparisons_short_circuit() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    // Color.parse("BLUE") fails, so the last comparison must be skipped
+    let src = "enum Color: RED\na = 3\nin = 0 < a < 10\nout = 5 < a < Color.parse(\"BLUE\")\n\
+               mixed = 1 <= a < 3.5 > 2\na = 0 < a <= 3\nconst C = 1 < 3 < 2";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!(["in", "out", "mixed", "a"].map(get), [1, 0, 1, 1]);
+
+    let err = compile_source("x = 1 < 2 < \"a\"", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "cannot compare int and str");
+}
+
+#[cfg(all(feature = "isa-float", feature = "isa-strings"))]
+#[test]
+fn match_runs_the_first_matching_arm() {
+    use crate::frontend::compile_with_diagnostics;
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let src = "\
+enum Color: RED, GREEN, BLUE
+def rank(c):
+    match c:
+        case Color.RED:
+            return 1
+        case Color.GREEN:
+            return 2
+        case _:
+            return 9
+end
+a = rank(Color.GREEN)
+b = rank(Color.BLUE)
+s = \"y\"
+t = 0
+match s + \"es\":
+case \"no\":
+    t = 1
+case \"yes\":
+    t = 2
+case \"yes\":
+    t = 3
+end
+u = 0
+match a:
+    case 5:
+        u = 5";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!(["a", "b", "t", "u"].map(get), [2, 9, 2, 0]);
+
+    for (src, err) in [
+        ("match 1.5:\ncase 1:\n  x = 1\nend", "cannot match a float; the subject must be an int or a str"),
+        ("match 1:\ncase \"a\":\n  x = 1\nend", "a str pattern cannot match a int subject"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print_const).unwrap_err(), err);
+    }
+    let src = "x = 1\nmatch x:\ncase _:\n  y = 0\ncase 1:\n  y = 1\nend";
+    let (_, diagnostics) = compile_with_diagnostics(&KaytonSyntax, src, &mut vm, print_const).unwrap();
+    let found: Vec<_> = diagnostics.warnings().map(|d| (d.line, d.code)).collect();
+    assert_eq!(found, [(2, "unreachable-case")]);
+}
+
+#[test]
+fn imported_modules_share_their_globals() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let dir = std::env::temp_dir().join("kayton_codegen_import_test");
+    std::fs::create_dir_all(&dir).unwrap();
+    let geometry = "\"Shapes\"\nimport units\ndef double(n):\n    return n + n\nend\n\
+                    side = 3\narea = double(side) + units.scale";
+    std::fs::write(dir.join("geometry.kay"), geometry).unwrap();
+    std::fs::write(dir.join("units.kay"), "scale = 10\nlabel = \"cm\"").unwrap();
+    vm.modules.add_path(&dir);
+
+    let src = "import geometry\nside = 100\nimport units\nimport geometry\n\
+               total = geometry.area + units.scale + side";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    let names = ["geometry.side", "geometry.area", "units.scale", "side", "total"];
+    assert_eq!(names.map(get), [3, 16, 10, 100, 126]);
+    assert!(vm.global_vars.get("scale").is_none());
+
+    for (src, err) in [
+        ("import geometry\nx = geometry.nope", "module 'geometry' has no global 'nope'"),
+        ("import geometry\nx = geometry.double(1)", "'geometry.double' cannot be called: modules share only their globals"),
+        ("import geometry\nx = double(1)", "unknown function 'double'"),
+        ("def f():\n    import units\nend\nf()", "'import' is only allowed at the top level"),
+        ("x = 1\nimport nothing", "module 'nothing' not found: no nothing.kay in {}"),
+    ] {
+        let err = err.replace("{}", &dir.display().to_string());
+        assert_eq!(compile_source(src, &mut vm, print_const).unwrap_err(), err);
+    }
+    std::fs::write(dir.join("units.kay"), "scale = 1 + \"cm\"").unwrap();
+    let err = compile_source("import geometry", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "in module 'geometry': in module 'units': cannot add str and int");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "isa-float")]
+#[test]
+fn if_elif_else_runs_one_branch() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let src = "\
+if n < 10:
+    size = 1
+elif n < 100:
+    size = 2
+    if n > 50:
+        size = size + 10
+    end
+else:
+    size = 3
+end
+if \"\":
+    print(\"never\")
+end";
+    for (n, size) in [(5, 1), (42, 2), (77, 12), (500, 3)] {
+        let mut vm = VirtualMachine::builder().global_i64("n", n).build();
+        let print_idx = vm.host_functions.register("print", 0, 1, 3, host_print);
+        let print_const = vm.const_pool.add_value("", print_idx as u64, ValueType::FuncHost) as u16;
+        output().lock().unwrap().clear();
+        let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+        vm.eval_program(&bytecode).unwrap();
+        let size_reg = vm.global_vars.get("size").unwrap().register_id;
+        assert_eq!(vm.get_register_i64(size_reg), size, "n = {}", n);
+        assert!(output().lock().unwrap().is_empty());
+    }
+
+    let (mut vm, print_const) = setup_vm();
+    for (src, err) in [
+        ("x = 1\nend", "'end' without a matching 'if', 'while' or 'def'"),
+        ("if 1:\nx = 1", "expected 'end' to close the block"),
+        ("x = 1\n  y = 2", "unexpected indent"),
+        ("if 1.5:\n  x = 1\nend", "a float cannot be used as a condition"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print_const).unwrap_err(), err);
+    }
+}
+
+#[test]
+fn while_loops_compute_factorials() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    // No `*` yet: each product is a loop of additions
+    let src = "\
+fact = 1
+i = 1
+while i <= n:
+    product = 0
+    k = 0
+    while k < i:
+        product = product + fact
+        k = k + 1
+    end
+    fact = product
+    i = 1 + i
+end
+skipped = 0
+while 0:
+    skipped = 1
+end";
+    for (n, fact) in [(0, 1), (1, 1), (5, 120), (10, 3_628_800)] {
+        let mut vm = VirtualMachine::builder().global_i64("n", n).build();
+        let print_idx = vm.host_functions.register("print", 0, 1, 3, host_print);
+        let print_const = vm.const_pool.add_value("", print_idx as u64, ValueType::FuncHost) as u16;
+        let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+        vm.eval_program(&bytecode).unwrap();
+        let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+        assert_eq!([get("fact"), get("i"), get("skipped")], [fact, n + 1, 0], "n = {}", n);
+    }
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn functions_recurse_and_specialize_per_argument_kind() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let src = "\
+def sum_to(n, i):
+    \"Sum of i..n\"
+    if n < i:
+        return 0
+    end
+    return i + sum_to(n, i + 1)
+end
+def twice(x):
+    return x + x
+end
+def shout(s):
+    print(twice(s))
+end
+total = sum_to(100, 1)
+ints = twice(21)
+label = twice(\"ab\")
+shout(\"hey\")
+last = i";
+    let (mut vm, print_const) = setup_vm();
+    output().lock().unwrap().clear();
+    let err = compile_source(src, &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "undefined variable");
+
+    let src = src.trim_end_matches("\nlast = i");
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!([get("total"), get("ints")], [5050, 42]);
+    assert_eq!(vm.global_slice("label"), Some(&b"abab"[..]));
+    assert_eq!(*output().lock().unwrap(), ["heyhey"]);
+    // Parameters and locals are not globals
+    assert!(vm.global_vars.get("n").is_none());
+    assert_eq!(vm.help_text("sum_to"), "sum_to(n, i)\n\nSum of i..n");
+    // twice() has one body for ints and one for strings; every body ends in
+    // a RET besides its return statements
+    let stats = crate::vm::BytecodeStats::from_bytecode(&bytecode).unwrap();
+    assert_eq!((stats.opcodes["CALL"], stats.opcodes["RET"]), (6, 8));
+}
+
+#[test]
+fn break_and_continue_jump_out_of_the_innermost_loop() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let src = "\
+total = 0
+i = 0
+while i < 10:
+    i = i + 1
+    if i < 4:
+        continue
+    end
+    if i > 7:
+        break
+    end
+    inner = 0
+    while 1:
+        inner = inner + 1
+        break
+    end
+    total = total + i + inner
+end";
+    let (mut vm, print_const) = setup_vm();
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!([get("total"), get("i")], [26, 8]);
+
+    for (src, err) in [
+        ("break", "'break' outside a loop"),
+        ("if 1:\n  continue\nend", "'continue' outside a loop"),
+        ("def f():\n  break\nend\nwhile 1:\n  f()\nend", "'break' outside a loop"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print_const).unwrap_err(), err);
+    }
+}
+
+#[test]
+fn function_errors() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    for (src, err) in [
+        ("return 1", "'return' outside a function"),
+        ("def f(a):\n  return a\nend\nx = f()", "f() takes 1 arguments but 0 were given"),
+        ("def f():\n  x = 1\nend\ny = f()", "'f' does not return a value"),
+        (
+            "def f(a):\n  if a:\n    return 1\n  end\n  return \"s\"\nend\ny = f(1)",
+            "'f' returns both int and str",
+        ),
+        (
+            "def f(a):\n  if a:\n    return\n  end\n  return 1\nend\nf(1)",
+            "'f' returns a value on some paths but not others",
+        ),
+        (
+            "def f(a):\n  return f(a)\nend\ny = f(1)",
+            "cannot tell what 'f' returns before it reaches a return statement; put the base case first",
+        ),
+        ("g = 1\ndef f():\n  return g\nend\ny = f()", "functions cannot read global 'g'; pass it as an argument"),
+        ("def f():\n  def g():\n  end\nend\nf()", "functions can only be defined at the top level"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print_const).unwrap_err(), err);
+    }
+}
+
+#[cfg(feature = "isa-float")]
+#[test]
+fn type_annotations_are_enforced() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    // A declared return kind lets a recursive call come before the base case
+    let src = "def f(n: int) -> int:\n  if n:\n    return f(0) + 1\n  end\n  return 10\nend\n\
+               x: int = f(3)\nx = x + 1\ns = \"a\"\ns = 2";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!(get("x"), 12);
+    assert_eq!(get("s"), 2);
+
+    for (src, err) in [
+        ("x: int = 1\nx = \"a\"", "'x' is declared as int but assigned a str"),
+        ("x: int = 1\nx: float = 1.5", "'x' is already declared as int"),
+        ("x: num = 1", "unknown type 'num'; expected int, float, str, bytes, list or dict"),
+        (
+            "def f(a: int):\n  a = \"s\"\nend\nf(1)",
+            "'a' is declared as int but assigned a str",
+        ),
+        (
+            "def f(a: int, b: str):\n  return a\nend\ny = f(1, 2)",
+            "parameter 'b' of f() is declared as str but given a int",
+        ),
+        ("def f() -> str:\n  return 1\nend\ny = f()", "'f' is declared to return str but returns a int"),
+        ("def f() -> int:\n  return\nend\nf()", "'f' is declared to return int but has a bare return"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print_const).unwrap_err(), err);
+    }
+}
+
+#[test]
+fn diagnostics_come_with_successful_compilation() {
+    use crate::frontend::compile_with_diagnostics;
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let src = "\"module doc\"\nx = 1\nx + 2\nprint(x)\nif x:\n  \"stray\"\nend";
+    let (bytecode, diagnostics) =
+        compile_with_diagnostics(&KaytonSyntax, src, &mut vm, print_const).unwrap();
+    assert!(!bytecode.is_empty());
+    assert!(!diagnostics.has_errors());
+    let found: Vec<_> = diagnostics.warnings().map(|d| (d.line, d.code)).collect();
+    assert_eq!(found, [(3, "unused-result"), (5, "unused-result")]);
+
+    let err = compile_with_diagnostics(&KaytonSyntax, "x = 1\ny = x + \"a\"\nz = (", &mut vm, print_const)
+        .unwrap_err();
+    assert_eq!(err.to_string(), "line 3: error[syntax]: Unexpected token EOF");
+    let err = compile_with_diagnostics(&KaytonSyntax, "x = 1\ny = x + \"a\"", &mut vm, print_const)
+        .unwrap_err();
+    assert_eq!(err.to_string(), "line 2: error[compile]: cannot add str and int");
+}
+
+#[cfg(all(feature = "stdlib", feature = "isa-float"))]
+#[test]
+fn promoting_large_int_literals_warns() {
+    use crate::frontend::compile_with_diagnostics;
+    let mut vm = VirtualMachine::builder().with_stdlib().build();
+    let print = crate::builtins::print_const(&vm).unwrap();
+    let src = "a = 9007199254740992 + 0.5\nb = 0.5 + 9007199254740993\nc = max(1, 2.5)";
+    let (_, diagnostics) = compile_with_diagnostics(&KaytonSyntax, src, &mut vm, print).unwrap();
+    assert_eq!(
+        diagnostics.to_string(),
+        "line 2: warning[precision-loss]: 9007199254740993 becomes 9007199254740992.0 as a float"
+    );
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn compile_limits_stop_pathological_sources() {
+    use crate::frontend::{compile_with_diagnostics, CompileLimits, DEFAULT_MAX_DEPTH};
+    use std::time::Duration;
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let nested = |depth: usize| format!("x = {}1{}", "(".repeat(depth - 1), ")".repeat(depth - 1));
+    assert!(compile_with_diagnostics(&KaytonSyntax, &nested(DEFAULT_MAX_DEPTH), &mut vm, print_const).is_ok());
+    let err = compile_with_diagnostics(&KaytonSyntax, &nested(100_000), &mut vm, print_const).unwrap_err();
+    assert_eq!(err.to_string(), "line 1: error[limit]: nesting deeper than 200 levels");
+    // Each shape at the default depth compiles within a test thread's stack
+    let depth = DEFAULT_MAX_DEPTH;
+    let sum = format!("x = 1{}", " + 1".repeat(depth - 1));
+    let ternaries = format!("x = {}1", "1 if 1 else ".repeat(depth - 1));
+    let strs = format!("x = \"a\"{}", " + f\"{1 + 1}\"".repeat(depth / 2 - 2));
+    let blocks = format!("{}x = 1\n{}", "if 1:\n".repeat(depth - 1), "end\n".repeat(depth - 1));
+    for src in [&sum, &strs, &blocks] {
+        assert!(compile_with_diagnostics(&KaytonSyntax, src, &mut vm, print_const).is_ok());
+    }
+    // Each pending ternary holds a register
+    let err = compile_with_diagnostics(&KaytonSyntax, &ternaries, &mut vm, print_const).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "line 1: error[compile]: expression needs more registers than a frame has; split it into assignments"
+    );
+    // A flat chain is not nesting, however long
+    let chain = format!("x = 1{}", " + 1".repeat(10_000));
+    let (bytecode, _) = compile_with_diagnostics(&KaytonSyntax, &chain, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(vm.get_register_i64(vm.global_vars.get("x").unwrap().register_id), 10_001);
+    let fstring = "x = f\"{((((1))))}\"";
+    assert!(compile_with_diagnostics(&KaytonSyntax, fstring, &mut vm, print_const).is_ok());
+
+    vm.compile_limits = CompileLimits {
+        max_depth: Some(4),
+        ..CompileLimits::default()
+    };
+    let err = compile_with_diagnostics(&KaytonSyntax, fstring, &mut vm, print_const).unwrap_err();
+    assert_eq!(err.to_string(), "line 1: error[limit]: nesting deeper than 4 levels");
+
+    vm.compile_limits = CompileLimits {
+        max_tokens: Some(10),
+        ..CompileLimits::unlimited()
+    };
+    let err = compile_with_diagnostics(&KaytonSyntax, "x = 1\ny = 2\nz = 3", &mut vm, print_const)
+        .unwrap_err();
+    assert_eq!(err.errors().next().unwrap().code, "limit");
+    assert_eq!(err.errors().next().unwrap().message, "source longer than 10 tokens");
+
+    vm.compile_limits = CompileLimits {
+        max_time: Some(Duration::from_nanos(1)),
+        ..CompileLimits::unlimited()
+    };
+    let src = "x = 1\n".repeat(5000);
+    let err = compile_with_diagnostics(&KaytonSyntax, &src, &mut vm, print_const).unwrap_err();
+    assert_eq!(err.errors().next().unwrap().message, "compilation took longer than 1ns");
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn assignments_read_the_old_value_before_writing() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let src = "x = 5\nx = 1 + x\ns = \"b\"\ns = \"a\" + s + \"c\"\nprint(s)";
+    output().lock().unwrap().clear();
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(vm.get_register_i64(vm.global_vars.get("x").unwrap().register_id), 6);
+    assert_eq!(*output().lock().unwrap(), ["abc"]);
+}
+
+#[cfg(all(feature = "stdlib", feature = "isa-float"))]
+#[test]
+fn mixed_comparisons_are_exact_past_2_pow_53() {
+    let mut vm = VirtualMachine::builder().with_stdlib().build();
+    let print = crate::builtins::print_const(&vm).unwrap();
+    // 2^53 + 1 has no f64 of its own: I64_TO_F64 would make it equal to 2^53
+    let src = "big = 9007199254740993\nf = 9007199254740992.0\n\
+               a = big > f\nb = f < big\nc = big <= f\nd = 1 < 2.5\ne = 2.5 >= 3\ng = f >= f";
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!(["a", "b", "c", "d", "e", "g"].map(get), [1, 1, 0, 1, 0, 1]);
+    let stats = crate::vm::BytecodeStats::from_bytecode(&bytecode).unwrap();
+    assert_eq!(stats.opcodes.get("I64_TO_F64"), None);
+    assert_eq!(stats.opcodes["GT_I64_F64"], 2);
+}
+
+#[cfg(all(feature = "stdlib", feature = "isa-float"))]
+#[test]
+fn ints_are_promoted_when_mixed_with_floats() {
+    let mut vm = VirtualMachine::builder().with_stdlib().build();
+    let print = crate::builtins::print_const(&vm).unwrap();
+    let src = "n = 2\na = 1 + 2.5\nb = 0.5 + n\nc = n + n + 0.25\nd = max(n, 1.5)\ne = min(0.5, n)";
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let float = |name: &str| vm.format_global(name).unwrap();
+    assert_eq!(["a", "b", "c", "d", "e"].map(float), ["3.5", "2.5", "4.25", "2.0", "0.5"]);
+    let n = vm.global_vars.get("n").unwrap();
+    assert_eq!(n.meta.typ, GlobalVarType::Value(ValueType::I64));
+    let stats = crate::vm::BytecodeStats::from_bytecode(&bytecode).unwrap();
+    // `n + n` stays an int add; only its sum is promoted
+    assert_eq!((stats.opcodes["I64_TO_F64"], stats.opcodes["ADD_I64"]), (5, 1));
+}
+
+#[cfg(all(feature = "stdlib", feature = "isa-float"))]
+#[test]
+fn rounding_builtins_turn_floats_into_ints() {
+    let mut vm = VirtualMachine::builder()
+        .global_f64("big", 1e300)
+        .with_stdlib()
+        .build();
+    let print = crate::builtins::print_const(&vm).unwrap();
+    let src = "x = 2.5\na = round(x)\nb = floor(x)\nc = ceil(x)\nd = trunc(x)\ne = round(x) + 1";
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!(["a", "b", "c", "d", "e"].map(get), [2, 2, 3, 2, 3]);
+
+    let bytecode = compile_source("y = floor(big)", &mut vm, print).unwrap();
+    let err = vm.eval_program(&bytecode).unwrap_err();
+    assert!(err.to_string().starts_with("Cannot convert 1e+300 to an int"), "{}", err);
+}
+
+#[test]
+fn len_and_collections_without_host_modules() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let bytecode = compile_source("n = len(\"abc\")", &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(vm.get_register_i64(vm.global_vars.get("n").unwrap().register_id), 3);
+
+    let err = compile_source("xs = [1]", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "lists need the vec_host functions registered; 'vec_host_new' is missing");
+    let err = compile_source("m = len(1)", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "a int has no len()");
+    let err = compile_source("s = \"ab\"\nm = s[0]", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "cannot index a str");
+    let err = compile_source("d = {1: 2}", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "dicts need the map_host functions registered; 'map_host_new' is missing");
+}
+
+#[test]
+fn constants_and_enums_fold_to_literals() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let src = "const BASE = 40\nconst LIMIT = BASE + 2 if BASE > 1 else 0\nenum Color: RED, GREEN, BLUE\nx = LIMIT + BLUE\ndef f(c):\n  return c + BASE\nend\ny = f(GREEN)";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!((get("x"), get("y")), (44, 41));
+    assert!(vm.global_vars.get("LIMIT").is_none());
+    assert_eq!(vm.debug_info.enum_variant("Color", 2), Some("BLUE"));
+    assert_eq!(vm.debug_info.enum_variant("Color", 3), None);
+
+    for (src, message) in [
+        ("const A = 1\nA = 2", "cannot assign to constant 'A'"),
+        ("const A = 1\nconst A = 2", "constant 'A' is already defined"),
+        ("v = 1\nconst B = v", "the value of constant 'B' is not known at compile time"),
+        ("v = 1\nenum E: v", "'v' is already a variable and cannot become a constant"),
+        ("def g():\n  const C = 1\nend\ng()", "constants can only be defined at the top level"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print_const).unwrap_err(), message);
+    }
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn enums_convert_between_names_and_values() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let src = "enum Color: RED, GREEN, BLUE\nc = Color.parse(\"BLUE\")\ng = Color.GREEN\nname = Color.name(g)\nr = Color.name(0)";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!((get("c"), get("g")), (2, 1));
+    assert_eq!(vm.format_global("name").unwrap(), "\"GREEN\"");
+    assert_eq!(vm.format_global("r").unwrap(), "\"RED\"");
+
+    for (src, message) in [
+        ("enum E: A, B\nx = E.name(2)", "Enum E has no variant with value 2"),
+        ("enum E: A, B\nx = E.parse(\"C\")", "Enum E has no variant named \"C\""),
+    ] {
+        let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+        let err = vm.eval_program(&bytecode).unwrap_err();
+        use crate::vm::VmError::{InvalidEnumValue, UnknownEnumName};
+        assert!(matches!(err, InvalidEnumValue { .. } | UnknownEnumName { .. }), "{:?}", err);
+        assert!(err.to_string().starts_with(message), "{}", err);
+    }
+    for (src, message) in [
+        ("enum E: A\nx = E.C", "enum 'E' has no variant 'C'"),
+        ("x = 1\ny = x.name(1)", "only enums have attributes, and 'x' is not one"),
+        ("enum E: A\nx = E.parse(1)", "the argument of parse() must be a str, not a int"),
+        ("enum E: A\nenum E: B", "enum 'E' is already defined"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print_const).unwrap_err(), message);
+    }
+}
+
+#[cfg(feature = "stdlib")]
+#[test]
+fn bench_times_a_function_and_reports_through_the_output() {
+    use crate::vm::Capabilities;
+    let sink = std::sync::Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut vm = VirtualMachine::builder()
+        .capabilities(Capabilities { time: true, ..Capabilities::none() })
+        .output(sink.clone())
+        .build();
+    let print = crate::builtins::print_const(&vm).unwrap();
+    let src = "def f():\n  print(1)\nend\nn = 3\nbench(\"one\", f, n)\nbench(\"none\", f, 0)";
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let printed = String::from_utf8(sink.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = printed.lines().collect();
+    // 3 warmup calls and 3 timed calls; a bench of 0 iterations skips warmup
+    assert_eq!(lines[..6], ["1"; 6]);
+    assert!(lines[6].starts_with("bench one: 3 iterations, mean "), "{}", printed);
+    assert!(lines[6].contains(", min ") && lines[6].contains(", max "), "{}", printed);
+    assert_eq!(lines[7], "bench none: 0 iterations");
+
+    for (src, message) in [
+        ("def g(x):\n  return x\nend\nbench(\"g\", g, 1)", "bench() needs a function without parameters; 'g' takes 1"),
+        ("bench(\"h\", h, 1)", "bench() needs a function defined with def; 'h' is not one"),
+        ("def k():\n  return 1\nend\nbench(1, k, 1)", "the name of a bench() must be a str, not a int"),
+        ("bench(\"x\")", "bench() expects a name, a function and an iteration count"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print).unwrap_err(), message);
+    }
+
+    let (mut vm, print_const) = setup_vm();
+    let err = compile_source("def f():\n  return 1\nend\nbench(\"f\", f, 1)", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "bench() needs the time capability; 'monotonic_ns' is missing");
+}
+
+#[test]
+fn methods_of_foreign_objects_dispatch_on_their_type() {
+    use crate::vm::{ForeignParam, ForeignType};
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let mut vm = VirtualMachine::builder().global_i64("conn", 0).global_i64("other", 0).build();
+    let print_idx = vm.host_functions.register("print", 0, 1, 3, host_print);
+    let print_const = vm.const_pool.add_value("", print_idx as u64, ValueType::FuncHost) as u16;
+    let table = vm.register_foreign_type(
+        ForeignType::new("Table")
+            .method("query", &[ForeignParam::Str, ForeignParam::Int], |object, args| {
+                let rows = object.downcast_ref::<u64>().unwrap();
+                Ok(rows + args[0].as_str().unwrap().len() as u64 + args[1].as_int().unwrap() as u64)
+            })
+            .method("size", &[], |object, _| Ok(*object.downcast_ref::<u64>().unwrap())),
+    );
+    let counter = vm.register_foreign_type(ForeignType::new("Counter").method("size", &[], |_, _| Ok(7)));
+    let conn = vm.foreign.insert(100u64, &table);
+    let other = vm.foreign.insert((), &counter);
+    for (name, handle) in [("conn", conn), ("other", other)] {
+        let reg = vm.global_vars.get(name).unwrap().register_id;
+        vm.set_register_raw(reg, handle);
+    }
+
+    let src = "a = conn.query(\"users\", 2)\nb = conn.size() + other.size()";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!((get("a"), get("b")), (107, 107));
+
+    for (src, message) in [
+        ("conn.close()", "no foreign type has a method 'close'"),
+        ("s = \"x\"\ns.size()", "a str has no methods; only foreign objects do"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print_const).unwrap_err(), message);
+    }
+    for (src, message) in [
+        ("other.query(\"x\", 1)", "Counter has no method 'query'"),
+        ("conn.query(\"x\")", "Table.query() takes 2 arguments but 1 were given"),
+        ("conn.query(1, 2)", "argument 1 of Table.query() must be a str, not a int"),
+    ] {
+        let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+        let err = vm.eval_program(&bytecode).unwrap_err();
+        assert_eq!(err.to_string(), format!("Host error: {}", message));
+    }
+    vm.foreign.release(conn);
+    let bytecode = compile_source("conn.size()", &mut vm, print_const).unwrap();
+    let err = vm.eval_program(&bytecode).unwrap_err();
+    assert!(err.to_string().contains("not a live object handle"), "{}", err);
+}
+
+#[test]
+fn remainder_and_power_operators() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let mut vm = VirtualMachine::builder().global_i64("n", -7).build();
+    let print_idx = vm.host_functions.register("print", 0, 1, 3, host_print);
+    let print_const = vm.const_pool.add_value("", print_idx as u64, ValueType::FuncHost) as u16;
+    let src = "a = n % 3\nb = 2 ** 3 ** 2\nc = 1 + 10 % 4 ** 2\nconst K = 2 ** 10 % 1000\nd = K\ne = 0\ni = 0\nwhile i < 10:\n  e = e + i % 2\n  i = i + 1\nend";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!(["a", "b", "c", "d", "e"].map(get), [2, 512, 11, 24, 5]);
+    let stats = crate::vm::BytecodeStats::from_bytecode(&bytecode).unwrap();
+    assert_eq!((stats.opcodes["MOD_I64"], stats.opcodes["POW_I64"]), (3, 3));
+
+    for (src, message) in [
+        ("x = \"a\" % 2", "'%' needs two ints, not str and int"),
+        ("x = \"a\" ** 2", "'**' needs two numbers, not str and int"),
+        ("const Z = 1 % 0", "constant 'Z' takes a remainder by zero"),
+        ("const P = 2 ** 64", "constant 'P' overflows an int"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print_const).unwrap_err(), message);
+    }
+    let bytecode = compile_source("z = 0\ny = 1 % z", &mut vm, print_const).unwrap();
+    assert!(matches!(vm.eval_program(&bytecode), Err(crate::vm::VmError::DivisionByZero(_))));
+}
+
+#[test]
+fn bitwise_operators() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let mut vm = VirtualMachine::builder().global_i64("n", -12).build();
+    let print_idx = vm.host_functions.register("print", 0, 1, 3, host_print);
+    let print_const = vm.const_pool.add_value("", print_idx as u64, ValueType::FuncHost) as u16;
+    let src = "a = n & 10 | 1\nb = n ^ 5\nc = ~n\nd = n >> 2\ne = 1 << 3 + 1\nconst K = ~0 << 4 & 255\ng = K\nh = 6 & 3 < 4";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!(["a", "b", "c", "d", "e", "g", "h"].map(get), [1, -15, 11, -3, 16, 240, 1]);
+    let stats = crate::vm::BytecodeStats::from_bytecode(&bytecode).unwrap();
+    assert_eq!((stats.opcodes["AND_I64"], stats.opcodes["NOT_I64"]), (2, 1));
+
+    for (src, message) in [
+        ("x = b\"a\" & 1", "'&' needs two ints, not bytes and int"),
+        ("x = \"a\" << 1", "'<<' needs two ints, not str and int"),
+        ("x = ~\"a\"", "'~' needs an int, not a str"),
+        ("const S = 1 >> ~0", "constant 'S' shifts by a negative count"),
+    ] {
+        assert_eq!(compile_source(src, &mut vm, print_const).unwrap_err(), message);
+    }
+    let bytecode = compile_source("z = ~0\ny = 1 << z", &mut vm, print_const).unwrap();
+    let err = vm.eval_program(&bytecode).unwrap_err();
+    assert!(matches!(err, crate::vm::VmError::NegativeShift { count: -1, .. }), "{:?}", err);
+}
+
+#[cfg(feature = "isa-float")]
+#[test]
+fn float_powers_promote_ints() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    let (mut vm, print_const) = setup_vm();
+    let src = "x = 2 ** 0.5\ny = 4.0 ** 2";
+    let bytecode = compile_source(src, &mut vm, print_const).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let get = |name: &str| vm.get_register_f64(vm.global_vars.get(name).unwrap().register_id);
+    assert_eq!((get("x"), get("y")), (2f64.sqrt(), 16.0));
+    let err = compile_source("x = 2.5 % 2", &mut vm, print_const).unwrap_err();
+    assert_eq!(err, "'%' needs two ints, not float and int");
+}
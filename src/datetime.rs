@@ -0,0 +1,197 @@
+//! UTC timestamp formatting and parsing for the `format_timestamp` /
+//! `parse_timestamp` builtins. Timestamps are milliseconds since the Unix
+//! epoch, as returned by `now_ms`.
+//!
+//! Supported directives, a subset of `strftime`:
+//!
+//! | directive | meaning                       |
+//! |-----------|-------------------------------|
+//! | `%Y`      | year, four digits             |
+//! | `%m`      | month, `01`-`12`              |
+//! | `%d`      | day of the month, `01`-`31`   |
+//! | `%H`      | hour, `00`-`23`               |
+//! | `%M`      | minute, `00`-`59`             |
+//! | `%S`      | second, `00`-`59`             |
+//! | `%f`      | millisecond, `000`-`999`      |
+//! | `%j`      | day of the year, `001`-`366`  |
+//! | `%%`      | a literal `%`                 |
+
+const MS_PER_DAY: i64 = 86_400_000;
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's
+/// `days_from_civil`)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    // Days since March 1st, which puts the leap day at the end of the year
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// (year, month, day) of a day count since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Format `ms` since the epoch (UTC) according to `fmt`
+pub fn format_timestamp(ms: i64, fmt: &str) -> Result<String, String> {
+    let days = ms.div_euclid(MS_PER_DAY);
+    let ms_of_day = ms.rem_euclid(MS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let day_of_year = days - days_from_civil(year, 1, 1) + 1;
+
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", year)),
+            Some('m') => out.push_str(&format!("{:02}", month)),
+            Some('d') => out.push_str(&format!("{:02}", day)),
+            Some('H') => out.push_str(&format!("{:02}", ms_of_day / 3_600_000)),
+            Some('M') => out.push_str(&format!("{:02}", ms_of_day / 60_000 % 60)),
+            Some('S') => out.push_str(&format!("{:02}", ms_of_day / 1000 % 60)),
+            Some('f') => out.push_str(&format!("{:03}", ms_of_day % 1000)),
+            Some('j') => out.push_str(&format!("{:03}", day_of_year)),
+            Some('%') => out.push('%'),
+            Some(other) => return Err(format!("unknown directive %{} in {:?}", other, fmt)),
+            None => return Err(format!("format {:?} ends in a lone %", fmt)),
+        }
+    }
+    Ok(out)
+}
+
+/// Parse `text` laid out as `fmt` into ms since the epoch (UTC). Fields
+/// missing from `fmt` default to 1970-01-01 00:00:00.000.
+pub fn parse_timestamp(text: &str, fmt: &str) -> Result<i64, String> {
+    let mismatch = || format!("{:?} does not match format {:?}", text, fmt);
+    let (mut year, mut month, mut day) = (1970i64, 1u32, 1u32);
+    let (mut hour, mut minute, mut second, mut milli) = (0i64, 0i64, 0i64, 0i64);
+    let mut day_of_year = None;
+
+    let mut rest = text;
+    let mut chars = fmt.chars();
+    // Up to `max` leading digits of `rest`
+    let number = |rest: &mut &str, max: usize| -> Result<i64, String> {
+        let len = rest.bytes().take(max).take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return Err(mismatch());
+        }
+        let value = rest[..len].parse().map_err(|_| mismatch())?;
+        *rest = &rest[len..];
+        Ok(value)
+    };
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            rest = rest.strip_prefix(c).ok_or_else(mismatch)?;
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => year = number(&mut rest, 4)?,
+            Some('m') => month = number(&mut rest, 2)? as u32,
+            Some('d') => day = number(&mut rest, 2)? as u32,
+            Some('H') => hour = number(&mut rest, 2)?,
+            Some('M') => minute = number(&mut rest, 2)?,
+            Some('S') => second = number(&mut rest, 2)?,
+            Some('f') => milli = number(&mut rest, 3)?,
+            Some('j') => day_of_year = Some(number(&mut rest, 3)?),
+            Some('%') => rest = rest.strip_prefix('%').ok_or_else(mismatch)?,
+            Some(other) => return Err(format!("unknown directive %{} in {:?}", other, fmt)),
+            None => return Err(format!("format {:?} ends in a lone %", fmt)),
+        }
+    }
+    if !rest.is_empty() {
+        return Err(format!("unconverted text {:?} after parsing {:?}", rest, fmt));
+    }
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return Err(format!("invalid date in {:?}", text));
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return Err(format!("invalid time in {:?}", text));
+    }
+    let days = match day_of_year {
+        Some(n) if n == 0 || n > 365 + is_leap_year(year) as i64 => {
+            return Err(format!("invalid day of the year in {:?}", text));
+        }
+        Some(n) => days_from_civil(year, 1, 1) + n - 1,
+        None => days_from_civil(year, month, day),
+    };
+    Ok(days * MS_PER_DAY + ((hour * 60 + minute) * 60 + second) * 1000 + milli)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_utc_fields() {
+        let fmt = "%Y-%m-%dT%H:%M:%S.%f";
+        assert_eq!(format_timestamp(0, fmt).unwrap(), "1970-01-01T00:00:00.000");
+        // 2024-02-29 13:45:06.789, a leap day
+        assert_eq!(
+            format_timestamp(1_709_214_306_789, fmt).unwrap(),
+            "2024-02-29T13:45:06.789"
+        );
+        assert_eq!(format_timestamp(-1, fmt).unwrap(), "1969-12-31T23:59:59.999");
+        assert_eq!(format_timestamp(1_709_214_306_789, "%j 100%%").unwrap(), "060 100%");
+        assert!(format_timestamp(0, "%Q").is_err());
+        assert!(format_timestamp(0, "%").is_err());
+    }
+
+    #[test]
+    fn parse_round_trips() {
+        let fmt = "%Y-%m-%d %H:%M:%S.%f";
+        for ms in [0, 1_709_214_306_789, -86_400_001, 253_402_300_799_999, 951_782_400_000] {
+            let text = format_timestamp(ms, fmt).unwrap();
+            assert_eq!(parse_timestamp(&text, fmt), Ok(ms), "{}", text);
+        }
+        assert_eq!(parse_timestamp("2024/3/1", "%Y/%m/%d"), Ok(1_709_251_200_000));
+        assert_eq!(parse_timestamp("2024-061", "%Y-%j"), Ok(1_709_251_200_000));
+        assert_eq!(parse_timestamp("12:30", "%H:%M"), Ok(45_000_000));
+    }
+
+    #[test]
+    fn parse_rejects_bad_input() {
+        for (text, fmt) in [
+            ("2023-02-29", "%Y-%m-%d"),
+            ("2024-13-01", "%Y-%m-%d"),
+            ("24:00", "%H:%M"),
+            ("2024-01-01x", "%Y-%m-%d"),
+            ("2024-01", "%Y-%m-%d"),
+            ("2023-366", "%Y-%j"),
+            ("abc", "%Y"),
+        ] {
+            assert!(parse_timestamp(text, fmt).is_err(), "{} {}", text, fmt);
+        }
+    }
+}
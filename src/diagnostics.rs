@@ -0,0 +1,420 @@
+//! Errors and warnings found while compiling a script. The lexer, parser
+//! and codegen report into one `Diagnostics` collector, which
+//! `frontend::compile_with_diagnostics` returns alongside the bytecode (or
+//! instead of it, when there are errors).
+//!
+//! Every diagnostic carries a stable `code` that tools can match on:
+//!
+//! | code                | severity | meaning                                      |
+//! |---------------------|----------|----------------------------------------------|
+//! | `syntax`            | error    | the source could not be parsed               |
+//! | `compile`           | error    | a type error, unknown name or similar        |
+//! | `limit`             | error    | the source went over a `CompileLimits` bound |
+//! | `unused-result`     | warning  | a statement computes a value and drops it    |
+//! | `precision-loss`    | warning  | an int promoted to a float loses digits      |
+//!
+//! `Display` gives one line per diagnostic; `Renderer` shows each with the
+//! offending source line, a caret underline and its notes:
+//!
+//! ```text
+//! warning[unused-result]: this expression's value is computed and then dropped
+//!  --> demo.ky:3
+//!   |
+//! 3 | x + 2
+//!   | ^^^^^
+//!   = help: assign it to a variable or remove it
+//! ```
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    /// 1-based source line, 0 if unknown
+    pub line: u32,
+    /// 1-based column (in chars) of the underlined span; 0 underlines the
+    /// whole line
+    pub column: u32,
+    pub width: u32,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    /// Narrow the underline to `width` chars starting at `column`
+    pub fn at_column(&mut self, column: u32, width: u32) -> &mut Self {
+        self.column = column;
+        self.width = width;
+        self
+    }
+
+    pub fn note(&mut self, note: impl Into<String>) -> &mut Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn help(&mut self, help: impl Into<String>) -> &mut Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    /// `line 3: warning[unused-result]: ...`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line != 0 {
+            write!(f, "line {}: ", self.line)?;
+        }
+        write!(f, "{}[{}]: {}", self.severity.as_str(), self.code, self.message)
+    }
+}
+
+/// Diagnostics in the order they were reported
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    pub items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report an error; the returned diagnostic can take a column, notes and help
+    pub fn error(
+        &mut self,
+        line: u32,
+        code: &'static str,
+        message: impl Into<String>,
+    ) -> &mut Diagnostic {
+        self.push(Severity::Error, line, code, message.into())
+    }
+
+    pub fn warning(
+        &mut self,
+        line: u32,
+        code: &'static str,
+        message: impl Into<String>,
+    ) -> &mut Diagnostic {
+        self.push(Severity::Warning, line, code, message.into())
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        line: u32,
+        code: &'static str,
+        message: String,
+    ) -> &mut Diagnostic {
+        self.items.push(Diagnostic {
+            severity,
+            code,
+            message,
+            line,
+            column: 0,
+            width: 0,
+            notes: Vec::new(),
+            help: None,
+        });
+        self.items.last_mut().unwrap()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter().filter(|d| d.severity == Severity::Error)
+    }
+
+    /// A copy holding just the errors, for reports that skip warnings
+    pub fn errors_only(&self) -> Diagnostics {
+        Diagnostics {
+            items: self.errors().cloned().collect(),
+        }
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter().filter(|d| d.severity == Severity::Warning)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// A JSON array of `{"severity", "code", "line", "message"}` objects;
+    /// `column`, `notes` and `help` are added when set
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("[");
+        for (i, d) in self.items.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str(&format!(
+                "{{\"severity\":\"{}\",\"code\":\"{}\",\"line\":{},\"message\":",
+                d.severity.as_str(),
+                d.code,
+                d.line
+            ));
+            write_json_str(&d.message, &mut out);
+            if d.column != 0 {
+                out.push_str(&format!(",\"column\":{},\"width\":{}", d.column, d.width));
+            }
+            if !d.notes.is_empty() {
+                out.push_str(",\"notes\":[");
+                for (i, note) in d.notes.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_json_str(note, &mut out);
+                }
+                out.push(']');
+            }
+            if let Some(help) = &d.help {
+                out.push_str(",\"help\":");
+                write_json_str(help, &mut out);
+            }
+            out.push('}');
+        }
+        out.push(']');
+        out
+    }
+}
+
+/// One diagnostic per line
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, d) in self.items.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", d)?;
+        }
+        Ok(())
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Renders diagnostics against the source they were reported for, in the
+/// style of rustc: header, location, the source line with a caret
+/// underline, then notes and help
+pub struct Renderer<'a> {
+    source: &'a str,
+    origin: Option<&'a str>,
+    color: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            origin: None,
+            color: false,
+        }
+    }
+
+    /// Name shown in the `-->` location, usually the file path
+    pub fn with_origin(mut self, origin: &'a str) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    /// Use ANSI colors, for terminals
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Every diagnostic, separated by blank lines
+    pub fn render_all(&self, diagnostics: &Diagnostics) -> String {
+        let rendered: Vec<String> = diagnostics.items.iter().map(|d| self.render(d)).collect();
+        rendered.join("\n\n")
+    }
+
+    pub fn render(&self, d: &Diagnostic) -> String {
+        let severity_color = match d.severity {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+        };
+        let (blue, reset) = (self.paint(BLUE), self.paint(RESET));
+        let mut out = format!(
+            "{}{}[{}]{}{}: {}{}",
+            self.paint(severity_color),
+            d.severity.as_str(),
+            d.code,
+            reset,
+            self.paint(BOLD),
+            d.message,
+            reset
+        );
+        let line_text = match d.line {
+            0 => None,
+            n => self.source.lines().nth(n as usize - 1),
+        };
+        let gutter = " ".repeat(d.line.to_string().len());
+        if d.line != 0 {
+            let location = match (self.origin, d.column) {
+                (Some(origin), 0) => format!("{}:{}", origin, d.line),
+                (Some(origin), column) => format!("{}:{}:{}", origin, d.line, column),
+                (None, 0) => format!("line {}", d.line),
+                (None, column) => format!("line {}, column {}", d.line, column),
+            };
+            out.push_str(&format!("\n{}{}-->{} {}", gutter, blue, reset, location));
+        }
+        if let Some(text) = line_text {
+            let (start, width) = underline(text, d.column, d.width);
+            // Keep tabs so the carets line up with the text above them
+            let pad: String = text
+                .chars()
+                .take(start)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            out.push_str(&format!("\n{} {}|{}", gutter, blue, reset));
+            out.push_str(&format!("\n{}{} |{} {}", blue, d.line, reset, text));
+            out.push_str(&format!(
+                "\n{} {}|{} {}{}{}{}",
+                gutter,
+                blue,
+                reset,
+                pad,
+                self.paint(severity_color),
+                "^".repeat(width),
+                reset
+            ));
+        }
+        for note in &d.notes {
+            out.push_str(&format!("\n{} {}={} note: {}", gutter, blue, reset, note));
+        }
+        if let Some(help) = &d.help {
+            out.push_str(&format!("\n{} {}={} help: {}", gutter, blue, reset, help));
+        }
+        out
+    }
+
+    fn paint(&self, code: &'static str) -> &'static str {
+        if self.color { code } else { "" }
+    }
+}
+
+/// Start (0-based, in chars) and width of the underline in `text`. Without a
+/// column, the line's text minus its indentation and trailing blanks.
+fn underline(text: &str, column: u32, width: u32) -> (usize, usize) {
+    let len = text.chars().count();
+    if column != 0 {
+        let start = (column as usize - 1).min(len);
+        return (start, (width as usize).max(1));
+    }
+    let start = text.chars().take_while(|c| c.is_whitespace()).count();
+    let end = len - text.chars().rev().take_while(|c| c.is_whitespace()).count();
+    (start, end.saturating_sub(start).max(1))
+}
+
+fn write_json_str(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_text_and_json() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.warning(3, "unused-result", "the value of this expression is unused");
+        diagnostics.error(0, "syntax", "expected \"end\"\n");
+        assert!(diagnostics.has_errors());
+        assert_eq!(diagnostics.warnings().count(), 1);
+        assert_eq!(
+            diagnostics.to_string(),
+            "line 3: warning[unused-result]: the value of this expression is unused\n\
+             error[syntax]: expected \"end\"\n"
+        );
+        assert_eq!(
+            diagnostics.to_json(),
+            "[{\"severity\":\"warning\",\"code\":\"unused-result\",\"line\":3,\
+             \"message\":\"the value of this expression is unused\"},\
+             {\"severity\":\"error\",\"code\":\"syntax\",\"line\":0,\
+             \"message\":\"expected \\\"end\\\"\\n\"}]"
+        );
+        assert_eq!(Diagnostics::new().to_json(), "[]");
+    }
+
+    #[test]
+    fn renders_source_snippets() {
+        let source = "x = 1\n\ty = x + 2\n";
+        let mut diagnostics = Diagnostics::new();
+        diagnostics
+            .warning(2, "unused-result", "this value is dropped")
+            .at_column(8, 1)
+            .note("it is computed for nothing");
+        diagnostics
+            .error(2, "compile", "cannot add str and int")
+            .help("convert one side first");
+        diagnostics.error(0, "syntax", "no line");
+        assert_eq!(
+            Renderer::new(source).with_origin("a.ky").render_all(&diagnostics),
+            "warning[unused-result]: this value is dropped\n\
+             \x20--> a.ky:2:8\n\
+             \x20 |\n\
+             2 | \ty = x + 2\n\
+             \x20 | \t      ^\n\
+             \x20 = note: it is computed for nothing\n\
+             \n\
+             error[compile]: cannot add str and int\n\
+             \x20--> a.ky:2\n\
+             \x20 |\n\
+             2 | \ty = x + 2\n\
+             \x20 | \t^^^^^^^^^\n\
+             \x20 = help: convert one side first\n\
+             \n\
+             error[syntax]: no line"
+        );
+        let colored = Renderer::new(source).with_color(true).render(&diagnostics.items[1]);
+        assert!(colored.starts_with("\x1b[1;31merror[compile]\x1b[0m\x1b[1m: cannot add"));
+        assert!(colored.contains("\x1b[1;34m-->\x1b[0m line 2"));
+        assert!(diagnostics.to_json().contains(
+            "\"column\":8,\"width\":1,\"notes\":[\"it is computed for nothing\"]}"
+        ));
+        assert!(diagnostics.to_json().contains("\"help\":\"convert one side first\"}"));
+        assert_eq!(
+            diagnostics.items[0].to_string(),
+            "line 2: warning[unused-result]: this value is dropped"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Differential tracing for codegen changes: compile the same source two ways
+//! (say, with and without an optimization), run both, and compare everything
+//! a script can observe — host calls, printed output, final globals and the
+//! error it ended with. Any difference is a semantic regression.
+
+use crate::builtins::register_stdlib;
+use crate::vm::{Capabilities, VirtualMachine, VmError};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Turns source into bytecode for a VM with builtins registered; the `u16` is
+/// the const index holding `print`. `codegen::compile_source` is one.
+pub type Compiler = dyn Fn(&str, &mut VirtualMachine, u16) -> Result<Vec<u8>, String>;
+
+/// What one run of a program did
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+    /// Host functions called, in order; failed calls carry their error
+    pub host_calls: Vec<String>,
+    pub output: String,
+    /// Final value of each global
+    pub globals: BTreeMap<String, String>,
+    /// Runtime error that ended the run
+    pub error: Option<String>,
+}
+
+impl Trace {
+    /// Compile `src` with `compile` and run it in a fresh VM. Only the time
+    /// capability is granted, with the clock frozen at 0 so runs are
+    /// comparable; compile errors are returned as `Err`.
+    pub fn capture(src: &str, compile: &Compiler) -> Result<Self, String> {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut vm = VirtualMachine::new();
+        vm.capabilities = Capabilities {
+            time: true,
+            ..Capabilities::none()
+        };
+        let print_const = register_stdlib(
+            &mut vm,
+            Some(output.clone()),
+            Some(Arc::new(|| 0)),
+            Vec::new(),
+        );
+        let bytecode = compile(src, &mut vm, print_const)?;
+        vm.start_recording();
+        let error = vm.eval_program(&bytecode).err().map(|err| match err {
+            VmError::Exit(code) => format!("exit({})", code),
+            err => err.to_string(),
+        });
+        let host_calls = vm
+            .stop_replay()
+            .unwrap_or_default()
+            .events
+            .into_iter()
+            .map(|event| match event.error {
+                Some(err) => format!("{} -> error: {}", event.name, err),
+                None => event.name,
+            })
+            .collect();
+        let globals = vm
+            .global_vars
+            .iter()
+            .filter_map(|(name, _)| Some((name.to_string(), vm.format_global(name)?)))
+            .collect();
+        let output = output.lock().map_err(|e| e.to_string())?;
+        let output = String::from_utf8_lossy(&output).into_owned();
+        Ok(Self {
+            host_calls,
+            output,
+            globals,
+            error,
+        })
+    }
+
+    /// How `other` differs from `self`, one line per difference
+    pub fn diff(&self, other: &Trace) -> Vec<String> {
+        let mut diffs = Vec::new();
+        let calls = self.host_calls.iter().zip(&other.host_calls);
+        if let Some((i, (a, b))) = calls.enumerate().find(|(_, (a, b))| a != b) {
+            diffs.push(format!("host call {}: {} vs {}", i, a, b));
+        } else if self.host_calls.len() != other.host_calls.len() {
+            diffs.push(format!(
+                "{} host calls vs {}",
+                self.host_calls.len(),
+                other.host_calls.len()
+            ));
+        }
+        if self.output != other.output {
+            let (a, b) = (self.output.lines(), other.output.lines());
+            let line = a.clone().zip(b.clone()).take_while(|(a, b)| a == b).count();
+            diffs.push(format!(
+                "output line {}: {:?} vs {:?}",
+                line + 1,
+                a.clone().nth(line).unwrap_or("<end>"),
+                b.clone().nth(line).unwrap_or("<end>")
+            ));
+        }
+        for (name, value) in &self.globals {
+            match other.globals.get(name) {
+                Some(other_value) if other_value == value => {}
+                Some(other_value) => diffs.push(format!("{} = {} vs {}", name, value, other_value)),
+                None => diffs.push(format!("{} = {} vs <missing>", name, value)),
+            }
+        }
+        for (name, value) in &other.globals {
+            if !self.globals.contains_key(name) {
+                diffs.push(format!("{} = <missing> vs {}", name, value));
+            }
+        }
+        if self.error != other.error {
+            diffs.push(format!("error: {:?} vs {:?}", self.error, other.error));
+        }
+        diffs
+    }
+}
+
+/// Run `src` compiled by `baseline` and by `candidate`, failing with a report
+/// of every observable difference
+pub fn compare(src: &str, baseline: &Compiler, candidate: &Compiler) -> Result<(), String> {
+    let expected = Trace::capture(src, baseline).map_err(|e| format!("baseline: {}", e))?;
+    let actual = Trace::capture(src, candidate).map_err(|e| format!("candidate: {}", e))?;
+    let diffs = expected.diff(&actual);
+    if diffs.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("traces differ (baseline vs candidate):\n  {}", diffs.join("\n  ")))
+    }
+}
+
+#[cfg(all(test, feature = "disasm"))]
+mod tests {
+    use super::*;
+    use crate::codegen::compile_source;
+    use crate::vm::SUB_I64;
+    use crate::vm::stepper::Stepper;
+
+    const SRC: &str = "x = 2\ny = x + 3\nprint(y)\ns = \"ab\"\nt = now_ms()";
+
+    #[test]
+    fn identical_compilers_agree() {
+        assert_eq!(compare(SRC, &compile_source, &compile_source), Ok(()));
+        let trace = Trace::capture(SRC, &compile_source).unwrap();
+        assert_eq!(trace.host_calls, vec!["print", "now_ms"]);
+        assert_eq!(trace.output, "5\n");
+        assert_eq!(trace.globals["y"], "5");
+        assert_eq!(trace.globals["s"], "\"ab\"");
+        assert_eq!(trace.globals["t"], "0");
+        assert_eq!(trace.error, None);
+    }
+
+    #[test]
+    fn miscompiled_arithmetic_is_reported() {
+        // A broken "optimization" that turns every addition into a subtraction
+        let broken = |src: &str, vm: &mut VirtualMachine, print_const: u16| {
+            let mut bytecode = compile_source(src, vm, print_const)?;
+            let listing = Stepper::new(bytecode.clone()).listing().to_vec();
+            for (pc, _) in listing.iter().filter(|(_, text)| text.contains("ADD_I64")) {
+                bytecode[*pc] = SUB_I64;
+            }
+            Ok(bytecode)
+        };
+        let err = compare(SRC, &compile_source, &broken).unwrap_err();
+        assert!(err.contains("output line 1: \"5\" vs \"-1\""), "{}", err);
+        assert!(err.contains("y = 5 vs -1"), "{}", err);
+        assert!(!err.contains("host call"), "{}", err);
+    }
+
+    #[test]
+    fn dropped_effects_and_errors_are_reported() {
+        let truncated = |src: &str, vm: &mut VirtualMachine, print_const: u16| {
+            compile_source(src.lines().next().unwrap_or(""), vm, print_const)
+        };
+        let err = compare(SRC, &compile_source, &truncated).unwrap_err();
+        assert!(err.contains("2 host calls vs 0"), "{}", err);
+        assert!(err.contains("t = 0 vs <missing>"), "{}", err);
+
+        let failing = |_: &str, _: &mut VirtualMachine, _: u16| Ok(vec![0xFF]);
+        let err = compare(SRC, &compile_source, &failing).unwrap_err();
+        assert!(err.contains("error: None vs Some(\"Invalid opcode: 0xFF\")"), "{}", err);
+
+        let err = compare("x = (", &compile_source, &compile_source).unwrap_err();
+        assert!(err.starts_with("baseline: "), "{}", err);
+    }
+}
@@ -0,0 +1,456 @@
+//! Evaluating single expressions against named inputs, for hosts with
+//! "formula fields": `Engine::eval_expr("price + tax", &bindings)`.
+//!
+//! Each distinct (expression, binding types) pair is compiled once and the
+//! bytecode kept in an LRU cache keyed by its hash, so re-evaluating a
+//! formula over many rows only pays for loading its inputs and running it.
+
+use crate::builtins::register_builtins;
+use crate::diagnostics::Diagnostics;
+use crate::frontend::{
+    Ast, CompileBudget, Frontend, compile_with, parse_expression, report_parse_errors,
+};
+use crate::parser::Stmt;
+use crate::vm::const_pool::{ConstCheckpoint, SliceType, ValueType};
+use crate::vm::{GlobalVarType, GlobalVars, PtrType, RegisterType, VirtualMachine};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Global the compiled expression assigns its value to
+const RESULT: &str = "__value";
+
+/// A value passed into or returned from an expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    pub(crate) fn global_type(&self) -> GlobalVarType {
+        match self {
+            Value::Int(_) => GlobalVarType::Value(ValueType::I64),
+            Value::Float(_) => GlobalVarType::Value(ValueType::F64),
+            Value::Str(_) => GlobalVarType::Ptr(PtrType::Slice(SliceType::Utf8Str)),
+            Value::Bytes(_) => GlobalVarType::Ptr(PtrType::Slice(SliceType::Binary)),
+        }
+    }
+}
+
+/// One expression, parsed as the program `__value = <expr>`
+struct ExprSyntax;
+
+impl Frontend for ExprSyntax {
+    fn parse(&self, src: &str) -> Result<Ast, String> {
+        self.parse_with_diagnostics(src, &CompileBudget::default(), &mut Diagnostics::new())
+    }
+
+    fn parse_with_diagnostics(
+        &self,
+        src: &str,
+        budget: &CompileBudget,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Ast, String> {
+        let expr = parse_expression(src, budget, diagnostics)
+            .map_err(|errors| report_parse_errors(&errors, diagnostics))?;
+        Ok(Ast {
+            stmts: vec![Stmt::Assign {
+                name: RESULT.to_string(),
+                annotation: None,
+                expr,
+            }],
+            lines: vec![1],
+        })
+    }
+}
+
+/// Identifies a compilation: bytecode depends on the binding types, not values
+type CacheKey = (String, Vec<(String, GlobalVarType)>);
+
+struct CompiledExpr {
+    /// Kept to tell hash collisions apart
+    key: CacheKey,
+    bytecode: Vec<u8>,
+    /// Register of each binding, in key order
+    inputs: Vec<usize>,
+    result: usize,
+    result_type: GlobalVarType,
+    /// `ExprCache::tick` when last used
+    last_used: u64,
+}
+
+/// Compiled-expression cache counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room for new ones
+    pub evictions: u64,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    /// Share of lookups that found compiled bytecode, 0.0 before any lookup
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// Least-recently-used map from key hashes to compiled expressions
+struct ExprCache {
+    entries: HashMap<u64, CompiledExpr>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl ExprCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            tick: 0,
+            stats: CacheStats {
+                capacity,
+                ..CacheStats::default()
+            },
+        }
+    }
+
+    fn get(&mut self, hash: u64, key: &CacheKey) -> Option<&CompiledExpr> {
+        self.tick += 1;
+        match self.entries.get_mut(&hash) {
+            Some(entry) if entry.key == *key => {
+                entry.last_used = self.tick;
+                self.stats.hits += 1;
+                Some(entry)
+            }
+            _ => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, hash: u64, mut entry: CompiledExpr) {
+        if self.stats.capacity == 0 {
+            return;
+        }
+        if !self.entries.contains_key(&hash) && self.entries.len() >= self.stats.capacity {
+            let oldest = self.entries.iter().min_by_key(|(_, e)| e.last_used).map(|(h, _)| *h);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+                self.stats.evictions += 1;
+            }
+        }
+        entry.last_used = self.tick;
+        self.entries.insert(hash, entry);
+    }
+}
+
+/// Evaluates expressions in a scratch VM with the builtins registered but no
+/// capabilities granted
+pub struct Engine {
+    vm: VirtualMachine,
+    print_const: u16,
+    cache: ExprCache,
+    /// Const pool right after builtin registration
+    builtins_checkpoint: ConstCheckpoint,
+}
+
+impl Engine {
+    pub const DEFAULT_CACHE_CAPACITY: usize = 256;
+
+    pub fn new() -> Self {
+        Self::with_cache_capacity(Self::DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Engine keeping at most `capacity` compiled expressions; 0 disables caching
+    pub fn with_cache_capacity(capacity: usize) -> Self {
+        let mut vm = VirtualMachine::new();
+        let print_const = register_builtins(&mut vm);
+        let builtins_checkpoint = vm.const_pool.checkpoint();
+        Self {
+            vm,
+            print_const,
+            cache: ExprCache::new(capacity),
+            builtins_checkpoint,
+        }
+    }
+
+    /// Evaluate `src`, a single expression, with `bindings` as its globals
+    pub fn eval_expr(&mut self, src: &str, bindings: &HashMap<String, Value>) -> Result<Value, String> {
+        let mut inputs: Vec<(&String, &Value)> = bindings.iter().collect();
+        inputs.sort_by_key(|(name, _)| *name);
+        let key: CacheKey = (
+            src.to_string(),
+            inputs.iter().map(|(name, value)| (name.to_string(), value.global_type())).collect(),
+        );
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        match self.cache.get(hash, &key) {
+            Some(compiled) => run_compiled(&mut self.vm, compiled, &inputs),
+            None => {
+                let compiled = self.compile(src, key)?;
+                let value = run_compiled(&mut self.vm, &compiled, &inputs);
+                self.cache.insert(hash, compiled);
+                value
+            }
+        }
+    }
+
+    /// Lookup counters and current size of the compiled-expression cache
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            len: self.cache.entries.len(),
+            ..self.cache.stats
+        }
+    }
+
+    /// Drop every compiled expression, and the literals they added to the
+    /// const pool. Evicting single entries keeps their literals, so hosts
+    /// evaluating unbounded sets of formulas should clear now and then.
+    pub fn clear_cache(&mut self) {
+        self.cache.entries.clear();
+        self.vm.const_pool.rollback(self.builtins_checkpoint);
+    }
+
+    fn compile(&mut self, src: &str, key: CacheKey) -> Result<CompiledExpr, String> {
+        let registers = declare_bindings(&mut self.vm, &key.1)?;
+        let bytecode = compile_with(&ExprSyntax, src, &mut self.vm, self.print_const)
+            .map_err(|e| format!("compile error: {}", e))?;
+        let result = self
+            .vm
+            .global_vars
+            .get(RESULT)
+            .ok_or("expression produced no value")?;
+        Ok(CompiledExpr {
+            key,
+            bytecode,
+            inputs: registers,
+            result: result.register_id,
+            result_type: result.meta.typ,
+            last_used: 0,
+        })
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Load `inputs` into the registers `compiled` reads them from, run it and
+/// read back its value
+fn run_compiled(
+    vm: &mut VirtualMachine,
+    compiled: &CompiledExpr,
+    inputs: &[(&String, &Value)],
+) -> Result<Value, String> {
+    load_inputs(vm, inputs.iter().map(|(_, value)| *value), &compiled.inputs);
+    vm.eval_program(&compiled.bytecode)
+        .map_err(|e| format!("runtime error: {}", e))?;
+
+    let raw = vm.registers.get(compiled.result);
+    Ok(match compiled.result_type {
+        GlobalVarType::Value(ValueType::F64) => Value::Float(f64::from_bits(raw)),
+        GlobalVarType::Value(_) => Value::Int(raw as i64),
+        GlobalVarType::Ptr(PtrType::Slice(typ)) => {
+            let len = vm.registers.get(compiled.result + 1) as usize;
+            let data = if len == 0 {
+                Vec::new()
+            } else {
+                // The result points into the const pool or string heap,
+                // both untouched since the run
+                unsafe { std::slice::from_raw_parts(raw as *const u8, len) }.to_vec()
+            };
+            match typ {
+                SliceType::Binary => Value::Bytes(data),
+                SliceType::Utf8Str => Value::Str(String::from_utf8(data).map_err(|e| e.to_string())?),
+            }
+        }
+    })
+}
+
+/// Make `bindings` the VM's only globals, in registers 1.. (0 is reserved
+/// for call bases), and return their registers
+pub(crate) fn declare_bindings(
+    vm: &mut VirtualMachine,
+    bindings: &[(String, GlobalVarType)],
+) -> Result<Vec<usize>, String> {
+    vm.global_vars = GlobalVars::new();
+    let mut registers = Vec::new();
+    let mut next_reg = 1;
+    for (name, typ) in bindings {
+        vm.global_vars.insert(name, next_reg, *typ);
+        registers.push(next_reg);
+        next_reg += if matches!(typ, GlobalVarType::Value(_)) { 1 } else { 2 };
+    }
+    if next_reg > u8::MAX as usize {
+        return Err(format!("too many bindings ({})", bindings.len()));
+    }
+    Ok(registers)
+}
+
+/// Store `values` in the registers `declare_bindings` gave them, replacing
+/// the strings of the previous evaluation
+pub(crate) fn load_inputs<'a>(
+    vm: &mut VirtualMachine,
+    values: impl Iterator<Item = &'a Value>,
+    registers: &[usize],
+) {
+    vm.strings.clear();
+    for (value, &reg) in values.zip(registers) {
+        match value {
+            Value::Int(n) => {
+                vm.registers.set(reg, *n as u64);
+                vm.registers_type.set(reg, RegisterType::ValueRegister);
+            }
+            Value::Float(x) => {
+                vm.registers.set(reg, x.to_bits());
+                vm.registers_type.set(reg, RegisterType::ValueRegister);
+            }
+            Value::Str(text) => store_slice(vm, reg, text.as_bytes()),
+            Value::Bytes(data) => store_slice(vm, reg, data),
+        }
+    }
+}
+
+/// Copy `data` into the string heap and point the pair at `reg` to it
+fn store_slice(vm: &mut VirtualMachine, reg: usize, data: &[u8]) {
+    let ptr = vm.strings.alloc(data);
+    vm.registers.set(reg, ptr as u64);
+    vm.registers.set(reg + 1, data.len() as u64);
+    vm.registers_type.set(reg, RegisterType::HeapStrMain);
+    vm.registers_type.set(reg + 1, RegisterType::HeapStrLen);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings(values: &[(&str, Value)]) -> HashMap<String, Value> {
+        values.iter().map(|(name, value)| (name.to_string(), value.clone())).collect()
+    }
+
+    #[test]
+    fn evaluates_formulas_over_bindings() {
+        let mut engine = Engine::new();
+        let row = bindings(&[("price", Value::Int(40)), ("tax", Value::Int(2))]);
+        assert_eq!(engine.eval_expr("price + tax", &row), Ok(Value::Int(42)));
+        assert_eq!(engine.eval_expr("\n7\n", &HashMap::new()), Ok(Value::Int(7)));
+
+        // Same formula and binding types: compiled once
+        for (price, expected) in [(1, 3), (100, 102)] {
+            let row = bindings(&[("price", Value::Int(price)), ("tax", Value::Int(2))]);
+            assert_eq!(engine.eval_expr("price + tax", &row), Ok(Value::Int(expected)));
+        }
+        assert_eq!(engine.cache_stats().len, 2);
+    }
+
+    #[test]
+    #[cfg(feature = "isa-float")]
+    fn floats_round_trip() {
+        let mut engine = Engine::new();
+        let row = bindings(&[("price", Value::Float(9.99))]);
+        assert_eq!(engine.eval_expr("price + 0.01", &row), Ok(Value::Float(9.99 + 0.01)));
+        assert_eq!(engine.eval_expr("price + 1", &row), Ok(Value::Float(9.99 + 1.0)));
+    }
+
+    #[test]
+    #[cfg(feature = "isa-strings")]
+    fn strings_and_bytes_round_trip() {
+        let mut engine = Engine::new();
+        let row = bindings(&[("name", Value::Str("Ada".to_string())), ("n", Value::Int(3))]);
+        assert_eq!(
+            engine.eval_expr("f\"{name} x{n}\"", &row),
+            Ok(Value::Str("Ada x3".to_string()))
+        );
+        let row = bindings(&[("data", Value::Bytes(vec![1, 2]))]);
+        assert_eq!(engine.eval_expr("data + b\"\\x03\"", &row), Ok(Value::Bytes(vec![1, 2, 3])));
+
+        // A binding changing type compiles the formula again
+        let row = bindings(&[("name", Value::Int(1)), ("n", Value::Int(3))]);
+        assert_eq!(engine.eval_expr("name + n", &row), Ok(Value::Int(4)));
+        let row = bindings(&[("name", Value::Str("a".to_string())), ("n", Value::Str("b".to_string()))]);
+        assert_eq!(engine.eval_expr("name + n", &row), Ok(Value::Str("ab".to_string())));
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let mut engine = Engine::new();
+        let err = engine.eval_expr("x = 1", &HashMap::new()).unwrap_err();
+        assert!(err.starts_with("compile error: Unexpected token"), "{}", err);
+        // Formulas are parsed within the VM's compile limits
+        let nested = format!("{}1{}", "(".repeat(300), ")".repeat(300));
+        let err = engine.eval_expr(&nested, &HashMap::new()).unwrap_err();
+        assert_eq!(err, "compile error: nesting deeper than 200 levels");
+        let row = bindings(&[("s", Value::Str("a".to_string()))]);
+        assert!(engine.eval_expr("s + 1", &row).is_err());
+        // Formulas get no capabilities
+        let err = engine.eval_expr("now_ms()", &HashMap::new()).unwrap_err();
+        assert!(err.contains("now_ms"), "{}", err);
+        assert_eq!(engine.cache_stats().len, 0);
+    }
+
+    #[test]
+    fn cache_counts_hits_and_evicts_least_recently_used() {
+        let mut engine = Engine::with_cache_capacity(2);
+        let none = HashMap::new();
+        assert_eq!(engine.cache_stats().hit_rate(), 0.0);
+        assert_eq!(engine.eval_expr("1 + 1", &none), Ok(Value::Int(2)));
+        assert_eq!(engine.eval_expr("2 + 2", &none), Ok(Value::Int(4)));
+        assert_eq!(engine.eval_expr("1 + 1", &none), Ok(Value::Int(2)));
+        // "2 + 2" is now the least recently used
+        assert_eq!(engine.eval_expr("3 + 3", &none), Ok(Value::Int(6)));
+        assert_eq!(engine.eval_expr("1 + 1", &none), Ok(Value::Int(2)));
+        assert_eq!(engine.eval_expr("2 + 2", &none), Ok(Value::Int(4)));
+        assert_eq!(
+            engine.cache_stats(),
+            CacheStats {
+                hits: 2,
+                misses: 4,
+                evictions: 2,
+                len: 2,
+                capacity: 2,
+            }
+        );
+        assert!((engine.cache_stats().hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+
+        engine.clear_cache();
+        assert_eq!(engine.cache_stats().len, 0);
+        assert_eq!(engine.eval_expr("2 + 2", &none), Ok(Value::Int(4)));
+        assert_eq!(engine.cache_stats().misses, 5);
+    }
+
+    #[test]
+    #[cfg(feature = "isa-strings")]
+    fn clearing_the_cache_frees_literals() {
+        let mut engine = Engine::new();
+        let slices = engine.vm.const_pool.slice_count();
+        assert_eq!(engine.eval_expr("\"a\" + \"b\"", &HashMap::new()), Ok(Value::Str("ab".to_string())));
+        engine.clear_cache();
+        assert_eq!(engine.vm.const_pool.slice_count(), slices);
+        // Literals compiled after clearing are usable
+        assert_eq!(engine.eval_expr("\"c\"", &HashMap::new()), Ok(Value::Str("c".to_string())));
+    }
+
+    #[test]
+    fn zero_capacity_disables_caching() {
+        let mut engine = Engine::with_cache_capacity(0);
+        for _ in 0..2 {
+            assert_eq!(engine.eval_expr("5", &HashMap::new()), Ok(Value::Int(5)));
+        }
+        let stats = engine.cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.len), (0, 2, 0));
+    }
+}
@@ -0,0 +1,439 @@
+//! The interface between syntax and code generation. A `Frontend` turns
+//! source text in any notation into an `Ast`, which codegen compiles to the
+//! same bytecode whatever the notation was.
+//!
+//! The AST is deliberately small:
+//!
+//! - `Stmt::Assign { name, annotation, expr }` defines or updates global
+//!   `name`; its type is fixed by the first assignment. With an annotation
+//!   (`x: int = 0`, one of int, float, str, bytes, list or dict) every
+//!   assignment to `name` in that scope must give that type.
+//! - `Stmt::ExprStmt(expr)` evaluates `expr` for its effects. A call to
+//!   `print` with one argument, and to `help`, are handled specially.
+//!   `bench("name", f, n)` calls `f` a few times to warm up, then `n` times
+//!   under the `monotonic_ns` clock, and reports mean, min and max through
+//!   the `bench_report` host function.
+//! - `Stmt::If { cond, then, otherwise }` runs `then` if `cond` is truthy
+//!   (a non-zero int, a non-empty str or bytes), else `otherwise`. Source
+//!   syntax closes the chain with `end`:
+//!
+//!   ```text
+//!   if x < 10:
+//!       size = "small"
+//!   elif x < 100:
+//!       size = "medium"
+//!   else:
+//!       size = "large"
+//!   end
+//!   ```
+//!
+//!   An indented block ends where the indentation does, so its `end` may
+//!   be left out, Python-style; an unindented block needs it. The same
+//!   holds for `while` and `def`. Statements in a block report the line of
+//!   the `if` that opens it.
+//! - `Stmt::While { cond, body }` runs `body` for as long as `cond` is
+//!   truthy; source syntax is `while cond:` ... `end`.
+//! - `Stmt::Break` and `Stmt::Continue` leave the innermost loop, or skip
+//!   to its next test. Outside a loop they are compile errors; a function
+//!   body called in a loop is not in it.
+//! - `Stmt::FuncDef { name, params, annotations, returns, body }` defines a
+//!   function (`def name(a, b):` ... `end`), allowed only at the top level.
+//!   Its body is compiled at the first call for each combination of argument
+//!   types, and sees only its parameters and its own locals. A leading
+//!   string literal is its docstring. Annotated parameters (`a: int`) only
+//!   accept arguments of that type, and a return annotation (`-> float`)
+//!   fixes the type of every returned value.
+//! - `Stmt::Return(value)` leaves the function, with a value or without.
+//!   All returns of a function must agree on the value's type; falling off
+//!   the end gives 0, 0.0 or an empty str or bytes if they return one. A
+//!   recursive call can only be used as a value once a return before it
+//!   has fixed that type, or the function has a return annotation.
+//! - `Expr::Int`, `Expr::Float`, `Expr::Str` and `Expr::Bytes` are literals.
+//! - `Expr::Ident` reads a global, or inside a function a parameter or local.
+//! - `Expr::Binary` with `BinOp::Add` adds two numbers, or concatenates
+//!   strings (or bytes) of the same kind. Adding an int and a float promotes
+//!   the int, giving a float.
+//! - `BinOp::Mod` (`%`) takes the remainder of two ints with the sign of the
+//!   divisor, as Python does; a zero divisor fails the run. `BinOp::Pow`
+//!   (`**`) raises an int to a non-negative int power, wrapping like `+`, or
+//!   promotes as `+` does and gives a float.
+//! - `BitAnd` (`&`), `BitOr` (`|`), `BitXor` (`^`), `Shl` (`<<`) and `Shr`
+//!   (`>>`) take two ints, binding as in Python. Shifts by 64 or more clear
+//!   the int, or for `>>` fill it with its sign; a negative count fails the
+//!   run. `Expr::Unary` with `UnaryOp::BitNot` (`~`) flips every bit of an int.
+//! - `BinOp::Lt`, `Le`, `Gt` and `Ge` compare two numbers, giving 1 or 0. An
+//!   int and a float compare by exact value rather than by promotion, so no
+//!   precision is lost above 2^53. `Expr::Compare` chains them as Python
+//!   does: `0 < x < 10` evaluates `x` once and stops at the first false
+//!   comparison.
+//! - `Expr::Call { func, args }` calls a function defined with `def`, or
+//!   else a host function; `func` must be an `Expr::Ident` naming it.
+//! - `Expr::InterpolatedString` joins text and integer or string parts.
+//! - `Expr::Ternary` picks `then` if `cond` is truthy, else `otherwise`;
+//!   both must have the same type.
+//! - Calls to `min` and `max` with two numbers (promoted as for `+`), and
+//!   to `abs` with one, compile to a single opcode unless a host function of
+//!   that name is registered. So do `round`, `floor`, `ceil` and `trunc`, which
+//!   turn a float into an int and fail at runtime on NaN, infinities and
+//!   floats outside the int range; `round` sends halves to the even int, as
+//!   Python does.
+//! - `Expr::List(items)` builds a list of ints (`[1, 2, 3]`), `Expr::Index`
+//!   reads one item (`xs[i]`) and `Stmt::SetIndex` replaces one (`xs[i] =
+//!   v`). Lists live on the host: these compile to calls of the `vec_host_*`
+//!   functions, which must be registered. `Expr::Dict(entries)` builds a
+//!   dict of ints keyed by ints (`{1: 10, 2: 20}`), indexed the same way and
+//!   lowered to the `map_host_*` functions; reading a missing key fails at
+//!   runtime. `len(x)` gives the length of a list, dict, str or bytes,
+//!   unless a host function named `len` is registered.
+//! - `Stmt::Const { name, expr }` (`const LIMIT = 10 + 5`) names a value
+//!   folded at compile time from literals, earlier constants, `+`,
+//!   comparisons and conditionals; reading it compiles to the literal.
+//!   `Stmt::Enum` (`enum Color: RED, GREEN`) defines constants 0, 1, ...,
+//!   and records the variant names in `DebugInfo` so hosts can show them.
+//!   `Expr::Attribute` on an enum gives a variant (`Color.RED`), and
+//!   `Color.name(x)` and `Color.parse(s)` convert between values and variant
+//!   names at runtime, failing on values and names that are not variants.
+//!   Both are top-level only, and constants cannot be reassigned.
+//! - `Stmt::Match` (`match x:` with `case 1:`, `case "a":`, `case Color.RED:`
+//!   and `case _:` arms) runs the first arm whose `Pattern` equals an int
+//!   or str subject, tested one after another; no arm running is not an
+//!   error.
+//! - `Stmt::Import` (`import geometry`) compiles `geometry.kay`, found by
+//!   `VirtualMachine::modules`, where it is first imported; its globals are
+//!   read as `geometry.area`. See `modules`.
+//! - A call of an attribute (`conn.query("users", 10)`) on an int holding a
+//!   foreign object handle runs the method of that name of the object's
+//!   `ForeignType`, checking its argument count and kinds at run time.
+//!
+//! Callers see compile errors only as values: `compile_with` returns the
+//! first as a `String`, and `compile_with_diagnostics` returns them all as
+//! `Diagnostics`, along with warnings, such as results computed and dropped.
+//! Inside, codegen still stops at a type error or unknown name by panicking,
+//! and a `Frontend::parse` may panic too; `catch_compile_panic` turns those
+//! panics into errors without the panic hook printing them, so no Rust
+//! panic message or backtrace reaches the user.
+//!
+//! `VirtualMachine::compile_limits` bounds the work compiling one source may
+//! take, so a service compiling untrusted scripts stays responsive; going
+//! over a limit is a `limit` error.
+
+use crate::codegen::generate_bytecode_with_diagnostics;
+use crate::diagnostics::Diagnostics;
+use crate::lexer::{Lexer, Span, Token};
+use crate::modules::{imports, load_imports};
+use crate::parser::rewrite::rewrite_program;
+use crate::parser::{ParseError, Parser};
+use crate::vm::VirtualMachine;
+use std::any::Any;
+use std::cell::Cell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+pub use crate::parser::{BinOp, Expr, MatchArm, Pattern, Stmt, StringPart, UnaryOp};
+
+/// A parsed program, ready for code generation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ast {
+    pub stmts: Vec<Stmt>,
+    /// 1-based source line of each statement, for source maps; may be empty,
+    /// and 0 means unknown
+    pub lines: Vec<u32>,
+}
+
+/// Nesting of expressions and blocks allowed by default; the parser and
+/// codegen recurse once per level, so deeper input could overflow the stack
+pub const DEFAULT_MAX_DEPTH: usize = 200;
+
+/// Bounds on compiling one source. `None` means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompileLimits {
+    /// Nested parentheses, calls, ternaries, `~`, `**` and blocks. A flat
+    /// chain such as `a + b + c` is compiled in a loop and does not count.
+    pub max_depth: Option<usize>,
+    /// Tokens the lexer may produce
+    pub max_tokens: Option<usize>,
+    /// Wall-clock time for parsing and code generation together
+    pub max_time: Option<Duration>,
+}
+
+impl Default for CompileLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_tokens: None,
+            max_time: None,
+        }
+    }
+}
+
+impl CompileLimits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_depth: None,
+            max_tokens: None,
+            max_time: None,
+        }
+    }
+
+    /// Start the clock of one compilation
+    pub fn start(self) -> CompileBudget {
+        CompileBudget {
+            limits: self,
+            deadline: self.max_time.map(|max| Instant::now() + max),
+        }
+    }
+}
+
+/// `CompileLimits` of a compilation in progress
+#[derive(Debug, Clone, Copy)]
+pub struct CompileBudget {
+    pub limits: CompileLimits,
+    deadline: Option<Instant>,
+}
+
+impl CompileBudget {
+    pub fn check_time(&self) -> Result<(), LimitExceeded> {
+        match (self.deadline, self.limits.max_time) {
+            (Some(deadline), Some(max)) if Instant::now() > deadline => Err(LimitExceeded::Time(max)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_depth(&self, depth: usize) -> Result<(), LimitExceeded> {
+        match self.limits.max_depth {
+            Some(max) if depth > max => Err(LimitExceeded::Depth(max)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_tokens(&self, count: usize) -> Result<(), LimitExceeded> {
+        match self.limits.max_tokens {
+            Some(max) if count > max => Err(LimitExceeded::Tokens(max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for CompileBudget {
+    fn default() -> Self {
+        CompileLimits::default().start()
+    }
+}
+
+/// Which of the `CompileLimits` a compilation went over. The parser reports
+/// it in a `ParseError`; codegen unwinds with it as the panic payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Depth(usize),
+    Tokens(usize),
+    Time(Duration),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Depth(max) => write!(f, "nesting deeper than {} levels", max),
+            LimitExceeded::Tokens(max) => write!(f, "source longer than {} tokens", max),
+            LimitExceeded::Time(max) => write!(f, "compilation took longer than {:?}", max),
+        }
+    }
+}
+
+/// A syntax that compiles to Kayton bytecode
+pub trait Frontend {
+    fn parse(&self, src: &str) -> Result<Ast, String>;
+
+    /// Like `parse`, reporting warnings, and errors with their lines, to
+    /// `diagnostics`. An error returned without being reported is reported
+    /// by the caller, at an unknown line. Frontends that can should also
+    /// enforce `budget`.
+    fn parse_with_diagnostics(
+        &self,
+        src: &str,
+        _budget: &CompileBudget,
+        _diagnostics: &mut Diagnostics,
+    ) -> Result<Ast, String> {
+        self.parse(src)
+    }
+}
+
+/// The standard Python-like syntax
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KaytonSyntax;
+
+impl Frontend for KaytonSyntax {
+    fn parse(&self, src: &str) -> Result<Ast, String> {
+        self.parse_with_diagnostics(src, &CompileBudget::default(), &mut Diagnostics::new())
+    }
+
+    fn parse_with_diagnostics(
+        &self,
+        src: &str,
+        budget: &CompileBudget,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Ast, String> {
+        let parsed = tokenize_within(src, budget, diagnostics).and_then(|(tokens, spans)| {
+            let mut parser = Parser::new(tokens).with_spans(spans).with_budget(*budget);
+            let stmts = parser.parse()?;
+            Ok(Ast {
+                stmts,
+                lines: parser.stmt_lines().to_vec(),
+            })
+        });
+        parsed.map_err(|errors| report_parse_errors(&errors, diagnostics))
+    }
+}
+
+/// Lex `src`, failing if it has more tokens than `budget` allows
+fn tokenize_within(
+    src: &str,
+    budget: &CompileBudget,
+    diagnostics: &mut Diagnostics,
+) -> Result<(Vec<Token>, Vec<Span>), Vec<ParseError>> {
+    let (tokens, spans) = Lexer::new(src).tokenize_spanned(diagnostics)?;
+    if let Err(limit) = budget.check_tokens(tokens.len()) {
+        let mut error = ParseError::new(limit.to_string(), Token::EOF, Span::default());
+        error.limit = Some(limit);
+        return Err(vec![error]);
+    }
+    Ok((tokens, spans))
+}
+
+/// Lex and parse `src` as exactly one expression, within `budget`. For
+/// frontends that wrap formulas into programs.
+pub fn parse_expression(
+    src: &str,
+    budget: &CompileBudget,
+    diagnostics: &mut Diagnostics,
+) -> Result<Expr, Vec<ParseError>> {
+    let (tokens, spans) = tokenize_within(src, budget, diagnostics)?;
+    let mut parser = Parser::new(tokens).with_spans(spans).with_budget(*budget);
+    parser.parse_expression().map_err(|error| vec![error])
+}
+
+/// Report `errors` to `diagnostics`, as `syntax` or `limit` errors at their
+/// spans, returning the first one's message
+pub fn report_parse_errors(errors: &[ParseError], diagnostics: &mut Diagnostics) -> String {
+    for error in errors {
+        let code = if error.limit.is_some() { "limit" } else { "syntax" };
+        diagnostics
+            .error(error.span.line, code, error.message.clone())
+            .at_column(error.span.column, error.span.width);
+    }
+    errors[0].message.clone()
+}
+
+/// Parse `src` with `frontend`, apply the VM's AST rewriters and generate
+/// bytecode. `print_const` is the const index holding `print`.
+pub fn compile_with(
+    frontend: &dyn Frontend,
+    src: &str,
+    vm: &mut VirtualMachine,
+    print_const: u16,
+) -> Result<Vec<u8>, String> {
+    compile_with_diagnostics(frontend, src, vm, print_const)
+        .map(|(bytecode, _)| bytecode)
+        .map_err(|diagnostics| match diagnostics.errors().next() {
+            Some(error) => error.message.clone(),
+            None => "compile error".to_string(),
+        })
+}
+
+/// Like `compile_with`, also returning the warnings of a successful
+/// compilation. On failure, the diagnostics hold at least one error.
+pub fn compile_with_diagnostics(
+    frontend: &dyn Frontend,
+    src: &str,
+    vm: &mut VirtualMachine,
+    print_const: u16,
+) -> Result<(Vec<u8>, Diagnostics), Diagnostics> {
+    let mut diagnostics = Diagnostics::new();
+    let rewriters = vm.rewriters.clone();
+    let budget = vm.compile_limits.start();
+    let parsed = catch_compile_panic(|| {
+        let ast = frontend.parse_with_diagnostics(src, &budget, &mut diagnostics)?;
+        Ok(rewrite_program(ast.stmts, ast.lines, &rewriters))
+    })
+    .unwrap_or_else(|payload| Err(panic_message(payload.as_ref())));
+    let (stmts, lines) = match parsed {
+        Ok(program) => program,
+        Err(message) => {
+            if !diagnostics.has_errors() {
+                diagnostics.error(0, "syntax", message);
+            }
+            return Err(diagnostics);
+        }
+    };
+    let modules = match load_imports(&stmts, &vm.modules, frontend, &rewriters, &budget) {
+        Ok(modules) => modules,
+        Err(error) => {
+            // Reported at the top-level statement holding the failed import
+            let index = stmts
+                .iter()
+                .position(|stmt| imports(std::slice::from_ref(stmt)).contains(&error.import.as_str()));
+            let line = index.and_then(|i| lines.get(i)).copied().unwrap_or(0);
+            diagnostics.error(line, "import", error.message);
+            return Err(diagnostics);
+        }
+    };
+    match generate_bytecode_with_diagnostics(
+        &stmts,
+        &lines,
+        &modules,
+        vm,
+        print_const,
+        &budget,
+        &mut diagnostics,
+    ) {
+        Some(bytecode) => Ok((bytecode, diagnostics)),
+        None => Err(diagnostics),
+    }
+}
+
+thread_local! {
+    /// `catch_compile_panic` calls running on this thread
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Run `f`, returning the payload if it panics, as `catch_unwind` does, but
+/// without the panic hook printing the panic: it is a compile error that
+/// the caller reports. The first call wraps the process's hook in one that
+/// stays silent on threads inside this function and defers to the hook it
+/// replaced everywhere else.
+pub(crate) fn catch_compile_panic<T>(f: impl FnOnce() -> T) -> Result<T, Box<dyn Any + Send>> {
+    static QUIET_HOOK: Once = Once::new();
+    QUIET_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) == 0 {
+                previous(info);
+            }
+        }));
+    });
+    CATCHING.with(|catching| catching.set(catching.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|catching| catching.set(catching.get() - 1));
+    result
+}
+
+/// The message a frontend or codegen panicked with
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .or_else(|| payload.downcast_ref::<LimitExceeded>().map(|limit| limit.to_string()))
+        .unwrap_or_else(|| "compile error".to_string())
+}
+
+/// The diagnostic code of a panic: `limit` for `LimitExceeded`, otherwise
+/// `code`
+pub(crate) fn panic_code(payload: &(dyn Any + Send), code: &'static str) -> &'static str {
+    if payload.is::<LimitExceeded>() {
+        "limit"
+    } else {
+        code
+    }
+}
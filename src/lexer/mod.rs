@@ -1,17 +1,49 @@
-use std::iter::Peekable;
+use crate::diagnostics::Diagnostics;
+use crate::parser::ParseError;
 use std::str::Chars;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Int(i64),
+    /// Decimal literal with a fractional part, such as `3.14`
+    Float(f64),
     Str(String),
+    /// `b"..."` literal
+    Bytes(Vec<u8>),
     Ident(String),
     Plus,
+    Percent,
+    /// `**`, raising to a power
+    StarStar,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
     Equal,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    /// `<<`
+    LessLess,
+    /// `>>`
+    GreaterGreater,
     LParen,
     RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Dot,
     Comma,
+    Colon,
+    /// `->` before the return type of a `def`
+    Arrow,
     Newline,
+    /// A line indented deeper than the one before it
+    Indent,
+    /// Closes the innermost `Indent` a line is no longer indented to
+    Dedent,
     EOF,
     InterpolatedString(Vec<FStringPart>),
 }
@@ -22,22 +54,98 @@ pub enum FStringPart {
     Expr(String),
 }
 
+/// Where a token is in the source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    /// 1-based line
+    pub line: u32,
+    /// 1-based column, in chars; 0 if unknown
+    pub column: u32,
+    /// Length in chars
+    pub width: u32,
+}
+
 pub struct Lexer<'a> {
-    chars: Peekable<Chars<'a>>,
+    input: &'a str,
+    chars: Chars<'a>,
+    /// 1-based line of the next character
+    line: u32,
+    /// Byte offset of the start of the current line
+    line_start: usize,
+    /// Byte offset and column last computed, so columns on long lines are
+    /// counted incrementally
+    column_mark: (usize, u32),
+    /// Line, column and byte offset of the token being lexed
+    token_start: (u32, u32, usize),
+    spans: Vec<Span>,
+    diagnostics: Diagnostics,
+    errors: Vec<ParseError>,
+    /// Open indentation levels, in columns; the first is that of the
+    /// first line with code, so uniformly indented input lexes as unindented
+    indents: Vec<usize>,
+    at_line_start: bool,
+    pending_dedents: usize,
+    /// After the dedents, an `Indent` to a level no enclosing line had,
+    /// which the parser rejects
+    pending_indent: bool,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
         Self {
-            chars: input.chars().peekable(),
+            input,
+            chars: input.chars(),
+            line: 1,
+            line_start: 0,
+            column_mark: (0, 1),
+            token_start: (1, 1, 0),
+            spans: Vec::new(),
+            diagnostics: Diagnostics::new(),
+            errors: Vec::new(),
+            indents: Vec::new(),
+            at_line_start: true,
+            pending_dedents: 0,
+            pending_indent: false,
+        }
+    }
+
+    /// Tokenize the input, with the span of each token, adding the lexer's
+    /// warnings to `diagnostics`. Malformed literals are errors.
+    pub fn tokenize_spanned(
+        mut self,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<(Vec<Token>, Vec<Span>), Vec<ParseError>> {
+        let tokens = self.tokens();
+        diagnostics.items.append(&mut self.diagnostics.items);
+        if self.errors.is_empty() {
+            Ok((tokens, self.spans))
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    /// Like `tokenize`, adding the lexer's warnings to `diagnostics`
+    pub fn tokenize_into(self, diagnostics: &mut Diagnostics) -> Vec<Token> {
+        match self.tokenize_spanned(diagnostics) {
+            Ok((tokens, _)) => tokens,
+            Err(errors) => panic!("{}", errors[0]),
         }
     }
 
     /// Tokenize the input. The resulting token stream will always end with `Token::EOF`.
-    pub fn tokenize(mut self) -> Vec<Token> {
+    /// Panics with the message of the first malformed literal, if any; see
+    /// `tokenize_spanned`.
+    pub fn tokenize(self) -> Vec<Token> {
+        self.tokenize_into(&mut Diagnostics::new())
+    }
+
+    fn tokens(&mut self) -> Vec<Token> {
         let mut tokens = Vec::new();
         loop {
             let tok = self.next_token();
+            let (line, column, start) = self.token_start;
+            let width = self.input[start..self.offset()].chars().count() as u32;
+            self.spans.push(Span { line, column, width });
             let end = tok == Token::EOF;
             tokens.push(tok);
             if end {
@@ -47,76 +155,293 @@ impl<'a> Lexer<'a> {
         tokens
     }
 
+    /// Note that the token being lexed starts at the next character
+    fn mark_token_start(&mut self) {
+        let column = self.column();
+        self.token_start = (self.line, column, self.offset());
+    }
+
+    fn error(&mut self, message: String) {
+        let (line, column, start) = self.token_start;
+        let width = self.input[start..self.offset()].chars().count() as u32;
+        self.errors.push(ParseError::new(message, Token::EOF, Span { line, column, width }));
+    }
+
     fn next_token(&mut self) -> Token {
-        self.skip_whitespace();
-        let ch = match self.chars.peek().copied() {
-            Some(c) => c,
-            None => return Token::EOF,
-        };
+        if self.at_line_start {
+            self.at_line_start = false;
+            self.lex_indentation();
+        }
+        self.mark_token_start();
+        if self.pending_dedents > 0 {
+            self.pending_dedents -= 1;
+            return Token::Dedent;
+        }
+        if self.pending_indent {
+            self.pending_indent = false;
+            return Token::Indent;
+        }
+        loop {
+            self.skip_whitespace();
+            self.mark_token_start();
+            let ch = match self.peek() {
+                Some(c) => c,
+                None if self.indents.len() > 1 => {
+                    self.indents.pop();
+                    return Token::Dedent;
+                }
+                None => return Token::EOF,
+            };
 
-        match ch {
-            '\n' => {
-                self.chars.next();
-                Token::Newline
-            }
-            '=' => {
-                self.chars.next();
-                Token::Equal
-            }
-            '+' => {
-                self.chars.next();
-                Token::Plus
-            }
-            '(' => {
-                self.chars.next();
-                Token::LParen
-            }
-            ')' => {
-                self.chars.next();
-                Token::RParen
-            }
-            ',' => {
-                self.chars.next();
-                Token::Comma
-            }
-            '0'..='9' => self.lex_number(ch),
-            'a'..='z' | 'A'..='Z' | '_' => {
-                if ch == 'f' {
-                    if let Some('"') = self.peek_next() {
+            return match ch {
+                '\n' => {
+                    self.chars.next();
+                    self.line += 1;
+                    self.line_start = self.offset();
+                    self.at_line_start = true;
+                    Token::Newline
+                }
+                '=' => {
+                    self.chars.next();
+                    Token::Equal
+                }
+                '+' => {
+                    self.chars.next();
+                    Token::Plus
+                }
+                '%' => {
+                    self.chars.next();
+                    Token::Percent
+                }
+                '*' if self.peek_next() == Some('*') => {
+                    self.chars.next();
+                    self.chars.next();
+                    Token::StarStar
+                }
+                '-' if self.peek_next() == Some('>') => {
+                    self.chars.next();
+                    self.chars.next();
+                    Token::Arrow
+                }
+                '&' | '|' | '^' | '~' => {
+                    self.chars.next();
+                    match ch {
+                        '&' => Token::Ampersand,
+                        '|' => Token::Pipe,
+                        '^' => Token::Caret,
+                        _ => Token::Tilde,
+                    }
+                }
+                '<' | '>' if self.peek_next() == Some(ch) => {
+                    self.chars.next();
+                    self.chars.next();
+                    if ch == '<' { Token::LessLess } else { Token::GreaterGreater }
+                }
+                '<' | '>' => {
+                    self.chars.next();
+                    let or_equal = self.next_if(|c| c == '=').is_some();
+                    match (ch, or_equal) {
+                        ('<', false) => Token::Less,
+                        ('<', true) => Token::LessEqual,
+                        ('>', false) => Token::Greater,
+                        _ => Token::GreaterEqual,
+                    }
+                }
+                '(' => {
+                    self.chars.next();
+                    Token::LParen
+                }
+                ')' => {
+                    self.chars.next();
+                    Token::RParen
+                }
+                '[' => {
+                    self.chars.next();
+                    Token::LBracket
+                }
+                ']' => {
+                    self.chars.next();
+                    Token::RBracket
+                }
+                '{' => {
+                    self.chars.next();
+                    Token::LBrace
+                }
+                '.' => {
+                    self.chars.next();
+                    Token::Dot
+                }
+                '}' => {
+                    self.chars.next();
+                    Token::RBrace
+                }
+                ',' => {
+                    self.chars.next();
+                    Token::Comma
+                }
+                ':' => {
+                    self.chars.next();
+                    Token::Colon
+                }
+                '0'..='9' => self.lex_number(ch),
+                'a'..='z' | 'A'..='Z' | '_' => {
+                    if ch == 'f' && self.peek_next() == Some('"') {
                         return self.lex_fstring();
                     }
+                    if ch == 'b' && self.peek_next() == Some('"') {
+                        return self.lex_bytes();
+                    }
+                    self.lex_ident(ch)
                 }
-                self.lex_ident(ch)
-            }
-            '"' => self.lex_string(),
-            _ => {
-                // Unknown character, skip
-                self.chars.next();
-                Token::EOF
-            }
+                '"' => self.lex_string(),
+                _ => {
+                    // Unknown character: an error, after which lexing goes on
+                    // so later errors are reported too
+                    self.chars.next();
+                    self.error(format!("unknown character {:?}", ch));
+                    continue;
+                }
+            };
+        }
+    }
+
+    /// 1-based column, in chars, of the next character
+    fn column(&mut self) -> u32 {
+        let offset = self.offset();
+        if self.column_mark.0 < self.line_start {
+            self.column_mark = (self.line_start, 1);
         }
+        let (from, column) = self.column_mark;
+        let column = column + self.input[from..offset].chars().count() as u32;
+        self.column_mark = (offset, column);
+        column
+    }
+
+    /// Byte offset of the next character
+    fn offset(&self) -> usize {
+        self.input.len() - self.chars.as_str().len()
     }
 
+    fn peek(&self) -> Option<char> {
+        self.chars.clone().next()
+    }
+
+    fn next_if(&mut self, accept: impl Fn(char) -> bool) -> Option<char> {
+        let c = self.peek().filter(|&c| accept(c))?;
+        self.chars.next();
+        Some(c)
+    }
+
+    /// A decimal int or float, or a `0x`, `0o` or `0b` int. A float has a
+    /// fraction, an exponent or both: `2.5`, `1e9`, `2.5e-3`. Digits may be
+    /// grouped with single underscores between them: `1_000_000`.
     fn lex_number(&mut self, first: char) -> Token {
-        let mut num = first.to_string();
         self.chars.next();
-        while let Some(c) = self.chars.peek() {
-            if c.is_ascii_digit() {
-                num.push(*c);
-                self.chars.next();
-            } else {
-                break;
+        let radix = match (first, self.peek()) {
+            ('0', Some('x' | 'X')) => Some((16, "hex")),
+            ('0', Some('o' | 'O')) => Some((8, "octal")),
+            ('0', Some('b' | 'B')) => Some((2, "binary")),
+            _ => None,
+        };
+        if let Some((radix, name)) = radix {
+            let prefix = format!("0{}", self.chars.next().unwrap());
+            // Letters are taken too, so `0x1G` is one malformed literal
+            let digits = self.lex_digits(String::new(), |c| c.is_ascii_alphanumeric());
+            let literal = format!("{}{}", prefix, digits);
+            if digits.is_empty() {
+                self.error(format!("missing digits after {}", prefix));
+            } else if let Some(bad) = digits.chars().find(|&c| c != '_' && !c.is_digit(radix)) {
+                self.error(format!("invalid digit {:?} in {} literal {}", bad, name, literal));
+            } else if self.check_underscores(&digits, &literal) {
+                match i64::from_str_radix(&digits.replace('_', ""), radix) {
+                    Ok(n) => return Token::Int(n),
+                    Err(_) => self.error(format!("integer literal {} is too large", literal)),
+                }
+            }
+            return Token::Int(0);
+        }
+
+        let mut num = self.lex_digits(first.to_string(), |c| c.is_ascii_digit());
+        let mut is_float = false;
+        if self.peek() == Some('.') && self.peek_next().is_some_and(|c| c.is_ascii_digit()) {
+            self.chars.next();
+            num.push('.');
+            num = self.lex_digits(num, |c| c.is_ascii_digit());
+            is_float = true;
+        }
+        if self.at_exponent() {
+            num.push(self.chars.next().unwrap());
+            if let Some(sign) = self.next_if(|c| c == '+' || c == '-') {
+                num.push(sign);
+            }
+            num = self.lex_digits(num, |c| c.is_ascii_digit());
+            is_float = true;
+        } else if let Some(e) = self.next_if(|c| c == 'e' || c == 'E') {
+            // `1e` and `1e+` are one bad literal, not `1` followed by `e`
+            num.push(e);
+            if let Some(sign) = self.next_if(|c| c == '+' || c == '-') {
+                num.push(sign);
+            }
+            self.error(format!("malformed float literal {}", num));
+            return Token::Float(0.0);
+        }
+        let mut parts = num.split(['.', 'e', 'E', '+', '-']);
+        if !parts.all(|part| self.check_underscores(part, &num)) {
+            return Token::Int(0);
+        }
+        let digits = num.replace('_', "");
+        if is_float {
+            let x: f64 = digits.parse().unwrap();
+            if x.is_infinite() {
+                self.error(format!("float literal {} is out of range", num));
+            }
+            return Token::Float(x);
+        }
+        match digits.parse() {
+            Ok(n) => Token::Int(n),
+            Err(_) => {
+                self.error(format!("integer literal {} is too large", num));
+                Token::Int(0)
             }
         }
-        Token::Int(num.parse().unwrap())
+    }
+
+    /// Whether an exponent, `e` with an optionally signed digit, comes next
+    fn at_exponent(&self) -> bool {
+        let mut next = self.chars.clone();
+        if !matches!(next.next(), Some('e' | 'E')) {
+            return false;
+        }
+        match next.next() {
+            Some('+' | '-') => next.next().is_some_and(|c| c.is_ascii_digit()),
+            c => c.is_some_and(|c| c.is_ascii_digit()),
+        }
+    }
+
+    /// `num` followed by the digits and underscores that come next
+    fn lex_digits(&mut self, mut num: String, is_digit: impl Fn(char) -> bool) -> String {
+        while let Some(c) = self.next_if(|c| is_digit(c) || c == '_') {
+            num.push(c);
+        }
+        num
+    }
+
+    /// Whether underscores in `digits` only separate digits, reporting an
+    /// error against `literal` if not
+    fn check_underscores(&mut self, digits: &str, literal: &str) -> bool {
+        let ok = !digits.starts_with('_') && !digits.ends_with('_') && !digits.contains("__");
+        if !ok {
+            self.error(format!("misplaced '_' in number literal {}", literal));
+        }
+        ok
     }
 
     fn lex_ident(&mut self, first: char) -> Token {
         let mut ident = first.to_string();
         self.chars.next();
-        while let Some(c) = self.chars.peek() {
-            if c.is_ascii_alphanumeric() || *c == '_' {
-                ident.push(*c);
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                ident.push(c);
                 self.chars.next();
             } else {
                 break;
@@ -128,7 +453,7 @@ impl<'a> Lexer<'a> {
     fn lex_string(&mut self) -> Token {
         self.chars.next(); // skip opening quote
         let mut s = String::new();
-        while let Some(c) = self.chars.next() {
+        for c in self.chars.by_ref() {
             if c == '"' {
                 break;
             } else {
@@ -138,6 +463,40 @@ impl<'a> Lexer<'a> {
         Token::Str(s)
     }
 
+    /// `b"..."`: `\xNN`, `\n`, `\r`, `\t`, `\0`, `\\` and `\"` escapes; other
+    /// characters stand for their UTF-8 bytes
+    fn lex_bytes(&mut self) -> Token {
+        self.chars.next(); // consume 'b'
+        self.chars.next(); // consume opening quote
+        let mut bytes = Vec::new();
+        while let Some(c) = self.chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match self.chars.next() {
+                    Some('x') => {
+                        let hex: String = self.chars.by_ref().take(2).collect();
+                        match u8::from_str_radix(&hex, 16) {
+                            Ok(byte) => bytes.push(byte),
+                            Err(_) => self.error(format!("invalid escape \\x{} in bytes literal", hex)),
+                        }
+                    }
+                    Some('n') => bytes.push(b'\n'),
+                    Some('r') => bytes.push(b'\r'),
+                    Some('t') => bytes.push(b'\t'),
+                    Some('0') => bytes.push(0),
+                    Some('\\') => bytes.push(b'\\'),
+                    Some('"') => bytes.push(b'"'),
+                    other => self.error(format!("invalid escape {:?} in bytes literal", other)),
+                },
+                c => {
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+        }
+        Token::Bytes(bytes)
+    }
+
     fn lex_fstring(&mut self) -> Token {
         self.chars.next(); // consume 'f'
         self.chars.next(); // consume opening quote
@@ -148,7 +507,7 @@ impl<'a> Lexer<'a> {
                 '"' => break,
                 '{' => {
                     let mut expr_src = String::new();
-                    while let Some(ch) = self.chars.next() {
+                    for ch in self.chars.by_ref() {
                         if ch == '}' {
                             break;
                         } else {
@@ -169,10 +528,45 @@ impl<'a> Lexer<'a> {
         Token::InterpolatedString(parts)
     }
 
+    /// Measure the indentation of a new line and queue the `Indent` or
+    /// `Dedent`s it calls for. Blank and comment-only lines change nothing.
+    /// A tab advances to the next multiple of 8 columns.
+    fn lex_indentation(&mut self) {
+        let mut level = 0;
+        while let Some(c) = self.next_if(|c| c == ' ' || c == '\t') {
+            level = if c == '\t' { (level / 8 + 1) * 8 } else { level + 1 };
+        }
+        if matches!(self.peek(), None | Some('\n' | '\r' | '#')) {
+            return;
+        }
+        let Some(&current) = self.indents.last() else {
+            self.indents.push(level);
+            return;
+        };
+        if level > current {
+            self.indents.push(level);
+            self.pending_indent = true;
+            return;
+        }
+        while self.indents.len() > 1 && level < self.indents[self.indents.len() - 1] {
+            self.indents.pop();
+            self.pending_dedents += 1;
+        }
+        if level < self.indents[0] {
+            self.indents[0] = level;
+        } else if level > self.indents[self.indents.len() - 1] {
+            self.indents.push(level);
+            self.pending_indent = true;
+        }
+    }
+
+    /// Skip blanks and a `#` comment, up to the newline that ends it
     fn skip_whitespace(&mut self) {
-        while let Some(&c) = self.chars.peek() {
+        while let Some(c) = self.peek() {
             if c == ' ' || c == '\t' || c == '\r' {
                 self.chars.next();
+            } else if c == '#' {
+                while self.next_if(|c| c != '\n').is_some() {}
             } else {
                 break;
             }
@@ -182,7 +576,7 @@ impl<'a> Lexer<'a> {
     fn peek_next(&mut self) -> Option<char> {
         let mut iter = self.chars.clone();
         iter.next();
-        iter.peek().copied()
+        iter.next()
     }
 }
 
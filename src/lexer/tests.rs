@@ -72,3 +72,238 @@ print(f"{x}")
         ]
     );
 }
+
+#[test]
+fn bytes_literal_tokens() {
+    let tokens = Lexer::new(r#"x = b"\x00\xffA\n\"é" + bytes"#).tokenize();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Ident("x".to_string()),
+            Token::Equal,
+            Token::Bytes(vec![0x00, 0xff, b'A', b'\n', b'"', 0xc3, 0xa9]),
+            Token::Plus,
+            Token::Ident("bytes".to_string()),
+            Token::EOF,
+        ]
+    );
+}
+
+#[test]
+fn float_literal_tokens() {
+    let tokens = Lexer::new("x = 2.75 + 10.0 + 7").tokenize();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Ident("x".to_string()),
+            Token::Equal,
+            Token::Float(2.75),
+            Token::Plus,
+            Token::Float(10.0),
+            Token::Plus,
+            Token::Int(7),
+            Token::EOF,
+        ]
+    );
+}
+
+#[test]
+fn exponent_float_literal_tokens() {
+    let tokens = Lexer::new("1e9 2.5e-3 1E+2 1_0e1_0 3 e1").tokenize();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Float(1e9),
+            Token::Float(2.5e-3),
+            Token::Float(100.0),
+            Token::Float(1e11),
+            Token::Int(3),
+            Token::Ident("e1".to_string()),
+            Token::EOF,
+        ]
+    );
+    for (src, message) in [
+        ("x = 1e999", "float literal 1e999 is out of range"),
+        ("x = 1_e5", "misplaced '_' in number literal 1_e5"),
+        ("x = 1e5_", "misplaced '_' in number literal 1e5_"),
+    ] {
+        let errors = Lexer::new(src).tokenize_spanned(&mut Diagnostics::new()).unwrap_err();
+        assert_eq!(errors[0].message, message);
+    }
+}
+
+#[test]
+fn comparison_tokens() {
+    let tokens = Lexer::new("a<b <= c>d >= 1").tokenize();
+    let ident = |name: &str| Token::Ident(name.to_string());
+    assert_eq!(
+        tokens,
+        vec![
+            ident("a"),
+            Token::Less,
+            ident("b"),
+            Token::LessEqual,
+            ident("c"),
+            Token::Greater,
+            ident("d"),
+            Token::GreaterEqual,
+            Token::Int(1),
+            Token::EOF,
+        ]
+    );
+}
+
+#[test]
+fn unknown_characters_are_errors() {
+    let errors = Lexer::new("x = 1\ny = 2 ; z = 3 $\nw = 4").tokenize_spanned(&mut Diagnostics::new()).unwrap_err();
+    let found: Vec<_> = errors.iter().map(|e| (e.message.as_str(), e.span.line, e.span.column, e.span.width)).collect();
+    // Lexing goes on past each one
+    assert_eq!(found, [("unknown character ';'", 2, 7, 1), ("unknown character '$'", 2, 15, 1)]);
+}
+
+#[test]
+fn comments_run_to_the_end_of_the_line() {
+    let src = "# setup\nx = 1 # one\n  # indented\ny = \"#not\" #\n";
+    let tokens = Lexer::new(src).tokenize();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Newline,
+            Token::Ident("x".to_string()),
+            Token::Equal,
+            Token::Int(1),
+            Token::Newline,
+            Token::Newline,
+            Token::Ident("y".to_string()),
+            Token::Equal,
+            Token::Str("#not".to_string()),
+            Token::Newline,
+            Token::EOF,
+        ]
+    );
+}
+
+#[test]
+fn indentation_opens_and_closes_blocks() {
+    let src = "  while x:\n    if y:\n\n      # note\n      a\n  b\n    c";
+    let tokens = Lexer::new(src).tokenize();
+    let ident = |name: &str| Token::Ident(name.to_string());
+    assert_eq!(
+        tokens,
+        vec![
+            ident("while"),
+            ident("x"),
+            Token::Colon,
+            Token::Newline,
+            Token::Indent,
+            ident("if"),
+            ident("y"),
+            Token::Colon,
+            Token::Newline,
+            Token::Newline,
+            Token::Newline,
+            Token::Indent,
+            ident("a"),
+            Token::Newline,
+            Token::Dedent,
+            Token::Dedent,
+            ident("b"),
+            Token::Newline,
+            Token::Indent,
+            ident("c"),
+            Token::Dedent,
+            Token::EOF,
+        ]
+    );
+}
+
+#[test]
+fn bad_escapes_are_errors_with_spans() {
+    let errors = Lexer::new("x = 1\ny = b\"\\xZZ\"").tokenize_spanned(&mut Diagnostics::new()).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].span.line, 2);
+    assert!(errors[0].span.column > 0);
+}
+
+#[test]
+fn remainder_and_power_operators() {
+    let tokens = Lexer::new("a % 2 ** b").tokenize();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Ident("a".to_string()),
+            Token::Percent,
+            Token::Int(2),
+            Token::StarStar,
+            Token::Ident("b".to_string()),
+            Token::EOF,
+        ]
+    );
+}
+
+#[test]
+fn bitwise_operators() {
+    let tokens = Lexer::new("~a & b | c ^ d << 1 >> 2 < e").tokenize();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Tilde,
+            Token::Ident("a".to_string()),
+            Token::Ampersand,
+            Token::Ident("b".to_string()),
+            Token::Pipe,
+            Token::Ident("c".to_string()),
+            Token::Caret,
+            Token::Ident("d".to_string()),
+            Token::LessLess,
+            Token::Int(1),
+            Token::GreaterGreater,
+            Token::Int(2),
+            Token::Less,
+            Token::Ident("e".to_string()),
+            Token::EOF,
+        ]
+    );
+}
+
+#[test]
+fn radix_literals_and_digit_underscores() {
+    let tokens = Lexer::new("0x1F 0b1010 0o77 1_000_000 0XfF 2_5.0_5 0").tokenize();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Int(31),
+            Token::Int(10),
+            Token::Int(63),
+            Token::Int(1_000_000),
+            Token::Int(255),
+            Token::Float(25.05),
+            Token::Int(0),
+            Token::EOF,
+        ]
+    );
+    assert_eq!(Lexer::new("0x7fff_ffff_ffff_ffff").tokenize()[0], Token::Int(i64::MAX));
+}
+
+#[test]
+fn malformed_number_literals_are_errors() {
+    for (src, message) in [
+        ("x = 0x", "missing digits after 0x"),
+        ("x = 0x1G", "invalid digit 'G' in hex literal 0x1G"),
+        ("x = 0b102", "invalid digit '2' in binary literal 0b102"),
+        ("x = 0o8", "invalid digit '8' in octal literal 0o8"),
+        ("x = 1__0", "misplaced '_' in number literal 1__0"),
+        ("x = 10_", "misplaced '_' in number literal 10_"),
+        ("x = 0x_1", "misplaced '_' in number literal 0x_1"),
+        ("x = 1_.5", "misplaced '_' in number literal 1_.5"),
+        ("x = 1e", "malformed float literal 1e"),
+        ("x = 2.5E+", "malformed float literal 2.5E+"),
+        ("x = 1e-", "malformed float literal 1e-"),
+        ("x = 9223372036854775808", "integer literal 9223372036854775808 is too large"),
+        ("x = 0x1_0000_0000_0000_0000", "integer literal 0x1_0000_0000_0000_0000 is too large"),
+    ] {
+        let errors = Lexer::new(src).tokenize_spanned(&mut Diagnostics::new()).unwrap_err();
+        assert_eq!(errors[0].message, message);
+        assert_eq!((errors[0].span.column, errors[0].span.width), (5, src.len() as u32 - 4));
+    }
+}
@@ -1,5 +1,35 @@
+#[cfg(feature = "stdlib")]
+pub mod builtins;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "frontend")]
 pub mod codegen;
+#[cfg(feature = "stdlib")]
+pub mod datetime;
+#[cfg(feature = "frontend")]
+pub mod diagnostics;
+#[cfg(all(feature = "frontend", feature = "stdlib"))]
+pub mod difftrace;
+#[cfg(all(feature = "frontend", feature = "stdlib"))]
+pub mod engine;
+#[cfg(feature = "frontend")]
+pub mod frontend;
+#[cfg(feature = "frontend")]
 pub mod lexer;
+#[cfg(feature = "frontend")]
+pub mod modules;
+#[cfg(feature = "frontend")]
 pub mod parser;
+#[cfg(feature = "pyo3")]
+pub mod python;
+#[cfg(all(feature = "frontend", feature = "stdlib"))]
+pub mod repl;
+#[cfg(all(feature = "frontend", feature = "stdlib"))]
+pub mod rules;
+#[cfg(all(feature = "frontend", feature = "stdlib"))]
+pub mod scheduler;
+#[cfg(all(feature = "frontend", feature = "stdlib"))]
+pub mod template;
 pub mod vm;
+#[cfg(feature = "stdlib")]
 pub mod write;
@@ -1,5 +1,4 @@
-use kayton::write;
-
 fn main() {
-    write::println_to_console(b"Hello, World!\n");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(kayton::cli::main(&args));
 }
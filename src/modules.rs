@@ -0,0 +1,218 @@
+//! Modules for `import name`: the resolver finds `name.kay` in a list of
+//! directories, and `load_imports` parses every module a program imports,
+//! directly or through other modules, before codegen. Codegen then compiles
+//! each module's code once, where it is first imported, into the same
+//! program; its globals live in the VM's `GlobalVars` as `name.x`, which is
+//! also how importers read them. A module's functions, constants and enums
+//! stay private to it.
+//!
+//! Search paths start empty, so a VM compiling untrusted source reads no
+//! files unless its host adds a directory; `kayton run` adds the script's.
+
+use crate::diagnostics::Diagnostics;
+use crate::frontend::{CompileBudget, Frontend};
+use crate::parser::rewrite::{rewrite_program, Rewriter};
+use crate::parser::{nodes, Node, Stmt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// File extension of module sources
+pub const MODULE_EXTENSION: &str = "kay";
+
+/// Where `import name` looks for `name.kay`
+#[derive(Debug, Clone, Default)]
+pub struct ModuleResolver {
+    /// Directories searched in order; the first match wins
+    pub search_paths: Vec<PathBuf>,
+}
+
+impl ModuleResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also search `dir`, after the directories added before it
+    pub fn add_path(&mut self, dir: impl Into<PathBuf>) {
+        self.search_paths.push(dir.into());
+    }
+
+    /// The file module `name` is loaded from
+    pub fn resolve(&self, name: &str) -> Option<PathBuf> {
+        self.search_paths
+            .iter()
+            .map(|dir| dir.join(name).with_extension(MODULE_EXTENSION))
+            .find(|path| path.is_file())
+    }
+}
+
+/// Why `load_imports` failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportError {
+    /// The module imported by the program whose loading failed, possibly
+    /// because of a module it imports in turn
+    pub import: String,
+    pub message: String,
+}
+
+/// A parsed module, ready for codegen
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub name: String,
+    pub path: PathBuf,
+    pub stmts: Vec<Stmt>,
+}
+
+/// Names `stmts` import, anywhere in them, in order of appearance
+pub fn imports(stmts: &[Stmt]) -> Vec<&str> {
+    nodes(stmts)
+        .into_iter()
+        .filter_map(|node| match node {
+            Node::Stmt(Stmt::Import(name)) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parse the modules `stmts` imports, and theirs, with `frontend` and
+/// `rewriters`. Each is loaded once; a module importing itself, directly or
+/// not, is an error.
+pub fn load_imports(
+    stmts: &[Stmt],
+    resolver: &ModuleResolver,
+    frontend: &dyn Frontend,
+    rewriters: &[Arc<dyn Rewriter>],
+    budget: &CompileBudget,
+) -> Result<Vec<Module>, ImportError> {
+    let mut loader = Loader {
+        resolver,
+        frontend,
+        rewriters,
+        budget,
+        loaded: Vec::new(),
+        stack: Vec::new(),
+    };
+    for name in imports(stmts) {
+        loader.load(name).map_err(|message| ImportError {
+            import: name.to_string(),
+            message,
+        })?;
+    }
+    Ok(loader.loaded)
+}
+
+struct Loader<'a> {
+    resolver: &'a ModuleResolver,
+    frontend: &'a dyn Frontend,
+    rewriters: &'a [Arc<dyn Rewriter>],
+    budget: &'a CompileBudget,
+    loaded: Vec<Module>,
+    /// Modules being loaded, importers first
+    stack: Vec<String>,
+}
+
+impl Loader<'_> {
+    fn load(&mut self, name: &str) -> Result<(), String> {
+        if self.stack.iter().any(|loading| loading == name) {
+            let cycle = [self.stack.as_slice(), &[name.to_string()]].concat();
+            return Err(format!("circular import: {}", cycle.join(" -> ")));
+        }
+        if self.loaded.iter().any(|module| module.name == name) {
+            return Ok(());
+        }
+        let Some(path) = self.resolver.resolve(name) else {
+            return Err(not_found(name, &self.resolver.search_paths));
+        };
+        let src = std::fs::read_to_string(&path)
+            .map_err(|e| format!("module '{}': {}: {}", name, path.display(), e))?;
+        let mut diagnostics = Diagnostics::new();
+        let ast = self
+            .frontend
+            .parse_with_diagnostics(&src, self.budget, &mut diagnostics)
+            .map_err(|message| match diagnostics.errors().next() {
+                Some(error) if error.line > 0 => {
+                    format!("in module '{}', line {}: {}", name, error.line, error.message)
+                }
+                _ => format!("in module '{}': {}", name, message),
+            })?;
+        let (stmts, _) = rewrite_program(ast.stmts, ast.lines, self.rewriters);
+
+        self.stack.push(name.to_string());
+        for import in imports(&stmts) {
+            self.load(import)?;
+        }
+        self.stack.pop();
+        self.loaded.push(Module {
+            name: name.to_string(),
+            path,
+            stmts,
+        });
+        Ok(())
+    }
+}
+
+fn not_found(name: &str, search_paths: &[PathBuf]) -> String {
+    let file = Path::new(name).with_extension(MODULE_EXTENSION);
+    if search_paths.is_empty() {
+        return format!("module '{}' not found: no module search paths are set", name);
+    }
+    let dirs: Vec<_> = search_paths.iter().map(|dir| dir.display().to_string()).collect();
+    format!("module '{}' not found: no {} in {}", name, file.display(), dirs.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::KaytonSyntax;
+
+    /// A fresh directory holding `files`
+    fn module_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kayton_modules_{}", test));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, src) in files {
+            std::fs::write(dir.join(name), src).unwrap();
+        }
+        dir
+    }
+
+    fn load(src: &str, resolver: &ModuleResolver) -> Result<Vec<Module>, ImportError> {
+        let stmts = KaytonSyntax.parse(src).unwrap().stmts;
+        load_imports(&stmts, resolver, &KaytonSyntax, &[], &CompileBudget::default())
+    }
+
+    #[test]
+    fn modules_load_once_after_their_imports() {
+        let dir = module_dir(
+            "order",
+            &[("a.kay", "import b\nimport c"), ("b.kay", "import c"), ("c.kay", "x = 1")],
+        );
+        let mut resolver = ModuleResolver::new();
+        resolver.add_path(dir.join("missing"));
+        resolver.add_path(&dir);
+        let modules = load("import a\nif 1:\n  import b\nend", &resolver).unwrap();
+        let names: Vec<_> = modules.iter().map(|module| module.name.as_str()).collect();
+        assert_eq!(names, ["c", "b", "a"]);
+        assert_eq!(modules[0].path, dir.join("c.kay"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn import_errors_name_the_failing_import() {
+        let dir = module_dir(
+            "errors",
+            &[("a.kay", "import b"), ("b.kay", "import a"), ("bad.kay", "x = 1\ny = (")],
+        );
+        let err = load("import a", &ModuleResolver::new()).unwrap_err();
+        assert_eq!(err.message, "module 'a' not found: no module search paths are set");
+        let mut resolver = ModuleResolver::new();
+        resolver.add_path(&dir);
+        let err = load("import a", &resolver).unwrap_err();
+        assert_eq!((err.import.as_str(), err.message.as_str()), ("a", "circular import: a -> b -> a"));
+        let err = load("import bad", &resolver).unwrap_err();
+        assert!(err.message.starts_with("in module 'bad', line 2: "), "{}", err.message);
+        let err = load("import nope", &resolver).unwrap_err();
+        let expected = format!("module 'nope' not found: no nope.kay in {}", dir.display());
+        assert_eq!(err.message, expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
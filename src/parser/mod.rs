@@ -1,15 +1,79 @@
-use crate::lexer::{FStringPart, Lexer, Token};
+use crate::diagnostics::Diagnostics;
+use crate::frontend::{CompileBudget, LimitExceeded};
+use crate::lexer::{FStringPart, Lexer, Span, Token};
+use std::fmt;
+
+pub mod rewrite;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
-    Assign { name: String, expr: Expr },
+    /// `name = expr`, or `name: type = expr` fixing the variable's type
+    Assign {
+        name: String,
+        annotation: Option<String>,
+        expr: Expr,
+    },
     ExprStmt(Expr),
+    /// `if cond:` ... `else:` ... `end`; an `elif` is an `If` that makes up
+    /// the whole of `otherwise`
+    If {
+        cond: Expr,
+        then: Vec<Stmt>,
+        otherwise: Vec<Stmt>,
+    },
+    /// `while cond:` ... `end`
+    While { cond: Expr, body: Vec<Stmt> },
+    /// `def name(params) -> type:` ... `end`; each parameter may be
+    /// annotated (`a: int`), and `annotations` has one entry per parameter
+    FuncDef {
+        name: String,
+        params: Vec<String>,
+        annotations: Vec<Option<String>>,
+        returns: Option<String>,
+        body: Vec<Stmt>,
+    },
+    /// `target[index] = expr`
+    SetIndex { target: Expr, index: Expr, expr: Expr },
+    /// `const NAME = expr`: a name for a value known at compile time
+    Const { name: String, expr: Expr },
+    /// `enum Name: A, B, C`: constants 0, 1, 2 with the enum's name
+    Enum { name: String, variants: Vec<String> },
+    /// `return` or `return expr`
+    Return(Option<Expr>),
+    /// `break`: leave the innermost loop
+    Break,
+    /// `continue`: go on to the innermost loop's next test
+    Continue,
+    /// `match subject:` with `case pattern:` arms, through `end`; the first
+    /// arm whose pattern matches runs
+    Match { subject: Expr, arms: Vec<MatchArm> },
+    /// `import name`: run module `name.kay` once and read its globals as
+    /// `name.x`
+    Import(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Vec<Stmt>,
+}
+
+/// What a `case` tests the subject of a `match` against
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `_`: matches anything
+    Wildcard,
+    /// An int or str literal, or an enum variant (`Color.RED`): matches an
+    /// equal subject
+    Value(Expr),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Int(i64),
+    Float(f64),
     Str(String),
+    Bytes(Vec<u8>),
     Ident(String),
     Binary {
         left: Box<Expr>,
@@ -21,11 +85,59 @@ pub enum Expr {
         args: Vec<Expr>,
     },
     InterpolatedString(Vec<StringPart>),
+    /// `[a, b, c]`
+    List(Vec<Expr>),
+    /// `{key: value, ...}`
+    Dict(Vec<(Expr, Expr)>),
+    /// `target[index]`
+    Index {
+        target: Box<Expr>,
+        index: Box<Expr>,
+    },
+    /// `target.name`
+    Attribute { target: Box<Expr>, name: String },
+    /// `~operand`
+    Unary { op: UnaryOp, operand: Box<Expr> },
+    /// `then if cond else otherwise`
+    Ternary {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+    },
+    /// `left < a <= b ...`: two or more comparisons, each operand evaluated
+    /// once; true when all of them are
+    Compare {
+        left: Box<Expr>,
+        rest: Vec<(BinOp, Expr)>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum BinOp {
     Add,
+    /// `%`: remainder with the sign of the divisor, ints only
+    Mod,
+    /// `**`: power; binds tighter than `%` and groups right to left
+    Pow,
+    /// `&`, `|` and `^` on ints; they bind looser than shifts, and all of
+    /// them looser than `+`, as in Python
+    BitAnd,
+    BitOr,
+    BitXor,
+    /// `<<` and `>>` on ints; `>>` keeps the sign
+    Shl,
+    Shr,
+    /// `<`; this and the other comparisons give 1 or 0
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnaryOp {
+    /// `~`: every bit of an int flipped
+    BitNot,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -34,65 +146,657 @@ pub enum StringPart {
     Expr(Box<Expr>),
 }
 
+/// Why a source could not be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// The token the parser stopped at
+    pub found: Token,
+    /// What would have been accepted in its place; empty when the error is
+    /// not about a missing token
+    pub expected: Vec<String>,
+    pub span: Span,
+    /// Set when parsing went over a `CompileLimits` bound rather than
+    /// meeting malformed input
+    pub limit: Option<LimitExceeded>,
+}
+
+impl ParseError {
+    pub fn new(message: String, found: Token, span: Span) -> Self {
+        Self {
+            message,
+            found,
+            expected: Vec::new(),
+            span,
+            limit: None,
+        }
+    }
+
+    fn expecting(mut self: Box<Self>, expected: &[&str]) -> Box<Self> {
+        self.expected = expected.iter().map(|e| e.to_string()).collect();
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Boxed so the recursive descent's frames stay small
+type ParseResult<T> = Result<T, Box<ParseError>>;
+
+/// Lex and parse a whole program, reporting every statement that does not
+/// parse
+pub fn parse_source(src: &str) -> Result<Vec<Stmt>, Vec<ParseError>> {
+    let (tokens, spans) = Lexer::new(src).tokenize_spanned(&mut Diagnostics::new())?;
+    Parser::new(tokens).with_spans(spans).parse()
+}
+
 pub struct Parser {
     tokens: Vec<Token>,
+    /// Span of each token, when the lexer's were passed in
+    spans: Vec<Span>,
     pos: usize,
+    /// 1-based line of the next token
+    line: u32,
+    /// `Indent`s consumed and not yet closed by a `Dedent`
+    indent: usize,
+    stmt_lines: Vec<u32>,
+    /// Expressions and blocks being parsed, counting those enclosing an
+    /// embedded f-string expression
+    depth: usize,
+    /// Levels entered since the compile deadline was last checked
+    since_time_check: usize,
+    budget: CompileBudget,
 }
 
+/// Levels entered between checks of the compile deadline
+const TIME_CHECK_INTERVAL: usize = 256;
+
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, pos: 0 }
+        Self {
+            tokens,
+            spans: Vec::new(),
+            pos: 0,
+            line: 1,
+            indent: 0,
+            stmt_lines: Vec::new(),
+            depth: 0,
+            since_time_check: 0,
+            budget: CompileBudget::default(),
+        }
+    }
+
+    /// Enforce `budget` instead of the default limits
+    pub fn with_budget(mut self, budget: CompileBudget) -> Self {
+        self.budget = budget;
+        self
     }
 
-    pub fn parse_program(&mut self) -> Vec<Stmt> {
+    /// Spans of the tokens, from `Lexer::tokenize_spanned`, for errors to
+    /// point at; without them errors only know the line
+    pub fn with_spans(mut self, spans: Vec<Span>) -> Self {
+        self.spans = spans;
+        self
+    }
+
+    fn enter(&mut self) -> ParseResult<()> {
+        self.depth += 1;
+        self.since_time_check += 1;
+        let mut checked = self.budget.check_depth(self.depth);
+        if self.since_time_check >= TIME_CHECK_INTERVAL {
+            self.since_time_check = 0;
+            checked = checked.and_then(|()| self.budget.check_time());
+        }
+        checked.map_err(|limit| {
+            let mut error = self.error_here(limit.to_string());
+            error.limit = Some(limit);
+            error
+        })
+    }
+
+    /// Parse the whole input. After an error, parsing resumes at the next
+    /// top-level statement, so every error is reported, though one mistake
+    /// can cause more than one. Going over the budget ends parsing.
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
         let mut stmts = Vec::new();
+        let mut errors = Vec::new();
         self.skip_newlines();
         while !self.is_at_end() {
-            if let Some(stmt) = self.parse_stmt() {
-                stmts.push(stmt);
+            let line = self.line;
+            match self.parse_stmt() {
+                Ok(Some(stmt)) => {
+                    stmts.push(stmt);
+                    self.stmt_lines.push(line);
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    let limit = error.limit.is_some();
+                    errors.push(*error);
+                    if limit {
+                        break;
+                    }
+                    self.synchronize();
+                }
             }
             self.skip_newlines();
         }
-        stmts
+        if errors.is_empty() { Ok(stmts) } else { Err(errors) }
     }
 
-    pub fn parse_expr(&mut self) -> Expr {
-        let mut left = self.parse_primary();
-        while matches!(self.peek(), Token::Plus) {
+    /// Skip to the start of the next top-level statement
+    fn synchronize(&mut self) {
+        self.depth = 0;
+        let mut at_line_start = false;
+        loop {
+            match self.peek() {
+                Token::EOF => return,
+                Token::Newline | Token::Indent | Token::Dedent => {}
+                // The `end` of a block the error was in
+                Token::Ident(word) if matches!(word.as_str(), "end" | "elif" | "else" | "case") => {}
+                _ if at_line_start && self.indent == 0 => return,
+                _ => {}
+            }
+            let tok = self.advance();
+            at_line_start = matches!(tok, Token::Newline | Token::Dedent)
+                || (at_line_start && tok == Token::Indent);
+        }
+    }
+
+    /// 1-based line of the next token; after an error, the line it happened on
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// Source line of each statement returned by `parse`
+    pub fn stmt_lines(&self) -> &[u32] {
+        &self.stmt_lines
+    }
+
+    /// Parse input that holds exactly one expression, such as a formula
+    pub fn parse_expression(&mut self) -> Result<Expr, ParseError> {
+        self.standalone_expr().map_err(|error| *error)
+    }
+
+    fn standalone_expr(&mut self) -> ParseResult<Expr> {
+        self.skip_newlines();
+        let expr = self.expr()?;
+        self.skip_newlines();
+        if !self.is_at_end() {
+            let message = format!("Unexpected token {:?} after expression", self.peek());
+            return Err(self.error_here(message));
+        }
+        Ok(expr)
+    }
+
+    fn expr(&mut self) -> ParseResult<Expr> {
+        self.enter()?;
+        let expr = self.parse_ternary()?;
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn parse_ternary(&mut self) -> ParseResult<Expr> {
+        let then = self.parse_comparison()?;
+        if !matches!(self.peek(), Token::Ident(word) if word == "if") {
+            return Ok(then);
+        }
+        self.advance();
+        let cond = self.parse_comparison()?;
+        self.expect(Token::Ident("else".to_string()))?;
+        // Right-associative: `a if x else b if y else c`
+        let otherwise = self.expr()?;
+        Ok(Expr::Ternary {
+            cond: Box::new(cond),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        })
+    }
+
+    /// `a < b` and friends bind looser than `|`. They chain as in Python:
+    /// `0 < x < 10` is an `Expr::Compare`
+    fn parse_comparison(&mut self) -> ParseResult<Expr> {
+        let left = self.parse_binary(1)?;
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.peek() {
+                Token::Less => BinOp::Lt,
+                Token::LessEqual => BinOp::Le,
+                Token::Greater => BinOp::Gt,
+                Token::GreaterEqual => BinOp::Ge,
+                _ => break,
+            };
+            self.advance();
+            rest.push((op, self.parse_binary(1)?));
+        }
+        if rest.len() > 1 {
+            return Ok(Expr::Compare {
+                left: Box::new(left),
+                rest,
+            });
+        }
+        Ok(match rest.pop() {
+            Some((op, right)) => Expr::Binary {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            },
+            None => left,
+        })
+    }
+
+    /// Operands joined by the operators from `|` to `%` whose binding
+    /// power is at least `min_power`, grouped from the left. Precedence
+    /// climbing keeps an operand a few stack frames deep however many
+    /// levels there are, and a chain such as `a + b + c` is parsed in a
+    /// loop, so neither counts as nesting
+    fn parse_binary(&mut self, min_power: u8) -> ParseResult<Expr> {
+        let mut left = self.parse_unary()?;
+        while let Some((op, power)) = binary_op(&self.peek()).filter(|(_, power)| *power >= min_power) {
             self.advance();
-            let right = self.parse_primary();
+            let right = self.parse_binary(power + 1)?;
             left = Expr::Binary {
                 left: Box::new(left),
-                op: BinOp::Add,
+                op,
                 right: Box::new(right),
             };
         }
-        left
+        Ok(left)
     }
 
-    fn parse_stmt(&mut self) -> Option<Stmt> {
+    /// `~x`, looser than `**` as in Python: `~2 ** 2` is `~(2 ** 2)`
+    fn parse_unary(&mut self) -> ParseResult<Expr> {
+        if self.peek() != Token::Tilde {
+            return self.parse_power();
+        }
+        self.advance();
+        self.enter()?;
+        let operand = self.parse_unary()?;
+        self.depth -= 1;
+        Ok(Expr::Unary {
+            op: UnaryOp::BitNot,
+            operand: Box::new(operand),
+        })
+    }
+
+    /// `a ** b ** c` is `a ** (b ** c)`, as in Python; the exponent may be
+    /// `~x`
+    fn parse_power(&mut self) -> ParseResult<Expr> {
+        let base = self.parse_primary()?;
+        if self.peek() != Token::StarStar {
+            return Ok(base);
+        }
+        self.advance();
+        self.enter()?;
+        let exponent = self.parse_unary()?;
+        self.depth -= 1;
+        Ok(Expr::Binary {
+            left: Box::new(base),
+            op: BinOp::Pow,
+            right: Box::new(exponent),
+        })
+    }
+
+    fn parse_stmt(&mut self) -> ParseResult<Option<Stmt>> {
         if self.is_at_end() {
-            return None;
+            return Ok(None);
         }
-        if let Token::Ident(name) = self.peek().clone() {
-            if self.peek_next_is(Token::Equal) {
-                self.advance(); // ident
-                self.advance(); // '='
-                let expr = self.parse_expr();
-                return Some(Stmt::Assign { name, expr });
+        if self.peek() == Token::Indent {
+            return Err(self.error_here("unexpected indent".to_string()));
+        }
+        if let Token::Ident(word) = self.peek() {
+            match word.as_str() {
+                "if" => {
+                    self.advance();
+                    return self.parse_if().map(Some);
+                }
+                "while" => {
+                    self.advance();
+                    let cond = self.expr()?;
+                    self.expect(Token::Colon)?;
+                    let (body, indented) = self.parse_block()?;
+                    self.expect_end(indented)?;
+                    return Ok(Some(Stmt::While { cond, body }));
+                }
+                "def" => {
+                    self.advance();
+                    return self.parse_def().map(Some);
+                }
+                "return" => {
+                    self.advance();
+                    let value = match self.peek() {
+                        Token::Newline | Token::Dedent | Token::EOF => None,
+                        _ => Some(self.expr()?),
+                    };
+                    return Ok(Some(Stmt::Return(value)));
+                }
+                "const" => {
+                    self.advance();
+                    let name = self.parse_name("a constant name")?;
+                    self.expect(Token::Equal)?;
+                    let expr = self.expr()?;
+                    return Ok(Some(Stmt::Const { name, expr }));
+                }
+                "enum" => {
+                    self.advance();
+                    return self.parse_enum().map(Some);
+                }
+                "break" => {
+                    self.advance();
+                    return Ok(Some(Stmt::Break));
+                }
+                "continue" => {
+                    self.advance();
+                    return Ok(Some(Stmt::Continue));
+                }
+                "match" => {
+                    self.advance();
+                    return self.parse_match().map(Some);
+                }
+                "import" => {
+                    self.advance();
+                    let name = self.parse_name("a module name")?;
+                    return Ok(Some(Stmt::Import(name)));
+                }
+                "elif" | "else" => {
+                    let message = format!("'{}' without a matching 'if'", word);
+                    return Err(self.error_here(message));
+                }
+                "case" => {
+                    let message = "'case' outside a 'match'".to_string();
+                    return Err(self.error_here(message));
+                }
+                "end" => {
+                    let message = "'end' without a matching 'if', 'while' or 'def'".to_string();
+                    return Err(self.error_here(message));
+                }
+                _ => {}
             }
         }
-        let expr = self.parse_expr();
-        Some(Stmt::ExprStmt(expr))
+        if let Token::Ident(name) = self.peek().clone()
+            && self.peek_next_is(Token::Equal)
+        {
+            self.advance(); // ident
+            self.advance(); // '='
+            let expr = self.expr()?;
+            return Ok(Some(Stmt::Assign {
+                name,
+                annotation: None,
+                expr,
+            }));
+        }
+        if let Token::Ident(name) = self.peek().clone()
+            && self.peek_next_is(Token::Colon)
+        {
+            self.advance(); // ident
+            self.advance(); // ':'
+            let annotation = Some(self.parse_name("a type name")?);
+            self.expect(Token::Equal)?;
+            let expr = self.expr()?;
+            return Ok(Some(Stmt::Assign {
+                name,
+                annotation,
+                expr,
+            }));
+        }
+        let expr = self.expr()?;
+        if self.peek() == Token::Equal
+            && let Expr::Index { target, index } = expr
+        {
+            self.advance(); // '='
+            let expr = self.expr()?;
+            return Ok(Some(Stmt::SetIndex {
+                target: *target,
+                index: *index,
+                expr,
+            }));
+        }
+        Ok(Some(Stmt::ExprStmt(expr)))
     }
 
-    fn parse_primary(&mut self) -> Expr {
+    /// An identifier naming what is being declared, `what` for the error
+    fn parse_name(&mut self, what: &str) -> ParseResult<String> {
         match self.advance() {
+            Token::Ident(name) => Ok(name),
+            other => {
+                let message = format!("expected {}, found {:?}", what, other);
+                Err(self.error_before(message).expecting(&[what]))
+            }
+        }
+    }
+
+    /// The rest of an `enum`: its name and comma-separated variants
+    fn parse_enum(&mut self) -> ParseResult<Stmt> {
+        let name = self.parse_name("an enum name")?;
+        self.expect(Token::Colon)?;
+        let mut variants = Vec::new();
+        loop {
+            let variant = self.parse_name("a variant name")?;
+            if variants.contains(&variant) {
+                let message = format!("duplicate variant '{}' in '{}'", variant, name);
+                return Err(self.error_before(message));
+            }
+            variants.push(variant);
+            if self.peek() != Token::Comma {
+                return Ok(Stmt::Enum { name, variants });
+            }
+            self.advance();
+        }
+    }
+
+    /// The rest of a `def`: name, parameter list and body through `end`
+    fn parse_def(&mut self) -> ParseResult<Stmt> {
+        let name = match self.advance() {
+            Token::Ident(name) => name,
+            other => {
+                let message = format!("expected a function name, found {:?}", other);
+                return Err(self.error_before(message).expecting(&["function name"]));
+            }
+        };
+        self.expect(Token::LParen)?;
+        let mut params = Vec::new();
+        let mut annotations = Vec::new();
+        while !matches!(self.peek(), Token::RParen) {
+            if !params.is_empty() {
+                self.expect(Token::Comma)?;
+            }
+            match self.advance() {
+                Token::Ident(param) if !params.contains(&param) => {
+                    params.push(param);
+                    let annotation = if self.peek() == Token::Colon {
+                        self.advance();
+                        Some(self.parse_name("a type name")?)
+                    } else {
+                        None
+                    };
+                    annotations.push(annotation);
+                }
+                Token::Ident(param) => {
+                    let message = format!("duplicate parameter '{}' in '{}'", param, name);
+                    return Err(self.error_before(message));
+                }
+                other => {
+                    let message = format!("expected a parameter name, found {:?}", other);
+                    return Err(self.error_before(message).expecting(&["parameter name"]));
+                }
+            }
+        }
+        self.advance(); // ')'
+        let returns = if self.peek() == Token::Arrow {
+            self.advance();
+            Some(self.parse_name("a type name")?)
+        } else {
+            None
+        };
+        self.expect(Token::Colon)?;
+        let (body, indented) = self.parse_block()?;
+        self.expect_end(indented)?;
+        Ok(Stmt::FuncDef {
+            name,
+            params,
+            annotations,
+            returns,
+            body,
+        })
+    }
+
+    /// The rest of an `if` or `elif`, through the `end` (or the dedent)
+    /// closing the chain
+    fn parse_if(&mut self) -> ParseResult<Stmt> {
+        let cond = self.expr()?;
+        self.expect(Token::Colon)?;
+        let (then, indented) = self.parse_block()?;
+        let otherwise = match self.peek() {
+            Token::Ident(word) if word == "elif" => {
+                self.advance();
+                vec![self.parse_if()?]
+            }
+            Token::Ident(word) if word == "else" => {
+                self.advance();
+                self.expect(Token::Colon)?;
+                let (otherwise, indented) = self.parse_block()?;
+                self.expect_end(indented)?;
+                otherwise
+            }
+            _ => {
+                self.expect_end(indented)?;
+                Vec::new()
+            }
+        };
+        Ok(Stmt::If {
+            cond,
+            then,
+            otherwise,
+        })
+    }
+
+    /// The rest of a `match`: the subject, then `case` arms through `end`.
+    /// The arms may be indented under the `match`, like a block.
+    fn parse_match(&mut self) -> ParseResult<Stmt> {
+        let subject = self.expr()?;
+        self.expect(Token::Colon)?;
+        self.skip_newlines();
+        let indented = self.peek() == Token::Indent;
+        if indented {
+            self.advance();
+        }
+        let mut arms = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                Token::Dedent if indented => {
+                    self.advance();
+                    break;
+                }
+                Token::Ident(word) if word == "end" && !indented => break,
+                Token::Ident(word) if word == "case" => {
+                    self.advance();
+                    let pattern = self.parse_pattern()?;
+                    self.expect(Token::Colon)?;
+                    let (body, _) = self.parse_block()?;
+                    arms.push(MatchArm { pattern, body });
+                }
+                other => {
+                    let message = format!("expected 'case', found {:?}", other);
+                    return Err(self.error_here(message).expecting(&["'case'"]));
+                }
+            }
+        }
+        if arms.is_empty() {
+            return Err(self.error_here("'match' needs at least one 'case'".to_string()));
+        }
+        self.expect_end(indented)?;
+        Ok(Stmt::Match { subject, arms })
+    }
+
+    /// `_`, an int or str literal, or a dotted enum variant. A bare name is
+    /// left for capture patterns, as in Python.
+    fn parse_pattern(&mut self) -> ParseResult<Pattern> {
+        match self.advance() {
+            Token::Ident(name) if name == "_" => Ok(Pattern::Wildcard),
+            Token::Int(n) => Ok(Pattern::Value(Expr::Int(n))),
+            Token::Str(s) => Ok(Pattern::Value(Expr::Str(s))),
+            Token::Ident(name) if self.peek() == Token::Dot => {
+                self.advance();
+                let variant = self.parse_name("a variant name")?;
+                Ok(Pattern::Value(Expr::Attribute {
+                    target: Box::new(Expr::Ident(name)),
+                    name: variant,
+                }))
+            }
+            Token::Ident(name) => {
+                let message = format!(
+                    "capture patterns are not supported; match a literal, a variant such as \
+                     Enum.{} or _",
+                    name
+                );
+                Err(self.error_before(message))
+            }
+            other => {
+                let message = format!("expected a pattern, found {:?}", other);
+                Err(self.error_before(message).expecting(&["pattern"]))
+            }
+        }
+    }
+
+    /// The statements of a block, and whether they were indented. An
+    /// indented block runs until the dedent after it; otherwise it runs up
+    /// to the `elif`, `else`, `case` or `end` that ends it, which is left
+    /// for the caller.
+    fn parse_block(&mut self) -> ParseResult<(Vec<Stmt>, bool)> {
+        self.enter()?;
+        self.skip_newlines();
+        let indented = self.peek() == Token::Indent;
+        if indented {
+            self.advance();
+        }
+        let mut stmts = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                Token::Dedent if indented => {
+                    self.advance();
+                    break;
+                }
+                Token::Ident(word)
+                    if !indented && matches!(word.as_str(), "elif" | "else" | "case" | "end") =>
+                {
+                    break;
+                }
+                Token::EOF => {
+                    let message = "expected 'end' to close the block".to_string();
+                    return Err(self.error_here(message).expecting(&["'end'"]));
+                }
+                _ => stmts.extend(self.parse_stmt()?),
+            }
+        }
+        self.depth -= 1;
+        Ok((stmts, indented))
+    }
+
+    /// The `end` closing a block, which an indented block may leave out
+    fn expect_end(&mut self, indented: bool) -> ParseResult<()> {
+        let end = Token::Ident("end".to_string());
+        if !indented || self.peek() == end {
+            self.expect(end)?;
+        }
+        Ok(())
+    }
+
+    fn parse_primary(&mut self) -> ParseResult<Expr> {
+        let expr = match self.advance() {
             Token::Int(n) => Expr::Int(n),
+            Token::Float(x) => Expr::Float(x),
             Token::Str(s) => Expr::Str(s),
+            Token::Bytes(b) => Expr::Bytes(b),
             Token::Ident(s) => {
                 let expr = Expr::Ident(s);
-                self.parse_call(expr)
+                self.parse_postfix(expr)?
             }
             Token::InterpolatedString(parts) => {
                 let mut ast_parts = Vec::new();
@@ -100,7 +804,7 @@ impl Parser {
                     match part {
                         FStringPart::Text(t) => ast_parts.push(StringPart::Text(t)),
                         FStringPart::Expr(src) => {
-                            let expr = parse_embedded_expr(&src);
+                            let expr = self.parse_embedded_expr(&src)?;
                             ast_parts.push(StringPart::Expr(Box::new(expr)));
                         }
                     }
@@ -108,37 +812,83 @@ impl Parser {
                 Expr::InterpolatedString(ast_parts)
             }
             Token::LParen => {
-                let expr = self.parse_expr();
-                self.expect(Token::RParen);
-                self.parse_call(expr)
+                let expr = self.expr()?;
+                self.expect(Token::RParen)?;
+                self.parse_postfix(expr)?
             }
-            other => panic!("Unexpected token {:?}", other),
-        }
+            Token::LBracket => {
+                let mut items = Vec::new();
+                while self.peek() != Token::RBracket {
+                    if !items.is_empty() {
+                        self.expect(Token::Comma)?;
+                    }
+                    items.push(self.expr()?);
+                }
+                self.advance(); // ']'
+                self.parse_postfix(Expr::List(items))?
+            }
+            Token::LBrace => {
+                let mut entries = Vec::new();
+                while self.peek() != Token::RBrace {
+                    if !entries.is_empty() {
+                        self.expect(Token::Comma)?;
+                    }
+                    let key = self.expr()?;
+                    self.expect(Token::Colon)?;
+                    entries.push((key, self.expr()?));
+                }
+                self.advance(); // '}'
+                self.parse_postfix(Expr::Dict(entries))?
+            }
+            other => {
+                let message = format!("Unexpected token {:?}", other);
+                return Err(self.error_before(message).expecting(&["expression"]));
+            }
+        };
+        Ok(expr)
     }
 
-    fn parse_call(&mut self, mut expr: Expr) -> Expr {
+    /// Calls, indexing and attributes after a primary expression: `f(x)[0]`,
+    /// `Color.name(c)`
+    fn parse_postfix(&mut self, mut expr: Expr) -> ParseResult<Expr> {
         loop {
             match self.peek() {
                 Token::LParen => {
                     self.advance(); // consume '('
                     let mut args = Vec::new();
                     if !matches!(self.peek(), Token::RParen) {
-                        args.push(self.parse_expr());
+                        args.push(self.expr()?);
                         while matches!(self.peek(), Token::Comma) {
                             self.advance();
-                            args.push(self.parse_expr());
+                            args.push(self.expr()?);
                         }
                     }
-                    self.expect(Token::RParen);
+                    self.expect(Token::RParen)?;
                     expr = Expr::Call {
                         func: Box::new(expr),
                         args,
                     };
                 }
-                _ => break,
+                Token::LBracket => {
+                    self.advance();
+                    let index = self.expr()?;
+                    self.expect(Token::RBracket)?;
+                    expr = Expr::Index {
+                        target: Box::new(expr),
+                        index: Box::new(index),
+                    };
+                }
+                Token::Dot => {
+                    self.advance();
+                    let name = self.parse_name("an attribute name")?;
+                    expr = Expr::Attribute {
+                        target: Box::new(expr),
+                        name,
+                    };
+                }
+                _ => return Ok(expr),
             }
         }
-        expr
     }
 
     fn skip_newlines(&mut self) {
@@ -147,11 +897,37 @@ impl Parser {
         }
     }
 
-    fn expect(&mut self, expected: Token) {
+    fn expect(&mut self, expected: Token) -> ParseResult<()> {
         let tok = self.advance();
         if tok != expected {
-            panic!("expected {:?}, found {:?}", expected, tok);
+            let message = format!("expected {:?}, found {:?}", expected, tok);
+            let expected = format!("{:?}", expected);
+            return Err(self.error_before(message).expecting(&[&expected]));
         }
+        Ok(())
+    }
+
+    /// An error at the next token
+    fn error_here(&self, message: String) -> Box<ParseError> {
+        self.error_at(self.pos, message)
+    }
+
+    /// An error at the token just consumed
+    fn error_before(&self, message: String) -> Box<ParseError> {
+        self.error_at(self.pos.saturating_sub(1), message)
+    }
+
+    fn error_at(&self, index: usize, message: String) -> Box<ParseError> {
+        let found = self.tokens.get(index).cloned().unwrap_or(Token::EOF);
+        Box::new(ParseError::new(message, found, self.span_at(index)))
+    }
+
+    fn span_at(&self, index: usize) -> Span {
+        self.spans.get(index).copied().unwrap_or(Span {
+            line: self.line,
+            column: 0,
+            width: 0,
+        })
     }
 
     fn peek(&self) -> Token {
@@ -162,13 +938,20 @@ impl Parser {
         self.tokens
             .get(self.pos + 1)
             .cloned()
-            .map_or(false, |t| t == expected)
+            .is_some_and(|t| t == expected)
     }
 
+    /// Consume the next token, keeping count of lines and indentation
     fn advance(&mut self) -> Token {
         let tok = self.peek();
         if !self.is_at_end() {
             self.pos += 1;
+            match tok {
+                Token::Newline => self.line += 1,
+                Token::Indent => self.indent += 1,
+                Token::Dedent => self.indent = self.indent.saturating_sub(1),
+                _ => {}
+            }
         }
         tok
     }
@@ -176,13 +959,198 @@ impl Parser {
     fn is_at_end(&self) -> bool {
         matches!(self.peek(), Token::EOF)
     }
+
+    /// Parse the expression of an f-string placeholder, within the limits
+    /// left to this parser. Errors name the placeholder and point at the
+    /// string holding it, the token just consumed.
+    fn parse_embedded_expr(&self, src: &str) -> ParseResult<Expr> {
+        let in_placeholder = |message: &str| format!("in f-string placeholder {{{}}}: {}", src, message);
+        if src.trim().is_empty() {
+            return Err(self.error_before(format!("empty f-string placeholder {{{}}}", src)));
+        }
+        let mut diagnostics = Diagnostics::new();
+        let tokens = match Lexer::new(src).tokenize_spanned(&mut diagnostics) {
+            Ok((tokens, _)) => tokens,
+            Err(errors) => return Err(self.error_before(in_placeholder(&errors[0].message))),
+        };
+        let mut parser = Parser::new(tokens).with_budget(self.budget);
+        parser.depth = self.depth;
+        parser.standalone_expr().map_err(|mut error| {
+            if error.limit.is_none() {
+                error.message = in_placeholder(&error.message);
+            }
+            error.span = self.span_at(self.pos - 1);
+            error
+        })
+    }
+}
+
+/// The operator `token` is among those looser than `~` and tighter than
+/// comparisons, with its binding power: `|` binds loosest, then `^`, `&`,
+/// shifts, `+` and `%`, as in Python
+fn binary_op(token: &Token) -> Option<(BinOp, u8)> {
+    Some(match token {
+        Token::Pipe => (BinOp::BitOr, 1),
+        Token::Caret => (BinOp::BitXor, 2),
+        Token::Ampersand => (BinOp::BitAnd, 3),
+        Token::LessLess => (BinOp::Shl, 4),
+        Token::GreaterGreater => (BinOp::Shr, 4),
+        Token::Plus => (BinOp::Add, 5),
+        Token::Percent => (BinOp::Mod, 6),
+        _ => return None,
+    })
+}
+
+/// Return the docstring of a body: a string literal as its first statement
+pub fn docstring(stmts: &[Stmt]) -> Option<&str> {
+    match stmts.first() {
+        Some(Stmt::ExprStmt(Expr::Str(doc))) => Some(doc),
+        _ => None,
+    }
 }
 
-fn parse_embedded_expr(src: &str) -> Expr {
-    let tokens = Lexer::new(src).tokenize();
-    let mut parser = Parser::new(tokens);
-    parser.parse_expr()
+/// The innermost left operand of a chain of `Expr::Binary` such as
+/// `a + b + c`, and each binary node with its right operand, innermost
+/// first (here `(a + b, b)` then `(a + b + c, c)`)
+pub fn left_spine(expr: &Expr) -> (&Expr, Vec<(&Expr, &Expr)>) {
+    let mut spine = Vec::new();
+    let mut node = expr;
+    while let Expr::Binary { left, right, .. } = node {
+        spine.push((node, &**right));
+        node = left;
+    }
+    spine.reverse();
+    (node, spine)
 }
 
+/// A statement or expression of a parsed program
+#[derive(Debug, Clone, Copy)]
+pub enum Node<'a> {
+    Stmt(&'a Stmt),
+    Expr(&'a Expr),
+}
+
+/// Every node of `stmts` in pre-order; a node's position in this list is its
+/// id in source maps
+pub fn nodes(stmts: &[Stmt]) -> Vec<Node<'_>> {
+    fn expr<'a>(e: &'a Expr, out: &mut Vec<Node<'a>>) {
+        out.push(Node::Expr(e));
+        match e {
+            Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Bytes(_) | Expr::Ident(_) => {}
+            Expr::Binary { .. } => {
+                // Down the left operands in a loop, so long chains don't
+                // recurse once per operator
+                let (leftmost, spine) = left_spine(e);
+                let inner = &spine[..spine.len() - 1];
+                out.extend(inner.iter().rev().map(|&(node, _)| Node::Expr(node)));
+                expr(leftmost, out);
+                for (_, right) in spine {
+                    expr(right, out);
+                }
+            }
+            Expr::Unary { operand, .. } => expr(operand, out),
+            Expr::Call { func, args } => {
+                expr(func, out);
+                for arg in args {
+                    expr(arg, out);
+                }
+            }
+            Expr::InterpolatedString(parts) => {
+                for part in parts {
+                    if let StringPart::Expr(e) = part {
+                        expr(e, out);
+                    }
+                }
+            }
+            Expr::Ternary {
+                cond,
+                then,
+                otherwise,
+            } => {
+                expr(cond, out);
+                expr(then, out);
+                expr(otherwise, out);
+            }
+            Expr::Compare { left, rest } => {
+                expr(left, out);
+                for (_, right) in rest {
+                    expr(right, out);
+                }
+            }
+            Expr::List(items) => {
+                for item in items {
+                    expr(item, out);
+                }
+            }
+            Expr::Dict(entries) => {
+                for (key, value) in entries {
+                    expr(key, out);
+                    expr(value, out);
+                }
+            }
+            Expr::Index { target, index } => {
+                expr(target, out);
+                expr(index, out);
+            }
+            Expr::Attribute { target, .. } => expr(target, out),
+        }
+    }
+    fn stmt<'a>(s: &'a Stmt, out: &mut Vec<Node<'a>>) {
+        out.push(Node::Stmt(s));
+        match s {
+            Stmt::Assign { expr: e, .. } | Stmt::ExprStmt(e) => expr(e, out),
+            Stmt::SetIndex {
+                target,
+                index,
+                expr: e,
+            } => {
+                expr(target, out);
+                expr(index, out);
+                expr(e, out);
+            }
+            Stmt::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                expr(cond, out);
+                for s in then.iter().chain(otherwise) {
+                    stmt(s, out);
+                }
+            }
+            Stmt::While { cond, body } => {
+                expr(cond, out);
+                for s in body {
+                    stmt(s, out);
+                }
+            }
+            Stmt::FuncDef { body, .. } => {
+                for s in body {
+                    stmt(s, out);
+                }
+            }
+            Stmt::Return(value) => {
+                if let Some(e) = value {
+                    expr(e, out);
+                }
+            }
+            Stmt::Const { expr: e, .. } => expr(e, out),
+            Stmt::Match { subject, arms } => {
+                expr(subject, out);
+                for s in arms.iter().flat_map(|arm| &arm.body) {
+                    stmt(s, out);
+                }
+            }
+            Stmt::Break | Stmt::Continue | Stmt::Enum { .. } | Stmt::Import(_) => {}
+        }
+    }
+    let mut out = Vec::new();
+    for s in stmts {
+        stmt(s, &mut out);
+    }
+    out
+}
+
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,211 @@
+//! AST rewriting between parsing and code generation, so embedders can add
+//! domain-specific constructs that expand into ordinary Kayton.
+//!
+//! A `Rewriter` sees the tree bottom-up: the default methods rebuild a node
+//! after rewriting its children, and overriding one method is enough to
+//! replace the nodes it cares about. A rewriter reports invalid input by
+//! panicking, which `compile_source` turns into a compile error.
+//!
+//! ```
+//! use kayton::parser::rewrite::{Rewriter, walk_expr};
+//! use kayton::parser::{BinOp, Expr};
+//!
+//! /// `double(x)` -> `x + x`
+//! struct Double;
+//!
+//! impl Rewriter for Double {
+//!     fn rewrite_expr(&self, expr: Expr) -> Expr {
+//!         match walk_expr(self, expr) {
+//!             Expr::Call { func, mut args } if *func == Expr::Ident("double".into()) => {
+//!                 let arg = args.pop().expect("double() takes one argument");
+//!                 Expr::Binary { left: Box::new(arg.clone()), op: BinOp::Add, right: Box::new(arg) }
+//!             }
+//!             other => other,
+//!         }
+//!     }
+//! }
+//! ```
+
+use super::{Expr, MatchArm, Stmt, StringPart};
+
+pub trait Rewriter: Send + Sync {
+    /// Replace a statement with any number of statements. Its children have
+    /// not been rewritten yet; call `walk_stmt` for that.
+    fn rewrite_stmt(&self, stmt: Stmt) -> Vec<Stmt> {
+        vec![walk_stmt(self, stmt)]
+    }
+
+    /// Replace an expression. Its children have not been rewritten yet; call
+    /// `walk_expr` for that.
+    fn rewrite_expr(&self, expr: Expr) -> Expr {
+        walk_expr(self, expr)
+    }
+}
+
+/// Plain functions rewrite expressions, after their children
+impl<F: Fn(Expr) -> Expr + Send + Sync> Rewriter for F {
+    fn rewrite_expr(&self, expr: Expr) -> Expr {
+        self(walk_expr(self, expr))
+    }
+}
+
+/// `stmt` with its expression passed through `rewriter`
+pub fn walk_stmt<R: Rewriter + ?Sized>(rewriter: &R, stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Assign {
+            name,
+            annotation,
+            expr,
+        } => Stmt::Assign {
+            name,
+            annotation,
+            expr: rewriter.rewrite_expr(expr),
+        },
+        Stmt::ExprStmt(expr) => Stmt::ExprStmt(rewriter.rewrite_expr(expr)),
+        Stmt::SetIndex {
+            target,
+            index,
+            expr,
+        } => Stmt::SetIndex {
+            target: rewriter.rewrite_expr(target),
+            index: rewriter.rewrite_expr(index),
+            expr: rewriter.rewrite_expr(expr),
+        },
+        Stmt::If {
+            cond,
+            then,
+            otherwise,
+        } => Stmt::If {
+            cond: rewriter.rewrite_expr(cond),
+            then: rewrite_body(rewriter, then),
+            otherwise: rewrite_body(rewriter, otherwise),
+        },
+        Stmt::While { cond, body } => Stmt::While {
+            cond: rewriter.rewrite_expr(cond),
+            body: rewrite_body(rewriter, body),
+        },
+        Stmt::FuncDef {
+            name,
+            params,
+            annotations,
+            returns,
+            body,
+        } => Stmt::FuncDef {
+            name,
+            params,
+            annotations,
+            returns,
+            body: rewrite_body(rewriter, body),
+        },
+        Stmt::Return(value) => Stmt::Return(value.map(|expr| rewriter.rewrite_expr(expr))),
+        Stmt::Const { name, expr } => Stmt::Const {
+            name,
+            expr: rewriter.rewrite_expr(expr),
+        },
+        Stmt::Match { subject, arms } => Stmt::Match {
+            subject: rewriter.rewrite_expr(subject),
+            arms: arms
+                .into_iter()
+                .map(|arm| MatchArm {
+                    pattern: arm.pattern,
+                    body: rewrite_body(rewriter, arm.body),
+                })
+                .collect(),
+        },
+        Stmt::Enum { .. } | Stmt::Break | Stmt::Continue | Stmt::Import(_) => stmt,
+    }
+}
+
+/// Statements of a block, each passed through `rewriter`
+fn rewrite_body<R: Rewriter + ?Sized>(rewriter: &R, stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts
+        .into_iter()
+        .flat_map(|stmt| rewriter.rewrite_stmt(stmt))
+        .collect()
+}
+
+/// `expr` with its sub-expressions passed through `rewriter`
+pub fn walk_expr<R: Rewriter + ?Sized>(rewriter: &R, expr: Expr) -> Expr {
+    match expr {
+        Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Bytes(_) | Expr::Ident(_) => expr,
+        Expr::Binary { left, op, right } => Expr::Binary {
+            left: Box::new(rewriter.rewrite_expr(*left)),
+            op,
+            right: Box::new(rewriter.rewrite_expr(*right)),
+        },
+        Expr::Unary { op, operand } => Expr::Unary {
+            op,
+            operand: Box::new(rewriter.rewrite_expr(*operand)),
+        },
+        Expr::Call { func, args } => Expr::Call {
+            func: Box::new(rewriter.rewrite_expr(*func)),
+            args: args.into_iter().map(|arg| rewriter.rewrite_expr(arg)).collect(),
+        },
+        Expr::InterpolatedString(parts) => Expr::InterpolatedString(
+            parts
+                .into_iter()
+                .map(|part| match part {
+                    StringPart::Expr(e) => StringPart::Expr(Box::new(rewriter.rewrite_expr(*e))),
+                    text => text,
+                })
+                .collect(),
+        ),
+        Expr::Ternary {
+            cond,
+            then,
+            otherwise,
+        } => Expr::Ternary {
+            cond: Box::new(rewriter.rewrite_expr(*cond)),
+            then: Box::new(rewriter.rewrite_expr(*then)),
+            otherwise: Box::new(rewriter.rewrite_expr(*otherwise)),
+        },
+        Expr::Compare { left, rest } => Expr::Compare {
+            left: Box::new(rewriter.rewrite_expr(*left)),
+            rest: rest
+                .into_iter()
+                .map(|(op, right)| (op, rewriter.rewrite_expr(right)))
+                .collect(),
+        },
+        Expr::List(items) => {
+            Expr::List(items.into_iter().map(|item| rewriter.rewrite_expr(item)).collect())
+        }
+        Expr::Dict(entries) => Expr::Dict(
+            entries
+                .into_iter()
+                .map(|(key, value)| (rewriter.rewrite_expr(key), rewriter.rewrite_expr(value)))
+                .collect(),
+        ),
+        Expr::Index { target, index } => Expr::Index {
+            target: Box::new(rewriter.rewrite_expr(*target)),
+            index: Box::new(rewriter.rewrite_expr(*index)),
+        },
+        Expr::Attribute { target, name } => Expr::Attribute {
+            target: Box::new(rewriter.rewrite_expr(*target)),
+            name,
+        },
+    }
+}
+
+/// Run `rewriters` over a program in order. `lines` holds the source line of
+/// each statement (see `Parser::stmt_lines`) and is kept in step, so
+/// statements a rewriter expands into all keep the line of the original.
+pub fn rewrite_program<R: AsRef<dyn Rewriter>>(
+    mut stmts: Vec<Stmt>,
+    mut lines: Vec<u32>,
+    rewriters: &[R],
+) -> (Vec<Stmt>, Vec<u32>) {
+    for rewriter in rewriters {
+        let mut new_stmts = Vec::with_capacity(stmts.len());
+        let mut new_lines = Vec::with_capacity(lines.len());
+        for (i, stmt) in stmts.into_iter().enumerate() {
+            let line = lines.get(i).copied().unwrap_or(0);
+            for stmt in rewriter.as_ref().rewrite_stmt(stmt) {
+                new_stmts.push(stmt);
+                new_lines.push(line);
+            }
+        }
+        stmts = new_stmts;
+        lines = new_lines;
+    }
+    (stmts, lines)
+}
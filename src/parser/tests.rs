@@ -8,16 +8,18 @@ x = x + 1
 print(x)
 "#;
     let tokens = Lexer::new(input).tokenize();
-    let ast = Parser::new(tokens).parse_program();
+    let ast = Parser::new(tokens).parse().unwrap();
     assert_eq!(
         ast,
         vec![
             Stmt::Assign {
                 name: "x".to_string(),
+                annotation: None,
                 expr: Expr::Int(12),
             },
             Stmt::Assign {
                 name: "x".to_string(),
+                annotation: None,
                 expr: Expr::Binary {
                     left: Box::new(Expr::Ident("x".to_string())),
                     op: BinOp::Add,
@@ -36,7 +38,7 @@ print(x)
 fn program2_ast() {
     let input = r#"print("Hello, World")"#;
     let tokens = Lexer::new(input).tokenize();
-    let ast = Parser::new(tokens).parse_program();
+    let ast = Parser::new(tokens).parse().unwrap();
     assert_eq!(
         ast,
         vec![Stmt::ExprStmt(Expr::Call {
@@ -52,12 +54,13 @@ fn program3_ast() {
 print(f"{x}")
 "#;
     let tokens = Lexer::new(input).tokenize();
-    let ast = Parser::new(tokens).parse_program();
+    let ast = Parser::new(tokens).parse().unwrap();
     assert_eq!(
         ast,
         vec![
             Stmt::Assign {
                 name: "x".to_string(),
+                annotation: None,
                 expr: Expr::Int(12),
             },
             Stmt::ExprStmt(Expr::Call {
@@ -71,3 +74,541 @@ print(f"{x}")
         ]
     );
 }
+
+#[test]
+fn ternaries_bind_loosest_and_nest_to_the_right() {
+    let tokens = Lexer::new("x = a + 1 if b else c if d else 2").tokenize();
+    let ast = Parser::new(tokens).parse().unwrap();
+    let ident = |name: &str| Box::new(Expr::Ident(name.to_string()));
+    assert_eq!(
+        ast,
+        vec![Stmt::Assign {
+            name: "x".to_string(),
+            annotation: None,
+            expr: Expr::Ternary {
+                cond: ident("b"),
+                then: Box::new(Expr::Binary {
+                    left: ident("a"),
+                    op: BinOp::Add,
+                    right: Box::new(Expr::Int(1)),
+                }),
+                otherwise: Box::new(Expr::Ternary {
+                    cond: ident("d"),
+                    then: ident("c"),
+                    otherwise: Box::new(Expr::Int(2)),
+                }),
+            },
+        }]
+    );
+}
+
+#[test]
+fn comparisons_chain() {
+    let tokens = Lexer::new("x = 0 < a + 1 <= 9").tokenize();
+    let ast = Parser::new(tokens).parse().unwrap();
+    assert_eq!(
+        ast[0],
+        Stmt::Assign {
+            name: "x".to_string(),
+            annotation: None,
+            expr: Expr::Compare {
+                left: Box::new(Expr::Int(0)),
+                rest: vec![
+                    (
+                        BinOp::Lt,
+                        Expr::Binary {
+                            left: Box::new(Expr::Ident("a".to_string())),
+                            op: BinOp::Add,
+                            right: Box::new(Expr::Int(1)),
+                        },
+                    ),
+                    (BinOp::Le, Expr::Int(9)),
+                ],
+            },
+        }
+    );
+}
+
+#[test]
+fn comparisons_bind_looser_than_sums() {
+    let tokens = Lexer::new("x = a + 1 <= 2.5 if b > c else d").tokenize();
+    let ast = Parser::new(tokens).parse().unwrap();
+    let ident = |name: &str| Box::new(Expr::Ident(name.to_string()));
+    assert_eq!(
+        ast,
+        vec![Stmt::Assign {
+            name: "x".to_string(),
+            annotation: None,
+            expr: Expr::Ternary {
+                cond: Box::new(Expr::Binary {
+                    left: ident("b"),
+                    op: BinOp::Gt,
+                    right: ident("c"),
+                }),
+                then: Box::new(Expr::Binary {
+                    left: Box::new(Expr::Binary {
+                        left: ident("a"),
+                        op: BinOp::Add,
+                        right: Box::new(Expr::Int(1)),
+                    }),
+                    op: BinOp::Le,
+                    right: Box::new(Expr::Float(2.5)),
+                }),
+                otherwise: ident("d"),
+            },
+        }]
+    );
+}
+
+#[test]
+fn powers_bind_tighter_than_remainders() {
+    let tokens = Lexer::new("x = a + b % 2 ** c ** 3 % d").tokenize();
+    let ast = Parser::new(tokens).parse().unwrap();
+    let ident = |name: &str| Box::new(Expr::Ident(name.to_string()));
+    let binary = |left, op, right| Box::new(Expr::Binary { left, op, right });
+    let power = binary(
+        Box::new(Expr::Int(2)),
+        BinOp::Pow,
+        binary(ident("c"), BinOp::Pow, Box::new(Expr::Int(3))),
+    );
+    let remainder = binary(binary(ident("b"), BinOp::Mod, power), BinOp::Mod, ident("d"));
+    assert_eq!(
+        ast,
+        vec![Stmt::Assign {
+            name: "x".to_string(),
+            annotation: None,
+            expr: *binary(ident("a"), BinOp::Add, remainder),
+        }]
+    );
+}
+
+#[test]
+fn bitwise_operators_bind_like_python() {
+    let tokens = Lexer::new("x = a | b ^ c & d << 1 + 2 < ~e ** 2").tokenize();
+    let ast = Parser::new(tokens).parse().unwrap();
+    let ident = |name: &str| Box::new(Expr::Ident(name.to_string()));
+    let int = |n| Box::new(Expr::Int(n));
+    let binary = |left, op, right| Box::new(Expr::Binary { left, op, right });
+    let shift = binary(ident("d"), BinOp::Shl, binary(int(1), BinOp::Add, int(2)));
+    let xor = binary(ident("b"), BinOp::BitXor, binary(ident("c"), BinOp::BitAnd, shift));
+    let not = Box::new(Expr::Unary {
+        op: UnaryOp::BitNot,
+        operand: binary(ident("e"), BinOp::Pow, int(2)),
+    });
+    assert_eq!(
+        ast,
+        vec![Stmt::Assign {
+            name: "x".to_string(),
+            annotation: None,
+            expr: *binary(binary(ident("a"), BinOp::BitOr, xor), BinOp::Lt, not),
+        }]
+    );
+}
+
+#[test]
+fn if_chains_nest_elif_in_the_else_body() {
+    let src = "if a:\n    x = 1\n    y = 2\nelif b:\n    x = 3\nelse:\n    x = 4\nend\nif c: print(c) end";
+    let mut parser = Parser::new(Lexer::new(src).tokenize());
+    let ast = parser.parse().unwrap();
+    let ident = |name: &str| Expr::Ident(name.to_string());
+    let assign = |name: &str, n| Stmt::Assign {
+        name: name.to_string(),
+        annotation: None,
+        expr: Expr::Int(n),
+    };
+    assert_eq!(
+        ast,
+        vec![
+            Stmt::If {
+                cond: ident("a"),
+                then: vec![assign("x", 1), assign("y", 2)],
+                otherwise: vec![Stmt::If {
+                    cond: ident("b"),
+                    then: vec![assign("x", 3)],
+                    otherwise: vec![assign("x", 4)],
+                }],
+            },
+            Stmt::If {
+                cond: ident("c"),
+                then: vec![Stmt::ExprStmt(Expr::Call {
+                    func: Box::new(ident("print")),
+                    args: vec![ident("c")],
+                })],
+                otherwise: vec![],
+            },
+        ]
+    );
+    assert_eq!(parser.stmt_lines(), [1, 9]);
+}
+
+#[test]
+fn while_loops_hold_a_block() {
+    let tokens = Lexer::new("while i < 3:\n  i = i + 1\nend").tokenize();
+    let ast = Parser::new(tokens).parse().unwrap();
+    let ident = |name: &str| Box::new(Expr::Ident(name.to_string()));
+    assert_eq!(
+        ast,
+        vec![Stmt::While {
+            cond: Expr::Binary {
+                left: ident("i"),
+                op: BinOp::Lt,
+                right: Box::new(Expr::Int(3)),
+            },
+            body: vec![Stmt::Assign {
+                name: "i".to_string(),
+                annotation: None,
+                expr: Expr::Binary {
+                    left: ident("i"),
+                    op: BinOp::Add,
+                    right: Box::new(Expr::Int(1)),
+                },
+            }],
+        }]
+    );
+}
+
+#[test]
+fn indented_blocks_need_no_end() {
+    let parse = |src: &str| Parser::new(Lexer::new(src).tokenize()).parse().unwrap();
+    let indented = parse(
+        "def f(a):\n    if a:\n        return 1\n    elif b:\n        return 2\n    else:\n        while a:\n            a = 0\n    return 3\nx = f(1)\n",
+    );
+    let with_ends = parse(
+        "def f(a):\nif a:\nreturn 1\nelif b:\nreturn 2\nelse:\nwhile a:\na = 0\nend\nend\nreturn 3\nend\nx = f(1)\n",
+    );
+    assert_eq!(indented, with_ends);
+    let mixed = parse("while a:\n    a = 0\nend\nif a:\n    b = 1\nend\nc = 2");
+    assert_eq!(mixed.len(), 3);
+}
+
+#[test]
+fn break_and_continue_are_statements() {
+    let tokens = Lexer::new("while x:\n  continue\n  break\nend").tokenize();
+    let ast = Parser::new(tokens).parse().unwrap();
+    assert_eq!(
+        ast,
+        vec![Stmt::While {
+            cond: Expr::Ident("x".to_string()),
+            body: vec![Stmt::Continue, Stmt::Break],
+        }]
+    );
+}
+
+#[test]
+fn defs_hold_parameters_and_returns() {
+    let tokens = Lexer::new("def add(a, b):\n  return a + b\nend\ndef stop():\n  return\nend").tokenize();
+    let ast = Parser::new(tokens).parse().unwrap();
+    let ident = |name: &str| Box::new(Expr::Ident(name.to_string()));
+    assert_eq!(
+        ast,
+        vec![
+            Stmt::FuncDef {
+                name: "add".to_string(),
+                params: vec!["a".to_string(), "b".to_string()],
+                annotations: vec![None; 2],
+                returns: None,
+                body: vec![Stmt::Return(Some(Expr::Binary {
+                    left: ident("a"),
+                    op: BinOp::Add,
+                    right: ident("b"),
+                }))],
+            },
+            Stmt::FuncDef {
+                name: "stop".to_string(),
+                params: Vec::new(),
+                annotations: Vec::new(),
+                returns: None,
+                body: vec![Stmt::Return(None)],
+            },
+        ]
+    );
+    let errors = Parser::new(Lexer::new("def f(a, a):\nend").tokenize()).parse().unwrap_err();
+    assert_eq!(errors[0].message, "duplicate parameter 'a' in 'f'");
+}
+
+#[test]
+fn rewriters_expand_statements_and_keep_lines() {
+    use super::rewrite::{Rewriter, rewrite_program, walk_stmt};
+
+    /// `twice(e)` as a statement -> `e` twice
+    struct Twice;
+    impl Rewriter for Twice {
+        fn rewrite_stmt(&self, stmt: Stmt) -> Vec<Stmt> {
+            match walk_stmt(self, stmt) {
+                Stmt::ExprStmt(Expr::Call { func, mut args }) if *func == Expr::Ident("twice".into()) => {
+                    let arg = args.pop().unwrap();
+                    vec![Stmt::ExprStmt(arg.clone()), Stmt::ExprStmt(arg)]
+                }
+                other => vec![other],
+            }
+        }
+    }
+    // Closures rewrite expressions bottom-up: 1 -> 10, everywhere
+    let tens = |expr: Expr| match expr {
+        Expr::Int(1) => Expr::Int(10),
+        other => other,
+    };
+
+    let mut parser = Parser::new(Lexer::new("x = 1 + 2\n\ntwice(f(1))\ny = f\"{1}\"").tokenize());
+    let stmts = parser.parse().unwrap();
+    let rewriters: Vec<Box<dyn Rewriter>> = vec![Box::new(Twice), Box::new(tens)];
+    let (stmts, lines) = rewrite_program(stmts, parser.stmt_lines().to_vec(), &rewriters);
+
+    let call = Expr::Call {
+        func: Box::new(Expr::Ident("f".to_string())),
+        args: vec![Expr::Int(10)],
+    };
+    assert_eq!(
+        stmts,
+        vec![
+            Stmt::Assign {
+                name: "x".to_string(),
+                annotation: None,
+                expr: Expr::Binary {
+                    left: Box::new(Expr::Int(10)),
+                    op: BinOp::Add,
+                    right: Box::new(Expr::Int(2)),
+                },
+            },
+            Stmt::ExprStmt(call.clone()),
+            Stmt::ExprStmt(call),
+            Stmt::Assign {
+                name: "y".to_string(),
+                annotation: None,
+                expr: Expr::InterpolatedString(vec![
+                    StringPart::Text(String::new()),
+                    StringPart::Expr(Box::new(Expr::Int(10))),
+                    StringPart::Text(String::new()),
+                ]),
+            },
+        ]
+    );
+    assert_eq!(lines, vec![1, 3, 3, 4]);
+}
+
+#[test]
+fn bad_fstring_placeholders_are_syntax_errors() {
+    use crate::frontend::{Frontend, KaytonSyntax};
+    for (src, message) in [
+        ("x = 1\ny = f\"a{x +}\"", "in f-string placeholder {x +}: Unexpected token EOF"),
+        ("x = 1\ny = f\"{x x}\"", "in f-string placeholder {x x}: Unexpected token Ident(\"x\") after expression"),
+        ("x = 1\ny = f\"{x ; 2}\"", "in f-string placeholder {x ; 2}: unknown character ';'"),
+        ("x = 1\ny = f\"{ }\"", "empty f-string placeholder { }"),
+    ] {
+        let mut diagnostics = Diagnostics::new();
+        let budget = Default::default();
+        assert_eq!(KaytonSyntax.parse_with_diagnostics(src, &budget, &mut diagnostics).unwrap_err(), message);
+        assert_eq!(diagnostics.to_string(), format!("line 2: error[syntax]: {}", message));
+    }
+}
+
+#[test]
+fn parse_reports_every_bad_statement_with_its_span() {
+    let errors = parse_source("x = (1\ny = 2\nz = )\nif 1:\n  w = ,\nprint(y)\n").unwrap_err();
+    let found: Vec<_> = errors
+        .iter()
+        .map(|e| (e.message.as_str(), e.found.clone(), e.expected.clone(), e.span))
+        .collect();
+    let span = |line, column, width| Span { line, column, width };
+    assert_eq!(
+        found,
+        vec![
+            ("expected RParen, found Newline", Token::Newline, vec!["RParen".to_string()], span(1, 7, 1)),
+            ("Unexpected token RParen", Token::RParen, vec!["expression".to_string()], span(3, 5, 1)),
+            ("Unexpected token Comma", Token::Comma, vec!["expression".to_string()], span(5, 7, 1)),
+        ]
+    );
+    assert_eq!(parse_source("x = 1\nprint(x)").unwrap().len(), 2);
+}
+
+#[test]
+fn list_literals_and_indexing() {
+    let ast = parse_source("xs = [1, 2]\nxs[0] = xs[1][0]\ny = []").unwrap();
+    let ident = |name: &str| Box::new(Expr::Ident(name.to_string()));
+    assert_eq!(
+        ast,
+        vec![
+            Stmt::Assign {
+                name: "xs".to_string(),
+                annotation: None,
+                expr: Expr::List(vec![Expr::Int(1), Expr::Int(2)]),
+            },
+            Stmt::SetIndex {
+                target: Expr::Ident("xs".to_string()),
+                index: Expr::Int(0),
+                expr: Expr::Index {
+                    target: Box::new(Expr::Index { target: ident("xs"), index: Box::new(Expr::Int(1)) }),
+                    index: Box::new(Expr::Int(0)),
+                },
+            },
+            Stmt::Assign {
+                name: "y".to_string(),
+                annotation: None,
+                expr: Expr::List(Vec::new()),
+            },
+        ]
+    );
+    assert!(parse_source("xs = [1,").is_err());
+}
+
+#[test]
+fn dict_literals_and_subscripts() {
+    let ast = parse_source("d = {1: 2, 3: x}\nd[1] = {}").unwrap();
+    assert_eq!(
+        ast,
+        vec![
+            Stmt::Assign {
+                name: "d".to_string(),
+                annotation: None,
+                expr: Expr::Dict(vec![
+                    (Expr::Int(1), Expr::Int(2)),
+                    (Expr::Int(3), Expr::Ident("x".to_string())),
+                ]),
+            },
+            Stmt::SetIndex {
+                target: Expr::Ident("d".to_string()),
+                index: Expr::Int(1),
+                expr: Expr::Dict(Vec::new()),
+            },
+        ]
+    );
+    let errors = parse_source("d = {1 2}").unwrap_err();
+    assert_eq!(errors[0].expected, vec!["Colon".to_string()]);
+}
+
+#[test]
+fn const_and_enum_declarations() {
+    let ast = parse_source("const N = 1 + 2\nenum Color: RED, GREEN").unwrap();
+    assert_eq!(
+        ast,
+        vec![
+            Stmt::Const {
+                name: "N".to_string(),
+                expr: Expr::Binary {
+                    left: Box::new(Expr::Int(1)),
+                    op: BinOp::Add,
+                    right: Box::new(Expr::Int(2)),
+                },
+            },
+            Stmt::Enum {
+                name: "Color".to_string(),
+                variants: vec!["RED".to_string(), "GREEN".to_string()],
+            },
+        ]
+    );
+    let errors = parse_source("enum Color: RED, RED").unwrap_err();
+    assert_eq!(errors[0].message, "duplicate variant 'RED' in 'Color'");
+    let errors = parse_source("const 1 = 2").unwrap_err();
+    assert_eq!(errors[0].message, "expected a constant name, found Int(1)");
+}
+
+#[test]
+fn attributes_follow_a_dot() {
+    let ast = parse_source("x = Color.name(c)\ny = 1.5").unwrap();
+    assert_eq!(
+        ast[0],
+        Stmt::Assign {
+            name: "x".to_string(),
+            annotation: None,
+            expr: Expr::Call {
+                func: Box::new(Expr::Attribute {
+                    target: Box::new(Expr::Ident("Color".to_string())),
+                    name: "name".to_string(),
+                }),
+                args: vec![Expr::Ident("c".to_string())],
+            },
+        }
+    );
+    assert_eq!(ast[1], Stmt::Assign { name: "y".to_string(), annotation: None, expr: Expr::Float(1.5) });
+    let errors = parse_source("x = Color.1").unwrap_err();
+    assert_eq!(errors[0].message, "expected an attribute name, found Int(1)");
+}
+
+#[test]
+fn type_annotations() {
+    let ast = parse_source("x: int = 1\ndef f(a: float, b) -> str:\n  return b\nend").unwrap();
+    assert_eq!(
+        ast[0],
+        Stmt::Assign {
+            name: "x".to_string(),
+            annotation: Some("int".to_string()),
+            expr: Expr::Int(1),
+        }
+    );
+    assert_eq!(
+        ast[1],
+        Stmt::FuncDef {
+            name: "f".to_string(),
+            params: vec!["a".to_string(), "b".to_string()],
+            annotations: vec![Some("float".to_string()), None],
+            returns: Some("str".to_string()),
+            body: vec![Stmt::Return(Some(Expr::Ident("b".to_string())))],
+        }
+    );
+    let errors = parse_source("x: = 1").unwrap_err();
+    assert_eq!(errors[0].message, "expected a type name, found Equal");
+    let errors = parse_source("def f() -> 1:\nend").unwrap_err();
+    assert_eq!(errors[0].message, "expected a type name, found Int(1)");
+}
+
+#[test]
+fn match_statements() {
+    let src = "match x:\n  case 1:\n    y = 1\n  case Color.RED:\n    y = 3\nmatch s:\ncase \"a\":\ncase _:\n  y = 2\nend";
+    let ast = parse_source(src).unwrap();
+    let assign = |n| Stmt::Assign {
+        name: "y".to_string(),
+        annotation: None,
+        expr: Expr::Int(n),
+    };
+    assert_eq!(
+        ast,
+        vec![
+            Stmt::Match {
+                subject: Expr::Ident("x".to_string()),
+                arms: vec![
+                    MatchArm {
+                        pattern: Pattern::Value(Expr::Int(1)),
+                        body: vec![assign(1)],
+                    },
+                    MatchArm {
+                        pattern: Pattern::Value(Expr::Attribute {
+                            target: Box::new(Expr::Ident("Color".to_string())),
+                            name: "RED".to_string(),
+                        }),
+                        body: vec![assign(3)],
+                    },
+                ],
+            },
+            Stmt::Match {
+                subject: Expr::Ident("s".to_string()),
+                arms: vec![
+                    MatchArm {
+                        pattern: Pattern::Value(Expr::Str("a".to_string())),
+                        body: vec![],
+                    },
+                    MatchArm {
+                        pattern: Pattern::Wildcard,
+                        body: vec![assign(2)],
+                    },
+                ],
+            },
+        ]
+    );
+    let errors = parse_source("match x:\ncase y:\nend").unwrap_err();
+    assert_eq!(
+        errors[0].message,
+        "capture patterns are not supported; match a literal, a variant such as Enum.y or _"
+    );
+    let errors = parse_source("match x:\nend").unwrap_err();
+    assert_eq!(errors[0].message, "'match' needs at least one 'case'");
+    let errors = parse_source("case 1:\n  y = 1").unwrap_err();
+    assert_eq!(errors[0].message, "'case' outside a 'match'");
+}
+
+#[test]
+fn import_statements() {
+    let ast = parse_source("import geometry\nx = geometry.area").unwrap();
+    assert_eq!(ast[0], Stmt::Import("geometry".to_string()));
+    let errors = parse_source("import \"geometry\"").unwrap_err();
+    assert_eq!(errors[0].message, "expected a module name, found Str(\"geometry\")");
+}
@@ -0,0 +1,149 @@
+//! Python bindings (`pyo3` feature): `kayton.KaytonEngine` wraps one VM.
+//!
+//! Build the extension module with `maturin develop --features pyo3`.
+
+use pyo3::exceptions::{PyKeyError, PyRuntimeError, PyTypeError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::sync::Arc;
+
+use crate::builtins::register_builtins;
+use crate::codegen::compile_source;
+use crate::vm::const_pool::{SliceType, ValueType};
+use crate::vm::{GlobalVarType, PtrType, VirtualMachine};
+
+#[pyclass(unsendable)]
+pub struct KaytonEngine {
+    vm: VirtualMachine,
+    print_const: u16,
+}
+
+#[pymethods]
+impl KaytonEngine {
+    #[new]
+    fn new() -> Self {
+        let mut vm = VirtualMachine::new();
+        let print_const = register_builtins(&mut vm);
+        Self { vm, print_const }
+    }
+
+    /// Compile and run `source`; globals persist between calls
+    fn eval(&mut self, source: &str) -> PyResult<()> {
+        let bytecode = compile_source(source, &mut self.vm, self.print_const)
+            .map_err(|e| PyRuntimeError::new_err(format!("compile error: {}", e)))?;
+        self.vm
+            .eval_program(&bytecode)
+            .map_err(|e| PyRuntimeError::new_err(format!("runtime error: {}", e)))
+    }
+
+    /// Value of global `name` as a Python int, float, bool or str
+    fn get(&self, py: Python<'_>, name: &str) -> PyResult<PyObject> {
+        match self.vm.global_vars.get(name) {
+            Some(var) => self.global_value(py, var.register_id, var.meta.typ),
+            None => Err(PyKeyError::new_err(name.to_string())),
+        }
+    }
+
+    /// All globals as a dict
+    fn globals<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        for (name, var) in self.vm.global_vars.iter() {
+            dict.set_item(name, self.global_value(py, var.register_id, var.meta.typ)?)?;
+        }
+        Ok(dict)
+    }
+
+    /// Expose `callable` to scripts as host function `name`. Arguments are
+    /// passed as ints and the result must be an int (or None for 0).
+    fn register(&mut self, name: &str, callable: PyObject, num_params: usize) -> usize {
+        // Registry names are 'static; engines usually live as long as the process
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let index = self.vm.host_functions.register_closure(
+            name,
+            1,
+            num_params,
+            num_params + 1,
+            Arc::new(move |base, registers| {
+                Python::with_gil(|py| {
+                    let args: Vec<i64> = (1..=num_params)
+                        .map(|i| registers.get(base + i) as i64)
+                        .collect();
+                    let result = callable
+                        .call1(
+                            py,
+                            pyo3::types::PyTuple::new(py, args).map_err(|e| e.to_string())?,
+                        )
+                        .map_err(|e| format!("{}: {}", name, e))?;
+                    let value: i64 = if result.is_none(py) {
+                        0
+                    } else {
+                        result
+                            .extract(py)
+                            .map_err(|_| format!("{}: expected an int result", name))?
+                    };
+                    registers.set(base, value as u64);
+                    Ok(())
+                })
+            }),
+        );
+        self.vm
+            .const_pool
+            .add_value(name, index as u64, ValueType::FuncHost);
+        index
+    }
+}
+
+impl KaytonEngine {
+    fn global_value(&self, py: Python<'_>, reg: usize, typ: GlobalVarType) -> PyResult<PyObject> {
+        let value = match typ {
+            GlobalVarType::Value(ValueType::I64) => self
+                .vm
+                .get_register_i64(reg)
+                .into_pyobject(py)?
+                .into_any()
+                .unbind(),
+            GlobalVarType::Value(ValueType::F64) => self
+                .vm
+                .get_register_f64(reg)
+                .into_pyobject(py)?
+                .into_any()
+                .unbind(),
+            GlobalVarType::Value(ValueType::Bool) => (self.vm.get_register_raw(reg) != 0)
+                .into_pyobject(py)?
+                .to_owned()
+                .into_any()
+                .unbind(),
+            GlobalVarType::Ptr(PtrType::Slice(SliceType::Binary)) => {
+                let ptr = self.vm.get_register_raw(reg) as *const u8;
+                let len = self.vm.get_register_raw(reg + 1) as usize;
+                let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+                PyBytes::new(py, bytes).into_any().unbind()
+            }
+            GlobalVarType::Ptr(PtrType::Slice(_)) => {
+                let ptr = self.vm.get_register_raw(reg) as *const u8;
+                let len = self.vm.get_register_raw(reg + 1) as usize;
+                // String globals point into the VM's const pool, which outlives this borrow
+                let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+                String::from_utf8_lossy(bytes)
+                    .into_pyobject(py)?
+                    .into_any()
+                    .unbind()
+            }
+            other => {
+                return Err(PyTypeError::new_err(format!(
+                    "unsupported global type {:?}",
+                    other
+                )));
+            }
+        };
+        Ok(value)
+    }
+}
+
+#[pymodule]
+fn kayton(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<KaytonEngine>()
+}
+
+#[cfg(test)]
+mod tests;
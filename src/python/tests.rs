@@ -0,0 +1,23 @@
+use super::*;
+use pyo3::ffi::c_str;
+
+#[test]
+fn eval_globals_and_python_callbacks() {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let mut engine = KaytonEngine::new();
+        let triple = py
+            .eval(c_str!("lambda x: x * 3"), None, None)
+            .unwrap()
+            .unbind();
+        engine.register("triple", triple, 1);
+        engine.eval("x = triple(14)\ns = \"hi\"").unwrap();
+
+        assert_eq!(engine.get(py, "x").unwrap().extract::<i64>(py).unwrap(), 42);
+        let globals = engine.globals(py).unwrap();
+        let s: String = globals.get_item("s").unwrap().unwrap().extract().unwrap();
+        assert_eq!(s, "hi");
+        assert!(engine.get(py, "missing").is_err());
+        assert!(engine.eval("x = )").is_err());
+    });
+}
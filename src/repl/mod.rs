@@ -0,0 +1,118 @@
+use crate::builtins::register_builtins;
+use crate::diagnostics::Renderer;
+use crate::frontend::{KaytonSyntax, compile_with_diagnostics};
+use crate::vm::VirtualMachine;
+use crate::write;
+use std::io::BufRead;
+
+/// File used by `:save` / `:load` when no path is given
+pub const DEFAULT_SESSION_FILE: &str = "session.kayvm";
+
+/// Interactive session keeping one VM alive across evaluated lines
+pub struct Repl {
+    pub vm: VirtualMachine,
+    print_const: u16,
+}
+
+/// What the REPL loop should do after handling a line
+#[derive(Debug, PartialEq)]
+pub enum ReplAction {
+    Continue,
+    Output(String),
+    Quit,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        let mut vm = VirtualMachine::new();
+        let print_const = register_builtins(&mut vm);
+        // `import` finds modules in the directory the session started in
+        vm.modules.add_path(".");
+        Self { vm, print_const }
+    }
+
+    /// Handle one line of input: either a `:command` or Kayton source. Errors
+    /// are ready to print: compile errors come rendered with the line and a
+    /// caret underline.
+    pub fn eval_line(&mut self, line: &str) -> Result<ReplAction, String> {
+        let line = line.trim_end();
+        if let Some(command) = line.strip_prefix(':') {
+            return self
+                .eval_command(command)
+                .map_err(|e| format!("error: {}", e));
+        }
+        if line.trim().is_empty() {
+            return Ok(ReplAction::Continue);
+        }
+        self.eval_source(line)?;
+        Ok(ReplAction::Continue)
+    }
+
+    fn eval_command(&mut self, command: &str) -> Result<ReplAction, String> {
+        let mut parts = command.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("quit"), None) | (Some("q"), None) => Ok(ReplAction::Quit),
+            (Some("help"), Some(name)) => Ok(ReplAction::Output(self.vm.help_text(name))),
+            (Some("save"), path) => {
+                let path = path.unwrap_or(DEFAULT_SESSION_FILE);
+                let data = self.vm.save_snapshot().map_err(|e| e.to_string())?;
+                std::fs::write(path, data).map_err(|e| format!("{}: {}", path, e))?;
+                Ok(ReplAction::Output(format!("Session saved to {}", path)))
+            }
+            (Some("load"), path) => {
+                let path = path.unwrap_or(DEFAULT_SESSION_FILE);
+                let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+                // Builtins are registered in the same order by every REPL, so host
+                // function indices stored in the saved const pool stay valid
+                self.vm.restore_snapshot(&data).map_err(|e| e.to_string())?;
+                Ok(ReplAction::Output(format!("Session loaded from {}", path)))
+            }
+            (Some("help"), None) => Ok(ReplAction::Output(
+                ":help <name>  show signature and docstring\n\
+                 :save [file]  save globals and constants (default session.kayvm)\n\
+                 :load [file]  restore a saved session\n\
+                 :quit         leave the REPL"
+                    .to_string(),
+            )),
+            _ => Err(format!("Unknown command ':{}'", command)),
+        }
+    }
+
+    fn eval_source(&mut self, src: &str) -> Result<(), String> {
+        let (bytecode, _) =
+            compile_with_diagnostics(&KaytonSyntax, src, &mut self.vm, self.print_const)
+                .map_err(|diagnostics| Renderer::new(src).render_all(&diagnostics.errors_only()))?;
+        self.vm
+            .eval_program(&bytecode)
+            .map_err(|e| format!("runtime error: {}", e))
+    }
+
+    /// Read-eval-print loop over stdin until EOF or `:quit`
+    pub fn run(&mut self) {
+        let stdin = std::io::stdin();
+        let mut line = String::new();
+        loop {
+            write::print_to_console(b">>> ");
+            line.clear();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            match self.eval_line(&line) {
+                Ok(ReplAction::Continue) => {}
+                Ok(ReplAction::Output(text)) => write::println_to_console(text.as_bytes()),
+                Ok(ReplAction::Quit) => break,
+                Err(err) => write::println_to_console(err.as_bytes()),
+            }
+        }
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,72 @@
+use super::*;
+
+#[test]
+fn globals_persist_across_lines() {
+    let mut repl = Repl::new();
+    assert_eq!(repl.eval_line("x = 40"), Ok(ReplAction::Continue));
+    assert_eq!(repl.eval_line("x = x + 2"), Ok(ReplAction::Continue));
+    let var = repl.vm.global_vars.get("x").expect("global var x");
+    assert_eq!(repl.vm.get_register_i64(var.register_id), 42);
+}
+
+#[test]
+fn help_command_shows_signature_and_doc() {
+    let mut repl = Repl::new();
+    let action = repl.eval_line(":help print").unwrap();
+    assert_eq!(
+        action,
+        ReplAction::Output(
+            "print(arg1)\n\nPrint an integer or string followed by a newline.".to_string()
+        )
+    );
+}
+
+#[test]
+fn module_docstring_is_recorded() {
+    let mut repl = Repl::new();
+    repl.eval_line("\"Scratch session\"").unwrap();
+    assert_eq!(
+        repl.eval_line(":help __module__").unwrap(),
+        ReplAction::Output("Scratch session".to_string())
+    );
+}
+
+#[test]
+fn quit_and_unknown_commands() {
+    let mut repl = Repl::new();
+    assert_eq!(repl.eval_line(":quit"), Ok(ReplAction::Quit));
+    assert_eq!(repl.eval_line(":bogus"), Err("error: Unknown command ':bogus'".to_string()));
+}
+
+#[test]
+fn compile_errors_show_the_line() {
+    let mut repl = Repl::new();
+    assert_eq!(
+        repl.eval_line("x = \"a\" + 1"),
+        Err("error[compile]: cannot add str and int\n --> line 1\n  |\n1 | x = \"a\" + 1\n  | ^^^^^^^^^^^"
+            .to_string())
+    );
+}
+
+#[test]
+fn save_and_load_session() {
+    let path = std::env::temp_dir().join(format!("kayton_repl_{}.kayvm", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+
+    let mut repl = Repl::new();
+    repl.eval_line("x = 7").unwrap();
+    repl.eval_line("s = \"kept\"").unwrap();
+    repl.eval_line(&format!(":save {}", path)).unwrap();
+
+    let mut resumed = Repl::new();
+    resumed.eval_line(&format!(":load {}", path)).unwrap();
+    resumed.eval_line("x = x + 1").unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let x = resumed.vm.global_vars.get("x").unwrap().register_id;
+    assert_eq!(resumed.vm.get_register_i64(x), 8);
+    let s = resumed.vm.global_vars.get("s").unwrap().register_id;
+    let ptr = resumed.vm.get_register_raw(s) as *const u8;
+    let len = resumed.vm.get_register_raw(s + 1) as usize;
+    assert_eq!(unsafe { std::slice::from_raw_parts(ptr, len) }, b"kept");
+}
@@ -0,0 +1,343 @@
+//! Rules engines: many small named predicates over one binding schema,
+//! compiled together so that checking an input against all of them loads the
+//! bindings once and shares one const pool and register layout.
+//!
+//! ```
+//! use kayton::engine::Value;
+//! use kayton::rules::{BindingType, RuleSet};
+//! use std::collections::HashMap;
+//!
+//! let mut rules = RuleSet::compile(
+//!     &[("items", BindingType::Int), ("coupons", BindingType::Int)],
+//!     &[("non_empty", "items"), ("discounted", "coupons")],
+//! )
+//! .unwrap();
+//! let order = HashMap::from([
+//!     ("items".to_string(), Value::Int(3)),
+//!     ("coupons".to_string(), Value::Int(0)),
+//! ]);
+//! assert_eq!(rules.evaluate(&order).unwrap(), vec!["non_empty"]);
+//! ```
+//!
+//! A rule matches when its value is truthy: a non-zero integer or float, or a
+//! non-empty string or bytes value.
+
+use crate::builtins::register_builtins;
+use crate::engine::{Value, declare_bindings, load_inputs};
+use crate::diagnostics::Diagnostics;
+use crate::frontend::{
+    Ast, CompileBudget, Frontend, compile_with, parse_expression, report_parse_errors,
+};
+use crate::parser::Stmt;
+use crate::vm::const_pool::{SliceType, ValueType};
+use crate::vm::{GlobalVarType, PtrType, VirtualMachine};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Type of a binding in a rule set's schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BindingType {
+    Int,
+    Float,
+    Str,
+    Bytes,
+}
+
+impl BindingType {
+    pub fn of(value: &Value) -> Self {
+        match value {
+            Value::Int(_) => BindingType::Int,
+            Value::Float(_) => BindingType::Float,
+            Value::Str(_) => BindingType::Str,
+            Value::Bytes(_) => BindingType::Bytes,
+        }
+    }
+
+    fn global_type(self) -> GlobalVarType {
+        match self {
+            BindingType::Int => GlobalVarType::Value(ValueType::I64),
+            BindingType::Float => GlobalVarType::Value(ValueType::F64),
+            BindingType::Str => GlobalVarType::Ptr(PtrType::Slice(SliceType::Utf8Str)),
+            BindingType::Bytes => GlobalVarType::Ptr(PtrType::Slice(SliceType::Binary)),
+        }
+    }
+}
+
+impl fmt::Display for BindingType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BindingType::Int => "int",
+            BindingType::Float => "float",
+            BindingType::Str => "str",
+            BindingType::Bytes => "bytes",
+        })
+    }
+}
+
+/// One rule per line, each assigned to its own `__rule_<n>` global
+struct RuleSyntax;
+
+impl Frontend for RuleSyntax {
+    fn parse(&self, src: &str) -> Result<Ast, String> {
+        self.parse_with_diagnostics(src, &CompileBudget::default(), &mut Diagnostics::new())
+    }
+
+    fn parse_with_diagnostics(
+        &self,
+        src: &str,
+        budget: &CompileBudget,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Ast, String> {
+        let mut ast = Ast::default();
+        for (i, line) in src.split('\n').enumerate() {
+            let expr = parse_expression(line, budget, diagnostics).map_err(|mut errors| {
+                // Each rule is lexed alone, so its errors are on its line 1
+                for error in &mut errors {
+                    error.span.line = i as u32 + 1;
+                }
+                report_parse_errors(&errors, diagnostics)
+            })?;
+            ast.stmts.push(Stmt::Assign {
+                name: format!("__rule_{}", i),
+                annotation: None,
+                expr,
+            });
+            ast.lines.push(i as u32 + 1);
+        }
+        Ok(ast)
+    }
+}
+
+struct Rule {
+    name: String,
+    register: usize,
+    typ: GlobalVarType,
+}
+
+/// Rules evaluated by one run of `bytecode`
+struct Batch {
+    bytecode: Vec<u8>,
+    rules: Vec<Rule>,
+}
+
+/// Named predicates compiled against a fixed set of typed bindings
+pub struct RuleSet {
+    vm: VirtualMachine,
+    /// Sorted by name
+    schema: Vec<(String, BindingType)>,
+    inputs: Vec<usize>,
+    /// Rules are split across programs so their results fit in the registers
+    batches: Vec<Batch>,
+}
+
+impl RuleSet {
+    /// Compile `rules`, each a `(name, expression)` pair, for inputs with the
+    /// bindings of `schema`. Rules run in a VM with the builtins registered
+    /// but no capabilities granted.
+    pub fn compile(schema: &[(&str, BindingType)], rules: &[(&str, &str)]) -> Result<Self, String> {
+        let mut schema: Vec<(String, BindingType)> =
+            schema.iter().map(|(name, typ)| (name.to_string(), *typ)).collect();
+        schema.sort_by(|a, b| a.0.cmp(&b.0));
+        if let Some(pair) = schema.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(format!("binding '{}' is declared twice", pair[0].0));
+        }
+        for (i, (name, src)) in rules.iter().enumerate() {
+            if rules[..i].iter().any(|(other, _)| other == name) {
+                return Err(format!("rule '{}' is defined twice", name));
+            }
+            if src.contains('\n') {
+                return Err(format!("rule '{}' must be a single line", name));
+            }
+        }
+
+        let mut vm = VirtualMachine::new();
+        let print_const = register_builtins(&mut vm);
+        let globals: Vec<(String, GlobalVarType)> = schema
+            .iter()
+            .map(|(name, typ)| (name.clone(), typ.global_type()))
+            .collect();
+        let inputs = declare_bindings(&mut vm, &globals)?;
+        let first_free = vm
+            .global_vars
+            .iter()
+            .map(|(_, var)| var.register_id + 2)
+            .max()
+            .unwrap_or(1);
+        // Results take at most two registers; leave half the rest for temporaries
+        let per_batch = ((u8::MAX as usize + 1).saturating_sub(first_free) / 4).max(1);
+
+        let mut batches = Vec::new();
+        for chunk in rules.chunks(per_batch) {
+            declare_bindings(&mut vm, &globals)?;
+            let src = chunk.iter().map(|(_, src)| *src).collect::<Vec<_>>().join("\n");
+            let bytecode = match compile_with(&RuleSyntax, &src, &mut vm, print_const) {
+                Ok(bytecode) => bytecode,
+                Err(err) => return Err(blame(&mut vm, &globals, chunk, print_const, err)),
+            };
+            let rules = chunk
+                .iter()
+                .enumerate()
+                .map(|(i, (name, _))| {
+                    let var = vm
+                        .global_vars
+                        .get(&format!("__rule_{}", i))
+                        .ok_or(format!("rule '{}' produced no value", name))?;
+                    Ok(Rule {
+                        name: name.to_string(),
+                        register: var.register_id,
+                        typ: var.meta.typ,
+                    })
+                })
+                .collect::<Result<_, String>>()?;
+            batches.push(Batch { bytecode, rules });
+        }
+        Ok(Self {
+            vm,
+            schema,
+            inputs,
+            batches,
+        })
+    }
+
+    /// Number of rules
+    pub fn len(&self) -> usize {
+        self.batches.iter().map(|batch| batch.rules.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Names of the rules `bindings` match, in definition order. Every
+    /// binding of the schema must be given, with its declared type; others
+    /// are ignored.
+    pub fn evaluate(&mut self, bindings: &HashMap<String, Value>) -> Result<Vec<&str>, String> {
+        let mut values = Vec::with_capacity(self.schema.len());
+        for (name, typ) in &self.schema {
+            let value = bindings
+                .get(name)
+                .ok_or_else(|| format!("missing binding '{}'", name))?;
+            if BindingType::of(value) != *typ {
+                return Err(format!(
+                    "binding '{}' should be {}, got {}",
+                    name,
+                    typ,
+                    BindingType::of(value)
+                ));
+            }
+            values.push(value);
+        }
+        load_inputs(&mut self.vm, values.into_iter(), &self.inputs);
+
+        let mut matched = Vec::new();
+        for batch in &self.batches {
+            self.vm
+                .eval_program(&batch.bytecode)
+                .map_err(|e| format!("runtime error: {}", e))?;
+            for rule in &batch.rules {
+                let truthy = match rule.typ {
+                    GlobalVarType::Value(ValueType::F64) => {
+                        f64::from_bits(self.vm.registers.get(rule.register)) != 0.0
+                    }
+                    GlobalVarType::Value(_) => self.vm.registers.get(rule.register) != 0,
+                    GlobalVarType::Ptr(_) => self.vm.registers.get(rule.register + 1) != 0,
+                };
+                if truthy {
+                    matched.push(rule.name.as_str());
+                }
+            }
+        }
+        Ok(matched)
+    }
+}
+
+/// Find which rule of a batch that failed to compile is at fault, by
+/// compiling them one at a time
+fn blame(
+    vm: &mut VirtualMachine,
+    globals: &[(String, GlobalVarType)],
+    rules: &[(&str, &str)],
+    print_const: u16,
+    err: String,
+) -> String {
+    for (name, src) in rules {
+        if declare_bindings(vm, globals).is_ok()
+            && let Err(err) = compile_with(&RuleSyntax, src, vm, print_const)
+        {
+            return format!("compile error: rule '{}': {}", name, err);
+        }
+    }
+    format!("compile error: {}", err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(values: &[(&str, Value)]) -> HashMap<String, Value> {
+        values.iter().map(|(name, value)| (name.to_string(), value.clone())).collect()
+    }
+
+    #[test]
+    fn matching_rules_are_returned_in_order() {
+        let mut rules = RuleSet::compile(
+            &[("a", BindingType::Int), ("b", BindingType::Int)],
+            &[("sum", "a + b"), ("a", "a"), ("b", "b"), ("never", "0")],
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 4);
+        let row = input(&[("a", Value::Int(1)), ("b", Value::Int(0))]);
+        assert_eq!(rules.evaluate(&row).unwrap(), vec!["sum", "a"]);
+        let row = input(&[("a", Value::Int(0)), ("b", Value::Int(0)), ("extra", Value::Int(1))]);
+        assert!(rules.evaluate(&row).unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "isa-strings")]
+    fn strings_are_truthy_when_non_empty() {
+        let mut rules = RuleSet::compile(
+            &[("name", BindingType::Str), ("tag", BindingType::Bytes)],
+            &[("named", "name"), ("tagged", "tag"), ("label", "f\"{name}\"")],
+        )
+        .unwrap();
+        let row = input(&[("name", Value::Str(String::new())), ("tag", Value::Bytes(vec![0]))]);
+        assert_eq!(rules.evaluate(&row).unwrap(), vec!["tagged"]);
+        let row = input(&[("name", Value::Str("x".into())), ("tag", Value::Bytes(Vec::new()))]);
+        assert_eq!(rules.evaluate(&row).unwrap(), vec!["named", "label"]);
+    }
+
+    #[test]
+    fn many_rules_are_split_into_batches() {
+        let names: Vec<String> = (0..300).map(|i| format!("r{}", i)).collect();
+        let rules: Vec<(&str, &str)> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), if i % 3 == 0 { "x" } else { "0" }))
+            .collect();
+        let mut set = RuleSet::compile(&[("x", BindingType::Int)], &rules).unwrap();
+        assert!(set.batches.len() > 1);
+        let matched = set.evaluate(&input(&[("x", Value::Int(7))])).unwrap();
+        assert_eq!(matched.len(), 100);
+        assert_eq!(matched[99], "r297");
+    }
+
+    #[test]
+    fn errors_name_the_rule_or_binding() {
+        let schema = [("x", BindingType::Int)];
+        let err = RuleSet::compile(&schema, &[("ok", "x"), ("bad", "x +")]).err().unwrap();
+        assert!(err.starts_with("compile error: rule 'bad': "), "{}", err);
+        let err = RuleSet::compile(&schema, &[("ok", "x"), ("bad", "x $ 1")]).err().unwrap();
+        assert_eq!(err, "compile error: rule 'bad': unknown character '$'");
+        let err = RuleSet::compile(&schema, &[("ok", "x"), ("bad", "y")]).err().unwrap();
+        assert!(err.starts_with("compile error: rule 'bad': "), "{}", err);
+        let err = RuleSet::compile(&schema, &[("r", "x"), ("r", "x")]).err().unwrap();
+        assert_eq!(err, "rule 'r' is defined twice");
+        let err = RuleSet::compile(&[("x", BindingType::Int), ("x", BindingType::Str)], &[]).err();
+        assert_eq!(err.unwrap(), "binding 'x' is declared twice");
+
+        let mut rules = RuleSet::compile(&schema, &[("r", "x")]).unwrap();
+        assert_eq!(rules.evaluate(&HashMap::new()).unwrap_err(), "missing binding 'x'");
+        let row = input(&[("x", Value::Str("1".into()))]);
+        assert_eq!(rules.evaluate(&row).unwrap_err(), "binding 'x' should be int, got str");
+    }
+}
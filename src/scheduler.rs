@@ -0,0 +1,206 @@
+//! Timer callbacks for game loops and simulations. After `Scheduler::install`
+//! a script can call `on_timer(ms, callback)`, where `callback` is Kayton
+//! source, and the embedder calls `Scheduler::tick` from its own loop to run
+//! every callback that has come due.
+//!
+//! ```text
+//! count = 0
+//! on_timer(100, "count = count + 1")
+//! ```
+//!
+//! Callbacks run on the script's VM, so they read and update its globals and
+//! can schedule further callbacks (a callback that calls `on_timer` with its
+//! own source repeats). `on_timer` returns an id for `cancel_timer(id)`.
+
+use crate::builtins::{Clock, str_arg};
+use crate::codegen::compile_source;
+use crate::vm::VirtualMachine;
+use crate::vm::const_pool::ValueType;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct Timers {
+    next_id: u64,
+    /// Callback source by (due time, id): ties fire in scheduling order
+    pending: BTreeMap<(u64, u64), String>,
+}
+
+/// Timers scripts have scheduled, waiting for `tick`
+pub struct Scheduler {
+    clock: Clock,
+    timers: Arc<Mutex<Timers>>,
+    /// Bytecode of each callback source, compiled on first use
+    compiled: HashMap<String, Vec<u8>>,
+}
+
+impl Scheduler {
+    /// Scheduler measuring time with `clock`, in milliseconds
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            timers: Arc::new(Mutex::new(Timers::default())),
+            compiled: HashMap::new(),
+        }
+    }
+
+    /// Register `on_timer` and `cancel_timer` with `vm`
+    pub fn install(&self, vm: &mut VirtualMachine) {
+        let (clock, timers) = (self.clock.clone(), self.timers.clone());
+        let on_timer = vm.host_functions.register_closure(
+            "on_timer",
+            1,
+            2,
+            4,
+            Arc::new(move |base, registers| {
+                let delay = (registers.get(base + 1) as i64).max(0) as u64;
+                let callback = str_arg(registers, base + 2)?.to_string();
+                let mut timers = timers.lock().map_err(|e| e.to_string())?;
+                timers.next_id += 1;
+                let id = timers.next_id;
+                let due = clock().saturating_add(delay);
+                timers.pending.insert((due, id), callback);
+                registers.set(base, id);
+                Ok(())
+            }),
+        );
+        vm.debug_info.set_doc(
+            "on_timer",
+            "Run the source `callback` once `ms` milliseconds have passed; returns a timer id.",
+        );
+        vm.const_pool
+            .add_value("on_timer", on_timer as u64, ValueType::FuncHost);
+
+        let timers = self.timers.clone();
+        let cancel = vm.host_functions.register_closure(
+            "cancel_timer",
+            1,
+            1,
+            2,
+            Arc::new(move |base, registers| {
+                let id = registers.get(base + 1);
+                let mut timers = timers.lock().map_err(|e| e.to_string())?;
+                let key = timers.pending.keys().find(|(_, other)| *other == id).copied();
+                let cancelled = key.and_then(|key| timers.pending.remove(&key)).is_some();
+                registers.set(base, cancelled as u64);
+                Ok(())
+            }),
+        );
+        vm.debug_info.set_doc(
+            "cancel_timer",
+            "Cancel a timer from on_timer; returns 1 if it had not fired yet.",
+        );
+        vm.const_pool
+            .add_value("cancel_timer", cancel as u64, ValueType::FuncHost);
+    }
+
+    /// Number of timers that have not fired
+    pub fn pending(&self) -> usize {
+        self.timers.lock().map(|t| t.pending.len()).unwrap_or(0)
+    }
+
+    /// When the earliest pending timer is due
+    pub fn next_due(&self) -> Option<u64> {
+        let timers = self.timers.lock().ok()?;
+        timers.pending.keys().next().map(|(due, _)| *due)
+    }
+
+    /// Run, in due order, every callback due by now, returning how many ran.
+    /// Callbacks they schedule wait for a later tick, even if already due.
+    /// Stops at the first callback that fails to compile or run; the
+    /// remaining due callbacks stay pending.
+    pub fn tick(&mut self, vm: &mut VirtualMachine, print_const: u16) -> Result<usize, String> {
+        let now = (self.clock)();
+        let due: Vec<(u64, u64)> = {
+            let timers = self.timers.lock().map_err(|e| e.to_string())?;
+            timers.pending.range(..(now + 1, 0)).map(|(key, _)| *key).collect()
+        };
+        let mut ran = 0;
+        for key in due {
+            // An earlier callback may have cancelled this one
+            let callback = match self.timers.lock().map_err(|e| e.to_string())?.pending.remove(&key) {
+                Some(callback) => callback,
+                None => continue,
+            };
+            let bytecode = match self.compiled.get(&callback) {
+                Some(bytecode) => bytecode,
+                None => {
+                    let bytecode = compile_source(&callback, vm, print_const)
+                        .map_err(|e| format!("timer {}: compile error: {}", key.1, e))?;
+                    self.compiled.entry(callback).or_insert(bytecode)
+                }
+            };
+            vm.eval_program(bytecode)
+                .map_err(|e| format!("timer {}: runtime error: {}", key.1, e))?;
+            ran += 1;
+        }
+        Ok(ran)
+    }
+}
+
+#[cfg(all(test, feature = "isa-strings"))]
+mod tests {
+    use super::*;
+    use crate::builtins::print_const;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// VM running `src` with a scheduler whose clock the test advances
+    fn setup(src: &str) -> (VirtualMachine, Scheduler, Arc<AtomicU64>, u16) {
+        let now = Arc::new(AtomicU64::new(1_000));
+        let clock_now = now.clone();
+        let mut scheduler = Scheduler::new(Arc::new(move || clock_now.load(Ordering::SeqCst)));
+        let mut vm = VirtualMachine::builder().with_stdlib().build();
+        scheduler.install(&mut vm);
+        let print = print_const(&vm).unwrap();
+        let bytecode = compile_source(src, &mut vm, print).unwrap();
+        vm.eval_program(&bytecode).unwrap();
+        assert_eq!(scheduler.tick(&mut vm, print), Ok(0));
+        (vm, scheduler, now, print)
+    }
+
+    fn global(vm: &VirtualMachine, name: &str) -> i64 {
+        vm.get_register_i64(vm.global_vars.get(name).unwrap().register_id)
+    }
+
+    #[test]
+    fn callbacks_run_in_due_order_and_update_globals() {
+        let src = "n = 0\nlast = 0\non_timer(50, \"n = n + 1\nlast = 2\")\non_timer(10, \"last = 1\")";
+        let (mut vm, mut scheduler, now, print) = setup(src);
+        assert_eq!((scheduler.pending(), scheduler.next_due()), (2, Some(1_010)));
+
+        now.store(1_010, Ordering::SeqCst);
+        assert_eq!(scheduler.tick(&mut vm, print), Ok(1));
+        assert_eq!((global(&vm, "n"), global(&vm, "last")), (0, 1));
+
+        now.store(2_000, Ordering::SeqCst);
+        assert_eq!(scheduler.tick(&mut vm, print), Ok(1));
+        assert_eq!((global(&vm, "n"), global(&vm, "last")), (1, 2));
+        assert_eq!(scheduler.pending(), 0);
+    }
+
+    #[test]
+    fn callbacks_can_reschedule_and_cancel() {
+        // `tick` re-arms itself; compiled once and reused
+        let src = "ticks = 0\ntick = \"ticks = ticks + 1\non_timer(10, tick)\"\non_timer(10, tick)\n\
+                   doomed = on_timer(15, \"ticks = 100\")\ncancel_timer(doomed)";
+        let (mut vm, mut scheduler, now, print) = setup(src);
+        assert_eq!(scheduler.pending(), 1);
+        for step in 1..=3 {
+            now.fetch_add(10, Ordering::SeqCst);
+            assert_eq!(scheduler.tick(&mut vm, print), Ok(1));
+            assert_eq!(global(&vm, "ticks"), step);
+        }
+        assert_eq!(scheduler.compiled.len(), 1);
+    }
+
+    #[test]
+    fn failing_callbacks_are_reported() {
+        let (mut vm, mut scheduler, now, print) =
+            setup("on_timer(5, \"x = (\")\non_timer(5, \"y = 1\")");
+        now.fetch_add(5, Ordering::SeqCst);
+        let err = scheduler.tick(&mut vm, print).unwrap_err();
+        assert!(err.starts_with("timer 1: compile error:"), "{}", err);
+        assert_eq!(scheduler.tick(&mut vm, print), Ok(1));
+        assert!(vm.global_vars.get("y").is_some());
+    }
+}
@@ -0,0 +1,120 @@
+//! Template rendering: a whole file is text, with `{{ expr }}` holes that are
+//! filled in with the value of the expression, for config files and
+//! generated code.
+//!
+//! ```text
+//! [server]
+//! user = "{{ env_get("USER") }}"
+//! port = {{ base_port + 1 }}
+//! ```
+//!
+//! A template compiles to one f-string assigned to `__rendered`, so holes
+//! follow f-string rules: integers and strings are formatted, bytes are a
+//! compile error. A hole ends at the first `}}`, even inside a string
+//! literal; a literal `{{` can be written as `{{ "{{" }}`.
+
+use crate::diagnostics::Diagnostics;
+use crate::frontend::{Ast, Frontend, compile_with};
+use crate::lexer::Lexer;
+use crate::parser::{Expr, Parser, Stmt, StringPart};
+use crate::vm::VirtualMachine;
+
+/// Global the rendered text is assigned to
+pub const RENDERED: &str = "__rendered";
+
+/// Parses a template into `__rendered = f"..."`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TemplateSyntax;
+
+impl Frontend for TemplateSyntax {
+    fn parse(&self, src: &str) -> Result<Ast, String> {
+        let mut parts = Vec::new();
+        let mut rest = src;
+        let mut line = 1;
+        while let Some(start) = rest.find("{{") {
+            let (text, after) = rest.split_at(start);
+            parts.push(StringPart::Text(text.to_string()));
+            line += text.matches('\n').count();
+            let hole = &after[2..];
+            let end = hole
+                .find("}}")
+                .ok_or(format!("line {}: unterminated {{{{", line))?;
+            let expr_src = &hole[..end];
+            if expr_src.trim().is_empty() {
+                return Err(format!("line {}: empty {{{{ }}}}", line));
+            }
+            let (tokens, _) = Lexer::new(expr_src)
+                .tokenize_spanned(&mut Diagnostics::new())
+                .map_err(|errors| format!("line {}: {}", line, errors[0]))?;
+            let expr = Parser::new(tokens)
+                .parse_expression()
+                .map_err(|error| format!("line {}: {}", line, error))?;
+            parts.push(StringPart::Expr(Box::new(expr)));
+            line += expr_src.matches('\n').count();
+            rest = &hole[end + 2..];
+        }
+        parts.push(StringPart::Text(rest.to_string()));
+        Ok(Ast {
+            stmts: vec![Stmt::Assign {
+                name: RENDERED.to_string(),
+                annotation: None,
+                expr: Expr::InterpolatedString(parts),
+            }],
+            lines: vec![1],
+        })
+    }
+}
+
+/// Compile and run template `src` on `vm`, returning the rendered text.
+/// Holes see the VM's globals and host functions; `print_const` is the const
+/// index holding `print`.
+pub fn render(src: &str, vm: &mut VirtualMachine, print_const: u16) -> Result<String, String> {
+    let bytecode = compile_with(&TemplateSyntax, src, vm, print_const)
+        .map_err(|e| format!("compile error: {}", e))?;
+    vm.eval_program(&bytecode)
+        .map_err(|e| format!("runtime error: {}", e))?;
+    let text = vm
+        .global_slice(RENDERED)
+        .ok_or("template produced no text")?;
+    String::from_utf8(text.to_vec()).map_err(|e| e.to_string())
+}
+
+#[cfg(all(test, feature = "isa-strings"))]
+mod tests {
+    use super::*;
+    use crate::builtins::print_const;
+
+    fn render_new(src: &str) -> Result<String, String> {
+        let mut vm = VirtualMachine::builder()
+            .global_i64("port", 8080)
+            .global_str("name", "api")
+            .with_stdlib()
+            .build();
+        let print = print_const(&vm).unwrap();
+        render(src, &mut vm, print)
+    }
+
+    #[test]
+    fn holes_are_filled_in() {
+        let src = "[{{ name }}]\nport = {{port + 1}}\nurl = \"{{ \"http://\" + name }}\"\n";
+        assert_eq!(
+            render_new(src),
+            Ok("[api]\nport = 8081\nurl = \"http://api\"\n".to_string())
+        );
+        assert_eq!(render_new("no holes"), Ok("no holes".to_string()));
+        assert_eq!(render_new(""), Ok(String::new()));
+        assert_eq!(render_new("{{ \"{{\" }} x }}"), Ok("{{ x }}".to_string()));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(render_new("a\nb {{ port"), Err("compile error: line 2: unterminated {{".to_string()));
+        assert_eq!(render_new("\n\n{{ }}"), Err("compile error: line 3: empty {{ }}".to_string()));
+        let err = render_new("ok\n{{ port port }}").unwrap_err();
+        assert!(err.starts_with("compile error: line 2: Unexpected token"), "{}", err);
+        let err = render_new("{{ missing }}").unwrap_err();
+        assert!(err.starts_with("compile error:"), "{}", err);
+        let err = render_new("{{ b\"x\" }}").unwrap_err();
+        assert!(err.contains("bytes cannot be formatted"), "{}", err);
+    }
+}
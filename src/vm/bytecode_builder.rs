@@ -14,12 +14,43 @@ pub(crate) enum JumpType {
     ForwardIfTrue,
 }
 
+/// A function (code segment) in a `ProgramImage`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionEntry {
+    pub name: String,
+    pub offset: u16,
+    pub len: u16,
+}
+
+/// Bytecode for several functions with a function table and the offset
+/// execution starts at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramImage {
+    pub bytecode: Vec<u8>,
+    pub functions: Vec<FunctionEntry>,
+    pub entry: u16,
+}
+
+impl ProgramImage {
+    pub fn function(&self, name: &str) -> Option<&FunctionEntry> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+}
+
 /// Enhanced bytecode builder with label support and target-based jumps
 pub struct BytecodeBuilder {
     bytecode: Vec<u8>,
     labels: std::collections::HashMap<u32, u16>,
     next_label_id: u32,
     pending_jumps: Vec<PendingJump>,
+    functions: Vec<FunctionEntry>,
+    /// Label of every function defined or referenced so far
+    function_labels: std::collections::HashMap<String, u32>,
+    /// Set while between `begin_function` and `end_function`
+    open_function: Option<usize>,
+    entry: Option<String>,
+    /// Placed at the very end of the image by `build`; jumping there halts
+    halt_label: Option<u32>,
 }
 
 impl BytecodeBuilder {
@@ -29,6 +60,11 @@ impl BytecodeBuilder {
             labels: std::collections::HashMap::new(),
             next_label_id: 0,
             pending_jumps: Vec::new(),
+            functions: Vec::new(),
+            function_labels: std::collections::HashMap::new(),
+            open_function: None,
+            entry: None,
+            halt_label: None,
         }
     }
 
@@ -36,6 +72,16 @@ impl BytecodeBuilder {
         self.bytecode.len() as u16
     }
 
+    /// Bytecode emitted so far, with forward jumps possibly unresolved
+    pub fn bytecode(&self) -> &[u8] {
+        &self.bytecode
+    }
+
+    /// Number of labels placed so far
+    pub fn label_count(&self) -> usize {
+        self.labels.len()
+    }
+
     // === LABEL MANAGEMENT ===
 
     /// Create a new label and return its ID
@@ -58,6 +104,41 @@ impl BytecodeBuilder {
         self.bytecode.extend_from_slice(&reg_fn_index_and_base.to_le_bytes());
     }
 
+    /// Call a host function following the frame convention: `dst_base`
+    /// gets the function index (and later the return value) and the
+    /// argument registers are copied to `dst_base + 1..`, skipping moves for
+    /// arguments already in place. A string argument is two registers
+    /// (pointer, length) in `arg_regs`.
+    pub fn call_host_fn(
+        &mut self,
+        metadata: &HostFunctionMetadata,
+        fn_const_idx: u16,
+        arg_regs: &[u8],
+        dst_base: u8,
+    ) {
+        if arg_regs.len() < metadata.num_params || 1 + arg_regs.len() > metadata.num_registers {
+            panic!(
+                "{}() takes {} arguments in {} registers, got {} registers",
+                metadata.name,
+                metadata.num_params,
+                metadata.num_registers.saturating_sub(1),
+                arg_regs.len()
+            );
+        }
+        for (i, &src) in arg_regs.iter().enumerate() {
+            let dst = dst_base + 1 + i as u8;
+            // A source below `dst` in the frame was already overwritten
+            if src > dst_base && src < dst && src != arg_regs[(src - dst_base - 1) as usize] {
+                panic!("argument r{} is overwritten before it is moved", src);
+            }
+            if src != dst {
+                self.mov(src, dst);
+            }
+        }
+        self.load_const_value(fn_const_idx, dst_base);
+        self.call_host(dst_base as u16);
+    }
+
     pub fn add_i64(&mut self, r1: u8, r2: u8, dst: u8) {
         self.bytecode.push(ADD_I64);
         self.bytecode.push(r1);
@@ -65,6 +146,47 @@ impl BytecodeBuilder {
         self.bytecode.push(dst);
     }
 
+    /// Concatenate the strings in register pairs r1 and r2 into the pair at dst
+    pub fn str_concat(&mut self, r1: u8, r2: u8, dst: u8) {
+        self.bytecode.push(STR_CONCAT);
+        self.bytecode.push(r1);
+        self.bytecode.push(r2);
+        self.bytecode.push(dst);
+    }
+
+    /// dst = 1 if the strings in register pairs r1 and r2 have the same bytes, else 0
+    pub fn str_eq(&mut self, r1: u8, r2: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[STR_EQ, r1, r2, dst]);
+    }
+
+    /// dst = `str_hash` of the string in the register pair at src
+    pub fn str_hash(&mut self, src: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[STR_HASH, src, dst]);
+    }
+
+    /// Open a string builder; STR_APPEND* add to it until STR_BUILDER_FINISH
+    pub fn str_builder_new(&mut self) {
+        self.bytecode.push(STR_BUILDER_NEW);
+    }
+
+    /// Append the string in the register pair at src
+    pub fn str_append(&mut self, src: u8) {
+        self.bytecode.push(STR_APPEND);
+        self.bytecode.push(src);
+    }
+
+    /// Append the decimal form of the integer in src
+    pub fn str_append_i64(&mut self, src: u8) {
+        self.bytecode.push(STR_APPEND_I64);
+        self.bytecode.push(src);
+    }
+
+    /// Store the finished string as a (ptr, len) pair at dst
+    pub fn str_builder_finish(&mut self, dst: u8) {
+        self.bytecode.push(STR_BUILDER_FINISH);
+        self.bytecode.push(dst);
+    }
+
     pub fn sub_i64(&mut self, r1: u8, r2: u8, dst: u8) {
         self.bytecode.push(SUB_I64);
         self.bytecode.push(r1);
@@ -168,6 +290,157 @@ impl BytecodeBuilder {
         self.bytecode.extend_from_slice(&index.to_le_bytes());
     }
 
+    /// Fail the program with the string const `message` unless `cond_reg` is non-zero
+    pub fn assert(&mut self, cond_reg: u8, message: u16) {
+        self.bytecode.push(ASSERT);
+        self.bytecode.push(cond_reg);
+        self.bytecode.extend_from_slice(&message.to_le_bytes());
+    }
+
+    /// Fail the program with `VmError::InvalidEnumValue`, naming the string
+    /// const `enum_name` and the int in `value`, unless `cond_reg` is non-zero
+    pub fn assert_enum_value(&mut self, cond_reg: u8, value: u8, enum_name: u16) {
+        self.bytecode.extend_from_slice(&[ASSERT_ENUM_VALUE, cond_reg, value]);
+        self.bytecode.extend_from_slice(&enum_name.to_le_bytes());
+    }
+
+    /// Like `assert_enum_value`, failing with `VmError::UnknownEnumName` and
+    /// the string in `name`
+    pub fn assert_enum_name(&mut self, cond_reg: u8, name: u8, enum_name: u16) {
+        self.bytecode.extend_from_slice(&[ASSERT_ENUM_NAME, cond_reg, name]);
+        self.bytecode.extend_from_slice(&enum_name.to_le_bytes());
+    }
+
+    pub fn swap(&mut self, r1: u8, r2: u8) {
+        self.bytecode.push(SWAP);
+        self.bytecode.push(r1);
+        self.bytecode.push(r2);
+    }
+
+    /// `dst = a` if `cond` is non-zero, else `dst = b`, without branching
+    pub fn select(&mut self, cond: u8, a: u8, b: u8, dst: u8) {
+        self.bytecode.push(SELECT);
+        self.bytecode.extend_from_slice(&[cond, a, b, dst]);
+    }
+
+    pub fn min_i64(&mut self, r1: u8, r2: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[MIN_I64, r1, r2, dst]);
+    }
+
+    pub fn max_i64(&mut self, r1: u8, r2: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[MAX_I64, r1, r2, dst]);
+    }
+
+    pub fn abs_i64(&mut self, src: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[ABS_I64, src, dst]);
+    }
+
+    /// dst = r1 % r2, with the sign of r2; fails the run if r2 is 0
+    pub fn mod_i64(&mut self, r1: u8, r2: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[MOD_I64, r1, r2, dst]);
+    }
+
+    /// dst = base ** exponent; fails the run if the exponent is negative
+    pub fn pow_i64(&mut self, base: u8, exponent: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[POW_I64, base, exponent, dst]);
+    }
+
+    pub fn pow_f64(&mut self, base: u8, exponent: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[POW_F64, base, exponent, dst]);
+    }
+
+    pub fn and_i64(&mut self, r1: u8, r2: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[AND_I64, r1, r2, dst]);
+    }
+
+    pub fn or_i64(&mut self, r1: u8, r2: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[OR_I64, r1, r2, dst]);
+    }
+
+    pub fn xor_i64(&mut self, r1: u8, r2: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[XOR_I64, r1, r2, dst]);
+    }
+
+    /// dst = ~src, every bit flipped
+    pub fn not_i64(&mut self, src: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[NOT_I64, src, dst]);
+    }
+
+    /// dst = value << count; fails the run if the count is negative
+    pub fn shl_i64(&mut self, value: u8, count: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[SHL_I64, value, count, dst]);
+    }
+
+    /// dst = value >> count, keeping the sign; fails the run if the count is negative
+    pub fn shr_i64(&mut self, value: u8, count: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[SHR_I64, value, count, dst]);
+    }
+
+    pub fn min_f64(&mut self, r1: u8, r2: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[MIN_F64, r1, r2, dst]);
+    }
+
+    pub fn max_f64(&mut self, r1: u8, r2: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[MAX_F64, r1, r2, dst]);
+    }
+
+    pub fn abs_f64(&mut self, src: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[ABS_F64, src, dst]);
+    }
+
+    /// dst = int_reg < float_reg, comparing the exact values
+    pub fn lt_i64_f64(&mut self, int_reg: u8, float_reg: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[LT_I64_F64, int_reg, float_reg, dst]);
+    }
+
+    pub fn lte_i64_f64(&mut self, int_reg: u8, float_reg: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[LTE_I64_F64, int_reg, float_reg, dst]);
+    }
+
+    pub fn gt_i64_f64(&mut self, int_reg: u8, float_reg: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[GT_I64_F64, int_reg, float_reg, dst]);
+    }
+
+    pub fn gte_i64_f64(&mut self, int_reg: u8, float_reg: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[GTE_I64_F64, int_reg, float_reg, dst]);
+    }
+
+    pub fn round_f64(&mut self, src: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[ROUND_F64, src, dst]);
+    }
+
+    pub fn floor_f64(&mut self, src: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[FLOOR_F64, src, dst]);
+    }
+
+    pub fn ceil_f64(&mut self, src: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[CEIL_F64, src, dst]);
+    }
+
+    pub fn trunc_f64(&mut self, src: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[TRUNC_F64, src, dst]);
+    }
+
+    pub fn nop(&mut self) {
+        self.bytecode.push(NOP);
+    }
+
+    /// Breakpoint for the stepper and `VirtualMachine::break_hook`
+    pub fn debug_break(&mut self) {
+        self.bytecode.push(DEBUG_BREAK);
+    }
+
+    pub fn mov(&mut self, src: u8, dst: u8) {
+        self.bytecode.push(MOV);
+        self.bytecode.push(src);
+        self.bytecode.push(dst);
+    }
+
+    /// MOV for an int or float: the register type of `dst` is left as is
+    pub fn mov_value(&mut self, src: u8, dst: u8) {
+        self.bytecode.extend_from_slice(&[MOV_VALUE, src, dst]);
+    }
+
     pub fn i64_to_f64(&mut self, src: u8, dst: u8) {
         self.bytecode.push(I64_TO_F64);
         self.bytecode.push(src);
@@ -217,6 +490,21 @@ impl BytecodeBuilder {
         target_bytes_pos
     }
 
+    /// Call the bytecode at `target` with a register window starting at
+    /// `base`; returns the position of the target operand for patching
+    pub fn call(&mut self, target: u16, base: u8) -> u16 {
+        self.bytecode.push(CALL);
+        let target_bytes_pos = self.bytecode.len() as u16;
+        self.bytecode.extend_from_slice(&target.to_le_bytes());
+        self.bytecode.push(base);
+        target_bytes_pos
+    }
+
+    /// Return from the innermost CALL
+    pub fn ret(&mut self) {
+        self.bytecode.push(RET);
+    }
+
     /// Patch a target address at the given position
     pub fn patch_target(&mut self, target_pos: u16, target_value: u16) {
         let pos = target_pos as usize;
@@ -233,7 +521,8 @@ impl BytecodeBuilder {
         if target <= current_pos {
             panic!("Forward jump target must be after current position");
         }
-        let offset = target - current_pos - 4; // -4 for instruction size
+        // Offsets count from the operand, two bytes into the instruction
+        let offset = target - current_pos - 2;
         self.bytecode.push(JUMP_FORWARD_IF_FALSE);
         self.bytecode.push(cond_reg);
         self.bytecode.extend_from_slice(&offset.to_le_bytes());
@@ -245,7 +534,8 @@ impl BytecodeBuilder {
         if target <= current_pos {
             panic!("Forward jump target must be after current position");
         }
-        let offset = target - current_pos - 4; // -4 for instruction size
+        // Offsets count from the operand, two bytes into the instruction
+        let offset = target - current_pos - 2;
         self.bytecode.push(JUMP_FORWARD_IF_TRUE);
         self.bytecode.push(cond_reg);
         self.bytecode.extend_from_slice(&offset.to_le_bytes());
@@ -334,10 +624,156 @@ impl BytecodeBuilder {
         }
     }
 
+    // === STRUCTURED EMITTERS ===
+
+    /// Run `then` if `cond_reg` is non-zero. Chain `.else_(...)` for an else
+    /// branch; the labels are placed when the returned guard is dropped.
+    pub fn if_(&mut self, cond_reg: u8, then: impl FnOnce(&mut Self)) -> IfElse<'_> {
+        let else_label = self.create_label();
+        self.jump_if_false_to_label(cond_reg, else_label);
+        then(self);
+        IfElse {
+            builder: self,
+            else_label: Some(else_label),
+        }
+    }
+
+    /// Loop while the register returned by `cond` is non-zero; the code
+    /// `cond` emits runs before every iteration
+    pub fn while_(&mut self, cond: impl FnOnce(&mut Self) -> u8, body: impl FnOnce(&mut Self)) {
+        let top = self.create_label();
+        let end = self.create_label();
+        self.place_label(top);
+        let cond_reg = cond(self);
+        self.jump_if_false_to_label(cond_reg, end);
+        body(self);
+        self.jmp_to_label(top);
+        self.place_label(end);
+    }
+
+    /// Emit `body` with a label placed right after it, so code inside can
+    /// jump out of the block early
+    pub fn block(&mut self, body: impl FnOnce(&mut Self, u32)) {
+        let end = self.create_label();
+        body(self, end);
+        self.place_label(end);
+    }
+
+    // === FUNCTIONS ===
+
+    fn function_label(&mut self, name: &str) -> u32 {
+        if let Some(&label) = self.function_labels.get(name) {
+            return label;
+        }
+        let label = self.create_label();
+        self.function_labels.insert(name.to_string(), label);
+        label
+    }
+
+    /// CALL the code at `label_id`, which may be placed later
+    pub fn call_label(&mut self, label_id: u32, base: u8) {
+        let target = self.labels.get(&label_id).copied();
+        let patch_pos = self.call(target.unwrap_or(0), base);
+        if target.is_none() {
+            self.pending_jumps.push(PendingJump {
+                label_id,
+                patch_position: patch_pos,
+                jump_type: JumpType::Absolute,
+            });
+        }
+    }
+
+    /// CALL function `name`, which may be defined later
+    pub fn call_function(&mut self, name: &str, base: u8) {
+        let label = self.function_label(name);
+        self.call_label(label, base);
+    }
+
+    /// Start the code segment of function `name`; it runs until `end_function`
+    pub fn begin_function(&mut self, name: &str) {
+        if self.open_function.is_some() {
+            panic!("function '{}' started before the previous one ended", name);
+        }
+        if self.functions.iter().any(|f| f.name == name) {
+            panic!("function '{}' is defined twice", name);
+        }
+        let label = self.function_label(name);
+        self.place_label(label);
+        self.open_function = Some(self.functions.len());
+        self.functions.push(FunctionEntry {
+            name: name.to_string(),
+            offset: self.current_pos(),
+            len: 0,
+        });
+    }
+
+    /// End the current function. Reaching its end ends the program rather
+    /// than falling into the next function; functions that are CALLed should
+    /// `ret` before that.
+    pub fn end_function(&mut self) {
+        let index = self
+            .open_function
+            .take()
+            .expect("end_function without begin_function");
+        let halt = match self.halt_label {
+            Some(label) => label,
+            None => {
+                let label = self.create_label();
+                self.halt_label = Some(label);
+                label
+            }
+        };
+        self.jmp_to_label(halt);
+        let end = self.current_pos();
+        let function = &mut self.functions[index];
+        function.len = end - function.offset;
+    }
+
+    /// Function execution starts in; defaults to the first one defined
+    pub fn set_entry(&mut self, name: &str) {
+        self.entry = Some(name.to_string());
+    }
+
+    /// Jump to the start of function `name`, which may be defined later
+    pub fn jmp_to_function(&mut self, name: &str) {
+        let label = self.function_label(name);
+        self.jmp_to_label(label);
+    }
+
+    /// Build an image of all functions with their function table
+    pub fn build_image(&mut self) -> ProgramImage {
+        if let Some(index) = self.open_function {
+            panic!("function '{}' was never ended", self.functions[index].name);
+        }
+        for name in self.function_labels.keys() {
+            if !self.functions.iter().any(|f| &f.name == name) {
+                panic!("undefined function '{}'", name);
+            }
+        }
+        let entry = match &self.entry {
+            Some(name) => {
+                self.functions
+                    .iter()
+                    .find(|f| &f.name == name)
+                    .unwrap_or_else(|| panic!("undefined entry function '{}'", name))
+                    .offset
+            }
+            None => 0,
+        };
+        ProgramImage {
+            bytecode: self.build(),
+            functions: self.functions.clone(),
+            entry,
+        }
+    }
+
     // === BUILD METHOD ===
 
     /// Build the final bytecode, resolving all pending jumps
     pub fn build(&mut self) -> Vec<u8> {
+        if let Some(halt) = self.halt_label {
+            self.place_label(halt);
+        }
         // Resolve all pending jumps
         for pending in std::mem::take(&mut self.pending_jumps) {
             if let Some(&target) = self.labels.get(&pending.label_id) {
@@ -359,6 +795,34 @@ impl BytecodeBuilder {
     }
 }
 
+/// Guard returned by `BytecodeBuilder::if_`
+pub struct IfElse<'a> {
+    builder: &'a mut BytecodeBuilder,
+    /// Still to be placed; `None` once `else_` has run
+    else_label: Option<u32>,
+}
+
+impl IfElse<'_> {
+    pub fn else_(mut self, body: impl FnOnce(&mut BytecodeBuilder)) {
+        let Some(else_label) = self.else_label.take() else {
+            return;
+        };
+        let end = self.builder.create_label();
+        self.builder.jmp_to_label(end);
+        self.builder.place_label(else_label);
+        body(self.builder);
+        self.builder.place_label(end);
+    }
+}
+
+impl Drop for IfElse<'_> {
+    fn drop(&mut self) {
+        if let Some(else_label) = self.else_label {
+            self.builder.place_label(else_label);
+        }
+    }
+}
+
 impl Default for BytecodeBuilder {
     fn default() -> Self {
         Self::new()
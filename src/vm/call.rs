@@ -1,23 +1,84 @@
 use super::registers::Registers;
+use super::VmError;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
 
 pub type HostFn = fn(base: usize, registers: &mut Registers) -> Result<(), String>;
 
+/// Host function that can capture state (output sinks, clocks, embedder callbacks)
+pub type HostClosure = Arc<dyn Fn(usize, &mut Registers) -> Result<(), String> + Send + Sync>;
+
+/// Unwind payload with which a host function ends the whole script
+pub struct ExitRequest(pub i64);
+
+/// End the running script with `code`, surfacing as `VmError::Exit`. Call
+/// only from inside a host function; it unwinds without running the panic hook.
+pub fn request_exit(code: i64) -> ! {
+    panic::resume_unwind(Box::new(ExitRequest(code)))
+}
+
 #[derive(Clone)]
 pub struct HostFunctionMetadata {
     pub name: &'static str,
     pub num_return_registers: usize,
     pub num_params: usize,
     pub num_registers: usize,
+    /// Bumped when the signature changes; a program compiled against one
+    /// version runs on that version or later
+    pub version: u32,
+    /// Register values for the last `defaults.len()` parameters, which calls
+    /// may leave out. Every parameter of a function with defaults takes one
+    /// register.
+    pub defaults: &'static [u64],
+}
+
+impl HostFunctionMetadata {
+    /// Fewest arguments a call may pass
+    pub fn min_params(&self) -> usize {
+        self.num_params.saturating_sub(self.defaults.len())
+    }
+
+    /// Human-readable signature, e.g. `add(arg1, arg2) -> ret`
+    pub fn signature(&self) -> String {
+        let params: Vec<String> = (1..=self.num_params).map(|i| format!("arg{}", i)).collect();
+        let mut sig = format!("{}({})", self.name, params.join(", "));
+        match self.num_return_registers {
+            0 => {}
+            1 => sig.push_str(" -> ret"),
+            n => {
+                let rets: Vec<String> = (1..=n).map(|i| format!("ret{}", i)).collect();
+                sig.push_str(&format!(" -> ({})", rets.join(", ")));
+            }
+        }
+        sig
+    }
 }
 
 pub struct HostFunctionRegistry {
-    pub funcs: Vec<HostFn>,
+    pub funcs: Vec<HostClosure>,
     pub metadata: Vec<HostFunctionMetadata>,
+    /// Functions whose two return registers hold bytes rather than a string
+    bytes_returns: HashSet<usize>,
+    /// `(index, num_params)` to the function `with_params` made for them
+    adapters: HashMap<(usize, usize), usize>,
+}
+
+impl Default for HostFunctionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HostFunctionRegistry {
     pub fn new() -> Self {
-        Self { funcs: Vec::new(), metadata: Vec::new() }
+        Self {
+            funcs: Vec::new(),
+            metadata: Vec::new(),
+            bytes_returns: HashSet::new(),
+            adapters: HashMap::new(),
+        }
     }
 
     pub fn register(
@@ -28,22 +89,135 @@ impl HostFunctionRegistry {
         num_registers: usize,
         func: HostFn,
     ) -> usize {
-        let index = self.funcs.len();
-        self.funcs.push(func);
-        self.metadata.push(HostFunctionMetadata {
+        self.register_closure(name, num_return_registers, num_params, num_registers, Arc::new(func))
+    }
+
+    /// Register a host function that captures state
+    pub fn register_closure(
+        &mut self,
+        name: &'static str,
+        num_return_registers: usize,
+        num_params: usize,
+        num_registers: usize,
+        func: HostClosure,
+    ) -> usize {
+        let metadata = HostFunctionMetadata {
             name,
             num_return_registers,
             num_params,
             num_registers,
-        });
+            version: 0,
+            defaults: &[],
+        };
+        self.register_metadata(metadata, func)
+    }
+
+    /// Register a host function described by `metadata`, which can give it a
+    /// version and optional parameters
+    pub fn register_metadata(&mut self, metadata: HostFunctionMetadata, func: HostClosure) -> usize {
+        let index = self.funcs.len();
+        self.funcs.push(func);
+        self.metadata.push(metadata);
         index
     }
+
+    /// Index of a function taking the first `num_params` parameters of the
+    /// function at `index` and passing its defaults for the rest, for
+    /// bytecode compiled before the others were added. Registered under the
+    /// same name the first time it is asked for; `find` keeps returning
+    /// the original.
+    pub fn with_params(&mut self, index: usize, num_params: usize) -> usize {
+        let meta = &self.metadata[index];
+        if num_params >= meta.num_params {
+            return index;
+        }
+        if let Some(&adapter) = self.adapters.get(&(index, num_params)) {
+            return adapter;
+        }
+        let defaults = &meta.defaults[meta.defaults.len() - (meta.num_params - num_params)..];
+        let func = self.funcs[index].clone();
+        let adapted: HostClosure = Arc::new(move |base, registers: &mut Registers| {
+            for (i, &value) in defaults.iter().enumerate() {
+                registers.set(base + 1 + num_params + i, value);
+            }
+            func(base, registers)
+        });
+        let metadata = HostFunctionMetadata { num_params, defaults: &[], ..meta.clone() };
+        let adapter = self.register_metadata(metadata, adapted);
+        if self.returns_bytes(index) {
+            self.mark_returns_bytes(adapter);
+        }
+        self.adapters.insert((index, num_params), adapter);
+        adapter
+    }
+
+    /// Declare that the function at `index` returns bytes (pointer, length),
+    /// so the compiler types its result as bytes instead of a string
+    pub fn mark_returns_bytes(&mut self, index: usize) {
+        self.bytes_returns.insert(index);
+    }
+
+    pub fn returns_bytes(&self, index: usize) -> bool {
+        self.bytes_returns.contains(&index)
+    }
+
+    /// Look up a registered host function index by name
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.metadata.iter().position(|m| m.name == name)
+    }
+
+    /// Error for a call to an unregistered `index`, naming the functions
+    /// registered around it (or the last few, for indices far past the end)
+    pub(crate) fn unknown(&self, index: usize) -> VmError {
+        const WINDOW: usize = 5;
+        let len = self.metadata.len();
+        let start = index.saturating_sub(WINDOW / 2).min(len.saturating_sub(WINDOW));
+        let end = (start + WINDOW).min(len);
+        VmError::UnknownHostFunction {
+            index,
+            nearby: self.metadata[start..end]
+                .iter()
+                .enumerate()
+                .map(|(i, m)| format!("{} ({})", m.name, start + i))
+                .collect(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum CallInfo {
     Global { base: usize, top: usize },
-    Call { base: usize, top: usize, function_index: usize },
+    /// A CALL into bytecode starting at `entry`; RET resumes at `return_pc`
+    Call {
+        base: usize,
+        top: usize,
+        entry: usize,
+        return_pc: usize,
+    },
     CallHost { base: usize, top: usize, host_fn_index: usize },
 }
 
+/// Call a host function, turning a panic into `VmError::HostPanic` instead of
+/// unwinding through the interpreter. Registers may be half-written when that
+/// happens, which is why the VM refuses to run again until it is reset.
+pub(crate) fn invoke_host(
+    func: &HostClosure,
+    base: usize,
+    registers: &mut Registers,
+) -> Result<Result<(), String>, VmError> {
+    panic::catch_unwind(AssertUnwindSafe(|| func(base, registers))).map_err(|payload| {
+        match payload.downcast_ref::<ExitRequest>() {
+            Some(ExitRequest(code)) => VmError::Exit(*code),
+            None => VmError::HostPanic(panic_message(payload.as_ref())),
+        }
+    })
+}
+
+/// Text of a panic payload raised with `panic!`
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
@@ -1,22 +1,51 @@
-use bumpalo::Bump;
 use std::collections::HashMap;
 
 //
 // Value constants
 //
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ValueType {
     I64,
     F64,
     Bool,
     FuncHost,
+    /// Pointer to a list made by the `vec_host` functions
+    List,
+    /// Pointer to a dict made by the `map_host` functions
+    Dict,
     // Add more types if needed
 }
 
+impl ValueType {
+    /// Stable numeric tag used by serialized formats
+    pub fn tag(self) -> u8 {
+        match self {
+            ValueType::I64 => 0,
+            ValueType::F64 => 1,
+            ValueType::Bool => 2,
+            ValueType::FuncHost => 3,
+            ValueType::List => 4,
+            ValueType::Dict => 5,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(ValueType::I64),
+            1 => Some(ValueType::F64),
+            2 => Some(ValueType::Bool),
+            3 => Some(ValueType::FuncHost),
+            4 => Some(ValueType::List),
+            5 => Some(ValueType::Dict),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ValueConstMeta {
-    pub name: &'static str,
+    pub name: String,
     pub typ: ValueType,
     pub index: usize,
 }
@@ -25,15 +54,33 @@ pub struct ValueConstMeta {
 // Slice constants
 //
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SliceType {
     Utf8Str,
     Binary,
 }
 
+impl SliceType {
+    /// Stable numeric tag used by serialized formats
+    pub fn tag(self) -> u8 {
+        match self {
+            SliceType::Utf8Str => 0,
+            SliceType::Binary => 1,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(SliceType::Utf8Str),
+            1 => Some(SliceType::Binary),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SliceConstMeta {
-    pub name: &'static str,
+    pub name: String,
     pub typ: SliceType,
     pub index: usize,
 }
@@ -42,59 +89,66 @@ pub struct SliceConstMeta {
 // Unified constant pool
 //
 
+/// Marks the pool size at some point so later constants can be dropped again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConstCheckpoint {
+    id: u64,
+    values: usize,
+    slices: usize,
+}
+
+/// Constants referenced by bytecode through their index.
+///
+/// Each slice owns a separate heap allocation that never moves while the
+/// slice is in the pool, so the (ptr, len) pairs LOAD_CONST_SLICE puts in
+/// registers stay valid until the slice is dropped by `rollback`, `reset` or
+/// dropping the pool. Rust code borrows slices with lifetimes tied to the pool.
+#[derive(Default)]
 pub struct ConstPool {
-    arena: Bump,
+    /// Open checkpoints, innermost last
+    checkpoints: Vec<u64>,
+    next_checkpoint_id: u64,
+    /// Bumped whenever constants are dropped; bytecode compiled at an older
+    /// generation may refer to constants that no longer exist
+    generation: u64,
 
     // value constants
     pub values: Vec<u64>,
     pub value_metadata: Vec<ValueConstMeta>,
-    pub value_name_to_index: HashMap<&'static str, usize>,
+    pub value_name_to_index: HashMap<String, usize>,
 
     // slice constants
-    pub slices: Vec<&'static [u8]>,
+    slices: Vec<Box<[u8]>>,
     pub slice_metadata: Vec<SliceConstMeta>,
-    pub slice_name_to_index: HashMap<&'static str, usize>,
+    pub slice_name_to_index: HashMap<String, usize>,
 }
 
 impl ConstPool {
     pub fn new() -> Self {
-        ConstPool {
-            arena: Bump::new(),
-
-            values: Vec::new(),
-            value_metadata: Vec::new(),
-            value_name_to_index: HashMap::new(),
-
-            slices: Vec::new(),
-            slice_metadata: Vec::new(),
-            slice_name_to_index: HashMap::new(),
-        }
+        Self::default()
     }
 
     pub fn add_value(&mut self, name: &str, value: u64, typ: ValueType) -> usize {
-        let name_static = self.alloc_static_str(name);
         let index = self.values.len();
         self.values.push(value);
         self.value_metadata.push(ValueConstMeta {
-            name: name_static,
+            name: name.to_string(),
             typ,
             index,
         });
-        self.value_name_to_index.insert(name_static, index);
+        self.value_name_to_index.insert(name.to_string(), index);
         index
     }
 
     pub fn add_slice(&mut self, name: &str, data: &[u8], typ: SliceType) -> usize {
-        let name_static = self.alloc_static_str(name);
-        let data_static = self.alloc_static_slice(data);
         let index = self.slices.len();
-        self.slices.push(data_static);
+        self.slices.push(data.into());
         self.slice_metadata.push(SliceConstMeta {
-            name: name_static,
+            name: name.to_string(),
             typ,
             index,
         });
-        self.slice_name_to_index.insert(name_static, index);
+        self.slice_name_to_index.insert(name.to_string(), index);
         index
     }
 
@@ -102,17 +156,98 @@ impl ConstPool {
         self.value_name_to_index.get(name).map(|&i| self.values[i])
     }
 
-    pub fn get_slice(&self, name: &str) -> Option<&'static [u8]> {
-        self.slice_name_to_index.get(name).map(|&i| self.slices[i])
+    /// Index of the latest value constant called `name`
+    pub fn value_index(&self, name: &str) -> Option<usize> {
+        self.value_name_to_index.get(name).copied()
     }
 
-    fn alloc_static_str(&self, s: &str) -> &'static str {
-        let s = self.arena.alloc_str(s);
-        unsafe { std::mem::transmute::<&str, &'static str>(s) }
+    pub fn get_slice(&self, name: &str) -> Option<&[u8]> {
+        self.slice_name_to_index.get(name).map(|&i| &*self.slices[i])
     }
 
-    fn alloc_static_slice(&self, slice: &[u8]) -> &'static [u8] {
-        let s = self.arena.alloc_slice_copy(slice);
-        unsafe { std::mem::transmute::<&[u8], &'static [u8]>(s) }
+    /// Slice constant by index, as LOAD_CONST_SLICE sees it
+    pub fn slice(&self, index: usize) -> Option<&[u8]> {
+        self.slices.get(index).map(|s| &**s)
+    }
+
+    pub fn slice_count(&self) -> usize {
+        self.slices.len()
+    }
+
+    /// Slice constants in index order
+    pub fn slices(&self) -> impl Iterator<Item = &[u8]> {
+        self.slices.iter().map(|s| &**s)
+    }
+
+    /// Find the slice containing `ptr`, returning its index and the offset into it
+    pub fn locate_slice(&self, ptr: u64) -> Option<(usize, usize)> {
+        self.slices.iter().position(|s| {
+            let start = s.as_ptr() as u64;
+            ptr >= start && ptr <= start + s.len() as u64
+        })
+        .map(|i| (i, (ptr - self.slices[i].as_ptr() as u64) as usize))
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Start a scope; constants added from now on can be dropped with `rollback`
+    pub fn checkpoint(&mut self) -> ConstCheckpoint {
+        self.next_checkpoint_id += 1;
+        let id = self.next_checkpoint_id;
+        self.checkpoints.push(id);
+        ConstCheckpoint {
+            id,
+            values: self.values.len(),
+            slices: self.slices.len(),
+        }
+    }
+
+    /// Drop every constant added since `checkpoint` (and any nested checkpoints),
+    /// freeing their memory. The checkpoint stays usable for further rollbacks.
+    /// Returns false if the checkpoint was already discarded by an outer rollback
+    /// or `reset`.
+    ///
+    /// Registers and bytecode referring to dropped constants become invalid;
+    /// `generation()` changes so holders of compiled bytecode can tell.
+    pub fn rollback(&mut self, checkpoint: ConstCheckpoint) -> bool {
+        let Some(depth) = self.checkpoints.iter().position(|id| *id == checkpoint.id) else {
+            return false;
+        };
+        self.checkpoints.truncate(depth + 1);
+        self.values.truncate(checkpoint.values);
+        self.value_metadata.truncate(checkpoint.values);
+        self.slices.truncate(checkpoint.slices);
+        self.slice_metadata.truncate(checkpoint.slices);
+        self.rebuild_name_maps();
+        self.generation += 1;
+        true
+    }
+
+    /// Drop every constant; all checkpoints become stale
+    pub fn reset(&mut self) {
+        *self = Self {
+            generation: self.generation + 1,
+            next_checkpoint_id: self.next_checkpoint_id,
+            ..Self::new()
+        };
+    }
+
+    /// Bytes currently held by slice constants
+    pub fn allocated_bytes(&self) -> usize {
+        self.slices.iter().map(|s| s.len()).sum()
+    }
+
+    fn rebuild_name_maps(&mut self) {
+        // Later constants shadow earlier ones with the same name, as in add_*
+        self.value_name_to_index.clear();
+        for meta in &self.value_metadata {
+            self.value_name_to_index.insert(meta.name.clone(), meta.index);
+        }
+        self.slice_name_to_index.clear();
+        for meta in &self.slice_metadata {
+            self.slice_name_to_index.insert(meta.name.clone(), meta.index);
+        }
     }
 }
@@ -0,0 +1,70 @@
+use super::debug_info::SourceMap;
+use super::profile::Profile;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Which source lines ran, derived from a `Profile` through a source map.
+/// Only lines that generated bytecode are tracked.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    /// Line -> how many times it ran (the count of its most executed instruction)
+    pub lines: BTreeMap<u32, u64>,
+}
+
+impl Coverage {
+    pub fn from_profile(profile: &Profile, map: &SourceMap) -> Self {
+        let mut lines = BTreeMap::new();
+        for range in &map.ranges {
+            let line = map.node_lines.get(range.node as usize).copied().unwrap_or(0);
+            if line == 0 {
+                continue;
+            }
+            let hits = profile
+                .counts
+                .get(range.start..range.end)
+                .and_then(|counts| counts.iter().max().copied())
+                .unwrap_or(0);
+            let entry = lines.entry(line).or_insert(0);
+            *entry = (*entry).max(hits);
+        }
+        Self { lines }
+    }
+
+    pub fn lines_found(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|&&hits| hits > 0).count()
+    }
+
+    /// Report in the lcov tracefile format, for `genhtml` and CI tooling
+    pub fn to_lcov(&self, source_file: &str) -> String {
+        let mut out = format!("TN:\nSF:{}\n", source_file);
+        for (line, hits) in &self.lines {
+            let _ = writeln!(out, "DA:{},{}", line, hits);
+        }
+        let _ = writeln!(out, "LF:{}\nLH:{}\nend_of_record", self.lines_found(), self.lines_hit());
+        out
+    }
+
+    /// The source with hit counts, marking lines that never ran with `#####`
+    pub fn annotate(&self, src: &str) -> String {
+        let mut out = String::new();
+        for (i, text) in src.lines().enumerate() {
+            let marker = match self.lines.get(&(i as u32 + 1)) {
+                None => "-".to_string(),
+                Some(0) => "#####".to_string(),
+                Some(hits) => hits.to_string(),
+            };
+            let _ = writeln!(out, "{:>9}: {:>4}: {}", marker, i + 1, text);
+        }
+        let _ = writeln!(
+            out,
+            "{}/{} lines covered",
+            self.lines_hit(),
+            self.lines_found()
+        );
+        out
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+/// Key under which the module-level docstring is stored
+pub const MODULE_DOC: &str = "__module__";
+
+/// Bytecode in `start..end` was generated for AST node `node`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcRange {
+    pub start: usize,
+    pub end: usize,
+    pub node: u32,
+}
+
+/// Links bytecode to the AST nodes (numbered as by `parser::nodes`) it was
+/// generated from. Ranges nest: an expression's range lies inside the ranges
+/// of its parent expression and statement.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SourceMap {
+    pub ranges: Vec<PcRange>,
+    /// Source line of each node, 0 if unknown
+    pub node_lines: Vec<u32>,
+}
+
+impl SourceMap {
+    /// Innermost node whose bytecode contains `pc`
+    pub fn node_at(&self, pc: usize) -> Option<u32> {
+        self.ranges
+            .iter()
+            .filter(|r| r.start <= pc && pc < r.end)
+            .min_by_key(|r| r.end - r.start)
+            .map(|r| r.node)
+    }
+
+    pub fn line_at(&self, pc: usize) -> Option<u32> {
+        let node = self.node_at(pc)?;
+        self.node_lines.get(node as usize).copied().filter(|&line| line != 0)
+    }
+}
+
+/// Debug metadata collected during compilation (docstrings and script
+/// function signatures keyed by name, and the source map of the last
+/// compiled program)
+#[derive(Debug, Default)]
+pub struct DebugInfo {
+    docs: HashMap<String, String>,
+    /// Signature of each script function, e.g. `add(a: int, b) -> int`
+    signatures: HashMap<String, String>,
+    /// Variant names of each `enum`, in value order
+    enums: HashMap<String, Vec<String>>,
+    pub source_map: SourceMap,
+}
+
+impl DebugInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_doc(&mut self, name: &str, doc: &str) {
+        self.docs.insert(name.to_string(), doc.to_string());
+    }
+
+    pub fn doc(&self, name: &str) -> Option<&str> {
+        self.docs.get(name).map(|d| d.as_str())
+    }
+
+    pub fn set_signature(&mut self, name: &str, signature: &str) {
+        self.signatures.insert(name.to_string(), signature.to_string());
+    }
+
+    pub fn signature(&self, name: &str) -> Option<&str> {
+        self.signatures.get(name).map(|s| s.as_str())
+    }
+
+    pub fn set_enum(&mut self, name: &str, variants: &[String]) {
+        self.enums.insert(name.to_string(), variants.to_vec());
+    }
+
+    /// The name of `value` as a variant of enum `name`, e.g. `"RED"`
+    pub fn enum_variant(&self, name: &str, value: i64) -> Option<&str> {
+        let variants = self.enums.get(name)?;
+        let i = usize::try_from(value).ok()?;
+        variants.get(i).map(|v| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.docs.iter().map(|(name, doc)| (name.as_str(), doc.as_str()))
+    }
+}
@@ -0,0 +1,540 @@
+//! Embedder objects handed to scripts: database connections, entities,
+//! anything a host wants scripts to hold but not look inside.
+//!
+//! A script sees a foreign object as an int handle that it can store and
+//! pass around, and calls the methods of the object's type on it:
+//! `conn.query("users")`. Each method name gets one host function, named
+//! `.query`, registered by `VirtualMachine::register_foreign_type`; it finds
+//! the type of the object at run time and checks the arguments against
+//! that type's method. Each handle carries a generation, so a handle that
+//! was released, or one made up by the script, is an error rather than a
+//! different object.
+//!
+//! Host code that wants to know when an object goes away, to drop a cache
+//! entry say, can add a finalizer to it, or keep a `WeakHandle`, which
+//! neither keeps the object nor the table alive.
+
+use super::registers::Registers;
+use super::VirtualMachine;
+use std::any::Any;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+/// Most arguments a method call passes
+pub const MAX_METHOD_ARGS: usize = 16;
+
+/// Kind of a method parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForeignParam {
+    Int,
+    Float,
+    Str,
+}
+
+impl ForeignParam {
+    pub fn name(self) -> &'static str {
+        match self {
+            ForeignParam::Int => "int",
+            ForeignParam::Float => "float",
+            ForeignParam::Str => "str",
+        }
+    }
+
+    /// Registers an argument of this kind takes
+    fn width(self) -> usize {
+        match self {
+            ForeignParam::Int | ForeignParam::Float => 1,
+            ForeignParam::Str => 2,
+        }
+    }
+}
+
+/// An argument passed to a method, of the kind its parameter declares
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ForeignArg<'a> {
+    Int(i64),
+    Float(f64),
+    Str(&'a str),
+}
+
+impl<'a> ForeignArg<'a> {
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            ForeignArg::Int(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match *self {
+            ForeignArg::Float(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        match *self {
+            ForeignArg::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// The argument kinds of a method call, as the compiler passes them to the
+/// method's host function: the count in the low byte, then two bits a kind
+pub fn method_signature(kinds: &[ForeignParam]) -> u64 {
+    assert!(kinds.len() <= MAX_METHOD_ARGS, "too many method arguments");
+    kinds.iter().enumerate().fold(kinds.len() as u64, |sig, (i, kind)| {
+        let code = match kind {
+            ForeignParam::Int => 0,
+            ForeignParam::Float => 1,
+            ForeignParam::Str => 2,
+        };
+        sig | code << (8 + 2 * i)
+    })
+}
+
+fn signature_kinds(sig: u64) -> Result<Vec<ForeignParam>, String> {
+    let count = (sig & 0xff) as usize;
+    if count > MAX_METHOD_ARGS {
+        return Err(format!("bad method signature {:#x}", sig));
+    }
+    (0..count)
+        .map(|i| match (sig >> (8 + 2 * i)) & 3 {
+            0 => Ok(ForeignParam::Int),
+            1 => Ok(ForeignParam::Float),
+            2 => Ok(ForeignParam::Str),
+            _ => Err(format!("bad method signature {:#x}", sig)),
+        })
+        .collect()
+}
+
+/// Method of a foreign type: the object, then arguments of the declared
+/// kinds; the value returned lands in the call's return register
+pub type ForeignMethod =
+    Arc<dyn Fn(&dyn Any, &[ForeignArg]) -> Result<u64, String> + Send + Sync>;
+
+#[derive(Clone)]
+struct MethodDef {
+    name: &'static str,
+    params: Vec<ForeignParam>,
+    func: ForeignMethod,
+}
+
+/// How the VM treats objects of one embedder type: what it calls them,
+/// how it shows them, what to do when one is released and which methods
+/// scripts may call on them
+#[derive(Clone)]
+pub struct ForeignType {
+    name: &'static str,
+    display: Option<fn(&dyn Any) -> String>,
+    on_drop: Option<fn(&dyn Any)>,
+    methods: Vec<MethodDef>,
+}
+
+impl ForeignType {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            display: None,
+            on_drop: None,
+            methods: Vec::new(),
+        }
+    }
+
+    /// How `ForeignObjects::display` shows an object; the type name by default
+    pub fn display(mut self, display: fn(&dyn Any) -> String) -> Self {
+        self.display = Some(display);
+        self
+    }
+
+    /// Called with each object when it is released, before its last
+    /// reference from the VM goes away, e.g. to close a connection
+    pub fn on_drop(mut self, on_drop: fn(&dyn Any)) -> Self {
+        self.on_drop = Some(on_drop);
+        self
+    }
+
+    /// Method `name`, called from scripts as `object.name(args)` with
+    /// arguments of the kinds in `params`
+    pub fn method(
+        mut self,
+        name: &'static str,
+        params: &[ForeignParam],
+        method: impl Fn(&dyn Any, &[ForeignArg]) -> Result<u64, String> + Send + Sync + 'static,
+    ) -> Self {
+        assert!(params.len() <= MAX_METHOD_ARGS, "{}() has too many parameters", name);
+        self.methods.push(MethodDef {
+            name,
+            params: params.to_vec(),
+            func: Arc::new(method),
+        });
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl fmt::Debug for ForeignType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let methods: Vec<_> = self.methods.iter().map(|method| method.name).collect();
+        f.debug_struct("ForeignType")
+            .field("name", &self.name)
+            .field("methods", &methods)
+            .finish()
+    }
+}
+
+/// Run once with an object when it is released, before its type's drop hook
+pub type Finalizer = Box<dyn FnOnce(&dyn Any) + Send>;
+
+type Object = (Arc<dyn Any + Send + Sync>, Arc<ForeignType>);
+
+struct Entry {
+    generation: u32,
+    object: Option<Object>,
+    finalizers: Vec<Finalizer>,
+}
+
+/// Run the finalizers of a released object, then its type's drop hook
+fn finalize((value, ty): Object, finalizers: Vec<Finalizer>) {
+    for finalizer in finalizers {
+        finalizer(value.as_ref());
+    }
+    if let Some(on_drop) = ty.on_drop {
+        on_drop(value.as_ref());
+    }
+}
+
+#[derive(Default)]
+struct Table {
+    entries: Vec<Entry>,
+    free: Vec<u32>,
+}
+
+impl Drop for Table {
+    /// Objects still live when the VM and its host functions are gone get
+    /// their finalizers and drop hooks too
+    fn drop(&mut self) {
+        for entry in &mut self.entries {
+            if let Some(object) = entry.object.take() {
+                finalize(object, std::mem::take(&mut entry.finalizers));
+            }
+        }
+    }
+}
+
+/// The foreign objects scripts can reach, shared between the VM and the
+/// host functions of their methods. Cloning shares the table.
+#[derive(Clone, Default)]
+pub struct ForeignObjects {
+    table: Arc<Mutex<Table>>,
+}
+
+/// Handle of slot `index`: the generation in the high half, and the index
+/// plus one in the low half so no handle is 0
+fn handle(index: u32, generation: u32) -> u64 {
+    ((generation as u64) << 32) | (index as u64 + 1)
+}
+
+fn split(handle: u64) -> Option<(usize, u32)> {
+    let index = (handle as u32).checked_sub(1)?;
+    Some((index as usize, (handle >> 32) as u32))
+}
+
+impl ForeignObjects {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Table> {
+        // A panicking method never holds the lock, so a poisoned table is intact
+        self.table.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Hand `value` to scripts as an object of `ty`, returning its handle
+    pub fn insert(&self, value: impl Any + Send + Sync, ty: &Arc<ForeignType>) -> u64 {
+        let mut table = self.lock();
+        let object = Some((Arc::new(value) as Arc<dyn Any + Send + Sync>, ty.clone()));
+        match table.free.pop() {
+            Some(index) => {
+                let entry = &mut table.entries[index as usize];
+                entry.object = object;
+                handle(index, entry.generation)
+            }
+            None => {
+                let index = table.entries.len() as u32;
+                table.entries.push(Entry {
+                    generation: 0,
+                    object,
+                    finalizers: Vec::new(),
+                });
+                handle(index, 0)
+            }
+        }
+    }
+
+    fn lookup(&self, handle: u64) -> Option<Object> {
+        lookup(&self.lock(), handle)
+    }
+
+    /// The object behind `handle`, if it is live and a `T`
+    pub fn get<T: Any + Send + Sync>(&self, handle: u64) -> Option<Arc<T>> {
+        self.lookup(handle)?.0.downcast().ok()
+    }
+
+    /// Name of the type of the object behind `handle`
+    pub fn type_name(&self, handle: u64) -> Option<&'static str> {
+        self.lookup(handle).map(|(_, ty)| ty.name)
+    }
+
+    /// The object behind `handle` as its type shows it, e.g. for a debugger
+    pub fn display(&self, handle: u64) -> Option<String> {
+        let (value, ty) = self.lookup(handle)?;
+        Some(match ty.display {
+            Some(display) => display(value.as_ref()),
+            None => format!("<{}>", ty.name),
+        })
+    }
+
+    /// Call `finalizer` with the object behind `handle` when it is
+    /// released, after the finalizers added before it. False, dropping
+    /// `finalizer` uncalled, if the handle is not live.
+    pub fn add_finalizer(&self, handle: u64, finalizer: impl FnOnce(&dyn Any) + Send + 'static) -> bool {
+        let mut table = self.lock();
+        match live_entry(&mut table, handle) {
+            Some(entry) => {
+                entry.finalizers.push(Box::new(finalizer));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A reference to the object behind `handle` that does not keep it,
+    /// or these objects, alive; `None` if the handle is not live
+    pub fn downgrade(&self, handle: u64) -> Option<WeakHandle> {
+        self.lookup(handle)?;
+        Some(WeakHandle {
+            table: Arc::downgrade(&self.table),
+            handle,
+        })
+    }
+
+    /// Forget the object behind `handle`, running its finalizers and its
+    /// type's drop hook; the handle, its copies and its weak handles
+    /// become invalid. False if it was not live.
+    pub fn release(&self, handle: u64) -> bool {
+        let (object, finalizers) = {
+            let Some((index, generation)) = split(handle) else {
+                return false;
+            };
+            let mut table = self.lock();
+            let Some(entry) = table.entries.get_mut(index) else {
+                return false;
+            };
+            if entry.generation != generation {
+                return false;
+            }
+            let Some(object) = entry.object.take() else {
+                return false;
+            };
+            entry.generation = entry.generation.wrapping_add(1);
+            let finalizers = std::mem::take(&mut entry.finalizers);
+            table.free.push(index as u32);
+            (object, finalizers)
+        };
+        // Outside the lock, so the hooks may use the table
+        finalize(object, finalizers);
+        true
+    }
+
+    /// Release every object
+    pub fn clear(&self) {
+        let live: Vec<u64> = {
+            let table = self.lock();
+            table
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.object.is_some())
+                .map(|(index, entry)| handle(index as u32, entry.generation))
+                .collect()
+        };
+        for handle in live {
+            self.release(handle);
+        }
+    }
+
+    /// Number of live objects
+    pub fn len(&self) -> usize {
+        let table = self.lock();
+        table.entries.len() - table.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Run method `name` of the type of the object behind `handle`; the
+    /// table is not locked during the call, so methods may create or
+    /// release objects
+    fn call_method(&self, handle: u64, name: &str, args: &[ForeignArg]) -> Result<u64, String> {
+        let Some((value, ty)) = self.lookup(handle) else {
+            return Err(format!(
+                "cannot call {}() on {}: not a live object handle",
+                name, handle as i64
+            ));
+        };
+        let Some(method) = ty.methods.iter().find(|method| method.name == name) else {
+            return Err(format!("{} has no method '{}'", ty.name, name));
+        };
+        if args.len() != method.params.len() {
+            return Err(format!(
+                "{}.{}() takes {} arguments but {} were given",
+                ty.name,
+                name,
+                method.params.len(),
+                args.len()
+            ));
+        }
+        for (i, (arg, param)) in args.iter().zip(&method.params).enumerate() {
+            let kind = match arg {
+                ForeignArg::Int(_) => ForeignParam::Int,
+                ForeignArg::Float(_) => ForeignParam::Float,
+                ForeignArg::Str(_) => ForeignParam::Str,
+            };
+            if kind != *param {
+                return Err(format!(
+                    "argument {} of {}.{}() must be a {}, not a {}",
+                    i + 1,
+                    ty.name,
+                    name,
+                    param.name(),
+                    kind.name()
+                ));
+            }
+        }
+        (method.func)(value.as_ref(), args)
+    }
+}
+
+fn lookup(table: &Table, handle: u64) -> Option<Object> {
+    let (index, generation) = split(handle)?;
+    let entry = table.entries.get(index)?;
+    if entry.generation != generation {
+        return None;
+    }
+    entry.object.clone()
+}
+
+fn live_entry(table: &mut Table, handle: u64) -> Option<&mut Entry> {
+    let (index, generation) = split(handle)?;
+    let entry = table.entries.get_mut(index)?;
+    (entry.generation == generation && entry.object.is_some()).then_some(entry)
+}
+
+/// A foreign object handle that does not keep its object alive, made by
+/// `ForeignObjects::downgrade`. It outlives the VM, and then reaches
+/// nothing.
+#[derive(Clone)]
+pub struct WeakHandle {
+    table: Weak<Mutex<Table>>,
+    handle: u64,
+}
+
+impl WeakHandle {
+    /// The handle scripts know the object by
+    pub fn handle(&self) -> u64 {
+        self.handle
+    }
+
+    fn lookup(&self) -> Option<Object> {
+        let table = self.table.upgrade()?;
+        let table = table.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        lookup(&table, self.handle)
+    }
+
+    /// Whether the object has not been released yet
+    pub fn is_alive(&self) -> bool {
+        self.lookup().is_some()
+    }
+
+    /// The object, if it is still live and a `T`
+    pub fn upgrade<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.lookup()?.0.downcast().ok()
+    }
+}
+
+impl fmt::Debug for WeakHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WeakHandle").field("handle", &self.handle).finish()
+    }
+}
+
+impl fmt::Debug for ForeignObjects {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ForeignObjects").field("len", &self.len()).finish()
+    }
+}
+
+/// Name of the host function calling method `name` of any foreign type
+pub fn method_function(name: &str) -> String {
+    format!(".{}", name)
+}
+
+impl VirtualMachine {
+    /// Make the methods of `ty` callable from scripts, registering the host
+    /// function of each method name no type registered before. Pass the
+    /// returned type to `ForeignObjects::insert`.
+    pub fn register_foreign_type(&mut self, ty: ForeignType) -> Arc<ForeignType> {
+        for method in &ty.methods {
+            let function = method_function(method.name);
+            if self.host_functions.find(&function).is_some() {
+                continue;
+            }
+            // Registered once per method name for the life of the program
+            let function: &'static str = Box::leak(function.into_boxed_str());
+            let (objects, name) = (self.foreign.clone(), method.name);
+            self.host_functions.register_closure(
+                function,
+                1,
+                2,
+                3 + 2 * MAX_METHOD_ARGS,
+                Arc::new(move |base, registers: &mut Registers| {
+                    let kinds = signature_kinds(registers.get(base + 2))?;
+                    let mut reg = base + 3;
+                    let mut args = Vec::with_capacity(kinds.len());
+                    for kind in kinds {
+                        args.push(read_arg(registers, reg, kind)?);
+                        reg += kind.width();
+                    }
+                    let result = objects.call_method(registers.get(base + 1), name, &args)?;
+                    registers.set(base, result);
+                    Ok(())
+                }),
+            );
+        }
+        Arc::new(ty)
+    }
+}
+
+fn read_arg(registers: &Registers, reg: usize, kind: ForeignParam) -> Result<ForeignArg<'_>, String> {
+    let value = registers.get(reg);
+    Ok(match kind {
+        ForeignParam::Int => ForeignArg::Int(value as i64),
+        ForeignParam::Float => ForeignArg::Float(f64::from_bits(value)),
+        ForeignParam::Str => {
+            let len = registers.get(reg + 1) as usize;
+            if len == 0 {
+                return Ok(ForeignArg::Str(""));
+            }
+            // The compiler passes a str as a (pointer, length) pair into a
+            // const slice or the string heap, both alive for the call
+            let bytes = unsafe { std::slice::from_raw_parts(value as *const u8, len) };
+            ForeignArg::Str(std::str::from_utf8(bytes).map_err(|e| e.to_string())?)
+        }
+    })
+}
@@ -2,12 +2,12 @@ use std::collections::HashMap;
 
 use super::const_pool::{SliceType, ValueType};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PtrType {
     Slice(SliceType),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum GlobalVarType {
     Value(ValueType),
     Ptr(PtrType),
@@ -51,5 +51,9 @@ impl GlobalVars {
     pub fn is_empty(&self) -> bool {
         self.vars.is_empty()
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &GlobalVar)> {
+        self.vars.iter().map(|(name, var)| (name.as_str(), var))
+    }
 }
 
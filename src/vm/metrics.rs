@@ -0,0 +1,39 @@
+use super::string_heap::StringHeapStats;
+use super::*;
+use std::collections::BTreeMap;
+
+/// Resource usage of a VM, for embedders monitoring scripts in production
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    /// Most registers ever spilled past the fixed register file
+    pub peak_spill_registers: usize,
+    /// Runtime string objects and bytes, inline and large
+    pub strings: StringHeapStats,
+    pub const_values: usize,
+    pub const_slices: usize,
+    pub const_slice_bytes: usize,
+    /// Calls per host function name; functions never called are left out
+    pub host_calls: BTreeMap<String, u64>,
+}
+
+impl VirtualMachine {
+    pub fn metrics(&self) -> Metrics {
+        // Adapters for older programs share their function's name
+        let mut host_calls = BTreeMap::new();
+        for (index, &count) in self.host_calls.iter().enumerate() {
+            if let Some(meta) = self.host_functions.metadata.get(index)
+                && count > 0
+            {
+                *host_calls.entry(meta.name.to_string()).or_default() += count;
+            }
+        }
+        Metrics {
+            peak_spill_registers: self.registers.spill_len(),
+            strings: self.strings.stats(),
+            const_values: self.const_pool.values.len(),
+            const_slices: self.const_pool.slice_count(),
+            const_slice_bytes: self.const_pool.allocated_bytes(),
+            host_calls,
+        }
+    }
+}
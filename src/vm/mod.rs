@@ -1,13 +1,31 @@
 mod bytecode_builder;
 mod call;
 pub mod const_pool;
+pub mod debug_info;
+mod encoding;
+pub mod foreign;
 mod global_vars;
+pub mod number_format;
+#[cfg(feature = "disasm")]
 mod print_bytecode;
+pub mod program;
 mod register_types;
 mod registers;
-#[cfg(test)]
+mod sandbox;
+mod stats;
+mod verifier;
+pub mod string_heap;
+pub mod snapshot;
+pub mod replay;
+pub mod profile;
+pub mod coverage;
+#[cfg(feature = "disasm")]
+pub mod stepper;
+mod metrics;
+mod vm_builder;
+#[cfg(all(test, feature = "disasm"))]
 mod tests;
-#[cfg(test)]
+#[cfg(all(test, feature = "disasm"))]
 mod tests_bytecode_builder;
 #[cfg(test)]
 mod tests_call;
@@ -16,48 +34,248 @@ mod tests_const_opcodes;
 #[cfg(test)]
 mod tests_const_pool;
 #[cfg(test)]
+mod tests_debug_info;
+#[cfg(test)]
+mod tests_foreign;
+#[cfg(test)]
 mod tests_global_vars;
 #[cfg(test)]
+mod tests_number_format;
+#[cfg(all(test, feature = "disasm"))]
 mod tests_print_bytecode;
 #[cfg(test)]
+mod tests_program;
+#[cfg(test)]
 mod tests_registers;
+#[cfg(all(test, feature = "frontend", feature = "stdlib"))]
+mod tests_replay;
+#[cfg(test)]
+mod tests_snapshot;
+#[cfg(all(test, feature = "disasm"))]
+mod tests_stepper;
+#[cfg(test)]
+mod tests_string_heap;
+#[cfg(test)]
+mod tests_verifier;
+#[cfg(all(test, feature = "frontend", feature = "stdlib"))]
+mod tests_vm_builder;
 
-pub use bytecode_builder::BytecodeBuilder;
-pub use call::{CallInfo, HostFunctionMetadata, HostFunctionRegistry};
+pub use bytecode_builder::{BytecodeBuilder, FunctionEntry, IfElse, ProgramImage};
+pub use call::{
+    CallInfo, ExitRequest, HostClosure, HostFunctionMetadata, HostFunctionRegistry, request_exit,
+};
+pub use debug_info::DebugInfo;
+pub use foreign::{ForeignArg, ForeignObjects, ForeignParam, ForeignType, WeakHandle};
 pub use global_vars::{GlobalVarType, GlobalVars, PtrType};
-pub use print_bytecode::print_bytecode;
+#[cfg(feature = "disasm")]
+pub use print_bytecode::{format_bytecode_with_host_functions, format_consts, print_bytecode};
+pub use program::{Program, ProgramError};
 pub use register_types::{RegisterType, RegisterTypes};
 pub use registers::Registers;
+pub use sandbox::{Capabilities, CostTable, Limits};
+pub use metrics::Metrics;
+pub use stats::BytecodeStats;
+pub use string_heap::{AllocationScope, StringHeap};
+pub use verifier::VerifiedBytecode;
+use verifier::instruction_boundaries;
+pub use vm_builder::VmBuilder;
 
-use const_pool::ConstPool;
+use const_pool::{ConstPool, SliceType, ValueType};
+use profile::Profile;
+use replay::ReplayMode;
+#[cfg(feature = "frontend")]
+use crate::codegen::CompileMode;
+#[cfg(feature = "frontend")]
+use crate::frontend::CompileLimits;
+#[cfg(feature = "frontend")]
+use crate::modules::ModuleResolver;
+#[cfg(feature = "frontend")]
+use crate::parser::rewrite::Rewriter;
 use std::fmt;
+use std::sync::Arc;
 use std::time::Instant;
 
-// Instruction opcodes
+// Instruction opcodes (ISA version 3)
+//
+// Opcodes form one dense range so the interpreter match compiles to a single
+// jump table. The hottest instructions come first; optional feature families
+// follow in contiguous blocks. 0x00 stays invalid so zeroed memory traps.
+// Version 2 ended at 0x1E; version 3 added the opcodes from 0x1F on.
+//
+// | range       | family                                        |
+// |-------------|-----------------------------------------------|
+// | 0x01 - 0x02 | loads and moves                               |
+// | 0x03 - 0x09 | i64 arithmetic and comparisons                |
+// | 0x0A - 0x0E | jumps                                         |
+// | 0x0F - 0x10 | slice constants, host calls                   |
+// | 0x11 - 0x19 | f64 arithmetic, comparisons and conversions   |
+// | 0x1A - 0x1E | runtime strings                               |
+// | 0x1F        | assertions                                    |
+// | 0x20 - 0x21 | no-op and debugger breakpoint                 |
+// | 0x22 - 0x23 | register swap and conditional move            |
+// | 0x24 - 0x26 | i64 min, max and abs                          |
+// | 0x27 - 0x29 | f64 min, max and abs                          |
+// | 0x2A - 0x2D | checked f64 to i64 rounding                   |
+// | 0x2E - 0x2F | string equality and hashing                   |
+// | 0x30 - 0x33 | exact i64-to-f64 comparisons                  |
+// | 0x34 - 0x35 | bytecode function call and return             |
+// | 0x36        | move of an int or float, leaving the type tag |
+// | 0x37 - 0x39 | i64 modulo, i64 and f64 power                 |
+// | 0x3A - 0x3F | i64 bitwise operations and shifts             |
+// | 0x40 - 0x41 | enum value and name assertions                |
+pub const LOAD_CONST_VALUE: u8 = 0x01;
+pub const MOV: u8 = 0x02;
 pub const ADD_I64: u8 = 0x03;
 pub const SUB_I64: u8 = 0x04;
 pub const MUL_I64: u8 = 0x05;
-pub const GT_I64: u8 = 0x06;
-pub const ADD_F64: u8 = 0x07;
-pub const SUB_F64: u8 = 0x08;
-pub const MUL_F64: u8 = 0x09;
-pub const GT_F64: u8 = 0x0A;
+pub const LT_I64: u8 = 0x06;
+pub const LTE_I64: u8 = 0x07;
+pub const GT_I64: u8 = 0x08;
+pub const GTE_I64: u8 = 0x09;
+pub const JMP: u8 = 0x0A;
 pub const JUMP_FORWARD_IF_FALSE: u8 = 0x0B;
-pub const JMP: u8 = 0x0C;
-pub const I64_TO_F64: u8 = 0x0D;
-pub const F64_TO_I64: u8 = 0x0E;
-pub const JUMP_BACKWARD_IF_FALSE: u8 = 0x0F;
-pub const JUMP_BACKWARD_IF_TRUE: u8 = 0x10;
-pub const JUMP_FORWARD_IF_TRUE: u8 = 0x11;
-pub const GTE_I64: u8 = 0x12;
-pub const LT_I64: u8 = 0x13;
-pub const LTE_I64: u8 = 0x14;
-pub const GTE_F64: u8 = 0x15;
-pub const LT_F64: u8 = 0x16;
-pub const LTE_F64: u8 = 0x17;
-pub const LOAD_CONST_VALUE: u8 = 0x18;
-pub const LOAD_CONST_SLICE: u8 = 0x19;
-pub const CALL_HOST: u8 = 0x1A;
+pub const JUMP_FORWARD_IF_TRUE: u8 = 0x0C;
+pub const JUMP_BACKWARD_IF_FALSE: u8 = 0x0D;
+pub const JUMP_BACKWARD_IF_TRUE: u8 = 0x0E;
+pub const LOAD_CONST_SLICE: u8 = 0x0F;
+pub const CALL_HOST: u8 = 0x10;
+pub const ADD_F64: u8 = 0x11;
+pub const SUB_F64: u8 = 0x12;
+pub const MUL_F64: u8 = 0x13;
+pub const LT_F64: u8 = 0x14;
+pub const LTE_F64: u8 = 0x15;
+pub const GT_F64: u8 = 0x16;
+pub const GTE_F64: u8 = 0x17;
+pub const I64_TO_F64: u8 = 0x18;
+pub const F64_TO_I64: u8 = 0x19;
+pub const STR_CONCAT: u8 = 0x1A;
+pub const STR_BUILDER_NEW: u8 = 0x1B;
+pub const STR_APPEND: u8 = 0x1C;
+pub const STR_APPEND_I64: u8 = 0x1D;
+pub const STR_BUILDER_FINISH: u8 = 0x1E;
+pub const ASSERT: u8 = 0x1F;
+pub const NOP: u8 = 0x20;
+pub const DEBUG_BREAK: u8 = 0x21;
+pub const SWAP: u8 = 0x22;
+pub const SELECT: u8 = 0x23;
+pub const MIN_I64: u8 = 0x24;
+pub const MAX_I64: u8 = 0x25;
+pub const ABS_I64: u8 = 0x26;
+pub const MIN_F64: u8 = 0x27;
+pub const MAX_F64: u8 = 0x28;
+pub const ABS_F64: u8 = 0x29;
+pub const ROUND_F64: u8 = 0x2A;
+pub const FLOOR_F64: u8 = 0x2B;
+pub const CEIL_F64: u8 = 0x2C;
+pub const TRUNC_F64: u8 = 0x2D;
+pub const STR_EQ: u8 = 0x2E;
+pub const STR_HASH: u8 = 0x2F;
+pub const LT_I64_F64: u8 = 0x30;
+pub const LTE_I64_F64: u8 = 0x31;
+pub const GT_I64_F64: u8 = 0x32;
+pub const GTE_I64_F64: u8 = 0x33;
+pub const CALL: u8 = 0x34;
+pub const RET: u8 = 0x35;
+pub const MOV_VALUE: u8 = 0x36;
+pub const MOD_I64: u8 = 0x37;
+pub const POW_I64: u8 = 0x38;
+pub const POW_F64: u8 = 0x39;
+pub const AND_I64: u8 = 0x3A;
+pub const OR_I64: u8 = 0x3B;
+pub const XOR_I64: u8 = 0x3C;
+pub const NOT_I64: u8 = 0x3D;
+pub const SHL_I64: u8 = 0x3E;
+pub const SHR_I64: u8 = 0x3F;
+pub const ASSERT_ENUM_VALUE: u8 = 0x40;
+pub const ASSERT_ENUM_NAME: u8 = 0x41;
+
+/// Version of the opcode numbering and operand layout; bump on incompatible
+/// changes, and when opcodes are added, so a VM that predates them rejects
+/// programs with `NewerIsa` instead of failing on the first new opcode
+pub const ISA_VERSION: u16 = 3;
+
+// Optional instruction set features, recorded as a bitmap in compiled programs
+pub const ISA_FLOAT: u32 = 1 << 0;
+pub const ISA_HOST_CALLS: u32 = 1 << 1;
+pub const ISA_STRINGS: u32 = 1 << 2;
+/// Features this VM can execute; optional families are cargo features
+/// (`isa-float`, `isa-strings`) so minimal builds leave their interpreter arms out
+pub const SUPPORTED_ISA_FEATURES: u32 = ISA_HOST_CALLS
+    | if cfg!(feature = "isa-float") { ISA_FLOAT } else { 0 }
+    | if cfg!(feature = "isa-strings") { ISA_STRINGS } else { 0 };
+
+/// 2^63: rounded floats in `-I64_LIMIT..I64_LIMIT` convert to i64 exactly
+#[cfg(feature = "isa-float")]
+const I64_LIMIT: f64 = 9_223_372_036_854_775_808.0;
+
+/// Exact ordering of an int and a float, `None` if the float is NaN. Every
+/// i64 and f64 is compared by value, so `2^53 + 1` is greater than `2^53 as f64`
+/// even though converting the int to f64 would make them equal.
+#[cfg(feature = "isa-float")]
+pub fn cmp_i64_f64(int: i64, float: f64) -> Option<std::cmp::Ordering> {
+    use std::cmp::Ordering;
+    if float.is_nan() {
+        return None;
+    }
+    if float >= I64_LIMIT {
+        return Some(Ordering::Less);
+    }
+    if float < -I64_LIMIT {
+        return Some(Ordering::Greater);
+    }
+    // In range, so the integral part converts exactly; the fractional part
+    // only breaks a tie, as no int lies strictly between trunc and the float
+    let whole = float.trunc();
+    Some(int.cmp(&(whole as i64)).then_with(|| 0.0.partial_cmp(&(float - whole)).unwrap()))
+}
+
+/// `a % b` as MOD_I64 computes it: the remainder of floor division, with
+/// the sign of `b`. `b` must not be 0; `i64::MIN % -1` is 0.
+pub fn mod_i64(a: i64, b: i64) -> i64 {
+    let rem = a.wrapping_rem(b);
+    if rem != 0 && (rem < 0) != (b < 0) { rem + b } else { rem }
+}
+
+/// `base ** exponent` as POW_I64 computes it, wrapping on overflow like the
+/// other int opcodes
+pub fn pow_i64(mut base: i64, mut exponent: u64) -> i64 {
+    let mut result: i64 = 1;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// `value << count` as SHL_I64 computes it: bits shifted past the top are
+/// lost, so a count of 64 or more gives 0
+pub fn shl_i64(value: i64, count: u64) -> i64 {
+    if count >= 64 { 0 } else { value << count }
+}
+
+/// `value >> count` as SHR_I64 computes it: an arithmetic shift, so a count
+/// of 64 or more gives 0, or -1 for a negative value
+pub fn shr_i64(value: i64, count: u64) -> i64 {
+    value >> count.min(63)
+}
+
+/// Hash STR_HASH computes: 64-bit FNV-1a over the bytes, the same on every
+/// platform and run, so hashes can be stored alongside compiled programs
+pub fn str_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Nested CALLs allowed before a run fails with `VmError::CallDepth`; each
+/// frame holds a full register window, so this also bounds register growth
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// Called by DEBUG_BREAK with the VM and the pc of the instruction
+pub type BreakHook = Arc<dyn Fn(&mut VirtualMachine, usize) + Send + Sync>;
 
 #[derive(Debug)]
 pub enum VmError {
@@ -66,7 +284,51 @@ pub enum VmError {
     InvalidConstIndex(usize),
     UnexpectedEndOfProgram,
     Timeout(std::time::Duration),
+    InstructionLimit(u64),
+    RegisterLimit(usize),
     HostError(String),
+    /// Known opcode whose instruction set feature was not compiled in
+    UnsupportedOpcode(u8),
+    /// STR_APPEND / STR_BUILDER_FINISH without a matching STR_BUILDER_NEW
+    NoStringBuilder,
+    /// `Limits::max_fuel` was used up
+    OutOfFuel(u64),
+    /// A replayed run made a host call the replay log does not match
+    ReplayDivergence(String),
+    /// CALL_HOST with an index no host function is registered under;
+    /// `nearby` lists names registered at neighbouring indices
+    UnknownHostFunction { index: usize, nearby: Vec<String> },
+    /// A host function was called directly with the wrong number of arguments
+    HostFunctionArity {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    /// A host function panicked; the VM is poisoned until `clear_poison`
+    HostPanic(String),
+    /// A previous run ended in a host panic and VM state may be inconsistent
+    Poisoned,
+    /// The script called `exit(code)`
+    Exit(i64),
+    /// ASSERT found its condition register zero
+    AssertionFailed { pc: usize, message: String },
+    /// ROUND_F64 / FLOOR_F64 / CEIL_F64 / TRUNC_F64 on NaN, an infinity, or a
+    /// value whose rounding is outside the i64 range
+    FloatToInt { pc: usize, value: f64 },
+    /// RET executed outside any CALL
+    ReturnWithoutCall(usize),
+    /// CALLs nested deeper than `MAX_CALL_DEPTH`, usually runaway recursion
+    CallDepth(usize),
+    /// MOD_I64 with a zero divisor, at the given pc
+    DivisionByZero(usize),
+    /// POW_I64 with a negative exponent, which has no int result
+    NegativeExponent { pc: usize, exponent: i64 },
+    /// SHL_I64 or SHR_I64 by a negative count
+    NegativeShift { pc: usize, count: i64 },
+    /// ASSERT_ENUM_VALUE failed: no variant of `enum_name` is numbered `value`
+    InvalidEnumValue { pc: usize, enum_name: String, value: i64 },
+    /// ASSERT_ENUM_NAME failed: no variant of `enum_name` is called `name`
+    UnknownEnumName { pc: usize, enum_name: String, name: String },
     // InvalidRegister(u8),
 }
 
@@ -80,7 +342,65 @@ impl fmt::Display for VmError {
             }
             VmError::UnexpectedEndOfProgram => write!(f, "Unexpected end of program"),
             VmError::Timeout(duration) => write!(f, "Execution timeout after {:?}", duration),
+            VmError::InstructionLimit(limit) => {
+                write!(f, "Instruction limit of {} exceeded", limit)
+            }
+            VmError::RegisterLimit(len) => {
+                write!(f, "Register limit exceeded: {} registers requested", len)
+            }
             VmError::HostError(err) => write!(f, "Host error: {}", err),
+            VmError::UnsupportedOpcode(opcode) => {
+                write!(f, "Opcode 0x{:02X} is not compiled into this VM", opcode)
+            }
+            VmError::NoStringBuilder => write!(f, "No string builder is open"),
+            VmError::OutOfFuel(limit) => write!(f, "Out of fuel: budget of {} used up", limit),
+            VmError::ReplayDivergence(msg) => write!(f, "Replay diverged: {}", msg),
+            VmError::UnknownHostFunction { index, nearby } => {
+                write!(f, "Unknown host function index {}", index)?;
+                if nearby.is_empty() {
+                    write!(f, " (no host functions are registered)")
+                } else {
+                    write!(f, " (registered nearby: {})", nearby.join(", "))
+                }
+            }
+            VmError::HostFunctionArity {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{}() takes {} arguments but {} were given",
+                name, expected, found
+            ),
+            VmError::HostPanic(msg) => write!(f, "Host function panicked: {}", msg),
+            VmError::Poisoned => {
+                write!(f, "VM is poisoned by an earlier host panic; reset it before reuse")
+            }
+            VmError::Exit(code) => write!(f, "Script exited with code {}", code),
+            VmError::AssertionFailed { pc, message } => {
+                write!(f, "Assertion failed at pc {}: {}", pc, message)
+            }
+            VmError::FloatToInt { pc, value } => {
+                let value = number_format::format_f64(*value);
+                write!(f, "Cannot convert {} to an int at pc {}", value, pc)
+            }
+            VmError::ReturnWithoutCall(pc) => write!(f, "RET outside a call at pc {}", pc),
+            VmError::CallDepth(limit) => {
+                write!(f, "Call depth limit of {} exceeded", limit)
+            }
+            VmError::DivisionByZero(pc) => write!(f, "Modulo by zero at pc {}", pc),
+            VmError::NegativeExponent { pc, exponent } => {
+                write!(f, "Cannot raise an int to the negative power {} at pc {}", exponent, pc)
+            }
+            VmError::NegativeShift { pc, count } => {
+                write!(f, "Cannot shift by the negative count {} at pc {}", count, pc)
+            }
+            VmError::InvalidEnumValue { pc, enum_name, value } => {
+                write!(f, "Enum {} has no variant with value {} at pc {}", enum_name, value, pc)
+            }
+            VmError::UnknownEnumName { pc, enum_name, name } => {
+                write!(f, "Enum {} has no variant named {:?} at pc {}", enum_name, name, pc)
+            }
             // VmError::InvalidRegister(reg) => write!(f, "Invalid register: {}", reg),
         }
     }
@@ -92,10 +412,50 @@ pub struct VirtualMachine {
     pub registers: Registers,
     pub registers_type: RegisterTypes,
     pub const_pool: ConstPool,
+    /// Strings created while running programs
+    pub strings: StringHeap,
+    /// Whether `strings` are freed at the end of each run
+    pub allocation_scope: AllocationScope,
     pub host_functions: HostFunctionRegistry,
     pub call_stack: Vec<CallInfo>,
     pub base: usize,
     pub global_vars: GlobalVars,
+    pub debug_info: DebugInfo,
+    pub limits: Limits,
+    /// Prices instructions against `limits.max_fuel`
+    pub costs: CostTable,
+    /// Fuel burnt by the current or last `eval_program` call
+    pub fuel_used: u64,
+    pub capabilities: Capabilities,
+    /// Records or replays host call results
+    pub replay: ReplayMode,
+    /// Per-instruction execution counts, while profiling
+    pub profile: Option<Profile>,
+    /// Set when a host function panicked mid-run
+    pub poisoned: bool,
+    /// Calls made to each host function, by function index
+    pub host_calls: Vec<u64>,
+    /// Fuel price of each host function by index, taken from `costs` when
+    /// a metered run starts so CALL_HOST needs no lookup by name
+    pub(crate) host_prices: Vec<u64>,
+    /// Embedder objects scripts hold by handle; see `register_foreign_type`
+    pub foreign: ForeignObjects,
+    /// Timeout applied by `eval_program`
+    pub default_timeout: Option<std::time::Duration>,
+    /// Debugger or trace hook DEBUG_BREAK calls; without one it does nothing
+    pub break_hook: Option<BreakHook>,
+    /// AST rewriters `compile_source` runs between parsing and codegen
+    #[cfg(feature = "frontend")]
+    pub rewriters: Vec<Arc<dyn Rewriter>>,
+    /// Whether `compile_source` keeps `assert(...)` checks and breakpoints
+    #[cfg(feature = "frontend")]
+    pub compile_mode: CompileMode,
+    /// Nesting, token and time bounds on each `compile_source`
+    #[cfg(feature = "frontend")]
+    pub compile_limits: CompileLimits,
+    /// Where `import` finds modules; empty unless the host adds a directory
+    #[cfg(feature = "frontend")]
+    pub modules: ModuleResolver,
 }
 
 impl VirtualMachine {
@@ -104,13 +464,48 @@ impl VirtualMachine {
             registers: Registers::new(),
             registers_type: RegisterTypes::new(),
             const_pool: ConstPool::new(),
+            strings: StringHeap::new(),
+            allocation_scope: AllocationScope::Vm,
             host_functions: HostFunctionRegistry::new(),
             call_stack: vec![CallInfo::Global { base: 0, top: 0 }],
             base: 0,
             global_vars: GlobalVars::new(),
+            debug_info: DebugInfo::new(),
+            limits: Limits::default(),
+            costs: CostTable::new(),
+            fuel_used: 0,
+            capabilities: Capabilities::none(),
+            replay: ReplayMode::Off,
+            profile: None,
+            poisoned: false,
+            host_calls: Vec::new(),
+            host_prices: Vec::new(),
+            foreign: ForeignObjects::new(),
+            default_timeout: None,
+            break_hook: None,
+            #[cfg(feature = "frontend")]
+            rewriters: Vec::new(),
+            #[cfg(feature = "frontend")]
+            compile_mode: CompileMode::Debug,
+            #[cfg(feature = "frontend")]
+            compile_limits: CompileLimits::default(),
+            #[cfg(feature = "frontend")]
+            modules: ModuleResolver::new(),
         }
     }
 
+    /// Have `compile_source` pass parsed programs through `rewriter`, after
+    /// the rewriters added before it
+    #[cfg(feature = "frontend")]
+    pub fn add_rewriter(&mut self, rewriter: impl Rewriter + 'static) {
+        self.rewriters.push(Arc::new(rewriter));
+    }
+
+    /// Start configuring a VM fluently
+    pub fn builder() -> VmBuilder {
+        VmBuilder::new()
+    }
+
     /// Interpret register value as i64
     fn get_i64(&self, reg: usize) -> i64 {
         self.registers.get(reg) as i64
@@ -131,211 +526,225 @@ impl VirtualMachine {
         self.registers.set(reg, value.to_bits());
     }
 
+    /// The string const at `index`, for error messages
+    fn const_str(&self, index: usize) -> Result<String, VmError> {
+        let bytes = self.const_pool.slice(index).ok_or(VmError::InvalidConstIndex(index))?;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    /// String held in the (ptr, len) register pair starting at `reg`
+    #[cfg(feature = "isa-strings")]
+    fn str_operand(&self, reg: usize) -> &'static [u8] {
+        let ptr = self.registers.get(reg) as *const u8;
+        let len = self.registers.get(reg + 1) as usize;
+        if len == 0 {
+            return &[];
+        }
+        // Pairs are only produced by LOAD_CONST_SLICE and string opcodes, whose
+        // storage lives as long as the const pool / string heap
+        unsafe { std::slice::from_raw_parts(ptr, len) }
+    }
+
+    /// Register base of the innermost frame on the call stack
+    fn frame_base(&self) -> usize {
+        match self.call_stack.last() {
+            Some(CallInfo::Global { base, .. })
+            | Some(CallInfo::Call { base, .. })
+            | Some(CallInfo::CallHost { base, .. }) => *base,
+            None => 0,
+        }
+    }
+
+    /// Read a bytecode byte. With `CHECKED == false` the caller guarantees
+    /// `pos` is in bounds because the bytecode passed `verify`.
+    #[inline(always)]
+    fn byte<const CHECKED: bool>(bytecode: &[u8], pos: usize) -> u8 {
+        if CHECKED {
+            bytecode[pos]
+        } else {
+            // SAFETY: verified bytecode has all operands of every reachable
+            // instruction in bounds (see `verifier`)
+            unsafe { *bytecode.get_unchecked(pos) }
+        }
+    }
+
     /// Read a u16 from bytecode at given position (little-endian)
-    fn read_u16(&self, bytecode: &[u8], pos: usize) -> Result<u16, VmError> {
-        if pos + 1 >= bytecode.len() {
+    #[inline(always)]
+    fn read_u16<const CHECKED: bool>(&self, bytecode: &[u8], pos: usize) -> Result<u16, VmError> {
+        if CHECKED && pos + 1 >= bytecode.len() {
             return Err(VmError::UnexpectedEndOfProgram);
         }
-        Ok(u16::from_le_bytes([bytecode[pos], bytecode[pos + 1]]))
+        Ok(u16::from_le_bytes([
+            Self::byte::<CHECKED>(bytecode, pos),
+            Self::byte::<CHECKED>(bytecode, pos + 1),
+        ]))
     }
 
-    /// Execute a single instruction
-    fn execute_instruction(&mut self, bytecode: &[u8], pc: &mut usize) -> Result<(), VmError> {
-        if *pc >= bytecode.len() {
+    /// Execute a single instruction. `CHECKED == false` is the fast path for
+    /// verified bytecode: operand and jump target bounds checks are skipped.
+    fn execute_instruction<const CHECKED: bool>(
+        &mut self,
+        bytecode: &[u8],
+        pc: &mut usize,
+    ) -> Result<(), VmError> {
+        if CHECKED && *pc >= bytecode.len() {
             return Err(VmError::UnexpectedEndOfProgram);
         }
 
-        let opcode = bytecode[*pc];
+        let opcode = Self::byte::<CHECKED>(bytecode, *pc);
         *pc += 1;
 
         match opcode {
-            ADD_I64 => {
-                // Format: [opcode, r1, r2, dst]
-                if *pc + 2 >= bytecode.len() {
+            LOAD_CONST_VALUE => {
+                // Format: [opcode, dst, index[2]]
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let index = self.read_u16::<CHECKED>(bytecode, *pc + 1)? as usize;
                 *pc += 3;
-                let val1 = self.get_i64(r1);
-                let val2 = self.get_i64(r2);
-                self.set_i64(dst, val1.wrapping_add(val2));
+                let value = self
+                    .const_pool
+                    .values
+                    .get(index)
+                    .ok_or(VmError::InvalidConstIndex(index))?;
+                self.registers.set(dst, *value);
             }
-            SUB_I64 => {
-                // Format: [opcode, r1, r2, dst]
-                if *pc + 2 >= bytecode.len() {
+            MOV => {
+                // Format: [opcode, src, dst]
+                if CHECKED && *pc + 1 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
-                *pc += 3;
-                let val1 = self.get_i64(r1);
-                let val2 = self.get_i64(r2);
-                self.set_i64(dst, val1.wrapping_sub(val2));
+                let src = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                *pc += 2;
+                self.registers.set(dst, self.registers.get(src));
+                self.registers_type.set(dst, self.registers_type.get(src));
             }
-            MUL_I64 => {
+            MOV_VALUE => {
+                // Format: [opcode, src, dst]; codegen emits it for registers
+                // it knows hold an int or a float, which need no type tag
+                if CHECKED && *pc + 1 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let src = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                *pc += 2;
+                self.registers.set(dst, self.registers.get(src));
+            }
+            ADD_I64 => {
                 // Format: [opcode, r1, r2, dst]
-                if *pc + 2 >= bytecode.len() {
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
                 *pc += 3;
                 let val1 = self.get_i64(r1);
                 let val2 = self.get_i64(r2);
-                self.set_i64(dst, val1.wrapping_mul(val2));
+                self.set_i64(dst, val1.wrapping_add(val2));
             }
-            GT_I64 => {
+            SUB_I64 => {
                 // Format: [opcode, r1, r2, dst]
-                if *pc + 2 >= bytecode.len() {
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
                 *pc += 3;
                 let val1 = self.get_i64(r1);
                 let val2 = self.get_i64(r2);
-                self.set_i64(dst, if val1 > val2 { 1 } else { 0 });
+                self.set_i64(dst, val1.wrapping_sub(val2));
             }
-            GTE_I64 => {
-                if *pc + 2 >= bytecode.len() {
+            MUL_I64 => {
+                // Format: [opcode, r1, r2, dst]
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
                 *pc += 3;
                 let val1 = self.get_i64(r1);
                 let val2 = self.get_i64(r2);
-                self.set_i64(dst, if val1 >= val2 { 1 } else { 0 });
+                self.set_i64(dst, val1.wrapping_mul(val2));
             }
             LT_I64 => {
-                if *pc + 2 >= bytecode.len() {
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
                 *pc += 3;
                 let val1 = self.get_i64(r1);
                 let val2 = self.get_i64(r2);
                 self.set_i64(dst, if val1 < val2 { 1 } else { 0 });
             }
             LTE_I64 => {
-                if *pc + 2 >= bytecode.len() {
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
                 *pc += 3;
                 let val1 = self.get_i64(r1);
                 let val2 = self.get_i64(r2);
                 self.set_i64(dst, if val1 <= val2 { 1 } else { 0 });
             }
-            ADD_F64 => {
-                // Format: [opcode, r1, r2, dst]
-                if *pc + 2 >= bytecode.len() {
-                    return Err(VmError::UnexpectedEndOfProgram);
-                }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
-                *pc += 3;
-                let val1 = self.get_f64(r1);
-                let val2 = self.get_f64(r2);
-                self.set_f64(dst, val1 + val2);
-            }
-            SUB_F64 => {
-                // Format: [opcode, r1, r2, dst]
-                if *pc + 2 >= bytecode.len() {
-                    return Err(VmError::UnexpectedEndOfProgram);
-                }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
-                *pc += 3;
-                let val1 = self.get_f64(r1);
-                let val2 = self.get_f64(r2);
-                self.set_f64(dst, val1 - val2);
-            }
-            MUL_F64 => {
-                // Format: [opcode, r1, r2, dst]
-                if *pc + 2 >= bytecode.len() {
-                    return Err(VmError::UnexpectedEndOfProgram);
-                }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
-                *pc += 3;
-                let val1 = self.get_f64(r1);
-                let val2 = self.get_f64(r2);
-                self.set_f64(dst, val1 * val2);
-            }
-            GT_F64 => {
+            GT_I64 => {
                 // Format: [opcode, r1, r2, dst]
-                if *pc + 2 >= bytecode.len() {
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
                 *pc += 3;
-                let val1 = self.get_f64(r1);
-                let val2 = self.get_f64(r2);
+                let val1 = self.get_i64(r1);
+                let val2 = self.get_i64(r2);
                 self.set_i64(dst, if val1 > val2 { 1 } else { 0 });
             }
-            GTE_F64 => {
-                if *pc + 2 >= bytecode.len() {
+            GTE_I64 => {
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
                 *pc += 3;
-                let val1 = self.get_f64(r1);
-                let val2 = self.get_f64(r2);
+                let val1 = self.get_i64(r1);
+                let val2 = self.get_i64(r2);
                 self.set_i64(dst, if val1 >= val2 { 1 } else { 0 });
             }
-            LT_F64 => {
-                if *pc + 2 >= bytecode.len() {
+            JMP => {
+                // Format: [opcode, target[2]]
+                if CHECKED && *pc + 1 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
-                *pc += 3;
-                let val1 = self.get_f64(r1);
-                let val2 = self.get_f64(r2);
-                self.set_i64(dst, if val1 < val2 { 1 } else { 0 });
-            }
-            LTE_F64 => {
-                if *pc + 2 >= bytecode.len() {
-                    return Err(VmError::UnexpectedEndOfProgram);
+                let target = self.read_u16::<CHECKED>(bytecode, *pc)? as usize;
+                *pc += 2;
+
+                if CHECKED && target > bytecode.len() {
+                    return Err(VmError::InvalidJumpTarget(target));
                 }
-                let r1 = self.base + bytecode[*pc] as usize;
-                let r2 = self.base + bytecode[*pc + 1] as usize;
-                let dst = self.base + bytecode[*pc + 2] as usize;
-                *pc += 3;
-                let val1 = self.get_f64(r1);
-                let val2 = self.get_f64(r2);
-                self.set_i64(dst, if val1 <= val2 { 1 } else { 0 });
+
+                *pc = target;
             }
             JUMP_FORWARD_IF_FALSE => {
                 // Format: [opcode, cond_reg, target[2]]
-                if *pc + 2 >= bytecode.len() {
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let cond_reg = self.base + bytecode[*pc] as usize;
+                let cond_reg = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
                 *pc += 1;
-                let target = *pc + self.read_u16(bytecode, *pc)? as usize;
+                let target = *pc + self.read_u16::<CHECKED>(bytecode, *pc)? as usize;
                 *pc += 2;
 
-                if target > bytecode.len() {
+                if CHECKED && target > bytecode.len() {
                     return Err(VmError::InvalidJumpTarget(target));
                 }
 
@@ -345,15 +754,15 @@ impl VirtualMachine {
             }
             JUMP_FORWARD_IF_TRUE => {
                 // Format: [opcode, cond_reg, offset[2]]
-                if *pc + 2 >= bytecode.len() {
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let cond_reg = self.base + bytecode[*pc] as usize;
+                let cond_reg = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
                 *pc += 1;
-                let target = *pc + self.read_u16(bytecode, *pc)? as usize;
+                let target = *pc + self.read_u16::<CHECKED>(bytecode, *pc)? as usize;
                 *pc += 2;
 
-                if target > bytecode.len() {
+                if CHECKED && target > bytecode.len() {
                     return Err(VmError::InvalidJumpTarget(target));
                 }
 
@@ -363,15 +772,15 @@ impl VirtualMachine {
             }
             JUMP_BACKWARD_IF_FALSE => {
                 // Format: [opcode, cond_reg, offset[2]]
-                if *pc + 2 >= bytecode.len() {
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let cond_reg = self.base + bytecode[*pc] as usize;
+                let cond_reg = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
                 *pc += 1;
-                let offset = self.read_u16(bytecode, *pc)? as usize;
+                let offset = self.read_u16::<CHECKED>(bytecode, *pc)? as usize;
                 *pc += 2;
 
-                if offset > *pc {
+                if CHECKED && offset > *pc {
                     let invalid_target = (*pc as isize - offset as isize) as usize;
                     return Err(VmError::InvalidJumpTarget(invalid_target));
                 }
@@ -382,15 +791,15 @@ impl VirtualMachine {
             }
             JUMP_BACKWARD_IF_TRUE => {
                 // Format: [opcode, cond_reg, offset[2]]
-                if *pc + 2 >= bytecode.len() {
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let cond_reg = self.base + bytecode[*pc] as usize;
+                let cond_reg = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
                 *pc += 1;
-                let offset = self.read_u16(bytecode, *pc)? as usize;
+                let offset = self.read_u16::<CHECKED>(bytecode, *pc)? as usize;
                 *pc += 2;
 
-                if offset > *pc {
+                if CHECKED && offset > *pc {
                     let invalid_target = (*pc as isize - offset as isize) as usize;
                     return Err(VmError::InvalidJumpTarget(invalid_target));
                 }
@@ -399,69 +808,17 @@ impl VirtualMachine {
                     *pc -= offset;
                 }
             }
-            JMP => {
-                // Format: [opcode, target[2]]
-                if *pc + 1 >= bytecode.len() {
-                    return Err(VmError::UnexpectedEndOfProgram);
-                }
-                let target = self.read_u16(bytecode, *pc)? as usize;
-                *pc += 2;
-
-                if target > bytecode.len() {
-                    return Err(VmError::InvalidJumpTarget(target));
-                }
-
-                *pc = target;
-            }
-            I64_TO_F64 => {
-                // Format: [opcode, src, dst]
-                if *pc + 1 >= bytecode.len() {
-                    return Err(VmError::UnexpectedEndOfProgram);
-                }
-                let src = self.base + bytecode[*pc] as usize;
-                let dst = self.base + bytecode[*pc + 1] as usize;
-                *pc += 2;
-                let i64_val = self.get_i64(src);
-                self.set_f64(dst, i64_val as f64);
-            }
-            F64_TO_I64 => {
-                // Format: [opcode, src, dst]
-                if *pc + 1 >= bytecode.len() {
-                    return Err(VmError::UnexpectedEndOfProgram);
-                }
-                let src = self.base + bytecode[*pc] as usize;
-                let dst = self.base + bytecode[*pc + 1] as usize;
-                *pc += 2;
-                let f64_val = self.get_f64(src);
-                self.set_i64(dst, f64_val as i64);
-            }
-            LOAD_CONST_VALUE => {
-                // Format: [opcode, dst, index[2]]
-                if *pc + 2 >= bytecode.len() {
-                    return Err(VmError::UnexpectedEndOfProgram);
-                }
-                let dst = self.base + bytecode[*pc] as usize;
-                let index = self.read_u16(bytecode, *pc + 1)? as usize;
-                *pc += 3;
-                let value = self
-                    .const_pool
-                    .values
-                    .get(index)
-                    .ok_or(VmError::InvalidConstIndex(index))?;
-                self.registers.set(dst, *value);
-            }
             LOAD_CONST_SLICE => {
                 // Format: [opcode, dst, index[2]]
-                if *pc + 2 >= bytecode.len() {
+                if CHECKED && *pc + 2 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let dst = self.base + bytecode[*pc] as usize;
-                let index = self.read_u16(bytecode, *pc + 1)? as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let index = self.read_u16::<CHECKED>(bytecode, *pc + 1)? as usize;
                 *pc += 3;
                 let slice = self
                     .const_pool
-                    .slices
-                    .get(index)
+                    .slice(index)
                     .ok_or(VmError::InvalidConstIndex(index))?;
                 let ptr = slice.as_ptr() as u64;
                 let len = slice.len() as u64;
@@ -478,46 +835,566 @@ impl VirtualMachine {
                 }
             }
             CALL_HOST => {
-                if *pc + 1 >= bytecode.len() {
+                if CHECKED && *pc + 1 >= bytecode.len() {
                     return Err(VmError::UnexpectedEndOfProgram);
                 }
-                let reg_index = self.read_u16(bytecode, *pc)? as usize;
+                let reg_index = self.read_u16::<CHECKED>(bytecode, *pc)? as usize;
                 *pc += 2;
                 let abs_index = self.base + reg_index;
                 let fn_index = self.registers.get(abs_index) as usize;
-                let func = self
-                    .host_functions
-                    .funcs
-                    .get(fn_index)
-                    .ok_or(VmError::InvalidConstIndex(fn_index))?;
-                let meta = self
-                    .host_functions
-                    .metadata
-                    .get(fn_index)
-                    .ok_or(VmError::InvalidConstIndex(fn_index))?;
+                if self.limits.max_fuel.is_some() {
+                    self.charge_fuel(self.host_prices.get(fn_index).copied().unwrap_or(0))?;
+                }
+                let (Some(func), Some(meta)) = (
+                    self.host_functions.funcs.get(fn_index),
+                    self.host_functions.metadata.get(fn_index),
+                ) else {
+                    return Err(self.host_functions.unknown(fn_index));
+                };
+                if fn_index >= self.host_calls.len() {
+                    self.host_calls.resize(fn_index + 1, 0);
+                }
+                self.host_calls[fn_index] += 1;
                 let base = abs_index;
                 let top = base + meta.num_registers.saturating_sub(1);
+                if let Some(max) = self.limits.max_registers
+                    && top + 1 > max
+                {
+                    return Err(VmError::RegisterLimit(top + 1));
+                }
                 self.call_stack.push(CallInfo::CallHost {
                     base,
                     top,
                     host_fn_index: fn_index,
                 });
+                if let Some(profile) = &mut self.profile {
+                    profile.enter_frame(self.call_stack.len() - 1, top);
+                }
                 self.base = base;
                 self.registers.ensure_len(top + 1);
                 self.registers_type.ensure_len(top + 1);
-                let result = func(base, &mut self.registers);
+                let result = self.replay.call(func, meta.name, &mut self.registers, base, top);
                 self.call_stack.pop();
-                if let Some(info) = self.call_stack.last() {
-                    self.base = match info {
-                        CallInfo::Global { base, .. } => *base,
-                        CallInfo::Call { base, .. } => *base,
-                        CallInfo::CallHost { base, .. } => *base,
-                    };
+                self.base = self.frame_base();
+                if let Err(err) = result? {
+                    return Err(VmError::HostError(err));
+                }
+            }
+            CALL => {
+                // Format: [opcode, target[2], base]
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let target = self.read_u16::<CHECKED>(bytecode, *pc)? as usize;
+                let base = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                if CHECKED && target > bytecode.len() {
+                    return Err(VmError::InvalidJumpTarget(target));
+                }
+                if self.call_stack.len() > MAX_CALL_DEPTH {
+                    return Err(VmError::CallDepth(MAX_CALL_DEPTH));
+                }
+                // The callee gets a whole register window; its r0 is the
+                // caller's `base` and receives the return value
+                let top = base + Registers::FIXED_COUNT - 1;
+                if let Some(max) = self.limits.max_registers
+                    && top + 1 > max
+                {
+                    return Err(VmError::RegisterLimit(top + 1));
+                }
+                self.registers.ensure_len(top + 1);
+                self.registers_type.ensure_len(top + 1);
+                self.call_stack.push(CallInfo::Call {
+                    base,
+                    top,
+                    entry: target,
+                    return_pc: *pc,
+                });
+                if let Some(profile) = &mut self.profile {
+                    profile.enter_frame(self.call_stack.len() - 1, top);
+                }
+                self.base = base;
+                *pc = target;
+            }
+            RET => {
+                // Format: [opcode]
+                let Some(&CallInfo::Call { return_pc, .. }) = self.call_stack.last() else {
+                    return Err(VmError::ReturnWithoutCall(*pc - 1));
+                };
+                self.call_stack.pop();
+                self.base = self.frame_base();
+                *pc = return_pc;
+            }
+            #[cfg(feature = "isa-float")]
+            ADD_F64 => {
+                // Format: [opcode, r1, r2, dst]
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let val1 = self.get_f64(r1);
+                let val2 = self.get_f64(r2);
+                self.set_f64(dst, val1 + val2);
+            }
+            #[cfg(feature = "isa-float")]
+            SUB_F64 => {
+                // Format: [opcode, r1, r2, dst]
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let val1 = self.get_f64(r1);
+                let val2 = self.get_f64(r2);
+                self.set_f64(dst, val1 - val2);
+            }
+            #[cfg(feature = "isa-float")]
+            MUL_F64 => {
+                // Format: [opcode, r1, r2, dst]
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let val1 = self.get_f64(r1);
+                let val2 = self.get_f64(r2);
+                self.set_f64(dst, val1 * val2);
+            }
+            #[cfg(feature = "isa-float")]
+            LT_F64 => {
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let val1 = self.get_f64(r1);
+                let val2 = self.get_f64(r2);
+                self.set_i64(dst, if val1 < val2 { 1 } else { 0 });
+            }
+            #[cfg(feature = "isa-float")]
+            LTE_F64 => {
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let val1 = self.get_f64(r1);
+                let val2 = self.get_f64(r2);
+                self.set_i64(dst, if val1 <= val2 { 1 } else { 0 });
+            }
+            #[cfg(feature = "isa-float")]
+            GT_F64 => {
+                // Format: [opcode, r1, r2, dst]
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let val1 = self.get_f64(r1);
+                let val2 = self.get_f64(r2);
+                self.set_i64(dst, if val1 > val2 { 1 } else { 0 });
+            }
+            #[cfg(feature = "isa-float")]
+            GTE_F64 => {
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let val1 = self.get_f64(r1);
+                let val2 = self.get_f64(r2);
+                self.set_i64(dst, if val1 >= val2 { 1 } else { 0 });
+            }
+            #[cfg(feature = "isa-float")]
+            I64_TO_F64 => {
+                // Format: [opcode, src, dst]
+                if CHECKED && *pc + 1 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let src = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                *pc += 2;
+                let i64_val = self.get_i64(src);
+                self.set_f64(dst, i64_val as f64);
+            }
+            #[cfg(feature = "isa-float")]
+            F64_TO_I64 => {
+                // Format: [opcode, src, dst]; truncates and saturates, with NaN
+                // giving 0. ROUND_F64 and friends report those cases instead.
+                if CHECKED && *pc + 1 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let src = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                *pc += 2;
+                let f64_val = self.get_f64(src);
+                self.set_i64(dst, f64_val as i64);
+            }
+            #[cfg(feature = "isa-float")]
+            LT_I64_F64 | LTE_I64_F64 | GT_I64_F64 | GTE_I64_F64 => {
+                // Format: [opcode, int_reg, float_reg, dst]; exact, unlike
+                // comparing after I64_TO_F64, and false whenever the float is NaN
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let result = cmp_i64_f64(self.get_i64(r1), self.get_f64(r2)).is_some_and(|ord| {
+                    match opcode {
+                        LT_I64_F64 => ord.is_lt(),
+                        LTE_I64_F64 => ord.is_le(),
+                        GT_I64_F64 => ord.is_gt(),
+                        _ => ord.is_ge(),
+                    }
+                });
+                self.set_i64(dst, result as i64);
+            }
+            #[cfg(feature = "isa-float")]
+            ROUND_F64 | FLOOR_F64 | CEIL_F64 | TRUNC_F64 => {
+                // Format: [opcode, src, dst]; ROUND_F64 rounds half to even, as Python does
+                if CHECKED && *pc + 1 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let src = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                *pc += 2;
+                let value = self.get_f64(src);
+                let rounded = match opcode {
+                    ROUND_F64 => value.round_ties_even(),
+                    FLOOR_F64 => value.floor(),
+                    CEIL_F64 => value.ceil(),
+                    _ => value.trunc(),
+                };
+                // i64::MIN is exactly -2^63 as an f64; NaN fails the check too
+                if !(-I64_LIMIT..I64_LIMIT).contains(&rounded) {
+                    return Err(VmError::FloatToInt { pc: *pc - 3, value });
+                }
+                self.set_i64(dst, rounded as i64);
+            }
+            #[cfg(feature = "isa-strings")]
+            STR_CONCAT => {
+                // Format: [opcode, r1, r2, dst]; each operand is a (ptr, len) pair
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let left = self.str_operand(r1);
+                let right = self.str_operand(r2);
+                let len = left.len() + right.len();
+                let ptr = self.strings.alloc_concat(&[left, right]);
+                self.registers.set(dst, ptr as u64);
+                self.registers.set(dst + 1, len as u64);
+                self.registers_type.set(dst, RegisterType::HeapStrMain);
+                self.registers_type.set(dst + 1, RegisterType::HeapStrLen);
+            }
+            #[cfg(feature = "isa-strings")]
+            STR_EQ => {
+                // Format: [opcode, r1, r2, dst]; compares bytes, so a const
+                // slice and a heap string with the same text are equal
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let equal = self.str_operand(r1) == self.str_operand(r2);
+                self.set_i64(dst, equal as i64);
+            }
+            #[cfg(feature = "isa-strings")]
+            STR_HASH => {
+                // Format: [opcode, src, dst]; src is a (ptr, len) pair
+                if CHECKED && *pc + 1 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let src = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                *pc += 2;
+                let hash = str_hash(self.str_operand(src));
+                self.set_i64(dst, hash as i64);
+            }
+            #[cfg(feature = "isa-strings")]
+            STR_BUILDER_NEW => {
+                // Format: [opcode]
+                self.strings.begin_builder();
+            }
+            #[cfg(feature = "isa-strings")]
+            STR_APPEND | STR_APPEND_I64 => {
+                // Format: [opcode, src]; STR_APPEND reads a (ptr, len) pair
+                if CHECKED && *pc >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let src = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                *pc += 1;
+                let appended = if opcode == STR_APPEND {
+                    let data = self.str_operand(src);
+                    self.strings.append(data)
                 } else {
-                    self.base = 0;
+                    let text = self.get_i64(src).to_string();
+                    self.strings.append(text.as_bytes())
+                };
+                if !appended {
+                    return Err(VmError::NoStringBuilder);
                 }
-                if let Err(err) = result {
-                    return Err(VmError::HostError(err));
+            }
+            #[cfg(feature = "isa-strings")]
+            STR_BUILDER_FINISH => {
+                // Format: [opcode, dst]
+                if CHECKED && *pc >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                *pc += 1;
+                let (ptr, len) = self
+                    .strings
+                    .finish_builder()
+                    .ok_or(VmError::NoStringBuilder)?;
+                self.registers.set(dst, ptr as u64);
+                self.registers.set(dst + 1, len as u64);
+                self.registers_type.set(dst, RegisterType::HeapStrMain);
+                self.registers_type.set(dst + 1, RegisterType::HeapStrLen);
+            }
+            ASSERT => {
+                // Format: [opcode, cond_reg, message_index[2]]
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let cond_reg = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let index = self.read_u16::<CHECKED>(bytecode, *pc + 1)? as usize;
+                *pc += 3;
+                if self.registers.get(cond_reg) == 0 {
+                    return Err(VmError::AssertionFailed {
+                        pc: *pc - 4,
+                        message: self.const_str(index)?,
+                    });
+                }
+            }
+            ASSERT_ENUM_VALUE => {
+                // Format: [opcode, cond_reg, value, enum_name_index[2]]
+                if CHECKED && *pc + 3 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let cond_reg = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let value = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let index = self.read_u16::<CHECKED>(bytecode, *pc + 2)? as usize;
+                *pc += 4;
+                if self.registers.get(cond_reg) == 0 {
+                    return Err(VmError::InvalidEnumValue {
+                        pc: *pc - 5,
+                        enum_name: self.const_str(index)?,
+                        value: self.get_i64(value),
+                    });
+                }
+            }
+            #[cfg(feature = "isa-strings")]
+            ASSERT_ENUM_NAME => {
+                // Format: [opcode, cond_reg, name, enum_name_index[2]]; name
+                // is a (ptr, len) pair
+                if CHECKED && *pc + 3 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let cond_reg = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let name = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let index = self.read_u16::<CHECKED>(bytecode, *pc + 2)? as usize;
+                *pc += 4;
+                if self.registers.get(cond_reg) == 0 {
+                    return Err(VmError::UnknownEnumName {
+                        pc: *pc - 5,
+                        enum_name: self.const_str(index)?,
+                        name: String::from_utf8_lossy(self.str_operand(name)).into_owned(),
+                    });
+                }
+            }
+            SWAP => {
+                // Format: [opcode, r1, r2]
+                if CHECKED && *pc + 1 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                *pc += 2;
+                let (v1, v2) = (self.registers.get(r1), self.registers.get(r2));
+                self.registers.set(r1, v2);
+                self.registers.set(r2, v1);
+                let (t1, t2) = (self.registers_type.get(r1), self.registers_type.get(r2));
+                self.registers_type.set(r1, t2);
+                self.registers_type.set(r2, t1);
+            }
+            SELECT => {
+                // Format: [opcode, cond, a, b, dst]; dst = cond != 0 ? a : b
+                if CHECKED && *pc + 3 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let cond = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let a = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let b = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 3) as usize;
+                *pc += 4;
+                let src = if self.registers.get(cond) != 0 { a } else { b };
+                self.registers.set(dst, self.registers.get(src));
+                self.registers_type.set(dst, self.registers_type.get(src));
+            }
+            MIN_I64 | MAX_I64 => {
+                // Format: [opcode, r1, r2, dst]
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let (val1, val2) = (self.get_i64(r1), self.get_i64(r2));
+                self.set_i64(dst, if opcode == MIN_I64 { val1.min(val2) } else { val1.max(val2) });
+            }
+            MOD_I64 => {
+                // Format: [opcode, r1, r2, dst]; the result takes the sign of
+                // the divisor, as in Python, so `-7 % 3` is 2
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let (val1, val2) = (self.get_i64(r1), self.get_i64(r2));
+                if val2 == 0 {
+                    return Err(VmError::DivisionByZero(*pc - 4));
+                }
+                self.set_i64(dst, mod_i64(val1, val2));
+            }
+            POW_I64 => {
+                // Format: [opcode, base, exponent, dst]; wraps on overflow
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let (val1, val2) = (self.get_i64(r1), self.get_i64(r2));
+                if val2 < 0 {
+                    return Err(VmError::NegativeExponent { pc: *pc - 4, exponent: val2 });
+                }
+                self.set_i64(dst, pow_i64(val1, val2 as u64));
+            }
+            AND_I64 | OR_I64 | XOR_I64 => {
+                // Format: [opcode, r1, r2, dst]
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let (val1, val2) = (self.get_i64(r1), self.get_i64(r2));
+                let result = match opcode {
+                    AND_I64 => val1 & val2,
+                    OR_I64 => val1 | val2,
+                    _ => val1 ^ val2,
+                };
+                self.set_i64(dst, result);
+            }
+            NOT_I64 => {
+                // Format: [opcode, src, dst]
+                if CHECKED && *pc + 1 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let src = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                *pc += 2;
+                self.set_i64(dst, !self.get_i64(src));
+            }
+            SHL_I64 | SHR_I64 => {
+                // Format: [opcode, value, count, dst]; see `shl_i64` and `shr_i64`
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let (val1, val2) = (self.get_i64(r1), self.get_i64(r2));
+                if val2 < 0 {
+                    return Err(VmError::NegativeShift { pc: *pc - 4, count: val2 });
+                }
+                let count = val2 as u64;
+                let result = if opcode == SHL_I64 { shl_i64(val1, count) } else { shr_i64(val1, count) };
+                self.set_i64(dst, result);
+            }
+            ABS_I64 => {
+                // Format: [opcode, src, dst]; i64::MIN stays i64::MIN, like
+                // the wrapping arithmetic opcodes
+                if CHECKED && *pc + 1 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let src = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                *pc += 2;
+                self.set_i64(dst, self.get_i64(src).wrapping_abs());
+            }
+            #[cfg(feature = "isa-float")]
+            MIN_F64 | MAX_F64 => {
+                // Format: [opcode, r1, r2, dst]; a NaN operand yields the other one
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                let (val1, val2) = (self.get_f64(r1), self.get_f64(r2));
+                self.set_f64(dst, if opcode == MIN_F64 { val1.min(val2) } else { val1.max(val2) });
+            }
+            #[cfg(feature = "isa-float")]
+            POW_F64 => {
+                // Format: [opcode, base, exponent, dst]
+                if CHECKED && *pc + 2 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let r1 = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let r2 = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 2) as usize;
+                *pc += 3;
+                self.set_f64(dst, self.get_f64(r1).powf(self.get_f64(r2)));
+            }
+            #[cfg(feature = "isa-float")]
+            ABS_F64 => {
+                // Format: [opcode, src, dst]
+                if CHECKED && *pc + 1 >= bytecode.len() {
+                    return Err(VmError::UnexpectedEndOfProgram);
+                }
+                let src = self.base + Self::byte::<CHECKED>(bytecode, *pc) as usize;
+                let dst = self.base + Self::byte::<CHECKED>(bytecode, *pc + 1) as usize;
+                *pc += 2;
+                self.set_f64(dst, self.get_f64(src).abs());
+            }
+            NOP => {
+                // Format: [opcode]
+            }
+            DEBUG_BREAK => {
+                // Format: [opcode]
+                if let Some(hook) = self.break_hook.clone() {
+                    hook(self, *pc - 1);
                 }
             }
             _ => {
@@ -528,9 +1405,9 @@ impl VirtualMachine {
         Ok(())
     }
 
-    /// Execute a program from bytecode without timeout
+    /// Execute a program from bytecode using the VM's default timeout
     pub fn eval_program(&mut self, bytecode: &[u8]) -> Result<(), VmError> {
-        self.eval_program_with_timeout(bytecode, None)
+        self.eval_program_with_timeout(bytecode, self.default_timeout)
     }
 
     /// Execute a program from bytecode with optional timeout
@@ -539,25 +1416,154 @@ impl VirtualMachine {
         bytecode: &[u8],
         timeout: Option<std::time::Duration>,
     ) -> Result<(), VmError> {
-        let mut pc = 0usize;
+        self.run::<true>(bytecode, 0, timeout)
+    }
+
+    /// Execute bytecode approved by `verify` on the fast path, using the
+    /// VM's default timeout
+    pub fn eval_verified(&mut self, program: VerifiedBytecode) -> Result<(), VmError> {
+        self.run::<false>(program.bytecode(), 0, self.default_timeout)
+    }
+
+    /// Execute a multi-function image from its entry function
+    pub fn eval_image(&mut self, image: &ProgramImage) -> Result<(), VmError> {
+        self.run::<true>(&image.bytecode, image.entry as usize, self.default_timeout)
+    }
+
+    fn run<const CHECKED: bool>(
+        &mut self,
+        bytecode: &[u8],
+        start: usize,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<(), VmError> {
+        if self.poisoned {
+            return Err(VmError::Poisoned);
+        }
+        let (depth, base) = (self.call_stack.len(), self.base);
+        let result = match &mut self.profile {
+            None => self.run_loop::<CHECKED>(bytecode, start, timeout),
+            Some(profile) => {
+                profile.start(bytecode.len());
+                let started = Instant::now();
+                let result = self.run_loop::<CHECKED>(bytecode, start, timeout);
+                if let Some(profile) = &mut self.profile {
+                    profile.elapsed += started.elapsed();
+                }
+                result
+            }
+        };
+        if result.is_err() {
+            // Drop the frames of calls the error left unfinished
+            self.call_stack.truncate(depth);
+            self.base = base;
+        }
+        if self.allocation_scope == AllocationScope::Execution && depth == 1 {
+            self.free_run_strings();
+        }
+        result
+    }
+
+    /// End of a run under `AllocationScope::Execution`: free its strings,
+    /// moving those globals hold into the emptied heap. Any other register
+    /// typed as a heap string is reset to the empty string, so a later run
+    /// reading it never sees freed or reused memory.
+    fn free_run_strings(&mut self) {
+        let mut registers: Vec<usize> = self
+            .global_vars
+            .iter()
+            .filter(|(_, var)| matches!(var.meta.typ, GlobalVarType::Ptr(_)))
+            .map(|(_, var)| var.register_id)
+            .collect();
+        registers.sort_unstable();
+        let dangling: Vec<usize> = (0..Registers::FIXED_COUNT + self.registers.spill_len())
+            .filter(|&reg| {
+                self.registers_type.get(reg) == RegisterType::HeapStrMain
+                    && registers.binary_search(&reg).is_err()
+            })
+            .collect();
+        let mut kept: Vec<(u64, u64)> = registers
+            .iter()
+            .map(|&reg| (self.registers.get(reg), self.registers.get(reg + 1)))
+            .collect();
+        self.strings.retain_only(&mut kept);
+        for (reg, (ptr, _)) in registers.into_iter().zip(kept) {
+            self.registers.set(reg, ptr);
+        }
+        for reg in dangling {
+            self.registers.set(reg, "".as_ptr() as u64);
+            self.registers.set(reg + 1, 0);
+            self.registers_type.set(reg, RegisterType::ConstSliceVarMain);
+            self.registers_type.set(reg + 1, RegisterType::ConstSliceVarLen);
+        }
+    }
+
+    fn run_loop<const CHECKED: bool>(
+        &mut self,
+        bytecode: &[u8],
+        start: usize,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<(), VmError> {
+        let mut pc = start;
         let start_time = Instant::now();
         let mut instruction_count = 0u64;
+        self.fuel_used = 0;
+        if self.limits.max_fuel.is_some() {
+            let (metadata, costs) = (&self.host_functions.metadata, &self.costs);
+            self.host_prices.clear();
+            self.host_prices
+                .extend(metadata.iter().map(|meta| costs.host_function_cost(meta.name)));
+        }
+        // Instruction start offsets, computed once a jump executes so
+        // straight-line programs don't pay for it
+        let mut boundaries: Option<Vec<bool>> = None;
 
         // Check timeout every N instructions to balance performance and responsiveness
         const TIMEOUT_CHECK_INTERVAL: u64 = 1000;
 
         while pc < bytecode.len() {
-            self.execute_instruction(bytecode, &mut pc)?;
+            if let Some(limit) = self.limits.max_instructions
+                && instruction_count >= limit
+            {
+                return Err(VmError::InstructionLimit(limit));
+            }
+            if self.limits.max_fuel.is_some() {
+                self.charge_fuel(self.costs.opcode_cost(bytecode[pc]))?;
+            }
+            if let Some(profile) = &mut self.profile {
+                profile.counts[pc] += 1;
+            }
+
+            let opcode = bytecode[pc];
+            if let Err(err) = self.execute_instruction::<CHECKED>(bytecode, &mut pc) {
+                if matches!(err, VmError::HostPanic(_)) {
+                    self.poisoned = true;
+                }
+                return Err(err);
+            }
+            // Verified bytecode only jumps to instruction starts
+            if CHECKED
+                && matches!(
+                    opcode,
+                    JMP | CALL
+                        | JUMP_FORWARD_IF_FALSE
+                        | JUMP_FORWARD_IF_TRUE
+                        | JUMP_BACKWARD_IF_FALSE
+                        | JUMP_BACKWARD_IF_TRUE
+                )
+                && !boundaries.get_or_insert_with(|| instruction_boundaries(bytecode))[pc]
+            {
+                return Err(VmError::InvalidJumpTarget(pc));
+            }
 
             instruction_count += 1;
 
             // Periodically check for timeout to avoid overhead on every instruction
-            if let Some(timeout_duration) = timeout {
-                if instruction_count % TIMEOUT_CHECK_INTERVAL == 0 {
-                    let elapsed = start_time.elapsed();
-                    if elapsed > timeout_duration {
-                        return Err(VmError::Timeout(elapsed));
-                    }
+            if let Some(timeout_duration) = timeout
+                && instruction_count.is_multiple_of(TIMEOUT_CHECK_INTERVAL)
+            {
+                let elapsed = start_time.elapsed();
+                if elapsed > timeout_duration {
+                    return Err(VmError::Timeout(elapsed));
                 }
             }
         }
@@ -565,6 +1571,56 @@ impl VirtualMachine {
         Ok(())
     }
 
+    /// Call host function `fn_index` from Rust with integer arguments,
+    /// returning the value it leaves in its return register
+    pub fn call_host_function(&mut self, fn_index: usize, args: &[u64]) -> Result<u64, VmError> {
+        if self.poisoned {
+            return Err(VmError::Poisoned);
+        }
+        let (Some(func), Some(meta)) = (
+            self.host_functions.funcs.get(fn_index),
+            self.host_functions.metadata.get(fn_index),
+        ) else {
+            return Err(self.host_functions.unknown(fn_index));
+        };
+        if args.len() != meta.num_params {
+            return Err(VmError::HostFunctionArity {
+                name: meta.name.to_string(),
+                expected: meta.num_params,
+                found: args.len(),
+            });
+        }
+        // Above the registers bytecode in the global frame can address
+        let base = Registers::FIXED_COUNT;
+        let top = base + meta.num_registers.max(args.len() + 1) - 1;
+        self.registers.ensure_len(top + 1);
+        for (i, arg) in args.iter().enumerate() {
+            self.registers.set(base + 1 + i, *arg);
+        }
+        let result = self.replay.call(func, meta.name, &mut self.registers, base, top);
+        if let Err(VmError::HostPanic(_)) = result {
+            self.poisoned = true;
+        }
+        result?.map_err(VmError::HostError)?;
+        Ok(self.registers.get(base))
+    }
+
+    /// Allow running again after a host panic, once the embedder has reset
+    /// or inspected whatever state the panicking call may have left behind
+    pub fn clear_poison(&mut self) {
+        self.poisoned = false;
+        self.call_stack.truncate(1);
+        self.base = 0;
+    }
+
+    fn charge_fuel(&mut self, cost: u64) -> Result<(), VmError> {
+        self.fuel_used = self.fuel_used.saturating_add(cost);
+        match self.limits.max_fuel {
+            Some(max) if self.fuel_used > max => Err(VmError::OutOfFuel(max)),
+            _ => Ok(()),
+        }
+    }
+
     /// Get register value as i64
     pub fn get_register_i64(&self, reg: usize) -> i64 {
         self.get_i64(reg)
@@ -604,6 +1660,69 @@ impl VirtualMachine {
     pub fn set_register_type(&mut self, reg: usize, typ: RegisterType) {
         self.registers_type.set(reg, typ);
     }
+
+    /// Render help for a name: signature from host metadata or global type, then docstring
+    pub fn help_text(&self, name: &str) -> String {
+        let header = if let Some(index) = self.host_functions.find(name) {
+            Some(self.host_functions.metadata[index].signature())
+        } else if let Some(signature) = self.debug_info.signature(name) {
+            Some(signature.to_string())
+        } else {
+            self.global_vars
+                .get(name)
+                .map(|var| format!("{}: {:?}", name, var.meta.typ))
+        };
+        let doc = self.debug_info.doc(name);
+        match (header, doc) {
+            (Some(header), Some(doc)) => format!("{}\n\n{}", header, doc),
+            (Some(header), None) => header,
+            (None, Some(doc)) => doc.to_string(),
+            (None, None) => format!("No help available for '{}'", name),
+        }
+    }
+
+    /// Current value of global `name` for display, with strings quoted
+    pub fn format_global(&self, name: &str) -> Option<String> {
+        let var = self.global_vars.get(name)?;
+        let raw = self.registers.get(var.register_id);
+        let slice = || self.global_slice(name).unwrap_or_default();
+        Some(match var.meta.typ {
+            GlobalVarType::Value(ValueType::F64) => number_format::format_f64(f64::from_bits(raw)),
+            GlobalVarType::Value(ValueType::Bool) => {
+                (if raw != 0 { "True" } else { "False" }).to_string()
+            }
+            GlobalVarType::Value(ValueType::List) => format!("<list at {:#x}>", raw),
+            GlobalVarType::Value(ValueType::Dict) => format!("<dict at {:#x}>", raw),
+            GlobalVarType::Value(_) => (raw as i64).to_string(),
+            GlobalVarType::Ptr(PtrType::Slice(SliceType::Binary)) => {
+                let escaped: String = slice()
+                    .iter()
+                    .flat_map(|b| std::ascii::escape_default(*b))
+                    .map(char::from)
+                    .collect();
+                format!("b\"{}\"", escaped)
+            }
+            GlobalVarType::Ptr(PtrType::Slice(SliceType::Utf8Str)) => {
+                format!("{:?}", String::from_utf8_lossy(slice()))
+            }
+        })
+    }
+
+    /// Contents of a string or bytes global
+    pub fn global_slice(&self, name: &str) -> Option<&[u8]> {
+        let var = self.global_vars.get(name)?;
+        if !matches!(var.meta.typ, GlobalVarType::Ptr(_)) {
+            return None;
+        }
+        let len = self.registers.get(var.register_id + 1) as usize;
+        if len == 0 {
+            return Some(&[]);
+        }
+        let ptr = self.registers.get(var.register_id) as *const u8;
+        // Slice globals point into the const pool or string heap, which
+        // outlive the run
+        Some(unsafe { std::slice::from_raw_parts(ptr, len) })
+    }
 }
 
 impl Default for VirtualMachine {
@@ -0,0 +1,125 @@
+//! Number formatting and parsing shared by `print`, the builtins and the
+//! const dump.
+//!
+//! Floats are printed with the fewest digits that parse back to the same
+//! value (`0.1` prints as `0.1`, not `0.1000000000000000055...`), using
+//! Python's `repr` layout: fixed notation for exponents in `-4..16`,
+//! scientific (`1e+16`, `2.5e-05`) otherwise, and a trailing `.0` on
+//! integral values so floats never look like ints. The digits come from
+//! Rust's own shortest round-trip formatting, so output is identical on
+//! every platform.
+
+/// `print` length marking base+1 as f64 bits rather than a string
+pub const PRINT_F64: u64 = u64::MAX;
+
+/// Format `value` as the shortest string that round-trips
+pub fn format_f64(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    }
+    if value.is_infinite() {
+        return if value < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    if value == 0.0 {
+        return if value.is_sign_negative() { "-0.0" } else { "0.0" }.to_string();
+    }
+    // `{:e}` gives the shortest digits as `d.ddde<exp>`
+    let sci = format!("{:e}", value.abs());
+    let (mantissa, exp) = sci.split_once('e').expect("`{:e}` always has an exponent");
+    let exp: i32 = exp.parse().expect("`{:e}` exponent is an integer");
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let sign = if value < 0.0 { "-" } else { "" };
+
+    if (-4..16).contains(&exp) {
+        let point = exp + 1;
+        let body = if point <= 0 {
+            format!("0.{}{}", "0".repeat(-point as usize), digits)
+        } else if point as usize >= digits.len() {
+            format!("{}{}.0", digits, "0".repeat(point as usize - digits.len()))
+        } else {
+            let (int, frac) = digits.split_at(point as usize);
+            format!("{}.{}", int, frac)
+        };
+        format!("{}{}", sign, body)
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let mantissa = if rest.is_empty() {
+            first.to_string()
+        } else {
+            format!("{}.{}", first, rest)
+        };
+        let exp_sign = if exp < 0 { '-' } else { '+' };
+        format!("{}{}e{}{:02}", sign, mantissa, exp_sign, exp.abs())
+    }
+}
+
+/// Format `value` in base 2, 8 or 16 with Python's prefixes (`0b`, `0o`,
+/// `0x`) and a leading `-` for negatives
+pub fn format_radix(value: i64, radix: u32) -> String {
+    let magnitude = value.unsigned_abs();
+    let digits = match radix {
+        2 => format!("0b{:b}", magnitude),
+        8 => format!("0o{:o}", magnitude),
+        16 => format!("0x{:x}", magnitude),
+        _ => panic!("unsupported radix {}", radix),
+    };
+    if value < 0 {
+        format!("-{}", digits)
+    } else {
+        digits
+    }
+}
+
+/// Parse an integer in `radix` (2 to 36). Surrounding whitespace, a sign,
+/// `_` between digits and a prefix matching the radix are allowed; radix 0
+/// picks the base from the prefix (`0x`, `0o`, `0b`, else decimal).
+pub fn parse_int(text: &str, radix: u32) -> Result<i64, String> {
+    let invalid = || format!("invalid literal for parse_int() with base {}: {:?}", radix, text);
+    if radix == 1 || radix > 36 {
+        return Err(format!("parse_int() base must be 0 or 2..=36, not {}", radix));
+    }
+    let trimmed = text.trim();
+    let (negative, unsigned) = match trimmed.as_bytes().first() {
+        Some(b'-') => (true, &trimmed[1..]),
+        Some(b'+') => (false, &trimmed[1..]),
+        _ => (false, trimmed),
+    };
+    let prefix_radix = match unsigned.get(..2).map(str::to_ascii_lowercase).as_deref() {
+        Some("0x") => Some(16),
+        Some("0o") => Some(8),
+        Some("0b") => Some(2),
+        _ => None,
+    };
+    // An underscore may follow the prefix, as in `0x_ff`
+    let after_prefix = || unsigned[2..].strip_prefix('_').unwrap_or(&unsigned[2..]);
+    let (radix, digits) = match (radix, prefix_radix) {
+        (0, Some(p)) => (p, after_prefix()),
+        (0, None) => (10, unsigned),
+        (r, Some(p)) if r == p => (r, after_prefix()),
+        (r, _) => (r, unsigned),
+    };
+    if digits.is_empty()
+        || digits.starts_with('_')
+        || digits.ends_with('_')
+        || digits.contains("__")
+        || !digits.chars().all(|c| c == '_' || c.is_digit(radix))
+    {
+        return Err(invalid());
+    }
+    let mut value: i64 = 0;
+    for c in digits.chars().filter(|&c| c != '_') {
+        let digit = c.to_digit(radix).expect("digits were checked") as i64;
+        // Accumulate negatively so i64::MIN parses
+        value = value
+            .checked_mul(radix as i64)
+            .and_then(|v| v.checked_sub(digit))
+            .ok_or_else(|| format!("parse_int() result does not fit in 64 bits: {:?}", text))?;
+    }
+    if negative {
+        Ok(value)
+    } else {
+        value
+            .checked_neg()
+            .ok_or_else(|| format!("parse_int() result does not fit in 64 bits: {:?}", text))
+    }
+}
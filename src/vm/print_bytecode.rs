@@ -1,4 +1,9 @@
 use super::*;
+use super::call::HostFunctionRegistry;
+use super::const_pool::{ConstPool, SliceType, ValueType};
+use super::number_format::format_f64;
+use super::program::opcode_name;
+use std::collections::HashMap;
 
 /// Format bytecode as a human-readable string
 pub fn format_bytecode(bytecode: &[u8]) -> Result<String, String> {
@@ -6,343 +11,61 @@ pub fn format_bytecode(bytecode: &[u8]) -> Result<String, String> {
     let mut pc = 0;
 
     while pc < bytecode.len() {
-        let opcode = bytecode[pc];
-        let start_pc = pc;
-        pc += 1;
+        pc = format_instruction(bytecode, pc, &mut output)?;
+    }
+
+    output.push_str(&format!("pc={}\n", pc));
+    output.push_str(&format!("bytecode.len()={}\n", bytecode.len()));
+
+    Ok(output)
+}
+
+/// Like `format_bytecode`, with each CALL_HOST followed by a comment naming
+/// the function it calls, its parameter count and its register window
+/// `[base..top]`. The function is the host function constant last loaded
+/// into the base register by LOAD_CONST_VALUE; calls whose base was not
+/// loaded that way get no comment.
+pub fn format_bytecode_with_host_functions(
+    bytecode: &[u8],
+    pool: &ConstPool,
+    host_functions: &HostFunctionRegistry,
+) -> Result<String, String> {
+    let mut output = String::new();
+    // Host function index last loaded into each register
+    let mut loaded: HashMap<u16, usize> = HashMap::new();
+    let mut pc = 0;
 
-        match opcode {
+    while pc < bytecode.len() {
+        let start = pc;
+        pc = format_instruction(bytecode, pc, &mut output)?;
+        match bytecode[start] {
             LOAD_CONST_VALUE => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete LOAD_CONST_VALUE instruction at pc {}: missing operands",
-                        start_pc
-                    ));
-                }
-                let reg = bytecode[pc];
-                pc += 1;
-                let index = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
-                pc += 2;
-                output.push_str(&format!("{} LOAD_CONST_VALUE r{}, {}\n", start_pc, reg, index));
-            }
-            LOAD_CONST_SLICE => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete LOAD_CONST_SLICE instruction at pc {}: missing operands",
-                        start_pc
-                    ));
-                }
-                let reg = bytecode[pc];
-                pc += 1;
-                let index = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
-                pc += 2;
-                output.push_str(&format!("{} LOAD_CONST_SLICE r{}, {}\n", start_pc, reg, index));
-            }
-            ADD_I64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete ADD_I64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!(
-                    "{} ADD_I64 r{}, r{}, r{}\n",
-                    start_pc, r1, r2, dst
-                ));
-            }
-            SUB_I64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete SUB_I64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!(
-                    "{} SUB_I64 r{}, r{}, r{}\n",
-                    start_pc, r1, r2, dst
-                ));
-            }
-            MUL_I64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete MUL_I64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!(
-                    "{} MUL_I64 r{}, r{}, r{}\n",
-                    start_pc, r1, r2, dst
-                ));
-            }
-            GT_I64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete GT_I64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!("{} GT_I64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
-            }
-            GTE_I64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete GTE_I64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!("{} GTE_I64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
-            }
-            LT_I64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete LT_I64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!("{} LT_I64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
-            }
-            LTE_I64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete LTE_I64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!("{} LTE_I64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
-            }
-            ADD_F64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete ADD_F64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!(
-                    "{} ADD_F64 r{}, r{}, r{}\n",
-                    start_pc, r1, r2, dst
-                ));
-            }
-            SUB_F64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete SUB_F64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!(
-                    "{} SUB_F64 r{}, r{}, r{}\n",
-                    start_pc, r1, r2, dst
-                ));
-            }
-            MUL_F64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete MUL_F64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!(
-                    "{} MUL_F64 r{}, r{}, r{}\n",
-                    start_pc, r1, r2, dst
-                ));
-            }
-            GT_F64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete GT_F64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!("{} GT_F64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
-            }
-            GTE_F64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete GTE_F64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!("{} GTE_F64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
-            }
-            LT_F64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete LT_F64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!("{} LT_F64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
-            }
-            LTE_F64 => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete LTE_F64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
-                }
-                let r1 = bytecode[pc];
-                let r2 = bytecode[pc + 1];
-                let dst = bytecode[pc + 2];
-                pc += 3;
-                output.push_str(&format!("{} LTE_F64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
-            }
-            JUMP_FORWARD_IF_FALSE => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete JUMP_FORWARD_IF_FALSE instruction at pc {}: missing condition register or offset",
-                        start_pc
-                    ));
-                }
-                let cond_reg = bytecode[pc];
-                pc += 1;
-                let offset = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
-                let target = pc + offset as usize;
-                pc += 2;
-                output.push_str(&format!(
-                    "{} JUMP_FORWARD_IF_FALSE r{}, {} (offset: {})\n",
-                    start_pc, cond_reg, target, offset
-                ));
-            }
-            JUMP_FORWARD_IF_TRUE => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete JUMP_FORWARD_IF_TRUE instruction at pc {}: missing condition register or offset",
-                        start_pc
-                    ));
-                }
-                let cond_reg = bytecode[pc];
-                pc += 1;
-                let offset = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
-                let target = pc + offset as usize;
-                pc += 2;
-                output.push_str(&format!(
-                    "{} JUMP_FORWARD_IF_TRUE r{}, {} (offset: {})\n",
-                    start_pc, cond_reg, target, offset
-                ));
-            }
-            JUMP_BACKWARD_IF_FALSE => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete JUMP_BACKWARD_IF_FALSE instruction at pc {}: missing condition register or offset",
-                        start_pc
-                    ));
-                }
-                let cond_reg = bytecode[pc];
-                pc += 1;
-                let offset = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
-                pc += 2;
-                let target = pc as i64 - offset as i64;
-                output.push_str(&format!(
-                    "{} JUMP_BACKWARD_IF_FALSE r{}, {} (offset: {})\n",
-                    start_pc, cond_reg, target, offset
-                ));
-            }
-            JUMP_BACKWARD_IF_TRUE => {
-                if pc + 2 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete JUMP_BACKWARD_IF_TRUE instruction at pc {}: missing condition register or offset",
-                        start_pc
-                    ));
-                }
-                let cond_reg = bytecode[pc];
-                pc += 1;
-                let offset = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
-                pc += 2;
-                let target = pc as i64 - offset as i64;
-                output.push_str(&format!(
-                    "{} JUMP_BACKWARD_IF_TRUE r{}, {} (offset: {})\n",
-                    start_pc, cond_reg, target, offset
-                ));
-            }
-            JMP => {
-                if pc + 1 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete JMP instruction at pc {}: missing target address",
-                        start_pc
-                    ));
-                }
-                let target = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
-                pc += 2;
-                output.push_str(&format!("{} JMP {}\n", start_pc, target));
-            }
-            I64_TO_F64 => {
-                if pc + 1 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete I64_TO_F64 instruction at pc {}: missing register operands",
-                        start_pc
-                    ));
+                let reg = bytecode[start + 1] as u16;
+                let index = u16::from_le_bytes([bytecode[start + 2], bytecode[start + 3]]) as usize;
+                match pool.value_metadata.get(index) {
+                    Some(meta) if meta.typ == ValueType::FuncHost => {
+                        loaded.insert(reg, pool.values[index] as usize);
+                    }
+                    _ => {
+                        loaded.remove(&reg);
+                    }
                 }
-                let src = bytecode[pc];
-                let dst = bytecode[pc + 1];
-                pc += 2;
-                output.push_str(&format!("{} I64_TO_F64 r{}, r{}\n", start_pc, src, dst));
-            }
-            F64_TO_I64 => {
-                if pc + 1 >= bytecode.len() {
-                    return Err(format!(
-                        "Incomplete F64_TO_I64 instruction at pc {}: missing register operands",
-                        start_pc
+            }
+            CALL_HOST => {
+                let base = u16::from_le_bytes([bytecode[start + 1], bytecode[start + 2]]);
+                let meta = loaded
+                    .get(&base)
+                    .and_then(|&index| host_functions.metadata.get(index));
+                if let Some(meta) = meta {
+                    let top = base as usize + meta.num_registers.saturating_sub(1);
+                    output.pop(); // newline
+                    output.push_str(&format!(
+                        "  ; {} num_params={} [r{}..r{}]\n",
+                        meta.name, meta.num_params, base, top
                     ));
                 }
-                let src = bytecode[pc];
-                let dst = bytecode[pc + 1];
-                pc += 2;
-                output.push_str(&format!("{} F64_TO_I64 r{}, r{}\n", start_pc, src, dst));
-            }
-            _ => {
-                return Err(format!("{} UNKNOWN_OPCODE 0x{:02X}\n", start_pc, opcode));
             }
+            _ => {}
         }
     }
 
@@ -352,6 +75,556 @@ pub fn format_bytecode(bytecode: &[u8]) -> Result<String, String> {
     Ok(output)
 }
 
+/// Append the instruction starting at `pc` to `output`, returning where the
+/// next one starts
+pub(crate) fn format_instruction(
+    bytecode: &[u8],
+    mut pc: usize,
+    output: &mut String,
+) -> Result<usize, String> {
+    let opcode = bytecode[pc];
+    let start_pc = pc;
+    pc += 1;
+
+    match opcode {
+        LOAD_CONST_VALUE => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete LOAD_CONST_VALUE instruction at pc {}: missing operands",
+                    start_pc
+                ));
+            }
+            let reg = bytecode[pc];
+            pc += 1;
+            let index = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
+            pc += 2;
+            output.push_str(&format!("{} LOAD_CONST_VALUE r{}, {}\n", start_pc, reg, index));
+        }
+        LOAD_CONST_SLICE => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete LOAD_CONST_SLICE instruction at pc {}: missing operands",
+                    start_pc
+                ));
+            }
+            let reg = bytecode[pc];
+            pc += 1;
+            let index = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
+            pc += 2;
+            output.push_str(&format!("{} LOAD_CONST_SLICE r{}, {}\n", start_pc, reg, index));
+        }
+        SWAP => {
+            if pc + 1 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete SWAP instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            pc += 2;
+            output.push_str(&format!("{} SWAP r{}, r{}\n", start_pc, r1, r2));
+        }
+        SELECT => {
+            if pc + 3 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete SELECT instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let (cond, a, b, dst) = (bytecode[pc], bytecode[pc + 1], bytecode[pc + 2], bytecode[pc + 3]);
+            pc += 4;
+            output.push_str(&format!(
+                "{} SELECT r{}, r{}, r{}, r{}\n",
+                start_pc, cond, a, b, dst
+            ));
+        }
+        MIN_I64 | MAX_I64 | MIN_F64 | MAX_F64 | STR_EQ | LT_I64_F64 | LTE_I64_F64
+        | GT_I64_F64 | GTE_I64_F64 | MOD_I64 | POW_I64 | POW_F64 | AND_I64 | OR_I64
+        | XOR_I64 | SHL_I64 | SHR_I64 => {
+            let name = opcode_name(opcode).unwrap_or("?");
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete {} instruction at pc {}: missing register operands",
+                    name, start_pc
+                ));
+            }
+            let (r1, r2, dst) = (bytecode[pc], bytecode[pc + 1], bytecode[pc + 2]);
+            pc += 3;
+            output.push_str(&format!("{} {} r{}, r{}, r{}\n", start_pc, name, r1, r2, dst));
+        }
+        ABS_I64 | ABS_F64 | ROUND_F64 | FLOOR_F64 | CEIL_F64 | TRUNC_F64 | STR_HASH | NOT_I64 => {
+            let name = opcode_name(opcode).unwrap_or("?");
+            if pc + 1 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete {} instruction at pc {}: missing register operands",
+                    name, start_pc
+                ));
+            }
+            let (src, dst) = (bytecode[pc], bytecode[pc + 1]);
+            pc += 2;
+            output.push_str(&format!("{} {} r{}, r{}\n", start_pc, name, src, dst));
+        }
+        NOP => {
+            output.push_str(&format!("{} NOP\n", start_pc));
+        }
+        CALL_HOST => {
+            if pc + 1 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete CALL_HOST instruction at pc {}: missing register operand",
+                    start_pc
+                ));
+            }
+            let base = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
+            pc += 2;
+            output.push_str(&format!("{} CALL_HOST r{}\n", start_pc, base));
+        }
+        RET => {
+            output.push_str(&format!("{} RET\n", start_pc));
+        }
+        CALL => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete CALL instruction at pc {}: missing operands",
+                    start_pc
+                ));
+            }
+            let target = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
+            let base = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!("{} CALL {}, r{}\n", start_pc, target, base));
+        }
+        DEBUG_BREAK => {
+            output.push_str(&format!("{} DEBUG_BREAK\n", start_pc));
+        }
+        ASSERT => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete ASSERT instruction at pc {}: missing operands",
+                    start_pc
+                ));
+            }
+            let reg = bytecode[pc];
+            pc += 1;
+            let index = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
+            pc += 2;
+            output.push_str(&format!("{} ASSERT r{}, {}\n", start_pc, reg, index));
+        }
+        ASSERT_ENUM_VALUE | ASSERT_ENUM_NAME => {
+            let name = opcode_name(opcode).unwrap_or("?");
+            if pc + 3 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete {} instruction at pc {}: missing operands",
+                    name, start_pc
+                ));
+            }
+            let (cond, value) = (bytecode[pc], bytecode[pc + 1]);
+            let index = u16::from_le_bytes([bytecode[pc + 2], bytecode[pc + 3]]);
+            pc += 4;
+            output.push_str(&format!("{} {} r{}, r{}, {}\n", start_pc, name, cond, value, index));
+        }
+        ADD_I64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete ADD_I64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!(
+                "{} ADD_I64 r{}, r{}, r{}\n",
+                start_pc, r1, r2, dst
+            ));
+        }
+        SUB_I64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete SUB_I64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!(
+                "{} SUB_I64 r{}, r{}, r{}\n",
+                start_pc, r1, r2, dst
+            ));
+        }
+        MUL_I64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete MUL_I64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!(
+                "{} MUL_I64 r{}, r{}, r{}\n",
+                start_pc, r1, r2, dst
+            ));
+        }
+        GT_I64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete GT_I64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!("{} GT_I64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
+        }
+        GTE_I64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete GTE_I64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!("{} GTE_I64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
+        }
+        LT_I64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete LT_I64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!("{} LT_I64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
+        }
+        LTE_I64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete LTE_I64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!("{} LTE_I64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
+        }
+        ADD_F64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete ADD_F64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!(
+                "{} ADD_F64 r{}, r{}, r{}\n",
+                start_pc, r1, r2, dst
+            ));
+        }
+        SUB_F64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete SUB_F64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!(
+                "{} SUB_F64 r{}, r{}, r{}\n",
+                start_pc, r1, r2, dst
+            ));
+        }
+        MUL_F64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete MUL_F64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!(
+                "{} MUL_F64 r{}, r{}, r{}\n",
+                start_pc, r1, r2, dst
+            ));
+        }
+        GT_F64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete GT_F64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!("{} GT_F64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
+        }
+        GTE_F64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete GTE_F64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!("{} GTE_F64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
+        }
+        LT_F64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete LT_F64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!("{} LT_F64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
+        }
+        LTE_F64 => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete LTE_F64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!("{} LTE_F64 r{}, r{}, r{}\n", start_pc, r1, r2, dst));
+        }
+        JUMP_FORWARD_IF_FALSE => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete JUMP_FORWARD_IF_FALSE instruction at pc {}: missing condition register or offset",
+                    start_pc
+                ));
+            }
+            let cond_reg = bytecode[pc];
+            pc += 1;
+            let offset = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
+            let target = pc + offset as usize;
+            pc += 2;
+            output.push_str(&format!(
+                "{} JUMP_FORWARD_IF_FALSE r{}, {} (offset: {})\n",
+                start_pc, cond_reg, target, offset
+            ));
+        }
+        JUMP_FORWARD_IF_TRUE => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete JUMP_FORWARD_IF_TRUE instruction at pc {}: missing condition register or offset",
+                    start_pc
+                ));
+            }
+            let cond_reg = bytecode[pc];
+            pc += 1;
+            let offset = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
+            let target = pc + offset as usize;
+            pc += 2;
+            output.push_str(&format!(
+                "{} JUMP_FORWARD_IF_TRUE r{}, {} (offset: {})\n",
+                start_pc, cond_reg, target, offset
+            ));
+        }
+        JUMP_BACKWARD_IF_FALSE => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete JUMP_BACKWARD_IF_FALSE instruction at pc {}: missing condition register or offset",
+                    start_pc
+                ));
+            }
+            let cond_reg = bytecode[pc];
+            pc += 1;
+            let offset = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
+            pc += 2;
+            let target = pc as i64 - offset as i64;
+            output.push_str(&format!(
+                "{} JUMP_BACKWARD_IF_FALSE r{}, {} (offset: {})\n",
+                start_pc, cond_reg, target, offset
+            ));
+        }
+        JUMP_BACKWARD_IF_TRUE => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete JUMP_BACKWARD_IF_TRUE instruction at pc {}: missing condition register or offset",
+                    start_pc
+                ));
+            }
+            let cond_reg = bytecode[pc];
+            pc += 1;
+            let offset = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
+            pc += 2;
+            let target = pc as i64 - offset as i64;
+            output.push_str(&format!(
+                "{} JUMP_BACKWARD_IF_TRUE r{}, {} (offset: {})\n",
+                start_pc, cond_reg, target, offset
+            ));
+        }
+        JMP => {
+            if pc + 1 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete JMP instruction at pc {}: missing target address",
+                    start_pc
+                ));
+            }
+            let target = u16::from_le_bytes([bytecode[pc], bytecode[pc + 1]]);
+            pc += 2;
+            output.push_str(&format!("{} JMP {}\n", start_pc, target));
+        }
+        MOV | MOV_VALUE => {
+            let name = opcode_name(opcode).unwrap_or("?");
+            if pc + 1 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete {} instruction at pc {}: missing register operands",
+                    name, start_pc
+                ));
+            }
+            let src = bytecode[pc];
+            let dst = bytecode[pc + 1];
+            pc += 2;
+            output.push_str(&format!("{} {} r{}, r{}\n", start_pc, name, src, dst));
+        }
+        STR_CONCAT => {
+            if pc + 2 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete STR_CONCAT instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let r1 = bytecode[pc];
+            let r2 = bytecode[pc + 1];
+            let dst = bytecode[pc + 2];
+            pc += 3;
+            output.push_str(&format!(
+                "{} STR_CONCAT r{}, r{}, r{}\n",
+                start_pc, r1, r2, dst
+            ));
+        }
+        STR_BUILDER_NEW => {
+            output.push_str(&format!("{} STR_BUILDER_NEW\n", start_pc));
+        }
+        STR_APPEND => {
+            if pc >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete STR_APPEND instruction at pc {}: missing register operand",
+                    start_pc
+                ));
+            }
+            let reg = bytecode[pc];
+            pc += 1;
+            output.push_str(&format!("{} STR_APPEND r{}\n", start_pc, reg));
+        }
+        STR_APPEND_I64 => {
+            if pc >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete STR_APPEND_I64 instruction at pc {}: missing register operand",
+                    start_pc
+                ));
+            }
+            let reg = bytecode[pc];
+            pc += 1;
+            output.push_str(&format!("{} STR_APPEND_I64 r{}\n", start_pc, reg));
+        }
+        STR_BUILDER_FINISH => {
+            if pc >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete STR_BUILDER_FINISH instruction at pc {}: missing register operand",
+                    start_pc
+                ));
+            }
+            let reg = bytecode[pc];
+            pc += 1;
+            output.push_str(&format!("{} STR_BUILDER_FINISH r{}\n", start_pc, reg));
+        }
+        I64_TO_F64 => {
+            if pc + 1 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete I64_TO_F64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let src = bytecode[pc];
+            let dst = bytecode[pc + 1];
+            pc += 2;
+            output.push_str(&format!("{} I64_TO_F64 r{}, r{}\n", start_pc, src, dst));
+        }
+        F64_TO_I64 => {
+            if pc + 1 >= bytecode.len() {
+                return Err(format!(
+                    "Incomplete F64_TO_I64 instruction at pc {}: missing register operands",
+                    start_pc
+                ));
+            }
+            let src = bytecode[pc];
+            let dst = bytecode[pc + 1];
+            pc += 2;
+            output.push_str(&format!("{} F64_TO_I64 r{}, r{}\n", start_pc, src, dst));
+        }
+        _ => {
+            return Err(format!("{} UNKNOWN_OPCODE 0x{:02X}\n", start_pc, opcode));
+        }
+    }
+
+    Ok(pc)
+}
+
+/// List the constant pool, one constant per line, for disassembly listings
+pub fn format_consts(pool: &ConstPool) -> String {
+    let mut output = String::new();
+    for meta in &pool.value_metadata {
+        let raw = pool.values[meta.index];
+        let value = match meta.typ {
+            ValueType::I64 => (raw as i64).to_string(),
+            ValueType::F64 => format_f64(f64::from_bits(raw)),
+            ValueType::Bool => (raw != 0).to_string(),
+            ValueType::FuncHost => format!("host fn {}", raw),
+            ValueType::List => format!("list at {:#x}", raw),
+            ValueType::Dict => format!("dict at {:#x}", raw),
+        };
+        output.push_str(&format!("value {} {:?} {} {}\n", meta.index, meta.typ, meta.name, value));
+    }
+    for meta in &pool.slice_metadata {
+        let data = pool.slice(meta.index).unwrap_or_default();
+        let value = match meta.typ {
+            SliceType::Utf8Str => format!("{:?}", String::from_utf8_lossy(data)),
+            SliceType::Binary => format!("{} bytes", data.len()),
+        };
+        output.push_str(&format!("slice {} {:?} {} {}\n", meta.index, meta.typ, meta.name, value));
+    }
+    output
+}
+
 /// Disassemble bytecode and print in human-readable format
 pub fn print_bytecode(bytecode: &[u8]) {
     match format_bytecode(bytecode) {
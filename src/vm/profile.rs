@@ -0,0 +1,100 @@
+use super::debug_info::SourceMap;
+use super::*;
+use std::time::Duration;
+
+/// How often each instruction ran, the total time spent running, and how
+/// deep the run went
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    /// Execution count per pc (only instruction starts are ever non-zero)
+    pub counts: Vec<u64>,
+    pub elapsed: Duration,
+    /// Length of the register file the run needed: enough for
+    /// `Registers::ensure_len` to pre-size it, or for `Limits::max_registers`
+    pub peak_registers: usize,
+    /// Most calls active at once, a host call counting as one
+    pub peak_call_depth: usize,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make room for counters of a program of `len` bytes
+    pub(crate) fn start(&mut self, len: usize) {
+        if self.counts.len() < len {
+            self.counts.resize(len, 0);
+        }
+        self.peak_registers = self.peak_registers.max(Registers::FIXED_COUNT);
+    }
+
+    /// Note a frame `depth` calls deep whose last register is `top`
+    pub(crate) fn enter_frame(&mut self, depth: usize, top: usize) {
+        self.peak_registers = self.peak_registers.max(top + 1);
+        self.peak_call_depth = self.peak_call_depth.max(depth);
+    }
+
+    pub fn total_count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Instructions executed per source line, through `map`; index 0 holds
+    /// instructions without a known line
+    pub fn line_counts(&self, map: &SourceMap) -> Vec<u64> {
+        let mut lines = Vec::new();
+        for (pc, &count) in self.counts.iter().enumerate().filter(|(_, c)| **c > 0) {
+            let line = map.line_at(pc).unwrap_or(0) as usize;
+            if lines.len() <= line {
+                lines.resize(line + 1, 0);
+            }
+            lines[line] += count;
+        }
+        lines
+    }
+
+    /// The source annotated with per-line instruction counts and the share of
+    /// `elapsed` each line is estimated to take (proportional to its count)
+    pub fn annotate(&self, src: &str, map: &SourceMap) -> String {
+        let lines = self.line_counts(map);
+        let total = self.total_count().max(1);
+        let mut out = format!("{:>5} {:>10} {:>10}  source\n", "line", "count", "time");
+        for (i, text) in src.lines().enumerate() {
+            let count = lines.get(i + 1).copied().unwrap_or(0);
+            if count == 0 {
+                out.push_str(&format!("{:>5} {:>10} {:>10}  {}\n", i + 1, "", "", text));
+                continue;
+            }
+            let time = self.elapsed.mul_f64(count as f64 / total as f64);
+            out.push_str(&format!(
+                "{:>5} {:>10} {:>10}  {}\n",
+                i + 1,
+                count,
+                format!("{:.1?}", time),
+                text
+            ));
+        }
+        out.push_str(&format!(
+            "{} instructions in {:.1?}\n",
+            self.total_count(),
+            self.elapsed
+        ));
+        out.push_str(&format!(
+            "peak {} registers, call depth {}\n",
+            self.peak_registers, self.peak_call_depth
+        ));
+        out
+    }
+}
+
+impl VirtualMachine {
+    /// Count instruction executions from now on
+    pub fn start_profiling(&mut self) {
+        self.profile = Some(Profile::new());
+    }
+
+    /// Stop profiling and return what was collected
+    pub fn stop_profiling(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+}
@@ -0,0 +1,971 @@
+//! `.kayc` compiled programs: a header naming the instruction set the
+//! bytecode was compiled for, the constants it refers to, and the bytecode.
+//!
+//! Everything is stored little-endian with explicit type tags; slice constants
+//! are stored by value and host functions by name, so files do not depend on
+//! memory addresses or registration order of the machine that wrote them.
+//! Programs also list the host functions their bytecode calls, so a loading
+//! machine can report everything it lacks before running anything.
+
+use super::const_pool::{ConstPool, SliceType, ValueType};
+use super::encoding::{ByteReader, ByteWriter, crc32_update};
+use super::*;
+use std::collections::HashMap;
+
+const PROGRAM_MAGIC: &[u8; 4] = b"KAYC";
+/// Version 1 had no const section; such files run against the loading VM's pool.
+/// Version 2 had no checksum. Version 3 had no host function requirements.
+/// Version 4 had no host function versions. Version 5 had no frame sizes.
+pub const PROGRAM_FORMAT_VERSION: u16 = 6;
+
+const FLAG_CONSTS: u8 = 1;
+const FLAG_COMPRESSED: u8 = 2;
+/// Upper bound for an inflated const section, guarding against decompression bombs
+#[cfg(feature = "compress")]
+const MAX_CONSTS_SIZE: usize = 64 << 20;
+
+#[derive(Debug, PartialEq)]
+pub enum ProgramError {
+    BadMagic,
+    UnsupportedFormat(u16),
+    Truncated,
+    /// Compiled for a newer instruction set than this VM implements
+    NewerIsa {
+        found: u16,
+        supported: u16,
+    },
+    /// Compiled for an older instruction set that can no longer be migrated
+    ObsoleteIsa(u16),
+    /// Uses opcode families this VM was built without (bitmap of `ISA_*` flags)
+    UnsupportedFeatures(u32),
+    InvalidBytecode(String),
+    /// A host function referenced by the program is not registered in this VM
+    UnknownHostFunction(String),
+    /// The file was corrupted or truncated after it was written
+    ChecksumMismatch {
+        expected: u32,
+        found: u32,
+    },
+    /// The const section is compressed but kayton was built without `compress`
+    CompressionUnsupported,
+    /// Host functions the program calls that are missing or registered with
+    /// a different shape, all of them
+    UnmetRequirements(Vec<UnmetRequirement>),
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgramError::BadMagic => write!(f, "Not a compiled Kayton program"),
+            ProgramError::UnsupportedFormat(v) => {
+                write!(f, "Unsupported program format version: {}", v)
+            }
+            ProgramError::Truncated => write!(f, "Program file is truncated"),
+            ProgramError::NewerIsa { found, supported } => write!(
+                f,
+                "Program needs instruction set v{} but this VM supports up to v{}; upgrade kayton",
+                found, supported
+            ),
+            ProgramError::ObsoleteIsa(v) => {
+                write!(
+                    f,
+                    "Instruction set v{} is no longer supported; recompile the program",
+                    v
+                )
+            }
+            ProgramError::UnsupportedFeatures(bits) => {
+                write!(
+                    f,
+                    "Program uses unsupported instruction set features: {:#x}",
+                    bits
+                )
+            }
+            ProgramError::InvalidBytecode(msg) => write!(f, "Invalid bytecode: {}", msg),
+            ProgramError::UnknownHostFunction(name) => {
+                write!(f, "Program calls unregistered host function '{}'", name)
+            }
+            ProgramError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "Program is corrupted: checksum {:08x}, expected {:08x}",
+                found, expected
+            ),
+            ProgramError::CompressionUnsupported => write!(
+                f,
+                "Program is compressed; rebuild kayton with the `compress` feature"
+            ),
+            ProgramError::UnmetRequirements(unmet) => {
+                let unmet: Vec<String> = unmet.iter().map(|u| u.to_string()).collect();
+                write!(f, "Program needs host functions this VM lacks: {}", unmet.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProgramError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortableValue {
+    Bits(u64),
+    /// Host function, resolved to a registry index when loaded
+    HostFn(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueConst {
+    pub name: String,
+    pub typ: ValueType,
+    pub value: PortableValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SliceConst {
+    pub name: String,
+    pub typ: SliceType,
+    pub data: Vec<u8>,
+}
+
+/// A host function a program calls, with the shape it was compiled against
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostRequirement {
+    pub name: String,
+    pub num_params: usize,
+    pub num_return_registers: usize,
+    pub version: u32,
+}
+
+impl HostRequirement {
+    fn from_metadata(meta: &HostFunctionMetadata) -> Self {
+        Self {
+            name: meta.name.to_string(),
+            num_params: meta.num_params,
+            num_return_registers: meta.num_return_registers,
+            version: meta.version,
+        }
+    }
+
+    /// Whether the function registered as `meta` can run calls compiled
+    /// against this: the same returns, at least this version, and no
+    /// parameters the calls leave out that lack a default
+    fn is_met_by(&self, meta: &HostFunctionMetadata) -> bool {
+        self.num_return_registers == meta.num_return_registers
+            && self.version <= meta.version
+            && (meta.min_params()..=meta.num_params).contains(&self.num_params)
+    }
+
+    fn shape(&self) -> String {
+        let shape = format!("{} params, {} return registers", self.num_params, self.num_return_registers);
+        match self.version {
+            0 => shape,
+            version => format!("{}, version {}", shape, version),
+        }
+    }
+}
+
+/// A `HostRequirement` the VM does not meet, with the shape it registered
+/// the name under, if any
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnmetRequirement {
+    pub required: HostRequirement,
+    pub registered: Option<HostRequirement>,
+}
+
+impl fmt::Display for UnmetRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.registered {
+            None => write!(f, "'{}' ({}) is not registered", self.required.name, self.required.shape()),
+            Some(registered) => write!(
+                f,
+                "'{}' is registered with {} but the program expects {}",
+                self.required.name,
+                registered.shape(),
+                self.required.shape()
+            ),
+        }
+    }
+}
+
+/// Registers the frames of the function starting at `entry` reach, counting
+/// from the frame's base and including the frames it calls into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRegisters {
+    pub entry: usize,
+    /// A lower bound when the function is recursive, since its depth is
+    /// only known at run time
+    pub registers: usize,
+}
+
+/// Machine-independent copy of a const pool, in const index order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProgramConsts {
+    pub values: Vec<ValueConst>,
+    pub slices: Vec<SliceConst>,
+}
+
+impl ProgramConsts {
+    pub fn from_pool(
+        pool: &ConstPool,
+        host_functions: &HostFunctionRegistry,
+    ) -> Result<Self, ProgramError> {
+        let mut values = Vec::with_capacity(pool.values.len());
+        for (value, meta) in pool.values.iter().zip(&pool.value_metadata) {
+            let value = match meta.typ {
+                ValueType::FuncHost => {
+                    let func = host_functions
+                        .metadata
+                        .get(*value as usize)
+                        .ok_or_else(|| {
+                            ProgramError::InvalidBytecode(format!("host function index {}", value))
+                        })?;
+                    PortableValue::HostFn(func.name.to_string())
+                }
+                _ => PortableValue::Bits(*value),
+            };
+            values.push(ValueConst {
+                name: meta.name.to_string(),
+                typ: meta.typ,
+                value,
+            });
+        }
+        let slices = pool
+            .slices()
+            .zip(&pool.slice_metadata)
+            .map(|(data, meta)| SliceConst {
+                name: meta.name.to_string(),
+                typ: meta.typ,
+                data: data.to_vec(),
+            })
+            .collect();
+        Ok(Self { values, slices })
+    }
+
+    /// Rebuild a const pool, copying slice data into new allocations
+    pub fn to_pool(
+        &self,
+        host_functions: &HostFunctionRegistry,
+    ) -> Result<ConstPool, ProgramError> {
+        self.to_pool_resolving(|name| host_functions.find(name))
+    }
+
+    /// Like `to_pool`, looking host functions up with `resolve`
+    fn to_pool_resolving(
+        &self,
+        resolve: impl Fn(&str) -> Option<usize>,
+    ) -> Result<ConstPool, ProgramError> {
+        let mut pool = ConstPool::new();
+        for value in &self.values {
+            let bits = match &value.value {
+                PortableValue::Bits(bits) => *bits,
+                PortableValue::HostFn(name) => {
+                    resolve(name).ok_or_else(|| ProgramError::UnknownHostFunction(name.clone()))?
+                        as u64
+                }
+            };
+            pool.add_value(&value.name, bits, value.typ);
+        }
+        for slice in &self.slices {
+            pool.add_slice(&slice.name, &slice.data, slice.typ);
+        }
+        Ok(pool)
+    }
+
+    fn write(&self, w: &mut ByteWriter) {
+        w.u32(self.values.len() as u32);
+        for value in &self.values {
+            w.str(&value.name);
+            w.u8(value.typ.tag());
+            match &value.value {
+                PortableValue::Bits(bits) => w.u64(*bits),
+                PortableValue::HostFn(name) => w.str(name),
+            }
+        }
+        w.u32(self.slices.len() as u32);
+        for slice in &self.slices {
+            w.str(&slice.name);
+            w.u8(slice.typ.tag());
+            w.bytes(&slice.data);
+        }
+    }
+
+    fn read(r: &mut ByteReader) -> Result<Self, ProgramError> {
+        let mut consts = Self::default();
+        let value_count = r.u32().ok_or(ProgramError::Truncated)?;
+        for _ in 0..value_count {
+            let name = r.str().ok_or(ProgramError::Truncated)?.to_string();
+            let tag = r.u8().ok_or(ProgramError::Truncated)?;
+            let typ = ValueType::from_tag(tag)
+                .ok_or_else(|| ProgramError::InvalidBytecode(format!("value type tag {}", tag)))?;
+            let value = match typ {
+                ValueType::FuncHost => {
+                    PortableValue::HostFn(r.str().ok_or(ProgramError::Truncated)?.to_string())
+                }
+                _ => PortableValue::Bits(r.u64().ok_or(ProgramError::Truncated)?),
+            };
+            consts.values.push(ValueConst { name, typ, value });
+        }
+        let slice_count = r.u32().ok_or(ProgramError::Truncated)?;
+        for _ in 0..slice_count {
+            let name = r.str().ok_or(ProgramError::Truncated)?.to_string();
+            let tag = r.u8().ok_or(ProgramError::Truncated)?;
+            let typ = SliceType::from_tag(tag)
+                .ok_or_else(|| ProgramError::InvalidBytecode(format!("slice type tag {}", tag)))?;
+            let data = r.bytes().ok_or(ProgramError::Truncated)?.to_vec();
+            consts.slices.push(SliceConst { name, typ, data });
+        }
+        Ok(consts)
+    }
+}
+
+/// Bytecode tagged with the instruction set version and the features it uses
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub isa_version: u16,
+    pub features: u32,
+    /// `None` when the bytecode indexes into the loading VM's own const pool
+    pub consts: Option<ProgramConsts>,
+    /// Host functions the bytecode loads for CALL_HOST, in first-use order;
+    /// only known for programs built `with_consts`
+    pub requirements: Vec<HostRequirement>,
+    /// Register file size of each function, the top-level code first, so
+    /// loading can allocate it once up front. `load_program` does not trust
+    /// the sizes a file carries and recomputes them.
+    pub frames: Vec<FrameRegisters>,
+    pub bytecode: Vec<u8>,
+}
+
+impl Program {
+    /// Wrap bytecode produced by this build's compiler
+    pub fn new(bytecode: Vec<u8>) -> Result<Self, ProgramError> {
+        let features = required_features(&bytecode)?;
+        let frames = frame_registers(&bytecode, 1)?;
+        Ok(Self {
+            isa_version: ISA_VERSION,
+            features,
+            consts: None,
+            requirements: Vec::new(),
+            frames,
+            bytecode,
+        })
+    }
+
+    /// Wrap bytecode compiled against `vm`, carrying its const pool along
+    pub fn with_consts(bytecode: Vec<u8>, vm: &VirtualMachine) -> Result<Self, ProgramError> {
+        let mut program = Self::new(bytecode)?;
+        program.consts = Some(ProgramConsts::from_pool(
+            &vm.const_pool,
+            &vm.host_functions,
+        )?);
+        program.requirements =
+            required_host_functions(&program.bytecode, &vm.const_pool, &vm.host_functions)?;
+        program.frames = frame_registers(
+            &program.bytecode,
+            host_window(&program.requirements, &vm.host_functions),
+        )?;
+        Ok(program)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.encode(false)
+    }
+
+    /// Like `to_bytes`, with the const section deflate-compressed
+    #[cfg(feature = "compress")]
+    pub fn to_bytes_compressed(&self) -> Vec<u8> {
+        self.encode(true)
+    }
+
+    fn encode(&self, compress: bool) -> Vec<u8> {
+        let mut w = ByteWriter::new();
+        w.buf.extend_from_slice(PROGRAM_MAGIC);
+        w.u16(PROGRAM_FORMAT_VERSION);
+        w.u16(self.isa_version);
+        w.u32(self.features);
+        let mut flags = 0;
+        if self.consts.is_some() {
+            flags |= FLAG_CONSTS;
+            if compress {
+                flags |= FLAG_COMPRESSED;
+            }
+        }
+        w.u8(flags);
+        let crc_pos = w.buf.len();
+        w.u32(0);
+
+        if let Some(consts) = &self.consts {
+            let mut section = ByteWriter::new();
+            consts.write(&mut section);
+            if flags & FLAG_COMPRESSED != 0 {
+                w.bytes(&compress_section(&section.buf));
+            } else {
+                w.buf.extend_from_slice(&section.buf);
+            }
+        }
+        w.u32(self.requirements.len() as u32);
+        for requirement in &self.requirements {
+            w.str(&requirement.name);
+            w.u16(requirement.num_params as u16);
+            w.u16(requirement.num_return_registers as u16);
+            w.u32(requirement.version);
+        }
+        w.u32(self.frames.len() as u32);
+        for frame in &self.frames {
+            w.u32(frame.entry as u32);
+            w.u32(frame.registers as u32);
+        }
+        w.bytes(&self.bytecode);
+
+        let crc = checksum(&w.buf, crc_pos);
+        w.buf[crc_pos..crc_pos + 4].copy_from_slice(&crc.to_le_bytes());
+        w.buf
+    }
+
+    /// Parse a program file without checking it against a VM
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ProgramError> {
+        let mut r = ByteReader::new(data);
+        if r.take(PROGRAM_MAGIC.len()) != Some(PROGRAM_MAGIC.as_slice()) {
+            return Err(ProgramError::BadMagic);
+        }
+        let format = r.u16().ok_or(ProgramError::Truncated)?;
+        if format == 0 || format > PROGRAM_FORMAT_VERSION {
+            return Err(ProgramError::UnsupportedFormat(format));
+        }
+        let isa_version = r.u16().ok_or(ProgramError::Truncated)?;
+        let features = r.u32().ok_or(ProgramError::Truncated)?;
+        let consts = match format {
+            1 => None,
+            2 => match r.u8().ok_or(ProgramError::Truncated)? {
+                0 => None,
+                _ => Some(ProgramConsts::read(&mut r)?),
+            },
+            _ => {
+                let flags = r.u8().ok_or(ProgramError::Truncated)?;
+                let crc_pos = r.pos();
+                let expected = r.u32().ok_or(ProgramError::Truncated)?;
+                let found = checksum(data, crc_pos);
+                if found != expected {
+                    return Err(ProgramError::ChecksumMismatch { expected, found });
+                }
+                if flags & !(FLAG_CONSTS | FLAG_COMPRESSED) != 0 {
+                    return Err(ProgramError::InvalidBytecode(format!(
+                        "header flags {:#x}",
+                        flags
+                    )));
+                }
+                match (flags & FLAG_CONSTS != 0, flags & FLAG_COMPRESSED != 0) {
+                    (false, _) => None,
+                    (true, false) => Some(ProgramConsts::read(&mut r)?),
+                    (true, true) => {
+                        let compressed = r.bytes().ok_or(ProgramError::Truncated)?;
+                        let section = decompress_section(compressed)?;
+                        let mut section_reader = ByteReader::new(&section);
+                        let consts = ProgramConsts::read(&mut section_reader)?;
+                        if !section_reader.is_at_end() {
+                            return Err(ProgramError::InvalidBytecode(
+                                "trailing bytes in const section".to_string(),
+                            ));
+                        }
+                        Some(consts)
+                    }
+                }
+            }
+        };
+        let mut requirements = Vec::new();
+        if format >= 4 {
+            let count = r.u32().ok_or(ProgramError::Truncated)?;
+            for _ in 0..count {
+                requirements.push(HostRequirement {
+                    name: r.str().ok_or(ProgramError::Truncated)?.to_string(),
+                    num_params: r.u16().ok_or(ProgramError::Truncated)? as usize,
+                    num_return_registers: r.u16().ok_or(ProgramError::Truncated)? as usize,
+                    version: match format {
+                        4 => 0,
+                        _ => r.u32().ok_or(ProgramError::Truncated)?,
+                    },
+                });
+            }
+        }
+        let mut frames = Vec::new();
+        if format >= 6 {
+            let count = r.u32().ok_or(ProgramError::Truncated)?;
+            for _ in 0..count {
+                frames.push(FrameRegisters {
+                    entry: r.u32().ok_or(ProgramError::Truncated)? as usize,
+                    registers: r.u32().ok_or(ProgramError::Truncated)? as usize,
+                });
+            }
+        }
+        let bytecode = r.bytes().ok_or(ProgramError::Truncated)?.to_vec();
+        if !r.is_at_end() {
+            return Err(ProgramError::InvalidBytecode("trailing bytes".to_string()));
+        }
+        Ok(Self {
+            isa_version,
+            features,
+            consts,
+            requirements,
+            frames,
+            bytecode,
+        })
+    }
+
+    /// Bring bytecode from an older instruction set up to `ISA_VERSION`
+    pub fn migrate(self) -> Result<Self, ProgramError> {
+        match self.isa_version {
+            ISA_VERSION => Ok(self),
+            v if v > ISA_VERSION => Err(ProgramError::NewerIsa {
+                found: v,
+                supported: ISA_VERSION,
+            }),
+            1 => {
+                let bytecode = renumber_v1(&self.bytecode)?;
+                Self {
+                    isa_version: 2,
+                    bytecode,
+                    ..self
+                }
+                .migrate()
+            }
+            // Version 3 only added opcodes, so version 2 bytecode runs as is
+            2 => Ok(Self {
+                isa_version: 3,
+                ..self
+            }),
+            // Older versions get a rewrite step here when the ISA changes incompatibly
+            v => Err(ProgramError::ObsoleteIsa(v)),
+        }
+    }
+}
+
+/// ISA version 1 numbering, as (v1 opcode, v2 opcode); operand layouts are unchanged
+const V1_OPCODES: [(u8, u8); 30] = [
+    (0x03, ADD_I64),
+    (0x04, SUB_I64),
+    (0x05, MUL_I64),
+    (0x06, GT_I64),
+    (0x07, ADD_F64),
+    (0x08, SUB_F64),
+    (0x09, MUL_F64),
+    (0x0A, GT_F64),
+    (0x0B, JUMP_FORWARD_IF_FALSE),
+    (0x0C, JMP),
+    (0x0D, I64_TO_F64),
+    (0x0E, F64_TO_I64),
+    (0x0F, JUMP_BACKWARD_IF_FALSE),
+    (0x10, JUMP_BACKWARD_IF_TRUE),
+    (0x11, JUMP_FORWARD_IF_TRUE),
+    (0x12, GTE_I64),
+    (0x13, LT_I64),
+    (0x14, LTE_I64),
+    (0x15, GTE_F64),
+    (0x16, LT_F64),
+    (0x17, LTE_F64),
+    (0x18, LOAD_CONST_VALUE),
+    (0x19, LOAD_CONST_SLICE),
+    (0x1A, CALL_HOST),
+    (0x1B, MOV),
+    (0x1C, STR_CONCAT),
+    (0x1D, STR_BUILDER_NEW),
+    (0x1E, STR_APPEND),
+    (0x1F, STR_APPEND_I64),
+    (0x20, STR_BUILDER_FINISH),
+];
+
+/// Rewrite ISA version 1 bytecode to the current opcode numbering
+fn renumber_v1(bytecode: &[u8]) -> Result<Vec<u8>, ProgramError> {
+    let mut out = bytecode.to_vec();
+    let mut pc = 0;
+    while pc < out.len() {
+        let opcode = V1_OPCODES
+            .iter()
+            .find(|(old, _)| *old == out[pc])
+            .map(|(_, new)| *new)
+            .ok_or_else(|| {
+                ProgramError::InvalidBytecode(format!("opcode 0x{:02X} at pc {}", out[pc], pc))
+            })?;
+        out[pc] = opcode;
+        // operand_len never fails for opcodes from the table
+        pc += 1 + operand_len(opcode).unwrap_or(0);
+    }
+    if pc > out.len() {
+        return Err(ProgramError::Truncated);
+    }
+    Ok(out)
+}
+
+impl VirtualMachine {
+    /// Decode a `.kayc` file, migrating older bytecode, and return bytecode
+    /// that is safe to pass to `eval_program`. A program carrying its own
+    /// consts replaces the VM's const pool and globals.
+    pub fn load_program(&mut self, data: &[u8]) -> Result<Vec<u8>, ProgramError> {
+        let program = Program::from_bytes(data)?.migrate()?;
+        let unsupported = program.features & !SUPPORTED_ISA_FEATURES;
+        if unsupported != 0 {
+            return Err(ProgramError::UnsupportedFeatures(unsupported));
+        }
+        // The header may understate what the bytecode really uses
+        let used = required_features(&program.bytecode)?;
+        if used & !program.features != 0 {
+            return Err(ProgramError::InvalidBytecode(format!(
+                "header declares features {:#x} but bytecode uses {:#x}",
+                program.features, used
+            )));
+        }
+        self.check_requirements(&program)?;
+        if let Some(consts) = &program.consts {
+            // Calls compiled before a function gained optional parameters
+            // go through an adapter that passes their defaults
+            let mut adapted = HashMap::new();
+            for required in &program.requirements {
+                let index = self.host_functions.find(&required.name).expect("checked above");
+                let adapter = self.host_functions.with_params(index, required.num_params);
+                adapted.insert(required.name.as_str(), adapter);
+            }
+            self.const_pool = consts.to_pool_resolving(|name| {
+                adapted.get(name).copied().or_else(|| self.host_functions.find(name))
+            })?;
+            self.global_vars = GlobalVars::new();
+        }
+        // Grow the register file once instead of call by call, within
+        // limits. The file's own frame sizes are untrusted, so they are
+        // worked out again from the bytecode.
+        let window = host_window(&program.requirements, &self.host_functions);
+        if let Some(top_level) = frame_registers(&program.bytecode, window)?.first() {
+            let len = self.base + top_level.registers;
+            let len = self.limits.max_registers.map_or(len, |max| len.min(max));
+            self.registers.ensure_len(len);
+            self.registers_type.ensure_len(len);
+        }
+        Ok(program.bytecode)
+    }
+}
+
+impl VirtualMachine {
+    /// Check that every host function `program` calls is registered with
+    /// the return register count it was compiled against, at the same or a
+    /// later version, taking the parameters it passes, reporting all that
+    /// are not
+    pub fn check_requirements(&self, program: &Program) -> Result<(), ProgramError> {
+        let unmet: Vec<UnmetRequirement> = program
+            .requirements
+            .iter()
+            .filter_map(|required| {
+                let meta = self
+                    .host_functions
+                    .find(&required.name)
+                    .map(|index| &self.host_functions.metadata[index]);
+                match meta {
+                    Some(meta) if required.is_met_by(meta) => None,
+                    _ => Some(UnmetRequirement {
+                        required: required.clone(),
+                        registered: meta.map(HostRequirement::from_metadata),
+                    }),
+                }
+            })
+            .collect();
+        if unmet.is_empty() {
+            Ok(())
+        } else {
+            Err(ProgramError::UnmetRequirements(unmet))
+        }
+    }
+}
+
+/// CRC-32 of `data` with the 4-byte checksum field at `crc_pos` left out
+fn checksum(data: &[u8], crc_pos: usize) -> u32 {
+    let crc = crc32_update(0, &data[..crc_pos]);
+    crc32_update(crc, &data[crc_pos + 4..])
+}
+
+#[cfg(feature = "compress")]
+fn compress_section(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec(data, 6)
+}
+
+#[cfg(not(feature = "compress"))]
+fn compress_section(_data: &[u8]) -> Vec<u8> {
+    unreachable!("compressed programs are only written with the `compress` feature")
+}
+
+#[cfg(feature = "compress")]
+fn decompress_section(data: &[u8]) -> Result<Vec<u8>, ProgramError> {
+    miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_CONSTS_SIZE)
+        .map_err(|_| ProgramError::InvalidBytecode("corrupt compressed const section".to_string()))
+}
+
+#[cfg(not(feature = "compress"))]
+fn decompress_section(_data: &[u8]) -> Result<Vec<u8>, ProgramError> {
+    Err(ProgramError::CompressionUnsupported)
+}
+
+/// Number of operand bytes following `opcode`, or `None` for unknown opcodes
+pub(crate) fn operand_len(opcode: u8) -> Option<usize> {
+    match opcode {
+        ADD_I64 | SUB_I64 | MUL_I64 | GT_I64 | GTE_I64 | LT_I64 | LTE_I64 => Some(3),
+        ADD_F64 | SUB_F64 | MUL_F64 | GT_F64 | GTE_F64 | LT_F64 | LTE_F64 => Some(3),
+        JUMP_FORWARD_IF_FALSE
+        | JUMP_FORWARD_IF_TRUE
+        | JUMP_BACKWARD_IF_FALSE
+        | JUMP_BACKWARD_IF_TRUE => Some(3),
+        LOAD_CONST_VALUE | LOAD_CONST_SLICE | ASSERT => Some(3),
+        JMP | CALL_HOST => Some(2),
+        CALL => Some(3),
+        RET => Some(0),
+        I64_TO_F64 | F64_TO_I64 | MOV | MOV_VALUE | SWAP => Some(2),
+        MIN_I64 | MAX_I64 | MIN_F64 | MAX_F64 => Some(3),
+        MOD_I64 | POW_I64 | POW_F64 => Some(3),
+        AND_I64 | OR_I64 | XOR_I64 | SHL_I64 | SHR_I64 => Some(3),
+        NOT_I64 => Some(2),
+        LT_I64_F64 | LTE_I64_F64 | GT_I64_F64 | GTE_I64_F64 => Some(3),
+        ABS_I64 | ABS_F64 => Some(2),
+        ROUND_F64 | FLOOR_F64 | CEIL_F64 | TRUNC_F64 => Some(2),
+        SELECT | ASSERT_ENUM_VALUE | ASSERT_ENUM_NAME => Some(4),
+        STR_CONCAT | STR_EQ => Some(3),
+        STR_HASH => Some(2),
+        STR_BUILDER_NEW | NOP | DEBUG_BREAK => Some(0),
+        STR_APPEND | STR_APPEND_I64 | STR_BUILDER_FINISH => Some(1),
+        _ => None,
+    }
+}
+
+/// Mnemonic of `opcode`, as used by the disassembler
+pub fn opcode_name(opcode: u8) -> Option<&'static str> {
+    Some(match opcode {
+        LOAD_CONST_VALUE => "LOAD_CONST_VALUE",
+        MOV => "MOV",
+        MOV_VALUE => "MOV_VALUE",
+        ADD_I64 => "ADD_I64",
+        SUB_I64 => "SUB_I64",
+        MUL_I64 => "MUL_I64",
+        LT_I64 => "LT_I64",
+        LTE_I64 => "LTE_I64",
+        GT_I64 => "GT_I64",
+        GTE_I64 => "GTE_I64",
+        JMP => "JMP",
+        JUMP_FORWARD_IF_FALSE => "JUMP_FORWARD_IF_FALSE",
+        JUMP_FORWARD_IF_TRUE => "JUMP_FORWARD_IF_TRUE",
+        JUMP_BACKWARD_IF_FALSE => "JUMP_BACKWARD_IF_FALSE",
+        JUMP_BACKWARD_IF_TRUE => "JUMP_BACKWARD_IF_TRUE",
+        LOAD_CONST_SLICE => "LOAD_CONST_SLICE",
+        CALL_HOST => "CALL_HOST",
+        CALL => "CALL",
+        RET => "RET",
+        ADD_F64 => "ADD_F64",
+        SUB_F64 => "SUB_F64",
+        MUL_F64 => "MUL_F64",
+        LT_F64 => "LT_F64",
+        LTE_F64 => "LTE_F64",
+        GT_F64 => "GT_F64",
+        GTE_F64 => "GTE_F64",
+        I64_TO_F64 => "I64_TO_F64",
+        F64_TO_I64 => "F64_TO_I64",
+        STR_CONCAT => "STR_CONCAT",
+        STR_EQ => "STR_EQ",
+        STR_HASH => "STR_HASH",
+        LT_I64_F64 => "LT_I64_F64",
+        LTE_I64_F64 => "LTE_I64_F64",
+        GT_I64_F64 => "GT_I64_F64",
+        GTE_I64_F64 => "GTE_I64_F64",
+        STR_BUILDER_NEW => "STR_BUILDER_NEW",
+        STR_APPEND => "STR_APPEND",
+        STR_APPEND_I64 => "STR_APPEND_I64",
+        STR_BUILDER_FINISH => "STR_BUILDER_FINISH",
+        ASSERT => "ASSERT",
+        NOP => "NOP",
+        DEBUG_BREAK => "DEBUG_BREAK",
+        SWAP => "SWAP",
+        SELECT => "SELECT",
+        MIN_I64 => "MIN_I64",
+        MAX_I64 => "MAX_I64",
+        ABS_I64 => "ABS_I64",
+        MIN_F64 => "MIN_F64",
+        MAX_F64 => "MAX_F64",
+        ABS_F64 => "ABS_F64",
+        ROUND_F64 => "ROUND_F64",
+        FLOOR_F64 => "FLOOR_F64",
+        CEIL_F64 => "CEIL_F64",
+        TRUNC_F64 => "TRUNC_F64",
+        MOD_I64 => "MOD_I64",
+        POW_I64 => "POW_I64",
+        POW_F64 => "POW_F64",
+        AND_I64 => "AND_I64",
+        OR_I64 => "OR_I64",
+        XOR_I64 => "XOR_I64",
+        NOT_I64 => "NOT_I64",
+        SHL_I64 => "SHL_I64",
+        SHR_I64 => "SHR_I64",
+        ASSERT_ENUM_VALUE => "ASSERT_ENUM_VALUE",
+        ASSERT_ENUM_NAME => "ASSERT_ENUM_NAME",
+        _ => return None,
+    })
+}
+
+/// Instruction set feature flag an opcode belongs to (0 for the core set)
+pub(crate) fn opcode_feature(opcode: u8) -> u32 {
+    match opcode {
+        ADD_F64 | SUB_F64 | MUL_F64 | GT_F64 | GTE_F64 | LT_F64 | LTE_F64 | I64_TO_F64
+        | F64_TO_I64 | MIN_F64 | MAX_F64 | ABS_F64 | ROUND_F64 | FLOOR_F64 | CEIL_F64
+        | TRUNC_F64 | LT_I64_F64 | LTE_I64_F64 | GT_I64_F64 | GTE_I64_F64 | POW_F64 => ISA_FLOAT,
+        CALL_HOST => ISA_HOST_CALLS,
+        STR_CONCAT | STR_BUILDER_NEW | STR_APPEND | STR_APPEND_I64 | STR_BUILDER_FINISH
+        | STR_EQ | STR_HASH | ASSERT_ENUM_NAME => ISA_STRINGS,
+        _ => 0,
+    }
+}
+
+/// Bitmap of the `ISA_*` features used by `bytecode`
+pub fn required_features(bytecode: &[u8]) -> Result<u32, ProgramError> {
+    let mut features = 0;
+    let mut pc = 0;
+    while pc < bytecode.len() {
+        let opcode = bytecode[pc];
+        let len = operand_len(opcode).ok_or_else(|| {
+            ProgramError::InvalidBytecode(format!("opcode 0x{:02X} at pc {}", opcode, pc))
+        })?;
+        if pc + 1 + len > bytecode.len() {
+            return Err(ProgramError::Truncated);
+        }
+        features |= opcode_feature(opcode);
+        pc += 1 + len;
+    }
+    Ok(features)
+}
+
+/// Frame sizes of the top-level code and each CALL target in `bytecode`,
+/// by entry.
+/// Instructions address at most `Registers::FIXED_COUNT` registers; frames
+/// go past that by calling, at the CALL's base register, or calling a host
+/// function at the CALL_HOST's, whose window is taken as `host_window`.
+pub fn frame_registers(bytecode: &[u8], host_window: usize) -> Result<Vec<FrameRegisters>, ProgramError> {
+    // Calls of each function as (base, target), and its own frame's size
+    let mut entries = vec![0];
+    let mut calls: Vec<Vec<(usize, usize)>> = Vec::new();
+    let mut own: Vec<usize> = Vec::new();
+    let mut next = 0;
+    while next < entries.len() {
+        let mut registers = Registers::FIXED_COUNT;
+        let mut function_calls = Vec::new();
+        let mut visited = vec![false; bytecode.len()];
+        let mut pending = vec![entries[next]];
+        while let Some(mut pc) = pending.pop() {
+            while pc < bytecode.len() && !visited[pc] {
+                visited[pc] = true;
+                let opcode = bytecode[pc];
+                let len = operand_len(opcode).ok_or_else(|| {
+                    ProgramError::InvalidBytecode(format!("opcode 0x{:02X} at pc {}", opcode, pc))
+                })?;
+                if pc + 1 + len > bytecode.len() {
+                    return Err(ProgramError::Truncated);
+                }
+                let operand = |at: usize| u16::from_le_bytes([bytecode[at], bytecode[at + 1]]) as usize;
+                let after = pc + 1 + len;
+                match opcode {
+                    RET => break,
+                    JMP => {
+                        pc = operand(pc + 1);
+                        continue;
+                    }
+                    JUMP_FORWARD_IF_FALSE | JUMP_FORWARD_IF_TRUE => pending.push(pc + 2 + operand(pc + 2)),
+                    JUMP_BACKWARD_IF_FALSE | JUMP_BACKWARD_IF_TRUE => {
+                        pending.push((pc + 4).saturating_sub(operand(pc + 2)))
+                    }
+                    CALL_HOST => registers = registers.max(operand(pc + 1) + host_window),
+                    CALL => {
+                        let target = operand(pc + 1);
+                        function_calls.push((bytecode[pc + 3] as usize, target));
+                        if !entries.contains(&target) {
+                            entries.push(target);
+                        }
+                    }
+                    _ => {}
+                }
+                pc = after;
+            }
+        }
+        own.push(registers);
+        calls.push(function_calls);
+        next += 1;
+    }
+
+    // Add callees' frames, leaving out calls back into a function still
+    // being sized
+    fn size(
+        function: usize,
+        entries: &[usize],
+        calls: &[Vec<(usize, usize)>],
+        own: &[usize],
+        sizes: &mut [Option<usize>],
+        active: &mut Vec<usize>,
+    ) -> usize {
+        if let Some(size) = sizes[function] {
+            return size;
+        }
+        active.push(function);
+        let mut registers = own[function];
+        for &(base, target) in &calls[function] {
+            let callee = entries.iter().position(|&e| e == target).expect("collected above");
+            if !active.contains(&callee) {
+                registers = registers.max(base + size(callee, entries, calls, own, sizes, active));
+            }
+        }
+        active.pop();
+        sizes[function] = Some(registers);
+        registers
+    }
+    let mut sizes = vec![None; entries.len()];
+    let mut frames: Vec<FrameRegisters> = (0..entries.len())
+        .map(|function| FrameRegisters {
+            entry: entries[function],
+            registers: size(function, &entries, &calls, &own, &mut sizes, &mut Vec::new()),
+        })
+        .collect();
+    frames.sort_by_key(|frame| frame.entry);
+    Ok(frames)
+}
+
+/// Registers a host call frame takes: enough for the largest of the
+/// `requirements` registered in `host_functions`
+fn host_window(requirements: &[HostRequirement], host_functions: &HostFunctionRegistry) -> usize {
+    requirements
+        .iter()
+        .filter_map(|r| host_functions.find(&r.name))
+        .map(|index| host_functions.metadata[index].num_registers)
+        .max()
+        .unwrap_or(1)
+}
+
+/// Host functions whose constants `bytecode` loads with LOAD_CONST_VALUE,
+/// which is how compiled code gets a function to CALL_HOST
+pub fn required_host_functions(
+    bytecode: &[u8],
+    pool: &ConstPool,
+    host_functions: &HostFunctionRegistry,
+) -> Result<Vec<HostRequirement>, ProgramError> {
+    let mut requirements: Vec<HostRequirement> = Vec::new();
+    let mut pc = 0;
+    while pc < bytecode.len() {
+        let opcode = bytecode[pc];
+        let len = operand_len(opcode).ok_or_else(|| {
+            ProgramError::InvalidBytecode(format!("opcode 0x{:02X} at pc {}", opcode, pc))
+        })?;
+        if pc + 1 + len > bytecode.len() {
+            return Err(ProgramError::Truncated);
+        }
+        if opcode == LOAD_CONST_VALUE {
+            let index = u16::from_le_bytes([bytecode[pc + 2], bytecode[pc + 3]]) as usize;
+            if let Some(meta) = pool.value_metadata.get(index)
+                && meta.typ == ValueType::FuncHost
+            {
+                let func = host_functions
+                    .metadata
+                    .get(pool.values[index] as usize)
+                    .ok_or_else(|| {
+                        ProgramError::InvalidBytecode(format!("host function index {}", pool.values[index]))
+                    })?;
+                if !requirements.iter().any(|r| r.name == func.name) {
+                    requirements.push(HostRequirement::from_metadata(func));
+                }
+            }
+        }
+        pc += 1 + len;
+    }
+    Ok(requirements)
+}
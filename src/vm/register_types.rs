@@ -1,9 +1,10 @@
 use super::global_vars::GlobalVarType;
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum RegisterType {
     /// Default value register
+    #[default]
     ValueRegister = 0,
     /// Main register for an allocated pointer variable, carrying its type
     AllocatedPtrVarMain(GlobalVarType),
@@ -13,12 +14,10 @@ pub enum RegisterType {
     ConstSliceVarMain = 5,
     /// Register holding length for constant slice
     ConstSliceVarLen = 6,
-}
-
-impl Default for RegisterType {
-    fn default() -> Self {
-        RegisterType::ValueRegister
-    }
+    /// Register holding pointer to a string in the VM's string heap
+    HeapStrMain = 7,
+    /// Register holding length of a heap string
+    HeapStrLen = 8,
 }
 
 pub struct RegisterTypes {
@@ -45,6 +45,11 @@ impl Registers {
         }
     }
 
+    /// Registers allocated beyond the fixed ones; the spill area never shrinks,
+    /// so this is also the peak
+    pub fn spill_len(&self) -> usize {
+        self.spill.len()
+    }
 }
 
 impl Default for Registers {
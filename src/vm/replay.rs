@@ -0,0 +1,172 @@
+use super::encoding::{ByteReader, ByteWriter};
+use super::call::{HostClosure, invoke_host};
+use super::*;
+
+const REPLAY_MAGIC: &[u8; 5] = b"KAYRL";
+pub const REPLAY_VERSION: u16 = 1;
+
+/// One host call as the script saw it
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostCallEvent {
+    pub name: String,
+    /// The call frame after the call, from the base (return value) to the top
+    pub frame: Vec<u64>,
+    pub error: Option<String>,
+}
+
+/// Host call results recorded during a run. Host functions are the only
+/// source of nondeterminism (time, environment, I/O), so feeding their
+/// results back reproduces the run exactly. Pointers returned by host
+/// functions are only meaningful inside the recording process.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayLog {
+    pub events: Vec<HostCallEvent>,
+}
+
+#[derive(Debug, Default)]
+pub enum ReplayMode {
+    #[default]
+    Off,
+    Record(ReplayLog),
+    Replay { log: ReplayLog, next: usize },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReplayError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::BadMagic => write!(f, "Not a Kayton replay log"),
+            ReplayError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported replay log version: {}", v)
+            }
+            ReplayError::Truncated => write!(f, "Replay log is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl ReplayLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = ByteWriter::new();
+        w.buf.extend_from_slice(REPLAY_MAGIC);
+        w.u16(REPLAY_VERSION);
+        w.u32(self.events.len() as u32);
+        for event in &self.events {
+            w.str(&event.name);
+            w.u32(event.frame.len() as u32);
+            for value in &event.frame {
+                w.u64(*value);
+            }
+            match &event.error {
+                Some(err) => {
+                    w.u8(1);
+                    w.str(err);
+                }
+                None => w.u8(0),
+            }
+        }
+        w.buf
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ReplayError> {
+        let mut r = ByteReader::new(data);
+        if r.take(REPLAY_MAGIC.len()) != Some(REPLAY_MAGIC) {
+            return Err(ReplayError::BadMagic);
+        }
+        let version = r.u16().ok_or(ReplayError::Truncated)?;
+        if version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+        let count = r.u32().ok_or(ReplayError::Truncated)?;
+        let mut events = Vec::new();
+        for _ in 0..count {
+            let name = r.str().ok_or(ReplayError::Truncated)?.to_string();
+            let len = r.u32().ok_or(ReplayError::Truncated)?;
+            let mut frame = Vec::new();
+            for _ in 0..len {
+                frame.push(r.u64().ok_or(ReplayError::Truncated)?);
+            }
+            let error = match r.u8().ok_or(ReplayError::Truncated)? {
+                0 => None,
+                _ => Some(r.str().ok_or(ReplayError::Truncated)?.to_string()),
+            };
+            events.push(HostCallEvent { name, frame, error });
+        }
+        Ok(Self { events })
+    }
+}
+
+impl VirtualMachine {
+    /// Log every host call result from now on
+    pub fn start_recording(&mut self) {
+        self.replay = ReplayMode::Record(ReplayLog::new());
+    }
+
+    /// Feed `log` back instead of calling host functions; their side effects
+    /// (such as printing) do not happen again
+    pub fn start_replay(&mut self, log: ReplayLog) {
+        self.replay = ReplayMode::Replay { log, next: 0 };
+    }
+
+    /// Stop recording or replaying, returning the log
+    pub fn stop_replay(&mut self) -> Option<ReplayLog> {
+        match std::mem::take(&mut self.replay) {
+            ReplayMode::Off => None,
+            ReplayMode::Record(log) | ReplayMode::Replay { log, .. } => Some(log),
+        }
+    }
+
+}
+
+impl ReplayMode {
+    /// Run (or, when replaying, stand in for) the host function `name` over
+    /// the frame `base..=top`
+    pub(crate) fn call(
+        &mut self,
+        func: &HostClosure,
+        name: &str,
+        registers: &mut Registers,
+        base: usize,
+        top: usize,
+    ) -> Result<Result<(), String>, VmError> {
+        match self {
+            ReplayMode::Off => invoke_host(func, base, registers),
+            ReplayMode::Record(log) => {
+                let result = invoke_host(func, base, registers)?;
+                log.events.push(HostCallEvent {
+                    name: name.to_string(),
+                    frame: (base..=top).map(|r| registers.get(r)).collect(),
+                    error: result.clone().err(),
+                });
+                Ok(result)
+            }
+            ReplayMode::Replay { log, next } => {
+                let event = log.events.get(*next).ok_or_else(|| {
+                    VmError::ReplayDivergence(format!("no recorded call left for '{}'", name))
+                })?;
+                if event.name != name {
+                    return Err(VmError::ReplayDivergence(format!(
+                        "call {} was to '{}', now '{}'",
+                        next, event.name, name
+                    )));
+                }
+                for (offset, value) in event.frame.iter().enumerate() {
+                    registers.set(base + offset, *value);
+                }
+                *next += 1;
+                Ok(event.error.clone().map_or(Ok(()), Err))
+            }
+        }
+    }
+}
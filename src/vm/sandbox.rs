@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+/// Resource limits enforced while executing bytecode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of instructions a single `eval_program` call may execute
+    pub max_instructions: Option<u64>,
+    /// Maximum number of registers a program may grow the register file to
+    pub max_registers: Option<usize>,
+    /// Fuel a single `eval_program` call may burn, priced by the VM's `CostTable`
+    pub max_fuel: Option<u64>,
+}
+
+/// Fuel charged per instruction for gas-style metering. Every opcode costs 1
+/// unless configured otherwise; a host call additionally costs whatever was
+/// set for the function it calls.
+#[derive(Debug, Clone)]
+pub struct CostTable {
+    opcodes: [u64; 256],
+    host_functions: HashMap<String, u64>,
+}
+
+impl CostTable {
+    pub fn new() -> Self {
+        Self {
+            opcodes: [1; 256],
+            host_functions: HashMap::new(),
+        }
+    }
+
+    pub fn opcode(mut self, opcode: u8, cost: u64) -> Self {
+        self.opcodes[opcode as usize] = cost;
+        self
+    }
+
+    /// Extra cost of calling the host function `name`, on top of CALL_HOST
+    pub fn host_function(mut self, name: &str, cost: u64) -> Self {
+        self.host_functions.insert(name.to_string(), cost);
+        self
+    }
+
+    pub fn opcode_cost(&self, opcode: u8) -> u64 {
+        self.opcodes[opcode as usize]
+    }
+
+    pub fn host_function_cost(&self, name: &str) -> u64 {
+        self.host_functions.get(name).copied().unwrap_or(0)
+    }
+}
+
+impl Default for CostTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Capabilities granted to scripts; host modules that need one are only
+/// registered by the stdlib when it is enabled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub time: bool,
+    pub env: bool,
+    pub fs: bool,
+    pub net: bool,
+    pub process: bool,
+}
+
+impl Capabilities {
+    /// No access to the outside world (the default)
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn all() -> Self {
+        Self {
+            time: true,
+            env: true,
+            fs: true,
+            net: true,
+            process: true,
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use super::const_pool::{ConstPool, SliceType, ValueType};
+use super::debug_info::DebugInfo;
+use super::encoding::{ByteReader, ByteWriter};
+use super::*;
+
+const SNAPSHOT_MAGIC: &[u8; 5] = b"KAYVM";
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+const GLOBAL_VALUE: u8 = 0;
+const GLOBAL_SLICE: u8 = 1;
+/// String created at runtime, stored by value
+const GLOBAL_HEAP_STR: u8 = 2;
+
+#[derive(Debug, PartialEq)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidData(String),
+    /// A string global points outside the const pool and cannot be relocated
+    UnrelocatablePointer(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "Not a Kayton VM snapshot"),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported snapshot version: {}", v)
+            }
+            SnapshotError::Truncated => write!(f, "Snapshot is truncated"),
+            SnapshotError::InvalidData(msg) => write!(f, "Invalid snapshot data: {}", msg),
+            SnapshotError::UnrelocatablePointer(name) => {
+                write!(f, "Global '{}' does not point into the const pool", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl VirtualMachine {
+    /// Serialize globals, the const pool and debug info so a session can be resumed later.
+    /// Host functions are not saved; the restoring VM must register them in the same order.
+    pub fn save_snapshot(&self) -> Result<Vec<u8>, SnapshotError> {
+        let mut w = ByteWriter::new();
+        w.buf.extend_from_slice(SNAPSHOT_MAGIC);
+        w.u16(SNAPSHOT_VERSION);
+
+        // Const pool
+        let pool = &self.const_pool;
+        w.u32(pool.values.len() as u32);
+        for (value, meta) in pool.values.iter().zip(&pool.value_metadata) {
+            w.str(&meta.name);
+            w.u8(meta.typ.tag());
+            w.u64(*value);
+        }
+        w.u32(pool.slice_count() as u32);
+        for (slice, meta) in pool.slices().zip(&pool.slice_metadata) {
+            w.str(&meta.name);
+            w.u8(meta.typ.tag());
+            w.bytes(slice);
+        }
+
+        // Globals, with pointers stored as (slice index, offset)
+        let mut globals: Vec<_> = self.global_vars.iter().collect();
+        globals.sort_by_key(|(name, _)| *name);
+        w.u32(globals.len() as u32);
+        for (name, var) in globals {
+            w.str(name);
+            w.u64(var.register_id as u64);
+            match var.meta.typ {
+                // A list or dict lives in host memory, which the snapshot
+                // cannot hold
+                GlobalVarType::Value(ValueType::List | ValueType::Dict) => {
+                    return Err(SnapshotError::UnrelocatablePointer(name.to_string()));
+                }
+                GlobalVarType::Value(typ) => {
+                    w.u8(GLOBAL_VALUE);
+                    w.u8(typ.tag());
+                    w.u64(self.registers.get(var.register_id));
+                }
+                GlobalVarType::Ptr(PtrType::Slice(typ)) => {
+                    let ptr = self.registers.get(var.register_id);
+                    let len = self.registers.get(var.register_id + 1);
+                    if self.strings.contains(ptr) {
+                        let data =
+                            unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) };
+                        w.u8(GLOBAL_HEAP_STR);
+                        w.u8(typ.tag());
+                        w.bytes(data);
+                        continue;
+                    }
+                    let (index, offset) = self
+                        .const_pool
+                        .locate_slice(ptr)
+                        .ok_or_else(|| SnapshotError::UnrelocatablePointer(name.to_string()))?;
+                    w.u8(GLOBAL_SLICE);
+                    w.u8(typ.tag());
+                    w.u32(index as u32);
+                    w.u64(offset as u64);
+                    w.u64(len);
+                }
+            }
+        }
+
+        // Docstrings
+        let mut docs: Vec<_> = self.debug_info.iter().collect();
+        docs.sort();
+        w.u32(docs.len() as u32);
+        for (name, doc) in docs {
+            w.str(name);
+            w.str(doc);
+        }
+
+        Ok(w.buf)
+    }
+
+    /// Replace globals, const pool, string heap and debug info with the contents of a snapshot
+    pub fn restore_snapshot(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+        let mut r = ByteReader::new(data);
+        if r.take(SNAPSHOT_MAGIC.len()) != Some(SNAPSHOT_MAGIC.as_slice()) {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = r.u16().ok_or(SnapshotError::Truncated)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let mut pool = ConstPool::new();
+        let value_count = r.u32().ok_or(SnapshotError::Truncated)?;
+        for _ in 0..value_count {
+            let name = r.str().ok_or(SnapshotError::Truncated)?;
+            let typ = read_value_type(&mut r)?;
+            let value = r.u64().ok_or(SnapshotError::Truncated)?;
+            pool.add_value(name, value, typ);
+        }
+        let slice_count = r.u32().ok_or(SnapshotError::Truncated)?;
+        for _ in 0..slice_count {
+            let name = r.str().ok_or(SnapshotError::Truncated)?;
+            let typ = read_slice_type(&mut r)?;
+            let data = r.bytes().ok_or(SnapshotError::Truncated)?;
+            pool.add_slice(name, data, typ);
+        }
+
+        let mut strings = StringHeap::new();
+        let mut global_vars = GlobalVars::new();
+        let mut registers = Vec::new();
+        let global_count = r.u32().ok_or(SnapshotError::Truncated)?;
+        for _ in 0..global_count {
+            let name = r.str().ok_or(SnapshotError::Truncated)?;
+            let reg = r.u64().ok_or(SnapshotError::Truncated)? as usize;
+            match r.u8().ok_or(SnapshotError::Truncated)? {
+                GLOBAL_VALUE => {
+                    let typ = read_value_type(&mut r)?;
+                    let value = r.u64().ok_or(SnapshotError::Truncated)?;
+                    global_vars.insert(name, reg, GlobalVarType::Value(typ));
+                    registers.push((reg, value, RegisterType::ValueRegister));
+                }
+                GLOBAL_SLICE => {
+                    let typ = read_slice_type(&mut r)?;
+                    let index = r.u32().ok_or(SnapshotError::Truncated)? as usize;
+                    let offset = r.u64().ok_or(SnapshotError::Truncated)? as usize;
+                    let len = r.u64().ok_or(SnapshotError::Truncated)?;
+                    let slice = pool.slice(index).ok_or_else(|| {
+                        SnapshotError::InvalidData(format!("slice index {} for '{}'", index, name))
+                    })?;
+                    if offset as u64 + len > slice.len() as u64 {
+                        return Err(SnapshotError::InvalidData(format!(
+                            "slice range out of bounds for '{}'",
+                            name
+                        )));
+                    }
+                    let ptr = slice.as_ptr() as u64 + offset as u64;
+                    global_vars.insert(name, reg, GlobalVarType::Ptr(PtrType::Slice(typ)));
+                    registers.push((reg, ptr, RegisterType::ConstSliceVarMain));
+                    registers.push((reg + 1, len, RegisterType::ConstSliceVarLen));
+                }
+                GLOBAL_HEAP_STR => {
+                    let typ = read_slice_type(&mut r)?;
+                    let data = r.bytes().ok_or(SnapshotError::Truncated)?;
+                    let ptr = strings.alloc(data) as u64;
+                    global_vars.insert(name, reg, GlobalVarType::Ptr(PtrType::Slice(typ)));
+                    registers.push((reg, ptr, RegisterType::HeapStrMain));
+                    registers.push((reg + 1, data.len() as u64, RegisterType::HeapStrLen));
+                }
+                kind => {
+                    return Err(SnapshotError::InvalidData(format!("global kind {}", kind)));
+                }
+            }
+        }
+
+        let mut debug_info = DebugInfo::new();
+        let doc_count = r.u32().ok_or(SnapshotError::Truncated)?;
+        for _ in 0..doc_count {
+            let name = r.str().ok_or(SnapshotError::Truncated)?;
+            let doc = r.str().ok_or(SnapshotError::Truncated)?;
+            debug_info.set_doc(name, doc);
+        }
+        if !r.is_at_end() {
+            return Err(SnapshotError::InvalidData("trailing bytes".to_string()));
+        }
+
+        self.const_pool = pool;
+        self.strings = strings;
+        self.global_vars = global_vars;
+        self.debug_info = debug_info;
+        for (reg, value, typ) in registers {
+            self.registers.set(reg, value);
+            self.registers_type.set(reg, typ);
+        }
+        Ok(())
+    }
+}
+
+fn read_value_type(r: &mut ByteReader) -> Result<ValueType, SnapshotError> {
+    let tag = r.u8().ok_or(SnapshotError::Truncated)?;
+    ValueType::from_tag(tag)
+        .ok_or_else(|| SnapshotError::InvalidData(format!("value type tag {}", tag)))
+}
+
+fn read_slice_type(r: &mut ByteReader) -> Result<SliceType, SnapshotError> {
+    let tag = r.u8().ok_or(SnapshotError::Truncated)?;
+    SliceType::from_tag(tag)
+        .ok_or_else(|| SnapshotError::InvalidData(format!("slice type tag {}", tag)))
+}
@@ -0,0 +1,152 @@
+use super::program::{opcode_name, operand_len};
+use super::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Size and instruction mix of a piece of bytecode
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BytecodeStats {
+    /// Total size in bytes
+    pub size: usize,
+    pub instructions: usize,
+    /// Instruction count per opcode mnemonic
+    pub opcodes: BTreeMap<&'static str, usize>,
+    /// Labels placed in the builder; for compiled bytecode, distinct jump targets
+    pub labels: usize,
+    /// Moves and arithmetic on values of one known type, i64 or f64, that
+    /// leave register types alone
+    pub specialized: usize,
+    /// Moves that copy the register type along with the value: MOV, SWAP
+    /// and SELECT
+    pub generic: usize,
+}
+
+impl BytecodeStats {
+    pub fn from_bytecode(bytecode: &[u8]) -> Result<Self, VmError> {
+        let mut stats = Self {
+            size: bytecode.len(),
+            ..Self::default()
+        };
+        let mut targets = BTreeSet::new();
+        let mut pc = 0;
+        while pc < bytecode.len() {
+            let opcode = bytecode[pc];
+            let (Some(name), Some(len)) = (opcode_name(opcode), operand_len(opcode)) else {
+                return Err(VmError::InvalidOpcode(opcode));
+            };
+            if pc + 1 + len > bytecode.len() {
+                return Err(VmError::UnexpectedEndOfProgram);
+            }
+            let operand = |at: usize| u16::from_le_bytes([bytecode[at], bytecode[at + 1]]) as usize;
+            match opcode {
+                JMP => {
+                    targets.insert(operand(pc + 1));
+                }
+                JUMP_FORWARD_IF_FALSE | JUMP_FORWARD_IF_TRUE => {
+                    targets.insert(pc + 2 + operand(pc + 2));
+                }
+                JUMP_BACKWARD_IF_FALSE | JUMP_BACKWARD_IF_TRUE => {
+                    targets.insert((pc + 4).wrapping_sub(operand(pc + 2)));
+                }
+                _ => {}
+            }
+            match opcode {
+                MOV | SWAP | SELECT => stats.generic += 1,
+                op if is_specialized(op) => stats.specialized += 1,
+                _ => {}
+            }
+            *stats.opcodes.entry(name).or_default() += 1;
+            stats.instructions += 1;
+            pc += 1 + len;
+        }
+        stats.labels = targets.len();
+        Ok(stats)
+    }
+
+    /// Share of the value operations that are specialized, in percent;
+    /// `None` if there are none
+    pub fn specialized_percent(&self) -> Option<f64> {
+        let total = self.specialized + self.generic;
+        (total > 0).then(|| self.specialized as f64 * 100.0 / total as f64)
+    }
+}
+
+/// Whether `opcode` works on i64 or f64 values only
+fn is_specialized(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        MOV_VALUE
+            | ADD_I64
+            | SUB_I64
+            | MUL_I64
+            | LT_I64
+            | LTE_I64
+            | GT_I64
+            | GTE_I64
+            | MIN_I64
+            | MAX_I64
+            | ABS_I64
+            | MOD_I64
+            | POW_I64
+            | AND_I64
+            | OR_I64
+            | XOR_I64
+            | NOT_I64
+            | SHL_I64
+            | SHR_I64
+            | ADD_F64
+            | SUB_F64
+            | MUL_F64
+            | LT_F64
+            | LTE_F64
+            | GT_F64
+            | GTE_F64
+            | MIN_F64
+            | MAX_F64
+            | ABS_F64
+            | POW_F64
+            | I64_TO_F64
+            | F64_TO_I64
+            | ROUND_F64
+            | FLOOR_F64
+            | CEIL_F64
+            | TRUNC_F64
+            | LT_I64_F64
+            | LTE_I64_F64
+            | GT_I64_F64
+            | GTE_I64_F64
+    )
+}
+
+impl fmt::Display for BytecodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "size:         {} bytes", self.size)?;
+        writeln!(f, "instructions: {}", self.instructions)?;
+        writeln!(f, "labels:       {}", self.labels)?;
+        if let Some(percent) = self.specialized_percent() {
+            writeln!(
+                f,
+                "specialized:  {} of {} value operations ({:.1}%)",
+                self.specialized,
+                self.specialized + self.generic,
+                percent
+            )?;
+        }
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        // Most frequent first, ties by name
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (name, count) in opcodes {
+            writeln!(f, "  {:<24}{:>6}", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+impl BytecodeBuilder {
+    /// Statistics over the bytecode emitted so far
+    pub fn stats(&self) -> BytecodeStats {
+        let mut stats = BytecodeStats::from_bytecode(self.bytecode())
+            .expect("the builder only emits well-formed instructions");
+        stats.labels = self.label_count();
+        stats
+    }
+}
@@ -0,0 +1,222 @@
+use super::print_bytecode::format_instruction;
+use super::*;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// What a `Stepper` call stopped on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// One instruction ran; `pc` is the next one
+    Stepped,
+    /// Execution stopped before the breakpoint at this pc
+    Breakpoint(usize),
+    /// The program ran off its end
+    Finished,
+}
+
+/// Runs a program one instruction at a time on a borrowed VM, for debuggers.
+/// Uses the checked interpreter, so any bytecode is safe to step through.
+pub struct Stepper {
+    bytecode: Vec<u8>,
+    pub pc: usize,
+    pub breakpoints: BTreeSet<usize>,
+    /// Disassembly as (pc, text) per instruction
+    listing: Vec<(usize, String)>,
+    boundaries: Vec<bool>,
+}
+
+impl Stepper {
+    pub fn new(bytecode: Vec<u8>) -> Self {
+        Self::with_start(bytecode, 0)
+    }
+
+    /// Step a multi-function image from its entry function
+    pub fn from_image(image: &ProgramImage) -> Self {
+        Self::with_start(image.bytecode.clone(), image.entry as usize)
+    }
+
+    fn with_start(bytecode: Vec<u8>, pc: usize) -> Self {
+        let boundaries = instruction_boundaries(&bytecode);
+        let listing = disassemble(&bytecode, &boundaries);
+        Self {
+            bytecode,
+            pc,
+            breakpoints: BTreeSet::new(),
+            listing,
+            boundaries,
+        }
+    }
+
+    pub fn bytecode(&self) -> &[u8] {
+        &self.bytecode
+    }
+
+    /// Disassembled instructions as (pc, text)
+    pub fn listing(&self) -> &[(usize, String)] {
+        &self.listing
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.pc >= self.bytecode.len()
+    }
+
+    /// Set a breakpoint at `pc`, or clear it if one is set; returns whether
+    /// one is set afterwards
+    pub fn toggle_breakpoint(&mut self, pc: usize) -> bool {
+        if self.breakpoints.remove(&pc) {
+            false
+        } else {
+            self.breakpoints.insert(pc);
+            true
+        }
+    }
+
+    /// Execute the instruction at `pc`
+    pub fn step(&mut self, vm: &mut VirtualMachine) -> Result<StepOutcome, VmError> {
+        if self.is_finished() {
+            return Ok(StepOutcome::Finished);
+        }
+        if vm.poisoned {
+            return Err(VmError::Poisoned);
+        }
+        if let Err(err) = vm.execute_instruction::<true>(&self.bytecode, &mut self.pc) {
+            if matches!(err, VmError::HostPanic(_)) {
+                vm.poisoned = true;
+            }
+            return Err(err);
+        }
+        if !self.boundaries.get(self.pc).copied().unwrap_or(false) {
+            return Err(VmError::InvalidJumpTarget(self.pc));
+        }
+        Ok(if self.is_finished() {
+            StepOutcome::Finished
+        } else {
+            StepOutcome::Stepped
+        })
+    }
+
+    /// Run until the next breakpoint or the end of the program. The current
+    /// instruction always runs, so continuing from a breakpoint moves on.
+    /// A DEBUG_BREAK in the bytecode stops execution right after it.
+    pub fn resume(&mut self, vm: &mut VirtualMachine) -> Result<StepOutcome, VmError> {
+        loop {
+            let opcode = self.bytecode.get(self.pc).copied();
+            if self.step(vm)? == StepOutcome::Finished {
+                return Ok(StepOutcome::Finished);
+            }
+            if self.breakpoints.contains(&self.pc) || opcode == Some(DEBUG_BREAK) {
+                return Ok(StepOutcome::Breakpoint(self.pc));
+            }
+        }
+    }
+
+    /// Debugger view of the current state: disassembly around `pc` (`>` marks
+    /// it, `*` marks breakpoints), registers of the current frame with their
+    /// types, the call stack and globals. `rows` bounds the disassembly.
+    pub fn render(&self, vm: &VirtualMachine, rows: usize) -> String {
+        self.render_around(vm, rows, self.pc)
+    }
+
+    /// `render` with the disassembly window centred on `focus` instead of `pc`
+    pub fn render_around(&self, vm: &VirtualMachine, rows: usize, focus: usize) -> String {
+        let mut out = String::new();
+        let line = vm.debug_info.source_map.line_at(self.pc);
+        let _ = match line {
+            Some(line) => writeln!(out, "== code (pc {}, line {}) ==", self.pc, line),
+            None if self.is_finished() => writeln!(out, "== code (finished) =="),
+            None => writeln!(out, "== code (pc {}) ==", self.pc),
+        };
+        let current = self
+            .listing
+            .iter()
+            .position(|(pc, _)| *pc >= focus)
+            .unwrap_or(self.listing.len());
+        let first = current.saturating_sub(rows / 2);
+        for (pc, text) in self.listing.iter().skip(first).take(rows) {
+            let cursor = if *pc == self.pc { '>' } else { ' ' };
+            let breakpoint = if self.breakpoints.contains(pc) { '*' } else { ' ' };
+            let _ = writeln!(out, "{}{} {}", cursor, breakpoint, text);
+        }
+
+        let _ = writeln!(out, "== registers (base {}) ==", vm.base);
+        for reg in 0..Registers::FIXED_COUNT {
+            let abs = vm.base + reg;
+            let value = vm.registers.get(abs);
+            let typ = vm.registers_type.get(abs);
+            if value == 0 && typ == RegisterType::ValueRegister {
+                continue;
+            }
+            let _ = writeln!(out, "r{:<3} = {:<20} {}", reg, value as i64, type_label(typ));
+        }
+
+        let _ = writeln!(out, "== call stack ==");
+        for info in vm.call_stack.iter().rev() {
+            let _ = match info {
+                CallInfo::Global { base, .. } => writeln!(out, "<global> base {}", base),
+                CallInfo::Call { base, entry, .. } => {
+                    writeln!(out, "function at pc {} base {}", entry, base)
+                }
+                CallInfo::CallHost {
+                    base,
+                    host_fn_index,
+                    ..
+                } => {
+                    let name = vm.host_functions.metadata.get(*host_fn_index).map_or("?", |m| m.name);
+                    writeln!(out, "{}() base {}", name, base)
+                }
+            };
+        }
+
+        let _ = writeln!(out, "== globals ==");
+        let mut globals: Vec<_> = vm.global_vars.iter().collect();
+        globals.sort_by_key(|(name, _)| *name);
+        for (name, var) in globals {
+            let value = vm.format_global(name).unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "{} = {} (r{}, {:?})",
+                name, value, var.register_id, var.meta.typ
+            );
+        }
+        out
+    }
+}
+
+/// One line per instruction. Opcodes the disassembler does not know are
+/// shown as raw bytes, so the rest of the program still lists.
+fn disassemble(bytecode: &[u8], boundaries: &[bool]) -> Vec<(usize, String)> {
+    let mut listing = Vec::new();
+    let mut pc = 0;
+    while pc < bytecode.len() {
+        let mut text = String::new();
+        let next = match format_instruction(bytecode, pc, &mut text) {
+            Ok(next) => next,
+            Err(_) => {
+                let Some(len) = boundaries[pc + 1..].iter().position(|&b| b) else {
+                    break;
+                };
+                let raw: Vec<String> = bytecode[pc..=pc + len]
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect();
+                text = format!("{} <{}>", pc, raw.join(" "));
+                pc + 1 + len
+            }
+        };
+        listing.push((pc, text.trim_end().to_string()));
+        pc = next;
+    }
+    listing
+}
+
+fn type_label(typ: RegisterType) -> String {
+    match typ {
+        RegisterType::ValueRegister => "value".to_string(),
+        RegisterType::AllocatedPtrVarMain(var_type) => format!("ptr {:?}", var_type),
+        RegisterType::AllocatedPtrVarOther => "ptr (len)".to_string(),
+        RegisterType::ConstSliceVarMain => "const slice".to_string(),
+        RegisterType::ConstSliceVarLen => "const slice (len)".to_string(),
+        RegisterType::HeapStrMain => "heap str".to_string(),
+        RegisterType::HeapStrLen => "heap str (len)".to_string(),
+    }
+}
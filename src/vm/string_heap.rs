@@ -0,0 +1,200 @@
+//! Storage for strings created at runtime (concatenation, f-strings).
+//!
+//! Registers refer to heap strings the same way they refer to const slices,
+//! as a (pointer, length) pair, so host functions such as `print` handle both.
+//! Strings of up to `INLINE_CAPACITY` bytes are stored inline in 16-byte slots
+//! carved from shared slabs, so short strings cost no allocation of their own.
+//!
+//! Under `AllocationScope::Execution` the VM frees the strings of each run
+//! when it ends, keeping only those globals hold. Other registers typed as
+//! heap strings are reset to the empty string. Lists and dicts belong to
+//! their host modules and are not freed. The slabs themselves are
+//! kept and refilled by the next run, so a VM serving one run per request
+//! stops allocating for short strings once it is warm.
+
+/// Longest string stored inline in a slab slot
+pub const INLINE_CAPACITY: usize = 15;
+const SLOT_SIZE: usize = INLINE_CAPACITY + 1;
+const SLAB_SLOTS: usize = 256;
+
+type Slab = Box<[[u8; SLOT_SIZE]; SLAB_SLOTS]>;
+
+/// How long strings created by a run live
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationScope {
+    /// Until the host calls `StringHeap::clear`
+    #[default]
+    Vm,
+    /// Until the `eval_program` call that created them returns, unless a
+    /// global holds them then
+    Execution,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StringHeapStats {
+    pub inline_strings: usize,
+    pub large_strings: usize,
+    /// Bytes of string data held inline and in large strings
+    pub inline_bytes: usize,
+    pub large_bytes: usize,
+    /// Allocations made: one per slab plus one per large string
+    pub allocations: usize,
+}
+
+#[derive(Default)]
+pub struct StringHeap {
+    /// Slabs in use first; the rest were freed by `retain_only` and wait to
+    /// be reused
+    slabs: Vec<Slab>,
+    used_slabs: usize,
+    /// Next free slot in the last slab in use
+    next_slot: usize,
+    large: Vec<Box<[u8]>>,
+    stats: StringHeapStats,
+    /// Open string builders, innermost last (f-strings may nest)
+    builders: Vec<Vec<u8>>,
+    /// Finished builder buffers kept for reuse
+    spare_buffers: Vec<Vec<u8>>,
+}
+
+impl StringHeap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy `data` into the heap; the returned pointer stays valid until `clear`
+    pub fn alloc(&mut self, data: &[u8]) -> *const u8 {
+        self.alloc_concat(&[data])
+    }
+
+    /// Store the concatenation of `parts` without intermediate copies
+    pub fn alloc_concat(&mut self, parts: &[&[u8]]) -> *const u8 {
+        let len: usize = parts.iter().map(|p| p.len()).sum();
+        if len <= INLINE_CAPACITY {
+            self.stats.inline_bytes += len;
+            let slot = self.next_inline_slot();
+            let mut pos = 0;
+            for part in parts {
+                slot[pos..pos + part.len()].copy_from_slice(part);
+                pos += part.len();
+            }
+            // The last byte records the length so a slot is self-describing
+            slot[INLINE_CAPACITY] = len as u8;
+            return slot.as_ptr();
+        }
+        let mut data = Vec::with_capacity(len);
+        for part in parts {
+            data.extend_from_slice(part);
+        }
+        let data = data.into_boxed_slice();
+        let ptr = data.as_ptr();
+        self.large.push(data);
+        self.stats.large_strings += 1;
+        self.stats.large_bytes += len;
+        self.stats.allocations += 1;
+        ptr
+    }
+
+    fn next_inline_slot(&mut self) -> &mut [u8; SLOT_SIZE] {
+        if self.used_slabs == 0 || self.next_slot == SLAB_SLOTS {
+            if self.used_slabs == self.slabs.len() {
+                self.slabs.push(Box::new([[0; SLOT_SIZE]; SLAB_SLOTS]));
+                self.stats.allocations += 1;
+            }
+            self.used_slabs += 1;
+            self.next_slot = 0;
+        }
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        self.stats.inline_strings += 1;
+        &mut self.slabs[self.used_slabs - 1][slot]
+    }
+
+    /// Whether `ptr` points at a string owned by this heap
+    pub fn contains(&self, ptr: u64) -> bool {
+        let in_range = |start: *const u8, len: usize| {
+            let start = start as u64;
+            ptr >= start && ptr < start + len as u64
+        };
+        self.slabs[..self.used_slabs]
+            .iter()
+            .any(|slab| in_range(slab.as_ptr() as *const u8, SLOT_SIZE * SLAB_SLOTS))
+            || self.large.iter().any(|data| in_range(data.as_ptr(), data.len()))
+    }
+
+    /// Start building a string; parts are appended to the innermost builder
+    pub fn begin_builder(&mut self) {
+        let buf = self.spare_buffers.pop().unwrap_or_default();
+        self.builders.push(buf);
+    }
+
+    /// Append to the innermost builder; returns false if none is open
+    pub fn append(&mut self, data: &[u8]) -> bool {
+        match self.builders.last_mut() {
+            Some(buf) => {
+                buf.extend_from_slice(data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Close the innermost builder, storing its contents as one string
+    pub fn finish_builder(&mut self) -> Option<(*const u8, usize)> {
+        let mut buf = self.builders.pop()?;
+        let ptr = self.alloc(&buf);
+        let len = buf.len();
+        buf.clear();
+        self.spare_buffers.push(buf);
+        Some((ptr, len))
+    }
+
+    /// Free every string. Registers still pointing into the heap become dangling,
+    /// so callers must drop any globals or values referring to them first.
+    pub fn clear(&mut self) {
+        let mut spare_buffers = std::mem::take(&mut self.spare_buffers);
+        spare_buffers.extend(self.builders.drain(..).map(|mut buf| {
+            buf.clear();
+            buf
+        }));
+        *self = Self {
+            spare_buffers,
+            ..Self::new()
+        };
+    }
+
+    /// Free every string but the (pointer, length) pairs in `kept` that
+    /// point into the heap, which are copied and updated to their copies;
+    /// other pairs are left alone. Slabs are kept for later strings, and
+    /// open builders are dropped.
+    pub fn retain_only(&mut self, kept: &mut [(u64, u64)]) {
+        let copies: Vec<Option<Vec<u8>>> = kept
+            .iter()
+            .map(|&(ptr, len)| {
+                if len == 0 || !self.contains(ptr) {
+                    return None;
+                }
+                // The pair points into this heap, which is still intact
+                let data = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) };
+                Some(data.to_vec())
+            })
+            .collect();
+        for mut buf in self.builders.drain(..) {
+            buf.clear();
+            self.spare_buffers.push(buf);
+        }
+        self.large.clear();
+        self.used_slabs = 0;
+        self.next_slot = 0;
+        self.stats = StringHeapStats::default();
+        for (pair, copy) in kept.iter_mut().zip(copies) {
+            if let Some(data) = copy {
+                pair.0 = self.alloc(&data) as u64;
+            }
+        }
+    }
+
+    pub fn stats(&self) -> StringHeapStats {
+        self.stats
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::approx_constant)]
+
 use super::const_pool::ValueType;
 use super::*;
 use std::time::Duration;
@@ -6,6 +8,7 @@ fn add_i64(vm: &mut VirtualMachine, value: i64) -> u16 {
     vm.const_pool.add_value("", value as u64, ValueType::I64) as u16
 }
 
+#[cfg(feature = "isa-float")]
 fn add_f64(vm: &mut VirtualMachine, value: f64) -> u16 {
     vm.const_pool.add_value("", value.to_bits(), ValueType::F64) as u16
 }
@@ -30,6 +33,7 @@ fn test_basic_i64_arithmetic() {
     assert_eq!(vm.get_register_i64(0), 15);
 }
 
+#[cfg(feature = "isa-float")]
 #[test]
 fn test_basic_f64_arithmetic() {
     let mut vm = VirtualMachine::new();
@@ -51,6 +55,7 @@ fn test_basic_f64_arithmetic() {
     assert!((result - 6.28).abs() < 0.001);
 }
 
+#[cfg(feature = "isa-float")]
 #[test]
 fn test_type_conversions() {
     let mut vm = VirtualMachine::new();
@@ -116,6 +121,7 @@ fn test_register_base_offset() {
     assert_eq!(vm.get_register_i64(2), 0);
 }
 
+#[cfg(feature = "isa-float")]
 #[test]
 fn test_mixed_arithmetic() {
     let mut vm = VirtualMachine::new();
@@ -203,6 +209,7 @@ fn test_register_raw_operations() {
     assert_eq!(vm.get_register_raw(3), pi_bits);
 }
 
+#[cfg(feature = "isa-float")]
 #[test]
 fn test_f64_subtraction() {
     let mut vm = VirtualMachine::new();
@@ -247,6 +254,7 @@ fn test_i64_comparison_ops() {
     assert_eq!(vm.get_register_i64(4), 1);
 }
 
+#[cfg(feature = "isa-float")]
 #[test]
 fn test_f64_comparison_ops() {
     let mut vm = VirtualMachine::new();
@@ -269,6 +277,7 @@ fn test_f64_comparison_ops() {
     assert_eq!(vm.get_register_i64(4), 1);
 }
 
+#[cfg(feature = "isa-float")]
 #[test]
 fn test_f64_comparison() {
     let mut vm = VirtualMachine::new();
@@ -300,6 +309,7 @@ fn test_f64_comparison() {
     assert_eq!(vm.get_register_i64(5), 0); // 2.1 > 2.1 is false
 }
 
+#[cfg(feature = "isa-float")]
 #[test]
 fn test_f64_comparison_with_negatives() {
     let mut vm = VirtualMachine::new();
@@ -323,6 +333,7 @@ fn test_f64_comparison_with_negatives() {
     assert_eq!(vm.get_register_i64(0), 1); // -1.5 > -2.7 is true
 }
 
+#[cfg(feature = "isa-float")]
 #[test]
 fn test_complex_f64_operations() {
     let mut vm = VirtualMachine::new();
@@ -520,3 +531,37 @@ fn test_timeout_error_display() {
     assert!(error_string.contains("500ms"));
     println!("Timeout error display: {}", error_string);
 }
+
+#[test]
+fn test_mov_copies_value_and_type() {
+    let mut vm = VirtualMachine::new();
+    vm.const_pool
+        .add_slice("s", b"text", const_pool::SliceType::Utf8Str);
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_slice(0, 1);
+    builder.mov(1, 5);
+    builder.mov(2, 6);
+    let bytecode = builder.build();
+
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(vm.get_register_raw(5), vm.get_register_raw(1));
+    assert_eq!(vm.get_register_raw(6), 4);
+    assert_eq!(vm.get_register_type(5), RegisterType::ConstSliceVarMain);
+}
+
+#[test]
+fn test_mov_value_leaves_the_type() {
+    let mut vm = VirtualMachine::new();
+    vm.const_pool
+        .add_slice("s", b"text", const_pool::SliceType::Utf8Str);
+    let n = vm.const_pool.add_value("n", 42, ValueType::I64) as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_slice(0, 1);
+    builder.load_const_value(n, 3);
+    builder.mov_value(3, 1);
+    let bytecode = builder.build();
+
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(vm.get_register_i64(1), 42);
+    assert_eq!(vm.get_register_type(1), RegisterType::ConstSliceVarMain);
+}
@@ -513,3 +513,230 @@ fn test_target_based_error_handling() {
 
     println!("Target-based error handling tests passed");
 }
+
+#[test]
+fn test_target_based_forward_jump_taken() {
+    let mut vm = VirtualMachine::new();
+    let mut builder = BytecodeBuilder::new();
+    load_i64_const(&mut vm, &mut builder, 0, 1); // r1 = 0 (false)
+    let jump_pos = builder.current_pos();
+    // Skip the jump itself (4 bytes) and one LOAD_CONST_VALUE (4 bytes)
+    builder.jump_forward_if_false_to(1, jump_pos + 8);
+    load_i64_const(&mut vm, &mut builder, 100, 2); // skipped
+    load_i64_const(&mut vm, &mut builder, 200, 3);
+    let bytecode = builder.build();
+
+    vm.eval_program(&bytecode).unwrap();
+    assert!(vm.verify(&bytecode).is_ok());
+    assert_eq!(vm.get_register_i64(2), 0);
+    assert_eq!(vm.get_register_i64(3), 200);
+}
+
+#[test]
+fn test_jump_into_the_middle_of_an_instruction() {
+    let mut vm = VirtualMachine::new();
+    let mut builder = BytecodeBuilder::new();
+    load_i64_const(&mut vm, &mut builder, 1, 1);
+    builder.jmp_to(2); // operand bytes of the load
+    let bytecode = builder.build();
+    assert!(matches!(
+        vm.eval_program(&bytecode),
+        Err(VmError::InvalidJumpTarget(2))
+    ));
+}
+
+#[test]
+fn test_structured_if_else() {
+    for (cond, expected) in [(1, 10), (0, 20)] {
+        let mut vm = VirtualMachine::new();
+        let mut builder = BytecodeBuilder::new();
+        let ten = add_i64(&mut vm, 10);
+        let twenty = add_i64(&mut vm, 20);
+        load_i64_const(&mut vm, &mut builder, cond, 1);
+        builder
+            .if_(1, |b| b.load_const_value(ten, 2))
+            .else_(|b| b.load_const_value(twenty, 2));
+        // Without else_, the skip label is placed when the guard drops
+        builder.if_(1, |b| b.load_const_value(twenty, 3));
+        let bytecode = builder.build();
+
+        vm.eval_program(&bytecode).unwrap();
+        assert_eq!(vm.get_register_i64(2), expected);
+        assert_eq!(vm.get_register_i64(3), if cond != 0 { 20 } else { 0 });
+    }
+}
+
+#[test]
+fn test_structured_while_and_block() {
+    let mut vm = VirtualMachine::new();
+    let mut builder = BytecodeBuilder::new();
+    let one = add_i64(&mut vm, 1);
+    load_i64_const(&mut vm, &mut builder, 0, 1); // i = 0
+    load_i64_const(&mut vm, &mut builder, 5, 2); // n = 5
+    load_i64_const(&mut vm, &mut builder, 0, 3); // sum = 0
+    builder.load_const_value(one, 4);
+    builder.while_(
+        |b| {
+            b.lt_i64(1, 2, 5);
+            5
+        },
+        |b| {
+            b.add_i64(1, 4, 1);
+            b.add_i64(3, 1, 3);
+        },
+    );
+    builder.block(|b, end| {
+        b.jmp_to_label(end);
+        b.load_const_value(one, 3); // skipped
+    });
+    let bytecode = builder.build();
+
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(vm.get_register_i64(3), 1 + 2 + 3 + 4 + 5);
+}
+
+#[test]
+fn test_call_host_fn_places_arguments() {
+    fn sub(base: usize, registers: &mut Registers) -> Result<(), String> {
+        let a = registers.get(base + 1) as i64;
+        let b = registers.get(base + 2) as i64;
+        registers.set(base, (a - b) as u64);
+        Ok(())
+    }
+    let mut vm = VirtualMachine::new();
+    let fn_index = vm.host_functions.register("sub", 1, 2, 3, sub);
+    let meta = vm.host_functions.metadata[fn_index].clone();
+    let fn_const = vm
+        .const_pool
+        .add_value("sub", fn_index as u64, ValueType::FuncHost) as u16;
+    let mut builder = BytecodeBuilder::new();
+    load_i64_const(&mut vm, &mut builder, 50, 1);
+    load_i64_const(&mut vm, &mut builder, 8, 21);
+    // The first argument already sits in the frame, the second is moved in
+    builder.call_host_fn(&meta, fn_const, &[21, 1], 20);
+    let bytecode = builder.build();
+    // Two loads, one MOV, the function index load and CALL_HOST
+    assert_eq!(bytecode.len(), 4 + 4 + 3 + 4 + 3);
+    assert_eq!(&bytecode[8..11], &[MOV, 1, 22]);
+
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(vm.get_register_i64(20), 8 - 50);
+
+    let result = std::panic::catch_unwind(|| {
+        BytecodeBuilder::new().call_host_fn(&meta, fn_const, &[1], 20);
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_builder_stats() {
+    let mut builder = BytecodeBuilder::new();
+    let top = builder.create_label();
+    builder.place_label(top);
+    builder.add_i64(1, 2, 1);
+    builder.add_i64(1, 2, 1);
+    builder.lt_i64(1, 3, 4);
+    builder.jump_if_true_to_label(4, top);
+    builder.mov(1, 5);
+    let stats = builder.stats();
+    assert_eq!(stats.size, builder.bytecode().len());
+    assert_eq!(stats.instructions, 5);
+    assert_eq!((stats.specialized, stats.generic), (3, 1));
+    assert_eq!(stats.specialized_percent(), Some(75.0));
+    assert_eq!(stats.opcodes["ADD_I64"], 2);
+    assert_eq!(stats.labels, 1);
+    assert_eq!(BytecodeStats::from_bytecode(&builder.build()).unwrap().labels, 1);
+    assert!(stats.to_string().contains("ADD_I64"));
+    assert!(stats.to_string().contains("specialized:  3 of 4 value operations (75.0%)"));
+}
+
+#[test]
+fn test_multi_function_image() {
+    let mut vm = VirtualMachine::new();
+    let one = add_i64(&mut vm, 1);
+    let ten = add_i64(&mut vm, 10);
+    let mut builder = BytecodeBuilder::new();
+    builder.begin_function("helper");
+    builder.load_const_value(ten, 2);
+    builder.end_function();
+    builder.begin_function("main");
+    builder.load_const_value(one, 1);
+    // Forward reference, resolved at build time
+    builder.jmp_to_function("tail");
+    builder.end_function();
+    builder.begin_function("tail");
+    builder.add_i64(1, 1, 3);
+    builder.end_function();
+    builder.set_entry("main");
+    let image = builder.build_image();
+
+    assert_eq!(image.functions.len(), 3);
+    let main = image.function("main").unwrap();
+    assert_eq!(image.entry, main.offset);
+    assert_eq!(main.len, 4 + 3 + 3);
+    assert!(vm.verify(&image.bytecode).is_ok());
+
+    vm.eval_image(&image).unwrap();
+    // helper is never run; tail ends the program
+    assert_eq!(vm.get_register_i64(2), 0);
+    assert_eq!(vm.get_register_i64(3), 2);
+}
+
+#[test]
+fn test_undefined_function_reference() {
+    let result = std::panic::catch_unwind(|| {
+        let mut builder = BytecodeBuilder::new();
+        builder.begin_function("main");
+        builder.jmp_to_function("missing");
+        builder.end_function();
+        builder.build_image()
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_call_and_ret_switch_register_windows() {
+    let mut vm = VirtualMachine::new();
+    let one = add_i64(&mut vm, 1);
+    let five = add_i64(&mut vm, 5);
+    let mut builder = BytecodeBuilder::new();
+    builder.begin_function("main");
+    builder.load_const_value(five, 11);
+    // Callee window starts at r10: its r1 is our r11, its r0 our r10
+    builder.call_function("inc", 10);
+    builder.add_i64(10, 10, 3);
+    builder.end_function();
+    builder.begin_function("inc");
+    builder.load_const_value(one, 2);
+    builder.add_i64(1, 2, 0);
+    builder.ret();
+    builder.end_function();
+    let image = builder.build_image();
+    assert!(vm.verify(&image.bytecode).is_ok());
+
+    vm.eval_image(&image).unwrap();
+    assert_eq!(vm.get_register_i64(10), 6);
+    assert_eq!(vm.get_register_i64(3), 12);
+    assert_eq!(vm.get_register_i64(12), 1);
+    assert_eq!(vm.call_stack.len(), 1);
+    assert_eq!(vm.base, 0);
+}
+
+#[test]
+fn test_ret_without_call_and_runaway_recursion() {
+    let mut vm = VirtualMachine::new();
+    let mut builder = BytecodeBuilder::new();
+    builder.ret();
+    let err = vm.eval_program(&builder.build()).unwrap_err();
+    assert!(matches!(err, VmError::ReturnWithoutCall(0)));
+    assert_eq!(err.to_string(), "RET outside a call at pc 0");
+
+    let mut builder = BytecodeBuilder::new();
+    let top = builder.create_label();
+    builder.place_label(top);
+    builder.call_label(top, 1);
+    let err = vm.eval_program(&builder.build()).unwrap_err();
+    assert!(matches!(err, VmError::CallDepth(MAX_CALL_DEPTH)));
+    // The failed run's frames are dropped
+    assert_eq!((vm.call_stack.len(), vm.base), (1, 0));
+}
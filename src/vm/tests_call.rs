@@ -45,7 +45,43 @@ fn test_call_host_invalid_index() {
     let bytecode = builder.build();
 
     let result = vm.eval_program(&bytecode);
-    assert!(matches!(result, Err(VmError::InvalidConstIndex(999))));
+    assert!(matches!(
+        result,
+        Err(VmError::UnknownHostFunction { index: 999, ref nearby }) if nearby.is_empty()
+    ));
+}
+
+#[test]
+fn test_unknown_host_function_lists_nearby_names() {
+    let mut vm = VirtualMachine::new();
+    for name in ["a", "b", "c", "d", "e", "f", "g"] {
+        vm.host_functions.register(name, 1, 1, 2, inc);
+    }
+    let err = vm.host_functions.unknown(3);
+    assert_eq!(
+        err.to_string(),
+        "Unknown host function index 3 (registered nearby: b (1), c (2), d (3), e (4), f (5))"
+    );
+    let err = vm.host_functions.unknown(999);
+    assert!(err.to_string().ends_with("c (2), d (3), e (4), f (5), g (6))"));
+}
+
+#[test]
+fn test_call_host_function_directly() {
+    let mut vm = VirtualMachine::new();
+    let fn_index = vm.host_functions.register("inc", 1, 1, 2, inc);
+    assert_eq!(vm.call_host_function(fn_index, &[41]).unwrap(), 42);
+
+    let err = vm.call_host_function(fn_index, &[1, 2]).unwrap_err();
+    assert!(matches!(
+        &err,
+        VmError::HostFunctionArity { expected: 1, found: 2, .. }
+    ));
+    assert_eq!(err.to_string(), "inc() takes 1 arguments but 2 were given");
+    assert!(matches!(
+        vm.call_host_function(5, &[]),
+        Err(VmError::UnknownHostFunction { index: 5, .. })
+    ));
 }
 
 #[test]
@@ -92,3 +128,53 @@ fn test_call_host_multiple_calls() {
     assert_eq!(vm.get_register_i64(10), 6);
     assert_eq!(vm.get_register_i64(20), 101);
 }
+
+#[test]
+fn test_metrics_count_host_calls_and_spills() {
+    let mut vm = VirtualMachine::new();
+    let fn_index = vm.host_functions.register("inc", 1, 1, 2, inc);
+    vm.host_functions.register("unused", 1, 1, 2, inc);
+    // A wide frame near the end of the fixed registers spills past them
+    let wide_index = vm.host_functions.register("wide", 1, 1, 10, inc);
+    let idx1 = add_i64(&mut vm, 1);
+    let mut builder = BytecodeBuilder::new();
+    for (func, base) in [(fn_index, 10), (fn_index, 20), (wide_index, 250)] {
+        let fn_idx_const = add_fn(&mut vm, func);
+        builder.load_const_value(fn_idx_const, base);
+        builder.load_const_value(idx1, base + 1);
+        builder.call_host(base as u16);
+    }
+    vm.eval_program(&builder.build()).unwrap();
+
+    let metrics = vm.metrics();
+    assert_eq!(
+        metrics.host_calls.into_iter().collect::<Vec<_>>(),
+        vec![("inc".to_string(), 2), ("wide".to_string(), 1)]
+    );
+    assert_eq!(metrics.peak_spill_registers, 260 - Registers::FIXED_COUNT);
+    assert_eq!((metrics.const_values, metrics.const_slices), (4, 0));
+}
+
+fn explode(_base: usize, _registers: &mut Registers) -> Result<(), String> {
+    panic!("host blew up");
+}
+
+#[test]
+fn test_call_host_panic_poisons_vm() {
+    let mut vm = VirtualMachine::new();
+    let fn_index = vm.host_functions.register("explode", 0, 0, 1, explode);
+    let fn_idx_const = add_fn(&mut vm, fn_index);
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_value(fn_idx_const, 10);
+    builder.call_host(10);
+    let bytecode = builder.build();
+
+    let err = vm.eval_program(&bytecode).unwrap_err();
+    assert!(matches!(&err, VmError::HostPanic(msg) if msg == "host blew up"));
+    assert!(vm.poisoned);
+    assert!(matches!(vm.eval_program(&[]), Err(VmError::Poisoned)));
+
+    vm.clear_poison();
+    assert_eq!(vm.call_stack.len(), 1);
+    assert!(vm.eval_program(&[]).is_ok());
+}
@@ -1,5 +1,5 @@
 use super::const_pool::{SliceType, ValueType};
-use super::{BytecodeBuilder, VirtualMachine, RegisterType};
+use super::{mod_i64, BytecodeBuilder, RegisterType, VirtualMachine, VmError};
 
 #[test]
 fn test_load_const_value_and_slice() {
@@ -33,3 +33,282 @@ fn test_load_const_value_and_slice() {
         RegisterType::ConstSliceVarLen
     );
 }
+
+#[test]
+fn test_assert_fails_with_pc_and_message() {
+    let mut vm = VirtualMachine::new();
+    let one = vm.const_pool.add_value("one", 1, ValueType::I64) as u16;
+    let zero = vm.const_pool.add_value("zero", 0, ValueType::I64) as u16;
+    let msg = vm
+        .const_pool
+        .add_slice("msg", b"index in bounds", SliceType::Utf8Str) as u16;
+
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_value(one, 1);
+    builder.assert(1, msg);
+    builder.load_const_value(zero, 1);
+    builder.assert(1, msg);
+    builder.load_const_value(one, 2);
+    let bytecode = builder.build();
+
+    let err = vm.eval_program(&bytecode).unwrap_err();
+    assert_eq!(err.to_string(), "Assertion failed at pc 12: index in bounds");
+    // Nothing after the failed assertion ran
+    assert_eq!(vm.get_register_raw(2), 0);
+    assert!(vm.verify(&bytecode).is_ok());
+}
+
+#[test]
+fn test_nop_and_debug_break() {
+    use std::sync::{Arc, Mutex};
+
+    let mut vm = VirtualMachine::new();
+    let seven = vm.const_pool.add_value("seven", 7, ValueType::I64) as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.nop();
+    builder.debug_break();
+    builder.load_const_value(seven, 1);
+    builder.debug_break();
+    let bytecode = builder.build();
+    assert!(vm.verify(&bytecode).is_ok());
+
+    // Without a hook DEBUG_BREAK does nothing
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(vm.get_register_i64(1), 7);
+
+    let hits = Arc::new(Mutex::new(Vec::new()));
+    let seen = hits.clone();
+    vm.break_hook = Some(Arc::new(move |vm: &mut VirtualMachine, pc| {
+        seen.lock().unwrap().push((pc, vm.get_register_i64(1)));
+        vm.set_register_i64(1, 0);
+    }));
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(*hits.lock().unwrap(), vec![(1, 7), (6, 7)]);
+    assert_eq!(vm.get_register_i64(1), 0);
+}
+
+#[test]
+fn test_swap_and_select() {
+    let mut vm = VirtualMachine::new();
+    let five = vm.const_pool.add_value("five", 5, ValueType::I64) as u16;
+    let nine = vm.const_pool.add_value("nine", 9, ValueType::I64) as u16;
+    let msg = vm.const_pool.add_slice("s", b"hi", SliceType::Utf8Str) as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_value(five, 1);
+    builder.load_const_value(nine, 2);
+    builder.load_const_slice(msg, 3);
+    builder.swap(1, 3);
+    builder.select(2, 1, 3, 5); // r2 is truthy: r5 = r1
+    builder.select(0, 1, 3, 6); // r0 is zero: r6 = r3
+    builder.select(3, 6, 6, 3); // operands may alias dst
+    let bytecode = builder.build();
+    assert!(vm.verify(&bytecode).is_ok());
+
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(vm.get_register_i64(3), 5);
+    assert_eq!(vm.get_register_type(1), RegisterType::ConstSliceVarMain);
+    assert_eq!(vm.get_register_raw(5), vm.get_register_raw(1));
+    assert_eq!(vm.get_register_type(5), RegisterType::ConstSliceVarMain);
+    assert_eq!(vm.get_register_i64(6), 5);
+}
+
+#[test]
+fn test_min_max_abs() {
+    let mut vm = VirtualMachine::new();
+    let mut builder = BytecodeBuilder::new();
+    builder.min_i64(1, 2, 3);
+    builder.max_i64(1, 2, 4);
+    builder.abs_i64(1, 5);
+    builder.abs_i64(6, 6); // i64::MIN wraps
+    builder.max_i64(1, 2, 1); // operands may alias dst
+    let bytecode = builder.build();
+    assert!(vm.verify(&bytecode).is_ok());
+
+    vm.set_register_i64(1, -7);
+    vm.set_register_i64(2, 4);
+    vm.set_register_i64(6, i64::MIN);
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(
+        [1, 3, 4, 5, 6].map(|reg| vm.get_register_i64(reg)),
+        [4, -7, 4, 7, i64::MIN]
+    );
+}
+
+#[test]
+fn test_mod_and_pow() {
+    let mut vm = VirtualMachine::new();
+    let mut builder = BytecodeBuilder::new();
+    builder.mod_i64(1, 2, 3); // -7 % 4
+    builder.mod_i64(2, 1, 4); // 4 % -7
+    builder.pow_i64(2, 5, 6); // 4 ** 3
+    builder.pow_i64(2, 0, 7); // 4 ** 0
+    builder.pow_i64(2, 8, 8); // wraps
+    let bytecode = builder.build();
+    assert!(vm.verify(&bytecode).is_ok());
+
+    vm.set_register_i64(1, -7);
+    vm.set_register_i64(2, 4);
+    vm.set_register_i64(5, 3);
+    vm.set_register_i64(8, 40);
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(
+        [3, 4, 6, 7, 8].map(|reg| vm.get_register_i64(reg)),
+        [1, -3, 64, 1, 4i64.wrapping_pow(40)]
+    );
+    assert_eq!(mod_i64(i64::MIN, -1), 0);
+
+    let mut builder = BytecodeBuilder::new();
+    builder.mod_i64(1, 0, 2);
+    let err = vm.eval_program(&builder.build()).unwrap_err();
+    assert!(matches!(err, VmError::DivisionByZero(0)), "{:?}", err);
+    let mut builder = BytecodeBuilder::new();
+    builder.pow_i64(2, 1, 3);
+    let err = vm.eval_program(&builder.build()).unwrap_err();
+    assert_eq!(err.to_string(), "Cannot raise an int to the negative power -7 at pc 0");
+}
+
+#[test]
+fn test_bitwise_and_shifts() {
+    let mut vm = VirtualMachine::new();
+    let mut builder = BytecodeBuilder::new();
+    builder.and_i64(1, 2, 3); // 12 & 10
+    builder.or_i64(1, 2, 4); // 12 | 10
+    builder.xor_i64(1, 2, 5); // 12 ^ 10
+    builder.not_i64(1, 6); // ~12
+    builder.shl_i64(1, 7, 8); // 12 << 2
+    builder.shr_i64(9, 7, 10); // -12 >> 2 keeps the sign
+    builder.shl_i64(1, 11, 12); // shifts of 64 and more clear the value
+    builder.shr_i64(9, 11, 13); // or fill it with the sign
+    let bytecode = builder.build();
+    assert!(vm.verify(&bytecode).is_ok());
+
+    vm.set_register_i64(1, 12);
+    vm.set_register_i64(2, 10);
+    vm.set_register_i64(7, 2);
+    vm.set_register_i64(9, -12);
+    vm.set_register_i64(11, 64);
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(
+        [3, 4, 5, 6, 8, 10, 12, 13].map(|reg| vm.get_register_i64(reg)),
+        [8, 14, 6, -13, 48, -3, 0, -1]
+    );
+
+    let mut builder = BytecodeBuilder::new();
+    builder.shl_i64(2, 1, 3);
+    vm.set_register_i64(1, -1);
+    let err = vm.eval_program(&builder.build()).unwrap_err();
+    assert_eq!(err.to_string(), "Cannot shift by the negative count -1 at pc 0");
+}
+
+#[cfg(feature = "isa-float")]
+#[test]
+fn test_pow_f64() {
+    let mut vm = VirtualMachine::new();
+    let mut builder = BytecodeBuilder::new();
+    builder.pow_f64(1, 2, 3);
+    builder.pow_f64(1, 4, 4);
+    let bytecode = builder.build();
+    let features = super::program::required_features(&bytecode).unwrap();
+    assert_eq!(features, super::ISA_FLOAT);
+
+    vm.set_register_f64(1, 2.0);
+    vm.set_register_f64(2, 0.5);
+    vm.set_register_f64(4, -1.0);
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!([3, 4].map(|reg| vm.get_register_f64(reg)), [2f64.sqrt(), 0.5]);
+}
+
+#[cfg(feature = "isa-float")]
+#[test]
+fn test_min_max_abs_f64() {
+    let mut vm = VirtualMachine::new();
+    let mut builder = BytecodeBuilder::new();
+    builder.min_f64(1, 2, 3);
+    builder.max_f64(1, 2, 4);
+    builder.abs_f64(1, 5);
+    builder.min_f64(1, 6, 7); // NaN yields the other operand
+    let bytecode = builder.build();
+    assert!(vm.verify(&bytecode).is_ok());
+
+    vm.set_register_f64(1, -2.5);
+    vm.set_register_f64(2, 0.5);
+    vm.set_register_f64(6, f64::NAN);
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(
+        [3, 4, 5, 7].map(|reg| vm.get_register_f64(reg)),
+        [-2.5, 0.5, 2.5, -2.5]
+    );
+}
+
+#[cfg(feature = "isa-float")]
+#[test]
+fn test_float_rounding() {
+    let mut builder = BytecodeBuilder::new();
+    builder.round_f64(1, 2);
+    builder.floor_f64(1, 3);
+    builder.ceil_f64(1, 4);
+    builder.trunc_f64(1, 5);
+    let bytecode = builder.build();
+
+    let mut vm = VirtualMachine::new();
+    assert!(vm.verify(&bytecode).is_ok());
+    for (value, expected) in [(-2.5, [-2, -3, -2, -2]), (2.5, [2, 2, 3, 2]), (7.2, [7, 7, 8, 7])] {
+        vm.set_register_f64(1, value);
+        vm.eval_program(&bytecode).unwrap();
+        assert_eq!([2, 3, 4, 5].map(|reg| vm.get_register_i64(reg)), expected);
+    }
+    // Halves round to the even neighbour
+    for (value, expected) in [(0.5, 0), (1.5, 2), (2.5, 2), (-0.5, 0), (-1.5, -2)] {
+        vm.set_register_f64(1, value);
+        vm.eval_program(&bytecode).unwrap();
+        assert_eq!(vm.get_register_i64(2), expected, "round({})", value);
+    }
+
+    // -2^63 still fits; 2^63, NaN and infinities do not
+    vm.set_register_f64(1, -9_223_372_036_854_775_808.0);
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(vm.get_register_i64(5), i64::MIN);
+    for value in [9_223_372_036_854_775_808.0, f64::NAN, f64::NEG_INFINITY] {
+        vm.set_register_f64(1, value);
+        match vm.eval_program(&bytecode) {
+            Err(super::VmError::FloatToInt { pc: 0, value: v }) => {
+                assert_eq!(v.to_bits(), value.to_bits())
+            }
+            other => panic!("expected FloatToInt for {}, got {:?}", value, other),
+        }
+    }
+}
+
+#[cfg(feature = "isa-float")]
+#[test]
+fn test_mixed_comparisons_are_exact() {
+    let mut builder = BytecodeBuilder::new();
+    builder.lt_i64_f64(1, 2, 3);
+    builder.lte_i64_f64(1, 2, 4);
+    builder.gt_i64_f64(1, 2, 5);
+    builder.gte_i64_f64(1, 2, 6);
+    let bytecode = builder.build();
+    let mut vm = VirtualMachine::new();
+    assert!(vm.verify(&bytecode).is_ok());
+
+    const TWO_53: i64 = 1 << 53;
+    // [<, <=, >, >=]
+    for (int, float, expected) in [
+        (1, 2.5, [1, 1, 0, 0]),
+        (-3, -2.5, [1, 1, 0, 0]),
+        (-2, -2.5, [0, 0, 1, 1]),
+        (7, 7.0, [0, 1, 0, 1]),
+        // Converting the int to f64 would round it to 2^53 and call these equal
+        (TWO_53 + 1, TWO_53 as f64, [0, 0, 1, 1]),
+        (TWO_53 - 1, TWO_53 as f64, [1, 1, 0, 0]),
+        (i64::MAX, 9_223_372_036_854_775_808.0, [1, 1, 0, 0]),
+        (i64::MIN, -9_223_372_036_854_775_808.0, [0, 1, 0, 1]),
+        (i64::MIN, f64::NEG_INFINITY, [0, 0, 1, 1]),
+        (0, f64::NAN, [0, 0, 0, 0]),
+    ] {
+        vm.set_register_i64(1, int);
+        vm.set_register_f64(2, float);
+        vm.eval_program(&bytecode).unwrap();
+        assert_eq!([3, 4, 5, 6].map(|reg| vm.get_register_i64(reg)), expected, "{} vs {}", int, float);
+    }
+}
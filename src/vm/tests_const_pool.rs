@@ -1,3 +1,5 @@
+#![allow(clippy::approx_constant)]
+
 use super::const_pool::{ConstPool, SliceType, ValueType};
 
 #[test]
@@ -57,3 +59,52 @@ fn test_add_and_get_slice_constants() {
 
     assert_eq!(pool.get_slice("missing"), None);
 }
+
+#[test]
+fn test_checkpoint_rollback() {
+    let mut pool = ConstPool::new();
+    let print = pool.add_value("print", 0, ValueType::FuncHost);
+    let checkpoint = pool.checkpoint();
+    let generation = pool.generation();
+
+    for i in 0..100 {
+        pool.add_slice("s", format!("script constant {}", i).as_bytes(), SliceType::Utf8Str);
+    }
+    pool.add_value("print", 7, ValueType::I64);
+    let grown = pool.allocated_bytes();
+
+    assert!(pool.rollback(checkpoint));
+    assert_eq!(pool.values.len(), 1);
+    assert_eq!(pool.slice_count(), 0);
+    assert_eq!(pool.get_value("print"), Some(0));
+    assert_eq!(pool.get_slice("s"), None);
+    assert_ne!(pool.generation(), generation);
+    assert!(pool.allocated_bytes() <= grown);
+
+    // The checkpoint can be reused, and new constants get the same indices again
+    assert_eq!(pool.add_value("x", 1, ValueType::I64), print + 1);
+    assert!(pool.rollback(checkpoint));
+    assert_eq!(pool.values.len(), 1);
+}
+
+#[test]
+fn test_nested_checkpoints_and_reset() {
+    let mut pool = ConstPool::new();
+    let outer = pool.checkpoint();
+    pool.add_value("a", 1, ValueType::I64);
+    let inner = pool.checkpoint();
+    pool.add_value("b", 2, ValueType::I64);
+
+    assert!(pool.rollback(outer));
+    assert_eq!(pool.get_value("a"), None);
+    // Rolling back the outer scope discards the inner one
+    assert!(!pool.rollback(inner));
+
+    let checkpoint = pool.checkpoint();
+    pool.add_slice("s", b"data", SliceType::Binary);
+    let generation = pool.generation();
+    pool.reset();
+    assert_eq!(pool.slice_count(), 0);
+    assert!(pool.generation() > generation);
+    assert!(!pool.rollback(checkpoint));
+}
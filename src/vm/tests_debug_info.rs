@@ -0,0 +1,68 @@
+use super::const_pool::ValueType;
+use super::coverage::Coverage;
+use super::debug_info::{DebugInfo, MODULE_DOC, PcRange, SourceMap};
+use super::profile::Profile;
+use super::{GlobalVarType, Registers, VirtualMachine};
+
+fn noop(_base: usize, _registers: &mut Registers) -> Result<(), String> {
+    Ok(())
+}
+
+#[test]
+fn test_set_and_get_doc() {
+    let mut info = DebugInfo::new();
+    assert_eq!(info.doc(MODULE_DOC), None);
+    info.set_doc(MODULE_DOC, "Module docs");
+    assert_eq!(info.doc(MODULE_DOC), Some("Module docs"));
+}
+
+#[test]
+fn test_help_text_for_host_function() {
+    let mut vm = VirtualMachine::new();
+    vm.host_functions.register("add", 1, 2, 3, noop);
+    vm.debug_info.set_doc("add", "Add two integers.");
+    assert_eq!(vm.help_text("add"), "add(arg1, arg2) -> ret\n\nAdd two integers.");
+}
+
+#[test]
+fn test_help_text_for_global_and_unknown() {
+    let mut vm = VirtualMachine::new();
+    vm.global_vars
+        .insert("x", 1, GlobalVarType::Value(ValueType::I64));
+    assert_eq!(vm.help_text("x"), "x: Value(I64)");
+    assert_eq!(vm.help_text("missing"), "No help available for 'missing'");
+}
+
+#[test]
+fn test_coverage_marks_unexecuted_lines() {
+    let map = SourceMap {
+        ranges: vec![
+            PcRange { start: 0, end: 4, node: 0 },
+            PcRange { start: 4, end: 8, node: 1 },
+            PcRange { start: 8, end: 12, node: 2 },
+        ],
+        node_lines: vec![1, 3, 3],
+    };
+    let mut counts = vec![0; 12];
+    counts[0] = 1;
+    counts[8] = 5;
+    let profile = Profile { counts, ..Profile::new() };
+    let coverage = Coverage::from_profile(&profile, &map);
+    assert_eq!(coverage.lines.into_iter().collect::<Vec<_>>(), vec![(1, 1), (3, 5)]);
+
+    counts = vec![0; 12];
+    counts[0] = 1;
+    let profile = Profile { counts, ..Profile::new() };
+    let coverage = Coverage::from_profile(&profile, &map);
+    assert_eq!((coverage.lines_hit(), coverage.lines_found()), (1, 2));
+    assert_eq!(
+        coverage.to_lcov("a.ky"),
+        "TN:\nSF:a.ky\nDA:1,1\nDA:3,0\nLF:2\nLH:1\nend_of_record\n"
+    );
+    let report = coverage.annotate("a\n\nb");
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines[0], "        1:    1: a");
+    assert_eq!(lines[1], "        -:    2: ");
+    assert_eq!(lines[2], "    #####:    3: b");
+    assert_eq!(lines[3], "1/2 lines covered");
+}
@@ -0,0 +1,159 @@
+use super::foreign::method_signature;
+use super::*;
+use std::any::Any;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Connections closed by the drop hook, across tests
+static CLOSED: AtomicUsize = AtomicUsize::new(0);
+
+struct Connection {
+    rows: u64,
+}
+
+fn connection(object: &dyn Any) -> &Connection {
+    object.downcast_ref().expect("a Connection")
+}
+
+fn connection_type() -> ForeignType {
+    ForeignType::new("Connection")
+        .display(|object| format!("<Connection with {} rows>", connection(object).rows))
+        .on_drop(|_| {
+            CLOSED.fetch_add(1, Ordering::SeqCst);
+        })
+        .method("rows", &[], |object, _| Ok(connection(object).rows))
+        .method("page", &[ForeignParam::Int, ForeignParam::Int], |object, args| {
+            let (page, size) = (args[0].as_int().unwrap() as u64, args[1].as_int().unwrap() as u64);
+            Ok(connection(object).rows.saturating_sub(page * size).min(size))
+        })
+        .method("query", &[ForeignParam::Str], |object, args| {
+            let table = args[0].as_str().unwrap();
+            Ok(connection(object).rows + table.len() as u64)
+        })
+}
+
+/// Run method host function `name` from bytecode: the handle, the
+/// signature and the arguments are its registers
+fn call(vm: &mut VirtualMachine, name: &str, args: &[u64]) -> Result<u64, VmError> {
+    let index = vm.host_functions.find(name).unwrap();
+    let meta = vm.host_functions.metadata[index].clone();
+    let fn_const = vm.const_pool.add_value("", index as u64, const_pool::ValueType::FuncHost) as u16;
+    let mut builder = BytecodeBuilder::new();
+    let regs: Vec<u8> = (0..args.len() as u8).map(|i| 2 + i).collect();
+    for (arg, reg) in args.iter().zip(&regs) {
+        let idx = vm.const_pool.add_value("", *arg, const_pool::ValueType::I64) as u16;
+        builder.load_const_value(idx, *reg);
+    }
+    builder.call_host_fn(&meta, fn_const, &regs, 1);
+    vm.eval_program(&builder.build())?;
+    Ok(vm.get_register_raw(1))
+}
+
+#[test]
+fn test_methods_reach_the_object() {
+    let mut vm = VirtualMachine::new();
+    let ty = vm.register_foreign_type(connection_type());
+    let conn = vm.foreign.insert(Connection { rows: 25 }, &ty);
+    let ints = method_signature(&[ForeignParam::Int, ForeignParam::Int]);
+    assert_eq!(call(&mut vm, ".page", &[conn, ints, 2, 10]).unwrap(), 5);
+    // Without arguments a method is an ordinary two-parameter host function
+    let rows = vm.host_functions.find(".rows").unwrap();
+    assert_eq!(vm.call_host_function(rows, &[conn, method_signature(&[])]).unwrap(), 25);
+
+    assert_eq!(vm.foreign.display(conn).unwrap(), "<Connection with 25 rows>");
+    assert_eq!(vm.foreign.type_name(conn), Some("Connection"));
+    assert_eq!(vm.foreign.get::<Connection>(conn).unwrap().rows, 25);
+    assert!(vm.foreign.get::<String>(conn).is_none());
+}
+
+#[test]
+fn test_stale_and_foreign_handles_are_errors() {
+    let mut vm = VirtualMachine::new();
+    let ty = vm.register_foreign_type(connection_type());
+    let entity = vm.register_foreign_type(ForeignType::new("Entity"));
+    let rows = vm.host_functions.find(".rows").unwrap();
+    let none = method_signature(&[]);
+
+    let conn = vm.foreign.insert(Connection { rows: 1 }, &ty);
+    let closed = CLOSED.load(Ordering::SeqCst);
+    assert!(vm.foreign.release(conn));
+    assert!(CLOSED.load(Ordering::SeqCst) > closed);
+    assert!(!vm.foreign.release(conn));
+    // The slot is reused, but the old handle does not reach the new object
+    let other = vm.foreign.insert(Connection { rows: 2 }, &ty);
+    assert_ne!(other, conn);
+    let err = vm.call_host_function(rows, &[conn, none]).unwrap_err();
+    let expected = format!("Host error: cannot call rows() on {}: not a live object handle", conn as i64);
+    assert_eq!(err.to_string(), expected);
+    assert!(vm.call_host_function(rows, &[0, none]).is_err());
+    assert!(vm.call_host_function(rows, &[12345, none]).is_err());
+
+    let err = call(&mut vm, ".page", &[other, method_signature(&[ForeignParam::Int]), 1]).unwrap_err();
+    assert_eq!(err.to_string(), "Host error: Connection.page() takes 2 arguments but 1 were given");
+    let mixed = method_signature(&[ForeignParam::Int, ForeignParam::Float]);
+    let err = call(&mut vm, ".page", &[other, mixed, 1, 2.0f64.to_bits()]).unwrap_err();
+    assert_eq!(err.to_string(), "Host error: argument 2 of Connection.page() must be a int, not a float");
+
+    let e = vm.foreign.insert("player".to_string(), &entity);
+    let err = vm.call_host_function(rows, &[e, none]).unwrap_err();
+    assert_eq!(err.to_string(), "Host error: Entity has no method 'rows'");
+    assert_eq!(vm.foreign.display(e).unwrap(), "<Entity>");
+
+    assert_eq!(vm.foreign.len(), 2);
+    vm.foreign.clear();
+    assert!(vm.foreign.is_empty());
+}
+
+#[test]
+fn test_live_objects_are_dropped_with_the_vm() {
+    let mut vm = VirtualMachine::new();
+    let ty = vm.register_foreign_type(connection_type());
+    let conn = vm.foreign.insert(Connection { rows: 3 }, &ty);
+    let kept = vm.foreign.get::<Connection>(conn).unwrap();
+    let closed = CLOSED.load(Ordering::SeqCst);
+    drop(vm);
+    assert!(CLOSED.load(Ordering::SeqCst) > closed);
+    // Host code may still hold a reference of its own
+    assert_eq!(kept.rows, 3);
+}
+
+#[test]
+fn test_finalizers_and_weak_handles_see_the_release() {
+    let mut vm = VirtualMachine::new();
+    let ty = vm.register_foreign_type(connection_type());
+    let conn = vm.foreign.insert(Connection { rows: 4 }, &ty);
+    let cache = Arc::new(Mutex::new(vec![conn]));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    for tag in ["first", "second"] {
+        let (cache, seen) = (cache.clone(), seen.clone());
+        assert!(vm.foreign.add_finalizer(conn, move |object| {
+            cache.lock().unwrap().retain(|&handle| handle != conn);
+            seen.lock().unwrap().push((tag, connection(object).rows));
+        }));
+    }
+    let weak = vm.foreign.downgrade(conn).unwrap();
+    assert_eq!(weak.handle(), conn);
+    assert!(weak.is_alive());
+    assert_eq!(weak.upgrade::<Connection>().unwrap().rows, 4);
+    assert!(weak.upgrade::<String>().is_none());
+
+    assert!(vm.foreign.release(conn));
+    assert!(cache.lock().unwrap().is_empty());
+    assert_eq!(*seen.lock().unwrap(), [("first", 4), ("second", 4)]);
+    assert!(!weak.is_alive());
+    assert!(weak.upgrade::<Connection>().is_none());
+    assert!(!vm.foreign.add_finalizer(conn, |_| unreachable!()));
+    assert!(vm.foreign.downgrade(conn).is_none());
+
+    // Finalizers of objects live at the end run with the VM's drop, and
+    // weak handles do not keep the objects alive
+    let other = vm.foreign.insert(Connection { rows: 5 }, &ty);
+    let seen_at_drop = seen.clone();
+    vm.foreign.add_finalizer(other, move |object| {
+        seen_at_drop.lock().unwrap().push(("drop", connection(object).rows));
+    });
+    let weak = vm.foreign.downgrade(other).unwrap();
+    drop(vm);
+    assert_eq!(seen.lock().unwrap().last(), Some(&("drop", 5)));
+    assert!(!weak.is_alive());
+}
@@ -0,0 +1,92 @@
+use super::number_format::{format_f64, format_radix, parse_int};
+
+#[test]
+fn test_shortest_round_trip() {
+    assert_eq!(format_f64(0.1), "0.1");
+    assert_eq!(format_f64(0.1 + 0.2), "0.30000000000000004");
+    assert_eq!(format_f64(1.0 / 3.0), "0.3333333333333333");
+    assert_eq!(format_f64(-2.5), "-2.5");
+    assert_eq!(format_f64(123.456), "123.456");
+    for value in [0.1, 1e-7, 6.02214076e23, f64::MAX, f64::MIN_POSITIVE, 5e-324] {
+        assert_eq!(format_f64(value).parse::<f64>().unwrap(), value);
+    }
+}
+
+#[test]
+fn test_integral_values_keep_a_point() {
+    assert_eq!(format_f64(1.0), "1.0");
+    assert_eq!(format_f64(100.0), "100.0");
+    assert_eq!(format_f64(1e15), "1000000000000000.0");
+    assert_eq!(format_f64(0.0), "0.0");
+    assert_eq!(format_f64(-0.0), "-0.0");
+}
+
+#[test]
+fn test_scientific_outside_the_fixed_range() {
+    assert_eq!(format_f64(1e16), "1e+16");
+    assert_eq!(format_f64(1.5e300), "1.5e+300");
+    assert_eq!(format_f64(0.0001), "0.0001");
+    assert_eq!(format_f64(0.00001), "1e-05");
+    assert_eq!(format_f64(-2.5e-5), "-2.5e-05");
+    assert_eq!(format_f64(5e-324), "5e-324");
+}
+
+#[test]
+fn test_special_values() {
+    assert_eq!(format_f64(f64::NAN), "nan");
+    assert_eq!(format_f64(f64::INFINITY), "inf");
+    assert_eq!(format_f64(f64::NEG_INFINITY), "-inf");
+}
+
+#[test]
+#[cfg(all(feature = "stdlib", feature = "disasm"))]
+fn test_print_and_const_dump_share_the_format() {
+    use super::const_pool::ValueType;
+    use super::{BytecodeBuilder, VirtualMachine, format_consts};
+    use crate::builtins::{PRINT_F64, print_const};
+    use std::sync::{Arc, Mutex};
+
+    let sink = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut vm = VirtualMachine::builder().output(sink.clone()).build();
+    let print = print_const(&vm).unwrap();
+    let tenth = vm.const_pool.add_value("tenth", 0.1f64.to_bits(), ValueType::F64) as u16;
+    let marker = vm.const_pool.add_value("", PRINT_F64, ValueType::I64) as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_value(tenth, 1);
+    builder.load_const_value(marker, 2);
+    builder.load_const_value(print, 0);
+    builder.call_host(0);
+    vm.eval_program(&builder.build()).unwrap();
+    assert_eq!(sink.lock().unwrap().as_slice(), b"0.1\n");
+    let line = format!("value {} F64 tenth 0.1\n", tenth);
+    assert!(format_consts(&vm.const_pool).contains(&line));
+}
+
+#[test]
+fn test_format_radix() {
+    assert_eq!(format_radix(255, 16), "0xff");
+    assert_eq!(format_radix(-255, 16), "-0xff");
+    assert_eq!(format_radix(5, 2), "0b101");
+    assert_eq!(format_radix(0, 2), "0b0");
+    assert_eq!(format_radix(8, 8), "0o10");
+    assert_eq!(format_radix(i64::MIN, 16), "-0x8000000000000000");
+}
+
+#[test]
+fn test_parse_int() {
+    assert_eq!(parse_int("42", 10), Ok(42));
+    assert_eq!(parse_int(" -ff ", 16), Ok(-255));
+    assert_eq!(parse_int("0xff", 16), Ok(255));
+    assert_eq!(parse_int("0x_ff", 0), Ok(255));
+    assert_eq!(parse_int("0b101", 0), Ok(5));
+    assert_eq!(parse_int("1_000", 10), Ok(1000));
+    assert_eq!(parse_int("zz", 36), Ok(1295));
+    assert_eq!(parse_int("-9223372036854775808", 10), Ok(i64::MIN));
+
+    for (text, radix) in [("", 10), ("12a", 10), ("0x1", 10), ("_1", 10), ("1__0", 10), ("2", 2)] {
+        let err = parse_int(text, radix).unwrap_err();
+        assert!(err.starts_with("invalid literal"), "{:?}: {}", text, err);
+    }
+    assert!(parse_int("9223372036854775808", 10).unwrap_err().contains("64 bits"));
+    assert!(parse_int("1", 37).unwrap_err().contains("base"));
+}
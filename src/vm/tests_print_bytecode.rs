@@ -1,3 +1,5 @@
+#![allow(clippy::approx_constant)]
+
 use super::*;
 use crate::vm::print_bytecode::format_bytecode;
 use super::const_pool::{ValueType, SliceType};
@@ -366,6 +368,17 @@ fn test_format_jmp() {
     assert_eq!(lines[2], "bytecode.len()=3");
 }
 
+#[test]
+fn test_format_call_and_ret() {
+    let mut builder = BytecodeBuilder::new();
+    builder.call(7, 4);
+    builder.ret();
+    let formatted = format_bytecode(&builder.build()).unwrap();
+    let lines: Vec<&str> = formatted.lines().collect();
+    assert_eq!(lines[..2], ["0 CALL 7, r4", "4 RET"]);
+    assert!(format_bytecode(&[CALL, 7, 0]).unwrap_err().contains("Incomplete CALL"));
+}
+
 #[test]
 fn test_format_unknown_opcode() {
     let bytecode = vec![0xFF, 0x00, 0x01]; // Unknown opcode followed by some bytes
@@ -677,6 +690,15 @@ fn test_format_incomplete_all_i64_arithmetic() {
         (GTE_I64, "GTE_I64"),
         (LT_I64, "LT_I64"),
         (LTE_I64, "LTE_I64"),
+        (MOD_I64, "MOD_I64"),
+        (POW_I64, "POW_I64"),
+        (POW_F64, "POW_F64"),
+        (AND_I64, "AND_I64"),
+        (OR_I64, "OR_I64"),
+        (XOR_I64, "XOR_I64"),
+        (NOT_I64, "NOT_I64"),
+        (SHL_I64, "SHL_I64"),
+        (SHR_I64, "SHR_I64"),
     ];
 
     for (opcode, name) in instructions {
@@ -689,6 +711,40 @@ fn test_format_incomplete_all_i64_arithmetic() {
     }
 }
 
+#[test]
+fn test_format_mod_and_pow() {
+    let mut builder = BytecodeBuilder::new();
+    builder.mod_i64(1, 2, 3);
+    builder.pow_i64(3, 2, 4);
+    builder.pow_f64(5, 6, 7);
+    let formatted = format_bytecode(&builder.build()).unwrap();
+    let lines: Vec<&str> = formatted.lines().collect();
+    assert_eq!(lines[..3], ["0 MOD_I64 r1, r2, r3", "4 POW_I64 r3, r2, r4", "8 POW_F64 r5, r6, r7"]);
+}
+
+#[test]
+fn test_format_bitwise() {
+    let mut builder = BytecodeBuilder::new();
+    builder.xor_i64(1, 2, 3);
+    builder.not_i64(3, 4);
+    builder.shr_i64(4, 1, 5);
+    let formatted = format_bytecode(&builder.build()).unwrap();
+    let lines: Vec<&str> = formatted.lines().collect();
+    assert_eq!(lines[..3], ["0 XOR_I64 r1, r2, r3", "4 NOT_I64 r3, r4", "7 SHR_I64 r4, r1, r5"]);
+}
+
+#[test]
+fn test_format_enum_assertions() {
+    let mut builder = BytecodeBuilder::new();
+    builder.assert_enum_value(1, 2, 7);
+    builder.assert_enum_name(3, 4, 8);
+    let formatted = format_bytecode(&builder.build()).unwrap();
+    let lines: Vec<&str> = formatted.lines().collect();
+    assert_eq!(lines[..2], ["0 ASSERT_ENUM_VALUE r1, r2, 7", "5 ASSERT_ENUM_NAME r3, r4, 8"]);
+    let error = format_bytecode(&[ASSERT_ENUM_NAME, 3, 4, 8]).unwrap_err();
+    assert!(error.contains("Incomplete ASSERT_ENUM_NAME instruction at pc 0"));
+}
+
 #[test]
 fn test_format_incomplete_all_jump_instructions() {
     let instructions = vec![
@@ -719,3 +775,104 @@ fn test_format_incomplete_conversion_instructions() {
         assert!(error.contains("missing register operands"));
     }
 }
+
+#[test]
+fn test_format_mov() {
+    let mut builder = BytecodeBuilder::new();
+    builder.mov(3, 7);
+    let bytecode = builder.build();
+
+    let formatted = format_bytecode(&bytecode).expect("Should format successfully");
+    let lines: Vec<&str> = formatted.lines().collect();
+
+    assert_eq!(lines[0], "0 MOV r3, r7");
+    assert_eq!(lines[1], "pc=3");
+}
+
+#[test]
+fn test_format_str_concat() {
+    let mut builder = BytecodeBuilder::new();
+    builder.str_concat(1, 3, 5);
+    let bytecode = builder.build();
+
+    let formatted = format_bytecode(&bytecode).expect("Should format successfully");
+    let lines: Vec<&str> = formatted.lines().collect();
+
+    assert_eq!(lines[0], "0 STR_CONCAT r1, r3, r5");
+    assert_eq!(lines[1], "pc=4");
+}
+
+#[test]
+fn test_format_str_eq_and_hash() {
+    let mut builder = BytecodeBuilder::new();
+    builder.str_eq(1, 3, 5);
+    builder.str_hash(1, 6);
+    let formatted = format_bytecode(&builder.build()).unwrap();
+    let lines: Vec<&str> = formatted.lines().collect();
+    assert_eq!(lines[..2], ["0 STR_EQ r1, r3, r5", "4 STR_HASH r1, r6"]);
+    assert!(format_bytecode(&[STR_EQ, 1, 3]).unwrap_err().starts_with("Incomplete STR_EQ"));
+}
+
+#[test]
+fn test_format_string_builder() {
+    let mut builder = BytecodeBuilder::new();
+    builder.str_builder_new();
+    builder.str_append(1);
+    builder.str_append_i64(3);
+    builder.str_builder_finish(5);
+    let bytecode = builder.build();
+
+    let formatted = format_bytecode(&bytecode).expect("Should format successfully");
+    let lines: Vec<&str> = formatted.lines().collect();
+
+    assert_eq!(lines[0], "0 STR_BUILDER_NEW");
+    assert_eq!(lines[1], "1 STR_APPEND r1");
+    assert_eq!(lines[2], "3 STR_APPEND_I64 r3");
+    assert_eq!(lines[3], "5 STR_BUILDER_FINISH r5");
+    assert_eq!(lines[4], "pc=7");
+}
+
+#[test]
+fn test_format_consts() {
+    let mut vm = VirtualMachine::new();
+    add_i64(&mut vm, -7);
+    add_f64(&mut vm, 1e16);
+    vm.const_pool.add_value("flag", 1, ValueType::Bool);
+    vm.const_pool.add_slice("greeting", b"hi \"you\"", SliceType::Utf8Str);
+    vm.const_pool.add_slice("blob", &[0, 1, 2], SliceType::Binary);
+    assert_eq!(
+        format_consts(&vm.const_pool),
+        "value 0 I64  -7\n\
+         value 1 F64  1e+16\n\
+         value 2 Bool flag true\n\
+         slice 0 Utf8Str greeting \"hi \\\"you\\\"\"\n\
+         slice 1 Binary blob 3 bytes\n"
+    );
+}
+
+#[test]
+fn test_format_call_host_with_frame_comment() {
+    fn noop(_base: usize, _registers: &mut Registers) -> Result<(), String> {
+        Ok(())
+    }
+    let mut vm = VirtualMachine::new();
+    let index = vm.host_functions.register("pair", 1, 2, 4, noop);
+    let fn_const = vm.const_pool.add_value("pair", index as u64, ValueType::FuncHost) as u16;
+    let meta = vm.host_functions.metadata[index].clone();
+    let mut builder = BytecodeBuilder::new();
+    builder.call_host_fn(&meta, fn_const, &[1, 2], 3);
+    load_i64_const(&mut vm, &mut builder, 5, 3);
+    builder.call_host(3);
+    let bytecode = builder.build();
+
+    let plain = format_bytecode(&bytecode).unwrap();
+    assert!(plain.contains("CALL_HOST r3\n"));
+    let annotated =
+        format_bytecode_with_host_functions(&bytecode, &vm.const_pool, &vm.host_functions).unwrap();
+    let calls: Vec<&str> = annotated.lines().filter(|line| line.contains("CALL_HOST")).collect();
+    assert_eq!(calls.len(), 2);
+    assert!(calls[0].ends_with("CALL_HOST r3  ; pair num_params=2 [r3..r6]"), "{}", calls[0]);
+    // r3 was overwritten by an int, so the second call is not resolved
+    assert!(calls[1].ends_with("CALL_HOST r3"), "{}", calls[1]);
+    assert!(format_bytecode(&[CALL_HOST, 3]).unwrap_err().contains("Incomplete CALL_HOST"));
+}
@@ -0,0 +1,387 @@
+use super::const_pool::{SliceType, ValueType};
+use super::program::{FrameRegisters, PortableValue, required_features};
+use super::*;
+
+fn sample_bytecode() -> Vec<u8> {
+    let mut builder = BytecodeBuilder::new();
+    builder.add_i64(1, 2, 3);
+    builder.add_f64(1, 2, 3);
+    builder.build()
+}
+
+#[cfg(feature = "isa-float")]
+#[test]
+fn test_round_trip() {
+    let program = Program::new(sample_bytecode()).unwrap();
+    assert_eq!(program.isa_version, ISA_VERSION);
+    assert_eq!(program.features, ISA_FLOAT);
+
+    let data = program.to_bytes();
+    assert_eq!(Program::from_bytes(&data).unwrap(), program);
+    let mut vm = VirtualMachine::new();
+    assert_eq!(vm.load_program(&data).unwrap(), sample_bytecode());
+}
+
+#[test]
+fn test_required_features() {
+    let mut builder = BytecodeBuilder::new();
+    builder.add_i64(1, 2, 3);
+    assert_eq!(required_features(&builder.build()), Ok(0));
+    builder.call_host(4);
+    assert_eq!(required_features(&builder.build()), Ok(ISA_HOST_CALLS));
+    assert!(matches!(
+        required_features(&[0xFF]),
+        Err(ProgramError::InvalidBytecode(_))
+    ));
+    assert_eq!(required_features(&[ADD_I64, 1]), Err(ProgramError::Truncated));
+}
+
+#[test]
+fn test_newer_isa_is_rejected() {
+    let mut program = Program::new(sample_bytecode()).unwrap();
+    program.isa_version = ISA_VERSION + 1;
+    let err = VirtualMachine::new()
+        .load_program(&program.to_bytes())
+        .unwrap_err();
+    assert_eq!(
+        err,
+        ProgramError::NewerIsa {
+            found: ISA_VERSION + 1,
+            supported: ISA_VERSION
+        }
+    );
+    assert!(err.to_string().contains("upgrade kayton"));
+}
+
+#[cfg(feature = "isa-float")]
+#[test]
+fn test_unsupported_features_are_rejected() {
+    let mut program = Program::new(sample_bytecode()).unwrap();
+    program.features |= 1 << 31;
+    let err = VirtualMachine::new()
+        .load_program(&program.to_bytes())
+        .unwrap_err();
+    assert_eq!(err, ProgramError::UnsupportedFeatures(1 << 31));
+}
+
+#[test]
+fn test_understated_features_are_rejected() {
+    let mut program = Program::new(sample_bytecode()).unwrap();
+    program.features = 0;
+    let err = VirtualMachine::new()
+        .load_program(&program.to_bytes())
+        .unwrap_err();
+    assert!(matches!(err, ProgramError::InvalidBytecode(_)));
+}
+
+#[test]
+fn test_malformed_files() {
+    let mut vm = VirtualMachine::new();
+    assert_eq!(vm.load_program(b"NOPE"), Err(ProgramError::BadMagic));
+    let data = Program::new(sample_bytecode()).unwrap().to_bytes();
+    assert!(matches!(
+        vm.load_program(&data[..data.len() - 1]),
+        Err(ProgramError::ChecksumMismatch { .. })
+    ));
+    let mut bad_format = data.clone();
+    bad_format[4] = 9;
+    assert_eq!(
+        vm.load_program(&bad_format),
+        Err(ProgramError::UnsupportedFormat(9))
+    );
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(super::encoding::crc32_update(0, b"123456789"), 0xCBF4_3926);
+    let split = super::encoding::crc32_update(super::encoding::crc32_update(0, b"1234"), b"56789");
+    assert_eq!(split, 0xCBF4_3926);
+}
+
+#[test]
+fn test_consts_are_relocated() {
+    fn noop(_base: usize, _registers: &mut Registers) -> Result<(), String> {
+        Ok(())
+    }
+    let mut vm = VirtualMachine::new();
+    vm.host_functions.register("first", 0, 0, 1, noop);
+    let second = vm.host_functions.register("second", 0, 0, 1, noop);
+    vm.const_pool.add_value("f", second as u64, ValueType::FuncHost);
+    vm.const_pool.add_value("pi", 3.5f64.to_bits(), ValueType::F64);
+    vm.const_pool.add_slice("s", b"hello", SliceType::Utf8Str);
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_slice(0, 1);
+    let data = Program::with_consts(builder.build(), &vm).unwrap().to_bytes();
+
+    let consts = Program::from_bytes(&data).unwrap().consts.unwrap();
+    assert_eq!(consts.values[0].value, PortableValue::HostFn("second".to_string()));
+
+    // Registration order differs on the loading machine
+    let mut other = VirtualMachine::new();
+    other.host_functions.register("second", 0, 0, 1, noop);
+    other.load_program(&data).unwrap();
+    assert_eq!(other.const_pool.values, vec![0, 3.5f64.to_bits()]);
+    assert_eq!(other.const_pool.get_slice("s"), Some(b"hello".as_slice()));
+    assert_ne!(
+        other.const_pool.slice(0).unwrap().as_ptr(),
+        vm.const_pool.slice(0).unwrap().as_ptr()
+    );
+
+    let err = VirtualMachine::new().load_program(&data).unwrap_err();
+    assert_eq!(err, ProgramError::UnknownHostFunction("second".to_string()));
+}
+
+#[test]
+fn test_host_function_requirements() {
+    fn noop(_base: usize, _registers: &mut Registers) -> Result<(), String> {
+        Ok(())
+    }
+    let mut vm = VirtualMachine::new();
+    let print = vm.host_functions.register("print", 0, 1, 3, noop);
+    let pair = vm.host_functions.register("pair", 2, 2, 3, noop);
+    vm.host_functions.register("unused", 0, 0, 1, noop);
+    let print_const = vm.const_pool.add_value("", print as u64, ValueType::FuncHost) as u16;
+    let pair_const = vm.const_pool.add_value("", pair as u64, ValueType::FuncHost) as u16;
+    let mut builder = BytecodeBuilder::new();
+    for fn_const in [pair_const, print_const, pair_const] {
+        builder.load_const_value(fn_const, 1);
+        builder.call_host(1);
+    }
+    let program = Program::with_consts(builder.build(), &vm).unwrap();
+    let names: Vec<&str> = program.requirements.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, ["pair", "print"]);
+    assert_eq!(Program::from_bytes(&program.to_bytes()).unwrap(), program);
+    assert_eq!(vm.check_requirements(&program), Ok(()));
+
+    // Another machine registers pair differently and has no print
+    let mut other = VirtualMachine::new();
+    other.host_functions.register("pair", 1, 2, 3, noop);
+    let err = other.check_requirements(&program).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Program needs host functions this VM lacks: 'pair' is registered with 2 params, \
+         1 return registers but the program expects 2 params, 2 return registers; \
+         'print' (1 params, 0 return registers) is not registered"
+    );
+    assert_eq!(other.load_program(&program.to_bytes()), Err(err));
+}
+
+#[test]
+fn test_programs_run_on_later_host_function_versions() {
+    // mul(x, by = 10) -> x * by; version 1 had no `by`
+    fn mul(base: usize, registers: &mut Registers) -> Result<(), String> {
+        registers.set(base, registers.get(base + 1) * registers.get(base + 2));
+        Ok(())
+    }
+    let v1 = HostFunctionMetadata {
+        name: "mul",
+        num_return_registers: 1,
+        num_params: 1,
+        num_registers: 2,
+        version: 1,
+        defaults: &[],
+    };
+    let v2 = HostFunctionMetadata {
+        num_params: 2,
+        num_registers: 3,
+        version: 2,
+        defaults: &[10],
+        ..v1.clone()
+    };
+    let build = |meta: &HostFunctionMetadata, args: &[u64]| {
+        let mut vm = VirtualMachine::new();
+        let index = vm.host_functions.register_metadata(meta.clone(), Arc::new(mul));
+        let fn_const = vm.const_pool.add_value("", index as u64, ValueType::FuncHost) as u16;
+        let mut builder = BytecodeBuilder::new();
+        let mut arg_regs = Vec::new();
+        for (i, &arg) in args.iter().enumerate() {
+            let arg_const = vm.const_pool.add_value("", arg, ValueType::I64) as u16;
+            builder.load_const_value(arg_const, 10 + i as u8);
+            arg_regs.push(10 + i as u8);
+        }
+        builder.call_host_fn(meta, fn_const, &arg_regs, 1);
+        Program::with_consts(builder.build(), &vm).unwrap().to_bytes()
+    };
+    let old = build(&v1, &[4]);
+    let new = build(&v2, &[4, 3]);
+
+    let mut vm = VirtualMachine::new();
+    vm.host_functions.register_metadata(v2.clone(), Arc::new(mul));
+    for (image, expected) in [(&old, 40), (&new, 12), (&old, 40)] {
+        let bytecode = vm.load_program(image).unwrap();
+        vm.eval_program(&bytecode).unwrap();
+        assert_eq!(vm.get_register_i64(1), expected);
+    }
+    // One adapter, however often the old program is loaded
+    assert_eq!(vm.host_functions.metadata.len(), 2);
+
+    let mut older = VirtualMachine::new();
+    older.host_functions.register_metadata(v1, Arc::new(mul));
+    assert_eq!(
+        older.load_program(&new).unwrap_err().to_string(),
+        "Program needs host functions this VM lacks: 'mul' is registered with 1 params, \
+         1 return registers, version 1 but the program expects 2 params, 1 return registers, version 2"
+    );
+}
+
+#[test]
+fn test_frames_are_sized_and_preallocated() {
+    let mut builder = BytecodeBuilder::new();
+    builder.jmp(12);
+    // f: a host call at r300 and a call of g at r10
+    builder.call_host(300);
+    builder.call(11, 10);
+    builder.ret();
+    // g
+    builder.ret();
+    // top level: f at r5, then h at r2
+    builder.call(3, 5);
+    builder.call(23, 2);
+    builder.jmp(28);
+    // h calls itself at r7
+    builder.call(23, 7);
+    builder.ret();
+    let program = Program::new(builder.build()).unwrap();
+    let frame = |entry, registers| FrameRegisters { entry, registers };
+    assert_eq!(
+        program.frames,
+        [frame(0, 5 + 301), frame(3, 301), frame(11, 256), frame(23, 256)]
+    );
+    let data = program.to_bytes();
+    assert_eq!(Program::from_bytes(&data).unwrap(), program);
+
+    let mut vm = VirtualMachine::new();
+    vm.load_program(&data).unwrap();
+    assert_eq!(vm.registers.spill_len(), 306 - Registers::FIXED_COUNT);
+    let mut limited = VirtualMachine::new();
+    limited.limits.max_registers = Some(280);
+    limited.load_program(&data).unwrap();
+    assert_eq!(limited.registers.spill_len(), 280 - Registers::FIXED_COUNT);
+}
+
+#[test]
+fn test_tampered_frame_sizes_are_ignored() {
+    let mut builder = BytecodeBuilder::new();
+    builder.call_host(300);
+    let mut program = Program::new(builder.build()).unwrap();
+    program.frames[0].registers = u32::MAX as usize;
+    let data = program.to_bytes();
+    assert_eq!(Program::from_bytes(&data).unwrap().frames[0].registers, u32::MAX as usize);
+
+    // Sized from the bytecode, not the file
+    let mut vm = VirtualMachine::new();
+    vm.load_program(&data).unwrap();
+    assert_eq!(vm.registers.spill_len(), 301 - Registers::FIXED_COUNT);
+}
+
+#[test]
+fn test_corruption_is_detected() {
+    let mut vm = VirtualMachine::new();
+    vm.const_pool.add_slice("s", b"hello", SliceType::Utf8Str);
+    let data = Program::with_consts(sample_bytecode(), &vm)
+        .unwrap()
+        .to_bytes();
+    for i in [6, 12, 20, data.len() - 1] {
+        let mut corrupted = data.clone();
+        corrupted[i] ^= 0x40;
+        let err = Program::from_bytes(&corrupted).unwrap_err();
+        assert!(
+            matches!(err, ProgramError::ChecksumMismatch { .. }),
+            "byte {}: {:?}",
+            i,
+            err
+        );
+    }
+}
+
+#[cfg(feature = "compress")]
+#[test]
+fn test_compressed_consts() {
+    let mut vm = VirtualMachine::new();
+    vm.const_pool
+        .add_slice("s", "abc".repeat(1000).as_bytes(), SliceType::Utf8Str);
+    let program = Program::with_consts(sample_bytecode(), &vm).unwrap();
+    let compressed = program.to_bytes_compressed();
+    assert!(compressed.len() < program.to_bytes().len() / 10);
+    assert_eq!(Program::from_bytes(&compressed).unwrap(), program);
+}
+
+#[cfg(not(feature = "compress"))]
+#[test]
+fn test_compressed_consts_need_the_feature() {
+    let mut data = Program::new(sample_bytecode()).unwrap().to_bytes();
+    // Set both flags and re-seal so only the compression check can fail
+    data[12] = 3;
+    let crc = {
+        let mut body = data[..13].to_vec();
+        body.extend_from_slice(&data[17..]);
+        super::encoding::crc32_update(0, &body)
+    };
+    data[13..17].copy_from_slice(&crc.to_le_bytes());
+    assert_eq!(
+        Program::from_bytes(&data),
+        Err(ProgramError::CompressionUnsupported)
+    );
+}
+
+#[cfg(feature = "isa-float")]
+#[test]
+fn test_format_v1_uses_the_vm_pool() {
+    let bytecode = sample_bytecode();
+    let mut data = b"KAYC".to_vec();
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&ISA_VERSION.to_le_bytes());
+    data.extend_from_slice(&ISA_FLOAT.to_le_bytes());
+    data.extend_from_slice(&(bytecode.len() as u32).to_le_bytes());
+    data.extend_from_slice(&bytecode);
+    let program = Program::from_bytes(&data).unwrap();
+    assert_eq!(program.consts, None);
+    assert_eq!(VirtualMachine::new().load_program(&data).unwrap(), bytecode);
+}
+
+#[test]
+fn test_isa_v1_bytecode_is_renumbered() {
+    // x = 1 + 2 in the version 1 numbering: LOAD_CONST_VALUE 0x18, ADD_I64 0x03, MOV 0x1B
+    let v1 = vec![0x18, 1, 0, 0, 0x18, 2, 0, 0, 0x03, 1, 2, 3, 0x1B, 3, 4];
+    let program = Program {
+        bytecode: v1,
+        ..program_v1()
+    };
+    let migrated = program.migrate().unwrap();
+    assert_eq!(migrated.isa_version, ISA_VERSION);
+    assert_eq!(
+        migrated.bytecode,
+        vec![
+            LOAD_CONST_VALUE, 1, 0, 0, LOAD_CONST_VALUE, 2, 0, 0, ADD_I64, 1, 2, 3, MOV, 3, 4
+        ]
+    );
+
+    let bad = Program {
+        bytecode: vec![0x18, 1],
+        ..program_v1()
+    };
+    assert_eq!(bad.migrate(), Err(ProgramError::Truncated));
+}
+
+#[test]
+fn test_isa_v2_bytecode_runs_as_is() {
+    let bytecode = vec![LOAD_CONST_VALUE, 1, 0, 0, ADD_I64, 1, 1, 2];
+    let program = Program {
+        isa_version: 2,
+        bytecode: bytecode.clone(),
+        ..program_v1()
+    };
+    let migrated = program.migrate().unwrap();
+    assert_eq!((migrated.isa_version, migrated.bytecode), (ISA_VERSION, bytecode));
+}
+
+fn program_v1() -> Program {
+    Program {
+        isa_version: 1,
+        features: 0,
+        consts: None,
+        requirements: Vec::new(),
+        frames: Vec::new(),
+        bytecode: Vec::new(),
+    }
+}
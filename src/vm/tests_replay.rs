@@ -0,0 +1,60 @@
+use super::replay::{ReplayError, ReplayLog};
+use super::*;
+use crate::codegen::compile_source;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+const SRC: &str = "x = ticket(0)\ny = ticket(0) + x\nprint(y)\n";
+
+/// A VM whose `ticket` host function returns a different number on every call
+fn vm_with_ticket(start: u64, out: Arc<Mutex<Vec<u8>>>) -> (VirtualMachine, Vec<u8>) {
+    let mut vm = VirtualMachine::builder().output(out).build();
+    let counter = Arc::new(AtomicU64::new(start));
+    vm.host_functions.register_closure(
+        "ticket",
+        1,
+        1,
+        2,
+        Arc::new(move |base, registers| {
+            registers.set(base, counter.fetch_add(1, Ordering::SeqCst));
+            Ok(())
+        }),
+    );
+    let print = crate::builtins::print_const(&vm).unwrap();
+    let bytecode = compile_source(SRC, &mut vm, print).unwrap();
+    (vm, bytecode)
+}
+
+#[test]
+fn test_record_and_replay() {
+    let recorded_out = Arc::new(Mutex::new(Vec::new()));
+    let (mut vm, bytecode) = vm_with_ticket(100, recorded_out.clone());
+    vm.start_recording();
+    vm.eval_program(&bytecode).unwrap();
+    let log = vm.stop_replay().unwrap();
+    assert_eq!(recorded_out.lock().unwrap().as_slice(), b"201\n");
+    // Two tickets and one print
+    assert_eq!(log.events.len(), 3);
+    assert_eq!(log.events[0].name, "ticket");
+
+    // A later run would draw other tickets; replaying reproduces the original
+    let log = ReplayLog::from_bytes(&log.to_bytes()).unwrap();
+    let replayed_out = Arc::new(Mutex::new(Vec::new()));
+    let (mut vm, bytecode) = vm_with_ticket(7, replayed_out.clone());
+    vm.start_replay(log);
+    vm.eval_program(&bytecode).unwrap();
+    assert!(replayed_out.lock().unwrap().is_empty());
+    assert_eq!(vm.get_register_i64(vm.global_vars.get("y").unwrap().register_id), 201);
+}
+
+#[test]
+fn test_replay_divergence() {
+    let (mut vm, bytecode) = vm_with_ticket(0, Arc::new(Mutex::new(Vec::new())));
+    vm.start_replay(ReplayLog::new());
+    assert!(matches!(
+        vm.eval_program(&bytecode),
+        Err(VmError::ReplayDivergence(_))
+    ));
+    assert_eq!(ReplayLog::from_bytes(b"KAYRL"), Err(ReplayError::Truncated));
+    assert_eq!(ReplayLog::from_bytes(b"nope"), Err(ReplayError::BadMagic));
+}
@@ -0,0 +1,95 @@
+use super::const_pool::{SliceType, ValueType};
+use super::snapshot::SnapshotError;
+use super::*;
+
+#[test]
+fn test_snapshot_round_trip() {
+    let mut vm = VirtualMachine::new();
+    let idx_v = vm.const_pool.add_value("answer", 42, ValueType::I64) as u16;
+    let idx_s = vm
+        .const_pool
+        .add_slice("greeting", b"hello", SliceType::Utf8Str) as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_value(idx_v, 1);
+    builder.load_const_slice(idx_s, 2);
+    vm.eval_program(&builder.build()).unwrap();
+    vm.global_vars
+        .insert("n", 1, GlobalVarType::Value(ValueType::I64));
+    vm.global_vars.insert(
+        "s",
+        2,
+        GlobalVarType::Ptr(PtrType::Slice(SliceType::Utf8Str)),
+    );
+    vm.debug_info.set_doc("n", "The answer.");
+
+    let data = vm.save_snapshot().unwrap();
+
+    let mut restored = VirtualMachine::new();
+    restored.restore_snapshot(&data).unwrap();
+    assert_eq!(restored.const_pool.get_value("answer"), Some(42));
+    assert_eq!(restored.get_register_i64(1), 42);
+    let ptr = restored.get_register_raw(2);
+    let len = restored.get_register_raw(3) as usize;
+    let text = unsafe { std::slice::from_raw_parts(ptr as *const u8, len) };
+    assert_eq!(text, b"hello");
+    assert_eq!(ptr, restored.const_pool.get_slice("greeting").unwrap().as_ptr() as u64);
+    assert_eq!(restored.get_register_type(2), RegisterType::ConstSliceVarMain);
+    assert_eq!(restored.debug_info.doc("n"), Some("The answer."));
+    assert!(matches!(
+        restored.global_vars.get("s").unwrap().meta.typ,
+        GlobalVarType::Ptr(PtrType::Slice(SliceType::Utf8Str))
+    ));
+}
+
+#[test]
+fn test_snapshot_rejects_bad_input() {
+    let mut vm = VirtualMachine::new();
+    assert_eq!(vm.restore_snapshot(b"nope"), Err(SnapshotError::BadMagic));
+
+    let data = VirtualMachine::new().save_snapshot().unwrap();
+    assert_eq!(
+        vm.restore_snapshot(&data[..data.len() - 1]),
+        Err(SnapshotError::Truncated)
+    );
+}
+
+#[test]
+fn test_snapshot_unrelocatable_pointer() {
+    let mut vm = VirtualMachine::new();
+    vm.set_register_raw(1, 0xDEAD);
+    vm.global_vars.insert(
+        "s",
+        1,
+        GlobalVarType::Ptr(PtrType::Slice(SliceType::Utf8Str)),
+    );
+    assert_eq!(
+        vm.save_snapshot(),
+        Err(SnapshotError::UnrelocatablePointer("s".to_string()))
+    );
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn test_snapshot_heap_strings() {
+    let mut vm = VirtualMachine::new();
+    let idx = vm.const_pool.add_slice("", b"abc", SliceType::Utf8Str) as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_slice(idx, 1);
+    builder.str_concat(1, 1, 3);
+    vm.eval_program(&builder.build()).unwrap();
+    vm.global_vars.insert(
+        "s",
+        3,
+        GlobalVarType::Ptr(PtrType::Slice(SliceType::Utf8Str)),
+    );
+
+    let data = vm.save_snapshot().unwrap();
+    let mut restored = VirtualMachine::new();
+    restored.restore_snapshot(&data).unwrap();
+    let ptr = restored.get_register_raw(3);
+    let len = restored.get_register_raw(4) as usize;
+    assert!(restored.strings.contains(ptr));
+    let text = unsafe { std::slice::from_raw_parts(ptr as *const u8, len) };
+    assert_eq!(text, b"abcabc");
+    assert_eq!(restored.get_register_type(3), RegisterType::HeapStrMain);
+}
@@ -0,0 +1,101 @@
+use super::const_pool::ValueType;
+use super::stepper::{StepOutcome, Stepper};
+use super::*;
+
+/// r0 = 1; r1 = 2; r2 = r0 + r1; r2 = r2 * r2
+fn program(vm: &mut VirtualMachine) -> Vec<u8> {
+    let mut builder = BytecodeBuilder::new();
+    let one = vm.const_pool.add_value("", 1, ValueType::I64) as u16;
+    let two = vm.const_pool.add_value("", 2, ValueType::I64) as u16;
+    builder.load_const_value(one, 0);
+    builder.load_const_value(two, 1);
+    builder.add_i64(0, 1, 2);
+    builder.mul_i64(2, 2, 2);
+    builder.build()
+}
+
+#[test]
+fn test_step_runs_one_instruction_at_a_time() {
+    let mut vm = VirtualMachine::new();
+    let mut stepper = Stepper::new(program(&mut vm));
+    assert_eq!(stepper.listing().len(), 4);
+
+    assert_eq!(stepper.step(&mut vm).unwrap(), StepOutcome::Stepped);
+    assert_eq!(stepper.pc, 4);
+    assert_eq!(vm.get_register_i64(0), 1);
+    assert_eq!(vm.get_register_i64(1), 0);
+
+    stepper.step(&mut vm).unwrap();
+    stepper.step(&mut vm).unwrap();
+    assert_eq!(vm.get_register_i64(2), 3);
+    assert_eq!(stepper.step(&mut vm).unwrap(), StepOutcome::Finished);
+    assert_eq!(vm.get_register_i64(2), 9);
+    assert!(stepper.is_finished());
+    assert_eq!(stepper.step(&mut vm).unwrap(), StepOutcome::Finished);
+}
+
+#[test]
+fn test_resume_stops_at_breakpoints() {
+    let mut vm = VirtualMachine::new();
+    let mut stepper = Stepper::new(program(&mut vm));
+    let add_pc = stepper.listing()[2].0;
+    assert!(stepper.toggle_breakpoint(add_pc));
+
+    assert_eq!(stepper.resume(&mut vm).unwrap(), StepOutcome::Breakpoint(add_pc));
+    assert_eq!(vm.get_register_i64(2), 0);
+    // Continuing from the breakpoint runs past it
+    assert_eq!(stepper.resume(&mut vm).unwrap(), StepOutcome::Finished);
+    assert_eq!(vm.get_register_i64(2), 9);
+
+    assert!(!stepper.toggle_breakpoint(add_pc));
+    assert!(stepper.breakpoints.is_empty());
+}
+
+#[test]
+fn test_resume_stops_after_debug_break() {
+    let mut vm = VirtualMachine::new();
+    let mut bytecode = program(&mut vm);
+    // Break between the loads and the arithmetic
+    let mut builder = BytecodeBuilder::new();
+    builder.debug_break();
+    bytecode.splice(8..8, builder.build());
+    let mut stepper = Stepper::new(bytecode);
+    assert_eq!(stepper.listing()[2], (8, "8 DEBUG_BREAK".to_string()));
+
+    assert_eq!(stepper.resume(&mut vm).unwrap(), StepOutcome::Breakpoint(9));
+    assert_eq!((vm.get_register_i64(1), vm.get_register_i64(2)), (2, 0));
+    assert_eq!(stepper.resume(&mut vm).unwrap(), StepOutcome::Finished);
+    assert_eq!(vm.get_register_i64(2), 9);
+}
+
+#[test]
+fn test_render_shows_pc_registers_and_globals() {
+    let mut vm = VirtualMachine::new();
+    let mut stepper = Stepper::new(program(&mut vm));
+    vm.global_vars
+        .insert("total", 2, GlobalVarType::Value(ValueType::I64));
+    stepper.toggle_breakpoint(8);
+    stepper.step(&mut vm).unwrap();
+    stepper.step(&mut vm).unwrap();
+
+    let view = stepper.render(&vm, 10);
+    assert!(view.contains("== code (pc 8) =="), "{}", view);
+    assert!(view.contains(">* 8 ADD_I64 r0, r1, r2"), "{}", view);
+    assert!(view.contains("   0 LOAD_CONST_VALUE"), "{}", view);
+    assert!(view.contains("r1   = 2"), "{}", view);
+    assert!(!view.contains("r2 "), "{}", view);
+    assert!(view.contains("<global> base 0"), "{}", view);
+    assert!(view.contains("total = 0 (r2, Value(I64))"), "{}", view);
+
+    stepper.resume(&mut vm).unwrap();
+    let view = stepper.render(&vm, 1);
+    assert!(view.contains("== code (finished) =="), "{}", view);
+    assert!(view.contains("total = 9"), "{}", view);
+}
+
+#[test]
+fn test_step_reports_runtime_errors() {
+    let mut vm = VirtualMachine::new();
+    let mut stepper = Stepper::new(vec![0xFF]);
+    assert!(matches!(stepper.step(&mut vm), Err(VmError::InvalidOpcode(0xFF))));
+}
@@ -0,0 +1,246 @@
+use super::string_heap::INLINE_CAPACITY;
+use super::*;
+
+fn read(ptr: *const u8, len: usize) -> &'static [u8] {
+    unsafe { std::slice::from_raw_parts(ptr, len) }
+}
+
+#[test]
+fn test_short_strings_share_slabs() {
+    let mut heap = StringHeap::new();
+    let ptrs: Vec<_> = (0..100).map(|i| heap.alloc(format!("s{}", i).as_bytes())).collect();
+    assert_eq!(read(ptrs[42], 3), b"s42");
+    let stats = heap.stats();
+    assert_eq!(stats.inline_strings, 100);
+    assert_eq!(stats.allocations, 1);
+}
+
+#[test]
+fn test_long_strings_and_concat() {
+    let mut heap = StringHeap::new();
+    let exact = heap.alloc_concat(&[b"0123456", b"89abcdef"]);
+    assert_eq!(read(exact, INLINE_CAPACITY), b"012345689abcdef");
+    let long = heap.alloc_concat(&[b"0123456789", b"abcdefghij"]);
+    assert_eq!(read(long, 20), b"0123456789abcdefghij");
+    assert_eq!(heap.stats().large_strings, 1);
+    assert_eq!((heap.stats().inline_bytes, heap.stats().large_bytes), (15, 20));
+    assert!(heap.contains(exact as u64));
+    assert!(heap.contains(long as u64 + 19));
+    assert!(!heap.contains(b"static".as_ptr() as u64));
+    heap.clear();
+    assert!(!heap.contains(long as u64));
+    assert_eq!(heap.stats(), Default::default());
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn test_str_concat_opcode() {
+    let mut vm = VirtualMachine::new();
+    let a = vm.const_pool.add_slice("", b"foo", const_pool::SliceType::Utf8Str) as u16;
+    let b = vm.const_pool.add_slice("", b"bar", const_pool::SliceType::Utf8Str) as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_slice(a, 1);
+    builder.load_const_slice(b, 3);
+    builder.str_concat(1, 3, 5);
+    builder.str_concat(5, 5, 7);
+    vm.eval_program(&builder.build()).unwrap();
+
+    let ptr = vm.get_register_raw(7) as *const u8;
+    assert_eq!(vm.get_register_raw(8), 12);
+    assert_eq!(read(ptr, 12), b"foobarfoobar");
+    assert_eq!(vm.get_register_type(7), RegisterType::HeapStrMain);
+    assert_eq!(vm.get_register_type(8), RegisterType::HeapStrLen);
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn test_str_eq_and_hash_opcodes() {
+    let mut vm = VirtualMachine::new();
+    let foo = vm.const_pool.add_slice("", b"foo", const_pool::SliceType::Utf8Str) as u16;
+    let foofoo = vm.const_pool.add_slice("", b"foofoo", const_pool::SliceType::Utf8Str) as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_slice(foo, 1);
+    builder.load_const_slice(foofoo, 3);
+    builder.str_concat(1, 1, 5); // a heap copy of "foofoo"
+    builder.str_eq(3, 5, 7);
+    builder.str_eq(1, 5, 8);
+    builder.str_hash(3, 9);
+    builder.str_hash(5, 10);
+    builder.str_hash(1, 11);
+    builder.str_eq(20, 20, 12); // two empty strings
+    vm.eval_program(&builder.build()).unwrap();
+
+    assert_eq!([7, 8, 12].map(|reg| vm.get_register_i64(reg)), [1, 0, 1]);
+    assert_eq!(vm.get_register_raw(9), str_hash(b"foofoo"));
+    assert_eq!(vm.get_register_raw(10), vm.get_register_raw(9));
+    assert_ne!(vm.get_register_raw(11), vm.get_register_raw(9));
+    // FNV-1a test vectors: the hash is part of the bytecode contract
+    assert_eq!(str_hash(b""), 0xcbf29ce484222325);
+    assert_eq!(str_hash(b"a"), 0xaf63dc4c8601ec8c);
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn test_string_builder_opcodes() {
+    let mut vm = VirtualMachine::new();
+    let a = vm.const_pool.add_slice("", b"n=", const_pool::SliceType::Utf8Str) as u16;
+    let n = vm.const_pool.add_value("", -7i64 as u64, const_pool::ValueType::I64) as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_slice(a, 1);
+    builder.load_const_value(n, 3);
+    builder.str_builder_new();
+    builder.str_append(1);
+    builder.str_append_i64(3);
+    builder.str_builder_finish(4);
+    vm.eval_program(&builder.build()).unwrap();
+    assert_eq!(read(vm.get_register_raw(4) as *const u8, 4), b"n=-7");
+    assert_eq!(vm.get_register_raw(5), 4);
+
+    let mut builder = BytecodeBuilder::new();
+    builder.str_builder_finish(1);
+    assert!(matches!(
+        vm.eval_program(&builder.build()),
+        Err(VmError::NoStringBuilder)
+    ));
+}
+
+#[test]
+fn test_builder_buffers_are_reused() {
+    let mut heap = StringHeap::new();
+    heap.begin_builder();
+    heap.append(b"outer ");
+    heap.begin_builder();
+    heap.append(b"inner");
+    let (inner, len) = heap.finish_builder().unwrap();
+    heap.append(read(inner, len));
+    let (outer, len) = heap.finish_builder().unwrap();
+    assert_eq!(read(outer, len), b"outer inner");
+    assert!(heap.finish_builder().is_none());
+    assert!(!heap.append(b"x"));
+}
+
+#[test]
+fn test_retain_only_keeps_slabs_for_reuse() {
+    let mut heap = StringHeap::new();
+    let kept = heap.alloc(b"kept");
+    let long = heap.alloc(b"a string too long to be inline");
+    for i in 0..300 {
+        heap.alloc(format!("t{}", i).as_bytes());
+    }
+    assert_eq!(heap.stats().allocations, 3);
+    let constant = b"not in the heap";
+    let mut pairs = [(kept as u64, 4), (long as u64, 30), (constant.as_ptr() as u64, 15), (0, 0)];
+    heap.retain_only(&mut pairs);
+    assert_eq!(read(pairs[0].0 as *const u8, 4), b"kept");
+    assert_eq!(read(pairs[1].0 as *const u8, 30), b"a string too long to be inline");
+    assert_eq!(pairs[2..], [(constant.as_ptr() as u64, 15), (0, 0)]);
+    let stats = heap.stats();
+    assert_eq!((stats.inline_strings, stats.large_strings), (1, 1));
+    // Only the large copy allocated; inline strings refill the old slabs
+    for i in 0..300 {
+        heap.alloc(format!("u{}", i).as_bytes());
+    }
+    assert_eq!(heap.stats().allocations, 1);
+    assert!(heap.contains(pairs[0].0));
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn test_execution_scope_frees_all_but_global_strings() {
+    use super::global_vars::{GlobalVarType, PtrType};
+    let mut vm = VirtualMachine::builder().allocation_scope(AllocationScope::Execution).build();
+    let text = vm.const_pool.add_slice("", b"0123456789", const_pool::SliceType::Utf8Str) as u16;
+    let str_type = GlobalVarType::Ptr(PtrType::Slice(const_pool::SliceType::Utf8Str));
+    vm.global_vars.insert("kept", 3, str_type);
+    vm.global_vars.insert("constant", 7, str_type);
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_slice(text, 1);
+    builder.str_concat(1, 1, 3); // a global
+    builder.str_concat(3, 1, 5); // a temporary
+    builder.load_const_slice(text, 7);
+    let bytecode = builder.build();
+
+    for _ in 0..3 {
+        vm.eval_program(&bytecode).unwrap();
+        let kept = vm.get_register_raw(3);
+        assert_eq!(read(kept as *const u8, 20), b"01234567890123456789");
+        assert!(vm.strings.contains(kept));
+        assert!(!vm.strings.contains(vm.get_register_raw(7)));
+        // The temporary went with its run
+        assert_eq!(vm.strings.stats().large_strings, 1);
+    }
+    // A failed run frees its strings too
+    builder.assert(0, 0);
+    assert!(vm.eval_program(&builder.build()).is_err());
+    assert_eq!(vm.strings.stats().large_strings, 1);
+}
+
+#[cfg(feature = "isa-strings")]
+#[test]
+fn test_execution_scope_resets_temporary_string_registers() {
+    use super::global_vars::{GlobalVarType, PtrType};
+    let mut vm = VirtualMachine::builder().allocation_scope(AllocationScope::Execution).build();
+    let text = vm.const_pool.add_slice("", b"0123456789", const_pool::SliceType::Utf8Str) as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_slice(text, 1);
+    builder.str_concat(1, 1, 5); // a temporary left in r5
+    vm.eval_program(&builder.build()).unwrap();
+    assert_eq!(vm.get_register_raw(6), 0);
+    assert!(!vm.strings.contains(vm.get_register_raw(5)));
+
+    // The next run reads r5 after allocating where its string used to be,
+    // keeping the result in a global
+    let str_type = GlobalVarType::Ptr(PtrType::Slice(const_pool::SliceType::Utf8Str));
+    vm.global_vars.insert("read", 7, str_type);
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_slice(text, 1);
+    builder.str_concat(1, 1, 3);
+    builder.str_concat(5, 1, 7);
+    vm.eval_program(&builder.build()).unwrap();
+    assert_eq!(vm.get_register_raw(8), 10);
+    assert_eq!(read(vm.get_register_raw(7) as *const u8, 10), b"0123456789");
+
+    // Ints are left alone even when their value looks like a heap address
+    let address = vm.get_register_raw(7);
+    assert!(vm.strings.contains(address));
+    vm.set_register_i64(20, address as i64);
+    vm.set_register_i64(21, 99);
+    vm.eval_program(&builder.build()).unwrap();
+    assert_eq!([20, 21].map(|reg| vm.get_register_raw(reg)), [address, 99]);
+}
+
+#[cfg(all(feature = "isa-strings", feature = "frontend", feature = "stdlib"))]
+#[test]
+fn test_str_slice_shares_the_string() {
+    use crate::builtins::print_const;
+    use crate::codegen::compile_source;
+    use std::sync::{Arc, Mutex};
+    let sink = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut vm = VirtualMachine::builder()
+        .allocation_scope(AllocationScope::Execution)
+        .output(sink.clone())
+        .build();
+    let print = print_const(&vm).unwrap();
+    let src = "text = \"héllo, world\"\nhead = str_slice(text, 0, 6)\nlong = text + text\ntail = str_slice(long, 14, 26)\nprint(head)\nprint(str_slice(tail, 8, 12))";
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(String::from_utf8(sink.lock().unwrap().clone()).unwrap(), "héllo\norld\n");
+    let register = |name: &str| vm.global_vars.get(name).unwrap().register_id;
+    // A view of a literal points into it
+    assert_eq!(vm.get_register_raw(register("head")), vm.get_register_raw(register("text")));
+    assert_eq!(vm.get_register_raw(register("head") + 1), 6);
+    // The run freed `long`, but `tail` got a copy of its part
+    let tail = vm.get_register_raw(register("tail"));
+    assert!(vm.strings.contains(tail));
+    let copy = unsafe { std::slice::from_raw_parts(tail as *const u8, 12) };
+    assert_eq!(copy, "éllo, world".as_bytes());
+
+    for (src, err) in [
+        ("x = str_slice(\"abc\", 2, 4)", "str_slice(2, 4) is out of range for 3 bytes"),
+        ("x = str_slice(\"é\", 0, 1)", "str_slice(0, 1) splits a character"),
+    ] {
+        let bytecode = compile_source(src, &mut vm, print).unwrap();
+        let message = vm.eval_program(&bytecode).unwrap_err().to_string();
+        assert!(message.contains(err), "{}: {}", src, message);
+    }
+}
@@ -0,0 +1,89 @@
+use super::*;
+use super::const_pool::ValueType;
+
+#[test]
+fn test_verify_accepts_well_formed_bytecode() {
+    let mut builder = BytecodeBuilder::new();
+    builder.add_i64(1, 2, 3);
+    builder.mov(3, 4);
+    builder.call_host(5);
+    let vm = VirtualMachine::new();
+    assert!(vm.verify(&builder.build()).is_ok());
+}
+
+#[test]
+fn test_verify_rejects_malformed_bytecode() {
+    let vm = VirtualMachine::new();
+    assert!(matches!(vm.verify(&[0xFF]), Err(VmError::InvalidOpcode(0xFF))));
+    assert!(matches!(
+        vm.verify(&[ADD_I64, 1, 2]),
+        Err(VmError::UnexpectedEndOfProgram)
+    ));
+    // CALL targets are checked like jumps
+    assert!(matches!(
+        vm.verify(&[CALL, 1, 0, 0, RET]),
+        Err(VmError::InvalidJumpTarget(1))
+    ));
+    assert!(vm.verify(&[CALL, 4, 0, 0, RET]).is_ok());
+}
+
+#[test]
+fn test_verify_checks_compiled_in_features() {
+    let mut builder = BytecodeBuilder::new();
+    builder.add_f64(1, 2, 3);
+    builder.str_concat(1, 3, 5);
+    let bytecode = builder.build();
+    let result = VirtualMachine::new().verify(&bytecode);
+    if cfg!(all(feature = "isa-float", feature = "isa-strings")) {
+        assert!(result.is_ok());
+    } else {
+        assert!(matches!(result, Err(VmError::UnsupportedOpcode(_))));
+    }
+}
+
+#[test]
+fn test_verify_rejects_jumps_into_operands() {
+    let vm = VirtualMachine::new();
+    // JMP to offset 1 lands on the operand of MOV
+    assert!(matches!(
+        vm.verify(&[MOV, 1, 2, JMP, 1, 0]),
+        Err(VmError::InvalidJumpTarget(1))
+    ));
+    // Jumping to the end of the program is fine
+    assert!(vm.verify(&[JMP, 3, 0]).is_ok());
+    assert!(matches!(
+        vm.verify(&[JUMP_BACKWARD_IF_TRUE, 1, 9, 0]),
+        Err(VmError::InvalidJumpTarget(_))
+    ));
+}
+
+#[test]
+fn test_eval_verified_matches_checked_path() {
+    // r1 counts from 0 up to r3 in steps of r2
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_value(0, 1);
+    builder.load_const_value(1, 2);
+    builder.load_const_value(2, 3);
+    let top = builder.create_label();
+    builder.place_label(top);
+    builder.add_i64(1, 2, 1);
+    builder.lt_i64(1, 3, 4);
+    builder.jump_if_true_to_label(4, top);
+    let bytecode = builder.build();
+
+    let run = |fast: bool| {
+        let mut vm = VirtualMachine::new();
+        vm.const_pool.add_value("0", 0, ValueType::I64);
+        vm.const_pool.add_value("1", 1, ValueType::I64);
+        vm.const_pool.add_value("1000", 1000, ValueType::I64);
+        if fast {
+            let verified = vm.verify(&bytecode).unwrap();
+            vm.eval_verified(verified).unwrap();
+        } else {
+            vm.eval_program(&bytecode).unwrap();
+        }
+        vm.get_register_i64(1)
+    };
+    assert_eq!(run(true), 1000);
+    assert_eq!(run(false), 1000);
+}
@@ -0,0 +1,261 @@
+use super::const_pool::ValueType;
+use super::*;
+use crate::builtins::print_const;
+use crate::codegen::compile_source;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn looping_program(vm: &mut VirtualMachine) -> Vec<u8> {
+    let one = vm.const_pool.add_value("", 1, ValueType::I64) as u16;
+    let mut builder = BytecodeBuilder::new();
+    let start = builder.create_label();
+    builder.load_const_value(one, 1);
+    builder.place_label(start);
+    builder.add_i64(1, 1, 2);
+    builder.jump_if_true_to_label(1, start);
+    builder.build()
+}
+
+#[test]
+fn test_instruction_limit() {
+    let mut vm = VirtualMachine::builder().max_instructions(100).build();
+    let bytecode = looping_program(&mut vm);
+    let result = vm.eval_program(&bytecode);
+    assert!(matches!(result, Err(VmError::InstructionLimit(100))));
+}
+
+#[test]
+fn test_fuel_is_priced_by_the_cost_table() {
+    // LOAD_CONST_VALUE once, then ADD_I64 + JUMP_BACKWARD_IF_TRUE per iteration
+    let mut vm = VirtualMachine::builder()
+        .max_fuel(1 + 10 * 11)
+        .costs(CostTable::new().opcode(ADD_I64, 10))
+        .build();
+    let bytecode = looping_program(&mut vm);
+    let result = vm.eval_program(&bytecode);
+    assert!(matches!(result, Err(VmError::OutOfFuel(111))));
+    assert_eq!(vm.fuel_used, 1 + 10 * 11 + 10);
+}
+
+#[test]
+fn test_host_call_fuel() {
+    let src = "print(1)\nprint(2)\n";
+    let costs = CostTable::new().host_function("print", 100);
+    let mut vm = VirtualMachine::builder()
+        .output(Arc::new(Mutex::new(Vec::<u8>::new())))
+        .max_fuel(150)
+        .costs(costs)
+        .build();
+    let print = print_const(&vm).unwrap();
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    assert!(matches!(vm.eval_program(&bytecode), Err(VmError::OutOfFuel(150))));
+
+    vm.limits.max_fuel = Some(1000);
+    vm.eval_program(&bytecode).unwrap();
+    // 4 instructions per print, plus the host function's own price
+    assert_eq!(vm.fuel_used, 2 * (4 + 100));
+
+    // A new price applies from the next run on
+    vm.costs = CostTable::new().host_function("print", 10);
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(vm.fuel_used, 2 * (4 + 10));
+}
+
+#[test]
+fn test_default_timeout() {
+    let mut vm = VirtualMachine::builder()
+        .timeout(Duration::from_millis(10))
+        .build();
+    assert_eq!(vm.default_timeout, Some(Duration::from_millis(10)));
+    let bytecode = looping_program(&mut vm);
+    assert!(matches!(vm.eval_program(&bytecode), Err(VmError::Timeout(_))));
+}
+
+#[test]
+fn test_register_limit() {
+    fn noop(_base: usize, _registers: &mut Registers) -> Result<(), String> {
+        Ok(())
+    }
+    let mut vm = VirtualMachine::builder().max_registers(300).build();
+    let fn_index = vm.host_functions.register("noop", 0, 0, 100, noop);
+    let fn_const = vm
+        .const_pool
+        .add_value("", fn_index as u64, ValueType::FuncHost) as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_value(fn_const, 250);
+    builder.call_host(250);
+    let result = vm.eval_program(&builder.build());
+    assert!(matches!(result, Err(VmError::RegisterLimit(350))));
+}
+
+#[test]
+fn test_output_sink_and_preset_globals() {
+    let sink = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut vm = VirtualMachine::builder()
+        .output(sink.clone())
+        .global_i64("limit", 41)
+        .global_str("name", "kayton")
+        .build();
+    let print = print_const(&vm).expect("stdlib registered");
+    let bytecode = compile_source("print(name)\nx = limit + 1\nprint(x)", &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(sink.lock().unwrap().as_slice(), b"kayton\n42\n");
+}
+
+#[test]
+fn test_number_builtins() {
+    let sink = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut vm = VirtualMachine::builder().output(sink.clone()).build();
+    let print = print_const(&vm).unwrap();
+    let src = "n = parse_int(\"0xff\", 0)\nprint(n + 1)\nh = to_hex(n)\nprint(h)\nprint(to_bin(5))";
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(sink.lock().unwrap().as_slice(), b"256\n0xff\n0b101\n");
+
+    let bytecode = compile_source("n = parse_int(\"12x\", 10)", &mut vm, print).unwrap();
+    let err = vm.eval_program(&bytecode).unwrap_err();
+    assert!(err.to_string().contains("invalid literal for parse_int()"), "{}", err);
+}
+
+#[test]
+#[cfg(feature = "isa-strings")]
+fn test_bytes_builtins() {
+    let sink = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut vm = VirtualMachine::builder().output(sink.clone()).build();
+    let print = print_const(&vm).unwrap();
+    let src = r#"b = bytes_from_hex("0100ff")
+print(read_u16(b, 1))
+w = bytes_new(8)
+write_u32(w, 2, 305419896)
+print(bytes_hex(w))
+lit = b"\x01\x02AB"
+print(read_u8(lit, 2))
+print(bytes_hex(bytes_slice(lit, 1, 3)))
+print(bytes_len(lit + b"\n"))
+tail = bytes_slice(w, 6, 8)
+write_u16(tail, 0, 65535)
+print(bytes_hex(w))"#;
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(
+        String::from_utf8(sink.lock().unwrap().clone()).unwrap(),
+        "65280\n0000785634120000\n65\n0241\n5\n000078563412ffff\n"
+    );
+    let lit = vm.global_vars.get("lit").unwrap();
+    assert_eq!(lit.meta.typ, GlobalVarType::Ptr(PtrType::Slice(super::const_pool::SliceType::Binary)));
+
+    for (src, err) in [
+        ("write_u8(b\"a\", 0, 1)", "read-only"),
+        ("w = bytes_new(2)\nwrite_u8(w, 0, 256)", "does not fit"),
+        ("x = read_u32(bytes_new(3), 0)", "out of range"),
+        ("x = bytes_slice(b\"abc\", 2, 1)", "out of range"),
+        ("x = bytes_from_hex(\"abc\")", "invalid hex"),
+    ] {
+        let bytecode = compile_source(src, &mut vm, print).unwrap();
+        let message = vm.eval_program(&bytecode).unwrap_err().to_string();
+        assert!(message.contains(err), "{}: {}", src, message);
+    }
+    assert!(compile_source("x = b\"a\" + \"a\"", &mut vm, print).is_err());
+}
+
+#[test]
+fn test_timestamp_builtins() {
+    let sink = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut vm = VirtualMachine::builder()
+        .capabilities(Capabilities { time: true, ..Capabilities::none() })
+        .clock(Arc::new(|| 1_709_214_306_789))
+        .output(sink.clone())
+        .build();
+    let print = print_const(&vm).unwrap();
+    let src = "print(format_timestamp(now_ms(), \"%Y-%m-%d %H:%M:%S\"))\n\
+               print(parse_timestamp(\"1970-01-02\", \"%Y-%m-%d\"))";
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    assert_eq!(sink.lock().unwrap().as_slice(), b"2024-02-29 13:45:06\n86400000\n");
+}
+
+#[test]
+fn test_clock_requires_time_capability() {
+    let vm = VirtualMachine::builder()
+        .clock(Arc::new(|| 1234))
+        .build();
+    assert!(vm.host_functions.find("now_ms").is_none());
+
+    let mut vm = VirtualMachine::builder()
+        .capabilities(Capabilities { time: true, ..Capabilities::none() })
+        .clock(Arc::new(|| 1234))
+        .build();
+    let now = vm.const_pool.value_name_to_index["now_ms"] as u16;
+    let mut builder = BytecodeBuilder::new();
+    builder.load_const_value(now, 5);
+    builder.call_host(5);
+    vm.eval_program(&builder.build()).unwrap();
+    assert_eq!(vm.get_register_i64(5), 1234);
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_http_builtins_need_net_capability() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let vm = VirtualMachine::builder().build();
+    assert!(vm.host_functions.find("http_get").is_none());
+
+    // Answers two requests, echoing the request line and body
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        for status in ["200 OK", "404 Not Found"] {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let len = text
+                        .lines()
+                        .find_map(|l| {
+                            let l = l.to_ascii_lowercase();
+                            l.strip_prefix("content-length: ").map(|v| v.trim().parse().unwrap())
+                        })
+                        .unwrap_or(0);
+                    if request.len() >= end + 4 + len {
+                        break;
+                    }
+                }
+            }
+            let text = String::from_utf8_lossy(&request).into_owned();
+            let (head, body) = text.split_once("\r\n\r\n").unwrap();
+            let reply = format!("{} {}", head.lines().next().unwrap(), body);
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                reply.len(),
+                reply
+            )
+            .unwrap();
+        }
+    });
+
+    let sink = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut vm = VirtualMachine::builder()
+        .capabilities(Capabilities { net: true, ..Capabilities::none() })
+        .output(sink.clone())
+        .build();
+    let print = print_const(&vm).unwrap();
+    let src = format!(
+        "print(http_get(\"http://{0}/a\"))\nprint(http_status())\nprint(http_post(\"http://{0}/b\", \"hi\"))\nprint(http_status())",
+        addr
+    );
+    let bytecode = compile_source(&src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    server.join().unwrap();
+    assert_eq!(
+        String::from_utf8(sink.lock().unwrap().clone()).unwrap(),
+        "GET /a HTTP/1.1 \n200\nPOST /b HTTP/1.1 hi\n404\n"
+    );
+}
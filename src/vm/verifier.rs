@@ -0,0 +1,77 @@
+use super::program::{opcode_feature, operand_len};
+use super::*;
+
+/// Bytecode that passed `VirtualMachine::verify`, runnable on the unchecked
+/// fast path with `VirtualMachine::eval_verified`
+#[derive(Debug, Clone, Copy)]
+pub struct VerifiedBytecode<'a> {
+    bytecode: &'a [u8],
+}
+
+impl<'a> VerifiedBytecode<'a> {
+    pub fn bytecode(&self) -> &'a [u8] {
+        self.bytecode
+    }
+}
+
+impl VirtualMachine {
+    /// Check that every instruction is well-formed and compiled into this VM,
+    /// before running bytecode from an untrusted source
+    pub fn verify<'a>(&self, bytecode: &'a [u8]) -> Result<VerifiedBytecode<'a>, VmError> {
+        verify_bytecode(bytecode)?;
+        Ok(VerifiedBytecode { bytecode })
+    }
+}
+
+/// Besides operand bounds, jumps must land on an instruction boundary (or the
+/// end of the program); the fast path relies on both.
+pub(crate) fn verify_bytecode(bytecode: &[u8]) -> Result<(), VmError> {
+    let mut boundaries = vec![false; bytecode.len() + 1];
+    let mut jumps = Vec::new();
+    let mut pc = 0;
+    while pc < bytecode.len() {
+        boundaries[pc] = true;
+        let opcode = bytecode[pc];
+        let len = operand_len(opcode).ok_or(VmError::InvalidOpcode(opcode))?;
+        if opcode_feature(opcode) & !SUPPORTED_ISA_FEATURES != 0 {
+            return Err(VmError::UnsupportedOpcode(opcode));
+        }
+        if pc + 1 + len > bytecode.len() {
+            return Err(VmError::UnexpectedEndOfProgram);
+        }
+        let operand = |at: usize| u16::from_le_bytes([bytecode[at], bytecode[at + 1]]) as usize;
+        let target = match opcode {
+            JMP | CALL => Some(operand(pc + 1) as isize),
+            JUMP_FORWARD_IF_FALSE | JUMP_FORWARD_IF_TRUE => Some((pc + 2 + operand(pc + 2)) as isize),
+            JUMP_BACKWARD_IF_FALSE | JUMP_BACKWARD_IF_TRUE => {
+                Some((pc + 4) as isize - operand(pc + 2) as isize)
+            }
+            _ => None,
+        };
+        jumps.extend(target);
+        pc += 1 + len;
+    }
+    boundaries[bytecode.len()] = true;
+    for target in jumps {
+        if target < 0 || !boundaries.get(target as usize).copied().unwrap_or(false) {
+            return Err(VmError::InvalidJumpTarget(target as usize));
+        }
+    }
+    Ok(())
+}
+
+/// `result[i]` is true if an instruction starts at `i` (or `i` is the end of
+/// the program), decoding from the start until the first malformed instruction
+pub(crate) fn instruction_boundaries(bytecode: &[u8]) -> Vec<bool> {
+    let mut boundaries = vec![false; bytecode.len() + 1];
+    let mut pc = 0;
+    while pc < bytecode.len() {
+        boundaries[pc] = true;
+        match operand_len(bytecode[pc]) {
+            Some(len) => pc += 1 + len,
+            None => return boundaries,
+        }
+    }
+    boundaries[bytecode.len()] = true;
+    boundaries
+}
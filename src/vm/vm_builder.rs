@@ -0,0 +1,255 @@
+use super::const_pool::{SliceType, ValueType};
+use super::*;
+#[cfg(feature = "stdlib")]
+use crate::builtins::{register_stdlib, Clock, OutputSink};
+use std::time::Duration;
+
+enum PresetGlobal {
+    Value(String, u64, ValueType),
+    Str(String, String),
+}
+
+/// Fluent configuration for a `VirtualMachine`.
+/// `build()` applies the options in the order the VM needs them
+/// (capabilities before stdlib registration, stdlib before globals).
+pub struct VmBuilder {
+    limits: Limits,
+    costs: CostTable,
+    capabilities: Capabilities,
+    #[cfg(feature = "stdlib")]
+    stdlib: bool,
+    #[cfg(feature = "stdlib")]
+    output: Option<OutputSink>,
+    #[cfg(feature = "stdlib")]
+    clock: Option<Clock>,
+    #[cfg(feature = "stdlib")]
+    args: Vec<String>,
+    timeout: Option<Duration>,
+    break_hook: Option<BreakHook>,
+    allocation_scope: AllocationScope,
+    globals: Vec<PresetGlobal>,
+    #[cfg(feature = "frontend")]
+    rewriters: Vec<Arc<dyn Rewriter>>,
+    #[cfg(feature = "frontend")]
+    compile_mode: CompileMode,
+    #[cfg(feature = "frontend")]
+    compile_limits: CompileLimits,
+    #[cfg(feature = "frontend")]
+    modules: ModuleResolver,
+}
+
+impl VmBuilder {
+    pub fn new() -> Self {
+        Self {
+            limits: Limits::default(),
+            costs: CostTable::new(),
+            capabilities: Capabilities::none(),
+            #[cfg(feature = "stdlib")]
+            stdlib: false,
+            #[cfg(feature = "stdlib")]
+            output: None,
+            #[cfg(feature = "stdlib")]
+            clock: None,
+            #[cfg(feature = "stdlib")]
+            args: Vec::new(),
+            timeout: None,
+            break_hook: None,
+            allocation_scope: AllocationScope::Vm,
+            globals: Vec::new(),
+            #[cfg(feature = "frontend")]
+            rewriters: Vec::new(),
+            #[cfg(feature = "frontend")]
+            compile_mode: CompileMode::Debug,
+            #[cfg(feature = "frontend")]
+            compile_limits: CompileLimits::default(),
+            #[cfg(feature = "frontend")]
+            modules: ModuleResolver::new(),
+        }
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn max_instructions(mut self, max: u64) -> Self {
+        self.limits.max_instructions = Some(max);
+        self
+    }
+
+    pub fn max_registers(mut self, max: usize) -> Self {
+        self.limits.max_registers = Some(max);
+        self
+    }
+
+    /// Gas-style metering: fail with `OutOfFuel` once `max` fuel is burnt
+    pub fn max_fuel(mut self, max: u64) -> Self {
+        self.limits.max_fuel = Some(max);
+        self
+    }
+
+    pub fn costs(mut self, costs: CostTable) -> Self {
+        self.costs = costs;
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Register the builtin host functions (`print`, and capability-gated modules)
+    #[cfg(feature = "stdlib")]
+    pub fn with_stdlib(mut self) -> Self {
+        self.stdlib = true;
+        self
+    }
+
+    /// Send `print` output to `sink` instead of the console (implies `with_stdlib`)
+    #[cfg(feature = "stdlib")]
+    pub fn output(mut self, sink: OutputSink) -> Self {
+        self.stdlib = true;
+        self.output = Some(sink);
+        self
+    }
+
+    /// Clock used by time host functions (implies `with_stdlib`)
+    #[cfg(feature = "stdlib")]
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.stdlib = true;
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Arguments seen by `arg_count()` / `arg(i)` (implies `with_stdlib`;
+    /// scripts need the process capability to read them)
+    #[cfg(feature = "stdlib")]
+    pub fn args(mut self, args: Vec<String>) -> Self {
+        self.stdlib = true;
+        self.args = args;
+        self
+    }
+
+    /// Timeout used by `eval_program`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Called whenever a program executes DEBUG_BREAK
+    pub fn break_hook(mut self, hook: BreakHook) -> Self {
+        self.break_hook = Some(hook);
+        self
+    }
+
+    /// `AllocationScope::Execution` frees the strings of each run when it
+    /// ends, except those held by globals
+    pub fn allocation_scope(mut self, scope: AllocationScope) -> Self {
+        self.allocation_scope = scope;
+        self
+    }
+
+    /// AST rewriter run by `compile_source`; see `VirtualMachine::add_rewriter`
+    #[cfg(feature = "frontend")]
+    pub fn rewriter(mut self, rewriter: impl Rewriter + 'static) -> Self {
+        self.rewriters.push(Arc::new(rewriter));
+        self
+    }
+
+    /// `CompileMode::Release` strips assertions from compiled scripts
+    #[cfg(feature = "frontend")]
+    pub fn compile_mode(mut self, mode: CompileMode) -> Self {
+        self.compile_mode = mode;
+        self
+    }
+
+    /// Bounds on compiling untrusted source; see `CompileLimits`
+    #[cfg(feature = "frontend")]
+    pub fn compile_limits(mut self, limits: CompileLimits) -> Self {
+        self.compile_limits = limits;
+        self
+    }
+
+    /// Directory `import` searches for modules, after those added before it
+    #[cfg(feature = "frontend")]
+    pub fn module_path(mut self, dir: impl Into<std::path::PathBuf>) -> Self {
+        self.modules.add_path(dir);
+        self
+    }
+
+    pub fn global_i64(mut self, name: &str, value: i64) -> Self {
+        self.globals
+            .push(PresetGlobal::Value(name.to_string(), value as u64, ValueType::I64));
+        self
+    }
+
+    pub fn global_f64(mut self, name: &str, value: f64) -> Self {
+        self.globals
+            .push(PresetGlobal::Value(name.to_string(), value.to_bits(), ValueType::F64));
+        self
+    }
+
+    pub fn global_str(mut self, name: &str, value: &str) -> Self {
+        self.globals
+            .push(PresetGlobal::Str(name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn build(self) -> VirtualMachine {
+        let mut vm = VirtualMachine::new();
+        vm.limits = self.limits;
+        vm.costs = self.costs;
+        vm.capabilities = self.capabilities;
+        vm.default_timeout = self.timeout;
+        vm.break_hook = self.break_hook;
+        vm.allocation_scope = self.allocation_scope;
+        #[cfg(feature = "frontend")]
+        {
+            vm.rewriters = self.rewriters;
+            vm.compile_mode = self.compile_mode;
+            vm.compile_limits = self.compile_limits;
+            vm.modules = self.modules;
+        }
+        #[cfg(feature = "stdlib")]
+        if self.stdlib {
+            register_stdlib(&mut vm, self.output, self.clock, self.args);
+        }
+
+        let mut next_reg = 1; // register 0 is reserved for call bases
+        for global in self.globals {
+            match global {
+                PresetGlobal::Value(name, value, typ) => {
+                    vm.registers.set(next_reg, value);
+                    vm.global_vars
+                        .insert(&name, next_reg, GlobalVarType::Value(typ));
+                    next_reg += 1;
+                }
+                PresetGlobal::Str(name, value) => {
+                    let index = vm
+                        .const_pool
+                        .add_slice(&name, value.as_bytes(), SliceType::Utf8Str);
+                    let slice = vm.const_pool.slice(index).unwrap();
+                    vm.registers.set(next_reg, slice.as_ptr() as u64);
+                    vm.registers.set(next_reg + 1, slice.len() as u64);
+                    vm.registers_type
+                        .set(next_reg, RegisterType::ConstSliceVarMain);
+                    vm.registers_type
+                        .set(next_reg + 1, RegisterType::ConstSliceVarLen);
+                    vm.global_vars.insert(
+                        &name,
+                        next_reg,
+                        GlobalVarType::Ptr(PtrType::Slice(SliceType::Utf8Str)),
+                    );
+                    next_reg += 2;
+                }
+            }
+        }
+        vm
+    }
+}
+
+impl Default for VmBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,110 @@
+//! Element-wise addition of two vectors as a scripted loop (three CALL_HOSTs
+//! per element) versus one bulk `vec_host_add` call.
+//!
+//! `cargo bench -p vec_host --bench bulk_ops`
+
+use kayton::vm::const_pool::ValueType;
+use kayton::vm::{BytecodeBuilder, HostFunctionMetadata, VirtualMachine};
+use std::sync::Arc;
+use std::time::Instant;
+use vec_host::*;
+
+const LEN: u64 = 100_000;
+const RUNS: usize = 20;
+
+type VecFn = fn(&mut [u64]) -> Result<(), String>;
+
+/// Register `func` with the VM and return the const index holding it
+fn register(vm: &mut VirtualMachine, meta: &HostFunctionMetadata, func: VecFn) -> u16 {
+    let n = meta.num_registers;
+    let idx = vm.host_functions.register_closure(
+        meta.name,
+        meta.num_return_registers,
+        meta.num_params,
+        n,
+        Arc::new(move |base, registers| {
+            let mut frame: Vec<u64> = (0..n).map(|i| registers.get(base + i)).collect();
+            func(&mut frame)?;
+            for (i, value) in frame.into_iter().enumerate() {
+                registers.set(base + i, value);
+            }
+            Ok(())
+        }),
+    );
+    vm.const_pool.add_value(meta.name, idx as u64, ValueType::FuncHost) as u16
+}
+
+fn call(func: VecFn, args: &[u64]) -> u64 {
+    let mut registers = vec![0];
+    registers.extend_from_slice(args);
+    func(&mut registers).unwrap();
+    registers[0]
+}
+
+fn filled(len: u64) -> u64 {
+    let ptr = call(vec_host_new, &[]);
+    for i in 0..len {
+        call(vec_host_append, &[ptr, i]);
+    }
+    ptr
+}
+
+/// r1 = a, r2 = b, r3 = out, r4 = i, r5 = len, r6 = 1
+fn scripted_add(vm: &mut VirtualMachine) -> Vec<u8> {
+    let meta = vec_host_meta_data();
+    let get = register(vm, &meta["vec_host_get"], vec_host_get);
+    let append = register(vm, &meta["vec_host_append"], vec_host_append);
+    let mut builder = BytecodeBuilder::new();
+    let top = builder.create_label();
+    builder.place_label(top);
+    builder.call_host_fn(&meta["vec_host_get"], get, &[1, 4], 10);
+    builder.mov(10, 8);
+    builder.call_host_fn(&meta["vec_host_get"], get, &[2, 4], 10);
+    builder.add_i64(8, 10, 9);
+    builder.call_host_fn(&meta["vec_host_append"], append, &[3, 9], 10);
+    builder.add_i64(4, 6, 4);
+    builder.lt_i64(4, 5, 7);
+    builder.jump_if_true_to_label(7, top);
+    builder.build()
+}
+
+fn bulk_add(vm: &mut VirtualMachine) -> Vec<u8> {
+    let meta = vec_host_meta_data();
+    let add = register(vm, &meta["vec_host_add"], vec_host_add);
+    let mut builder = BytecodeBuilder::new();
+    builder.call_host_fn(&meta["vec_host_add"], add, &[1, 2], 3);
+    builder.build()
+}
+
+fn bench(name: &str, compile: fn(&mut VirtualMachine) -> Vec<u8>) {
+    let (a, b) = (filled(LEN), filled(LEN));
+    let mut vm = VirtualMachine::new();
+    let bytecode = compile(&mut vm);
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        let empty = call(vec_host_new, &[]);
+        for (reg, value) in [(1, a), (2, b), (3, empty), (4, 0), (5, LEN), (6, 1)] {
+            vm.registers.set(reg, value);
+        }
+        vm.eval_program(&bytecode).unwrap();
+        let out = vm.registers.get(3);
+        assert_eq!(call(vec_host_get, &[out, LEN - 1]), 2 * (LEN - 1));
+        call(vec_host_drop, &[out]);
+        if out != empty {
+            call(vec_host_drop, &[empty]);
+        }
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<9} {:>8.2} ns/element",
+        name,
+        elapsed.as_nanos() as f64 / (RUNS as u64 * LEN) as f64
+    );
+    call(vec_host_drop, &[a]);
+    call(vec_host_drop, &[b]);
+}
+
+fn main() {
+    bench("scripted", scripted_add);
+    bench("bulk", bulk_add);
+}
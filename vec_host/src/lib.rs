@@ -1,5 +1,9 @@
+pub mod parallel;
+
+use kayton::vm::{HostClosure, VirtualMachine};
 use std::collections::HashMap;
 use std::ptr::NonNull;
+use std::sync::Arc;
 
 pub use kayton::vm::HostFunctionMetadata;
 
@@ -15,7 +19,7 @@ fn read_ptr(reg: u64) -> Result<NonNull<Vec<u64>>, String> {
 
 #[unsafe(no_mangle)]
 pub fn vec_host_new(registers: &mut [u64]) -> Result<(), String> {
-    let v: Box<Vec<u64>> = Box::new(Vec::new());
+    let v: Box<Vec<u64>> = Box::default();
     let ptr = Box::into_raw(v) as u64;
     if let Some(r0) = registers.get_mut(0) {
         *r0 = ptr;
@@ -53,10 +57,7 @@ pub fn vec_host_append(registers: &mut [u64]) -> Result<(), String> {
     }
     let ptr_val = registers[1];
     let value = registers[2];
-    let nn = match read_ptr(ptr_val) {
-        Ok(nn) => nn,
-        Err(e) => return Err(e),
-    };
+    let nn = read_ptr(ptr_val)?;
     unsafe {
         (*nn.as_ptr()).push(value);
     }
@@ -72,11 +73,8 @@ pub fn vec_host_get(registers: &mut [u64]) -> Result<(), String> {
     }
     let ptr_val = registers[1];
     let index = registers[2] as usize;
-    let nn = match read_ptr(ptr_val) {
-        Ok(nn) => nn,
-        Err(e) => return Err(e),
-    };
-    let value = unsafe { (*nn.as_ptr()).get(index).copied() };
+    let nn = read_ptr(ptr_val)?;
+    let value = unsafe { nn.as_ref().get(index).copied() };
     match value {
         Some(v) => {
             registers[0] = v;
@@ -95,10 +93,7 @@ pub fn vec_host_set(registers: &mut [u64]) -> Result<(), String> {
     let ptr_val = registers[1];
     let index = registers[2] as usize;
     let value = registers[3];
-    let nn = match read_ptr(ptr_val) {
-        Ok(nn) => nn,
-        Err(e) => return Err(e),
-    };
+    let nn = read_ptr(ptr_val)?;
     let vec_ref = unsafe { &mut *nn.as_ptr() };
     if index >= vec_ref.len() {
         return Err("index out of bounds".to_string());
@@ -115,15 +110,81 @@ pub fn vec_host_len(registers: &mut [u64]) -> Result<(), String> {
         return Err("insufficient registers".to_string());
     }
     let ptr_val = registers[1];
-    let nn = match read_ptr(ptr_val) {
-        Ok(nn) => nn,
-        Err(e) => return Err(e),
-    };
+    let nn = read_ptr(ptr_val)?;
     let len = unsafe { (*nn.as_ptr()).len() as u64 };
     registers[0] = len;
     Ok(())
 }
 
+// Bulk operations work on whole vectors in one call, so numeric scripts do
+// not pay a CALL_HOST per element. Elements are i64 or f64 bit patterns.
+
+// Element-wise `op` of two vectors of equal length, into a new vector
+fn zip_into_new(registers: &mut [u64], op: impl Fn(u64, u64) -> u64) -> Result<(), String> {
+    if registers.len() < 3 {
+        return Err("insufficient registers".to_string());
+    }
+    let a = read_ptr(registers[1])?;
+    let b = read_ptr(registers[2])?;
+    let (a, b) = unsafe { (a.as_ref(), b.as_ref()) };
+    if a.len() != b.len() {
+        return Err(format!("length mismatch: {} vs {}", a.len(), b.len()));
+    }
+    let out: Box<Vec<u64>> = Box::new(a.iter().zip(b).map(|(&x, &y)| op(x, y)).collect());
+    registers[0] = Box::into_raw(out) as u64;
+    Ok(())
+}
+
+// add(a, b) -> new vec of a[i] + b[i], wrapping on overflow
+#[unsafe(no_mangle)]
+pub fn vec_host_add(registers: &mut [u64]) -> Result<(), String> {
+    zip_into_new(registers, |x, y| (x as i64).wrapping_add(y as i64) as u64)
+}
+
+// mul(a, b) -> new vec of a[i] * b[i], wrapping on overflow
+#[unsafe(no_mangle)]
+pub fn vec_host_mul(registers: &mut [u64]) -> Result<(), String> {
+    zip_into_new(registers, |x, y| (x as i64).wrapping_mul(y as i64) as u64)
+}
+
+// sum(vec_ptr) -> sum of the elements, wrapping on overflow
+#[unsafe(no_mangle)]
+pub fn vec_host_sum(registers: &mut [u64]) -> Result<(), String> {
+    if registers.len() < 2 {
+        return Err("insufficient registers".to_string());
+    }
+    let nn = read_ptr(registers[1])?;
+    let sum = unsafe { nn.as_ref() }
+        .iter()
+        .fold(0i64, |acc, &x| acc.wrapping_add(x as i64));
+    registers[0] = sum as u64;
+    Ok(())
+}
+
+// add_f64(a, b) -> new vec of a[i] + b[i] as f64
+#[unsafe(no_mangle)]
+pub fn vec_host_add_f64(registers: &mut [u64]) -> Result<(), String> {
+    zip_into_new(registers, |x, y| (f64::from_bits(x) + f64::from_bits(y)).to_bits())
+}
+
+// mul_f64(a, b) -> new vec of a[i] * b[i] as f64
+#[unsafe(no_mangle)]
+pub fn vec_host_mul_f64(registers: &mut [u64]) -> Result<(), String> {
+    zip_into_new(registers, |x, y| (f64::from_bits(x) * f64::from_bits(y)).to_bits())
+}
+
+// sum_f64(vec_ptr) -> sum of the elements as f64
+#[unsafe(no_mangle)]
+pub fn vec_host_sum_f64(registers: &mut [u64]) -> Result<(), String> {
+    if registers.len() < 2 {
+        return Err("insufficient registers".to_string());
+    }
+    let nn = read_ptr(registers[1])?;
+    let sum: f64 = unsafe { nn.as_ref() }.iter().map(|&x| f64::from_bits(x)).sum();
+    registers[0] = sum.to_bits();
+    Ok(())
+}
+
 #[unsafe(no_mangle)]
 pub fn vec_host_meta_data() -> HashMap<&'static str, HostFunctionMetadata> {
     let mut m = HashMap::new();
@@ -135,6 +196,8 @@ pub fn vec_host_meta_data() -> HashMap<&'static str, HostFunctionMetadata> {
             num_return_registers: 1,
             num_params: 0,
             num_registers: 1,
+            version: 0,
+            defaults: &[],
         },
     );
     m.insert(
@@ -144,6 +207,8 @@ pub fn vec_host_meta_data() -> HashMap<&'static str, HostFunctionMetadata> {
             num_return_registers: 1,
             num_params: 1,
             num_registers: 2,
+            version: 0,
+            defaults: &[],
         },
     );
     m.insert(
@@ -153,6 +218,8 @@ pub fn vec_host_meta_data() -> HashMap<&'static str, HostFunctionMetadata> {
             num_return_registers: 1,
             num_params: 2,
             num_registers: 3,
+            version: 0,
+            defaults: &[],
         },
     );
     m.insert(
@@ -162,6 +229,8 @@ pub fn vec_host_meta_data() -> HashMap<&'static str, HostFunctionMetadata> {
             num_return_registers: 1,
             num_params: 2,
             num_registers: 3,
+            version: 0,
+            defaults: &[],
         },
     );
     m.insert(
@@ -171,6 +240,8 @@ pub fn vec_host_meta_data() -> HashMap<&'static str, HostFunctionMetadata> {
             num_return_registers: 1,
             num_params: 3,
             num_registers: 4,
+            version: 0,
+            defaults: &[],
         },
     );
     m.insert(
@@ -180,7 +251,109 @@ pub fn vec_host_meta_data() -> HashMap<&'static str, HostFunctionMetadata> {
             num_return_registers: 1,
             num_params: 1,
             num_registers: 2,
+            version: 0,
+            defaults: &[],
+        },
+    );
+    m.insert(
+        "vec_host_add",
+        HostFunctionMetadata {
+            name: "vec_host_add",
+            num_return_registers: 1,
+            num_params: 2,
+            num_registers: 3,
+            version: 0,
+            defaults: &[],
+        },
+    );
+    m.insert(
+        "vec_host_mul",
+        HostFunctionMetadata {
+            name: "vec_host_mul",
+            num_return_registers: 1,
+            num_params: 2,
+            num_registers: 3,
+            version: 0,
+            defaults: &[],
+        },
+    );
+    m.insert(
+        "vec_host_sum",
+        HostFunctionMetadata {
+            name: "vec_host_sum",
+            num_return_registers: 1,
+            num_params: 1,
+            num_registers: 2,
+            version: 0,
+            defaults: &[],
+        },
+    );
+    m.insert(
+        "vec_host_add_f64",
+        HostFunctionMetadata {
+            name: "vec_host_add_f64",
+            num_return_registers: 1,
+            num_params: 2,
+            num_registers: 3,
+            version: 0,
+            defaults: &[],
+        },
+    );
+    m.insert(
+        "vec_host_mul_f64",
+        HostFunctionMetadata {
+            name: "vec_host_mul_f64",
+            num_return_registers: 1,
+            num_params: 2,
+            num_registers: 3,
+            version: 0,
+            defaults: &[],
+        },
+    );
+    m.insert(
+        "vec_host_sum_f64",
+        HostFunctionMetadata {
+            name: "vec_host_sum_f64",
+            num_return_registers: 1,
+            num_params: 1,
+            num_registers: 2,
+            version: 0,
+            defaults: &[],
         },
     );
     m
 }
+
+type VecFn = fn(&mut [u64]) -> Result<(), String>;
+
+/// Register every `vec_host_*` function with `vm`. List literals, indexing
+/// and `len()` on lists compile to calls of these.
+pub fn register_vec_host(vm: &mut VirtualMachine) {
+    let funcs: [(&str, VecFn); 12] = [
+        ("vec_host_new", vec_host_new),
+        ("vec_host_drop", vec_host_drop),
+        ("vec_host_append", vec_host_append),
+        ("vec_host_get", vec_host_get),
+        ("vec_host_set", vec_host_set),
+        ("vec_host_len", vec_host_len),
+        ("vec_host_add", vec_host_add),
+        ("vec_host_mul", vec_host_mul),
+        ("vec_host_sum", vec_host_sum),
+        ("vec_host_add_f64", vec_host_add_f64),
+        ("vec_host_mul_f64", vec_host_mul_f64),
+        ("vec_host_sum_f64", vec_host_sum_f64),
+    ];
+    let meta = vec_host_meta_data();
+    for (name, func) in funcs {
+        let n = meta[name].num_registers;
+        let call: HostClosure = Arc::new(move |base, registers| {
+            let mut frame: Vec<u64> = (0..n).map(|i| registers.get(base + i)).collect();
+            func(&mut frame)?;
+            for (i, value) in frame.into_iter().enumerate() {
+                registers.set(base + i, value);
+            }
+            Ok(())
+        });
+        vm.host_functions.register_metadata(meta[name].clone(), call);
+    }
+}
@@ -0,0 +1,142 @@
+//! `parallel_map(vec, "expr")`: map a pure expression over a vector on the
+//! Rayon thread pool.
+//!
+//! The expression reads the element as `x` and must produce an int. It is
+//! compiled once, in a VM with no host functions or capabilities, into a
+//! `.kayc` image; every worker thread loads that image into a VM of its own.
+//! Elements therefore share no state, and the expression can reach nothing
+//! but its element.
+
+use kayton::diagnostics::Diagnostics;
+use kayton::frontend::{
+    Ast, CompileBudget, Frontend, Stmt, compile_with, parse_expression, report_parse_errors,
+};
+use kayton::vm::const_pool::ValueType;
+use kayton::vm::{GlobalVarType, Program, Registers, VirtualMachine};
+use rayon::prelude::*;
+
+use super::read_ptr;
+
+const INPUT: &str = "x";
+const OUTPUT: &str = "__result";
+
+/// One expression, parsed as the program `__result = <expr>`
+struct MapSyntax;
+
+impl Frontend for MapSyntax {
+    fn parse(&self, src: &str) -> Result<Ast, String> {
+        self.parse_with_diagnostics(src, &CompileBudget::default(), &mut Diagnostics::new())
+    }
+
+    fn parse_with_diagnostics(
+        &self,
+        src: &str,
+        budget: &CompileBudget,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Ast, String> {
+        let expr = parse_expression(src, budget, diagnostics)
+            .map_err(|errors| report_parse_errors(&errors, diagnostics))?;
+        Ok(Ast {
+            stmts: vec![Stmt::Assign {
+                name: OUTPUT.to_string(),
+                annotation: None,
+                expr,
+            }],
+            lines: vec![1],
+        })
+    }
+}
+
+/// A compiled element function
+pub struct MapFn {
+    image: Vec<u8>,
+    input: usize,
+    output: usize,
+}
+
+/// A worker thread's own VM with the function loaded
+struct Worker {
+    vm: VirtualMachine,
+    bytecode: Vec<u8>,
+}
+
+impl MapFn {
+    /// Compile `expr`, an int expression over `x`
+    pub fn compile(expr: &str) -> Result<Self, String> {
+        let mut vm = VirtualMachine::builder().global_i64(INPUT, 0).build();
+        // No print is registered, and expressions cannot print anyway
+        let bytecode = compile_with(&MapSyntax, expr, &mut vm, 0)?;
+        let input = vm.global_vars.get(INPUT).ok_or("x is not defined")?.register_id;
+        let output = vm
+            .global_vars
+            .get(OUTPUT)
+            .ok_or("expression produced no value")?;
+        if !matches!(output.meta.typ, GlobalVarType::Value(ValueType::I64)) {
+            return Err("parallel_map functions must return an int".to_string());
+        }
+        let output = output.register_id;
+        let image = Program::with_consts(bytecode, &vm)
+            .map_err(|e| e.to_string())?
+            .to_bytes();
+        Ok(Self {
+            image,
+            input,
+            output,
+        })
+    }
+
+    fn worker(&self) -> Result<Worker, String> {
+        let mut vm = VirtualMachine::new();
+        let bytecode = vm.load_program(&self.image).map_err(|e| e.to_string())?;
+        Ok(Worker { vm, bytecode })
+    }
+
+    /// Apply the function to every element, in parallel
+    pub fn map(&self, input: &[u64]) -> Result<Vec<u64>, String> {
+        input
+            .par_iter()
+            .enumerate()
+            .map_init(
+                || self.worker(),
+                |worker, (i, &x)| {
+                    let worker = worker.as_mut().map_err(|e| e.clone())?;
+                    worker.vm.strings.clear();
+                    worker.vm.set_register_i64(self.input, x as i64);
+                    worker
+                        .vm
+                        .eval_program(&worker.bytecode)
+                        .map_err(|e| format!("element {}: {}", i, e))?;
+                    Ok(worker.vm.get_register_i64(self.output) as u64)
+                },
+            )
+            .collect()
+    }
+}
+
+// parallel_map(vec_ptr, expr) -> new vec_ptr
+fn host_parallel_map(base: usize, registers: &mut Registers) -> Result<(), String> {
+    let input = read_ptr(registers.get(base + 1))?;
+    let (ptr, len) = (registers.get(base + 2), registers.get(base + 3));
+    let src = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) };
+    let src = std::str::from_utf8(src).map_err(|e| e.to_string())?;
+    let func = MapFn::compile(src).map_err(|e| format!("parallel_map: {}", e))?;
+    let output = func
+        .map(unsafe { input.as_ref() })
+        .map_err(|e| format!("parallel_map: {}", e))?;
+    registers.set(base, Box::into_raw(Box::new(output)) as u64);
+    Ok(())
+}
+
+/// Make `parallel_map(vec, "expr")` callable from scripts, returning the
+/// const index holding it. The result is a new vector the script owns.
+pub fn register_parallel_map(vm: &mut VirtualMachine) -> u16 {
+    let idx = vm
+        .host_functions
+        .register("parallel_map", 1, 2, 4, host_parallel_map);
+    vm.debug_info.set_doc(
+        "parallel_map",
+        "New vector of an int expression over `x`, evaluated for every element on all cores.",
+    );
+    vm.const_pool
+        .add_value("parallel_map", idx as u64, ValueType::FuncHost) as u16
+}
@@ -0,0 +1,47 @@
+use kayton::builtins::print_const;
+use kayton::codegen::compile_source;
+use kayton::vm::VirtualMachine;
+use vec_host::parallel::{MapFn, register_parallel_map};
+
+#[test]
+fn maps_every_element() {
+    let func = MapFn::compile("x + x + 1").unwrap();
+    let input: Vec<u64> = (0..10_000).collect();
+    let output = func.map(&input).unwrap();
+    assert_eq!(output.len(), input.len());
+    assert!(output.iter().zip(&input).all(|(y, x)| *y == 2 * x + 1));
+    assert_eq!(func.map(&[]).unwrap(), Vec::<u64>::new());
+}
+
+#[test]
+fn functions_must_be_pure_int_expressions() {
+    let err = MapFn::compile("print(x)").err().unwrap();
+    assert!(err.contains("unknown function 'print'"), "{}", err);
+    assert_eq!(MapFn::compile("y").err().unwrap(), "undefined variable");
+    assert!(MapFn::compile("x = 1").is_err());
+    assert_eq!(
+        MapFn::compile("\"s\"").err().unwrap(),
+        "parallel_map functions must return an int"
+    );
+}
+
+#[test]
+fn scripts_call_parallel_map() {
+    let input = Box::into_raw(Box::new(vec![1u64, 2, 3])) as u64;
+    let mut vm = VirtualMachine::builder()
+        .with_stdlib()
+        .global_i64("v", input as i64)
+        .build();
+    register_parallel_map(&mut vm);
+    let print = print_const(&vm).unwrap();
+    let bytecode = compile_source("out = parallel_map(v, \"x + 10\")", &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let out = vm.global_vars.get("out").unwrap().register_id;
+    let out = unsafe { Box::from_raw(vm.get_register_i64(out) as *mut Vec<u64>) };
+    assert_eq!(*out, vec![11, 12, 13]);
+
+    let bytecode = compile_source("bad = parallel_map(v, \"x +\")", &mut vm, print).unwrap();
+    let err = vm.eval_program(&bytecode).unwrap_err().to_string();
+    assert!(err.contains("parallel_map: "), "{}", err);
+    drop(unsafe { Box::from_raw(input as *mut Vec<u64>) });
+}
@@ -56,3 +56,94 @@ fn vec_get_out_of_bounds_returns_err() {
     let mut regs_drop = vec![0u64, ptr];
     assert_eq!(vec_host_drop(&mut regs_drop), Ok(()));
 }
+
+fn vec_of(values: &[u64]) -> u64 {
+    let mut regs_new = vec![0u64; 1];
+    assert_eq!(vec_host_new(&mut regs_new), Ok(()));
+    for &value in values {
+        let mut regs_append = vec![0u64, regs_new[0], value];
+        assert_eq!(vec_host_append(&mut regs_append), Ok(()));
+    }
+    regs_new[0]
+}
+
+fn contents(ptr: u64) -> Vec<u64> {
+    unsafe { (*(ptr as *const Vec<u64>)).clone() }
+}
+
+fn drop_vec(ptr: u64) {
+    let mut regs_drop = vec![0u64, ptr];
+    assert_eq!(vec_host_drop(&mut regs_drop), Ok(()));
+}
+
+#[test]
+fn bulk_integer_ops() {
+    let a = vec_of(&[1, 2, (-3i64) as u64]);
+    let b = vec_of(&[10, 20, 30]);
+
+    let mut regs_add = vec![0u64, a, b];
+    assert_eq!(vec_host_add(&mut regs_add), Ok(()));
+    assert_eq!(contents(regs_add[0]), vec![11, 22, 27]);
+
+    let mut regs_mul = vec![0u64, a, b];
+    assert_eq!(vec_host_mul(&mut regs_mul), Ok(()));
+    assert_eq!(contents(regs_mul[0]), vec![10, 40, (-90i64) as u64]);
+
+    let mut regs_sum = vec![0u64, a];
+    assert_eq!(vec_host_sum(&mut regs_sum), Ok(()));
+    assert_eq!(regs_sum[0] as i64, 0);
+
+    // Lengths must match
+    let short = vec_of(&[1]);
+    let mut regs_mismatch = vec![0u64, a, short];
+    assert!(vec_host_add(&mut regs_mismatch).is_err());
+
+    for ptr in [a, b, short, regs_add[0], regs_mul[0]] {
+        drop_vec(ptr);
+    }
+}
+
+#[test]
+fn bulk_float_ops() {
+    let a = vec_of(&[1.5f64.to_bits(), 2.0f64.to_bits()]);
+    let b = vec_of(&[0.5f64.to_bits(), 4.0f64.to_bits()]);
+
+    let mut regs_add = vec![0u64, a, b];
+    assert_eq!(vec_host_add_f64(&mut regs_add), Ok(()));
+    let mut regs_mul = vec![0u64, a, b];
+    assert_eq!(vec_host_mul_f64(&mut regs_mul), Ok(()));
+    let mut regs_sum = vec![0u64, regs_mul[0]];
+    assert_eq!(vec_host_sum_f64(&mut regs_sum), Ok(()));
+    let floats = |ptr| contents(ptr).into_iter().map(f64::from_bits).collect::<Vec<_>>();
+    assert_eq!(floats(regs_add[0]), vec![2.0, 6.0]);
+    assert_eq!(floats(regs_mul[0]), vec![0.75, 8.0]);
+    assert_eq!(f64::from_bits(regs_sum[0]), 8.75);
+
+    for ptr in [a, b, regs_add[0], regs_mul[0]] {
+        drop_vec(ptr);
+    }
+}
+
+#[test]
+fn scripts_use_list_literals_and_indexing() {
+    use kayton::builtins::print_const;
+    use kayton::codegen::compile_source;
+    use kayton::vm::VirtualMachine;
+
+    let mut vm = VirtualMachine::builder().with_stdlib().build();
+    register_vec_host(&mut vm);
+    let print = print_const(&vm).unwrap();
+    let src = "xs = [1, 2, 3]\nxs[1] = 20\ny = xs[1] + len(xs)\nn = len(\"abcd\")";
+    let bytecode = compile_source(src, &mut vm, print).unwrap();
+    vm.eval_program(&bytecode).unwrap();
+    let y = vm.global_vars.get("y").unwrap().register_id;
+    assert_eq!(vm.get_register_i64(y), 23);
+    let n = vm.global_vars.get("n").unwrap().register_id;
+    assert_eq!(vm.get_register_i64(n), 4);
+    let xs = vm.global_vars.get("xs").unwrap().register_id;
+    let xs = unsafe { Box::from_raw(vm.get_register_i64(xs) as *mut Vec<u64>) };
+    assert_eq!(*xs, vec![1, 20, 3]);
+
+    let bytecode = compile_source("zs = [1]\nzs[5]", &mut vm, print).unwrap();
+    assert!(vm.eval_program(&bytecode).is_err());
+}